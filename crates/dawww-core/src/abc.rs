@@ -0,0 +1,467 @@
+use std::collections::HashMap;
+use anyhow::{Result, anyhow, bail};
+use crate::pitch::{Pitch, Tone, OCTAVE_MAX};
+use crate::Note;
+
+/// Semitone offset of each note letter, C to B
+const LETTER_SEMITONES: [i32; 7] = [0, 2, 4, 5, 7, 9, 11];
+/// Letters sharpened by key signatures, in order (indexes into C to B)
+const SHARP_ORDER: [usize; 7] = [3, 0, 4, 1, 5, 2, 6];
+/// Letters flattened by key signatures, in order
+const FLAT_ORDER: [usize; 7] = [6, 2, 5, 1, 4, 0, 3];
+
+/// A tune read from ABC notation.
+///
+/// Supports the header fields `T:`, `M:`, `L:` and `K:` (also inline as `[K:...]`), notes with
+/// accidentals and octave marks, note lengths, rests, chords, ties, broken rhythm (`>`/`<`),
+/// and tuplets. Repeats are played once, and decorations, chord symbols and grace notes are
+/// skipped. Only the first tune in the text is read.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AbcTune {
+    pub title: Option<String>,
+    /// Time signature as (beats, beat value), if the tune has one
+    pub meter: Option<(u32, u32)>,
+    /// Notes as `(offset in 32nds, note)` pairs, in order
+    pub notes: Vec<(u32, Note)>,
+}
+
+impl AbcTune {
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut parser = Parser::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if parser.in_body && (line.is_empty() || line.starts_with("X:")) {
+                break;
+            }
+            parser.line(line)
+                .map_err(|e| anyhow!("ABC line {}: {}", number + 1, e))?;
+        }
+
+        let notes = parser.notes.into_iter()
+            .map(|(onset, pitch, length)| (onset.round() as u32, Note::new(pitch, (length.round() as u32).max(1))))
+            .collect();
+        Ok(Self {
+            title: parser.title,
+            meter: parser.meter,
+            notes,
+        })
+    }
+}
+
+struct Parser {
+    title: Option<String>,
+    meter: Option<(u32, u32)>,
+    /// Length of the `L:` unit in 32nds
+    unit: f64,
+    unit_set: bool,
+    /// Key signature accidental for each letter, C to B
+    key: [i32; 7],
+    /// Accidentals written earlier in the bar, by (letter, octave)
+    bar_accidentals: HashMap<(usize, i32), i32>,
+    in_body: bool,
+    cursor: f64,
+    /// (onset, pitch, length), with onset and length in 32nds
+    notes: Vec<(f64, Pitch, f64)>,
+    /// Index of the first note of the last note, chord or rest, and its length
+    last: Option<(usize, f64)>,
+    /// Length multiplier for the next element, from broken rhythm
+    next_factor: f64,
+    /// Elements left in the current tuplet and their length multiplier
+    tuplet: Option<(u32, f64)>,
+    tie: bool,
+}
+
+impl Parser {
+    fn new() -> Self {
+        Self {
+            title: None,
+            meter: None,
+            unit: 4.0,
+            unit_set: false,
+            key: [0; 7],
+            bar_accidentals: HashMap::new(),
+            in_body: false,
+            cursor: 0.0,
+            notes: Vec::new(),
+            last: None,
+            next_factor: 1.0,
+            tuplet: None,
+            tie: false,
+        }
+    }
+
+    fn line(&mut self, line: &str) -> Result<()> {
+        let chars: Vec<char> = line.chars().collect();
+        if chars.len() >= 2 && chars[0].is_ascii_alphabetic() && chars[1] == ':' {
+            return self.field(chars[0], line[2..].trim());
+        }
+        if !self.in_body {
+            return Ok(());
+        }
+
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            match c {
+                '%' => break,
+                '|' => {
+                    self.bar_accidentals.clear();
+                    i += 1;
+                }
+                '[' if chars.get(i + 2) == Some(&':') => {
+                    if !chars[i + 1].is_ascii_alphabetic() {
+                        bail!("Invalid inline field '[{}:'", chars[i + 1]);
+                    }
+                    let end = find(&chars, i + 2, ']')?;
+                    let field: String = chars[i + 3..end].iter().collect();
+                    self.field(chars[i + 1], field.trim())?;
+                    i = end + 1;
+                }
+                '[' if chars.get(i + 1).is_some_and(|c| c.is_ascii_digit() || *c == '|') => i += 1,
+                '[' => {
+                    i += 1;
+                    let mut pitches = Vec::new();
+                    let mut first_length = None;
+                    while i < chars.len() && chars[i] != ']' {
+                        if is_note_start(chars[i]) {
+                            pitches.push(self.pitch(&chars, &mut i)?);
+                            let length = parse_length(&chars, &mut i)?;
+                            first_length.get_or_insert(length);
+                        } else {
+                            i += 1;
+                        }
+                    }
+                    if i == chars.len() {
+                        bail!("Unclosed chord");
+                    }
+                    i += 1;
+                    let length = first_length.unwrap_or(1.0) * parse_length(&chars, &mut i)?;
+                    self.element(&pitches, length * self.unit);
+                }
+                '"' | '!' | '+' => i = find(&chars, i, c)? + 1,
+                '{' => i = find(&chars, i, '}')? + 1,
+                '(' if chars.get(i + 1).is_some_and(|c| c.is_ascii_digit()) => {
+                    let count = chars[i + 1].to_digit(10).unwrap_or(0);
+                    self.tuplet(count)?;
+                    i += 2;
+                }
+                '-' => {
+                    self.tie = true;
+                    i += 1;
+                }
+                '>' | '<' => {
+                    let run = chars[i..].iter().take_while(|&&r| r == c).count();
+                    self.broken_rhythm(c == '>', run as i32);
+                    i += run;
+                }
+                'z' | 'x' => {
+                    i += 1;
+                    let length = parse_length(&chars, &mut i)?;
+                    self.element(&[], length * self.unit);
+                }
+                'Z' | 'X' => {
+                    i += 1;
+                    let bars = parse_length(&chars, &mut i)?;
+                    let (beats, value) = self.meter.unwrap_or((4, 4));
+                    self.element(&[], bars * 32.0 * beats as f64 / value as f64);
+                }
+                c if is_note_start(c) => {
+                    let pitch = self.pitch(&chars, &mut i)?;
+                    let length = parse_length(&chars, &mut i)?;
+                    self.element(&[pitch], length * self.unit);
+                }
+                _ => i += 1,
+            }
+        }
+        Ok(())
+    }
+
+    fn field(&mut self, name: char, value: &str) -> Result<()> {
+        match name {
+            'T' => {
+                self.title.get_or_insert_with(|| value.to_string());
+            }
+            'M' => {
+                self.meter = parse_meter(value)?;
+                if !self.unit_set {
+                    let short = self.meter.is_some_and(|(beats, value)| (beats as f64 / value as f64) < 0.75);
+                    self.unit = if short { 2.0 } else { 4.0 };
+                }
+            }
+            'L' => {
+                let (num, den) = value.split_once('/')
+                    .and_then(|(n, d)| Some((n.trim().parse::<u32>().ok()?, d.trim().parse::<u32>().ok()?)))
+                    .filter(|(n, d)| *n > 0 && *d > 0)
+                    .ok_or_else(|| anyhow!("Invalid unit note length '{}'", value))?;
+                self.unit = 32.0 * num as f64 / den as f64;
+                self.unit_set = true;
+            }
+            'K' => {
+                self.key = parse_key(value)?;
+                self.in_body = true;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Read an accidental, note letter and octave marks
+    fn pitch(&mut self, chars: &[char], i: &mut usize) -> Result<Pitch> {
+        let mut accidental = None;
+        while let Some(&c) = chars.get(*i) {
+            let change = match c {
+                '^' => 1,
+                '_' => -1,
+                '=' => 0,
+                _ => break,
+            };
+            accidental = Some(accidental.unwrap_or(0) + change);
+            *i += 1;
+        }
+
+        let letter_char = *chars.get(*i).ok_or_else(|| anyhow!("Accidental without a note"))?;
+        let letter = "CDEFGAB".find(letter_char.to_ascii_uppercase())
+            .ok_or_else(|| anyhow!("Invalid note '{}'", letter_char))?;
+        let mut octave: i32 = if letter_char.is_ascii_lowercase() { 5 } else { 4 };
+        *i += 1;
+        while let Some(&c) = chars.get(*i) {
+            match c {
+                '\'' => octave += 1,
+                ',' => octave -= 1,
+                _ => break,
+            }
+            *i += 1;
+        }
+
+        let accidental = match accidental {
+            Some(accidental) => {
+                self.bar_accidentals.insert((letter, octave), accidental);
+                accidental
+            }
+            None => self.bar_accidentals.get(&(letter, octave)).copied().unwrap_or(self.key[letter]),
+        };
+
+        let semitone = octave * 12 + LETTER_SEMITONES[letter] + accidental;
+        let octave = semitone.div_euclid(12);
+        if !(0..=OCTAVE_MAX as i32).contains(&octave) {
+            bail!("Note '{}' is out of range", letter_char);
+        }
+        Ok(Pitch::new(Tone::from_index(semitone.rem_euclid(12) as u16), octave as u16))
+    }
+
+    /// Add a note, chord, or (with no pitches) rest at the cursor
+    fn element(&mut self, pitches: &[Pitch], length: f64) {
+        let mut length = length * self.next_factor;
+        self.next_factor = 1.0;
+        if let Some((remaining, factor)) = self.tuplet {
+            length *= factor;
+            self.tuplet = (remaining > 1).then_some((remaining - 1, factor));
+        }
+
+        let first = self.notes.len();
+        for &pitch in pitches {
+            let tied = self.tie.then(|| {
+                self.notes.iter_mut()
+                    .rev()
+                    .find(|(onset, p, l)| *p == pitch && (onset + l - self.cursor).abs() < 1e-9)
+            }).flatten();
+            match tied {
+                Some(note) => note.2 += length,
+                None => self.notes.push((self.cursor, pitch, length)),
+            }
+        }
+        self.tie = false;
+        self.last = Some((first, length));
+        self.cursor += length;
+    }
+
+    /// Dot the previous element and shorten the next one (`>`), or the reverse (`<`)
+    fn broken_rhythm(&mut self, lengthen_previous: bool, run: i32) {
+        let Some((first, length)) = self.last else {
+            return;
+        };
+        let short = 0.5_f64.powi(run);
+        let (previous, next) = if lengthen_previous { (2.0 - short, short) } else { (short, 2.0 - short) };
+
+        let change = length * (previous - 1.0);
+        for note in &mut self.notes[first..] {
+            note.2 += change;
+        }
+        self.cursor += change;
+        self.next_factor = next;
+    }
+
+    /// Start a tuplet of `count` notes
+    fn tuplet(&mut self, count: u32) -> Result<()> {
+        let compound = self.meter.is_some_and(|(beats, _)| beats % 3 == 0 && beats > 3);
+        let in_time_of = match count {
+            2 | 4 | 8 => 3,
+            3 | 6 => 2,
+            5 | 7 | 9 if compound => 3,
+            5 | 7 | 9 => 2,
+            _ => bail!("Invalid tuplet '({}'", count),
+        };
+        self.tuplet = Some((count, in_time_of as f64 / count as f64));
+        Ok(())
+    }
+}
+
+fn is_note_start(c: char) -> bool {
+    matches!(c, 'A'..='G' | 'a'..='g' | '^' | '_' | '=')
+}
+
+/// Index of the next `close` after position `open`
+fn find(chars: &[char], open: usize, close: char) -> Result<usize> {
+    chars[open + 1..].iter()
+        .position(|&c| c == close)
+        .map(|offset| open + 1 + offset)
+        .ok_or_else(|| anyhow!("Missing closing '{}'", close))
+}
+
+/// Read a length multiplier such as `2`, `/`, `//`, `3/2` or `/4`
+fn parse_length(chars: &[char], i: &mut usize) -> Result<f64> {
+    let numerator = parse_number(chars, i).unwrap_or(1);
+    let mut denominator = 1;
+    while chars.get(*i) == Some(&'/') {
+        *i += 1;
+        denominator *= parse_number(chars, i).unwrap_or(2);
+    }
+    if numerator == 0 || denominator == 0 {
+        bail!("Invalid note length");
+    }
+    Ok(numerator as f64 / denominator as f64)
+}
+
+fn parse_number(chars: &[char], i: &mut usize) -> Option<u32> {
+    let digits: String = chars[*i..].iter().take_while(|c| c.is_ascii_digit()).collect();
+    *i += digits.len();
+    digits.parse().ok()
+}
+
+fn parse_meter(value: &str) -> Result<Option<(u32, u32)>> {
+    match value {
+        "" | "none" => Ok(None),
+        "C" => Ok(Some((4, 4))),
+        "C|" => Ok(Some((2, 2))),
+        _ => value.split_once('/')
+            .and_then(|(b, v)| Some((b.trim().parse::<u32>().ok()?, v.trim().parse::<u32>().ok()?)))
+            .filter(|(b, v)| *b > 0 && *v > 0)
+            .map(Some)
+            .ok_or_else(|| anyhow!("Invalid meter '{}'", value)),
+    }
+}
+
+/// Key signature accidentals for a key such as `G`, `F#m`, `Bb` or `D mix`
+fn parse_key(value: &str) -> Result<[i32; 7]> {
+    let mut key = [0; 7];
+    let mut chars = value.chars().peekable();
+    let fifths: i32 = match chars.next() {
+        None | Some('H') => return Ok(key),
+        Some(_) if value.starts_with("none") => return Ok(key),
+        Some(tonic) => {
+            let mut fifths = match tonic {
+                'F' => -1,
+                'C' => 0,
+                'G' => 1,
+                'D' => 2,
+                'A' => 3,
+                'E' => 4,
+                'B' => 5,
+                _ => bail!("Invalid key '{}'", value),
+            };
+            match chars.peek() {
+                Some('#') => fifths += 7,
+                Some('b') => fifths -= 7,
+                _ => {}
+            }
+            if matches!(chars.peek(), Some('#') | Some('b')) {
+                chars.next();
+            }
+
+            let rest: String = chars.collect();
+            let mode: String = rest.trim_start()
+                .chars()
+                .take_while(|c| c.is_ascii_alphabetic())
+                .collect::<String>()
+                .to_lowercase();
+            fifths + match mode.get(..3).unwrap_or(&mode) {
+                "m" | "min" | "aeo" => -3,
+                "mix" => -1,
+                "dor" => -2,
+                "phr" => -4,
+                "lyd" => 1,
+                "loc" => -5,
+                _ => 0,
+            }
+        }
+    };
+
+    if !(-7..=7).contains(&fifths) {
+        bail!("Key '{}' needs more than 7 sharps or flats", value);
+    }
+    let (order, change) = if fifths >= 0 { (SHARP_ORDER, 1) } else { (FLAT_ORDER, -1) };
+    for &letter in order.iter().take(fifths.unsigned_abs() as usize) {
+        key[letter] = change;
+    }
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pitches(tune: &AbcTune) -> Vec<String> {
+        tune.notes.iter().map(|(_, n)| n.pitch.as_str()).collect()
+    }
+
+    fn timing(tune: &AbcTune) -> Vec<(u32, u32)> {
+        tune.notes.iter().map(|(onset, n)| (*onset, n.duration)).collect()
+    }
+
+    #[test]
+    fn test_key_and_accidentals() {
+        let tune = AbcTune::parse("X:1\nT:Test\nM:4/4\nL:1/8\nK:G\nGABc d^cf=f|fF,c'2 z2|]").unwrap();
+        assert_eq!(tune.title.as_deref(), Some("Test"));
+        assert_eq!(tune.meter, Some((4, 4)));
+        // F is sharp in G major, and accidentals last until the bar line
+        assert_eq!(
+            pitches(&tune),
+            vec!["G4", "A4", "B4", "C5", "D5", "C#5", "F#5", "F5", "F#5", "F#3", "C6"]
+        );
+        assert_eq!(tune.notes[10], (40, Note::new(Pitch::new(Tone::C, 6), 8)));
+
+        assert_eq!(parse_key("Bb").unwrap()[6], -1);
+        assert_eq!(parse_key("D dorian").unwrap(), [0; 7]);
+        assert_eq!(parse_key("F#m").unwrap(), parse_key("A").unwrap());
+        assert!(parse_key("Q").is_err());
+    }
+
+    #[test]
+    fn test_lengths_and_rhythm() {
+        // 2/4 uses a sixteenth as the default unit length
+        let tune = AbcTune::parse("M:2/4\nK:C\nC2 D/ E/ F | A>B c<d | (3efg a-a").unwrap();
+        assert_eq!(tune.meter, Some((2, 4)));
+        assert_eq!(
+            timing(&tune),
+            vec![
+                (0, 4), (4, 1), (5, 1), (6, 2),
+                (8, 3), (11, 1), (12, 1), (13, 3),
+                (16, 1), (17, 1), (19, 1), (20, 4),
+            ]
+        );
+        assert_eq!(parse_length(&['3', '/', '2'], &mut 0).unwrap(), 1.5);
+        assert_eq!(parse_length(&['/', '/'], &mut 0).unwrap(), 0.25);
+    }
+
+    #[test]
+    fn test_chords_and_rests() {
+        let tune = AbcTune::parse("L:1/4\nK:C\n\"Am\"[CEG]2 z !fermata!{g}A Z|c\n\nX:2\nK:C\nd").unwrap();
+        assert_eq!(timing(&tune), vec![(0, 16), (0, 16), (0, 16), (24, 8), (64, 8)]);
+        assert_eq!(pitches(&tune), vec!["C4", "E4", "G4", "A4", "C5"]);
+        assert!(AbcTune::parse("K:C\n[CE").is_err());
+        assert!(AbcTune::parse("L:x\nK:C").is_err());
+        // Malformed inline fields are errors, not panics
+        assert!(AbcTune::parse("K:C\n[]:").is_err());
+        assert!(AbcTune::parse("K:C\n[K:").is_err());
+    }
+}
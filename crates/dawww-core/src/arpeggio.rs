@@ -0,0 +1,132 @@
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use anyhow::{Result, bail};
+use crate::time::Grid;
+use crate::{Note, UnknownFields};
+
+/// Order an arpeggio walks through the chord's notes
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum ArpDirection {
+    /// Lowest to highest
+    #[default]
+    Up,
+    /// Highest to lowest
+    Down,
+    /// Up and back down, without repeating the top and bottom notes
+    UpDown,
+}
+
+/// Plays a chord's notes one at a time, repeating over the length of the chord
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct Arpeggio {
+    #[serde(default)]
+    pub direction: ArpDirection,
+    /// How often a new note starts
+    #[serde(default = "default_rate")]
+    pub rate: Grid,
+    /// How much of each step a note is held for, from above 0 up to 1 (legato)
+    #[serde(default = "default_gate", skip_serializing_if = "is_default_gate")]
+    pub gate: f64,
+    #[serde(flatten)]
+    pub extra: UnknownFields,
+}
+
+fn default_rate() -> Grid {
+    Grid::Sixteenth
+}
+
+fn default_gate() -> f64 {
+    0.5
+}
+
+fn is_default_gate(gate: &f64) -> bool {
+    *gate == default_gate()
+}
+
+impl Arpeggio {
+    pub fn new(direction: ArpDirection, rate: Grid) -> Self {
+        Self { direction, rate, gate: default_gate(), extra: UnknownFields::new() }
+    }
+
+    pub fn with_gate(mut self, gate: f64) -> Self {
+        self.gate = gate;
+        self
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if !(self.gate > 0.0 && self.gate <= 1.0) {
+            bail!("Arpeggio gate must be above 0 and at most 1, got {}", self.gate);
+        }
+        Ok(())
+    }
+
+    /// The notes of a chord as `(offset in 32nds, note)` pairs, one per step, running
+    /// for as long as the chord's longest note. Each note keeps its velocity.
+    pub fn expand(&self, chord: &[Note]) -> Vec<(u32, Note)> {
+        let mut up: Vec<&Note> = chord.iter().collect();
+        up.sort_by_key(|note| note.pitch);
+        let order: Vec<&Note> = match self.direction {
+            ArpDirection::Up => up,
+            ArpDirection::Down => up.into_iter().rev().collect(),
+            ArpDirection::UpDown => {
+                let down = up.iter().rev().skip(1).take(up.len().saturating_sub(2)).copied().collect::<Vec<_>>();
+                up.into_iter().chain(down).collect()
+            }
+        };
+
+        let length = chord.iter().map(|note| note.duration).max().unwrap_or(0);
+        let step = self.rate.step();
+        let held = ((step as f64 * self.gate).round() as u32).max(1);
+        (0..length)
+            .step_by(step as usize)
+            .zip(order.into_iter().cycle())
+            .map(|(offset, note)| (offset, Note { duration: held.min(length - offset), ..note.clone() }))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pitch::{Pitch, Tone};
+
+    fn triad() -> Vec<Note> {
+        [Tone::G, Tone::C, Tone::E].iter()
+            .map(|&tone| Note::new(Pitch::new(tone, 4), 16))
+            .collect()
+    }
+
+    fn tones(notes: &[(u32, Note)]) -> Vec<Tone> {
+        notes.iter().map(|(_, note)| note.pitch.tone).collect()
+    }
+
+    #[test]
+    fn test_directions() {
+        let up = Arpeggio::new(ArpDirection::Up, Grid::Eighth).expand(&triad());
+        assert_eq!(tones(&up), vec![Tone::C, Tone::E, Tone::G, Tone::C]);
+        assert_eq!(up.iter().map(|(offset, _)| *offset).collect::<Vec<_>>(), vec![0, 4, 8, 12]);
+
+        let down = Arpeggio::new(ArpDirection::Down, Grid::Eighth).expand(&triad());
+        assert_eq!(tones(&down), vec![Tone::G, Tone::E, Tone::C, Tone::G]);
+
+        let up_down = Arpeggio::new(ArpDirection::UpDown, Grid::Sixteenth).expand(&triad());
+        assert_eq!(tones(&up_down)[..6], [Tone::C, Tone::E, Tone::G, Tone::E, Tone::C, Tone::E]);
+        assert_eq!(up_down.len(), 8);
+    }
+
+    #[test]
+    fn test_gate() {
+        let notes = Arpeggio::new(ArpDirection::Up, Grid::Quarter).with_gate(0.25).expand(&triad());
+        assert!(notes.iter().all(|(_, note)| note.duration == 2));
+
+        // The last step is cut off at the end of the chord
+        let chord = vec![Note::new(Pitch::new(Tone::C, 4), 6)];
+        let legato = Arpeggio::new(ArpDirection::Up, Grid::Quarter).with_gate(1.0).expand(&chord);
+        assert_eq!(legato.iter().map(|(_, note)| note.duration).collect::<Vec<_>>(), vec![6]);
+
+        let arpeggio = Arpeggio::new(ArpDirection::Up, Grid::Sixteenth);
+        assert!(arpeggio.clone().with_gate(0.0).validate().is_err());
+        assert!(arpeggio.with_gate(1.5).validate().is_err());
+    }
+}
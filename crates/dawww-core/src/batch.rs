@@ -0,0 +1,80 @@
+use std::collections::HashMap;
+use anyhow::{Result, anyhow, bail};
+use crate::{time_to_32nds, Event, Events, Instrument, Note, UnknownFields};
+
+/// Collects many event edits so they can be validated and applied together.
+/// Created by [`crate::DawFile::batch`]; nothing is applied if any edit fails.
+pub struct BatchEditor<'a> {
+    instruments: &'a HashMap<String, Instrument>,
+    events: Events,
+    changed: bool,
+}
+
+impl<'a> BatchEditor<'a> {
+    pub(crate) fn new(instruments: &'a HashMap<String, Instrument>, events: Events) -> Self {
+        Self {
+            instruments,
+            events,
+            changed: false,
+        }
+    }
+
+    /// Events as they stand with the edits made so far
+    pub fn events(&self) -> &Events {
+        &self.events
+    }
+
+    /// Add an event, merging its notes into an existing event at the same time and instrument
+    pub fn add_event(&mut self, event: Event) -> Result<()> {
+        self.validate(&event.time, &event.instrument)?;
+        self.events.insert(event)?;
+        self.changed = true;
+        Ok(())
+    }
+
+    /// Add a note, creating its event if needed
+    pub fn add_note(&mut self, time: &str, instrument: &str, note: Note) -> Result<()> {
+        self.add_event(Event {
+            time: time.to_string(),
+            instrument: instrument.to_string(),
+            notes: vec![note],
+            extra: UnknownFields::new(),
+        })
+    }
+
+    /// Remove the event at a time and instrument
+    pub fn remove_event(&mut self, time: &str, instrument: &str) -> Result<Event> {
+        let event = self.events.remove(time, instrument)
+            .ok_or_else(|| anyhow!("Event not found at time '{}' for instrument '{}'", time, instrument))?;
+        self.changed = true;
+        Ok(event)
+    }
+
+    /// Remove a note, dropping its event if it was the last one
+    pub fn remove_note(&mut self, time: &str, instrument: &str, note: &Note) -> Result<()> {
+        let notes = &mut self.events.get_mut(time, instrument)
+            .ok_or_else(|| anyhow!("Event not found at time '{}' for instrument '{}'", time, instrument))?
+            .notes;
+        let pos = notes.iter().position(|n| n.pitch == note.pitch && n.duration == note.duration)
+            .ok_or_else(|| anyhow!("Note not found in event"))?;
+        notes.remove(pos);
+        if notes.is_empty() {
+            self.remove_event(time, instrument)?;
+        }
+        self.changed = true;
+        Ok(())
+    }
+
+    fn validate(&self, time: &str, instrument: &str) -> Result<()> {
+        time_to_32nds(time)?;
+        if !self.instruments.contains_key(instrument) {
+            bail!("Instrument '{}' not found", instrument);
+        }
+        Ok(())
+    }
+
+    /// The edited events, and whether anything changed
+    pub(crate) fn finish(self) -> (Events, bool) {
+        (self.events, self.changed)
+    }
+}
@@ -0,0 +1,298 @@
+use std::fmt;
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use anyhow::{Result, anyhow, bail};
+use crate::pitch::{Pitch, Tone};
+use crate::UnknownFields;
+
+/// Chord qualities, each a set of semitone steps above the root
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChordQuality {
+    Major,
+    Minor,
+    Diminished,
+    Augmented,
+    Sus2,
+    Sus4,
+    Power,
+    Major6,
+    Minor6,
+    Dominant7,
+    Major7,
+    Minor7,
+    MinorMajor7,
+    Diminished7,
+    HalfDiminished7,
+    Dominant7Sus4,
+    Dominant9,
+    Major9,
+    Minor9,
+    Add9,
+}
+
+/// Symbol suffixes for each quality. The first spelling of each quality is the one
+/// used when writing symbols.
+const SUFFIXES: &[(&str, ChordQuality)] = &[
+    ("", ChordQuality::Major),
+    ("maj", ChordQuality::Major),
+    ("M", ChordQuality::Major),
+    ("m", ChordQuality::Minor),
+    ("min", ChordQuality::Minor),
+    ("-", ChordQuality::Minor),
+    ("dim", ChordQuality::Diminished),
+    ("°", ChordQuality::Diminished),
+    ("o", ChordQuality::Diminished),
+    ("aug", ChordQuality::Augmented),
+    ("+", ChordQuality::Augmented),
+    ("sus2", ChordQuality::Sus2),
+    ("sus4", ChordQuality::Sus4),
+    ("sus", ChordQuality::Sus4),
+    ("5", ChordQuality::Power),
+    ("6", ChordQuality::Major6),
+    ("m6", ChordQuality::Minor6),
+    ("7", ChordQuality::Dominant7),
+    ("maj7", ChordQuality::Major7),
+    ("M7", ChordQuality::Major7),
+    ("Δ", ChordQuality::Major7),
+    ("Δ7", ChordQuality::Major7),
+    ("m7", ChordQuality::Minor7),
+    ("min7", ChordQuality::Minor7),
+    ("-7", ChordQuality::Minor7),
+    ("mMaj7", ChordQuality::MinorMajor7),
+    ("m(maj7)", ChordQuality::MinorMajor7),
+    ("mM7", ChordQuality::MinorMajor7),
+    ("dim7", ChordQuality::Diminished7),
+    ("°7", ChordQuality::Diminished7),
+    ("o7", ChordQuality::Diminished7),
+    ("m7b5", ChordQuality::HalfDiminished7),
+    ("ø", ChordQuality::HalfDiminished7),
+    ("ø7", ChordQuality::HalfDiminished7),
+    ("7sus4", ChordQuality::Dominant7Sus4),
+    ("9", ChordQuality::Dominant9),
+    ("maj9", ChordQuality::Major9),
+    ("m9", ChordQuality::Minor9),
+    ("add9", ChordQuality::Add9),
+];
+
+impl ChordQuality {
+    /// Semitones above the root of each chord tone, starting with 0
+    pub fn intervals(&self) -> &'static [u16] {
+        match self {
+            ChordQuality::Major => &[0, 4, 7],
+            ChordQuality::Minor => &[0, 3, 7],
+            ChordQuality::Diminished => &[0, 3, 6],
+            ChordQuality::Augmented => &[0, 4, 8],
+            ChordQuality::Sus2 => &[0, 2, 7],
+            ChordQuality::Sus4 => &[0, 5, 7],
+            ChordQuality::Power => &[0, 7],
+            ChordQuality::Major6 => &[0, 4, 7, 9],
+            ChordQuality::Minor6 => &[0, 3, 7, 9],
+            ChordQuality::Dominant7 => &[0, 4, 7, 10],
+            ChordQuality::Major7 => &[0, 4, 7, 11],
+            ChordQuality::Minor7 => &[0, 3, 7, 10],
+            ChordQuality::MinorMajor7 => &[0, 3, 7, 11],
+            ChordQuality::Diminished7 => &[0, 3, 6, 9],
+            ChordQuality::HalfDiminished7 => &[0, 3, 6, 10],
+            ChordQuality::Dominant7Sus4 => &[0, 5, 7, 10],
+            ChordQuality::Dominant9 => &[0, 4, 7, 10, 14],
+            ChordQuality::Major9 => &[0, 4, 7, 11, 14],
+            ChordQuality::Minor9 => &[0, 3, 7, 10, 14],
+            ChordQuality::Add9 => &[0, 4, 7, 14],
+        }
+    }
+
+    /// Suffix written after the root in a chord symbol, e.g. `m7` or `dim`
+    pub fn suffix(&self) -> &'static str {
+        SUFFIXES.iter().find(|(_, quality)| quality == self).map_or("", |(suffix, _)| suffix)
+    }
+}
+
+/// A chord built up from a root pitch, optionally inverted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chord {
+    pub root: Pitch,
+    pub quality: ChordQuality,
+    /// How many of the lowest chord tones are moved up an octave: 0 is root position,
+    /// 1 the first inversion, and so on
+    pub inversion: usize,
+}
+
+impl Chord {
+    /// The chord in root position
+    pub fn new(root: Pitch, quality: ChordQuality) -> Self {
+        Self { root, quality, inversion: 0 }
+    }
+
+    pub fn with_inversion(mut self, inversion: usize) -> Result<Self> {
+        let tones = self.quality.intervals().len();
+        if inversion >= tones {
+            bail!("A {}-note chord has no inversion {}", tones, inversion);
+        }
+        self.inversion = inversion;
+        Ok(self)
+    }
+
+    /// The chord's pitches from lowest to highest. Pitches above B8 are dropped.
+    pub fn pitches(&self) -> Vec<Pitch> {
+        let mut pitches: Vec<Pitch> = self.quality.intervals().iter()
+            .enumerate()
+            .filter_map(|(i, &interval)| {
+                let octave_up = if i < self.inversion { 12 } else { 0 };
+                self.root.transpose(interval as i32 + octave_up)
+            })
+            .collect();
+        pitches.sort();
+        pitches
+    }
+}
+
+/// A chord symbol such as `Am7`, `F#m7b5` or `C/G`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ChordSymbol {
+    pub root: Tone,
+    pub quality: ChordQuality,
+    /// Bass note for slash chords
+    pub bass: Option<Tone>,
+}
+
+impl ChordSymbol {
+    pub fn new(root: Tone, quality: ChordQuality) -> Self {
+        Self { root, quality, bass: None }
+    }
+
+    pub fn parse(symbol: &str) -> Result<Self> {
+        let invalid = || anyhow!("Invalid chord symbol '{}'", symbol);
+        let (chord, bass) = match symbol.trim().split_once('/') {
+            Some((chord, bass)) => (chord, Some(Tone::from_name(bass).ok_or_else(invalid)?)),
+            None => (symbol.trim(), None),
+        };
+
+        let root_length = match chord.as_bytes().get(1) {
+            Some(b'#') | Some(b'b') => 2,
+            _ => 1,
+        };
+        let root = chord.get(..root_length).and_then(Tone::from_name).ok_or_else(invalid)?;
+        let suffix = &chord[root_length..];
+        let quality = SUFFIXES.iter()
+            .find(|(spelling, _)| *spelling == suffix)
+            .map(|(_, quality)| *quality)
+            .ok_or_else(|| anyhow!("Unknown chord quality '{}' in '{}'", suffix, symbol))?;
+        Ok(Self { root, quality, bass })
+    }
+
+    /// The chord's tones, root first
+    pub fn tones(&self) -> Vec<Tone> {
+        self.quality.intervals().iter()
+            .map(|interval| Tone::from_index((self.root.index() + interval) % 12))
+            .collect()
+    }
+
+    /// Pitches of the chord in close position with the root in `octave`. A slash
+    /// chord's bass note is placed below the root. Pitches above B8 are dropped.
+    pub fn pitches(&self, octave: u16) -> Vec<Pitch> {
+        let root = Pitch::new(self.root, octave);
+        let bass = self.bass
+            .filter(|bass| *bass != self.root)
+            .and_then(|bass| root.transpose(-(((self.root.index() + 12 - bass.index()) % 12) as i32)));
+        bass.into_iter()
+            .chain(Chord::new(root, self.quality).pitches())
+            .collect()
+    }
+}
+
+impl fmt::Display for ChordSymbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.root.as_str(), self.quality.suffix())?;
+        if let Some(bass) = self.bass {
+            write!(f, "/{}", bass.as_str())?;
+        }
+        Ok(())
+    }
+}
+
+impl TryFrom<String> for ChordSymbol {
+    type Error = anyhow::Error;
+
+    fn try_from(symbol: String) -> Result<Self> {
+        Self::parse(&symbol)
+    }
+}
+
+impl From<ChordSymbol> for String {
+    fn from(chord: ChordSymbol) -> String {
+        chord.to_string()
+    }
+}
+
+/// A chord on the chord track, in effect from `time` until the next chord
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct ChordEvent {
+    #[schemars(regex(pattern = crate::schema::TIME_PATTERN))]
+    pub time: String,
+    #[schemars(with = "String")]
+    pub chord: ChordSymbol,
+    #[serde(flatten)]
+    pub extra: UnknownFields,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_chord_symbols() {
+        let am7 = ChordSymbol::parse("Am7").unwrap();
+        assert_eq!((am7.root, am7.quality, am7.bass), (Tone::A, ChordQuality::Minor7, None));
+        assert_eq!(am7.tones(), vec![Tone::A, Tone::C, Tone::E, Tone::G]);
+
+        assert_eq!(ChordSymbol::parse("Bbmaj7").unwrap().root, Tone::As);
+        assert_eq!(ChordSymbol::parse("F#ø").unwrap().quality, ChordQuality::HalfDiminished7);
+        assert_eq!(ChordSymbol::parse("C").unwrap().quality, ChordQuality::Major);
+        assert_eq!(ChordSymbol::parse("Cm(maj7)").unwrap().to_string(), "CmMaj7");
+        assert_eq!(ChordSymbol::parse("Db/Ab").unwrap().to_string(), "C#/G#");
+
+        for bad in ["", "H7", "Cx", "C/H", "m7"] {
+            assert!(ChordSymbol::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_chord_pitches() {
+        let c_over_g = ChordSymbol::parse("C/G").unwrap();
+        assert_eq!(c_over_g.pitches(4), vec![
+            Pitch::new(Tone::G, 3),
+            Pitch::new(Tone::C, 4),
+            Pitch::new(Tone::E, 4),
+            Pitch::new(Tone::G, 4),
+        ]);
+        let g9 = ChordSymbol::parse("G9").unwrap().pitches(3);
+        assert_eq!(g9.last(), Some(&Pitch::new(Tone::A, 4)));
+    }
+
+    #[test]
+    fn test_chord_inversions() {
+        let c4 = Pitch::new(Tone::C, 4);
+        let triad = Chord::new(c4, ChordQuality::Major);
+        assert_eq!(triad.pitches(), vec![c4, Pitch::new(Tone::E, 4), Pitch::new(Tone::G, 4)]);
+
+        let first = triad.with_inversion(1).unwrap().pitches();
+        assert_eq!(first, vec![Pitch::new(Tone::E, 4), Pitch::new(Tone::G, 4), Pitch::new(Tone::C, 5)]);
+
+        let third = Chord::new(c4, ChordQuality::Dominant7).with_inversion(3).unwrap().pitches();
+        assert_eq!(third.first(), Some(&Pitch::new(Tone::As, 4)));
+        assert_eq!(third.len(), 4);
+        assert!(triad.with_inversion(3).is_err());
+
+        // Tones past B8 are dropped
+        assert_eq!(Chord::new(Pitch::new(Tone::A, 8), ChordQuality::Major).pitches(), vec![Pitch::new(Tone::A, 8)]);
+    }
+
+    #[test]
+    fn test_chord_event_serialization() {
+        let event: ChordEvent = serde_json::from_str(r#"{"time": "2.0", "chord": "Am7"}"#).unwrap();
+        assert_eq!(event.chord, ChordSymbol::new(Tone::A, ChordQuality::Minor7));
+        assert_eq!(serde_json::to_string(&event).unwrap(), r#"{"time":"2.0","chord":"Am7"}"#);
+        assert!(serde_json::from_str::<ChordEvent>(r#"{"time": "2.0", "chord": "Zz"}"#).is_err());
+    }
+}
@@ -0,0 +1,186 @@
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use anyhow::{Result, bail};
+use crate::{time_to_32nds, UnknownFields};
+
+/// Which continuous controller a control event changes
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Controller {
+    /// Pitch offset in semitones
+    PitchBend,
+    /// Modulation wheel, 0 to 1
+    ModWheel,
+    /// Stereo position, -1 (left) to 1 (right), added to the track's pan
+    Pan,
+    /// Any other MIDI CC number, 0 to 1
+    Cc(u8),
+}
+
+impl Controller {
+    /// Validate a value for this controller
+    pub fn validate_value(&self, value: f64) -> Result<()> {
+        match self {
+            Controller::PitchBend => {
+                if !value.is_finite() || value.abs() > 48.0 {
+                    bail!("Pitch bend must be between -48 and 48 semitones, got {}", value);
+                }
+            }
+            Controller::Pan => {
+                if !(-1.0..=1.0).contains(&value) {
+                    bail!("Pan must be between -1 and 1, got {}", value);
+                }
+            }
+            Controller::ModWheel | Controller::Cc(_) => {
+                if !(0.0..=1.0).contains(&value) {
+                    bail!("Controller values must be between 0 and 1, got {}", value);
+                }
+            }
+        }
+        if let Controller::Cc(number) = self {
+            if *number > 127 {
+                bail!("CC number must be between 0 and 127, got {}", number);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How a control event is reached from the previous one
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Curve {
+    /// Jump to the value at the event time
+    #[default]
+    Step,
+    /// Ramp linearly from the previous event's value
+    Linear,
+}
+
+/// A controller value change for one instrument
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct ControlEvent {
+    #[schemars(regex(pattern = crate::schema::TIME_PATTERN))]
+    pub time: String,
+    pub instrument: String,
+    pub controller: Controller,
+    pub value: f64,
+    #[serde(default, skip_serializing_if = "is_step")]
+    pub curve: Curve,
+    #[serde(flatten)]
+    pub extra: UnknownFields,
+}
+
+fn is_step(curve: &Curve) -> bool {
+    *curve == Curve::Step
+}
+
+/// The breakpoints for one controller on one instrument, ready for lookup
+#[derive(Debug, Clone, PartialEq)]
+pub struct ControlCurve {
+    /// (position in 32nds, value, curve), sorted by position
+    points: Vec<(f64, f64, Curve)>,
+}
+
+impl ControlCurve {
+    /// Build a curve from events, which may be in any order.
+    /// Events for other instruments or controllers are ignored.
+    pub fn from_events<'a>(
+        events: impl IntoIterator<Item = &'a ControlEvent>,
+        instrument: &str,
+        controller: Controller,
+    ) -> Self {
+        let mut points: Vec<(f64, f64, Curve)> = events.into_iter()
+            .filter(|e| e.instrument == instrument && e.controller == controller)
+            .filter_map(|e| time_to_32nds(&e.time).ok().map(|t| (t as f64, e.value, e.curve)))
+            .collect();
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        Self { points }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// Controller value at `position` (in 32nds, fractional positions allowed).
+    /// Every controller rests at 0 before its first event.
+    pub fn value_at(&self, position: f64) -> f64 {
+        let next = self.points.partition_point(|p| p.0 <= position);
+        let Some(&(prev_time, prev_value, _)) = next.checked_sub(1).map(|i| &self.points[i]) else {
+            return 0.0;
+        };
+
+        match self.points.get(next) {
+            Some(&(next_time, next_value, Curve::Linear)) => {
+                let amount = (position - prev_time) / (next_time - prev_time);
+                prev_value + (next_value - prev_value) * amount
+            }
+            _ => prev_value,
+        }
+    }
+}
+
+/// Convert a pitch bend in semitones to a frequency ratio
+pub fn bend_ratio(semitones: f64) -> f64 {
+    2_f64.powf(semitones / 12.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(time: &str, controller: Controller, value: f64, curve: Curve) -> ControlEvent {
+        ControlEvent {
+            time: time.to_string(),
+            instrument: "lead".to_string(),
+            controller,
+            value,
+            curve,
+            extra: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_serialization() {
+        let events = vec![
+            event("1.0", Controller::PitchBend, 2.0, Curve::Step),
+            event("1.8", Controller::Cc(74), 0.5, Curve::Linear),
+        ];
+        let json = serde_json::to_value(&events).unwrap();
+        assert_eq!(json[0]["controller"], "pitch_bend");
+        assert!(json[0].get("curve").is_none());
+        assert_eq!(json[1]["controller"], serde_json::json!({ "cc": 74 }));
+        assert_eq!(json[1]["curve"], "linear");
+        assert_eq!(serde_json::from_value::<Vec<ControlEvent>>(json).unwrap(), events);
+    }
+
+    #[test]
+    fn test_curve_interpolation() {
+        let events = vec![
+            event("1.16", Controller::PitchBend, 2.0, Curve::Linear),
+            event("1.0", Controller::PitchBend, 0.0, Curve::Step),
+            event("2.0", Controller::PitchBend, -1.0, Curve::Step),
+            event("1.4", Controller::ModWheel, 1.0, Curve::Step),
+        ];
+        let curve = ControlCurve::from_events(&events, "lead", Controller::PitchBend);
+
+        assert_eq!(curve.value_at(-1.0), 0.0);
+        assert_eq!(curve.value_at(8.0), 1.0);
+        assert_eq!(curve.value_at(16.0), 2.0);
+        assert_eq!(curve.value_at(31.0), 2.0);
+        assert_eq!(curve.value_at(40.0), -1.0);
+        assert!(ControlCurve::from_events(&events, "bass", Controller::PitchBend).is_empty());
+    }
+
+    #[test]
+    fn test_value_validation() {
+        assert!(Controller::PitchBend.validate_value(-12.0).is_ok());
+        assert!(Controller::PitchBend.validate_value(60.0).is_err());
+        assert!(Controller::ModWheel.validate_value(1.5).is_err());
+        assert!(Controller::Pan.validate_value(-0.5).is_ok());
+        assert!(Controller::Pan.validate_value(-1.5).is_err());
+        assert!(Controller::Cc(128).validate_value(0.5).is_err());
+        assert!((bend_ratio(12.0) - 2.0).abs() < 1e-12);
+    }
+}
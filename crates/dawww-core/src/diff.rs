@@ -0,0 +1,267 @@
+use std::collections::{BTreeMap, BTreeSet};
+use crate::pitch::Tuning;
+use crate::{time_from_32nds, time_to_32nds, ChordEvent, ControlEvent, DawFile, Event, Events, Groove, Instrument, Key, LyricEvent, MixdownSettings, Mixer};
+
+/// How one keyed item differs between two versions of a song
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change<T> {
+    Added(T),
+    Removed(T),
+    Changed { from: T, to: T },
+}
+
+/// Events are identified by their position in 32nds and their instrument
+pub type EventKey = (u32, String);
+
+/// Everything that differs between two versions of a song. Scalar settings are
+/// `Some((old, new))` when they changed.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ProjectDiff {
+    pub title: Option<(String, String)>,
+    pub bpm: Option<(u32, u32)>,
+    pub groove: Option<(Groove, Groove)>,
+    pub tuning: Option<(Tuning, Tuning)>,
+    pub key: Option<(Option<Key>, Option<Key>)>,
+    pub mixdown: Option<(MixdownSettings, MixdownSettings)>,
+    pub mixdown_profiles: BTreeMap<String, Change<MixdownSettings>>,
+    pub mixer: Option<(Mixer, Mixer)>,
+    pub controls: Option<(Vec<ControlEvent>, Vec<ControlEvent>)>,
+    pub chords: Option<(Vec<ChordEvent>, Vec<ChordEvent>)>,
+    pub lyrics: Option<(Vec<LyricEvent>, Vec<LyricEvent>)>,
+    pub instruments: BTreeMap<String, Change<Instrument>>,
+    pub events: BTreeMap<EventKey, Change<Event>>,
+}
+
+impl ProjectDiff {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Something both sides of a merge changed differently. The merged song keeps "our" version.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MergeConflict {
+    /// What conflicted, e.g. `bpm`, `instruments/lead` or `events/1.4/lead`
+    pub path: String,
+}
+
+/// The result of a three-way merge
+#[derive(Debug, Clone)]
+pub struct MergeOutcome {
+    pub merged: DawFile,
+    pub conflicts: Vec<MergeConflict>,
+}
+
+impl MergeOutcome {
+    pub fn is_clean(&self) -> bool {
+        self.conflicts.is_empty()
+    }
+}
+
+pub(crate) fn diff(old: &DawFile, new: &DawFile) -> ProjectDiff {
+    ProjectDiff {
+        title: changed(&old.metadata.title, &new.metadata.title),
+        bpm: changed(&old.bpm, &new.bpm),
+        groove: changed(&old.groove, &new.groove),
+        tuning: changed(&old.tuning, &new.tuning),
+        key: changed(&old.metadata.key, &new.metadata.key),
+        mixdown: changed(&old.mixdown, &new.mixdown),
+        mixdown_profiles: diff_maps(&profile_map(old), &profile_map(new)),
+        mixer: changed(&old.mixer, &new.mixer),
+        controls: changed(&old.controls, &new.controls),
+        chords: changed(&old.chords, &new.chords),
+        lyrics: changed(&old.lyrics, &new.lyrics),
+        instruments: diff_maps(&instrument_map(old), &instrument_map(new)),
+        events: diff_maps(&event_map(old), &event_map(new)),
+    }
+}
+
+pub(crate) fn merge(base: &DawFile, ours: &DawFile, theirs: &DawFile) -> MergeOutcome {
+    let mut conflicts = Vec::new();
+    let mut merged = ours.clone();
+
+    let c = &mut conflicts;
+    merged.metadata.title = pick("title", &base.metadata.title, &ours.metadata.title, &theirs.metadata.title, c).clone();
+    merged.bpm = *pick("bpm", &base.bpm, &ours.bpm, &theirs.bpm, c);
+    merged.groove = pick("groove", &base.groove, &ours.groove, &theirs.groove, c).clone();
+    merged.tuning = pick("tuning", &base.tuning, &ours.tuning, &theirs.tuning, c).clone();
+    merged.metadata.key = pick("key", &base.metadata.key, &ours.metadata.key, &theirs.metadata.key, c).clone();
+    merged.mixdown = pick("mixdown", &base.mixdown, &ours.mixdown, &theirs.mixdown, c).clone();
+    merged.mixdown_profiles = merge_maps(&profile_map(base), &profile_map(ours), &profile_map(theirs), &mut |name| {
+        c.push(MergeConflict { path: format!("mixdown_profiles/{}", name) });
+    });
+    merged.mixer = pick("mixer", &base.mixer, &ours.mixer, &theirs.mixer, c).clone();
+    merged.controls = pick("controls", &base.controls, &ours.controls, &theirs.controls, c).clone();
+    merged.chords = pick("chords", &base.chords, &ours.chords, &theirs.chords, c).clone();
+    merged.lyrics = pick("lyrics", &base.lyrics, &ours.lyrics, &theirs.lyrics, c).clone();
+    merged.samples = pick("samples", &base.samples, &ours.samples, &theirs.samples, c).clone();
+    merged.extra = pick("extra", &base.extra, &ours.extra, &theirs.extra, c).clone();
+    merged.metadata.revision = ours.metadata.revision.max(theirs.metadata.revision);
+    merged.metadata.edit_count = ours.metadata.edit_count.max(theirs.metadata.edit_count);
+
+    merged.instruments = merge_maps(&instrument_map(base), &instrument_map(ours), &instrument_map(theirs), &mut |name| {
+        conflicts.push(MergeConflict { path: format!("instruments/{}", name) });
+    })
+    .into_iter()
+    .collect();
+
+    let events = merge_maps(&event_map(base), &event_map(ours), &event_map(theirs), &mut |(position, instrument)| {
+        conflicts.push(MergeConflict { path: format!("events/{}/{}", time_from_32nds(*position), instrument) });
+    });
+    merged.events = Events::try_from(events.into_values().collect::<Vec<_>>())
+        .expect("merged events are keyed by valid positions");
+
+    MergeOutcome { merged, conflicts }
+}
+
+fn changed<T: PartialEq + Clone>(old: &T, new: &T) -> Option<(T, T)> {
+    (old != new).then(|| (old.clone(), new.clone()))
+}
+
+fn instrument_map(daw: &DawFile) -> BTreeMap<String, &Instrument> {
+    daw.instruments.iter().map(|(name, i)| (name.clone(), i)).collect()
+}
+
+fn profile_map(daw: &DawFile) -> BTreeMap<String, &MixdownSettings> {
+    daw.mixdown_profiles.iter().map(|(name, p)| (name.clone(), p)).collect()
+}
+
+fn event_map(daw: &DawFile) -> BTreeMap<EventKey, &Event> {
+    daw.events.iter()
+        .map(|e| ((time_to_32nds(&e.time).unwrap_or(0), e.instrument.clone()), e))
+        .collect()
+}
+
+fn diff_maps<K: Ord + Clone, V: PartialEq + Clone>(old: &BTreeMap<K, &V>, new: &BTreeMap<K, &V>) -> BTreeMap<K, Change<V>> {
+    let keys: BTreeSet<&K> = old.keys().chain(new.keys()).collect();
+    keys.into_iter()
+        .filter_map(|key| {
+            let change = match (old.get(key), new.get(key)) {
+                (Some(from), Some(to)) if from == to => return None,
+                (Some(from), Some(to)) => Change::Changed { from: (*from).clone(), to: (*to).clone() },
+                (Some(from), None) => Change::Removed((*from).clone()),
+                (None, Some(to)) => Change::Added((*to).clone()),
+                (None, None) => return None,
+            };
+            Some((key.clone(), change))
+        })
+        .collect()
+}
+
+/// Pick the merged value, or `None` if both sides changed it differently
+fn merge_value<'a, T: PartialEq>(base: &'a T, ours: &'a T, theirs: &'a T) -> Option<&'a T> {
+    if ours == theirs || theirs == base {
+        Some(ours)
+    } else if ours == base {
+        Some(theirs)
+    } else {
+        None
+    }
+}
+
+/// Merge one setting, recording a conflict and keeping ours if both sides changed it
+fn pick<'a, T: PartialEq>(path: &str, base: &'a T, ours: &'a T, theirs: &'a T, conflicts: &mut Vec<MergeConflict>) -> &'a T {
+    merge_value(base, ours, theirs).unwrap_or_else(|| {
+        conflicts.push(MergeConflict { path: path.to_string() });
+        ours
+    })
+}
+
+fn merge_maps<K: Ord + Clone, V: PartialEq + Clone>(
+    base: &BTreeMap<K, &V>,
+    ours: &BTreeMap<K, &V>,
+    theirs: &BTreeMap<K, &V>,
+    on_conflict: &mut impl FnMut(&K),
+) -> BTreeMap<K, V> {
+    let keys: BTreeSet<&K> = base.keys().chain(ours.keys()).chain(theirs.keys()).collect();
+    keys.into_iter()
+        .filter_map(|key| {
+            let (b, o, t) = (base.get(key), ours.get(key), theirs.get(key));
+            let merged = *merge_value(&b, &o, &t).unwrap_or_else(|| {
+                on_conflict(key);
+                &o
+            });
+            merged.map(|value| (key.clone(), (*value).clone()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pitch::{Pitch, Tone};
+    use crate::Note;
+    use std::path::PathBuf;
+
+    fn base_song() -> DawFile {
+        let mut daw = DawFile::new("Base".to_string());
+        daw.create_sampler_instrument("drums".to_string(), PathBuf::from("kick.wav")).unwrap();
+        daw.create_sampler_instrument("bass".to_string(), PathBuf::from("bass.wav")).unwrap();
+        daw.add_note("1.0", "drums", Note::new(Pitch::new(Tone::C, 2), 4)).unwrap();
+        daw.add_note("1.0", "bass", Note::new(Pitch::new(Tone::E, 2), 8)).unwrap();
+        daw
+    }
+
+    fn note(tone: Tone) -> Note {
+        Note::new(Pitch::new(tone, 3), 8)
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = base_song();
+        assert!(old.diff(&old).is_empty());
+
+        let mut new = old.clone();
+        new.bpm = 96;
+        new.remove_event("1.0", "drums").unwrap();
+        new.remove_instrument("drums").unwrap();
+        new.add_note("1.0", "bass", note(Tone::G)).unwrap();
+        new.add_note("2.8", "bass", note(Tone::A)).unwrap();
+
+        let diff = old.diff(&new);
+        assert_eq!(diff.bpm, Some((120, 96)));
+        assert_eq!(diff.title, None);
+        assert!(matches!(diff.instruments["drums"], Change::Removed(_)));
+        assert_eq!(diff.instruments.len(), 1);
+        assert!(matches!(diff.events[&(0, "drums".to_string())], Change::Removed(_)));
+        assert!(matches!(&diff.events[&(0, "bass".to_string())], Change::Changed { to, .. } if to.notes.len() == 2));
+        assert!(matches!(diff.events[&(40, "bass".to_string())], Change::Added(_)));
+    }
+
+    #[test]
+    fn test_clean_merge() {
+        let base = base_song();
+        let mut ours = base.clone();
+        ours.bpm = 90;
+        ours.add_note("2.0", "bass", note(Tone::A)).unwrap();
+        let mut theirs = base.clone();
+        theirs.set_title("Theirs".to_string());
+        theirs.remove_event("1.0", "drums").unwrap();
+        theirs.add_note("3.0", "bass", note(Tone::B)).unwrap();
+
+        let outcome = DawFile::merge(&base, &ours, &theirs);
+        assert!(outcome.is_clean());
+        let merged = outcome.merged;
+        assert_eq!(merged.bpm, 90);
+        assert_eq!(merged.metadata.title, "Theirs");
+        let events: Vec<(&str, &str)> = merged.events.iter().map(|e| (e.time.as_str(), e.instrument.as_str())).collect();
+        assert_eq!(events, vec![("1.0", "bass"), ("2.0", "bass"), ("3.0", "bass")]);
+    }
+
+    #[test]
+    fn test_conflicting_merge_keeps_ours() {
+        let base = base_song();
+        let mut ours = base.clone();
+        ours.bpm = 90;
+        ours.add_note("1.0", "bass", note(Tone::G)).unwrap();
+        let mut theirs = base.clone();
+        theirs.bpm = 140;
+        theirs.remove_event("1.0", "bass").unwrap();
+
+        let outcome = DawFile::merge(&base, &ours, &theirs);
+        let paths: Vec<&str> = outcome.conflicts.iter().map(|c| c.path.as_str()).collect();
+        assert_eq!(paths, vec!["bpm", "events/1.0/bass"]);
+        assert_eq!(outcome.merged.bpm, 90);
+        assert_eq!(outcome.merged.events.iter().find(|e| e.instrument == "bass").unwrap().notes.len(), 2);
+    }
+}
@@ -0,0 +1,114 @@
+use anyhow::{Result, anyhow, bail};
+use crate::pitch::{Pitch, Tone};
+use crate::steps::{parse_pitch, Step, StepPattern};
+use crate::Note;
+
+/// Lane names understood by [`DrumPattern`], with their General MIDI drum pitches
+const DRUM_NAMES: &[(&str, Tone, u16)] = &[
+    ("kick", Tone::C, 2),
+    ("bd", Tone::C, 2),
+    ("rim", Tone::Cs, 2),
+    ("snare", Tone::D, 2),
+    ("sd", Tone::D, 2),
+    ("clap", Tone::Ds, 2),
+    ("hat", Tone::Fs, 2),
+    ("hh", Tone::Fs, 2),
+    ("tom_low", Tone::A, 2),
+    ("open_hat", Tone::As, 2),
+    ("oh", Tone::As, 2),
+    ("tom_mid", Tone::B, 2),
+    ("crash", Tone::Cs, 3),
+    ("tom_high", Tone::D, 3),
+    ("ride", Tone::Ds, 3),
+];
+
+/// Pitch for a drum lane name (`kick`, `snare`, `hat`, ...) or an explicit pitch (`C2`)
+pub fn drum_pitch(name: &str) -> Result<Pitch> {
+    let lower = name.to_lowercase();
+    DRUM_NAMES.iter()
+        .find(|(drum, _, _)| *drum == lower)
+        .map(|&(_, tone, octave)| Pitch::new(tone, octave))
+        .map_or_else(|| parse_pitch(name), Ok)
+        .map_err(|_| anyhow!("Unknown drum '{}'", name))
+}
+
+/// A multi-lane drum pattern, one lane per line:
+///
+/// ```text
+/// kick:  x... x... x... x...
+/// snare: .... x... .... x...
+/// hat:   x.x. x.x. x.x. x.x.
+/// ```
+///
+/// Each lane is a drum-style [`StepPattern`] played on the pitch named by its label
+/// (see [`drum_pitch`]). Blank lines and lines starting with `#` are ignored.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DrumPattern {
+    pub lanes: Vec<(Pitch, StepPattern)>,
+}
+
+impl DrumPattern {
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut lanes = Vec::new();
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (name, steps) = line.split_once(':')
+                .ok_or_else(|| anyhow!("Drum lane '{}' should look like 'name: x...'", line))?;
+            let pattern = StepPattern::parse(steps)?;
+            if pattern.steps.iter().any(|s| matches!(s, Step::Note(_))) {
+                bail!("Drum lane '{}' can only contain x, -, . and |", name.trim());
+            }
+            lanes.push((drum_pitch(name.trim())?, pattern));
+        }
+
+        if lanes.is_empty() {
+            bail!("Drum pattern has no lanes");
+        }
+        Ok(Self { lanes })
+    }
+
+    /// Expand every lane into `(offset in 32nds, note)` pairs, sorted by offset
+    pub fn expand(&self, step_length: u32) -> Vec<(u32, Note)> {
+        let mut notes: Vec<(u32, Note)> = self.lanes.iter()
+            .flat_map(|(pitch, pattern)| pattern.expand(step_length, *pitch))
+            .collect();
+        notes.sort_by_key(|(offset, _)| *offset);
+        notes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_expand() {
+        let pattern = DrumPattern::parse("
+            # four on the floor
+            kick:  x--- x--- x--- x---
+            Snare: .... x... | .... x...
+            F#2:   xx
+        ").unwrap();
+        assert_eq!(pattern.lanes.len(), 3);
+        assert_eq!(pattern.lanes[1].0, Pitch::new(Tone::D, 2));
+
+        let notes = pattern.expand(2);
+        let kicks: Vec<(u32, u32)> = notes.iter()
+            .filter(|(_, n)| n.pitch == Pitch::new(Tone::C, 2))
+            .map(|(offset, n)| (*offset, n.duration))
+            .collect();
+        assert_eq!(kicks, vec![(0, 8), (8, 8), (16, 8), (24, 8)]);
+        assert_eq!(notes.len(), 8);
+        assert!(notes.windows(2).all(|w| w[0].0 <= w[1].0));
+    }
+
+    #[test]
+    fn test_invalid_patterns() {
+        assert!(DrumPattern::parse("").is_err());
+        assert!(DrumPattern::parse("kick x...").is_err());
+        assert!(DrumPattern::parse("cowbell: x...").is_err());
+        assert!(DrumPattern::parse("kick: C4 E4").is_err());
+    }
+}
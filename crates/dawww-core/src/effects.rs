@@ -0,0 +1,576 @@
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use anyhow::{Result, anyhow, bail};
+use std::fmt;
+use crate::UnknownFields;
+
+/// One effect in an instrument's insert chain. Serializes as
+/// `{"type": "gain", "parameters": {...}}`, matching the instrument layout.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct EffectInstance {
+    #[serde(flatten)]
+    pub effect: Effect,
+    /// Bypassed effects stay in the chain but are skipped when rendering
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub bypass: bool,
+    #[serde(flatten)]
+    pub extra: UnknownFields,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq)]
+#[serde(tag = "type", content = "parameters", rename_all = "lowercase")]
+pub enum Effect {
+    Gain(GainParams),
+    Eq(EqParams),
+    Delay(DelayParams),
+    Reverb(ReverbParams),
+    Distortion(DistortionParams),
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct GainParams {
+    pub gain_db: f64,
+    #[serde(flatten)]
+    pub extra: UnknownFields,
+}
+
+/// Three-band EQ: low shelf, peaking mid, and high shelf
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct EqParams {
+    #[serde(default)]
+    pub low_gain_db: f64,
+    #[serde(default = "default_low_frequency")]
+    pub low_frequency: f64,
+    #[serde(default)]
+    pub mid_gain_db: f64,
+    #[serde(default = "default_mid_frequency")]
+    pub mid_frequency: f64,
+    #[serde(default = "default_mid_q")]
+    pub mid_q: f64,
+    #[serde(default)]
+    pub high_gain_db: f64,
+    #[serde(default = "default_high_frequency")]
+    pub high_frequency: f64,
+    #[serde(flatten)]
+    pub extra: UnknownFields,
+}
+
+fn default_low_frequency() -> f64 {
+    200.0
+}
+
+fn default_mid_frequency() -> f64 {
+    1000.0
+}
+
+fn default_mid_q() -> f64 {
+    0.707
+}
+
+fn default_high_frequency() -> f64 {
+    5000.0
+}
+
+impl Default for EqParams {
+    fn default() -> Self {
+        Self {
+            low_gain_db: 0.0,
+            low_frequency: default_low_frequency(),
+            mid_gain_db: 0.0,
+            mid_frequency: default_mid_frequency(),
+            mid_q: default_mid_q(),
+            high_gain_db: 0.0,
+            high_frequency: default_high_frequency(),
+            extra: UnknownFields::new(),
+        }
+    }
+}
+
+/// Feedback delay. `feedback` and `mix` are 0..1. With `ping_pong` the repeats alternate
+/// between the channels, and `low_cut`/`high_cut` (Hz) filter each repeat.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct DelayParams {
+    pub time: DelayTime,
+    #[serde(default)]
+    pub feedback: f64,
+    #[serde(default = "default_mix")]
+    pub mix: f64,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub ping_pong: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub low_cut: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub high_cut: Option<f64>,
+    #[serde(flatten)]
+    pub extra: UnknownFields,
+}
+
+fn default_mix() -> f64 {
+    0.5
+}
+
+/// A delay time, either in seconds or as a note value that follows the project tempo
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq)]
+#[serde(untagged)]
+pub enum DelayTime {
+    Seconds(f64),
+    Synced(#[schemars(with = "String")] NoteValue),
+}
+
+impl DelayTime {
+    /// Length in seconds at `bpm` quarter notes per minute
+    pub fn seconds(&self, bpm: f64) -> f64 {
+        match self {
+            DelayTime::Seconds(seconds) => *seconds,
+            DelayTime::Synced(note) => note.seconds(bpm),
+        }
+    }
+}
+
+impl From<f64> for DelayTime {
+    fn from(seconds: f64) -> Self {
+        DelayTime::Seconds(seconds)
+    }
+}
+
+impl From<NoteValue> for DelayTime {
+    fn from(note: NoteValue) -> Self {
+        DelayTime::Synced(note)
+    }
+}
+
+/// How a note value's length is modified
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoteFeel {
+    Straight,
+    /// One and a half times as long
+    Dotted,
+    /// Two thirds as long
+    Triplet,
+}
+
+/// A note length as a fraction of a whole note, written `1/8`, `3/16`, `1/8.` (dotted)
+/// or `1/8t` (triplet)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct NoteValue {
+    pub numerator: u32,
+    pub denominator: u32,
+    pub feel: NoteFeel,
+}
+
+impl NoteValue {
+    pub fn new(numerator: u32, denominator: u32) -> Self {
+        Self { numerator, denominator, feel: NoteFeel::Straight }
+    }
+
+    pub fn dotted(self) -> Self {
+        Self { feel: NoteFeel::Dotted, ..self }
+    }
+
+    pub fn triplet(self) -> Self {
+        Self { feel: NoteFeel::Triplet, ..self }
+    }
+
+    pub fn parse(value: &str) -> Result<Self> {
+        let invalid = || anyhow!("Invalid note value '{}'. Expected e.g. '1/8', '1/8.' or '1/8t'", value);
+        let value = value.trim();
+        let (fraction, feel) = if let Some(fraction) = value.strip_suffix('.') {
+            (fraction, NoteFeel::Dotted)
+        } else if let Some(fraction) = value.strip_suffix('t') {
+            (fraction, NoteFeel::Triplet)
+        } else {
+            (value, NoteFeel::Straight)
+        };
+        let (numerator, denominator) = fraction.split_once('/').ok_or_else(invalid)?;
+        let numerator = numerator.parse::<u32>().map_err(|_| invalid())?;
+        let denominator = denominator.parse::<u32>().map_err(|_| invalid())?;
+        if numerator == 0 || denominator == 0 {
+            bail!("Note value '{}' must be longer than zero", value);
+        }
+        Ok(Self { numerator, denominator, feel })
+    }
+
+    /// Length in quarter notes
+    pub fn beats(&self) -> f64 {
+        let straight = 4.0 * self.numerator as f64 / self.denominator as f64;
+        match self.feel {
+            NoteFeel::Straight => straight,
+            NoteFeel::Dotted => straight * 1.5,
+            NoteFeel::Triplet => straight * 2.0 / 3.0,
+        }
+    }
+
+    /// Length in seconds at `bpm` quarter notes per minute
+    pub fn seconds(&self, bpm: f64) -> f64 {
+        self.beats() * 60.0 / bpm
+    }
+}
+
+impl fmt::Display for NoteValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.numerator, self.denominator)?;
+        match self.feel {
+            NoteFeel::Straight => Ok(()),
+            NoteFeel::Dotted => write!(f, "."),
+            NoteFeel::Triplet => write!(f, "t"),
+        }
+    }
+}
+
+impl TryFrom<String> for NoteValue {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self> {
+        Self::parse(&value)
+    }
+}
+
+impl From<NoteValue> for String {
+    fn from(value: NoteValue) -> String {
+        value.to_string()
+    }
+}
+
+/// Algorithmic room reverb. `room_size`, `damping` (how quickly high frequencies die away)
+/// and `mix` are 0..1. Use a `mix` of 1 on a send bus.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct ReverbParams {
+    #[serde(default = "default_room_size")]
+    pub room_size: f64,
+    #[serde(default = "default_damping")]
+    pub damping: f64,
+    #[serde(default = "default_reverb_mix")]
+    pub mix: f64,
+    #[serde(flatten)]
+    pub extra: UnknownFields,
+}
+
+fn default_room_size() -> f64 {
+    0.5
+}
+
+fn default_damping() -> f64 {
+    0.5
+}
+
+fn default_reverb_mix() -> f64 {
+    0.3
+}
+
+impl Default for ReverbParams {
+    fn default() -> Self {
+        Self {
+            room_size: default_room_size(),
+            damping: default_damping(),
+            mix: default_reverb_mix(),
+            extra: UnknownFields::new(),
+        }
+    }
+}
+
+/// How the distortion shapes the signal
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum DistortionMode {
+    /// Cubic curve that flattens smoothly into hard clipping
+    SoftClip,
+    /// Hyperbolic tangent saturation, which never quite clips
+    #[default]
+    Tanh,
+    /// Quantization to `bits` bits
+    Bitcrush,
+}
+
+/// Waveshaping distortion. `drive_db` boosts the signal into the shaper, `tone` (0..1) darkens
+/// the result as it falls, and `mix` (0..1) blends it with the dry signal.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct DistortionParams {
+    #[serde(default)]
+    pub mode: DistortionMode,
+    #[serde(default = "default_drive_db")]
+    pub drive_db: f64,
+    #[serde(default = "default_tone")]
+    pub tone: f64,
+    #[serde(default = "default_distortion_mix")]
+    pub mix: f64,
+    /// Bit depth for `bitcrush`
+    #[serde(default = "default_bits")]
+    pub bits: u32,
+    #[serde(flatten)]
+    pub extra: UnknownFields,
+}
+
+fn default_drive_db() -> f64 {
+    12.0
+}
+
+fn default_tone() -> f64 {
+    1.0
+}
+
+fn default_distortion_mix() -> f64 {
+    1.0
+}
+
+fn default_bits() -> u32 {
+    8
+}
+
+impl Default for DistortionParams {
+    fn default() -> Self {
+        Self {
+            mode: DistortionMode::default(),
+            drive_db: default_drive_db(),
+            tone: default_tone(),
+            mix: default_distortion_mix(),
+            bits: default_bits(),
+            extra: UnknownFields::new(),
+        }
+    }
+}
+
+impl DistortionParams {
+    pub fn new(mode: DistortionMode, drive_db: f64) -> Self {
+        Self { mode, drive_db, ..Self::default() }
+    }
+}
+
+impl GainParams {
+    pub fn new(gain_db: f64) -> Self {
+        Self { gain_db, extra: UnknownFields::new() }
+    }
+}
+
+impl DelayParams {
+    pub fn new(time: impl Into<DelayTime>, feedback: f64, mix: f64) -> Self {
+        Self {
+            time: time.into(),
+            feedback,
+            mix,
+            ping_pong: false,
+            low_cut: None,
+            high_cut: None,
+            extra: UnknownFields::new(),
+        }
+    }
+
+    pub fn with_ping_pong(mut self) -> Self {
+        self.ping_pong = true;
+        self
+    }
+
+    /// Filter the repeats, cutting below `low_cut` and above `high_cut` Hz
+    pub fn with_filter(mut self, low_cut: Option<f64>, high_cut: Option<f64>) -> Self {
+        self.low_cut = low_cut;
+        self.high_cut = high_cut;
+        self
+    }
+}
+
+/// Longest delay time accepted, in seconds. Synced delays are capped to it when rendering.
+pub const MAX_DELAY_SECONDS: f64 = 10.0;
+
+impl EffectInstance {
+    pub fn new(effect: Effect) -> Self {
+        Self { effect, bypass: false, extra: UnknownFields::new() }
+    }
+
+    /// Validate the effect parameters
+    pub fn validate(&self) -> Result<()> {
+        self.effect.validate()
+    }
+}
+
+impl Effect {
+    /// The `type` string used in project files
+    pub fn effect_type(&self) -> &str {
+        match self {
+            Effect::Gain(_) => "gain",
+            Effect::Eq(_) => "eq",
+            Effect::Delay(_) => "delay",
+            Effect::Reverb(_) => "reverb",
+            Effect::Distortion(_) => "distortion",
+        }
+    }
+
+    /// Validate the effect parameters
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            Effect::Gain(params) => {
+                if !params.gain_db.is_finite() {
+                    bail!("Gain must be a finite number of dB");
+                }
+            }
+            Effect::Eq(params) => {
+                for (name, frequency) in [
+                    ("low_frequency", params.low_frequency),
+                    ("mid_frequency", params.mid_frequency),
+                    ("high_frequency", params.high_frequency),
+                ] {
+                    if frequency <= 0.0 || frequency.is_nan() {
+                        bail!("EQ {} must be positive, got {}", name, frequency);
+                    }
+                }
+                if params.mid_q <= 0.0 || params.mid_q.is_nan() {
+                    bail!("EQ mid_q must be positive, got {}", params.mid_q);
+                }
+                for gain in [params.low_gain_db, params.mid_gain_db, params.high_gain_db] {
+                    if !gain.is_finite() {
+                        bail!("EQ gains must be finite numbers of dB");
+                    }
+                }
+            }
+            Effect::Delay(params) => {
+                if let DelayTime::Seconds(time) = params.time {
+                    if time <= 0.0 || time > MAX_DELAY_SECONDS || time.is_nan() {
+                        bail!("Delay time must be between 0 and {} seconds, got {}", MAX_DELAY_SECONDS, time);
+                    }
+                }
+                if !(0.0..1.0).contains(&params.feedback) {
+                    bail!("Delay feedback must be at least 0 and less than 1, got {}", params.feedback);
+                }
+                if !(0.0..=1.0).contains(&params.mix) {
+                    bail!("Delay mix must be between 0 and 1, got {}", params.mix);
+                }
+                for (name, frequency) in [("low_cut", params.low_cut), ("high_cut", params.high_cut)] {
+                    if frequency.is_some_and(|f| f <= 0.0 || f.is_nan()) {
+                        bail!("Delay {} must be a positive frequency, got {}", name, frequency.unwrap());
+                    }
+                }
+            }
+            Effect::Reverb(params) => {
+                for (name, value) in [("room_size", params.room_size), ("damping", params.damping), ("mix", params.mix)] {
+                    if !(0.0..=1.0).contains(&value) {
+                        bail!("Reverb {} must be between 0 and 1, got {}", name, value);
+                    }
+                }
+            }
+            Effect::Distortion(params) => {
+                if !params.drive_db.is_finite() {
+                    bail!("Distortion drive must be a finite number of dB");
+                }
+                for (name, value) in [("tone", params.tone), ("mix", params.mix)] {
+                    if !(0.0..=1.0).contains(&value) {
+                        bail!("Distortion {} must be between 0 and 1, got {}", name, value);
+                    }
+                }
+                if !(1..=24).contains(&params.bits) {
+                    bail!("Distortion bits must be between 1 and 24, got {}", params.bits);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_effect_serialization() {
+        let chain = vec![
+            EffectInstance::new(Effect::Gain(GainParams::new(-6.0))),
+            EffectInstance {
+                effect: Effect::Delay(DelayParams::new(0.25, 0.4, 0.3)),
+                bypass: true,
+                extra: Default::default(),
+            },
+        ];
+
+        let json = serde_json::to_value(&chain).unwrap();
+        assert_eq!(json[0], serde_json::json!({ "type": "gain", "parameters": { "gain_db": -6.0 } }));
+        assert_eq!(json[1]["type"], "delay");
+        assert_eq!(json[1]["bypass"], true);
+
+        let chain2: Vec<EffectInstance> = serde_json::from_value(json).unwrap();
+        assert_eq!(chain, chain2);
+    }
+
+    #[test]
+    fn test_eq_defaults() {
+        let effect: EffectInstance = serde_json::from_value(serde_json::json!({
+            "type": "eq",
+            "parameters": { "low_gain_db": 3.0 }
+        })).unwrap();
+        assert_eq!(effect.effect, Effect::Eq(EqParams { low_gain_db: 3.0, ..EqParams::default() }));
+        assert!(!effect.bypass);
+    }
+
+    #[test]
+    fn test_effect_validation() {
+        assert!(Effect::Gain(GainParams::new(3.0)).validate().is_ok());
+        assert!(Effect::Gain(GainParams::new(f64::INFINITY)).validate().is_err());
+        assert!(Effect::Eq(EqParams::default()).validate().is_ok());
+        assert!(Effect::Eq(EqParams { mid_frequency: 0.0, ..EqParams::default() }).validate().is_err());
+        assert!(Effect::Delay(DelayParams::new(0.5, 0.5, 0.5)).validate().is_ok());
+        assert!(Effect::Delay(DelayParams::new(0.5, 1.0, 0.5)).validate().is_err());
+        assert!(Effect::Delay(DelayParams::new(0.0, 0.5, 0.5)).validate().is_err());
+        assert!(Effect::Delay(DelayParams::new(0.5, 0.5, 0.5).with_filter(Some(0.0), None)).validate().is_err());
+        assert!(Effect::Reverb(ReverbParams::default()).validate().is_ok());
+        assert!(Effect::Reverb(ReverbParams { room_size: 1.2, ..ReverbParams::default() }).validate().is_err());
+        assert!(Effect::Distortion(DistortionParams::default()).validate().is_ok());
+        assert!(Effect::Distortion(DistortionParams { bits: 0, ..DistortionParams::default() }).validate().is_err());
+        assert!(Effect::Distortion(DistortionParams { tone: -0.1, ..DistortionParams::default() }).validate().is_err());
+    }
+
+    #[test]
+    fn test_reverb_defaults() {
+        let effect: Effect = serde_json::from_value(serde_json::json!({
+            "type": "reverb",
+            "parameters": { "room_size": 0.8 }
+        })).unwrap();
+        assert_eq!(effect, Effect::Reverb(ReverbParams { room_size: 0.8, ..ReverbParams::default() }));
+        assert_eq!(effect.effect_type(), "reverb");
+    }
+
+    #[test]
+    fn test_distortion_defaults() {
+        let effect: Effect = serde_json::from_value(serde_json::json!({
+            "type": "distortion",
+            "parameters": { "mode": "soft_clip", "drive_db": 6.0 }
+        })).unwrap();
+        assert_eq!(effect, Effect::Distortion(DistortionParams::new(DistortionMode::SoftClip, 6.0)));
+        assert_eq!(effect.effect_type(), "distortion");
+    }
+
+    #[test]
+    fn test_note_values() {
+        assert_eq!(NoteValue::parse("1/8").unwrap(), NoteValue::new(1, 8));
+        assert_eq!(NoteValue::parse("1/8.").unwrap(), NoteValue::new(1, 8).dotted());
+        assert_eq!(NoteValue::parse("1/4t").unwrap(), NoteValue::new(1, 4).triplet());
+        assert_eq!(NoteValue::new(3, 16).to_string(), "3/16");
+        assert_eq!(NoteValue::new(1, 8).dotted().to_string(), "1/8.");
+        assert!(NoteValue::parse("1/0").is_err());
+        assert!(NoteValue::parse("eighth").is_err());
+
+        assert_eq!(NoteValue::new(1, 4).seconds(120.0), 0.5);
+        assert_eq!(NoteValue::new(1, 8).dotted().seconds(120.0), 0.375);
+        assert!((NoteValue::new(1, 4).triplet().seconds(120.0) - 1.0 / 3.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_synced_delay_serialization() {
+        let effect: Effect = serde_json::from_value(serde_json::json!({
+            "type": "delay",
+            "parameters": { "time": "1/8.", "feedback": 0.4, "ping_pong": true, "high_cut": 4000.0 }
+        })).unwrap();
+        let Effect::Delay(params) = &effect else { panic!("expected a delay") };
+        assert_eq!(params.time, DelayTime::Synced(NoteValue::new(1, 8).dotted()));
+        assert!(params.ping_pong);
+        assert_eq!(params.high_cut, Some(4000.0));
+        assert_eq!(params.low_cut, None);
+
+        let json = serde_json::to_value(&effect).unwrap();
+        assert_eq!(json["parameters"]["time"], "1/8.");
+        assert!(json["parameters"].get("low_cut").is_none());
+        assert_eq!(serde_json::to_value(Effect::Delay(DelayParams::new(0.25, 0.0, 0.5))).unwrap()["parameters"]["time"], 0.25);
+
+        let invalid = serde_json::from_value::<Effect>(serde_json::json!({
+            "type": "delay",
+            "parameters": { "time": "1/0" }
+        }));
+        assert!(invalid.is_err());
+    }
+}
@@ -0,0 +1,308 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ops::Bound;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use schemars::{JsonSchema, Schema, SchemaGenerator};
+use anyhow::Result;
+use crate::time::{time_to_32nds, TimeRange};
+use crate::{Event, Note};
+
+/// A song's events, indexed by position and by instrument. There's at most one event per
+/// position and instrument; adding another merges its notes in. Serializes as a list in
+/// time order, then by instrument.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Events {
+    /// Events by position in 32nds, then instrument
+    by_position: BTreeMap<u32, BTreeMap<String, Event>>,
+    /// Positions of each instrument's events
+    by_instrument: HashMap<String, BTreeSet<u32>>,
+    len: usize,
+}
+
+impl Events {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Every event in time order, then by instrument
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &Event> {
+        self.by_position.values().flat_map(|events| events.values())
+    }
+
+    /// Every event, mutably. Moving an event to another time or instrument has to go
+    /// through [`Events::remove`] and [`Events::insert`], so those fields mustn't be changed here.
+    pub(crate) fn iter_mut(&mut self) -> impl Iterator<Item = &mut Event> {
+        self.by_position.values_mut().flat_map(|events| events.values_mut())
+    }
+
+    pub fn first(&self) -> Option<&Event> {
+        self.iter().next()
+    }
+
+    pub fn last(&self) -> Option<&Event> {
+        self.iter().next_back()
+    }
+
+    /// The event at a "bar.32nd" time on an instrument
+    pub fn get(&self, time: &str, instrument: &str) -> Option<&Event> {
+        self.by_position.get(&time_to_32nds(time).ok()?)?.get(instrument)
+    }
+
+    pub(crate) fn get_mut(&mut self, time: &str, instrument: &str) -> Option<&mut Event> {
+        self.by_position.get_mut(&time_to_32nds(time).ok()?)?.get_mut(instrument)
+    }
+
+    /// Add an event, merging its notes into the event already at its time and instrument
+    pub fn insert(&mut self, event: Event) -> Result<()> {
+        let position = time_to_32nds(&event.time)?;
+        let at_position = self.by_position.entry(position).or_default();
+        match at_position.get_mut(&event.instrument) {
+            Some(existing) => existing.notes.extend(event.notes),
+            None => {
+                self.by_instrument.entry(event.instrument.clone()).or_default().insert(position);
+                at_position.insert(event.instrument.clone(), event);
+                self.len += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove and return the event at a "bar.32nd" time on an instrument
+    pub fn remove(&mut self, time: &str, instrument: &str) -> Option<Event> {
+        let position = time_to_32nds(time).ok()?;
+        let at_position = self.by_position.get_mut(&position)?;
+        let event = at_position.remove(instrument)?;
+        if at_position.is_empty() {
+            self.by_position.remove(&position);
+        }
+        let positions = self.by_instrument.get_mut(instrument).expect("indexed instrument");
+        positions.remove(&position);
+        if positions.is_empty() {
+            self.by_instrument.remove(instrument);
+        }
+        self.len -= 1;
+        Some(event)
+    }
+
+    /// Keep only the events `keep` returns true for
+    pub fn retain(&mut self, mut keep: impl FnMut(&Event) -> bool) {
+        let removed: Vec<(String, String)> = self.iter()
+            .filter(|e| !keep(e))
+            .map(|e| (e.time.clone(), e.instrument.clone()))
+            .collect();
+        for (time, instrument) in removed {
+            self.remove(&time, &instrument);
+        }
+    }
+
+    /// Remove and return the events starting in `range`, in time order
+    pub fn remove_range(&mut self, range: TimeRange) -> Vec<Event> {
+        let keys: Vec<(String, String)> = self.range(range)
+            .map(|e| (e.time.clone(), e.instrument.clone()))
+            .collect();
+        keys.iter().filter_map(|(time, instrument)| self.remove(time, instrument)).collect()
+    }
+
+    /// Events starting in `range`, in time order
+    pub fn range(&self, range: TimeRange) -> impl DoubleEndedIterator<Item = &Event> {
+        self.positioned(bounds(range)).map(|(_, event)| event)
+    }
+
+    /// One instrument's events, in time order
+    pub fn for_instrument(&self, instrument: &str) -> impl DoubleEndedIterator<Item = &Event> {
+        self.instrument_positioned(instrument, (Bound::Unbounded, Bound::Unbounded)).map(|(_, event)| event)
+    }
+
+    /// One instrument's events starting in `range`, in time order
+    pub fn for_instrument_in_range(&self, instrument: &str, range: TimeRange) -> impl DoubleEndedIterator<Item = &Event> {
+        self.instrument_positioned(instrument, bounds(range)).map(|(_, event)| event)
+    }
+
+    /// Every note as `(position in 32nds, instrument, note)`, in time order
+    pub fn notes(&self) -> impl DoubleEndedIterator<Item = (u32, &str, &Note)> {
+        notes_of(self.positioned((Bound::Unbounded, Bound::Unbounded)))
+    }
+
+    /// One instrument's notes, in time order
+    pub fn notes_for(&self, instrument: &str) -> impl DoubleEndedIterator<Item = (u32, &str, &Note)> {
+        notes_of(self.instrument_positioned(instrument, (Bound::Unbounded, Bound::Unbounded)))
+    }
+
+    /// Notes starting in `range`, in time order
+    pub fn notes_in_range(&self, range: TimeRange) -> impl DoubleEndedIterator<Item = (u32, &str, &Note)> {
+        notes_of(self.positioned(bounds(range)))
+    }
+
+    fn positioned(&self, positions: (Bound<u32>, Bound<u32>)) -> impl DoubleEndedIterator<Item = (u32, &Event)> {
+        self.by_position.range(positions)
+            .flat_map(|(&position, events)| events.values().map(move |event| (position, event)))
+    }
+
+    fn instrument_positioned(
+        &self,
+        instrument: &str,
+        positions: (Bound<u32>, Bound<u32>),
+    ) -> impl DoubleEndedIterator<Item = (u32, &Event)> {
+        self.by_instrument.get_key_value(instrument)
+            .into_iter()
+            .flat_map(move |(instrument, set)| {
+                set.range(positions).map(move |&position| (position, &self.by_position[&position][instrument]))
+            })
+    }
+
+    /// Whether any event is on `instrument`
+    pub fn uses_instrument(&self, instrument: &str) -> bool {
+        self.by_instrument.contains_key(instrument)
+    }
+}
+
+fn bounds(range: TimeRange) -> (Bound<u32>, Bound<u32>) {
+    (Bound::Included(range.start), Bound::Excluded(range.end.max(range.start)))
+}
+
+fn notes_of<'a>(
+    events: impl DoubleEndedIterator<Item = (u32, &'a Event)>,
+) -> impl DoubleEndedIterator<Item = (u32, &'a str, &'a Note)> {
+    events.flat_map(|(position, event)| event.notes.iter().map(move |note| (position, event.instrument.as_str(), note)))
+}
+
+impl TryFrom<Vec<Event>> for Events {
+    type Error = anyhow::Error;
+
+    fn try_from(list: Vec<Event>) -> Result<Self> {
+        let mut events = Events::new();
+        for event in list {
+            events.insert(event)?;
+        }
+        Ok(events)
+    }
+}
+
+impl IntoIterator for Events {
+    type Item = Event;
+    type IntoIter = Box<dyn Iterator<Item = Event>>;
+
+    /// The events in time order, then by instrument
+    fn into_iter(self) -> Self::IntoIter {
+        Box::new(self.by_position.into_values().flat_map(|events| events.into_values()))
+    }
+}
+
+impl<'a> IntoIterator for &'a Events {
+    type Item = &'a Event;
+    type IntoIter = Box<dyn DoubleEndedIterator<Item = &'a Event> + 'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        Box::new(self.iter())
+    }
+}
+
+impl Serialize for Events {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+impl<'de> Deserialize<'de> for Events {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let list = Vec::<Event>::deserialize(deserializer)?;
+        Events::try_from(list).map_err(serde::de::Error::custom)
+    }
+}
+
+/// A list of events, as written to disk
+impl JsonSchema for Events {
+    fn inline_schema() -> bool {
+        true
+    }
+
+    fn schema_name() -> Cow<'static, str> {
+        <Vec<Event>>::schema_name()
+    }
+
+    fn json_schema(generator: &mut SchemaGenerator) -> Schema {
+        <Vec<Event>>::json_schema(generator)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pitch::{Pitch, Tone};
+    use crate::UnknownFields;
+
+    fn event(time: &str, instrument: &str) -> Event {
+        Event {
+            time: time.to_string(),
+            instrument: instrument.to_string(),
+            notes: vec![Note::new(Pitch::new(Tone::C, 4), 8)],
+            extra: UnknownFields::new(),
+        }
+    }
+
+    fn times<'a>(events: impl Iterator<Item = &'a Event>) -> Vec<&'a str> {
+        events.map(|e| e.time.as_str()).collect()
+    }
+
+    #[test]
+    fn test_numeric_time_order() {
+        let events = Events::try_from(vec![event("10.0", "a"), event("2.0", "b"), event("2.0", "a"), event("1.16", "a")]).unwrap();
+        assert_eq!(times(events.iter()), vec!["1.16", "2.0", "2.0", "10.0"]);
+        assert_eq!(events.iter().nth(1).unwrap().instrument, "a");
+        assert_eq!(events.last().unwrap().time, "10.0");
+        assert!(Events::try_from(vec![event("0.0", "a")]).is_err());
+    }
+
+    #[test]
+    fn test_queries() {
+        let mut events = Events::new();
+        for (time, instrument) in [("1.0", "a"), ("1.8", "b"), ("2.0", "a"), ("3.0", "a"), ("3.0", "b")] {
+            events.insert(event(time, instrument)).unwrap();
+        }
+        events.insert(event("2.0", "a")).unwrap();
+        assert_eq!(events.len(), 5);
+        assert_eq!(events.get("2.0", "a").unwrap().notes.len(), 2);
+
+        assert_eq!(times(events.range(TimeRange::new(8, 96))), vec!["1.8", "2.0", "3.0", "3.0"]);
+        assert_eq!(times(events.for_instrument("a")), vec!["1.0", "2.0", "3.0"]);
+        assert_eq!(times(events.for_instrument_in_range("b", TimeRange::bars(2, 3))), vec!["3.0"]);
+        assert_eq!(events.for_instrument("missing").count(), 0);
+
+        assert!(events.remove("1.8", "b").is_some());
+        assert!(events.remove("1.8", "b").is_none());
+        events.retain(|e| e.instrument != "a");
+        assert_eq!(times(events.iter()), vec!["3.0"]);
+        assert!(!events.uses_instrument("a"));
+    }
+
+    #[test]
+    fn test_notes() {
+        let mut events = Events::try_from(vec![event("2.0", "a"), event("1.8", "b"), event("1.8", "a")]).unwrap();
+        events.get_mut("2.0", "a").unwrap().notes.push(Note::new(Pitch::new(Tone::E, 4), 4));
+
+        let notes: Vec<(u32, &str, u32)> = events.notes().map(|(position, instrument, note)| (position, instrument, note.duration)).collect();
+        assert_eq!(notes, vec![(8, "a", 8), (8, "b", 8), (32, "a", 8), (32, "a", 4)]);
+        assert_eq!(events.notes_for("b").count(), 1);
+        assert_eq!(events.notes_in_range(TimeRange::bars(2, 2)).count(), 2);
+    }
+
+    #[test]
+    fn test_serializes_as_a_list() {
+        let events = Events::try_from(vec![event("2.0", "b"), event("1.4", "a")]).unwrap();
+        let json = serde_json::to_value(&events).unwrap();
+        assert_eq!(json[0]["time"], "1.4");
+        assert_eq!(serde_json::from_value::<Events>(json).unwrap(), events);
+    }
+}
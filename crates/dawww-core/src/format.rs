@@ -0,0 +1,95 @@
+use std::path::Path;
+use anyhow::{Result, Context};
+use crate::DawFile;
+
+/// Extension for pretty-printed JSON project files
+pub const JSON_EXTENSION: &str = ".daw.json";
+/// Extension for compressed binary project files
+pub const BINARY_EXTENSION: &str = ".dawb";
+
+/// Every zstd frame starts with these bytes
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+
+/// On-disk encoding of a project
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileFormat {
+    /// Pretty-printed JSON, as described in FORMAT.md
+    Json,
+    /// The same document as CBOR, compressed with zstd
+    Binary,
+}
+
+impl FileFormat {
+    /// Format chosen by a path's extension; anything but `.dawb` is JSON
+    pub fn from_path(path: &Path) -> Self {
+        if path.to_string_lossy().ends_with(BINARY_EXTENSION) {
+            FileFormat::Binary
+        } else {
+            FileFormat::Json
+        }
+    }
+
+    /// Format of file contents, regardless of the file's name
+    pub fn detect(bytes: &[u8]) -> Self {
+        if bytes.starts_with(&ZSTD_MAGIC) {
+            FileFormat::Binary
+        } else {
+            FileFormat::Json
+        }
+    }
+
+    pub fn encode(&self, daw: &DawFile) -> Result<Vec<u8>> {
+        match self {
+            FileFormat::Json => Ok(serde_json::to_vec_pretty(daw)?),
+            FileFormat::Binary => {
+                let mut cbor = Vec::new();
+                ciborium::into_writer(daw, &mut cbor)?;
+                Ok(zstd::encode_all(cbor.as_slice(), zstd::DEFAULT_COMPRESSION_LEVEL)?)
+            }
+        }
+    }
+
+    pub fn decode(&self, bytes: &[u8]) -> Result<DawFile> {
+        match self {
+            FileFormat::Json => Ok(serde_json::from_slice(bytes)?),
+            FileFormat::Binary => {
+                let cbor = zstd::decode_all(bytes).context("Invalid compressed project file")?;
+                Ok(ciborium::from_reader(cbor.as_slice())?)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pitch::{Pitch, Tone};
+    use crate::Note;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_binary_round_trip() {
+        let mut daw = DawFile::new("Binary".to_string());
+        daw.create_sampler_instrument("drums".to_string(), PathBuf::from("kick.wav")).unwrap();
+        for bar in 1..=64 {
+            daw.add_note(&format!("{}.0", bar), "drums", Note::new(Pitch::new(Tone::C, 2), 4)).unwrap();
+        }
+        daw.extra.insert("future".to_string(), serde_json::json!({ "nested": [1, 2.5, "three"] }));
+
+        let binary = FileFormat::Binary.encode(&daw).unwrap();
+        let json = FileFormat::Json.encode(&daw).unwrap();
+        assert!(binary.len() * 4 < json.len());
+        assert_eq!(FileFormat::detect(&binary), FileFormat::Binary);
+        assert_eq!(FileFormat::detect(&json), FileFormat::Json);
+
+        let decoded = FileFormat::Binary.decode(&binary).unwrap();
+        assert_eq!(serde_json::to_value(&decoded).unwrap(), serde_json::to_value(&daw).unwrap());
+        assert!(FileFormat::Binary.decode(&json).is_err());
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(FileFormat::from_path(Path::new("song.dawb")), FileFormat::Binary);
+        assert_eq!(FileFormat::from_path(Path::new("song.daw.json")), FileFormat::Json);
+    }
+}
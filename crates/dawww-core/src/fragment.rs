@@ -0,0 +1,57 @@
+use serde::{Deserialize, Serialize};
+use anyhow::{Result, Context};
+use crate::{ControlEvent, Event, UnknownFields};
+
+/// A piece of a song copied for pasting elsewhere, in the same song, another running
+/// editor or through the system clipboard. Times count from the start of the piece, so
+/// its first 32nd is "1.0".
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Fragment {
+    /// Length of the copied span in 32nds, so pastes can be laid end to end
+    pub length: u32,
+    pub events: Vec<Event>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub controls: Vec<ControlEvent>,
+    #[serde(flatten)]
+    pub extra: UnknownFields,
+}
+
+impl Fragment {
+    pub fn new(length: u32) -> Self {
+        Self { length, events: Vec::new(), controls: Vec::new(), extra: UnknownFields::new() }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty() && self.controls.is_empty()
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self> {
+        serde_json::from_str(json).context("Not a dawww fragment")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pitch::{Pitch, Tone};
+    use crate::Note;
+
+    #[test]
+    fn test_json_round_trip() {
+        let mut fragment = Fragment::new(32);
+        fragment.events.push(Event {
+            time: "1.8".to_string(),
+            instrument: "bass".to_string(),
+            notes: vec![Note::new(Pitch::new(Tone::E, 2), 8)],
+            extra: UnknownFields::new(),
+        });
+        let json = fragment.to_json().unwrap();
+        assert!(!json.contains("controls"));
+        assert_eq!(Fragment::from_json(&json).unwrap(), fragment);
+        assert!(Fragment::from_json("C4 E4 G4").is_err());
+    }
+}
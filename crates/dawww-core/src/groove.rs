@@ -0,0 +1,158 @@
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use anyhow::{Result, bail};
+use crate::time::Grid;
+use crate::UnknownFields;
+
+/// Timing feel applied when positions are turned into time. Grid lines are moved by the
+/// swing and offset template, and positions between them are stretched to match, so
+/// notes keep their order and durations follow their end points.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct Groove {
+    /// Grid the groove works on; every second step is swung
+    #[serde(default = "default_grid")]
+    pub grid: Grid,
+    /// Where each off-beat step falls within its pair of steps, in percent: 50 is
+    /// straight and about 67 a triplet feel. 50 to 75.
+    #[serde(default = "default_swing", skip_serializing_if = "is_straight_swing")]
+    pub swing: f64,
+    /// Extra offset for each step as a fraction of a step (-0.5 to 0.5), repeating
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub offsets: Vec<f64>,
+    #[serde(flatten)]
+    pub extra: UnknownFields,
+}
+
+fn default_grid() -> Grid {
+    Grid::Sixteenth
+}
+
+fn default_swing() -> f64 {
+    50.0
+}
+
+fn is_straight_swing(swing: &f64) -> bool {
+    *swing == 50.0
+}
+
+impl Default for Groove {
+    fn default() -> Self {
+        Self::new(default_grid())
+    }
+}
+
+impl Groove {
+    /// A straight groove on `grid`
+    pub fn new(grid: Grid) -> Self {
+        Self { grid, swing: default_swing(), offsets: Vec::new(), extra: UnknownFields::new() }
+    }
+
+    pub fn with_swing(mut self, swing: f64) -> Self {
+        self.swing = swing;
+        self
+    }
+
+    pub fn with_offsets(mut self, offsets: Vec<f64>) -> Self {
+        self.offsets = offsets;
+        self
+    }
+
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Whether the groove leaves every position where it is
+    pub fn is_straight(&self) -> bool {
+        is_straight_swing(&self.swing) && self.offsets.iter().all(|&o| o == 0.0)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if !(50.0..=75.0).contains(&self.swing) {
+            bail!("Swing must be between 50 and 75 percent, got {}", self.swing);
+        }
+        if let Some(offset) = self.offsets.iter().find(|o| !(-0.5..=0.5).contains(*o)) {
+            bail!("Groove offsets must be between -0.5 and 0.5 steps, got {}", offset);
+        }
+        Ok(())
+    }
+
+    /// Where grid line `index` falls, in 32nds
+    fn line(&self, index: u32) -> f64 {
+        let step = self.grid.step() as f64;
+        let swing = if index % 2 == 1 { (self.swing / 50.0 - 1.0) * step } else { 0.0 };
+        let offset = match self.offsets.len() {
+            0 => 0.0,
+            n => self.offsets[index as usize % n] * step,
+        };
+        index as f64 * step + swing + offset
+    }
+
+    /// Where a position in 32nds is played once the groove is applied, in (fractional) 32nds
+    pub fn apply(&self, position: u32) -> f64 {
+        let step = self.grid.step();
+        let index = position / step;
+        let fraction = (position % step) as f64 / step as f64;
+        let (from, to) = (self.line(index), self.line(index + 1));
+        (from + (to - from) * fraction).max(0.0)
+    }
+
+    /// Move `position` toward the nearest grooved grid line by `strength` (0 = unchanged,
+    /// 1 = on the line), rounded to a 32nd
+    pub fn snap(&self, position: u32, strength: f64) -> u32 {
+        let step = self.grid.step();
+        let index = (position + step / 2) / step;
+        let nearest = (index.saturating_sub(1)..=index + 1)
+            .map(|i| self.line(i).max(0.0))
+            .min_by(|a, b| (a - position as f64).abs().total_cmp(&(b - position as f64).abs()))
+            .unwrap_or(position as f64);
+        let moved = position as f64 + (nearest - position as f64) * strength;
+        moved.round() as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_swing_moves_off_beats() {
+        let groove = Groove::new(Grid::Eighth).with_swing(75.0);
+        assert_eq!(groove.apply(0), 0.0);
+        assert_eq!(groove.apply(4), 6.0);
+        assert_eq!(groove.apply(8), 8.0);
+        // Between lines the time is stretched: 2 is halfway from 0 to the swung 6
+        assert_eq!(groove.apply(2), 3.0);
+        assert_eq!(groove.apply(6), 7.0);
+
+        assert!(Groove::default().is_straight());
+        assert_eq!(Groove::default().apply(13), 13.0);
+    }
+
+    #[test]
+    fn test_offset_template() {
+        let groove = Groove::new(Grid::Quarter).with_offsets(vec![0.0, 0.25, 0.0, -0.25]);
+        assert_eq!(groove.apply(8), 10.0);
+        assert_eq!(groove.apply(24), 22.0);
+        assert_eq!(groove.apply(40), 42.0);
+    }
+
+    #[test]
+    fn test_snap_to_groove() {
+        let groove = Groove::new(Grid::Eighth).with_swing(75.0);
+        assert_eq!(groove.snap(5, 1.0), 6);
+        assert_eq!(groove.snap(4, 1.0), 6);
+        assert_eq!(groove.snap(1, 1.0), 0);
+        assert_eq!(groove.snap(4, 0.5), 5);
+    }
+
+    #[test]
+    fn test_validate_and_serialize() {
+        assert!(Groove::new(Grid::Sixteenth).with_swing(80.0).validate().is_err());
+        assert!(Groove::default().with_offsets(vec![0.6]).validate().is_err());
+
+        let groove = Groove::new(Grid::Eighth).with_swing(60.0);
+        let json = serde_json::to_value(&groove).unwrap();
+        assert_eq!(json, serde_json::json!({ "grid": "eighth", "swing": 60.0 }));
+        assert_eq!(serde_json::from_value::<Groove>(json).unwrap(), groove);
+    }
+}
@@ -0,0 +1,59 @@
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+/// Bounded random jitter for note timing and velocity. The same seed always gives the
+/// same sequence of offsets, so a humanized take can be reproduced.
+pub struct Humanizer {
+    rng: ChaCha8Rng,
+    /// Largest timing shift either way, in 32nds
+    pub timing: u32,
+    /// Largest velocity change either way
+    pub velocity: u8,
+}
+
+impl Humanizer {
+    pub fn new(timing: u32, velocity: u8, seed: u64) -> Self {
+        Self { rng: ChaCha8Rng::seed_from_u64(seed), timing, velocity }
+    }
+
+    /// `position` moved by up to `timing` 32nds either way, never before the start of the song
+    pub fn position(&mut self, position: u32) -> u32 {
+        if self.timing == 0 {
+            return position;
+        }
+        let shift = self.rng.gen_range(-(self.timing as i64)..=self.timing as i64);
+        (position as i64 + shift).max(0) as u32
+    }
+
+    /// `velocity` changed by up to the velocity variation either way, kept within 1 to 127
+    pub fn velocity(&mut self, velocity: u8) -> u8 {
+        if self.velocity == 0 {
+            return velocity;
+        }
+        let change = self.rng.gen_range(-(self.velocity as i32)..=self.velocity as i32);
+        (velocity as i32 + change).clamp(1, 127) as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_jitter_is_bounded_and_reproducible() {
+        let mut a = Humanizer::new(2, 10, 7);
+        let mut b = Humanizer::new(2, 10, 7);
+        for _ in 0..100 {
+            let position = a.position(64);
+            assert!((62..=66).contains(&position));
+            assert_eq!(b.position(64), position);
+
+            let velocity = a.velocity(120);
+            assert!((110..=127).contains(&velocity));
+            assert_eq!(b.velocity(120), velocity);
+        }
+
+        let mut none = Humanizer::new(0, 0, 1);
+        assert_eq!((none.position(5), none.velocity(5)), (5, 5));
+    }
+}
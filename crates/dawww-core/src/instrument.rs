@@ -1,75 +1,848 @@
 use serde::{Deserialize, Serialize};
+use schemars::{json_schema, JsonSchema, Schema, SchemaGenerator};
+use std::borrow::Cow;
 use std::path::PathBuf;
 use anyhow::{Result, bail};
+use crate::arpeggio::Arpeggio;
+use crate::effects::EffectInstance;
+use crate::groove::Groove;
+use crate::pitch::Pitch;
+use crate::velocity::VelocityResponse;
+use crate::voices::Polyphony;
+use crate::UnknownFields;
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// An instrument definition. Serializes as `{"type": ..., "parameters": {...}}` so
+/// existing project files keep loading.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(try_from = "RawInstrument", into = "RawInstrument")]
 pub struct Instrument {
+    pub kind: InstrumentKind,
+    /// Insert effects applied in order after the instrument is rendered
+    pub effects: Vec<EffectInstance>,
+    /// Pitch offset applied to every note the instrument plays
+    pub tuning: Tuning,
+    /// Voice limit and stealing policy
+    pub polyphony: Polyphony,
+    /// How the level and tone follow each note's velocity
+    pub velocity: VelocityResponse,
+    /// Rendered audio played in place of the instrument's events while it's frozen
+    pub frozen: Option<Freeze>,
+    /// Timing feel for this instrument's events, in place of the song's groove
+    pub groove: Option<Groove>,
+    /// Plays each event's notes one at a time when rendering
+    pub arpeggio: Option<Arpeggio>,
+    /// Unrecognised keys next to `type` and `parameters`
+    pub extra: UnknownFields,
+}
+
+/// A bounce of an instrument's events (with its insert effects) to an audio file
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct Freeze {
+    /// Path to the audio, relative to the .daw.json file
+    pub audio_file: String,
+    /// Seconds into the song the audio starts at, 0 for the beginning
+    #[serde(default, skip_serializing_if = "is_zero")]
+    #[schemars(range(min = 0))]
+    pub start: f64,
+    #[serde(flatten)]
+    pub extra: UnknownFields,
+}
+
+impl Freeze {
+    pub fn new(audio_path: PathBuf) -> Self {
+        Self {
+            audio_file: audio_path.to_string_lossy().into_owned(),
+            start: 0.0,
+            extra: UnknownFields::new(),
+        }
+    }
+
+    /// The same audio starting `seconds` into the song
+    pub fn starting_at(self, seconds: f64) -> Self {
+        Self { start: seconds, ..self }
+    }
+}
+
+/// Coarse and fine tuning for an instrument, e.g. to play a sample recorded at a
+/// different root pitch
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq, Default)]
+#[schemars(rename = "InstrumentTuning")]
+pub struct Tuning {
+    /// Semitones, -48 to 48
+    #[serde(default, skip_serializing_if = "is_zero_i32")]
+    pub transpose: i32,
+    /// Whole octaves, -4 to 4
+    #[serde(default, skip_serializing_if = "is_zero_i32")]
+    pub octave: i32,
+    /// Cents, -100 to 100
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub fine_tune: f64,
+    #[serde(flatten)]
+    pub extra: UnknownFields,
+}
+
+impl Tuning {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Total offset in semitones
+    pub fn semitones(&self) -> f64 {
+        (self.octave * 12 + self.transpose) as f64 + self.fine_tune / 100.0
+    }
+
+    /// Frequency (or playback-rate) multiplier for the offset
+    pub fn ratio(&self) -> f64 {
+        2_f64.powf(self.semitones() / 12.0)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if !(-48..=48).contains(&self.transpose) {
+            bail!("Transpose must be between -48 and 48 semitones, got {}", self.transpose);
+        }
+        if !(-4..=4).contains(&self.octave) {
+            bail!("Octave offset must be between -4 and 4, got {}", self.octave);
+        }
+        if !(-100.0..=100.0).contains(&self.fine_tune) {
+            bail!("Fine tune must be between -100 and 100 cents, got {}", self.fine_tune);
+        }
+        Ok(())
+    }
+}
+
+fn is_zero_i32(value: &i32) -> bool {
+    *value == 0
+}
+
+/// The typed parameters for each supported instrument type
+#[derive(Debug, Clone, PartialEq)]
+pub enum InstrumentKind {
+    Sampler(SamplerParams),
+    Synth(SubtractiveParams),
+    Percussion(PercussionParams),
+    Sfz(SfzParams),
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct SamplerParams {
+    /// Path to the sample, relative to the .daw.json file. Played for notes that no zone covers.
+    pub sample_file: String,
+    /// Pitch `sample_file` was recorded at; notes are repitched relative to it. Without a
+    /// root the sample plays at its original pitch for every note.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub root: Option<Pitch>,
+    #[serde(rename = "loop", default)]
+    pub looping: bool,
+    /// Loop region in seconds; defaults to the whole sample
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub loop_start: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub loop_end: Option<f64>,
+    /// Length in seconds of the crossfade into the loop end
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub loop_crossfade: f64,
+    /// Multisample keymap; the first zone matching a note's pitch and velocity is played
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub zones: Vec<SampleZone>,
+    /// Seconds to fade each note in over
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub envelope_attack: f64,
+    /// Seconds each note fades out over after it ends
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub envelope_release: f64,
+    #[serde(flatten)]
+    pub extra: UnknownFields,
+}
+
+/// One multisample zone: a sample mapped across a pitch and velocity range
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct SampleZone {
+    /// Path to the sample, relative to the .daw.json file
+    pub sample_file: String,
+    /// Pitch the sample was recorded at; notes are repitched relative to it
+    pub root: Pitch,
+    pub low: Pitch,
+    pub high: Pitch,
+    #[serde(default = "default_low_velocity")]
+    pub low_velocity: u8,
+    #[serde(default = "default_high_velocity")]
+    pub high_velocity: u8,
+    /// Fine tuning applied on top of the repitch, in cents
+    #[serde(default)]
+    pub tune_cents: f64,
+    #[serde(flatten)]
+    pub extra: UnknownFields,
+}
+
+fn is_zero(value: &f64) -> bool {
+    *value == 0.0
+}
+
+fn default_low_velocity() -> u8 {
+    1
+}
+
+fn default_high_velocity() -> u8 {
+    127
+}
+
+/// A sample chosen for a note, with the playback-rate change needed to reach the note's pitch
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ZoneMatch<'a> {
+    pub sample_file: &'a str,
+    /// Ratio of playback speed to the sample's original speed
+    pub pitch_ratio: f64,
+    /// Whether the sample was repitched from a root note, rather than played as recorded
+    pub repitched: bool,
+}
+
+impl SampleZone {
+    /// Create a zone covering every velocity across `low..=high`
+    pub fn new(sample_path: PathBuf, root: Pitch, low: Pitch, high: Pitch) -> Self {
+        Self {
+            sample_file: sample_path.to_string_lossy().into_owned(),
+            root,
+            low,
+            high,
+            low_velocity: default_low_velocity(),
+            high_velocity: default_high_velocity(),
+            tune_cents: 0.0,
+            extra: UnknownFields::new(),
+        }
+    }
+
+    pub fn contains(&self, pitch: &Pitch, velocity: u8) -> bool {
+        *pitch >= self.low && *pitch <= self.high
+            && (self.low_velocity..=self.high_velocity).contains(&velocity)
+    }
+}
+
+impl SamplerParams {
+    pub fn new(sample_path: PathBuf) -> Self {
+        Self {
+            sample_file: sample_path.to_string_lossy().into_owned(),
+            root: None,
+            looping: false,
+            loop_start: None,
+            loop_end: None,
+            loop_crossfade: 0.0,
+            zones: Vec::new(),
+            envelope_attack: 0.0,
+            envelope_release: 0.0,
+            extra: UnknownFields::new(),
+        }
+    }
+
+    /// Pick the sample to play for a note
+    pub fn zone_for(&self, pitch: &Pitch, velocity: u8) -> ZoneMatch<'_> {
+        match self.zones.iter().find(|zone| zone.contains(pitch, velocity)) {
+            Some(zone) => {
+                let semitones = pitch.interval_from(&zone.root) + zone.tune_cents / 100.0;
+                ZoneMatch {
+                    sample_file: &zone.sample_file,
+                    pitch_ratio: 2_f64.powf(semitones / 12.0),
+                    repitched: true,
+                }
+            }
+            None => match &self.root {
+                Some(root) => ZoneMatch {
+                    sample_file: &self.sample_file,
+                    pitch_ratio: 2_f64.powf(pitch.interval_from(root) / 12.0),
+                    repitched: true,
+                },
+                None => ZoneMatch {
+                    sample_file: &self.sample_file,
+                    pitch_ratio: 2_f64.powf(pitch.cents as f64 / 1200.0),
+                    repitched: false,
+                },
+            },
+        }
+    }
+
+    /// Every sample file the instrument uses
+    pub fn sample_files(&self) -> Vec<&str> {
+        std::iter::once(self.sample_file.as_str())
+            .chain(self.zones.iter().map(|z| z.sample_file.as_str()))
+            .collect()
+    }
+}
+
+/// An instrument defined by an SFZ file, whose regions map samples across keys and velocities
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct SfzParams {
+    /// Path to the .sfz file, relative to the .daw.json file. Its sample paths are relative
+    /// to the .sfz file.
+    pub sfz_file: String,
+    #[serde(flatten)]
+    pub extra: UnknownFields,
+}
+
+impl SfzParams {
+    pub fn new(sfz_path: PathBuf) -> Self {
+        Self { sfz_file: sfz_path.to_string_lossy().into_owned(), extra: UnknownFields::new() }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Waveform {
+    Sine,
+    Square,
+    Sawtooth,
+    Triangle,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterType {
+    Lowpass,
+    Highpass,
+    Bandpass,
+}
+
+/// Parameters for the subtractive synth subtype. Envelope times are in seconds and
+/// sustain is a level between 0 and 1.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct SubtractiveParams {
+    pub oscillator_wave: Waveform,
+    pub filter_type: FilterType,
+    pub filter_cutoff: f64,
+    pub filter_resonance: f64,
+    pub envelope_attack: f64,
+    pub envelope_decay: f64,
+    pub envelope_sustain: f64,
+    pub envelope_release: f64,
+    /// Low-frequency oscillators modulating the voice, for vibrato, tremolo and filter sweeps
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub lfos: Vec<Lfo>,
+    #[serde(flatten)]
+    pub extra: UnknownFields,
+}
+
+impl Default for SubtractiveParams {
+    fn default() -> Self {
+        Self {
+            oscillator_wave: Waveform::Sine,
+            filter_type: FilterType::Lowpass,
+            filter_cutoff: 880.0,
+            filter_resonance: 0.3,
+            envelope_attack: 0.01,
+            envelope_decay: 0.2,
+            envelope_sustain: 0.7,
+            envelope_release: 0.1,
+            lfos: Vec::new(),
+            extra: UnknownFields::new(),
+        }
+    }
+}
+
+/// What an LFO modulates
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LfoTarget {
+    /// Vibrato; depth in semitones
+    Pitch,
+    /// Filter sweeps; depth in octaves
+    Cutoff,
+    /// Tremolo; depth from 0 (none) to 1 (fading to silence)
+    Amp,
+}
+
+/// A low-frequency oscillator. Each note starts its LFOs at the beginning of their cycle.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct Lfo {
+    pub target: LfoTarget,
+    #[serde(default = "default_lfo_shape")]
+    pub shape: Waveform,
+    /// Cycles per second
+    pub rate: f64,
+    pub depth: f64,
+    #[serde(flatten)]
+    pub extra: UnknownFields,
+}
+
+fn default_lfo_shape() -> Waveform {
+    Waveform::Sine
+}
+
+impl Lfo {
+    pub fn new(target: LfoTarget, shape: Waveform, rate: f64, depth: f64) -> Self {
+        Self { target, shape, rate, depth, extra: UnknownFields::new() }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if !(self.rate > 0.0 && self.rate.is_finite()) {
+            bail!("LFO rate must be a positive number of cycles per second, got {}", self.rate);
+        }
+        let in_range = match self.target {
+            LfoTarget::Amp => (0.0..=1.0).contains(&self.depth),
+            LfoTarget::Pitch | LfoTarget::Cutoff => self.depth.is_finite(),
+        };
+        if !in_range {
+            bail!("Invalid {:?} LFO depth {}", self.target, self.depth);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum NoiseColor {
+    /// Equal energy at every frequency
+    #[default]
+    White,
+    /// Equal energy per octave, darker than white noise
+    Pink,
+}
+
+/// Parameters for the percussion synth subtype: a sine tone that sweeps down to the
+/// note's pitch mixed with noise, under a one-shot envelope. Notes always ring for the
+/// attack and decay whatever their length. Times are in seconds and levels are 0 to 1.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct PercussionParams {
+    pub tone_level: f64,
+    /// Semitones above the note the tone starts at
+    pub pitch_sweep: f64,
+    /// How long the tone takes to fall most of the way to the note's pitch
+    pub sweep_time: f64,
+    pub noise_color: NoiseColor,
+    pub noise_level: f64,
+    pub envelope_attack: f64,
+    pub envelope_decay: f64,
+    #[serde(flatten)]
+    pub extra: UnknownFields,
+}
+
+/// A kick drum
+impl Default for PercussionParams {
+    fn default() -> Self {
+        Self {
+            tone_level: 1.0,
+            pitch_sweep: 24.0,
+            sweep_time: 0.05,
+            noise_color: NoiseColor::White,
+            noise_level: 0.0,
+            envelope_attack: 0.001,
+            envelope_decay: 0.4,
+            extra: UnknownFields::new(),
+        }
+    }
+}
+
+/// On-disk shape of an instrument
+#[derive(Serialize, Deserialize)]
+struct RawInstrument {
     #[serde(rename = "type")]
-    pub instrument_type: String,
-    pub parameters: serde_json::Value,
+    instrument_type: String,
+    /// Older files put the synth subtype next to `type` rather than in `parameters`
+    #[serde(default, skip_serializing)]
+    subtype: Option<String>,
+    parameters: serde_json::Value,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    effects: Vec<EffectInstance>,
+    #[serde(default, skip_serializing_if = "Tuning::is_default")]
+    tuning: Tuning,
+    #[serde(default, skip_serializing_if = "Polyphony::is_default")]
+    polyphony: Polyphony,
+    #[serde(default, skip_serializing_if = "VelocityResponse::is_default")]
+    velocity: VelocityResponse,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    frozen: Option<Freeze>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    groove: Option<Groove>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    arpeggio: Option<Arpeggio>,
+    #[serde(flatten)]
+    extra: UnknownFields,
+}
+
+impl TryFrom<RawInstrument> for Instrument {
+    type Error = String;
+
+    fn try_from(mut raw: RawInstrument) -> std::result::Result<Self, Self::Error> {
+        let kind = match raw.instrument_type.as_str() {
+            "sampler" => {
+                let params = serde_json::from_value(raw.parameters)
+                    .map_err(|e| format!("Invalid sampler parameters: {}", e))?;
+                InstrumentKind::Sampler(params)
+            }
+            "sfz" => {
+                let params = serde_json::from_value(raw.parameters)
+                    .map_err(|e| format!("Invalid sfz parameters: {}", e))?;
+                InstrumentKind::Sfz(params)
+            }
+            "synth" => {
+                let subtype = raw.parameters.get("subtype")
+                    .and_then(|v| v.as_str())
+                    .map(|s| s.to_string())
+                    .or(raw.subtype)
+                    .ok_or_else(|| "Synth must have a subtype parameter".to_string())?;
+                // The subtype is implied by the parameter type, so it isn't kept as an unknown field
+                if let Some(map) = raw.parameters.as_object_mut() {
+                    map.remove("subtype");
+                }
+                match subtype.as_str() {
+                    "subtractive" => {
+                        let params = serde_json::from_value(raw.parameters)
+                            .map_err(|e| format!("Invalid subtractive synth parameters: {}", e))?;
+                        InstrumentKind::Synth(params)
+                    }
+                    "percussion" => {
+                        let params = serde_json::from_value(raw.parameters)
+                            .map_err(|e| format!("Invalid percussion synth parameters: {}", e))?;
+                        InstrumentKind::Percussion(params)
+                    }
+                    other => return Err(format!("Unsupported synth subtype: {}", other)),
+                }
+            }
+            other => return Err(format!("Invalid instrument type: {}", other)),
+        };
+        raw.polyphony.validate().map_err(|e| format!("Invalid polyphony: {}", e))?;
+        if let Some(groove) = &raw.groove {
+            groove.validate().map_err(|e| format!("Invalid groove: {}", e))?;
+        }
+        Ok(Instrument { kind, effects: raw.effects, tuning: raw.tuning, polyphony: raw.polyphony, velocity: raw.velocity, frozen: raw.frozen, groove: raw.groove, arpeggio: raw.arpeggio, extra: raw.extra })
+    }
+}
+
+/// Matches the on-disk shape: one variant per instrument type, with that type's parameters
+impl JsonSchema for Instrument {
+    fn schema_name() -> Cow<'static, str> {
+        "Instrument".into()
+    }
+
+    fn json_schema(generator: &mut SchemaGenerator) -> Schema {
+        let effects = generator.subschema_for::<Vec<EffectInstance>>();
+        let tuning = generator.subschema_for::<Tuning>();
+        let polyphony = generator.subschema_for::<Polyphony>();
+        let velocity = generator.subschema_for::<VelocityResponse>();
+        let frozen = generator.subschema_for::<Freeze>();
+        let groove = generator.subschema_for::<Groove>();
+        let arpeggio = generator.subschema_for::<Arpeggio>();
+        let sampler = generator.subschema_for::<SamplerParams>();
+        let subtractive = generator.subschema_for::<SubtractiveParams>();
+        let percussion = generator.subschema_for::<PercussionParams>();
+        let sfz = generator.subschema_for::<SfzParams>();
+        json_schema!({
+            "oneOf": [
+                {
+                    "type": "object",
+                    "properties": {
+                        "type": { "const": "sampler" },
+                        "parameters": sampler,
+                        "effects": effects,
+                        "tuning": tuning,
+                        "polyphony": polyphony,
+                        "velocity": velocity,
+                        "frozen": frozen,
+                        "groove": groove,
+                        "arpeggio": arpeggio
+                    },
+                    "required": ["type", "parameters"]
+                },
+                {
+                    "type": "object",
+                    "properties": {
+                        "type": { "const": "synth" },
+                        "subtype": { "const": "subtractive" },
+                        "parameters": {
+                            "allOf": [subtractive],
+                            "properties": { "subtype": { "const": "subtractive" } }
+                        },
+                        "effects": effects,
+                        "tuning": tuning,
+                        "polyphony": polyphony,
+                        "velocity": velocity,
+                        "frozen": frozen,
+                        "groove": groove,
+                        "arpeggio": arpeggio
+                    },
+                    "required": ["type", "parameters"]
+                },
+                {
+                    "type": "object",
+                    "properties": {
+                        "type": { "const": "synth" },
+                        "subtype": { "const": "percussion" },
+                        "parameters": {
+                            "allOf": [percussion],
+                            "properties": { "subtype": { "const": "percussion" } }
+                        },
+                        "effects": effects,
+                        "tuning": tuning,
+                        "polyphony": polyphony,
+                        "velocity": velocity,
+                        "frozen": frozen,
+                        "groove": groove,
+                        "arpeggio": arpeggio
+                    },
+                    "required": ["type", "parameters"]
+                },
+                {
+                    "type": "object",
+                    "properties": {
+                        "type": { "const": "sfz" },
+                        "parameters": sfz,
+                        "effects": effects,
+                        "tuning": tuning,
+                        "polyphony": polyphony,
+                        "velocity": velocity,
+                        "frozen": frozen,
+                        "groove": groove,
+                        "arpeggio": arpeggio
+                    },
+                    "required": ["type", "parameters"]
+                }
+            ]
+        })
+    }
+}
+
+impl From<Instrument> for RawInstrument {
+    fn from(instrument: Instrument) -> Self {
+        let instrument_type = instrument.instrument_type().to_string();
+        let subtype = instrument.subtype().map(|s| s.to_string());
+        let mut parameters = match &instrument.kind {
+            InstrumentKind::Sampler(params) => serde_json::to_value(params),
+            InstrumentKind::Synth(params) => serde_json::to_value(params),
+            InstrumentKind::Percussion(params) => serde_json::to_value(params),
+            InstrumentKind::Sfz(params) => serde_json::to_value(params),
+        }
+        .expect("instrument parameters serialize to JSON");
+
+        if let (Some(subtype), Some(map)) = (subtype, parameters.as_object_mut()) {
+            map.insert("subtype".to_string(), serde_json::Value::String(subtype));
+        }
+
+        RawInstrument {
+            instrument_type,
+            subtype: None,
+            parameters,
+            effects: instrument.effects,
+            tuning: instrument.tuning,
+            polyphony: instrument.polyphony,
+            velocity: instrument.velocity,
+            frozen: instrument.frozen,
+            groove: instrument.groove,
+            arpeggio: instrument.arpeggio,
+            extra: instrument.extra,
+        }
+    }
 }
 
 impl Instrument {
+    pub fn new(kind: InstrumentKind) -> Self {
+        Self {
+            kind,
+            effects: Vec::new(),
+            tuning: Tuning::default(),
+            polyphony: Polyphony::default(),
+            velocity: VelocityResponse::default(),
+            frozen: None,
+            groove: None,
+            arpeggio: None,
+            extra: UnknownFields::new(),
+        }
+    }
+
     /// Create a new sampler instrument
     pub fn new_sampler(sample_path: PathBuf) -> Self {
-        let mut parameters = serde_json::Map::new();
-        parameters.insert("sample_file".to_string(), serde_json::Value::String(sample_path.to_string_lossy().into_owned()));
+        Self::new(InstrumentKind::Sampler(SamplerParams::new(sample_path)))
+    }
 
-        Self {
-            instrument_type: "sampler".to_string(),
-            parameters: serde_json::Value::Object(parameters),
+    /// Create a new instrument playing an SFZ file
+    pub fn new_sfz(sfz_path: PathBuf) -> Self {
+        Self::new(InstrumentKind::Sfz(SfzParams::new(sfz_path)))
+    }
+
+    /// Create a new subtractive synth instrument
+    pub fn new_subtractive(params: SubtractiveParams) -> Self {
+        Self::new(InstrumentKind::Synth(params))
+    }
+
+    /// Create a new percussion synth instrument
+    pub fn new_percussion(params: PercussionParams) -> Self {
+        Self::new(InstrumentKind::Percussion(params))
+    }
+
+    pub fn with_tuning(mut self, tuning: Tuning) -> Self {
+        self.tuning = tuning;
+        self
+    }
+
+    pub fn with_polyphony(mut self, polyphony: Polyphony) -> Self {
+        self.polyphony = polyphony;
+        self
+    }
+
+    pub fn with_velocity(mut self, velocity: VelocityResponse) -> Self {
+        self.velocity = velocity;
+        self
+    }
+
+    pub fn with_groove(mut self, groove: Groove) -> Self {
+        self.groove = Some(groove);
+        self
+    }
+
+    pub fn with_arpeggio(mut self, arpeggio: Arpeggio) -> Self {
+        self.arpeggio = Some(arpeggio);
+        self
+    }
+
+    /// Append an effect to the end of the insert chain
+    pub fn with_effect(mut self, effect: EffectInstance) -> Self {
+        self.effects.push(effect);
+        self
+    }
+
+    /// The `type` string used in project files
+    pub fn instrument_type(&self) -> &str {
+        match self.kind {
+            InstrumentKind::Sampler(_) => "sampler",
+            InstrumentKind::Sfz(_) => "sfz",
+            InstrumentKind::Synth(_) | InstrumentKind::Percussion(_) => "synth",
+        }
+    }
+
+    /// The synth subtype, if this is a synth
+    pub fn subtype(&self) -> Option<&str> {
+        match self.kind {
+            InstrumentKind::Sampler(_) | InstrumentKind::Sfz(_) => None,
+            InstrumentKind::Synth(_) => Some("subtractive"),
+            InstrumentKind::Percussion(_) => Some("percussion"),
         }
     }
 
-    /// Create a new synth instrument
-    pub fn new_synth(subtype: &str, parameters: serde_json::Map<String, serde_json::Value>) -> Self {
-        let mut params = parameters;
-        params.insert("subtype".to_string(), serde_json::Value::String(subtype.to_string()));
+    /// Get the sample file path for sampler instruments
+    pub fn sample_file(&self) -> Option<&str> {
+        match &self.kind {
+            InstrumentKind::Sampler(params) => Some(params.sample_file.as_str()),
+            _ => None,
+        }
+    }
 
-        Self {
-            instrument_type: "synth".to_string(),
-            parameters: serde_json::Value::Object(params),
+    /// Every sample file the instrument uses, including multisample zones. For SFZ
+    /// instruments this is the .sfz file.
+    pub fn sample_files(&self) -> Vec<&str> {
+        match &self.kind {
+            InstrumentKind::Sampler(params) => params.sample_files(),
+            InstrumentKind::Sfz(params) => vec![params.sfz_file.as_str()],
+            _ => Vec::new(),
         }
     }
 
     /// Validate the instrument configuration
     pub fn validate(&self) -> Result<()> {
-        match self.instrument_type.as_str() {
-            "sampler" => {
-                let params = self.parameters.as_object()
-                    .ok_or_else(|| anyhow::anyhow!("Sampler parameters must be an object"))?;
-                
-                if !params.contains_key("sample_file") {
+        match &self.kind {
+            InstrumentKind::Sampler(params) => {
+                if params.sample_file.is_empty() {
                     bail!("Sampler must have a sample_file parameter");
                 }
+                let loop_start = params.loop_start.unwrap_or(0.0);
+                if loop_start < 0.0 || loop_start.is_nan() {
+                    bail!("loop_start must not be negative, got {}", loop_start);
+                }
+                if let Some(loop_end) = params.loop_end {
+                    if loop_end <= loop_start || loop_end.is_nan() {
+                        bail!("loop_end must be after loop_start, got {}", loop_end);
+                    }
+                    if params.loop_crossfade > loop_end - loop_start {
+                        bail!("loop_crossfade can't be longer than the loop");
+                    }
+                }
+                if params.loop_crossfade < 0.0 || params.loop_crossfade.is_nan() {
+                    bail!("loop_crossfade must not be negative, got {}", params.loop_crossfade);
+                }
+                for zone in &params.zones {
+                    if zone.sample_file.is_empty() {
+                        bail!("Sampler zones must have a sample_file");
+                    }
+                    if zone.low > zone.high {
+                        bail!("Zone for '{}' has its low pitch above its high pitch", zone.sample_file);
+                    }
+                    if zone.low_velocity > zone.high_velocity || zone.high_velocity > 127 {
+                        bail!("Zone for '{}' has an invalid velocity range", zone.sample_file);
+                    }
+                    if !zone.tune_cents.is_finite() {
+                        bail!("Zone tuning must be a finite number of cents");
+                    }
+                }
+                for (name, time) in [("envelope_attack", params.envelope_attack), ("envelope_release", params.envelope_release)] {
+                    if !(time >= 0.0 && time.is_finite()) {
+                        bail!("{} must be a non-negative number of seconds, got {}", name, time);
+                    }
+                }
             }
-            "synth" => {
-                let params = self.parameters.as_object()
-                    .ok_or_else(|| anyhow::anyhow!("Synth parameters must be an object"))?;
-                
-                if !params.contains_key("subtype") {
-                    bail!("Synth must have a subtype parameter");
-                }
-
-                match params["subtype"].as_str() {
-                    Some("subtractive") => {
-                        // Validate required parameters for subtractive synth
-                        let required = vec!["oscillator_wave", "filter_type", "filter_cutoff", 
-                                         "filter_resonance", "envelope_attack", "envelope_decay", 
-                                         "envelope_sustain", "envelope_release"];
-                        for param in required {
-                            if !params.contains_key(param) {
-                                bail!("Subtractive synth missing required parameter: {}", param);
-                            }
-                        }
+            InstrumentKind::Synth(params) => {
+                if params.filter_cutoff <= 0.0 || params.filter_cutoff.is_nan() {
+                    bail!("Filter cutoff must be positive, got {}", params.filter_cutoff);
+                }
+                if !(0.0..=1.0).contains(&params.filter_resonance) {
+                    bail!("Filter resonance must be between 0 and 1, got {}", params.filter_resonance);
+                }
+                for (name, value) in [
+                    ("envelope_attack", params.envelope_attack),
+                    ("envelope_decay", params.envelope_decay),
+                    ("envelope_release", params.envelope_release),
+                ] {
+                    if value < 0.0 || value.is_nan() {
+                        bail!("{} must not be negative, got {}", name, value);
                     }
-                    Some(other) => bail!("Unsupported synth subtype: {}", other),
-                    None => bail!("Invalid synth subtype"),
+                }
+                if !(0.0..=1.0).contains(&params.envelope_sustain) {
+                    bail!("envelope_sustain must be between 0 and 1, got {}", params.envelope_sustain);
+                }
+                for lfo in &params.lfos {
+                    lfo.validate()?;
+                }
+            }
+            InstrumentKind::Percussion(params) => {
+                for (name, value) in [("tone_level", params.tone_level), ("noise_level", params.noise_level)] {
+                    if !(0.0..=1.0).contains(&value) {
+                        bail!("{} must be between 0 and 1, got {}", name, value);
+                    }
+                }
+                if !params.pitch_sweep.is_finite() {
+                    bail!("pitch_sweep must be a finite number of semitones");
+                }
+                for (name, value) in [
+                    ("sweep_time", params.sweep_time),
+                    ("envelope_attack", params.envelope_attack),
+                    ("envelope_decay", params.envelope_decay),
+                ] {
+                    if !(value >= 0.0 && value.is_finite()) {
+                        bail!("{} must be a non-negative number of seconds, got {}", name, value);
+                    }
+                }
+            }
+            InstrumentKind::Sfz(params) => {
+                if params.sfz_file.is_empty() {
+                    bail!("SFZ instruments must have an sfz_file parameter");
                 }
             }
-            _ => bail!("Invalid instrument type: {}", self.instrument_type),
         }
-        Ok(())
+
+        for effect in &self.effects {
+            effect.validate()?;
+        }
+        if let Some(frozen) = &self.frozen {
+            if frozen.audio_file.is_empty() {
+                bail!("Frozen instruments must have an audio_file");
+            }
+            if !(frozen.start >= 0.0 && frozen.start.is_finite()) {
+                bail!("Frozen audio must start at 0 seconds or later, got {}", frozen.start);
+            }
+        }
+        self.tuning.validate()?;
+        self.velocity.validate()?;
+        if let Some(groove) = &self.groove {
+            groove.validate()?;
+        }
+        if let Some(arpeggio) = &self.arpeggio {
+            arpeggio.validate()?;
+        }
+        self.polyphony.validate()
     }
 }
 
@@ -81,31 +854,153 @@ mod tests {
     fn test_new_sampler() {
         let sample_path = PathBuf::from("audio/kick.wav");
         let sampler = Instrument::new_sampler(sample_path.clone());
-        
-        assert_eq!(sampler.instrument_type, "sampler");
-        
-        let params = sampler.parameters.as_object().unwrap();
-        assert_eq!(params["sample_file"], sample_path.to_string_lossy().to_string());
+
+        assert_eq!(sampler.instrument_type(), "sampler");
+        assert_eq!(sampler.sample_file(), Some("audio/kick.wav"));
+
+        let json = serde_json::to_value(&sampler).unwrap();
+        assert_eq!(json["type"], "sampler");
+        assert_eq!(json["parameters"]["sample_file"], sample_path.to_string_lossy().to_string());
+    }
+
+    #[test]
+    fn test_new_sfz() {
+        let sfz = Instrument::new_sfz(PathBuf::from("piano/piano.sfz"));
+        assert_eq!(sfz.instrument_type(), "sfz");
+        assert_eq!(sfz.subtype(), None);
+        assert_eq!(sfz.sample_files(), vec!["piano/piano.sfz"]);
+
+        let json = serde_json::to_value(&sfz).unwrap();
+        assert_eq!(json, serde_json::json!({ "type": "sfz", "parameters": { "sfz_file": "piano/piano.sfz" } }));
+        assert_eq!(serde_json::from_value::<Instrument>(json).unwrap(), sfz);
+        assert!(Instrument::new_sfz(PathBuf::new()).validate().is_err());
     }
 
     #[test]
-    fn test_new_synth() {
-        let mut params = serde_json::Map::new();
-        params.insert("oscillator_wave".to_string(), serde_json::Value::String("sine".to_string()));
-        params.insert("filter_type".to_string(), serde_json::Value::String("lowpass".to_string()));
-        params.insert("filter_cutoff".to_string(), serde_json::Value::Number(serde_json::Number::from_f64(880.0).unwrap()));
-        params.insert("filter_resonance".to_string(), serde_json::Value::Number(serde_json::Number::from_f64(0.3).unwrap()));
-        params.insert("envelope_attack".to_string(), serde_json::Value::Number(serde_json::Number::from_f64(0.01).unwrap()));
-        params.insert("envelope_decay".to_string(), serde_json::Value::Number(serde_json::Number::from_f64(0.2).unwrap()));
-        params.insert("envelope_sustain".to_string(), serde_json::Value::Number(serde_json::Number::from_f64(0.7).unwrap()));
-        params.insert("envelope_release".to_string(), serde_json::Value::Number(serde_json::Number::from_f64(0.1).unwrap()));
-
-        let synth = Instrument::new_synth("subtractive", params);
-        
-        assert_eq!(synth.instrument_type, "synth");
-        let params = synth.parameters.as_object().unwrap();
-        assert_eq!(params["subtype"], "subtractive");
-        assert_eq!(params["oscillator_wave"], "sine");
+    fn test_new_subtractive() {
+        let synth = Instrument::new_subtractive(SubtractiveParams::default());
+
+        assert_eq!(synth.instrument_type(), "synth");
+        assert_eq!(synth.subtype(), Some("subtractive"));
+
+        let json = serde_json::to_value(&synth).unwrap();
+        assert_eq!(json["type"], "synth");
+        assert_eq!(json["parameters"]["subtype"], "subtractive");
+        assert_eq!(json["parameters"]["oscillator_wave"], "sine");
+        assert_eq!(json["parameters"]["filter_cutoff"], 880.0);
+    }
+
+    #[test]
+    fn test_new_percussion() {
+        let snare = PercussionParams { noise_color: NoiseColor::Pink, noise_level: 0.8, ..PercussionParams::default() };
+        let drum = Instrument::new_percussion(snare.clone());
+        assert_eq!(drum.instrument_type(), "synth");
+        assert_eq!(drum.subtype(), Some("percussion"));
+
+        let json = serde_json::to_value(&drum).unwrap();
+        assert_eq!(json["parameters"]["subtype"], "percussion");
+        assert_eq!(json["parameters"]["noise_color"], "pink");
+        let loaded: Instrument = serde_json::from_value(json).unwrap();
+        assert_eq!(loaded.kind, InstrumentKind::Percussion(snare));
+
+        assert!(drum.validate().is_ok());
+        let loud = Instrument::new_percussion(PercussionParams { noise_level: 1.5, ..PercussionParams::default() });
+        assert!(loud.validate().is_err());
+        let backwards = Instrument::new_percussion(PercussionParams { envelope_decay: -1.0, ..PercussionParams::default() });
+        assert!(backwards.validate().is_err());
+    }
+
+    #[test]
+    fn test_tuning() {
+        let tuning = Tuning { transpose: 3, octave: -1, fine_tune: 50.0, ..Default::default() };
+        assert_eq!(tuning.semitones(), -8.5);
+        assert!((Tuning { octave: 1, ..Default::default() }.ratio() - 2.0).abs() < 1e-12);
+
+        let sampler = Instrument::new_sampler(PathBuf::from("piano.wav")).with_tuning(tuning.clone());
+        let json = serde_json::to_value(&sampler).unwrap();
+        assert_eq!(json["tuning"], serde_json::json!({ "transpose": 3, "octave": -1, "fine_tune": 50.0 }));
+        let loaded: Instrument = serde_json::from_value(json).unwrap();
+        assert_eq!(loaded.tuning, tuning);
+        assert!(serde_json::to_value(Instrument::new_sampler(PathBuf::from("a.wav"))).unwrap().get("tuning").is_none());
+
+        for bad in [
+            Tuning { transpose: 49, ..Default::default() },
+            Tuning { octave: -5, ..Default::default() },
+            Tuning { fine_tune: f64::NAN, ..Default::default() },
+        ] {
+            assert!(Instrument::new_sampler(PathBuf::from("a.wav")).with_tuning(bad).validate().is_err());
+        }
+    }
+
+    #[test]
+    fn test_velocity_response() {
+        use crate::velocity::VelocityCurve;
+
+        let velocity = VelocityResponse::new(VelocityCurve::Hard, 0.7, 2.0);
+        let synth = Instrument::new_subtractive(SubtractiveParams::default()).with_velocity(velocity.clone());
+        let json = serde_json::to_value(&synth).unwrap();
+        assert_eq!(json["velocity"], serde_json::json!({ "curve": "hard", "amp": 0.7, "filter_octaves": 2.0 }));
+        assert_eq!(serde_json::from_value::<Instrument>(json).unwrap().velocity, velocity);
+        assert!(serde_json::to_value(Instrument::new_sampler(PathBuf::from("a.wav"))).unwrap().get("velocity").is_none());
+
+        let bad = VelocityResponse::new(VelocityCurve::Linear, -0.5, 0.0);
+        assert!(Instrument::new_subtractive(SubtractiveParams::default()).with_velocity(bad).validate().is_err());
+    }
+
+    #[test]
+    fn test_deserialize_existing_formats() {
+        // Subtype inside parameters, as written by DawFile::save
+        let synth: Instrument = serde_json::from_value(serde_json::json!({
+            "type": "synth",
+            "parameters": {
+                "subtype": "subtractive",
+                "oscillator_wave": "sawtooth",
+                "filter_type": "lowpass",
+                "filter_cutoff": 880.0,
+                "filter_resonance": 0.3,
+                "envelope_attack": 0.01,
+                "envelope_decay": 0.2,
+                "envelope_sustain": 0.7,
+                "envelope_release": 0.1
+            }
+        })).unwrap();
+        match &synth.kind {
+            InstrumentKind::Synth(params) => assert_eq!(params.oscillator_wave, Waveform::Sawtooth),
+            _ => panic!("expected synth"),
+        }
+
+        // Subtype next to type, as in FORMAT.md
+        let synth: Instrument = serde_json::from_value(serde_json::json!({
+            "type": "synth",
+            "subtype": "subtractive",
+            "parameters": serde_json::to_value(SubtractiveParams::default()).unwrap()
+        })).unwrap();
+        assert_eq!(synth.subtype(), Some("subtractive"));
+
+        let sampler: Instrument = serde_json::from_value(serde_json::json!({
+            "type": "sampler",
+            "parameters": { "sample_file": "audio/kick.wav", "loop": true }
+        })).unwrap();
+        assert_eq!(sampler.kind, InstrumentKind::Sampler(SamplerParams {
+            looping: true,
+            ..SamplerParams::new(PathBuf::from("audio/kick.wav"))
+        }));
+    }
+
+    #[test]
+    fn test_deserialize_rejects_invalid() {
+        let invalid = [
+            serde_json::json!({ "type": "sampler", "parameters": {} }),
+            serde_json::json!({ "type": "synth", "parameters": {} }),
+            serde_json::json!({ "type": "synth", "parameters": { "subtype": "fm" } }),
+            serde_json::json!({ "type": "synth", "parameters": { "subtype": "subtractive", "oscillator_wave": "sine" } }),
+            serde_json::json!({ "type": "drum", "parameters": {} }),
+            serde_json::json!({ "type": "synth", "parameters": { "subtype": "subtractive" }, "polyphony": { "max_voices": 0 } }),
+            serde_json::json!({ "type": "synth", "parameters": { "subtype": "subtractive" }, "groove": { "swing": 90.0 } }),
+        ];
+        for json in invalid {
+            assert!(serde_json::from_value::<Instrument>(json).is_err());
+        }
     }
 
     #[test]
@@ -115,32 +1010,138 @@ mod tests {
         );
         assert!(valid_sampler.validate().is_ok());
 
-        let invalid_sampler = Instrument {
-            instrument_type: "sampler".to_string(),
-            parameters: serde_json::json!({}),
-        };
+        let invalid_sampler = Instrument::new_sampler(PathBuf::new());
         assert!(invalid_sampler.validate().is_err());
     }
 
     #[test]
     fn test_validate_synth() {
-        let mut params = serde_json::Map::new();
-        params.insert("oscillator_wave".to_string(), serde_json::Value::String("sine".to_string()));
-        params.insert("filter_type".to_string(), serde_json::Value::String("lowpass".to_string()));
-        params.insert("filter_cutoff".to_string(), serde_json::Value::Number(serde_json::Number::from_f64(880.0).unwrap()));
-        params.insert("filter_resonance".to_string(), serde_json::Value::Number(serde_json::Number::from_f64(0.3).unwrap()));
-        params.insert("envelope_attack".to_string(), serde_json::Value::Number(serde_json::Number::from_f64(0.01).unwrap()));
-        params.insert("envelope_decay".to_string(), serde_json::Value::Number(serde_json::Number::from_f64(0.2).unwrap()));
-        params.insert("envelope_sustain".to_string(), serde_json::Value::Number(serde_json::Number::from_f64(0.7).unwrap()));
-        params.insert("envelope_release".to_string(), serde_json::Value::Number(serde_json::Number::from_f64(0.1).unwrap()));
-
-        let valid_synth = Instrument::new_synth("subtractive", params);
+        let valid_synth = Instrument::new_subtractive(SubtractiveParams::default());
         assert!(valid_synth.validate().is_ok());
 
-        let invalid_synth = Instrument {
-            instrument_type: "synth".to_string(),
-            parameters: serde_json::json!({}),
-        };
+        let invalid_synth = Instrument::new_subtractive(SubtractiveParams {
+            envelope_sustain: 1.5,
+            ..SubtractiveParams::default()
+        });
+        assert!(invalid_synth.validate().is_err());
+
+        let invalid_synth = Instrument::new_subtractive(SubtractiveParams {
+            filter_cutoff: 0.0,
+            ..SubtractiveParams::default()
+        });
         assert!(invalid_synth.validate().is_err());
+
+        for (lfo, valid) in [
+            (Lfo::new(LfoTarget::Pitch, Waveform::Sine, 5.0, 0.3), true),
+            (Lfo::new(LfoTarget::Cutoff, Waveform::Triangle, 0.25, -2.0), true),
+            (Lfo::new(LfoTarget::Amp, Waveform::Square, 4.0, 1.5), false),
+            (Lfo::new(LfoTarget::Pitch, Waveform::Sine, 0.0, 0.3), false),
+        ] {
+            let synth = Instrument::new_subtractive(SubtractiveParams { lfos: vec![lfo], ..SubtractiveParams::default() });
+            assert_eq!(synth.validate().is_ok(), valid);
+        }
+    }
+
+    #[test]
+    fn test_lfos_are_optional() {
+        let json = serde_json::to_value(SubtractiveParams::default()).unwrap();
+        assert!(json.get("lfos").is_none());
+
+        let vibrato: SubtractiveParams = serde_json::from_value(serde_json::json!({
+            "oscillator_wave": "sawtooth", "filter_type": "lowpass", "filter_cutoff": 2000.0,
+            "filter_resonance": 0.2, "envelope_attack": 0.0, "envelope_decay": 0.0,
+            "envelope_sustain": 1.0, "envelope_release": 0.1,
+            "lfos": [{ "target": "pitch", "rate": 5.5, "depth": 0.25 }]
+        })).unwrap();
+        assert_eq!(vibrato.lfos, vec![Lfo::new(LfoTarget::Pitch, Waveform::Sine, 5.5, 0.25)]);
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_sampler_zones() {
+        use crate::pitch::Tone;
+
+        let mut params = SamplerParams::new(PathBuf::from("piano_c4.wav"));
+        params.zones.push(SampleZone {
+            low_velocity: 90,
+            ..SampleZone::new(PathBuf::from("piano_c4_loud.wav"), Pitch::new(Tone::C, 4), Pitch::new(Tone::C, 3), Pitch::new(Tone::B, 4))
+        });
+        params.zones.push(SampleZone::new(PathBuf::from("piano_c4_soft.wav"), Pitch::new(Tone::C, 4), Pitch::new(Tone::C, 3), Pitch::new(Tone::B, 4)));
+        params.zones.push(SampleZone {
+            tune_cents: -50.0,
+            ..SampleZone::new(PathBuf::from("piano_c6.wav"), Pitch::new(Tone::C, 6), Pitch::new(Tone::C, 5), Pitch::new(Tone::B, 6))
+        });
+
+        let loud = params.zone_for(&Pitch::new(Tone::C, 5).prev().unwrap(), 120);
+        assert_eq!(loud.sample_file, "piano_c4_loud.wav");
+        assert!((loud.pitch_ratio - 2_f64.powf(11.0 / 12.0)).abs() < 1e-12);
+
+        let soft = params.zone_for(&Pitch::new(Tone::C, 3), 40);
+        assert_eq!(soft.sample_file, "piano_c4_soft.wav");
+        assert!((soft.pitch_ratio - 0.5).abs() < 1e-12);
+
+        let tuned = params.zone_for(&Pitch::new(Tone::C, 6), 100);
+        assert!((tuned.pitch_ratio - 2_f64.powf(-0.5 / 12.0)).abs() < 1e-12);
+
+        // Uncovered notes fall back to the main sample at its original pitch
+        let fallback = params.zone_for(&Pitch::new(Tone::C, 1), 100);
+        assert_eq!(fallback, ZoneMatch { sample_file: "piano_c4.wav", pitch_ratio: 1.0, repitched: false });
+
+        // ...or repitched from its root if it has one
+        let rooted = SamplerParams { root: Some(Pitch::new(Tone::C, 4)), ..params.clone() };
+        let fallback = rooted.zone_for(&Pitch::new(Tone::C, 2), 100);
+        assert!((fallback.pitch_ratio - 0.25).abs() < 1e-12);
+        assert!(fallback.repitched);
+
+        let sampler = Instrument::new(InstrumentKind::Sampler(params.clone()));
+        assert!(sampler.validate().is_ok());
+        assert_eq!(sampler.sample_files().len(), 4);
+        let json = serde_json::to_value(&sampler).unwrap();
+        assert_eq!(json["parameters"]["zones"][2]["tune_cents"], -50.0);
+        assert_eq!(serde_json::from_value::<Instrument>(json).unwrap(), sampler);
+
+        params.zones[0].low = Pitch::new(Tone::C, 8);
+        let invalid = Instrument::new(InstrumentKind::Sampler(params));
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn test_sampler_loop_points() {
+        let sampler: Instrument = serde_json::from_value(serde_json::json!({
+            "type": "sampler",
+            "parameters": { "sample_file": "pad.wav", "loop": true, "loop_start": 0.5, "loop_end": 1.2, "loop_crossfade": 0.05 }
+        })).unwrap();
+        assert!(sampler.validate().is_ok());
+        let InstrumentKind::Sampler(params) = &sampler.kind else { panic!("expected a sampler") };
+        assert_eq!((params.loop_start, params.loop_end, params.loop_crossfade), (Some(0.5), Some(1.2), 0.05));
+
+        let mut invalid = params.clone();
+        invalid.loop_end = Some(0.4);
+        assert!(Instrument::new(InstrumentKind::Sampler(invalid)).validate().is_err());
+
+        let mut invalid = params.clone();
+        invalid.loop_crossfade = 1.0;
+        assert!(Instrument::new(InstrumentKind::Sampler(invalid)).validate().is_err());
+    }
+
+    #[test]
+    fn test_effect_chain() {
+        use crate::effects::{Effect, GainParams, DelayParams};
+
+        let synth = Instrument::new_subtractive(SubtractiveParams::default())
+            .with_effect(EffectInstance::new(Effect::Gain(GainParams::new(-3.0))))
+            .with_effect(EffectInstance::new(Effect::Delay(DelayParams::new(0.25, 0.3, 0.5))));
+        assert!(synth.validate().is_ok());
+
+        let json = serde_json::to_value(&synth).unwrap();
+        assert_eq!(json["effects"][0]["type"], "gain");
+        assert_eq!(json["effects"][1]["type"], "delay");
+        assert_eq!(serde_json::from_value::<Instrument>(json).unwrap(), synth);
+
+        // Instruments without effects don't write the key
+        let sampler = serde_json::to_value(Instrument::new_sampler(PathBuf::from("kick.wav"))).unwrap();
+        assert!(sampler.get("effects").is_none());
+
+        let invalid = synth.with_effect(EffectInstance::new(Effect::Delay(DelayParams::new(-1.0, 0.0, 0.5))));
+        assert!(invalid.validate().is_err());
+    }
+}
@@ -1,49 +1,222 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use schemars::JsonSchema;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use anyhow::{Result, bail};
-use std::path::PathBuf;
-use std::time::SystemTime;
+use std::path::{Path, PathBuf};
 
 pub mod pitch;
 pub mod metadata;
 pub mod instrument;
+pub mod samples;
+pub mod effects;
+pub mod steps;
+pub mod mixer;
+pub mod presets;
+pub mod controllers;
+pub mod batch;
+pub mod time;
+pub mod abc;
+pub mod drums;
+pub mod format;
+pub mod schema;
+pub mod diff;
+pub mod observer;
+pub mod session;
+pub mod scale;
+pub mod chords;
+pub mod voices;
+pub mod velocity;
+pub mod mixdown;
+pub mod lyrics;
+pub mod groove;
+pub mod humanize;
+pub mod arpeggio;
+pub mod overlaps;
+pub mod events;
+pub mod selection;
+pub mod fragment;
+pub mod sfz;
 
 use pitch::Pitch;
 use metadata::Metadata;
-pub use instrument::Instrument;
-
-#[derive(Debug, Serialize, Deserialize, Clone)]
+pub use instrument::{Freeze, Instrument, InstrumentKind, Tuning};
+pub use samples::{SampleIssue, SampleRegistry};
+pub use steps::StepPattern;
+pub use mixer::{Mixer, Speakers};
+pub use presets::Preset;
+pub use controllers::{ControlCurve, ControlEvent, Controller};
+pub use batch::BatchEditor;
+pub use time::{time_from_32nds, time_to_32nds, Grid, TimeRange};
+pub use abc::AbcTune;
+pub use drums::DrumPattern;
+pub use format::FileFormat;
+pub use schema::SchemaViolation;
+pub use diff::{Change, MergeConflict, MergeOutcome, ProjectDiff};
+pub use observer::ProjectChange;
+pub use session::Session;
+pub use scale::{Key, ScaleType};
+pub use chords::{Chord, ChordEvent, ChordQuality, ChordSymbol};
+pub use voices::{Polyphony, StealPolicy, VoiceRequest};
+pub use velocity::{VelocityCurve, VelocityResponse};
+pub use mixdown::{ChannelLayout, Click, ClickMode, Dither, ExportFormat, MixdownSettings, Normalize, SampleFormat};
+pub use lyrics::{LyricEvent, TextKind};
+pub use groove::Groove;
+pub use humanize::Humanizer;
+pub use arpeggio::{ArpDirection, Arpeggio};
+pub use overlaps::OverlapPolicy;
+pub use events::Events;
+pub use selection::Selection;
+pub use fragment::Fragment;
+pub use sfz::Sfz;
+
+/// JSON keys this version doesn't recognise. They're kept on load and written back on
+/// save, so files from newer versions round-trip without losing data.
+pub type UnknownFields = serde_json::Map<String, serde_json::Value>;
+
+/// A song project. Serialization is canonical: instruments are written sorted by name and
+/// events and controls in time order, so saving the same song always produces the same JSON.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
 pub struct DawFile {
     pub metadata: Metadata,
     pub bpm: u32,
+    /// Swing or groove template applied to every instrument without its own
+    #[serde(default, skip_serializing_if = "Groove::is_default", deserialize_with = "deserialize_groove")]
+    pub groove: Groove,
+    /// How pitches map to frequencies; standard tuning when omitted
+    #[serde(default, skip_serializing_if = "pitch::Tuning::is_default", deserialize_with = "deserialize_tuning")]
+    pub tuning: pitch::Tuning,
+    /// Seed for everything random in a render (noise, dither) and for humanizing without
+    /// a seed of its own, so the same song always renders to the same audio
+    #[serde(default, skip_serializing_if = "is_default_seed")]
+    pub seed: u64,
+    /// Default output settings, used when no profile is chosen at render time
     pub mixdown: MixdownSettings,
+    /// Named alternative output settings, e.g. `cd-master` or `stems`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub mixdown_profiles: BTreeMap<String, MixdownSettings>,
+    #[serde(serialize_with = "serialize_sorted_instruments")]
     pub instruments: HashMap<String, Instrument>,
-    pub events: Vec<Event>,
+    pub events: Events,
+    #[serde(default, skip_serializing_if = "SampleRegistry::is_empty")]
+    pub samples: SampleRegistry,
+    #[serde(default, skip_serializing_if = "Mixer::is_default")]
+    pub mixer: Mixer,
+    /// Continuous controller changes (pitch bend, mod wheel, CCs)
+    #[serde(default, skip_serializing_if = "Vec::is_empty", serialize_with = "serialize_sorted_controls")]
+    pub controls: Vec<ControlEvent>,
+    /// Chord symbols giving the song's harmony, in time order
+    #[serde(default, skip_serializing_if = "Vec::is_empty", serialize_with = "serialize_sorted_chords")]
+    pub chords: Vec<ChordEvent>,
+    /// Lyrics and annotations, in time order
+    #[serde(default, skip_serializing_if = "Vec::is_empty", serialize_with = "serialize_sorted_lyrics")]
+    pub lyrics: Vec<LyricEvent>,
+    /// Editor state restored when the song is reopened (loop, playhead, snap)
+    #[serde(default, skip_serializing_if = "Session::is_empty")]
+    pub session: Session,
+    #[serde(flatten)]
+    pub extra: UnknownFields,
+    #[serde(skip)]
+    observers: observer::Observers,
+    /// Problems with the registered samples found when the file was read
+    #[serde(skip)]
+    sample_issues: Vec<SampleIssue>,
+}
+
+/// Read the song's tuning, refusing one that can't be played (e.g. with no steps)
+fn deserialize_tuning<'de, D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<pitch::Tuning, D::Error> {
+    let tuning = pitch::Tuning::deserialize(deserializer)?;
+    tuning.validate().map_err(serde::de::Error::custom)?;
+    Ok(tuning)
+}
+
+/// Read the song's groove, refusing one `set_groove` would
+fn deserialize_groove<'de, D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Groove, D::Error> {
+    let groove = Groove::deserialize(deserializer)?;
+    groove.validate().map_err(serde::de::Error::custom)?;
+    Ok(groove)
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct MixdownSettings {
-    pub sample_rate: u32,
-    pub bit_depth: u16,
+fn is_default_seed(seed: &u64) -> bool {
+    *seed == 0
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+fn serialize_sorted_instruments<S: serde::Serializer>(
+    instruments: &HashMap<String, Instrument>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.collect_map(instruments.iter().collect::<std::collections::BTreeMap<_, _>>())
+}
+
+fn serialize_sorted_chords<S: serde::Serializer>(chords: &[ChordEvent], serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    let mut sorted: Vec<&ChordEvent> = chords.iter().collect();
+    sorted.sort_by_key(|c| time_to_32nds(&c.time).unwrap_or(0));
+    serializer.collect_seq(sorted)
+}
+
+fn serialize_sorted_lyrics<S: serde::Serializer>(lyrics: &[LyricEvent], serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    let mut sorted: Vec<&LyricEvent> = lyrics.iter().collect();
+    sorted.sort_by_key(|l| (time_to_32nds(&l.time).unwrap_or(0), &l.instrument));
+    serializer.collect_seq(sorted)
+}
+
+fn serialize_sorted_controls<S: serde::Serializer>(controls: &[ControlEvent], serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    let mut sorted: Vec<&ControlEvent> = controls.iter().collect();
+    sorted.sort_by_key(|c| (time_to_32nds(&c.time).unwrap_or(0), &c.instrument, c.controller));
+    serializer.collect_seq(sorted)
+}
+
+/// Velocity used for notes that don't specify one
+pub const DEFAULT_VELOCITY: u8 = 100;
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq)]
 pub struct Note {
     pub pitch: Pitch,
     pub duration: u32,  // Duration in 32nd notes
+    /// MIDI-style velocity, 1 to 127
+    #[serde(default = "default_velocity", skip_serializing_if = "is_default_velocity")]
+    #[schemars(range(min = 1, max = 127))]
+    pub velocity: u8,
+    /// Stereo position from -1 (left) to 1 (right), on top of the track's pan
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(range(min = -1, max = 1))]
+    pub pan: Option<f64>,
+    #[serde(flatten)]
+    pub extra: UnknownFields,
+}
+
+fn default_velocity() -> u8 {
+    DEFAULT_VELOCITY
+}
+
+fn is_default_velocity(velocity: &u8) -> bool {
+    *velocity == DEFAULT_VELOCITY
 }
 
 impl Note {
     pub fn new(pitch: Pitch, duration: u32) -> Self {
-        Self { pitch, duration }
+        Self { pitch, duration, velocity: DEFAULT_VELOCITY, pan: None, extra: UnknownFields::new() }
+    }
+
+    pub fn with_velocity(mut self, velocity: u8) -> Self {
+        self.velocity = velocity;
+        self
+    }
+
+    pub fn with_pan(mut self, pan: f64) -> Self {
+        self.pan = Some(pan);
+        self
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq)]
 pub struct Event {
+    #[schemars(regex(pattern = schema::TIME_PATTERN))]
     pub time: String,
     pub instrument: String,
     pub notes: Vec<Note>,
+    #[serde(flatten)]
+    pub extra: UnknownFields,
 }
 
 impl DawFile {
@@ -52,45 +225,164 @@ impl DawFile {
         Self {
             metadata: Metadata::new(title),
             bpm: 120,
-            mixdown: MixdownSettings {
-                sample_rate: 44100,
-                bit_depth: 16,
-            },
+            groove: Groove::default(),
+            tuning: pitch::Tuning::default(),
+            seed: 0,
+            mixdown: MixdownSettings::default(),
+            mixdown_profiles: BTreeMap::new(),
             instruments: HashMap::new(),
-            events: Vec::new(),
+            events: Events::new(),
+            samples: SampleRegistry::new(),
+            mixer: Mixer::new(),
+            controls: Vec::new(),
+            chords: Vec::new(),
+            lyrics: Vec::new(),
+            session: Session::default(),
+            extra: UnknownFields::new(),
+            observers: Default::default(),
+            sample_issues: Vec::new(),
         }
     }
 
-    /// Save to disk, handling the revision increment
+    /// Check a JSON project document against the schema (see [`schema::json_schema`]) and
+    /// report every violation with its JSON pointer. An empty list means the document is valid.
+    pub fn validate_document(document: &str) -> Vec<SchemaViolation> {
+        schema::validate_document(document)
+    }
+
+    /// Receive a [`ProjectChange`] for every edit made through this song's methods from now on
+    pub fn subscribe(&mut self) -> std::sync::mpsc::Receiver<ProjectChange> {
+        self.observers.subscribe()
+    }
+
+    fn notify(&mut self, change: ProjectChange) {
+        self.observers.notify(change);
+    }
+
+    /// Everything that changed going from `self` to `other`
+    pub fn diff(&self, other: &DawFile) -> ProjectDiff {
+        diff::diff(self, other)
+    }
+
+    /// Three-way merge of two edits (`ours` and `theirs`) of the same `base` song.
+    /// Instruments and events merge one at a time; when both sides changed the same
+    /// thing differently, `ours` wins and the conflict is reported.
+    pub fn merge(base: &DawFile, ours: &DawFile, theirs: &DawFile) -> MergeOutcome {
+        diff::merge(base, ours, theirs)
+    }
+
+    /// Save to disk, handling the revision increment. Paths ending in `.dawb` are written
+    /// in the compressed binary format, anything else as JSON.
     pub fn save(&mut self, path: &PathBuf) -> Result<()> {
         // Update modification date and increment revision
         self.metadata.update_modification_date();
         self.metadata.increment_revision();
 
-        // Serialize and write to file
-        let content = serde_json::to_string_pretty(self)?;
+        // Register any newly referenced samples that exist next to the file
+        let base_dir = path.parent().unwrap_or(Path::new(""));
+        for sample_path in self.sample_references() {
+            if self.samples.get(&sample_path).is_none() && base_dir.join(&sample_path).is_file() {
+                self.samples.register(base_dir, &sample_path)?;
+            }
+        }
+
+        // Serialize in the format the extension asks for and write to file
+        let content = FileFormat::from_path(path).encode(self)?;
         std::fs::write(path, content)?;
         Ok(())
     }
 
     /// Update the song title
     pub fn set_title(&mut self, title: String) {
-        self.metadata.set_title(title);
+        self.metadata.set_title(title.clone());
+        self.notify(ProjectChange::TitleChanged { title });
     }
 
     /// Update the song tempo
     pub fn set_bpm(&mut self, bpm: u32) {
-        self.bpm = bpm;
-        std::thread::sleep(std::time::Duration::from_secs(1));
+        let from = std::mem::replace(&mut self.bpm, bpm);
+        self.metadata.update_modification_date();
+        self.notify(ProjectChange::BpmChanged { from, to: bpm });
+    }
+
+    /// Set or clear the song's key signature
+    pub fn set_key(&mut self, key: Option<Key>) {
+        self.metadata.key = key.clone();
         self.metadata.update_modification_date();
+        self.notify(ProjectChange::KeyChanged { key });
+    }
+
+    /// Set the seed that renders and unseeded humanizing draw their randomness from
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.metadata.update_modification_date();
+        self.notify(ProjectChange::SeedChanged { seed });
+    }
+
+    /// Replace the saved editor state. This isn't an edit to the song, so the
+    /// modification date is left alone.
+    pub fn set_session(&mut self, session: Session) -> Result<()> {
+        session.validate()?;
+        self.session = session;
+        Ok(())
     }
 
     /// Update the mixdown settings
     pub fn set_mixdown_settings(&mut self, sample_rate: u32, bit_depth: u16) {
         self.mixdown.sample_rate = sample_rate;
         self.mixdown.bit_depth = bit_depth;
-        std::thread::sleep(std::time::Duration::from_secs(1));
         self.metadata.update_modification_date();
+        self.notify(ProjectChange::MixdownChanged);
+    }
+
+    /// Add or replace a named mixdown profile
+    pub fn set_mixdown_profile(&mut self, name: &str, settings: MixdownSettings) -> Result<()> {
+        if name.is_empty() {
+            bail!("Mixdown profile name cannot be empty");
+        }
+        settings.validate()?;
+        self.mixdown_profiles.insert(name.to_string(), settings);
+        self.metadata.update_modification_date();
+        self.notify(ProjectChange::MixdownChanged);
+        Ok(())
+    }
+
+    pub fn remove_mixdown_profile(&mut self, name: &str) -> Result<MixdownSettings> {
+        let settings = self.mixdown_profiles.remove(name)
+            .ok_or_else(|| anyhow::anyhow!("Mixdown profile '{}' not found", name))?;
+        self.metadata.update_modification_date();
+        self.notify(ProjectChange::MixdownChanged);
+        Ok(settings)
+    }
+
+    /// Settings for a named profile, or the default `mixdown` settings for `None`
+    pub fn mixdown_profile(&self, name: Option<&str>) -> Result<&MixdownSettings> {
+        match name {
+            None => Ok(&self.mixdown),
+            Some(name) => self.mixdown_profiles.get(name)
+                .ok_or_else(|| anyhow::anyhow!("Mixdown profile '{}' not found", name)),
+        }
+    }
+
+    /// Change the project sample rate, returning the registered samples that no longer
+    /// match it and will need resampling when rendered
+    pub fn convert_sample_rate(&mut self, sample_rate: u32) -> Result<Vec<String>> {
+        if sample_rate == 0 {
+            bail!("Sample rate must be greater than zero");
+        }
+
+        self.mixdown.sample_rate = sample_rate;
+        self.metadata.update_modification_date();
+        self.notify(ProjectChange::MixdownChanged);
+        Ok(self.samples_needing_resample())
+    }
+
+    /// List registered samples whose sample rate differs from the project's
+    pub fn samples_needing_resample(&self) -> Vec<String> {
+        self.samples.needing_resample(self.mixdown.sample_rate)
+            .into_iter()
+            .map(|s| s.to_string())
+            .collect()
     }
 
     /// Add a new instrument
@@ -103,11 +395,172 @@ impl DawFile {
             bail!("Instrument with ID '{}' already exists", id);
         }
 
-        self.instruments.insert(id, instrument);
+        self.instruments.insert(id.clone(), instrument);
         self.metadata.update_modification_date();
+        self.notify(ProjectChange::InstrumentAdded { id });
         Ok(())
     }
 
+    /// Set the coarse and fine tuning of an instrument
+    pub fn set_instrument_tuning(&mut self, id: &str, tuning: Tuning) -> Result<()> {
+        tuning.validate()?;
+        let instrument = self.instruments.get_mut(id)
+            .ok_or_else(|| anyhow::anyhow!("Instrument '{}' not found", id))?;
+        instrument.tuning = tuning;
+        self.metadata.update_modification_date();
+        self.notify(ProjectChange::InstrumentChanged { id: id.to_string() });
+        Ok(())
+    }
+
+    /// Set the song's groove
+    pub fn set_groove(&mut self, groove: Groove) -> Result<()> {
+        groove.validate()?;
+        self.groove = groove;
+        self.metadata.update_modification_date();
+        self.notify(ProjectChange::GrooveChanged);
+        Ok(())
+    }
+
+    /// Set the tuning system every note is played in
+    pub fn set_tuning(&mut self, tuning: pitch::Tuning) -> Result<()> {
+        tuning.validate()?;
+        self.tuning = tuning;
+        self.metadata.update_modification_date();
+        self.notify(ProjectChange::TuningChanged);
+        Ok(())
+    }
+
+    /// Tune the song so A4 plays at `a4` Hz (e.g. 432 or 442), keeping the tuning system's intervals
+    pub fn set_a4_frequency(&mut self, a4: f64) -> Result<()> {
+        let tuning = self.tuning.clone().with_a4(a4)?;
+        self.set_tuning(tuning)
+    }
+
+    /// The frequency A4 plays at, or `None` if the tuning's keyboard mapping leaves it silent
+    pub fn a4_frequency(&self) -> Option<f64> {
+        self.tuning.a4()
+    }
+
+    /// Give an instrument its own groove, or `None` to follow the song's
+    pub fn set_instrument_groove(&mut self, id: &str, groove: Option<Groove>) -> Result<()> {
+        if let Some(groove) = &groove {
+            groove.validate()?;
+        }
+        let instrument = self.instruments.get_mut(id)
+            .ok_or_else(|| anyhow::anyhow!("Instrument '{}' not found", id))?;
+        instrument.groove = groove;
+        self.metadata.update_modification_date();
+        self.notify(ProjectChange::InstrumentChanged { id: id.to_string() });
+        Ok(())
+    }
+
+    /// The groove an instrument's events are played with
+    pub fn groove_for(&self, instrument: &str) -> &Groove {
+        self.instruments.get(instrument)
+            .and_then(|i| i.groove.as_ref())
+            .unwrap_or(&self.groove)
+    }
+
+    /// Arpeggiate an instrument's events when rendering, or `None` to play them as written
+    pub fn set_instrument_arpeggio(&mut self, id: &str, arpeggio: Option<Arpeggio>) -> Result<()> {
+        if let Some(arpeggio) = &arpeggio {
+            arpeggio.validate()?;
+        }
+        let instrument = self.instruments.get_mut(id)
+            .ok_or_else(|| anyhow::anyhow!("Instrument '{}' not found", id))?;
+        instrument.arpeggio = arpeggio;
+        self.metadata.update_modification_date();
+        self.notify(ProjectChange::InstrumentChanged { id: id.to_string() });
+        Ok(())
+    }
+
+    /// Replace the event at `time` on `instrument` with its notes played one at a time
+    /// by `arpeggio`. Returns the number of notes written.
+    pub fn arpeggiate(&mut self, time: &str, instrument: &str, arpeggio: &Arpeggio) -> Result<usize> {
+        arpeggio.validate()?;
+        let start = time_to_32nds(time)?;
+        self.batch(|editor| {
+            let event = editor.remove_event(time, instrument)?;
+            let notes = arpeggio.expand(&event.notes);
+            for (offset, note) in &notes {
+                editor.add_note(&time_from_32nds(start + offset), instrument, note.clone())?;
+            }
+            Ok(notes.len())
+        })
+    }
+
+    /// The events as they're played: events on instruments with an arpeggio are expanded
+    /// into one event per arpeggiated note
+    pub fn played_events(&self) -> Cow<'_, Events> {
+        let arpeggios: HashMap<&str, &Arpeggio> = self.instruments.iter()
+            .filter_map(|(id, i)| Some((id.as_str(), i.arpeggio.as_ref()?)))
+            .collect();
+        if arpeggios.is_empty() {
+            return Cow::Borrowed(&self.events);
+        }
+
+        let mut events = Events::new();
+        for event in &self.events {
+            let arpeggio = arpeggios.get(event.instrument.as_str());
+            let (Some(arpeggio), Ok(start)) = (arpeggio, time_to_32nds(&event.time)) else {
+                events.insert(event.clone()).expect("indexed events have valid times");
+                continue;
+            };
+            for (offset, note) in arpeggio.expand(&event.notes) {
+                events.insert(Event {
+                    time: time_from_32nds(start + offset),
+                    instrument: event.instrument.clone(),
+                    notes: vec![note],
+                    extra: event.extra.clone(),
+                }).expect("arpeggiated times are valid");
+            }
+        }
+        Cow::Owned(events)
+    }
+
+    /// Set how many notes an instrument can play at once
+    pub fn set_instrument_polyphony(&mut self, id: &str, polyphony: Polyphony) -> Result<()> {
+        polyphony.validate()?;
+        let instrument = self.instruments.get_mut(id)
+            .ok_or_else(|| anyhow::anyhow!("Instrument '{}' not found", id))?;
+        instrument.polyphony = polyphony;
+        self.metadata.update_modification_date();
+        self.notify(ProjectChange::InstrumentChanged { id: id.to_string() });
+        Ok(())
+    }
+
+    /// Play `audio_path` (relative to the project file) instead of an instrument's events.
+    /// The events are kept so the instrument can be unfrozen and edited again.
+    pub fn freeze_instrument(&mut self, id: &str, audio_path: PathBuf) -> Result<()> {
+        let instrument = self.instruments.get_mut(id)
+            .ok_or_else(|| anyhow::anyhow!("Instrument '{}' not found", id))?;
+        instrument.frozen = Some(Freeze::new(audio_path));
+        self.metadata.update_modification_date();
+        self.notify(ProjectChange::InstrumentChanged { id: id.to_string() });
+        Ok(())
+    }
+
+    /// Go back to playing an instrument's events, returning the freeze that was removed
+    /// so its audio file can be cleaned up
+    pub fn unfreeze_instrument(&mut self, id: &str) -> Result<Freeze> {
+        let instrument = self.instruments.get_mut(id)
+            .ok_or_else(|| anyhow::anyhow!("Instrument '{}' not found", id))?;
+        let freeze = instrument.frozen.take()
+            .ok_or_else(|| anyhow::anyhow!("Instrument '{}' is not frozen", id))?;
+        self.metadata.update_modification_date();
+        self.notify(ProjectChange::InstrumentChanged { id: id.to_string() });
+        Ok(freeze)
+    }
+
+    /// Put recorded audio on the timeline as a track of its own: an instrument `id` with no
+    /// events, frozen to `audio_path` (relative to the project file), which plays from
+    /// `start` seconds into the song. The instrument samples the same file.
+    pub fn add_audio_clip(&mut self, id: &str, audio_path: PathBuf, start: f64) -> Result<()> {
+        let mut instrument = Instrument::new_sampler(audio_path.clone());
+        instrument.frozen = Some(Freeze::new(audio_path).starting_at(start));
+        self.add_instrument(id.to_string(), instrument)
+    }
+
     /// Remove an instrument
     pub fn remove_instrument(&mut self, id: &str) -> Result<()> {
         // Check if instrument exists
@@ -116,12 +569,16 @@ impl DawFile {
         }
 
         // Check if instrument is used in any events
-        if self.events.iter().any(|e| e.instrument == id) {
+        if self.events.uses_instrument(id) {
             bail!("Cannot remove instrument '{}' as it is used in events", id);
         }
+        self.controls.retain(|c| c.instrument != id);
+        self.lyrics.retain(|l| l.instrument.as_deref() != Some(id));
 
         self.instruments.remove(id);
+        self.mixer.tracks.remove(id);
         self.metadata.update_modification_date();
+        self.notify(ProjectChange::InstrumentRemoved { id: id.to_string() });
         Ok(())
     }
 
@@ -140,15 +597,28 @@ impl DawFile {
         // Remove and reinsert with new key
         let instrument = self.instruments.remove(old_id).unwrap();
         self.instruments.insert(new_id.clone(), instrument);
+        self.mixer.rename_track(old_id, &new_id);
 
         // Update all events using this instrument
-        for event in &mut self.events {
-            if event.instrument == old_id {
-                event.instrument = new_id.clone();
+        let keys: Vec<String> = self.events.for_instrument(old_id).map(|e| e.time.clone()).collect();
+        for time in keys {
+            let mut event = self.events.remove(&time, old_id).expect("event listed for the instrument");
+            event.instrument = new_id.clone();
+            self.events.insert(event)?;
+        }
+        for control in &mut self.controls {
+            if control.instrument == old_id {
+                control.instrument = new_id.clone();
+            }
+        }
+        for lyric in &mut self.lyrics {
+            if lyric.instrument.as_deref() == Some(old_id) {
+                lyric.instrument = Some(new_id.clone());
             }
         }
 
         self.metadata.update_modification_date();
+        self.notify(ProjectChange::InstrumentRenamed { from: old_id.to_string(), to: new_id });
         Ok(())
     }
 
@@ -167,6 +637,19 @@ impl DawFile {
         self.instruments.keys().map(|s| s.as_str()).collect()
     }
 
+    /// Save an instrument's parameters and effects as a standalone preset file
+    pub fn export_preset(&self, instrument_id: &str, name: String, path: &Path) -> Result<()> {
+        let instrument = self.get_instrument(instrument_id)
+            .ok_or_else(|| anyhow::anyhow!("Instrument '{}' not found", instrument_id))?;
+        Preset::new(name, instrument.clone()).save(path)
+    }
+
+    /// Add a new instrument from a preset file
+    pub fn import_preset(&mut self, path: &Path, id: String) -> Result<()> {
+        let preset = Preset::load(path)?;
+        self.add_instrument(id, preset.instrument)
+    }
+
     /// Create a new sampler instrument
     pub fn create_sampler_instrument(
         &mut self,
@@ -177,6 +660,65 @@ impl DawFile {
         self.add_instrument(id, instrument)
     }
 
+    /// List the sample files referenced by instruments, sorted and deduplicated
+    pub fn sample_references(&self) -> Vec<String> {
+        let mut paths: Vec<String> = self.instruments.values()
+            .flat_map(|i| i.sample_files())
+            .map(|s| s.to_string())
+            .collect();
+        paths.sort();
+        paths.dedup();
+        paths
+    }
+
+    /// Hash every referenced sample under `base_dir` and drop registry entries no longer in use
+    pub fn sync_samples(&mut self, base_dir: &Path) -> Result<()> {
+        let paths = self.sample_references();
+        for path in &paths {
+            self.samples.register(base_dir, path)?;
+        }
+        self.samples.retain_paths(&paths);
+        Ok(())
+    }
+
+    /// Check registered samples for missing, moved, or modified files
+    pub fn check_samples(&self, base_dir: &Path) -> Vec<SampleIssue> {
+        self.samples.check(base_dir)
+    }
+
+    /// Missing, moved or modified samples found by `read_daw_file`, relative to the file
+    pub fn sample_issues(&self) -> &[SampleIssue] {
+        &self.sample_issues
+    }
+
+    /// Point every instrument using `old_path` at `new_path` and re-register the sample
+    pub fn relink_sample(&mut self, base_dir: &Path, old_path: &str, new_path: &str) -> Result<()> {
+        if !self.sample_references().iter().any(|p| p == old_path) {
+            bail!("No instrument references sample '{}'", old_path);
+        }
+
+        self.samples.register(base_dir, new_path)?;
+        self.samples.unregister(old_path);
+
+        for instrument in self.instruments.values_mut() {
+            let files: Vec<&mut String> = match &mut instrument.kind {
+                InstrumentKind::Sampler(params) => std::iter::once(&mut params.sample_file)
+                    .chain(params.zones.iter_mut().map(|z| &mut z.sample_file))
+                    .collect(),
+                InstrumentKind::Sfz(params) => vec![&mut params.sfz_file],
+                _ => Vec::new(),
+            };
+            for sample_file in files {
+                if sample_file == old_path {
+                    *sample_file = new_path.to_string();
+                }
+            }
+        }
+
+        self.metadata.update_modification_date();
+        Ok(())
+    }
+
     /// Add a new event
     pub fn add_event(&mut self, event: Event) -> Result<()> {
         // Validate instrument exists
@@ -187,11 +729,10 @@ impl DawFile {
         // Validate time format
         self.validate_time_format(&event.time)?;
 
-        // Insert event in correct position to maintain chronological order
-        let insert_pos = self.events.partition_point(|e| e.time < event.time);
-        self.events.insert(insert_pos, event);
-        
+        let change = ProjectChange::EventAdded { time: event.time.clone(), instrument: event.instrument.clone() };
+        self.events.insert(event)?;
         self.metadata.update_modification_date();
+        self.notify(change);
         Ok(())
     }
 
@@ -201,11 +742,10 @@ impl DawFile {
         self.validate_time_format(time)?;
 
         // Find and remove the event
-        let pos = self.events.iter().position(|e| e.time == time && e.instrument == instrument)
+        self.events.remove(time, instrument)
             .ok_or_else(|| anyhow::anyhow!("Event not found at time '{}' for instrument '{}'", time, instrument))?;
-        
-        self.events.remove(pos);
         self.metadata.update_modification_date();
+        self.notify(ProjectChange::EventRemoved { time: time.to_string(), instrument: instrument.to_string() });
         Ok(())
     }
 
@@ -220,48 +760,646 @@ impl DawFile {
             bail!("New instrument '{}' not found", new_event.instrument);
         }
 
-        // Find the event
-        let pos = self.events.iter().position(|e| e.time == time && e.instrument == instrument)
+        // Replace the event, re-indexing it under its new time and instrument
+        self.events.remove(time, instrument)
             .ok_or_else(|| anyhow::anyhow!("Event not found at time '{}' for instrument '{}'", time, instrument))?;
+        self.events.insert(new_event)?;
+
+        self.metadata.update_modification_date();
+        self.notify(ProjectChange::EventUpdated { time: time.to_string(), instrument: instrument.to_string() });
+        Ok(())
+    }
 
-        // If time changed, we need to maintain chronological order
-        if new_event.time != time {
-            self.events.remove(pos);
-            let insert_pos = self.events.partition_point(|e| e.time < new_event.time);
-            self.events.insert(insert_pos, new_event);
-        } else {
-            self.events[pos] = new_event;
+    /// Add a note to an existing event, or create a new event if none exists
+    pub fn add_note(&mut self, time: &str, instrument: &str, note: Note) -> Result<()> {
+        // Validate time format
+        self.validate_time_format(time)?;
+
+        // Validate instrument exists
+        if !self.instruments.contains_key(instrument) {
+            bail!("Instrument '{}' not found", instrument);
+        }
+        if let Some(pan) = note.pan {
+            if !(-1.0..=1.0).contains(&pan) {
+                bail!("Pan must be between -1 and 1, got {}", pan);
+            }
+        }
+
+        let change = ProjectChange::NoteAdded {
+            time: time.to_string(),
+            instrument: instrument.to_string(),
+            note: note.clone(),
+        };
+
+        // Add to the event at this time, creating it if needed
+        self.events.insert(Event {
+            time: time.to_string(),
+            instrument: instrument.to_string(),
+            notes: vec![note],
+            extra: UnknownFields::new(),
+        })?;
+        self.metadata.update_modification_date();
+        self.notify(change);
+        Ok(())
+    }
+
+    /// Apply many event edits at once. The edits are validated as they're made; if the
+    /// closure returns an error nothing is applied. The modification date is updated once
+    /// at the end.
+    pub fn batch<T>(&mut self, edit: impl FnOnce(&mut BatchEditor) -> Result<T>) -> Result<T> {
+        let mut editor = BatchEditor::new(&self.instruments, self.events.clone());
+        let result = edit(&mut editor)?;
+        let (events, changed) = editor.finish();
+        if changed {
+            self.events = events;
+            self.metadata.update_modification_date();
+            self.notify(ProjectChange::EventsChanged);
+        }
+        Ok(result)
+    }
+
+    /// Shift note pitches by `semitones`, optionally limited to a selection and/or one
+    /// instrument. Notes that would leave the pitch range are moved by whole octaves to stay
+    /// in it. Returns the number of notes changed.
+    pub fn transpose(&mut self, semitones: i32, selection: Option<Selection>, instrument: Option<&str>) -> Result<usize> {
+        if let Some(instrument) = instrument {
+            if !self.instruments.contains_key(instrument) {
+                bail!("Instrument '{}' not found", instrument);
+            }
+        }
+
+        let mut count = 0;
+        for event in self.events.iter_mut()
+            .filter(|e| instrument.is_none_or(|i| e.instrument == i))
+            .filter(|e| selection.is_none_or(|s| s.time.contains_time(&e.time)))
+        {
+            for note in event.notes.iter_mut().filter(|n| selection.is_none_or(|s| s.pitches.contains(&n.pitch))) {
+                note.pitch = note.pitch.transpose_clamped(semitones);
+                count += 1;
+            }
+        }
+
+        if count > 0 {
+            self.metadata.update_modification_date();
+            self.notify(ProjectChange::EventsChanged);
+        }
+        Ok(count)
+    }
+
+    /// Move note pitches by `steps` degrees of the song's key, optionally limited to a
+    /// selection and/or one instrument. Out-of-key notes are snapped into the key first, and
+    /// notes that would leave the pitch range are left alone. Returns the number of notes changed.
+    pub fn diatonic_transpose(&mut self, steps: i32, selection: Option<Selection>, instrument: Option<&str>) -> Result<usize> {
+        let key = self.metadata.key.clone().ok_or_else(|| anyhow::anyhow!("The song has no key set"))?;
+        if let Some(instrument) = instrument {
+            if !self.instruments.contains_key(instrument) {
+                bail!("Instrument '{}' not found", instrument);
+            }
+        }
+
+        let mut count = 0;
+        for event in self.events.iter_mut()
+            .filter(|e| instrument.is_none_or(|i| e.instrument == i))
+            .filter(|e| selection.is_none_or(|s| s.time.contains_time(&e.time)))
+        {
+            for note in event.notes.iter_mut().filter(|n| selection.is_none_or(|s| s.pitches.contains(&n.pitch))) {
+                if let Some(pitch) = key.diatonic_transpose(note.pitch, steps) {
+                    if pitch != note.pitch {
+                        note.pitch = pitch;
+                        count += 1;
+                    }
+                }
+            }
+        }
+
+        if count > 0 {
+            self.metadata.update_modification_date();
+            self.notify(ProjectChange::EventsChanged);
+        }
+        Ok(count)
+    }
+
+    /// Resolve overlapping notes of the same pitch on `instrument` with `policy`, looking
+    /// only at notes that sound during `range` if one is given. Returns the number of
+    /// overlaps resolved.
+    pub fn resolve_overlaps(&mut self, instrument: &str, range: Option<TimeRange>, policy: OverlapPolicy) -> Result<usize> {
+        if !self.instruments.contains_key(instrument) {
+            bail!("Instrument '{}' not found", instrument);
+        }
+
+        let mut originals = Vec::new();
+        let mut notes = Vec::new();
+        for event in self.events.for_instrument(instrument) {
+            let position = time_to_32nds(&event.time)?;
+            for note in &event.notes {
+                if range.is_none_or(|r| r.overlaps(position, position + note.duration)) {
+                    originals.push((event.time.clone(), note.clone()));
+                    notes.push((position, note.clone()));
+                }
+            }
+        }
+
+        let (resolved, count) = overlaps::resolve(&notes, policy);
+        if count == 0 {
+            return Ok(0);
+        }
+        self.batch(|editor| {
+            for (time, note) in &originals {
+                editor.remove_note(time, instrument, note)?;
+            }
+            for (position, note) in resolved {
+                editor.add_note(&time_from_32nds(position), instrument, note)?;
+            }
+            Ok(count)
+        })
+    }
+
+    /// Move note onsets toward `grid` by `strength` (0 to 1), optionally limited to a
+    /// selection. Notes landing on the same time and instrument are merged into one event.
+    /// Returns the number of events moved from.
+    pub fn quantize(&mut self, grid: Grid, strength: f64, selection: Option<Selection>) -> Result<usize> {
+        validate_strength(strength)?;
+        self.quantize_with(selection, |_, position| grid.snap(position, strength))
+    }
+
+    /// Like [`DawFile::quantize`], but toward the grid lines of each instrument's groove (see
+    /// [`DawFile::groove_for`]), rounded to 32nds. This writes the groove into the notes, so it's
+    /// meant for songs whose playback groove is straight.
+    pub fn quantize_to_groove(&mut self, strength: f64, selection: Option<Selection>) -> Result<usize> {
+        validate_strength(strength)?;
+        let song_groove = self.groove.clone();
+        let grooves: HashMap<String, Groove> = self.instruments.keys()
+            .map(|id| (id.clone(), self.groove_for(id).clone()))
+            .collect();
+        self.quantize_with(selection, |instrument, position| {
+            grooves.get(instrument).unwrap_or(&song_groove).snap(position, strength)
+        })
+    }
+
+    /// Move each selected note to the position `snap` gives for its instrument and onset
+    fn quantize_with(&mut self, selection: Option<Selection>, snap: impl Fn(&str, u32) -> u32) -> Result<usize> {
+        let moves: Vec<(u32, &str, &Note, u32)> = self.events.notes()
+            .filter(|(position, _, note)| selection.is_none_or(|s| s.contains(*position, &note.pitch)))
+            .filter_map(|(position, instrument, note)| {
+                let snapped = snap(instrument, position);
+                (snapped != position).then_some((position, instrument, note, snapped))
+            })
+            .collect();
+        let moves: Vec<(String, String, Note, u32)> = moves.into_iter()
+            .map(|(position, instrument, note, snapped)| (time_from_32nds(position), instrument.to_string(), note.clone(), snapped))
+            .collect();
+        let moved_from: BTreeSet<(&str, &str)> = moves.iter()
+            .map(|(time, instrument, _, _)| (time.as_str(), instrument.as_str()))
+            .collect();
+
+        self.batch(|editor| {
+            // Take every note out first so a moved note can't be picked up again
+            for (time, instrument, note, _) in &moves {
+                editor.remove_note(time, instrument, note)?;
+            }
+            for (_, instrument, note, snapped) in &moves {
+                editor.add_note(&time_from_32nds(*snapped), instrument, note.clone())?;
+            }
+            Ok(moved_from.len())
+        })
+    }
+
+    /// Move note durations toward multiples of `grid` by `strength` (0 to 1), never shorter
+    /// than one grid step. Returns the number of notes changed.
+    pub fn quantize_durations(&mut self, grid: Grid, strength: f64, selection: Option<Selection>) -> Result<usize> {
+        validate_strength(strength)?;
+
+        let mut count = 0;
+        for event in self.events.iter_mut().filter(|e| selection.is_none_or(|s| s.time.contains_time(&e.time))) {
+            for note in event.notes.iter_mut().filter(|n| selection.is_none_or(|s| s.pitches.contains(&n.pitch))) {
+                let duration = grid.snap(note.duration, strength).max(grid.step());
+                if duration != note.duration {
+                    note.duration = duration;
+                    count += 1;
+                }
+            }
+        }
+
+        if count > 0 {
+            self.metadata.update_modification_date();
+            self.notify(ProjectChange::EventsChanged);
+        }
+        Ok(count)
+    }
+
+    /// Stretch the events starting in `range` by `factor` (0.5 = double time, 2 = half time),
+    /// scaling onsets from the start of the range and note durations. Results are rounded to
+    /// the nearest 32nd, and durations are kept at least one 32nd long. Events outside the
+    /// range don't move. Returns the number of events changed.
+    pub fn scale_time(&mut self, range: TimeRange, factor: f64) -> Result<usize> {
+        if !factor.is_finite() || factor <= 0.0 {
+            bail!("Time scale factor must be greater than 0, got {}", factor);
+        }
+        let scale = |value: u32| (value as f64 * factor).round() as u32;
+
+        let keys: Vec<(String, String)> = self.events.iter()
+            .filter(|e| range.contains_time(&e.time))
+            .map(|e| (e.time.clone(), e.instrument.clone()))
+            .collect();
+
+        self.batch(|editor| {
+            // Take every event out before re-adding, so moved events never merge into
+            // ones that are still waiting to move
+            let mut scaled = Vec::with_capacity(keys.len());
+            for (time, instrument) in &keys {
+                let mut event = editor.remove_event(time, instrument)?;
+                let position = time_to_32nds(&event.time)?;
+                event.time = time_from_32nds(range.start + scale(position - range.start));
+                for note in &mut event.notes {
+                    note.duration = scale(note.duration).max(1);
+                }
+                scaled.push(event);
+            }
+            for event in scaled {
+                editor.add_event(event)?;
+            }
+            Ok(keys.len())
+        })
+    }
+
+    /// Nudge the events starting in `range` by up to `timing_var` 32nds and their note velocities
+    /// by up to `velocity_var`, at random but reproducibly for a given `seed`, or the song's seed
+    /// when `None`. Events landing on the same time and instrument are merged. Returns the number
+    /// of events changed.
+    pub fn humanize(&mut self, range: Option<TimeRange>, timing_var: u32, velocity_var: u8, seed: Option<u64>) -> Result<usize> {
+        let mut humanizer = Humanizer::new(timing_var, velocity_var, seed.unwrap_or(self.seed));
+        let keys: Vec<(String, String)> = self.events.iter()
+            .filter(|e| range.is_none_or(|r| r.contains_time(&e.time)))
+            .map(|e| (e.time.clone(), e.instrument.clone()))
+            .collect();
+
+        self.batch(|editor| {
+            // As in scale_time, take every event out first so a moved event can't merge into
+            // one that hasn't been humanized yet
+            let mut humanized = Vec::with_capacity(keys.len());
+            let mut count = 0;
+            for (time, instrument) in &keys {
+                let mut event = editor.remove_event(time, instrument)?;
+                let position = time_to_32nds(&event.time)?;
+                let mut changed = false;
+                event.time = time_from_32nds(humanizer.position(position));
+                changed |= event.time != *time;
+                for note in &mut event.notes {
+                    let velocity = humanizer.velocity(note.velocity);
+                    changed |= velocity != note.velocity;
+                    note.velocity = velocity;
+                }
+                count += usize::from(changed);
+                humanized.push(event);
+            }
+            for event in humanized {
+                editor.add_event(event)?;
+            }
+            Ok(count)
+        })
+    }
+
+    /// Insert `count` empty bars before bar `at`, pushing later events, controls, chords and lyrics back
+    pub fn insert_bars(&mut self, at: u32, count: u32) -> Result<()> {
+        if at == 0 {
+            bail!("Bar number must be greater than 0");
+        }
+        let from = (at - 1) * 32;
+        self.shift_positions(|position| (position >= from).then_some(position + count * 32));
+        self.metadata.update_modification_date();
+        Ok(())
+    }
+
+    /// Delete the bars in `range`, removing the events, controls, chords and lyrics that start in it and
+    /// pulling later ones earlier
+    pub fn delete_bars(&mut self, range: TimeRange) -> Result<()> {
+        validate_bar_range(&range)?;
+        let length = range.end - range.start;
+        self.events.remove_range(range);
+        self.controls.retain(|c| !range.contains_time(&c.time));
+        self.chords.retain(|c| !range.contains_time(&c.time));
+        self.lyrics.retain(|l| !range.contains_time(&l.time));
+        self.shift_positions(|position| Some(if position >= range.end { position - length } else { position }));
+        self.metadata.update_modification_date();
+        Ok(())
+    }
+
+    /// Copy the events, controls, chords and lyrics in `range` so they start at bar `to`, merging with
+    /// anything already there
+    pub fn duplicate_bars(&mut self, range: TimeRange, to: u32) -> Result<()> {
+        validate_bar_range(&range)?;
+        if to == 0 {
+            bail!("Bar number must be greater than 0");
+        }
+        let destination = (to - 1) * 32;
+        let moved = |time: &str| time_to_32nds(time).ok()
+            .map(|position| time_from_32nds(position - range.start + destination));
+
+        let copies: Vec<Event> = self.events.range(range)
+            .filter_map(|e| Some(Event { time: moved(&e.time)?, ..e.clone() }))
+            .collect();
+        let control_copies: Vec<ControlEvent> = self.controls.iter()
+            .filter(|c| range.contains_time(&c.time))
+            .filter_map(|c| Some(ControlEvent { time: moved(&c.time)?, ..c.clone() }))
+            .collect();
+        let chord_copies: Vec<ChordEvent> = self.chords.iter()
+            .filter(|c| range.contains_time(&c.time))
+            .filter_map(|c| Some(ChordEvent { time: moved(&c.time)?, ..c.clone() }))
+            .collect();
+        let lyric_copies: Vec<LyricEvent> = self.lyrics.iter()
+            .filter(|l| range.contains_time(&l.time))
+            .filter_map(|l| Some(LyricEvent { time: moved(&l.time)?, ..l.clone() }))
+            .collect();
+
+        self.batch(|editor| {
+            for event in copies {
+                editor.add_event(event)?;
+            }
+            Ok(())
+        })?;
+        for control in control_copies {
+            self.add_control(control)?;
+        }
+        if !chord_copies.is_empty() {
+            for chord in chord_copies {
+                self.insert_chord(chord);
+            }
+            self.notify(ProjectChange::ChordsChanged);
+        }
+        if !lyric_copies.is_empty() {
+            for lyric in lyric_copies {
+                self.insert_lyric(lyric);
+            }
+            self.notify(ProjectChange::LyricsChanged);
+        }
+        Ok(())
+    }
+
+    /// Copy the notes in a selection or time range, and the controls in its time range, into
+    /// a [`Fragment`] for pasting
+    pub fn copy_fragment(&self, selection: impl Into<Selection>) -> Fragment {
+        let selection = selection.into();
+        let start = selection.time.start;
+        let relative = |time: &str| time_to_32nds(time).ok().map(|position| time_from_32nds(position - start));
+
+        let mut fragment = Fragment::new(selection.time.len());
+        fragment.events = self.events.range(selection.time)
+            .filter_map(|e| {
+                let notes: Vec<Note> = e.notes.iter().filter(|n| selection.pitches.contains(&n.pitch)).cloned().collect();
+                (!notes.is_empty()).then(|| Some(Event { time: relative(&e.time)?, notes, ..e.clone() }))?
+            })
+            .collect();
+        fragment.controls = self.controls.iter()
+            .filter(|c| selection.time.contains_time(&c.time))
+            .filter_map(|c| Some(ControlEvent { time: relative(&c.time)?, ..c.clone() }))
+            .collect();
+        fragment
+    }
+
+    /// Paste a fragment so that it starts at `time`, merging its notes into any events already
+    /// there. Nothing is pasted if any of its instruments are missing. Returns the number of
+    /// notes pasted.
+    pub fn paste_fragment(&mut self, fragment: &Fragment, time: &str) -> Result<usize> {
+        let at = time_to_32nds(time)?;
+        let moved = |time: &str| -> Result<String> { Ok(time_from_32nds(time_to_32nds(time)? + at)) };
+
+        let controls = fragment.controls.iter()
+            .map(|c| {
+                if !self.instruments.contains_key(&c.instrument) {
+                    bail!("Instrument '{}' not found", c.instrument);
+                }
+                c.controller.validate_value(c.value)?;
+                Ok(ControlEvent { time: moved(&c.time)?, ..c.clone() })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let count = self.batch(|editor| {
+            let mut count = 0;
+            for event in &fragment.events {
+                count += event.notes.len();
+                editor.add_event(Event { time: moved(&event.time)?, ..event.clone() })?;
+            }
+            Ok(count)
+        })?;
+        for control in controls {
+            self.add_control(control)?;
+        }
+        Ok(count)
+    }
+
+    /// Move every event, control, chord and lyric with `map`, dropping those it maps to `None`
+    fn shift_positions(&mut self, map: impl Fn(u32) -> Option<u32>) {
+        let remap = |time: &mut String| {
+            if let Some(position) = time_to_32nds(time).ok().and_then(&map) {
+                *time = time_from_32nds(position);
+            }
+        };
+        for mut event in std::mem::take(&mut self.events) {
+            remap(&mut event.time);
+            self.events.insert(event).expect("remapped times are valid");
+        }
+        for control in &mut self.controls {
+            remap(&mut control.time);
+        }
+        for chord in &mut self.chords {
+            remap(&mut chord.time);
+        }
+        for lyric in &mut self.lyrics {
+            remap(&mut lyric.time);
+        }
+        self.controls.sort_by_key(|c| time_to_32nds(&c.time).unwrap_or(0));
+        self.chords.sort_by_key(|c| time_to_32nds(&c.time).unwrap_or(0));
+        self.lyrics.sort_by_key(|l| time_to_32nds(&l.time).unwrap_or(0));
+        self.notify(ProjectChange::EventsChanged);
+    }
+
+    /// Expand a step-sequencer string (see [`StepPattern`]) into notes starting at `time`.
+    /// Each step lasts `step_length` 32nds and `x` steps play `hit_pitch`.
+    /// Returns the number of notes added.
+    pub fn add_steps(&mut self, time: &str, instrument: &str, pattern: &str, step_length: u32, hit_pitch: Pitch) -> Result<usize> {
+        if step_length == 0 {
+            bail!("Step length must be greater than zero");
+        }
+        let start = time_to_32nds(time)?;
+        let notes = StepPattern::parse(pattern)?.expand(step_length, hit_pitch);
+
+        self.batch(|editor| {
+            let count = notes.len();
+            for (offset, note) in notes {
+                editor.add_note(&time_from_32nds(start + offset), instrument, note)?;
+            }
+            Ok(count)
+        })
+    }
+
+    /// Add a multi-lane drum pattern (see [`DrumPattern`]) to a drum kit instrument,
+    /// starting at `time`. Each step lasts `step_length` 32nds. Returns the number of hits added.
+    pub fn add_drums(&mut self, time: &str, instrument: &str, pattern: &str, step_length: u32) -> Result<usize> {
+        if step_length == 0 {
+            bail!("Step length must be greater than zero");
+        }
+        let start = time_to_32nds(time)?;
+        let notes = DrumPattern::parse(pattern)?.expand(step_length);
+
+        self.batch(|editor| {
+            let count = notes.len();
+            for (offset, note) in notes {
+                editor.add_note(&time_from_32nds(start + offset), instrument, note)?;
+            }
+            Ok(count)
+        })
+    }
+
+    /// Import the first tune in ABC notation text (see [`AbcTune`]) onto `instrument`,
+    /// starting at `time`. Returns the number of notes added.
+    pub fn import_abc(&mut self, time: &str, instrument: &str, text: &str) -> Result<usize> {
+        let start = time_to_32nds(time)?;
+        let tune = AbcTune::parse(text)?;
+
+        self.batch(|editor| {
+            let count = tune.notes.len();
+            for (offset, note) in tune.notes {
+                editor.add_note(&time_from_32nds(start + offset), instrument, note)?;
+            }
+            Ok(count)
+        })
+    }
+
+    /// Add a controller change, keeping control events in chronological order
+    pub fn add_control(&mut self, control: ControlEvent) -> Result<()> {
+        if !self.instruments.contains_key(&control.instrument) {
+            bail!("Instrument '{}' not found", control.instrument);
+        }
+        let position = time_to_32nds(&control.time)?;
+        control.controller.validate_value(control.value)?;
+
+        let insert_pos = self.controls.partition_point(|c| {
+            time_to_32nds(&c.time).map(|t| t <= position).unwrap_or(true)
+        });
+        let change = ProjectChange::ControlAdded {
+            time: control.time.clone(),
+            instrument: control.instrument.clone(),
+            controller: control.controller,
+        };
+        self.controls.insert(insert_pos, control);
+        self.metadata.update_modification_date();
+        self.notify(change);
+        Ok(())
+    }
+
+    /// Put a chord on the chord track at `time`, replacing any chord already there
+    pub fn add_chord(&mut self, time: &str, symbol: &str) -> Result<()> {
+        time_to_32nds(time)?;
+        let chord = ChordSymbol::parse(symbol)?;
+        self.insert_chord(ChordEvent { time: time.to_string(), chord, extra: UnknownFields::new() });
+        self.metadata.update_modification_date();
+        self.notify(ProjectChange::ChordsChanged);
+        Ok(())
+    }
+
+    fn insert_chord(&mut self, chord: ChordEvent) {
+        self.chords.retain(|c| c.time != chord.time);
+        let position = time_to_32nds(&chord.time).unwrap_or(0);
+        let insert_pos = self.chords.partition_point(|c| {
+            time_to_32nds(&c.time).map(|t| t < position).unwrap_or(true)
+        });
+        self.chords.insert(insert_pos, chord);
+    }
+
+    /// Remove the chord at `time`
+    pub fn remove_chord(&mut self, time: &str) -> Result<()> {
+        let pos = self.chords.iter().position(|c| c.time == time)
+            .ok_or_else(|| anyhow::anyhow!("No chord at time '{}'", time))?;
+        self.chords.remove(pos);
+        self.metadata.update_modification_date();
+        self.notify(ProjectChange::ChordsChanged);
+        Ok(())
+    }
+
+    /// The chord in effect at `time`: the last one starting at or before it
+    pub fn chord_at(&self, time: &str) -> Result<Option<ChordSymbol>> {
+        let position = time_to_32nds(time)?;
+        Ok(self.chords.iter()
+            .filter(|c| time_to_32nds(&c.time).is_ok_and(|t| t <= position))
+            .max_by_key(|c| time_to_32nds(&c.time).unwrap_or(0))
+            .map(|c| c.chord))
+    }
+
+    /// Add a lyric or annotation, replacing any of the same kind at the same time and instrument
+    pub fn add_lyric(&mut self, lyric: LyricEvent) -> Result<()> {
+        time_to_32nds(&lyric.time)?;
+        if let Some(instrument) = &lyric.instrument {
+            if !self.instruments.contains_key(instrument) {
+                bail!("Instrument '{}' not found", instrument);
+            }
         }
-
+        self.insert_lyric(lyric);
         self.metadata.update_modification_date();
+        self.notify(ProjectChange::LyricsChanged);
         Ok(())
     }
 
-    /// Add a note to an existing event, or create a new event if none exists
-    pub fn add_note(&mut self, time: &str, instrument: &str, note: Note) -> Result<()> {
-        // Validate time format
-        self.validate_time_format(time)?;
+    fn insert_lyric(&mut self, lyric: LyricEvent) {
+        self.lyrics.retain(|l| (&l.time, &l.instrument, l.kind) != (&lyric.time, &lyric.instrument, lyric.kind));
+        let position = time_to_32nds(&lyric.time).unwrap_or(0);
+        let insert_pos = self.lyrics.partition_point(|l| {
+            time_to_32nds(&l.time).map(|t| t <= position).unwrap_or(true)
+        });
+        self.lyrics.insert(insert_pos, lyric);
+    }
 
-        // Validate instrument exists
+    /// Remove the lyrics and annotations at `time` on `instrument` (`None` for unattached ones)
+    pub fn remove_lyric(&mut self, time: &str, instrument: Option<&str>) -> Result<Vec<LyricEvent>> {
+        let (removed, kept) = std::mem::take(&mut self.lyrics)
+            .into_iter()
+            .partition(|l| l.time == time && l.instrument.as_deref() == instrument);
+        self.lyrics = kept;
+        if removed.is_empty() {
+            bail!("No lyric at time '{}'", time);
+        }
+        self.metadata.update_modification_date();
+        self.notify(ProjectChange::LyricsChanged);
+        Ok(removed)
+    }
+
+    /// Sing `text` on an instrument one syllable per note (see [`lyrics::syllables`]), starting
+    /// with the first event at or after `time`. Lyrics already on the instrument from `time`
+    /// on are replaced. Returns the number of syllables placed.
+    pub fn set_lyrics(&mut self, instrument: &str, time: &str, text: &str) -> Result<usize> {
+        let start = time_to_32nds(time)?;
         if !self.instruments.contains_key(instrument) {
             bail!("Instrument '{}' not found", instrument);
         }
-
-        // Find or create event
-        if let Some(event) = self.events.iter_mut().find(|e| e.time == time && e.instrument == instrument) {
-            // Add note to existing event
-            event.notes.push(note);
-            self.metadata.update_modification_date();
-            Ok(())
-        } else {
-            // Create new event
-            let event = Event {
-                time: time.to_string(),
-                instrument: instrument.to_string(),
-                notes: vec![note],
-            };
-            self.add_event(event)
+        let onsets: Vec<u32> = self.events.for_instrument(instrument)
+            .filter_map(|e| time_to_32nds(&e.time).ok())
+            .filter(|&t| t >= start)
+            .collect();
+
+        let syllables = lyrics::syllables(text);
+        if syllables.len() > onsets.len() {
+            bail!("Lyrics have {} syllables but only {} notes follow {}", syllables.len(), onsets.len(), time);
         }
+        self.lyrics.retain(|l| {
+            l.kind != TextKind::Lyric
+                || l.instrument.as_deref() != Some(instrument)
+                || time_to_32nds(&l.time).is_ok_and(|t| t < start)
+        });
+        for (syllable, onset) in syllables.iter().zip(onsets) {
+            self.insert_lyric(LyricEvent::new(&time_from_32nds(onset), syllable).with_instrument(instrument));
+        }
+        self.metadata.update_modification_date();
+        self.notify(ProjectChange::LyricsChanged);
+        Ok(syllables.len())
+    }
+
+    /// Lyrics and annotations starting in `range`, in time order
+    pub fn lyrics_in(&self, range: TimeRange) -> impl Iterator<Item = &LyricEvent> {
+        self.lyrics.iter().filter(move |l| range.contains_time(&l.time))
+    }
+
+    /// Interpolation curve for one controller on one instrument
+    pub fn control_curve(&self, instrument: &str, controller: Controller) -> ControlCurve {
+        ControlCurve::from_events(&self.controls, instrument, controller)
     }
 
     /// Remove a note from an event
@@ -270,8 +1408,7 @@ impl DawFile {
         self.validate_time_format(time)?;
 
         // Find the event
-        let event = self.events.iter_mut()
-            .find(|e| e.time == time && e.instrument == instrument)
+        let event = self.events.get_mut(time, instrument)
             .ok_or_else(|| anyhow::anyhow!("Event not found at time '{}' for instrument '{}'", time, instrument))?;
 
         // Find and remove the note
@@ -282,10 +1419,15 @@ impl DawFile {
 
         // If event has no more notes, remove it
         if event.notes.is_empty() {
-            self.remove_event(time, instrument)?;
+            self.events.remove(time, instrument);
         }
 
         self.metadata.update_modification_date();
+        self.notify(ProjectChange::NoteRemoved {
+            time: time.to_string(),
+            instrument: instrument.to_string(),
+            note: note.clone(),
+        });
         Ok(())
     }
 
@@ -295,43 +1437,81 @@ impl DawFile {
         self.validate_time_format(time)?;
 
         // Find the event
-        let event = self.events.iter_mut()
-            .find(|e| e.time == time && e.instrument == instrument)
+        let event = self.events.get_mut(time, instrument)
             .ok_or_else(|| anyhow::anyhow!("Event not found at time '{}' for instrument '{}'", time, instrument))?;
 
         // Find and update the note
         let pos = event.notes.iter().position(|n| n.pitch == old_note.pitch && n.duration == old_note.duration)
             .ok_or_else(|| anyhow::anyhow!("Note not found in event"))?;
         
-        event.notes[pos] = new_note;
+        event.notes[pos] = new_note.clone();
         self.metadata.update_modification_date();
+        self.notify(ProjectChange::NoteUpdated {
+            time: time.to_string(),
+            instrument: instrument.to_string(),
+            from: old_note.clone(),
+            to: new_note,
+        });
         Ok(())
     }
 
-    /// Get events within a time range
+    /// Get events from `start_time` up to and including `end_time`
     pub fn get_events_in_range(&self, start_time: &str, end_time: &str) -> Result<Vec<&Event>> {
-        // Validate time format
-        self.validate_time_format(start_time)?;
-        self.validate_time_format(end_time)?;
-
-        Ok(self.events.iter()
-            .filter(|e| e.time.as_str() >= start_time && e.time.as_str() <= end_time)
-            .collect())
+        let range = TimeRange::new(time_to_32nds(start_time)?, time_to_32nds(end_time)? + 1);
+        Ok(self.events.range(range).collect())
     }
 
     /// Get all events for an instrument
     pub fn get_events_by_instrument(&self, instrument_id: &str) -> Vec<&Event> {
-        self.events.iter()
-            .filter(|e| e.instrument == instrument_id)
-            .collect()
+        self.events.for_instrument(instrument_id).collect()
     }
 
     /// Get all events in a specific bar
     pub fn get_events_in_bar(&self, bar: u32) -> Result<Vec<&Event>> {
-        let prefix = format!("{}.", bar);
-        Ok(self.events.iter()
-            .filter(|e| e.time.starts_with(&prefix))
-            .collect())
+        if bar == 0 {
+            bail!("Bar number must be greater than 0");
+        }
+        Ok(self.events.range(TimeRange::bars(bar, bar)).collect())
+    }
+
+    /// Every note as `(position in 32nds, instrument, note)`, in time order
+    pub fn iter_notes(&self) -> impl DoubleEndedIterator<Item = (u32, &str, &Note)> {
+        self.events.notes()
+    }
+
+    /// One instrument's notes as `(position in 32nds, instrument, note)`, in time order
+    pub fn iter_notes_for(&self, instrument: &str) -> impl DoubleEndedIterator<Item = (u32, &str, &Note)> {
+        self.events.notes_for(instrument)
+    }
+
+    /// Notes in a selection or time range as `(position in 32nds, instrument, note)`, in time order
+    pub fn iter_notes_in_range(&self, selection: impl Into<Selection>) -> impl DoubleEndedIterator<Item = (u32, &str, &Note)> {
+        let selection = selection.into();
+        self.events.notes_in_range(selection.time)
+            .filter(move |(_, _, note)| selection.pitches.contains(&note.pitch))
+    }
+
+    /// Remove the notes in a selection, optionally only on one instrument, leaving later
+    /// notes where they are. Returns the number of notes removed.
+    pub fn delete_notes(&mut self, selection: Selection, instrument: Option<&str>) -> Result<usize> {
+        if let Some(instrument) = instrument {
+            if !self.instruments.contains_key(instrument) {
+                bail!("Instrument '{}' not found", instrument);
+            }
+        }
+        let doomed: Vec<(String, String, Note)> = self.iter_notes_in_range(selection)
+            .filter(|(_, i, _)| instrument.is_none_or(|instrument| *i == instrument))
+            .map(|(position, i, note)| (time_from_32nds(position), i.to_string(), note.clone()))
+            .collect();
+        if doomed.is_empty() {
+            return Ok(0);
+        }
+        self.batch(|editor| {
+            for (time, instrument, note) in &doomed {
+                editor.remove_note(time, instrument, note)?;
+            }
+            Ok(doomed.len())
+        })
     }
 
     /// Validate time format (bar.32nd)
@@ -357,22 +1537,39 @@ impl DawFile {
     }
 }
 
-/// Find the .daw.json file in the given directory
+fn validate_bar_range(range: &TimeRange) -> Result<()> {
+    if !range.start.is_multiple_of(32) || !range.end.is_multiple_of(32) || range.end <= range.start {
+        bail!("Range must cover one or more whole bars");
+    }
+    Ok(())
+}
+
+fn validate_strength(strength: f64) -> Result<()> {
+    if !(0.0..=1.0).contains(&strength) {
+        bail!("Quantize strength must be between 0 and 1, got {}", strength);
+    }
+    Ok(())
+}
+
+/// Find the .daw.json or .dawb file in the given directory
 pub fn find_daw_file(dir: &PathBuf) -> Result<PathBuf> {
     for entry in std::fs::read_dir(dir)? {
         let entry = entry?;
-        if entry.file_name().to_string_lossy().ends_with(".daw.json") {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.ends_with(format::JSON_EXTENSION) || name.ends_with(format::BINARY_EXTENSION) {
             return Ok(entry.path());
         }
     }
-    anyhow::bail!("No .daw.json file found in {}", dir.display());
+    anyhow::bail!("No .daw.json or .dawb file found in {}", dir.display());
 }
 
-/// Read and parse a DAW file from the given path
+/// Read and parse a DAW file from the given path, detecting JSON or binary from its contents,
+/// and check its samples against the files next to it (see `DawFile::sample_issues`)
 pub fn read_daw_file(path: &PathBuf) -> Result<DawFile> {
-    let content = std::fs::read_to_string(path)?;
-    let daw_data: DawFile = serde_json::from_str(&content)?;
-    Ok(daw_data)
+    let content = std::fs::read(path)?;
+    let mut daw_file = FileFormat::detect(&content).decode(&content)?;
+    daw_file.sample_issues = daw_file.check_samples(path.parent().unwrap_or(Path::new("")));
+    Ok(daw_file)
 }
 
 #[cfg(test)]
@@ -380,7 +1577,7 @@ mod tests {
     use super::*;
     use std::fs;
     use tempfile::TempDir;
-    use pitch::{Pitch, Tone};
+    use pitch::{Pitch, PitchRange, Tone};
 
     fn create_test_daw_file() -> DawFile {
         let mut daw = DawFile::new("Test Song".to_string());
@@ -401,6 +1598,7 @@ mod tests {
             time: "1.1".to_string(),
             instrument: "sampler1".to_string(),
             notes: vec![Note::new(Pitch::new(Tone::C, 4), 8)],
+            extra: Default::default(),
         };
         daw.add_event(event).unwrap();
 
@@ -417,11 +1615,98 @@ mod tests {
         assert_eq!(daw.mixdown.bit_depth, daw2.mixdown.bit_depth);
         assert_eq!(daw.instruments.len(), daw2.instruments.len());
         assert_eq!(daw.events.len(), daw2.events.len());
-        assert_eq!(daw.events[0].time, daw2.events[0].time);
-        assert_eq!(daw.events[0].instrument, daw2.events[0].instrument);
-        assert_eq!(daw.events[0].notes.len(), daw2.events[0].notes.len());
-        assert_eq!(daw.events[0].notes[0].pitch.tone, daw2.events[0].notes[0].pitch.tone);
-        assert_eq!(daw.events[0].notes[0].duration, daw2.events[0].notes[0].duration);
+        assert_eq!(daw.events.first().unwrap().time, daw2.events.first().unwrap().time);
+        assert_eq!(daw.events.first().unwrap().instrument, daw2.events.first().unwrap().instrument);
+        assert_eq!(daw.events.first().unwrap().notes.len(), daw2.events.first().unwrap().notes.len());
+        assert_eq!(daw.events.first().unwrap().notes[0].pitch.tone, daw2.events.first().unwrap().notes[0].pitch.tone);
+        assert_eq!(daw.events.first().unwrap().notes[0].duration, daw2.events.first().unwrap().notes[0].duration);
+    }
+
+    #[test]
+    fn test_canonical_serialization() {
+        let build = |names: &[&str]| {
+            let mut daw = DawFile::new("Canonical".to_string());
+            for name in names {
+                daw.create_sampler_instrument(name.to_string(), PathBuf::from("a.wav")).unwrap();
+            }
+            daw
+        };
+        let mut first = build(&["drums", "bass", "lead", "keys", "pad"]);
+        let mut second = build(&["pad", "keys", "lead", "bass", "drums"]);
+        for (time, instrument) in [("1.16", "lead"), ("1.4", "bass"), ("1.4", "drums")] {
+            first.add_note(time, instrument, Note::new(Pitch::new(Tone::C, 4), 4)).unwrap();
+        }
+        for (time, instrument) in [("1.4", "drums"), ("1.16", "lead"), ("1.4", "bass")] {
+            second.add_note(time, instrument, Note::new(Pitch::new(Tone::C, 4), 4)).unwrap();
+        }
+        second.metadata = first.metadata.clone();
+
+        let json = serde_json::to_string_pretty(&first).unwrap();
+        assert_eq!(json, serde_json::to_string_pretty(&second).unwrap());
+
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let names: Vec<&String> = value["instruments"].as_object().unwrap().keys().collect();
+        assert_eq!(names, vec!["bass", "drums", "keys", "lead", "pad"]);
+        let events: Vec<(&str, &str)> = value["events"].as_array().unwrap().iter()
+            .map(|e| (e["time"].as_str().unwrap(), e["instrument"].as_str().unwrap()))
+            .collect();
+        assert_eq!(events, vec![("1.4", "bass"), ("1.4", "drums"), ("1.16", "lead")]);
+    }
+
+    #[test]
+    fn test_unknown_fields_round_trip() {
+        let document = serde_json::json!({
+            "metadata": {
+                "title": "Future Song",
+                "creation_date": "2024-01-01T00:00:00+00:00",
+                "modification_date": "2024-01-01T00:00:00+00:00",
+                "revision": 3,
+                "edit_count": 42,
+                "composer": "Someone"
+            },
+            "bpm": 120,
+            "mixdown": { "sample_rate": 44100, "bit_depth": 16, "dither": true },
+            "instruments": {
+                "lead": {
+                    "type": "synth",
+                    "parameters": {
+                        "subtype": "subtractive",
+                        "oscillator_wave": "sawtooth",
+                        "filter_type": "lowpass",
+                        "filter_cutoff": 880.0,
+                        "filter_resonance": 0.3,
+                        "envelope_attack": 0.01,
+                        "envelope_decay": 0.2,
+                        "envelope_sustain": 0.7,
+                        "envelope_release": 0.1,
+                        "unison": 4
+                    },
+                    "effects": [
+                        { "type": "gain", "parameters": { "gain_db": -3.0, "smoothing": 0.1 }, "color": "red" }
+                    ],
+                    "color": "#ff0000"
+                }
+            },
+            "events": [
+                {
+                    "time": "1.0",
+                    "instrument": "lead",
+                    "notes": [{ "pitch": { "tone": "C", "octave": 4 }, "duration": 8, "probability": 0.5 }],
+                    "muted": false
+                }
+            ],
+            "mixer": { "master": { "gain_db": -1.0, "limiter": true }, "tracks": { "lead": { "pan": 0.25 } } },
+            "controls": [
+                { "time": "1.0", "instrument": "lead", "controller": "mod_wheel", "value": 0.5, "shape": 2 }
+            ],
+            "markers": [{ "time": "2.0", "name": "Chorus" }]
+        });
+
+        let daw: DawFile = serde_json::from_value(document.clone()).unwrap();
+        assert_eq!(daw.extra["markers"][0]["name"], "Chorus");
+        assert_eq!(daw.events.first().unwrap().notes[0].extra["probability"], 0.5);
+        assert!(!daw.mixer.is_default());
+        assert_eq!(serde_json::to_value(&daw).unwrap(), document);
     }
 
     #[test]
@@ -463,6 +1748,51 @@ mod tests {
         assert_eq!(read_daw.events.len(), original_daw.events.len());
     }
 
+    #[test]
+    fn test_save_and_read_binary_daw_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("song.dawb");
+
+        let mut daw = create_test_daw_file();
+        daw.add_note("2.0", "sampler1", Note::new(Pitch::new(Tone::E, 4), 8)).unwrap();
+        daw.save(&file_path).unwrap();
+        assert!(!fs::read(&file_path).unwrap().starts_with(b"{"));
+
+        assert_eq!(find_daw_file(&temp_dir.path().to_path_buf()).unwrap(), file_path);
+        let read_daw = read_daw_file(&file_path).unwrap();
+        assert_eq!(read_daw.metadata.revision, 1);
+        assert_eq!(read_daw.events, daw.events);
+    }
+
+    #[test]
+    fn test_read_daw_file_rejects_no_voices() {
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("test.daw.json");
+        let mut json = serde_json::to_value(create_test_daw_file()).unwrap();
+        json["instruments"]["sampler1"]["polyphony"] = serde_json::json!({ "max_voices": 0 });
+        fs::write(&file_path, json.to_string()).unwrap();
+
+        let error = read_daw_file(&file_path).unwrap_err();
+        assert!(format!("{:#}", error).contains("max_voices must be at least 1"));
+    }
+
+    #[test]
+    fn test_read_daw_file_rejects_bad_grooves() {
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("test.daw.json");
+        let json = serde_json::to_value(create_test_daw_file()).unwrap();
+        let swung = serde_json::json!({ "grid": "eighth", "swing": 90.0 });
+        for path in ["/groove", "/instruments/sampler1/groove"] {
+            let mut json = json.clone();
+            let (parent, key) = path.rsplit_once('/').unwrap();
+            json.pointer_mut(parent).unwrap()[key] = swung.clone();
+            fs::write(&file_path, json.to_string()).unwrap();
+
+            let error = read_daw_file(&file_path).unwrap_err();
+            assert!(format!("{:#}", error).contains("Swing must be between 50 and 75"), "{:#}", error);
+        }
+    }
+
     #[test]
     fn test_read_daw_file_invalid_json() {
         let temp_dir = TempDir::new().unwrap();
@@ -476,6 +1806,16 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_read_sample_song() {
+        let path = PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/../../sample_song/song.daw.json"));
+        let daw = read_daw_file(&path).unwrap();
+        let synth = daw.get_instrument("synth1").unwrap();
+        assert_eq!(synth.instrument_type(), "synth");
+        assert_eq!(synth.subtype(), Some("subtractive"));
+        assert!(!daw.events.is_empty());
+    }
+
     #[test]
     fn test_new_daw_file() {
         let title = "New Song".to_string();
@@ -530,6 +1870,125 @@ mod tests {
         assert_eq!(read_daw.metadata.revision, 3);
     }
 
+    #[test]
+    fn test_session_saved_with_song() {
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("session.daw.json");
+        let mut daw = DawFile::new("Session".to_string());
+        let modified = daw.metadata.modification_date.clone();
+
+        let session = Session {
+            loop_start: Some("1.0".to_string()),
+            loop_end: Some("3.0".to_string()),
+            loop_enabled: true,
+            playhead: Some("2.4".to_string()),
+            snap: Some(Grid::Eighth),
+            ..Default::default()
+        };
+        daw.set_session(session.clone()).unwrap();
+        assert_eq!(daw.metadata.modification_date, modified);
+        assert!(daw.set_session(Session { playhead: Some("bad".to_string()), ..Default::default() }).is_err());
+
+        daw.save(&file_path).unwrap();
+        assert_eq!(read_daw_file(&file_path).unwrap().session, session);
+    }
+
+    #[test]
+    fn test_diatonic_transpose() {
+        let mut daw = DawFile::new("Scales".to_string());
+        daw.create_sampler_instrument("keys".to_string(), PathBuf::from("keys.wav")).unwrap();
+        daw.add_note("1.0", "keys", Note::new(Pitch::new(Tone::C, 4), 8)).unwrap();
+        daw.add_note("1.0", "keys", Note::new(Pitch::new(Tone::E, 4), 8)).unwrap();
+        daw.add_note("2.0", "keys", Note::new(Pitch::new(Tone::B, 4), 8)).unwrap();
+        assert!(daw.diatonic_transpose(1, None, None).is_err());
+
+        daw.set_key(Some(Key::parse("C major").unwrap()));
+        assert_eq!(daw.diatonic_transpose(2, None, Some("keys")).unwrap(), 3);
+        let pitches: Vec<Pitch> = daw.events.iter().flat_map(|e| e.notes.iter().map(|n| n.pitch)).collect();
+        assert_eq!(pitches, vec![Pitch::new(Tone::E, 4), Pitch::new(Tone::G, 4), Pitch::new(Tone::D, 5)]);
+
+        let json = serde_json::to_value(&daw).unwrap();
+        assert_eq!(json["metadata"]["key"], serde_json::json!({ "root": "C", "scale": "major" }));
+    }
+
+    #[test]
+    fn test_chord_track() {
+        let mut daw = DawFile::new("Changes".to_string());
+        daw.add_chord("3.0", "G7").unwrap();
+        daw.add_chord("1.0", "Am7").unwrap();
+        daw.add_chord("2.0", "Dm").unwrap();
+        daw.add_chord("2.0", "F/C").unwrap();
+        assert!(daw.add_chord("4.0", "Q").is_err());
+        assert!(daw.add_chord("0.0", "C").is_err());
+
+        let chords: Vec<String> = daw.chords.iter().map(|c| format!("{} {}", c.time, c.chord)).collect();
+        assert_eq!(chords, vec!["1.0 Am7", "2.0 F/C", "3.0 G7"]);
+        assert_eq!(daw.chord_at("2.31").unwrap().unwrap().to_string(), "F/C");
+        assert_eq!(daw.chord_at("9.0").unwrap().unwrap().to_string(), "G7");
+
+        daw.insert_bars(2, 1).unwrap();
+        assert_eq!(daw.chord_at("2.0").unwrap().unwrap().to_string(), "Am7");
+        daw.remove_chord("1.0").unwrap();
+        assert_eq!(daw.chord_at("1.0").unwrap(), None);
+
+        let json = serde_json::to_value(&daw).unwrap();
+        assert_eq!(json["chords"][0], serde_json::json!({ "time": "3.0", "chord": "F/C" }));
+    }
+
+    #[test]
+    fn test_lyrics() {
+        let mut daw = DawFile::new("Song".to_string());
+        daw.create_sampler_instrument("vox".to_string(), PathBuf::from("vox.wav")).unwrap();
+        for time in ["1.0", "1.8", "1.16", "2.0"] {
+            daw.add_note(time, "vox", Note::new(Pitch::new(Tone::A, 4), 8)).unwrap();
+        }
+        daw.add_lyric(LyricEvent::annotation("1.0", "verse")).unwrap();
+        assert!(daw.add_lyric(LyricEvent::new("1.0", "la").with_instrument("drums")).is_err());
+
+        assert_eq!(daw.set_lyrics("vox", "1.8", "hel-lo world").unwrap(), 3);
+        assert!(daw.set_lyrics("vox", "1.16", "too ma-ny words").is_err());
+        let sung: Vec<(&str, &str)> = daw.lyrics.iter()
+            .filter(|l| l.kind == TextKind::Lyric)
+            .map(|l| (l.time.as_str(), l.text.as_str()))
+            .collect();
+        assert_eq!(sung, vec![("1.8", "hel-"), ("1.16", "lo"), ("2.0", "world")]);
+        assert_eq!(daw.lyrics_in(TimeRange::bars(2, 2)).count(), 1);
+
+        daw.rename_instrument("vox", "lead".to_string()).unwrap();
+        daw.insert_bars(1, 1).unwrap();
+        assert_eq!(daw.lyrics[0].time, "2.0");
+        assert_eq!(daw.lyrics[1].instrument.as_deref(), Some("lead"));
+        assert_eq!(daw.remove_lyric("2.0", None).unwrap()[0].text, "verse");
+        assert!(daw.remove_lyric("2.0", None).is_err());
+
+        let json = serde_json::to_value(&daw).unwrap();
+        assert_eq!(json["lyrics"][0], serde_json::json!({ "time": "2.8", "text": "hel-", "instrument": "lead" }));
+    }
+
+    #[test]
+    fn test_change_notifications() {
+        let mut daw = DawFile::new("Song".to_string());
+        daw.create_sampler_instrument("bass".to_string(), PathBuf::from("bass.wav")).unwrap();
+        let changes = daw.subscribe();
+        let note = Note::new(Pitch::new(Tone::C, 3), 8);
+
+        daw.add_note("1.0", "bass", note.clone()).unwrap();
+        daw.transpose(2, None, None).unwrap();
+        daw.rename_instrument("bass", "sub".to_string()).unwrap();
+        let transposed = Note::new(Pitch::new(Tone::D, 3), 8);
+        daw.remove_note("1.0", "sub", &transposed).unwrap();
+        assert!(daw.remove_event("1.0", "sub").is_err());
+
+        let received: Vec<ProjectChange> = changes.try_iter().collect();
+        assert_eq!(received, vec![
+            ProjectChange::NoteAdded { time: "1.0".to_string(), instrument: "bass".to_string(), note },
+            ProjectChange::EventsChanged,
+            ProjectChange::InstrumentRenamed { from: "bass".to_string(), to: "sub".to_string() },
+            ProjectChange::NoteRemoved { time: "1.0".to_string(), instrument: "sub".to_string(), note: transposed },
+        ]);
+        assert!(daw.clone().subscribe().try_recv().is_err());
+    }
+
     #[test]
     fn test_metadata_management() {
         let mut daw_file = DawFile::new("Original Title".to_string());
@@ -567,10 +2026,7 @@ mod tests {
         assert!(daw_file.add_instrument("sampler1".to_string(), sampler.clone()).is_err());
 
         // Test adding an invalid instrument
-        let invalid_sampler = Instrument {
-            instrument_type: "sampler".to_string(),
-            parameters: serde_json::json!({}),
-        };
+        let invalid_sampler = Instrument::new_sampler(PathBuf::new());
         assert!(daw_file.add_instrument("sampler2".to_string(), invalid_sampler).is_err());
     }
 
@@ -586,11 +2042,12 @@ mod tests {
         assert!(daw_file.remove_instrument("nonexistent").is_err());
 
         // Add an event using the instrument
-        daw_file.events.push(Event {
+        daw_file.events.insert(Event {
             time: "1.1".to_string(),
             instrument: "sampler1".to_string(),
             notes: vec![Note::new(Pitch::new(Tone::C, 4), 8)],
-        });
+            extra: Default::default(),
+        }).unwrap();
 
         // Test removing an instrument that is in use
         assert!(daw_file.remove_instrument("sampler1").is_err());
@@ -601,6 +2058,17 @@ mod tests {
         assert!(!daw_file.instruments.contains_key("sampler1"));
     }
 
+    #[test]
+    fn test_add_audio_clip() {
+        let mut daw_file = DawFile::new("Test Song".to_string());
+        daw_file.add_audio_clip("audio1", PathBuf::from("recordings/audio1.wav"), 2.5).unwrap();
+        let instrument = daw_file.get_instrument("audio1").unwrap();
+        assert_eq!(instrument.frozen, Some(Freeze::new(PathBuf::from("recordings/audio1.wav")).starting_at(2.5)));
+        assert_eq!(daw_file.iter_notes_for("audio1").count(), 0);
+
+        assert!(daw_file.add_audio_clip("audio1", PathBuf::from("again.wav"), 0.0).is_err());
+    }
+
     #[test]
     fn test_rename_instrument() {
         let mut daw_file = DawFile::new("Test Song".to_string());
@@ -608,19 +2076,24 @@ mod tests {
 
         // Add an instrument and an event using it
         daw_file.add_instrument("sampler1".to_string(), sampler).unwrap();
-        daw_file.events.push(Event {
+        daw_file.events.insert(Event {
             time: "1.1".to_string(),
             instrument: "sampler1".to_string(),
             notes: vec![Note::new(Pitch::new(Tone::C, 4), 8)],
-        });
+            extra: Default::default(),
+        }).unwrap();
+        daw_file.mixer.add_bus("reverb", mixer::Bus::default()).unwrap();
+        daw_file.mixer.set_send("sampler1", "reverb", -12.0).unwrap();
 
         // Test renaming to a new ID
         assert!(daw_file.rename_instrument("sampler1", "new_sampler".to_string()).is_ok());
         assert!(!daw_file.instruments.contains_key("sampler1"));
         assert!(daw_file.instruments.contains_key("new_sampler"));
 
-        // Verify event was updated
-        assert_eq!(daw_file.events[0].instrument, "new_sampler");
+        // Verify event and mixer routing were updated
+        assert_eq!(daw_file.events.first().unwrap().instrument, "new_sampler");
+        assert_eq!(daw_file.mixer.track("new_sampler").sends["reverb"], -12.0);
+        assert!(!daw_file.mixer.tracks.contains_key("sampler1"));
 
         // Test renaming non-existent instrument
         assert!(daw_file.rename_instrument("nonexistent", "other".to_string()).is_err());
@@ -641,16 +2114,16 @@ mod tests {
 
         // Test get_instrument
         let instrument = daw_file.get_instrument("sampler1").unwrap();
-        assert_eq!(instrument.instrument_type, "sampler");
+        assert_eq!(instrument.instrument_type(), "sampler");
         
         // Test get_instrument_mut
         let instrument_mut = daw_file.get_instrument_mut("sampler1").unwrap();
-        instrument_mut.parameters = serde_json::json!({ "sample_file": "new_kick.wav" });
+        instrument_mut.kind = InstrumentKind::Sampler(instrument::SamplerParams::new(PathBuf::from("new_kick.wav")));
 
         // Verify the change
         assert_eq!(
-            daw_file.get_instrument("sampler1").unwrap().parameters,
-            serde_json::json!({ "sample_file": "new_kick.wav" })
+            daw_file.get_instrument("sampler1").unwrap().sample_file(),
+            Some("new_kick.wav")
         );
 
         // Test non-existent instrument
@@ -692,9 +2165,8 @@ mod tests {
 
         // Verify the instrument was created correctly
         let sampler = daw.get_instrument("sampler1").unwrap();
-        assert_eq!(sampler.instrument_type, "sampler");
-        let params = sampler.parameters.as_object().unwrap();
-        assert_eq!(params["sample_file"], sample_path.to_string_lossy().to_string());
+        assert_eq!(sampler.instrument_type(), "sampler");
+        assert_eq!(sampler.sample_file(), Some(sample_path.to_string_lossy().as_ref()));
     }
 
     #[test]
@@ -710,6 +2182,7 @@ mod tests {
             time: "1.0".to_string(),
             instrument: "test_instrument".to_string(),
             notes: vec![Note::new(Pitch::new(Tone::C, 4), 8)],
+            extra: Default::default(),
         };
         daw.add_event(event1.clone()).unwrap();
         println!("After adding event1: {:?}", daw.events);
@@ -719,26 +2192,29 @@ mod tests {
         let note2 = Note::new(Pitch::new(Tone::E, 4), 8);
         daw.add_note("1.0", "test_instrument", note2.clone()).unwrap();
         println!("After adding note2: {:?}", daw.events);
-        assert_eq!(daw.events[0].notes.len(), 2);
+        assert_eq!(daw.events.first().unwrap().notes.len(), 2);
+        assert!(daw.add_note("1.0", "test_instrument", note2.clone().with_pan(1.5)).is_err());
+        assert!(daw.add_note("1.0", "test_instrument", note2.clone().with_pan(f64::NAN)).is_err());
 
         // Test removing note
         daw.remove_note("1.0", "test_instrument", &note2).unwrap();
         println!("After removing note2: {:?}", daw.events);
-        assert_eq!(daw.events[0].notes.len(), 1);
+        assert_eq!(daw.events.first().unwrap().notes.len(), 1);
 
         // Test updating note
-        let old_note = daw.events[0].notes[0].clone();
+        let old_note = daw.events.first().unwrap().notes[0].clone();
         let new_note = Note::new(Pitch::new(Tone::G, 4), 16);
         daw.update_note("1.0", "test_instrument", &old_note, new_note.clone()).unwrap();
         println!("After updating note: {:?}", daw.events);
-        assert_eq!(daw.events[0].notes[0].pitch.tone, Tone::G);
-        assert_eq!(daw.events[0].notes[0].duration, 16);
+        assert_eq!(daw.events.first().unwrap().notes[0].pitch.tone, Tone::G);
+        assert_eq!(daw.events.first().unwrap().notes[0].duration, 16);
 
         // Test getting events by range
         let event2 = Event {
             time: "2.0".to_string(),
             instrument: "test_instrument".to_string(),
             notes: vec![Note::new(Pitch::new(Tone::D, 4), 8)],
+            extra: Default::default(),
         };
         daw.add_event(event2).unwrap();
         println!("After adding event2: {:?}", daw.events);
@@ -759,6 +2235,548 @@ mod tests {
         assert_eq!(daw.events.len(), 1);
     }
 
+    #[test]
+    fn test_sample_registry_sync_and_relink() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::create_dir(temp_dir.path().join("audio")).unwrap();
+        fs::write(temp_dir.path().join("audio/kick.wav"), b"kick").unwrap();
+
+        let mut daw = DawFile::new("Test".to_string());
+        daw.create_sampler_instrument("kick".to_string(), PathBuf::from("audio/kick.wav")).unwrap();
+        daw.sync_samples(temp_dir.path()).unwrap();
+        assert!(daw.samples.get("audio/kick.wav").is_some());
+        assert!(daw.check_samples(temp_dir.path()).is_empty());
+
+        // Move the sample; the registry should find it by hash
+        fs::create_dir(temp_dir.path().join("drums")).unwrap();
+        fs::rename(temp_dir.path().join("audio/kick.wav"), temp_dir.path().join("drums/kick.wav")).unwrap();
+        let issues = daw.check_samples(temp_dir.path());
+        assert_eq!(issues, vec![SampleIssue::Moved {
+            path: "audio/kick.wav".to_string(),
+            found_at: temp_dir.path().join("drums/kick.wav"),
+        }]);
+
+        daw.relink_sample(temp_dir.path(), "audio/kick.wav", "drums/kick.wav").unwrap();
+        assert_eq!(daw.get_instrument("kick").unwrap().sample_file(), Some("drums/kick.wav"));
+        assert!(daw.samples.get("audio/kick.wav").is_none());
+        assert!(daw.check_samples(temp_dir.path()).is_empty());
+        assert!(daw.relink_sample(temp_dir.path(), "audio/kick.wav", "drums/kick.wav").is_err());
+    }
+
+    #[test]
+    fn test_save_registers_samples() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("snare.wav"), b"snare").unwrap();
+        let file_path = temp_dir.path().join("test.daw.json");
+
+        let mut daw = DawFile::new("Test".to_string());
+        daw.create_sampler_instrument("snare".to_string(), PathBuf::from("snare.wav")).unwrap();
+        daw.create_sampler_instrument("missing".to_string(), PathBuf::from("missing.wav")).unwrap();
+        daw.save(&file_path).unwrap();
+
+        let read_daw = read_daw_file(&file_path).unwrap();
+        assert_eq!(read_daw.samples.paths(), vec!["snare.wav"]);
+        assert!(read_daw.sample_issues().is_empty());
+
+        // Missing samples are reported when the file is read
+        fs::remove_file(temp_dir.path().join("snare.wav")).unwrap();
+        let read_daw = read_daw_file(&file_path).unwrap();
+        assert_eq!(read_daw.sample_issues(), [SampleIssue::Missing { path: "snare.wav".to_string() }]);
+    }
+
+    #[test]
+    fn test_preset_export_import() {
+        let temp_dir = TempDir::new().unwrap();
+        let preset_path = temp_dir.path().join("pad.preset.json");
+
+        let daw = create_test_daw_file();
+        daw.export_preset("sampler1", "Pad".to_string(), &preset_path).unwrap();
+        assert!(daw.export_preset("missing", "Pad".to_string(), &preset_path).is_err());
+
+        let mut other = DawFile::new("Other".to_string());
+        other.import_preset(&preset_path, "pad".to_string()).unwrap();
+        assert_eq!(other.get_instrument("pad"), daw.get_instrument("sampler1"));
+        assert!(other.import_preset(&preset_path, "pad".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_add_control() {
+        use controllers::Curve;
+
+        let mut daw = create_test_daw_file();
+        let bend = |time: &str, value: f64| ControlEvent {
+            time: time.to_string(),
+            instrument: "sampler1".to_string(),
+            controller: Controller::PitchBend,
+            value,
+            curve: Curve::Linear,
+            extra: Default::default(),
+        };
+        daw.add_control(bend("2.0", 2.0)).unwrap();
+        daw.add_control(bend("1.16", 0.0)).unwrap();
+        assert_eq!(daw.controls[0].time, "1.16");
+
+        assert!(daw.add_control(bend("1.0", 99.0)).is_err());
+        assert!(daw.add_control(ControlEvent { instrument: "missing".to_string(), ..bend("1.0", 0.0) }).is_err());
+
+        let curve = daw.control_curve("sampler1", Controller::PitchBend);
+        assert_eq!(curve.value_at(24.0), 1.0);
+    }
+
+    #[test]
+    fn test_batch_edits() {
+        let mut daw = create_test_daw_file();
+        let before = daw.events.len();
+        let c4 = Note::new(Pitch::new(Tone::C, 4), 8);
+
+        let added = daw.batch(|editor| {
+            for bar in (1..=4).rev() {
+                editor.add_note(&format!("{}.0", bar), "sampler1", c4.clone())?;
+                editor.add_note(&format!("{}.0", bar), "sampler1", Note::new(Pitch::new(Tone::E, 4), 8))?;
+            }
+            editor.remove_note("4.0", "sampler1", &c4)?;
+            Ok(editor.events().len() - before)
+        }).unwrap();
+        assert_eq!(added, 4);
+        assert_eq!(daw.events.len(), before + 4);
+        assert_eq!(daw.events.get("4.0", "sampler1").unwrap().notes.len(), 1);
+
+        // A failing edit leaves the file untouched
+        let snapshot = serde_json::to_value(&daw).unwrap();
+        let result = daw.batch(|editor| {
+            editor.add_note("5.0", "sampler1", c4.clone())?;
+            editor.add_note("5.0", "missing", c4.clone())
+        });
+        assert!(result.is_err());
+        assert_eq!(serde_json::to_value(&daw).unwrap(), snapshot);
+    }
+
+    #[test]
+    fn test_transpose() {
+        let mut daw = create_test_daw_file();
+        daw.add_instrument("synth1".to_string(), Instrument::new_subtractive(instrument::SubtractiveParams::default())).unwrap();
+        daw.add_note("3.0", "sampler1", Note::new(Pitch::new(Tone::A, 4), 8)).unwrap();
+        daw.add_note("3.0", "synth1", Note::new(Pitch::new(Tone::B, 8), 8)).unwrap();
+        let pitch_at = |daw: &DawFile, instrument: &str| {
+            daw.events.iter().find(|e| e.time == "3.0" && e.instrument == instrument).unwrap().notes[0].pitch
+        };
+
+        // Only bar 3 of sampler1
+        assert_eq!(daw.transpose(3, Some(TimeRange::bars(3, 3).into()), Some("sampler1")).unwrap(), 1);
+        assert_eq!(pitch_at(&daw, "sampler1"), Pitch::new(Tone::C, 5));
+        assert_eq!(pitch_at(&daw, "synth1"), Pitch::new(Tone::B, 8));
+
+        // Past the top of the range drops back by an octave
+        daw.transpose(2, Some(TimeRange::bars(3, 3).into()), Some("synth1")).unwrap();
+        assert_eq!(pitch_at(&daw, "synth1"), Pitch::new(Tone::Cs, 8));
+
+        assert!(daw.transpose(1, None, Some("missing")).is_err());
+        assert_eq!(Pitch::new(Tone::C, 0).transpose(-1), None);
+        assert_eq!(Pitch::new(Tone::C, 0).transpose_clamped(-1), Pitch::new(Tone::B, 0));
+    }
+
+    #[test]
+    fn test_quantize() {
+        let mut daw = create_test_daw_file();
+        daw.events.clear();
+        daw.add_note("1.3", "sampler1", Note::new(Pitch::new(Tone::C, 4), 3)).unwrap();
+        daw.add_note("1.5", "sampler1", Note::new(Pitch::new(Tone::E, 4), 5)).unwrap();
+        daw.add_note("1.6", "sampler1", Note::new(Pitch::new(Tone::G, 4), 8)).unwrap();
+        daw.add_note("2.7", "sampler1", Note::new(Pitch::new(Tone::B, 4), 8)).unwrap();
+
+        // Bar 1 only; 1.3 and 1.5 both land on 1.4 and merge
+        assert_eq!(daw.quantize(Grid::Eighth, 1.0, Some(TimeRange::bars(1, 1).into())).unwrap(), 3);
+        let times: Vec<&str> = daw.events.iter().map(|e| e.time.as_str()).collect();
+        assert_eq!(times, vec!["1.4", "1.8", "2.7"]);
+        assert_eq!(daw.events.first().unwrap().notes.len(), 2);
+
+        // Half strength moves 2.7 (39) halfway to 2.8 (40), rounding up
+        assert_eq!(daw.quantize(Grid::Quarter, 0.5, Some(TimeRange::bars(2, 2).into())).unwrap(), 1);
+        assert_eq!(daw.events.iter().nth(2).unwrap().time, "2.8");
+
+        assert_eq!(daw.quantize_durations(Grid::Eighth, 1.0, None).unwrap(), 2);
+        assert_eq!(daw.events.first().unwrap().notes[0].duration, 4);
+        assert_eq!(daw.events.first().unwrap().notes[1].duration, 4);
+
+        assert!(daw.quantize(Grid::Eighth, 1.5, None).is_err());
+    }
+
+    #[test]
+    fn test_selection_edits() {
+        let mut daw = create_test_daw_file();
+        daw.events.clear();
+        for tone in [Tone::C, Tone::E, Tone::G] {
+            daw.add_note("1.3", "sampler1", Note::new(Pitch::new(tone, 4), 8)).unwrap();
+        }
+        daw.add_note("2.0", "sampler1", Note::new(Pitch::new(Tone::E, 4), 8)).unwrap();
+        let low = Selection::new(TimeRange::bars(1, 1), PitchRange::new(Pitch::new(Tone::C, 4), Pitch::new(Tone::E, 4)));
+
+        assert_eq!(daw.iter_notes_in_range(low).count(), 2);
+        assert_eq!(daw.iter_notes_in_range(TimeRange::bars(1, 2)).count(), 4);
+
+        // Only the selected notes move, splitting the chord
+        assert_eq!(daw.quantize(Grid::Eighth, 1.0, Some(low)).unwrap(), 1);
+        assert_eq!(daw.events.get("1.4", "sampler1").unwrap().notes.len(), 2);
+        assert_eq!(daw.events.get("1.3", "sampler1").unwrap().notes[0].pitch, Pitch::new(Tone::G, 4));
+
+        assert_eq!(daw.transpose(12, Some(low), None).unwrap(), 2);
+        assert_eq!(daw.iter_notes_in_range(low).count(), 0);
+
+        let high = Selection::new(TimeRange::bars(1, 2), PitchRange::new(Pitch::new(Tone::C, 5), Pitch::new(Tone::E, 5)));
+        assert_eq!(daw.delete_notes(high, Some("sampler1")).unwrap(), 2);
+        assert_eq!(daw.iter_notes().count(), 2);
+        assert!(daw.delete_notes(high, Some("missing")).is_err());
+    }
+
+    #[test]
+    fn test_copy_and_paste_fragment() {
+        let mut daw = create_test_daw_file();
+        daw.events.clear();
+        daw.add_note("2.4", "sampler1", Note::new(Pitch::new(Tone::C, 4), 8)).unwrap();
+        daw.add_note("2.4", "sampler1", Note::new(Pitch::new(Tone::C, 6), 8)).unwrap();
+        daw.add_note("3.0", "sampler1", Note::new(Pitch::new(Tone::D, 4), 8)).unwrap();
+        daw.add_control(ControlEvent {
+            time: "2.8".to_string(),
+            instrument: "sampler1".to_string(),
+            controller: Controller::ModWheel,
+            value: 0.5,
+            curve: controllers::Curve::Step,
+            extra: Default::default(),
+        }).unwrap();
+
+        let low = PitchRange::new(Pitch::new(Tone::C, 3), Pitch::new(Tone::B, 4));
+        let fragment = daw.copy_fragment(Selection::new(TimeRange::bars(2, 2), low));
+        assert_eq!(fragment.length, 32);
+        assert_eq!(fragment.events.len(), 1);
+        assert_eq!(fragment.events[0].time, "1.4");
+        assert_eq!(fragment.events[0].notes.len(), 1);
+        assert_eq!(fragment.controls[0].time, "1.8");
+
+        // Through the clipboard and back
+        let fragment = Fragment::from_json(&fragment.to_json().unwrap()).unwrap();
+        assert_eq!(daw.paste_fragment(&fragment, "3.0").unwrap(), 1);
+        assert_eq!(daw.events.get("3.4", "sampler1").unwrap().notes[0].pitch, Pitch::new(Tone::C, 4));
+        assert_eq!(daw.controls.len(), 2);
+
+        let mut elsewhere = fragment.clone();
+        elsewhere.events[0].instrument = "missing".to_string();
+        assert!(daw.paste_fragment(&elsewhere, "5.0").is_err());
+        assert!(daw.events.get("5.4", "sampler1").is_none());
+    }
+
+    #[test]
+    fn test_quantize_to_groove() {
+        let mut daw = create_test_daw_file();
+        daw.events.clear();
+        daw.create_sampler_instrument("hats".to_string(), PathBuf::from("hat.wav")).unwrap();
+        daw.add_note("1.5", "sampler1", Note::new(Pitch::new(Tone::C, 4), 2)).unwrap();
+        daw.add_note("1.5", "hats", Note::new(Pitch::new(Tone::F, 2), 2)).unwrap();
+
+        assert!(daw.set_groove(Groove::default().with_swing(90.0)).is_err());
+        daw.set_groove(Groove::new(Grid::Eighth).with_swing(75.0)).unwrap();
+        daw.set_instrument_groove("hats", Some(Groove::new(Grid::Eighth))).unwrap();
+        assert_eq!(daw.groove_for("sampler1").swing, 75.0);
+        assert_eq!(daw.groove_for("hats").swing, 50.0);
+
+        // The swung off-beat is at 6; the hats' own groove is straight
+        assert_eq!(daw.quantize_to_groove(1.0, None).unwrap(), 2);
+        let moved: Vec<(&str, &str)> = daw.events.iter().map(|e| (e.time.as_str(), e.instrument.as_str())).collect();
+        assert_eq!(moved, vec![("1.4", "hats"), ("1.6", "sampler1")]);
+
+        let json = serde_json::to_value(&daw).unwrap();
+        assert_eq!(json["groove"], serde_json::json!({ "grid": "eighth", "swing": 75.0 }));
+        assert_eq!(json["instruments"]["hats"]["groove"], serde_json::json!({ "grid": "eighth" }));
+    }
+
+    #[test]
+    fn test_humanize() {
+        let build = || {
+            let mut daw = create_test_daw_file();
+            daw.events.clear();
+            for beat in 0..8 {
+                let time = time_from_32nds(32 + beat * 8);
+                daw.add_note(&time, "sampler1", Note::new(Pitch::new(Tone::C, 4), 4)).unwrap();
+            }
+            daw
+        };
+
+        let mut a = build();
+        let mut b = build();
+        let changed = a.humanize(None, 1, 8, Some(42)).unwrap();
+        assert!(changed > 0);
+        assert_eq!(b.humanize(None, 1, 8, Some(42)).unwrap(), changed);
+        assert_eq!(a.events, b.events);
+
+        // Without a seed of its own, humanizing follows the song's seed
+        let mut d = build();
+        d.set_seed(42);
+        d.humanize(None, 1, 8, None).unwrap();
+        assert_eq!(d.events, a.events);
+        assert_eq!(serde_json::to_value(&d).unwrap()["seed"], 42);
+        assert!(serde_json::to_value(build()).unwrap().get("seed").is_none());
+
+        assert_eq!(a.events.len(), 8);
+        for event in &a.events {
+            // Beats are 8 apart, so each note is within 1 of the nearest one
+            let position = time_to_32nds(&event.time).unwrap();
+            assert!(matches!(position % 8, 0 | 1 | 7));
+            assert!(event.notes[0].velocity.abs_diff(DEFAULT_VELOCITY) <= 8);
+        }
+
+        // Events outside the range stay put
+        let mut c = build();
+        c.humanize(Some(TimeRange::bars(2, 2)), 2, 0, Some(1)).unwrap();
+        let bar_3 = |daw: &DawFile| daw.events.iter().filter(|e| e.time.starts_with("3.")).cloned().collect::<Vec<_>>();
+        assert_eq!(bar_3(&c), bar_3(&build()));
+    }
+
+    #[test]
+    fn test_arpeggiate() {
+        let mut daw = create_test_daw_file();
+        daw.events.clear();
+        for tone in [Tone::C, Tone::E, Tone::G] {
+            daw.add_note("2.0", "sampler1", Note::new(Pitch::new(tone, 4), 8)).unwrap();
+        }
+        let arpeggio = Arpeggio::new(ArpDirection::Up, Grid::Sixteenth);
+
+        // Played as written until the instrument has an arpeggio
+        assert!(matches!(daw.played_events(), Cow::Borrowed(_)));
+        daw.set_instrument_arpeggio("sampler1", Some(arpeggio.clone())).unwrap();
+        let played = daw.played_events();
+        let times: Vec<&str> = played.iter().map(|e| e.time.as_str()).collect();
+        assert_eq!(times, vec!["2.0", "2.2", "2.4", "2.6"]);
+        assert_eq!(daw.events.len(), 1);
+        assert_eq!(
+            serde_json::to_value(&daw).unwrap()["instruments"]["sampler1"]["arpeggio"],
+            serde_json::json!({ "direction": "up", "rate": "sixteenth" })
+        );
+
+        // Arpeggiating writes the notes into the song
+        daw.set_instrument_arpeggio("sampler1", None).unwrap();
+        assert_eq!(daw.arpeggiate("2.0", "sampler1", &arpeggio).unwrap(), 4);
+        let written: Vec<(&str, Tone, u32)> = daw.events.iter()
+            .map(|e| (e.time.as_str(), e.notes[0].pitch.tone, e.notes[0].duration))
+            .collect();
+        assert_eq!(written, vec![("2.0", Tone::C, 1), ("2.2", Tone::E, 1), ("2.4", Tone::G, 1), ("2.6", Tone::C, 1)]);
+        assert!(daw.arpeggiate("3.0", "sampler1", &arpeggio).is_err());
+    }
+
+    #[test]
+    fn test_resolve_overlaps() {
+        let mut daw = create_test_daw_file();
+        daw.events.clear();
+        let c4 = Pitch::new(Tone::C, 4);
+        daw.add_note("1.0", "sampler1", Note::new(c4, 16)).unwrap();
+        daw.add_note("1.8", "sampler1", Note::new(c4, 16)).unwrap();
+        daw.add_note("1.8", "sampler1", Note::new(Pitch::new(Tone::E, 4), 4)).unwrap();
+        daw.add_note("3.0", "sampler1", Note::new(c4, 8)).unwrap();
+        daw.add_note("3.4", "sampler1", Note::new(c4, 8)).unwrap();
+
+        assert!(daw.resolve_overlaps("missing", None, OverlapPolicy::Merge).is_err());
+        assert_eq!(daw.resolve_overlaps("sampler1", None, OverlapPolicy::KeepBoth).unwrap(), 0);
+
+        // Only the overlap sounding in bar 1 is touched
+        let bar_1 = Some(TimeRange::bars(1, 1));
+        assert_eq!(daw.resolve_overlaps("sampler1", bar_1, OverlapPolicy::Truncate).unwrap(), 1);
+        let durations = |daw: &DawFile, time: &str| {
+            let mut durations: Vec<u32> = daw.events.iter().filter(|e| e.time == time).flat_map(|e| e.notes.iter().map(|n| n.duration)).collect();
+            durations.sort();
+            durations
+        };
+        assert_eq!(durations(&daw, "1.0"), vec![8]);
+        assert_eq!(durations(&daw, "1.8"), vec![4, 16]);
+
+        assert_eq!(daw.resolve_overlaps("sampler1", None, OverlapPolicy::Merge).unwrap(), 1);
+        assert_eq!(durations(&daw, "3.0"), vec![12]);
+        assert!(durations(&daw, "3.4").is_empty());
+    }
+
+    #[test]
+    fn test_add_drums() {
+        let mut daw = create_test_daw_file();
+        daw.events.clear();
+        let pattern = "kick:  x.x.\nsnare: .x.x";
+        assert_eq!(daw.add_drums("1.0", "sampler1", pattern, 8).unwrap(), 4);
+        assert_eq!(daw.add_drums("2.0", "sampler1", "kick: xx", 4).unwrap(), 2);
+
+        let times: Vec<&str> = daw.events.iter().map(|e| e.time.as_str()).collect();
+        assert_eq!(times, vec!["1.0", "1.8", "1.16", "1.24", "2.0", "2.4"]);
+        assert_eq!(daw.events.iter().nth(1).unwrap().notes[0].pitch, Pitch::new(Tone::D, 2));
+        assert_eq!(daw.events.iter().nth(2).unwrap().notes[0].pitch, Pitch::new(Tone::C, 2));
+        assert!(daw.add_drums("1.0", "sampler1", pattern, 0).is_err());
+    }
+
+    #[test]
+    fn test_import_abc() {
+        let mut daw = create_test_daw_file();
+        daw.events.clear();
+        let tune = "X:1\nT:Example\nM:3/4\nL:1/4\nK:D\nd2 f|[Ac]3|";
+        assert_eq!(daw.import_abc("2.0", "sampler1", tune).unwrap(), 4);
+
+        let times: Vec<&str> = daw.events.iter().map(|e| e.time.as_str()).collect();
+        assert_eq!(times, vec!["2.0", "2.16", "2.24"]);
+        assert_eq!(daw.events.iter().nth(1).unwrap().notes, vec![Note::new(Pitch::new(Tone::Fs, 5), 8)]);
+        assert_eq!(daw.events.iter().nth(2).unwrap().notes.len(), 2);
+
+        assert!(daw.import_abc("1.0", "missing", tune).is_err());
+        assert_eq!(daw.events.len(), 3);
+    }
+
+    #[test]
+    fn test_scale_time() {
+        let mut daw = create_test_daw_file();
+        daw.events.clear();
+        for time in ["1.0", "1.8", "1.16", "2.0"] {
+            daw.add_note(time, "sampler1", Note::new(Pitch::new(Tone::C, 4), 8)).unwrap();
+        }
+        let positions = |daw: &DawFile| {
+            let mut positions: Vec<u32> = daw.events.iter().map(|e| time_to_32nds(&e.time).unwrap()).collect();
+            positions.sort();
+            positions
+        };
+
+        // Half time for the first bar
+        assert_eq!(daw.scale_time(TimeRange::bars(1, 1), 2.0).unwrap(), 3);
+        assert_eq!(positions(&daw), vec![0, 16, 32]);
+        assert_eq!(daw.events.first().unwrap().notes[0].duration, 16);
+        // The 1.16 note landed on 2.0 and merged with the note already there
+        let bar_two = daw.events.iter().find(|e| e.time == "2.0").unwrap();
+        assert_eq!(bar_two.notes.len(), 2);
+
+        // Back to double time, rounding 16 * 0.3 = 4.8 up to 5
+        daw.scale_time(TimeRange::new(0, 32), 0.3).unwrap();
+        assert_eq!(positions(&daw), vec![0, 5, 32]);
+        assert_eq!(daw.events.first().unwrap().notes[0].duration, 5);
+
+        assert!(daw.scale_time(TimeRange::bars(1, 1), 0.0).is_err());
+    }
+
+    #[test]
+    fn test_bar_editing() {
+        let mut daw = create_test_daw_file();
+        daw.events.clear();
+        for bar in 1..=3 {
+            daw.add_note(&format!("{}.4", bar), "sampler1", Note::new(Pitch::new(Tone::C, 4), 8)).unwrap();
+        }
+        daw.add_control(ControlEvent {
+            time: "3.0".to_string(),
+            instrument: "sampler1".to_string(),
+            controller: Controller::ModWheel,
+            value: 0.5,
+            curve: controllers::Curve::Step,
+            extra: Default::default(),
+        }).unwrap();
+        let times = |daw: &DawFile| daw.events.iter().map(|e| e.time.clone()).collect::<Vec<_>>();
+
+        daw.insert_bars(2, 2).unwrap();
+        assert_eq!(times(&daw), vec!["1.4", "4.4", "5.4"]);
+        assert_eq!(daw.controls[0].time, "5.0");
+
+        daw.delete_bars(TimeRange::bars(2, 4)).unwrap();
+        assert_eq!(times(&daw), vec!["1.4", "2.4"]);
+        assert_eq!(daw.controls[0].time, "2.0");
+
+        daw.duplicate_bars(TimeRange::bars(1, 2), 2).unwrap();
+        assert_eq!(times(&daw), vec!["1.4", "2.4", "3.4"]);
+        assert_eq!(daw.events.iter().nth(1).unwrap().notes.len(), 2);
+        assert_eq!(daw.controls.len(), 2);
+        assert_eq!(daw.controls[1].time, "3.0");
+
+        assert!(daw.delete_bars(TimeRange::new(4, 32)).is_err());
+        assert!(daw.insert_bars(0, 1).is_err());
+    }
+
+    #[test]
+    fn test_add_steps() {
+        let mut daw = create_test_daw_file();
+        let kick = Pitch::new(Tone::C, 2);
+        assert_eq!(daw.add_steps("2.0", "sampler1", "x...x...x...x..x", 2, kick).unwrap(), 5);
+        let mut positions: Vec<u32> = daw.get_events_in_bar(2).unwrap().iter()
+            .map(|e| time_to_32nds(&e.time).unwrap())
+            .collect();
+        positions.sort();
+        assert_eq!(positions, vec![32, 40, 48, 56, 62]);
+
+        // Melody wraps into the next bar
+        assert_eq!(daw.add_steps("3.16", "sampler1", "C4 E4 G4 -", 8, kick).unwrap(), 3);
+        let event = daw.events.iter().find(|e| e.time == "4.0").unwrap();
+        assert_eq!(event.notes[0].pitch, Pitch::new(Tone::G, 4));
+        assert_eq!(event.notes[0].duration, 16);
+
+        assert!(daw.add_steps("1.0", "missing", "x", 2, kick).is_err());
+        assert!(daw.add_steps("1.0", "sampler1", "x", 0, kick).is_err());
+    }
+
+    #[test]
+    fn test_convert_sample_rate() {
+        let mut daw = DawFile::new("Test".to_string());
+        assert!(daw.convert_sample_rate(48000).unwrap().is_empty());
+        assert_eq!(daw.mixdown.sample_rate, 48000);
+        assert!(daw.convert_sample_rate(0).is_err());
+        assert_eq!(daw.mixdown.sample_rate, 48000);
+    }
+
+    #[test]
+    fn test_project_tuning() {
+        let mut daw = DawFile::new("Test".to_string());
+        assert!(serde_json::to_value(&daw).unwrap().get("tuning").is_none());
+
+        let mut broken = pitch::Tuning::pythagorean();
+        broken.steps.clear();
+        assert!(daw.set_tuning(broken).is_err());
+
+        daw.set_tuning(pitch::Tuning::equal_temperament(19)).unwrap();
+        let json = serde_json::to_value(&daw).unwrap();
+        assert_eq!(json["tuning"]["name"], "19-TET");
+        assert_eq!(json["tuning"]["steps"].as_array().unwrap().len(), 19);
+        let read: DawFile = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(read.tuning, daw.tuning);
+
+        // A file with a tuning that can't be played is refused when it's read
+        let mut broken = json;
+        broken["tuning"]["steps"] = serde_json::json!([]);
+        let error = serde_json::from_value::<DawFile>(broken).unwrap_err();
+        assert!(error.to_string().contains("at least one step"), "{}", error);
+    }
+
+    #[test]
+    fn test_a4_frequency() {
+        let mut daw = DawFile::new("Test".to_string());
+        assert_eq!(daw.a4_frequency(), Some(440.0));
+        daw.set_a4_frequency(432.0).unwrap();
+        assert!(daw.set_a4_frequency(-1.0).is_err());
+        assert_eq!(serde_json::to_value(&daw).unwrap()["tuning"], serde_json::json!({ "mapping": { "reference_frequency": 432.0 } }));
+
+        let c4 = Pitch::new(Tone::C, 4);
+        assert!((daw.tuning.frequency(&c4).unwrap() - c4.frequency_at(432.0)).abs() < 1e-9);
+
+        // Other tuning systems keep their intervals and move A4
+        daw.set_tuning(pitch::Tuning::just_intonation()).unwrap();
+        daw.set_a4_frequency(442.0).unwrap();
+        assert!((daw.a4_frequency().unwrap() - 442.0).abs() < 1e-9);
+        assert!((daw.tuning.frequency(&c4).unwrap() - 442.0 * 3.0 / 5.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_mixdown_profiles() {
+        let mut daw = DawFile::new("Test".to_string());
+        let stems = MixdownSettings::new(48000, 24).with_normalize(false);
+        daw.set_mixdown_profile("stems", stems.clone()).unwrap();
+        assert!(daw.set_mixdown_profile("", MixdownSettings::default()).is_err());
+        assert!(daw.set_mixdown_profile("bad", MixdownSettings::new(44100, 12)).is_err());
+
+        assert_eq!(daw.mixdown_profile(None).unwrap(), &daw.mixdown);
+        assert_eq!(daw.mixdown_profile(Some("stems")).unwrap(), &stems);
+        assert!(daw.mixdown_profile(Some("cd-master")).is_err());
+
+        let read: DawFile = serde_json::from_str(&serde_json::to_string(&daw).unwrap()).unwrap();
+        assert_eq!(read.mixdown_profiles, daw.mixdown_profiles);
+
+        assert_eq!(daw.remove_mixdown_profile("stems").unwrap(), stems);
+        assert!(daw.remove_mixdown_profile("stems").is_err());
+        assert!(!serde_json::to_string(&daw).unwrap().contains("mixdown_profiles"));
+    }
+
     #[test]
     fn test_time_validation() {
         let daw = create_test_daw_file();
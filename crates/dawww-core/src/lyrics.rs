@@ -0,0 +1,125 @@
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use crate::UnknownFields;
+
+/// What a text event is for
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TextKind {
+    /// Sung text, usually one syllable per note
+    #[default]
+    Lyric,
+    /// A free-form note for the reader, e.g. "chorus" or "build up"
+    Annotation,
+}
+
+impl TextKind {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Text attached to a position in the song
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct LyricEvent {
+    #[schemars(regex(pattern = crate::schema::TIME_PATTERN))]
+    pub time: String,
+    pub text: String,
+    #[serde(default, skip_serializing_if = "TextKind::is_default")]
+    pub kind: TextKind,
+    /// Instrument whose note the text is sung on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instrument: Option<String>,
+    #[serde(flatten)]
+    pub extra: UnknownFields,
+}
+
+impl LyricEvent {
+    pub fn new(time: &str, text: &str) -> Self {
+        Self {
+            time: time.to_string(),
+            text: text.to_string(),
+            kind: TextKind::Lyric,
+            instrument: None,
+            extra: UnknownFields::new(),
+        }
+    }
+
+    pub fn annotation(time: &str, text: &str) -> Self {
+        Self { kind: TextKind::Annotation, ..Self::new(time, text) }
+    }
+
+    pub fn with_instrument(mut self, instrument: &str) -> Self {
+        self.instrument = Some(instrument.to_string());
+        self
+    }
+
+    /// The event as a Standard MIDI File meta event (lyric `FF 05` or text `FF 01`),
+    /// without the delta time
+    pub fn midi_meta_event(&self) -> Vec<u8> {
+        let meta_type = match self.kind {
+            TextKind::Lyric => 0x05,
+            TextKind::Annotation => 0x01,
+        };
+        let mut bytes = vec![0xFF, meta_type];
+        bytes.extend(variable_length(self.text.len() as u32));
+        bytes.extend(self.text.as_bytes());
+        bytes
+    }
+}
+
+/// MIDI variable-length quantity: 7 bits per byte, high bit set on all but the last
+fn variable_length(mut value: u32) -> Vec<u8> {
+    let mut bytes = vec![(value & 0x7F) as u8];
+    value >>= 7;
+    while value > 0 {
+        bytes.insert(0, (value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes
+}
+
+/// Split lyric text into syllables, one per note. Words are separated by whitespace and
+/// syllables within a word by `-`, which stays on the syllable before it so it's clear
+/// the word goes on ("hal-le-lu-jah" gives `hal-`, `le-`, `lu-`, `jah`).
+pub fn syllables(text: &str) -> Vec<String> {
+    text.split_whitespace()
+        .flat_map(|word| {
+            let parts: Vec<&str> = word.split('-').filter(|s| !s.is_empty()).collect();
+            let last = parts.len().saturating_sub(1);
+            parts.into_iter()
+                .enumerate()
+                .map(move |(i, part)| if i < last { format!("{}-", part) } else { part.to_string() })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_syllables() {
+        assert_eq!(syllables("hal-le-lu-jah  sing\nout-"), vec!["hal-", "le-", "lu-", "jah", "sing", "out"]);
+        assert!(syllables("  ").is_empty());
+    }
+
+    #[test]
+    fn test_midi_meta_event() {
+        assert_eq!(LyricEvent::new("1.0", "la").midi_meta_event(), vec![0xFF, 0x05, 2, b'l', b'a']);
+        assert_eq!(LyricEvent::annotation("1.0", "x").midi_meta_event(), vec![0xFF, 0x01, 1, b'x']);
+        assert_eq!(variable_length(0x7F), vec![0x7F]);
+        assert_eq!(variable_length(200), vec![0x81, 0x48]);
+    }
+
+    #[test]
+    fn test_serialization_omits_defaults() {
+        let json = serde_json::to_value(LyricEvent::new("2.4", "love")).unwrap();
+        assert_eq!(json, serde_json::json!({ "time": "2.4", "text": "love" }));
+
+        let annotation = LyricEvent::annotation("1.0", "verse").with_instrument("vox");
+        let json = serde_json::to_value(&annotation).unwrap();
+        assert_eq!(json["kind"], "annotation");
+        assert_eq!(serde_json::from_value::<LyricEvent>(json).unwrap(), annotation);
+    }
+}
@@ -1,51 +1,57 @@
 use serde::{Deserialize, Serialize};
-use std::time::SystemTime;
-use std::time::{UNIX_EPOCH};
+use schemars::JsonSchema;
+use chrono::{DateTime, SecondsFormat, Utc};
+use crate::scale::Key;
+use crate::UnknownFields;
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
 pub struct Metadata {
     pub title: String,
     pub creation_date: String,
     pub modification_date: String,
     pub revision: u32,
+    /// Number of edits made to the song, counted even when they land in the same instant
+    #[serde(default)]
+    pub edit_count: u64,
+    /// Key signature, used for scale-aware editing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<Key>,
+    #[serde(flatten)]
+    pub extra: UnknownFields,
 }
 
 impl Metadata {
     /// Create new metadata with the given title
     pub fn new(title: String) -> Self {
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-        let iso_date = chrono::DateTime::from_timestamp(now as i64, 0)
-            .unwrap()
-            .to_rfc3339();
+        let iso_date = format_date(Utc::now());
 
         Self {
             title,
             creation_date: iso_date.clone(),
             modification_date: iso_date,
             revision: 0,
+            edit_count: 0,
+            key: None,
+            extra: UnknownFields::new(),
         }
     }
 
     /// Update the title and modification date
     pub fn set_title(&mut self, title: String) {
         self.title = title;
-        // Sleep briefly to ensure different timestamp
-        std::thread::sleep(std::time::Duration::from_secs(1));
         self.update_modification_date();
     }
 
-    /// Update the modification date to the current time
+    /// Record an edit: count it and move the modification date to the current time.
+    /// The date always moves forward, by a nanosecond if the clock hasn't advanced.
     pub fn update_modification_date(&mut self) {
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-        self.modification_date = chrono::DateTime::from_timestamp(now as i64, 0)
-            .unwrap()
-            .to_rfc3339();
+        let mut now = Utc::now();
+        if let Ok(previous) = DateTime::parse_from_rfc3339(&self.modification_date) {
+            let earliest = previous.with_timezone(&Utc) + chrono::Duration::nanoseconds(1);
+            now = now.max(earliest);
+        }
+        self.modification_date = format_date(now);
+        self.edit_count += 1;
     }
 
     /// Increment the revision number
@@ -54,13 +60,18 @@ impl Metadata {
     }
 
     /// Get the modification date as a DateTime
-    pub fn modification_date(&self) -> chrono::DateTime<chrono::Utc> {
-        chrono::DateTime::parse_from_rfc3339(&self.modification_date)
+    pub fn modification_date(&self) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(&self.modification_date)
             .unwrap()
-            .with_timezone(&chrono::Utc)
+            .with_timezone(&Utc)
     }
 }
 
+/// RFC 3339 with nanoseconds, e.g. `2024-03-19T15:30:45.123456789Z`
+fn format_date(date: DateTime<Utc>) -> String {
+    date.to_rfc3339_opts(SecondsFormat::Nanos, true)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -89,6 +100,19 @@ mod tests {
         assert_eq!(metadata.title, "New Title");
         assert!(metadata.modification_date != original_date);
         assert!(chrono::DateTime::parse_from_rfc3339(&metadata.modification_date).is_ok());
+        assert_eq!(metadata.edit_count, 1);
+    }
+
+    #[test]
+    fn test_rapid_edits_keep_dates_increasing() {
+        let mut metadata = Metadata::new("Test Song".to_string());
+        let mut previous = metadata.modification_date();
+        for _ in 0..1000 {
+            metadata.update_modification_date();
+            assert!(metadata.modification_date() > previous);
+            previous = metadata.modification_date();
+        }
+        assert_eq!(metadata.edit_count, 1000);
     }
 
     #[test]
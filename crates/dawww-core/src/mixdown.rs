@@ -0,0 +1,591 @@
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use anyhow::{Result, bail};
+use crate::UnknownFields;
+
+/// How many channels a mixdown is written with. Surround files put their speakers in WAV
+/// order: front left and right, then center and LFE for 5.1, then rear left and right.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ChannelLayout {
+    Mono,
+    #[default]
+    Stereo,
+    /// Front and rear pairs
+    Quad,
+    /// Front pair, center, LFE and rear pair
+    #[serde(rename = "5.1")]
+    Surround51,
+}
+
+impl ChannelLayout {
+    pub fn channels(&self) -> u16 {
+        match self {
+            ChannelLayout::Mono => 1,
+            ChannelLayout::Stereo => 2,
+            ChannelLayout::Quad => 4,
+            ChannelLayout::Surround51 => 6,
+        }
+    }
+
+    /// Whether the layout has more speakers than a stereo pair
+    pub fn is_surround(&self) -> bool {
+        matches!(self, ChannelLayout::Quad | ChannelLayout::Surround51)
+    }
+
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// How samples are stored in a mixdown
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SampleFormat {
+    #[default]
+    Int,
+    /// 32-bit float, which keeps levels above full scale instead of clipping them
+    Float,
+}
+
+impl SampleFormat {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Noise added when samples are rounded to 16 bits or fewer, so quiet passages fade into
+/// noise instead of distorting
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Dither {
+    /// Plain rounding
+    #[default]
+    None,
+    /// Triangular (TPDF) dither of up to one step either way
+    Tpdf,
+    /// TPDF dither with the rounding error fed back, moving the noise up toward frequencies
+    /// the ear is less sensitive to
+    Shaped,
+}
+
+impl Dither {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// The kind of file a mixdown is written as. The lossy formats are for sharing demos and
+/// need the renderer built with the matching cargo feature.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Wav,
+    /// Big-endian integer PCM, as macOS tools and hardware samplers often expect
+    Aiff,
+    Mp3,
+    /// Ogg Vorbis
+    Ogg,
+    /// Opus in an Ogg container
+    Opus,
+}
+
+impl ExportFormat {
+    /// Usual file extension
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Wav => "wav",
+            ExportFormat::Aiff => "aiff",
+            ExportFormat::Mp3 => "mp3",
+            ExportFormat::Ogg => "ogg",
+            ExportFormat::Opus => "opus",
+        }
+    }
+
+    /// The format a file extension names, in any case
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension.to_ascii_lowercase().as_str() {
+            "wav" => Some(ExportFormat::Wav),
+            "aif" | "aiff" => Some(ExportFormat::Aiff),
+            "mp3" => Some(ExportFormat::Mp3),
+            "ogg" => Some(ExportFormat::Ogg),
+            "opus" => Some(ExportFormat::Opus),
+            _ => None,
+        }
+    }
+
+    pub fn is_lossy(&self) -> bool {
+        !matches!(self, ExportFormat::Wav | ExportFormat::Aiff)
+    }
+
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Where a metronome click ends up
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ClickMode {
+    /// Mixed into the render
+    #[default]
+    Mix,
+    /// Left out of the render and only written as a stem
+    Stem,
+}
+
+impl ClickMode {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// A metronome click on every beat (quarter note), with the first beat of each bar accented,
+/// e.g. for practice tracks. It keeps straight time whatever the groove.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct Click {
+    #[serde(default, skip_serializing_if = "ClickMode::is_default")]
+    pub mode: ClickMode,
+    /// Peak level of the accented clicks in dBFS; the other beats are 6 dB quieter. The click
+    /// is added after normalization, so it keeps this level.
+    #[serde(default = "default_click_level_db")]
+    pub level_db: f64,
+    #[serde(flatten)]
+    pub extra: UnknownFields,
+}
+
+fn default_click_level_db() -> f64 {
+    -6.0
+}
+
+impl Default for Click {
+    fn default() -> Self {
+        Self { mode: ClickMode::default(), level_db: default_click_level_db(), extra: UnknownFields::new() }
+    }
+}
+
+impl Click {
+    pub fn new(mode: ClickMode, level_db: f64) -> Self {
+        Self { mode, level_db, ..Self::default() }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if !(self.level_db <= 0.0 && self.level_db.is_finite()) {
+            bail!("Click level must be at most 0 dBFS, got {}", self.level_db);
+        }
+        Ok(())
+    }
+}
+
+fn default_oversampling() -> u32 {
+    1
+}
+
+fn is_default_oversampling(oversampling: &u32) -> bool {
+    *oversampling == default_oversampling()
+}
+
+/// Oversampling factors a mixdown can use
+pub const OVERSAMPLING_FACTORS: [u32; 3] = [1, 2, 4];
+
+/// Sample rates an Opus stream can be encoded at
+pub const OPUS_SAMPLE_RATES: [u32; 5] = [8000, 12000, 16000, 24000, 48000];
+
+/// Output format of a rendered mixdown
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct MixdownSettings {
+    pub sample_rate: u32,
+    pub bit_depth: u16,
+    #[serde(default, skip_serializing_if = "SampleFormat::is_default")]
+    pub sample_format: SampleFormat,
+    #[serde(default, skip_serializing_if = "ChannelLayout::is_default")]
+    pub channels: ChannelLayout,
+    /// How the mix's level is set before it's written, beyond the mixer's track gains and
+    /// headroom; anything over full scale is clipped
+    #[serde(
+        default,
+        skip_serializing_if = "Normalize::is_default",
+        serialize_with = "serialize_normalize",
+        deserialize_with = "deserialize_normalize"
+    )]
+    #[schemars(with = "RawNormalize")]
+    pub normalize: Normalize,
+    /// Dither for integer files of 16 bits or fewer
+    #[serde(
+        default,
+        skip_serializing_if = "Dither::is_default",
+        serialize_with = "serialize_dither",
+        deserialize_with = "deserialize_dither"
+    )]
+    #[schemars(with = "RawDither")]
+    pub dither: Dither,
+    #[serde(default, skip_serializing_if = "ExportFormat::is_default")]
+    pub format: ExportFormat,
+    /// Target bitrate in kbit/s for lossy formats
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bitrate: Option<u32>,
+    /// Variable-bitrate quality from 0 to 1 for Ogg Vorbis, used instead of a bitrate
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quality: Option<f64>,
+    /// Seconds of audio kept after the last note ends. Left out, the renderer keeps every
+    /// note's release and the tails of delays and reverbs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tail: Option<f64>,
+    /// Metronome click mixed in or written as a stem
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub click: Option<Click>,
+    /// Seconds the start of the render fades up from silence over
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fade_in: Option<f64>,
+    /// Seconds the end of the render fades down to silence over
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fade_out: Option<f64>,
+    /// Level in dBFS below which leading and trailing audio is cut, measured at the mix's
+    /// final level
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trim_silence_db: Option<f64>,
+    /// How many times the sample rate synth filters and distortion run at, to keep their
+    /// harmonics from aliasing: 1 (off), 2 or 4
+    #[serde(default = "default_oversampling", skip_serializing_if = "is_default_oversampling")]
+    #[schemars(range(min = 1))]
+    pub oversampling: u32,
+    #[serde(flatten)]
+    pub extra: UnknownFields,
+}
+
+/// How a mixdown's level is set
+#[derive(Debug, Default, Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq)]
+#[serde(tag = "mode", rename_all = "lowercase")]
+pub enum Normalize {
+    /// Keep the mix's level
+    #[default]
+    Off,
+    /// Scale the loudest sample to the mixer's headroom below full scale
+    Peak,
+    /// Scale the mix to an EBU R128 integrated loudness, without letting true peaks go over
+    /// `true_peak_db`. Mixes whose peaks hit the ceiling first end up quieter than the target.
+    Loudness {
+        target_lufs: f64,
+        #[serde(default = "default_true_peak_db")]
+        true_peak_db: f64,
+    },
+}
+
+fn default_true_peak_db() -> f64 {
+    -1.0
+}
+
+impl From<bool> for Normalize {
+    fn from(normalize: bool) -> Self {
+        if normalize { Normalize::Peak } else { Normalize::Off }
+    }
+}
+
+impl Normalize {
+    /// Loudness normalization to `target_lufs` with a -1 dBTP ceiling
+    pub fn loudness(target_lufs: f64) -> Self {
+        Normalize::Loudness { target_lufs, true_peak_db: default_true_peak_db() }
+    }
+
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        match *self {
+            Normalize::Off | Normalize::Peak => {}
+            Normalize::Loudness { target_lufs, true_peak_db } => {
+                if !(target_lufs < 0.0 && target_lufs.is_finite()) {
+                    bail!("Loudness target must be below 0 LUFS, got {}", target_lufs);
+                }
+                if !(true_peak_db <= 0.0 && true_peak_db.is_finite()) {
+                    bail!("True peak ceiling must be at most 0 dBTP, got {}", true_peak_db);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `normalize` as written in files: `true` and `false` for peak normalization and off, or a
+/// mode
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+enum RawNormalize {
+    Enabled(bool),
+    Mode(Normalize),
+}
+
+fn serialize_normalize<S: serde::Serializer>(normalize: &Normalize, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    match normalize {
+        Normalize::Off => RawNormalize::Enabled(false),
+        Normalize::Peak => RawNormalize::Enabled(true),
+        normalize => RawNormalize::Mode(*normalize),
+    }
+    .serialize(serializer)
+}
+
+fn deserialize_normalize<'de, D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Normalize, D::Error> {
+    Ok(match RawNormalize::deserialize(deserializer)? {
+        RawNormalize::Enabled(normalize) => normalize.into(),
+        RawNormalize::Mode(normalize) => normalize,
+    })
+}
+
+/// `dither` as written in files: `true` and `false` for TPDF dither and plain rounding,
+/// which older files use, or a kind of dither
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+enum RawDither {
+    Enabled(bool),
+    Kind(Dither),
+}
+
+fn serialize_dither<S: serde::Serializer>(dither: &Dither, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    match dither {
+        Dither::None => RawDither::Enabled(false),
+        Dither::Tpdf => RawDither::Enabled(true),
+        dither => RawDither::Kind(*dither),
+    }
+    .serialize(serializer)
+}
+
+fn deserialize_dither<'de, D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Dither, D::Error> {
+    Ok(match RawDither::deserialize(deserializer)? {
+        RawDither::Enabled(true) => Dither::Tpdf,
+        RawDither::Enabled(false) => Dither::None,
+        RawDither::Kind(dither) => dither,
+    })
+}
+
+impl MixdownSettings {
+    /// Normalized stereo at the given rate and depth
+    pub fn new(sample_rate: u32, bit_depth: u16) -> Self {
+        Self {
+            sample_rate,
+            bit_depth,
+            sample_format: SampleFormat::default(),
+            channels: ChannelLayout::default(),
+            normalize: Normalize::default(),
+            dither: Dither::default(),
+            format: ExportFormat::default(),
+            bitrate: None,
+            quality: None,
+            tail: None,
+            click: None,
+            fade_in: None,
+            fade_out: None,
+            trim_silence_db: None,
+            oversampling: default_oversampling(),
+            extra: UnknownFields::new(),
+        }
+    }
+
+    /// 32-bit float output
+    pub fn float(sample_rate: u32) -> Self {
+        Self { sample_format: SampleFormat::Float, ..Self::new(sample_rate, 32) }
+    }
+
+    pub fn with_channels(mut self, channels: ChannelLayout) -> Self {
+        self.channels = channels;
+        self
+    }
+
+    /// Set the normalization; `true` and `false` mean peak normalization and off
+    pub fn with_normalize(mut self, normalize: impl Into<Normalize>) -> Self {
+        self.normalize = normalize.into();
+        self
+    }
+
+    pub fn with_dither(mut self, dither: Dither) -> Self {
+        self.dither = dither;
+        self
+    }
+
+    /// Write a lossy file at `bitrate` kbit/s instead of a WAV
+    pub fn with_format(mut self, format: ExportFormat, bitrate: Option<u32>) -> Self {
+        self.format = format;
+        self.bitrate = bitrate;
+        self
+    }
+
+    /// End the render a fixed number of seconds after the last note
+    pub fn with_tail(mut self, seconds: f64) -> Self {
+        self.tail = Some(seconds);
+        self
+    }
+
+    /// Add a metronome click
+    pub fn with_click(mut self, click: Click) -> Self {
+        self.click = Some(click);
+        self
+    }
+
+    /// Fade the start and end of the render over the given seconds
+    pub fn with_fades(mut self, fade_in: f64, fade_out: f64) -> Self {
+        self.fade_in = Some(fade_in);
+        self.fade_out = Some(fade_out);
+        self
+    }
+
+    /// Cut leading and trailing audio quieter than `threshold_db` dBFS
+    pub fn with_trim_silence(mut self, threshold_db: f64) -> Self {
+        self.trim_silence_db = Some(threshold_db);
+        self
+    }
+
+    /// Run synth filters and distortion at `factor` times the sample rate
+    pub fn with_oversampling(mut self, factor: u32) -> Self {
+        self.oversampling = factor;
+        self
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.sample_rate == 0 {
+            bail!("Sample rate must be greater than zero");
+        }
+        if ![8, 16, 24, 32].contains(&self.bit_depth) {
+            bail!("Unsupported bit depth {}", self.bit_depth);
+        }
+        if self.sample_format == SampleFormat::Float && self.bit_depth != 32 {
+            bail!("Float output must be 32-bit, got {}-bit", self.bit_depth);
+        }
+        if self.format != ExportFormat::Wav && self.sample_format == SampleFormat::Float {
+            bail!("Float samples can only be written to WAV files");
+        }
+        if self.format.is_lossy() && self.channels.is_surround() {
+            bail!("Lossy formats can only be written in mono or stereo");
+        }
+        if self.bitrate == Some(0) {
+            bail!("Bitrate must be greater than zero");
+        }
+        if self.quality.is_some_and(|quality| !(0.0..=1.0).contains(&quality)) {
+            bail!("Quality must be between 0 and 1, got {}", self.quality.unwrap());
+        }
+        if self.format == ExportFormat::Opus && !OPUS_SAMPLE_RATES.contains(&self.sample_rate) {
+            bail!("Opus can't be encoded at {} Hz; use one of {:?}", self.sample_rate, OPUS_SAMPLE_RATES);
+        }
+        self.normalize.validate()?;
+        if self.tail.is_some_and(|tail| !(tail >= 0.0 && tail.is_finite())) {
+            bail!("Tail must be a non-negative number of seconds, got {}", self.tail.unwrap());
+        }
+        if let Some(click) = &self.click {
+            click.validate()?;
+        }
+        for (name, fade) in [("Fade in", self.fade_in), ("Fade out", self.fade_out)] {
+            if fade.is_some_and(|fade| !(fade >= 0.0 && fade.is_finite())) {
+                bail!("{} must be a non-negative number of seconds, got {}", name, fade.unwrap());
+            }
+        }
+        if !OVERSAMPLING_FACTORS.contains(&self.oversampling) {
+            bail!("Oversampling must be one of {:?}, got {}", OVERSAMPLING_FACTORS, self.oversampling);
+        }
+        if self.trim_silence_db.is_some_and(|threshold| !(threshold <= 0.0 && threshold.is_finite())) {
+            bail!("Silence threshold must be at most 0 dBFS, got {}", self.trim_silence_db.unwrap());
+        }
+        Ok(())
+    }
+}
+
+impl Default for MixdownSettings {
+    fn default() -> Self {
+        Self::new(44100, 16)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_defaults_are_omitted() {
+        let json = serde_json::to_value(MixdownSettings::default()).unwrap();
+        assert_eq!(json, serde_json::json!({ "sample_rate": 44100, "bit_depth": 16 }));
+
+        let stems = MixdownSettings::new(48000, 24).with_channels(ChannelLayout::Mono).with_normalize(true).with_dither(Dither::Shaped);
+        let json = serde_json::to_value(&stems).unwrap();
+        assert_eq!(json["channels"], "mono");
+        assert_eq!(json["normalize"], true);
+        assert_eq!(json["dither"], "shaped");
+        assert_eq!(serde_json::from_value::<MixdownSettings>(json).unwrap(), stems);
+        // Older files switch TPDF dither on with `true`
+        let json = serde_json::json!({ "sample_rate": 44100, "bit_depth": 16, "dither": true });
+        let dithered = serde_json::from_value::<MixdownSettings>(json.clone()).unwrap();
+        assert_eq!(dithered.dither, Dither::Tpdf);
+        assert_eq!(serde_json::to_value(&dithered).unwrap(), json);
+        let json = serde_json::json!({ "sample_rate": 44100, "bit_depth": 16, "dither": "tpdf" });
+        assert_eq!(serde_json::from_value::<MixdownSettings>(json).unwrap(), dithered);
+        let json = serde_json::to_value(MixdownSettings::default().with_channels(ChannelLayout::Surround51)).unwrap();
+        assert_eq!(json["channels"], "5.1");
+        assert_eq!(serde_json::from_value::<MixdownSettings>(json).unwrap().channels.channels(), 6);
+
+        let demo = MixdownSettings::new(44100, 16).with_format(ExportFormat::Mp3, Some(128));
+        let json = serde_json::to_value(&demo).unwrap();
+        assert_eq!(json["format"], "mp3");
+        assert_eq!(json["bitrate"], 128);
+        assert_eq!(serde_json::from_value::<MixdownSettings>(json).unwrap(), demo);
+
+        let master = MixdownSettings::default().with_normalize(Normalize::loudness(-14.0));
+        let json = serde_json::to_value(&master).unwrap();
+        assert_eq!(json["normalize"], serde_json::json!({ "mode": "loudness", "target_lufs": -14.0, "true_peak_db": -1.0 }));
+        assert_eq!(serde_json::from_value::<MixdownSettings>(json).unwrap(), master);
+        let json = serde_json::json!({ "sample_rate": 44100, "bit_depth": 16, "normalize": { "mode": "peak" } });
+        assert_eq!(serde_json::from_value::<MixdownSettings>(json).unwrap().normalize, Normalize::Peak);
+
+        let practice = MixdownSettings::default().with_click(Click::new(ClickMode::Stem, -12.0));
+        let json = serde_json::to_value(&practice).unwrap();
+        assert_eq!(json["click"], serde_json::json!({ "mode": "stem", "level_db": -12.0 }));
+        assert_eq!(serde_json::from_value::<MixdownSettings>(json).unwrap(), practice);
+        let json = serde_json::json!({ "sample_rate": 44100, "bit_depth": 16, "click": {} });
+        assert_eq!(serde_json::from_value::<MixdownSettings>(json).unwrap().click, Some(Click::default()));
+
+        let release = MixdownSettings::default().with_fades(0.0, 4.0).with_trim_silence(-60.0);
+        let json = serde_json::to_value(&release).unwrap();
+        assert_eq!(json["fade_out"], 4.0);
+        assert_eq!(json["trim_silence_db"], -60.0);
+        assert!(json.get("oversampling").is_none());
+        assert_eq!(serde_json::from_value::<MixdownSettings>(json).unwrap(), release);
+
+        let json = serde_json::to_value(MixdownSettings::float(48000)).unwrap();
+        assert_eq!(json, serde_json::json!({ "sample_rate": 48000, "bit_depth": 32, "sample_format": "float" }));
+    }
+
+    #[test]
+    fn test_format_from_extension() {
+        assert_eq!(ExportFormat::from_extension("AIF"), Some(ExportFormat::Aiff));
+        assert_eq!(ExportFormat::from_extension("aiff"), Some(ExportFormat::Aiff));
+        assert_eq!(ExportFormat::from_extension("ogg"), Some(ExportFormat::Ogg));
+        assert_eq!(ExportFormat::from_extension("flac"), None);
+        assert!(!ExportFormat::Aiff.is_lossy());
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(MixdownSettings::new(48000, 24).validate().is_ok());
+        assert!(MixdownSettings::new(0, 16).validate().is_err());
+        assert!(MixdownSettings::new(44100, 12).validate().is_err());
+        assert!(MixdownSettings::float(48000).validate().is_ok());
+        assert!(MixdownSettings { sample_format: SampleFormat::Float, ..MixdownSettings::new(48000, 24) }.validate().is_err());
+        assert!(MixdownSettings::new(48000, 16).with_format(ExportFormat::Opus, Some(96)).validate().is_ok());
+        assert!(MixdownSettings::new(44100, 16).with_format(ExportFormat::Opus, None).validate().is_err());
+        assert!(MixdownSettings::new(44100, 16).with_format(ExportFormat::Mp3, Some(0)).validate().is_err());
+        assert!(MixdownSettings::new(48000, 24).with_format(ExportFormat::Aiff, None).validate().is_ok());
+        assert!(MixdownSettings::float(48000).with_format(ExportFormat::Aiff, None).validate().is_err());
+        assert!(MixdownSettings::new(48000, 24).with_channels(ChannelLayout::Surround51).validate().is_ok());
+        assert!(MixdownSettings::new(48000, 16).with_channels(ChannelLayout::Quad).with_format(ExportFormat::Ogg, None).validate().is_err());
+        assert!(MixdownSettings { quality: Some(1.5), ..MixdownSettings::new(44100, 16).with_format(ExportFormat::Ogg, None) }.validate().is_err());
+        assert!(MixdownSettings::default().with_normalize(Normalize::loudness(-14.0)).validate().is_ok());
+        assert!(MixdownSettings::default().with_normalize(Normalize::loudness(3.0)).validate().is_err());
+        assert!(MixdownSettings::default().with_tail(2.0).validate().is_ok());
+        assert!(MixdownSettings::default().with_tail(-1.0).validate().is_err());
+        assert!(MixdownSettings::default().with_click(Click::new(ClickMode::Mix, 3.0)).validate().is_err());
+        assert!(MixdownSettings::default().with_fades(0.5, 3.0).with_trim_silence(-60.0).validate().is_ok());
+        assert!(MixdownSettings::default().with_fades(-0.5, 3.0).validate().is_err());
+        assert!(MixdownSettings::default().with_fades(0.0, f64::NAN).validate().is_err());
+        assert!(MixdownSettings::default().with_trim_silence(6.0).validate().is_err());
+        assert!(MixdownSettings::default().with_oversampling(4).validate().is_ok());
+        assert!(MixdownSettings::default().with_oversampling(3).validate().is_err());
+        assert!(MixdownSettings::default().with_oversampling(0).validate().is_err());
+    }
+}
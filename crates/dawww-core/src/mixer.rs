@@ -0,0 +1,416 @@
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use std::collections::BTreeMap;
+use anyhow::{Result, bail};
+use crate::effects::EffectInstance;
+use crate::UnknownFields;
+
+/// Name of the bus every track and bus feeds into by default
+pub const MASTER_BUS: &str = "master";
+
+/// Speakers a signal feeding the master bus plays from in surround mixdowns. Stereo and
+/// mono mixdowns fold everything but the front pair into left and right.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Speakers {
+    /// Front left and right
+    #[default]
+    Front,
+    /// Rear left and right
+    Rear,
+    /// The center speaker, in mono
+    Center,
+    /// The low-frequency effects channel, in mono
+    Lfe,
+}
+
+impl Speakers {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// A mix bus: summed input, an insert chain, and a fader
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Default, PartialEq)]
+pub struct Bus {
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub gain_db: f64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub effects: Vec<EffectInstance>,
+    /// Bus this bus feeds into; `None` means the master bus
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    /// Speakers the bus plays from if it feeds the master bus
+    #[serde(default, skip_serializing_if = "Speakers::is_default")]
+    pub speakers: Speakers,
+    #[serde(flatten)]
+    pub extra: UnknownFields,
+}
+
+/// Routing for one track (instrument)
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Default, PartialEq)]
+pub struct TrackRouting {
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub gain_db: f64,
+    /// Stereo position from -1 (left) to 1 (right), applied after the track's inserts
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub pan: f64,
+    /// Bus the track's output feeds into; `None` means the master bus
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    /// Post-insert send levels in dB, keyed by bus name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub sends: BTreeMap<String, f64>,
+    /// Speakers the track plays from if it feeds the master bus directly
+    #[serde(default, skip_serializing_if = "Speakers::is_default")]
+    pub speakers: Speakers,
+    /// Silences the track, sends included
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub mute: bool,
+    /// While any track is soloed, only soloed tracks play
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub solo: bool,
+    #[serde(flatten)]
+    pub extra: UnknownFields,
+}
+
+/// The mixer graph: named buses, per-track routing, and the master bus.
+/// Tracks without an entry go straight to master at unity gain.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Default, PartialEq)]
+pub struct Mixer {
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub buses: BTreeMap<String, Bus>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tracks: BTreeMap<String, TrackRouting>,
+    #[serde(default, skip_serializing_if = "is_default_bus")]
+    pub master: Bus,
+    /// dB the sum feeding the master bus is lowered by before the master effects, so tracks
+    /// at unity gain can be stacked without overdriving them. Peak normalization scales the
+    /// mix's loudest sample to this far below full scale too. Non-negative.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub headroom_db: f64,
+    #[serde(flatten)]
+    pub extra: UnknownFields,
+}
+
+fn is_zero(value: &f64) -> bool {
+    *value == 0.0
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+fn is_default_bus(bus: &Bus) -> bool {
+    *bus == Bus::default()
+}
+
+impl Mixer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether the mixer only has default routing
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Add a bus that feeds the master bus
+    pub fn add_bus(&mut self, name: &str, bus: Bus) -> Result<()> {
+        if name == MASTER_BUS {
+            bail!("'{}' is reserved for the master bus", MASTER_BUS);
+        }
+        if self.buses.contains_key(name) {
+            bail!("Bus '{}' already exists", name);
+        }
+        self.buses.insert(name.to_string(), bus);
+        Ok(())
+    }
+
+    /// Remove a bus along with every send and output pointing at it
+    pub fn remove_bus(&mut self, name: &str) -> Result<()> {
+        if self.buses.remove(name).is_none() {
+            bail!("Bus '{}' not found", name);
+        }
+        for track in self.tracks.values_mut() {
+            track.sends.remove(name);
+            if track.output.as_deref() == Some(name) {
+                track.output = None;
+            }
+        }
+        for bus in self.buses.values_mut() {
+            if bus.output.as_deref() == Some(name) {
+                bus.output = None;
+            }
+        }
+        Ok(())
+    }
+
+    /// Routing for a track, or the default routing if it has none
+    pub fn track(&self, track: &str) -> TrackRouting {
+        self.tracks.get(track).cloned().unwrap_or_default()
+    }
+
+    /// Set a track's send level to a bus in dB
+    pub fn set_send(&mut self, track: &str, bus: &str, level_db: f64) -> Result<()> {
+        self.check_bus(bus)?;
+        if bus == MASTER_BUS {
+            bail!("Tracks feed the master bus through their output, not a send");
+        }
+        if !level_db.is_finite() {
+            bail!("Send level must be a finite number of dB");
+        }
+        self.tracks.entry(track.to_string()).or_default().sends.insert(bus.to_string(), level_db);
+        Ok(())
+    }
+
+    /// Remove a track's send to a bus
+    pub fn remove_send(&mut self, track: &str, bus: &str) {
+        if let Some(routing) = self.tracks.get_mut(track) {
+            routing.sends.remove(bus);
+        }
+    }
+
+    /// Set a track's fader level in dB
+    pub fn set_track_gain(&mut self, track: &str, gain_db: f64) -> Result<()> {
+        if !gain_db.is_finite() {
+            bail!("Track gain must be a finite number of dB");
+        }
+        self.tracks.entry(track.to_string()).or_default().gain_db = gain_db;
+        Ok(())
+    }
+
+    pub fn set_mute(&mut self, track: &str, mute: bool) {
+        self.tracks.entry(track.to_string()).or_default().mute = mute;
+    }
+
+    pub fn set_solo(&mut self, track: &str, solo: bool) {
+        self.tracks.entry(track.to_string()).or_default().solo = solo;
+    }
+
+    /// Whether a track is heard: it isn't muted, and either it's soloed or nothing is
+    pub fn is_audible(&self, track: &str) -> bool {
+        let routing = self.tracks.get(track);
+        if routing.is_some_and(|routing| routing.mute) {
+            return false;
+        }
+        routing.is_some_and(|routing| routing.solo) || !self.tracks.values().any(|routing| routing.solo)
+    }
+
+    /// Linear gain a track plays at: its fader level, or 0 when it isn't audible
+    pub fn track_level(&self, track: &str) -> f64 {
+        if self.is_audible(track) {
+            10_f64.powf(self.track(track).gain_db / 20.0)
+        } else {
+            0.0
+        }
+    }
+
+    /// Set how far the master bus's input is lowered, in dB
+    pub fn set_headroom(&mut self, headroom_db: f64) -> Result<()> {
+        if !(headroom_db >= 0.0 && headroom_db.is_finite()) {
+            bail!("Headroom must be a non-negative number of dB, got {}", headroom_db);
+        }
+        self.headroom_db = headroom_db;
+        Ok(())
+    }
+
+    /// Set a track's stereo position, from -1 (left) to 1 (right)
+    pub fn set_pan(&mut self, track: &str, pan: f64) -> Result<()> {
+        if !(-1.0..=1.0).contains(&pan) {
+            bail!("Pan must be between -1 and 1, got {}", pan);
+        }
+        self.tracks.entry(track.to_string()).or_default().pan = pan;
+        Ok(())
+    }
+
+    /// Route a track's output to a bus (`master` or `None` for the master bus)
+    pub fn set_track_output(&mut self, track: &str, bus: Option<&str>) -> Result<()> {
+        let bus = bus.filter(|b| *b != MASTER_BUS);
+        if let Some(bus) = bus {
+            self.check_bus(bus)?;
+        }
+        self.tracks.entry(track.to_string()).or_default().output = bus.map(|b| b.to_string());
+        Ok(())
+    }
+
+    /// Choose the speakers a track plays from in surround mixdowns
+    pub fn set_track_speakers(&mut self, track: &str, speakers: Speakers) {
+        self.tracks.entry(track.to_string()).or_default().speakers = speakers;
+    }
+
+    /// Rename a track's routing entry
+    pub fn rename_track(&mut self, old: &str, new: &str) {
+        if let Some(routing) = self.tracks.remove(old) {
+            self.tracks.insert(new.to_string(), routing);
+        }
+    }
+
+    /// Check every reference in the graph and that buses don't feed back into themselves
+    pub fn validate(&self) -> Result<()> {
+        if self.buses.contains_key(MASTER_BUS) {
+            bail!("'{}' is reserved for the master bus", MASTER_BUS);
+        }
+        if !(self.headroom_db >= 0.0 && self.headroom_db.is_finite()) {
+            bail!("Headroom must be a non-negative number of dB, got {}", self.headroom_db);
+        }
+        for (name, track) in &self.tracks {
+            if !track.gain_db.is_finite() {
+                bail!("Track '{}' must have a finite gain in dB", name);
+            }
+            if !(-1.0..=1.0).contains(&track.pan) {
+                bail!("Track '{}' has pan {}; it must be between -1 and 1", name, track.pan);
+            }
+            if let Some(output) = &track.output {
+                self.check_bus(output)?;
+            }
+            for bus in track.sends.keys() {
+                if bus == MASTER_BUS {
+                    bail!("Track '{}' can't send to the master bus", name);
+                }
+                self.check_bus(bus)?;
+            }
+        }
+        for bus in self.buses.values() {
+            if let Some(output) = &bus.output {
+                self.check_bus(output)?;
+            }
+        }
+        self.bus_order().map(|_| ())
+    }
+
+    /// Bus names in processing order: every bus comes before the bus it feeds.
+    /// The master bus is not included.
+    pub fn bus_order(&self) -> Result<Vec<&str>> {
+        let mut order: Vec<&str> = Vec::new();
+        for name in self.buses.keys() {
+            // Walk the output chain, collecting buses not yet ordered
+            let mut chain: Vec<&str> = Vec::new();
+            let mut current = Some(name.as_str());
+            while let Some(bus) = current.filter(|b| *b != MASTER_BUS && !order.contains(b)) {
+                if chain.contains(&bus) {
+                    bail!("Bus '{}' feeds back into itself", bus);
+                }
+                chain.push(bus);
+                current = self.buses.get(bus).and_then(|b| b.output.as_deref());
+            }
+            order.extend(chain.into_iter().rev());
+        }
+        // Outputs were pushed before their inputs; flip so inputs come first
+        order.reverse();
+        Ok(order)
+    }
+
+    fn check_bus(&self, bus: &str) -> Result<()> {
+        if bus != MASTER_BUS && !self.buses.contains_key(bus) {
+            bail!("Bus '{}' not found", bus);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_routing_serialization() {
+        let mut mixer = Mixer::new();
+        mixer.add_bus("reverb", Bus::default()).unwrap();
+        mixer.set_send("lead", "reverb", -6.0).unwrap();
+        assert!(mixer.add_bus("reverb", Bus::default()).is_err());
+        assert!(mixer.add_bus(MASTER_BUS, Bus::default()).is_err());
+
+        let json = serde_json::to_value(&mixer).unwrap();
+        assert_eq!(json, serde_json::json!({
+            "buses": { "reverb": {} },
+            "tracks": { "lead": { "sends": { "reverb": -6.0 } } }
+        }));
+        assert_eq!(serde_json::from_value::<Mixer>(json).unwrap(), mixer);
+        assert!(Mixer::new().is_default());
+
+        mixer.set_track_speakers("pad", Speakers::Rear);
+        let json = serde_json::to_value(&mixer).unwrap();
+        assert_eq!(json["tracks"]["pad"], serde_json::json!({ "speakers": "rear" }));
+        assert_eq!(serde_json::from_value::<Mixer>(json).unwrap(), mixer);
+    }
+
+    #[test]
+    fn test_bus_order_and_cycles() {
+        let mut mixer = Mixer::new();
+        mixer.add_bus("drums", Bus { output: Some("group".to_string()), ..Bus::default() }).unwrap();
+        mixer.add_bus("group", Bus::default()).unwrap();
+        mixer.add_bus("a_fx", Bus { output: Some("drums".to_string()), ..Bus::default() }).unwrap();
+        assert_eq!(mixer.bus_order().unwrap(), vec!["a_fx", "drums", "group"]);
+        assert!(mixer.validate().is_ok());
+
+        mixer.buses.get_mut("group").unwrap().output = Some("a_fx".to_string());
+        assert!(mixer.validate().is_err());
+    }
+
+    #[test]
+    fn test_remove_bus_clears_references() {
+        let mut mixer = Mixer::new();
+        mixer.add_bus("delay", Bus::default()).unwrap();
+        mixer.set_send("lead", "delay", 0.0).unwrap();
+        mixer.set_track_output("bass", Some("delay")).unwrap();
+        assert!(mixer.set_send("lead", "missing", 0.0).is_err());
+
+        mixer.remove_bus("delay").unwrap();
+        assert!(mixer.track("lead").sends.is_empty());
+        assert_eq!(mixer.track("bass").output, None);
+        assert!(mixer.validate().is_ok());
+    }
+
+    #[test]
+    fn test_pan() {
+        let mut mixer = Mixer::new();
+        mixer.set_pan("lead", -0.5).unwrap();
+        assert!(mixer.set_pan("lead", 2.0).is_err());
+        assert_eq!(serde_json::to_value(&mixer).unwrap()["tracks"]["lead"], serde_json::json!({ "pan": -0.5 }));
+        assert!(mixer.validate().is_ok());
+
+        mixer.tracks.get_mut("lead").unwrap().pan = f64::NAN;
+        assert!(mixer.validate().is_err());
+    }
+
+    #[test]
+    fn test_gain_staging() {
+        let mut mixer = Mixer::new();
+        mixer.set_track_gain("lead", -4.5).unwrap();
+        assert!(mixer.set_track_gain("lead", f64::INFINITY).is_err());
+        mixer.set_headroom(6.0).unwrap();
+        assert!(mixer.set_headroom(-1.0).is_err());
+        let json = serde_json::to_value(&mixer).unwrap();
+        assert_eq!(json, serde_json::json!({ "tracks": { "lead": { "gain_db": -4.5 } }, "headroom_db": 6.0 }));
+        assert_eq!(serde_json::from_value::<Mixer>(json).unwrap(), mixer);
+        assert!(mixer.validate().is_ok());
+
+        mixer.tracks.get_mut("lead").unwrap().gain_db = f64::NAN;
+        assert!(mixer.validate().is_err());
+    }
+
+    #[test]
+    fn test_mute_and_solo() {
+        let mut mixer = Mixer::new();
+        mixer.set_track_gain("lead", -6.0).unwrap();
+        assert!(mixer.is_audible("lead") && mixer.is_audible("bass"));
+        assert!((mixer.track_level("lead") - 0.501).abs() < 0.001);
+        assert_eq!(mixer.track_level("bass"), 1.0);
+
+        mixer.set_mute("bass", true);
+        assert!(!mixer.is_audible("bass"));
+        assert_eq!(mixer.track_level("bass"), 0.0);
+
+        // Soloing silences every other track, and a muted solo stays muted
+        mixer.set_solo("drums", true);
+        assert!(mixer.is_audible("drums") && !mixer.is_audible("lead"));
+        mixer.set_solo("bass", true);
+        assert!(!mixer.is_audible("bass"));
+
+        let json = serde_json::to_value(&mixer).unwrap();
+        assert_eq!(json["tracks"]["bass"], serde_json::json!({ "mute": true, "solo": true }));
+        assert_eq!(json["tracks"]["lead"], serde_json::json!({ "gain_db": -6.0 }));
+        assert_eq!(serde_json::from_value::<Mixer>(json).unwrap(), mixer);
+    }
+}
@@ -0,0 +1,82 @@
+use std::fmt;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use crate::{Controller, Key, Note};
+
+/// A change made to a song through [`crate::DawFile`]'s editing methods.
+/// Edits made directly to public fields aren't reported.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProjectChange {
+    TitleChanged { title: String },
+    BpmChanged { from: u32, to: u32 },
+    GrooveChanged,
+    TuningChanged,
+    SeedChanged { seed: u64 },
+    KeyChanged { key: Option<Key> },
+    MixdownChanged,
+    InstrumentAdded { id: String },
+    InstrumentRemoved { id: String },
+    InstrumentRenamed { from: String, to: String },
+    /// An instrument's settings (such as its tuning) changed
+    InstrumentChanged { id: String },
+    EventAdded { time: String, instrument: String },
+    EventRemoved { time: String, instrument: String },
+    /// The event previously at `time` on `instrument` was replaced
+    EventUpdated { time: String, instrument: String },
+    NoteAdded { time: String, instrument: String, note: Note },
+    NoteRemoved { time: String, instrument: String, note: Note },
+    NoteUpdated { time: String, instrument: String, from: Note, to: Note },
+    ControlAdded { time: String, instrument: String, controller: Controller },
+    ChordsChanged,
+    LyricsChanged,
+    /// Many events or controls changed at once (batch edits, quantize, bar editing);
+    /// listeners should re-read them
+    EventsChanged,
+}
+
+/// Channels subscribed to a song's changes. Cloning a song doesn't copy its subscribers.
+#[derive(Default)]
+pub struct Observers {
+    senders: Vec<Sender<ProjectChange>>,
+}
+
+impl Observers {
+    pub(crate) fn subscribe(&mut self) -> Receiver<ProjectChange> {
+        let (sender, receiver) = channel();
+        self.senders.push(sender);
+        receiver
+    }
+
+    /// Send a change to every subscriber, dropping those whose receiver is gone
+    pub(crate) fn notify(&mut self, change: ProjectChange) {
+        self.senders.retain(|sender| sender.send(change.clone()).is_ok());
+    }
+}
+
+impl Clone for Observers {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl fmt::Debug for Observers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Observers({})", self.senders.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dropped_receivers_are_removed() {
+        let mut observers = Observers::default();
+        let kept = observers.subscribe();
+        drop(observers.subscribe());
+
+        observers.notify(ProjectChange::MixdownChanged);
+        assert_eq!(observers.senders.len(), 1);
+        assert_eq!(kept.try_recv(), Ok(ProjectChange::MixdownChanged));
+        assert_eq!(format!("{:?}", observers.clone()), "Observers(0)");
+    }
+}
@@ -0,0 +1,92 @@
+use std::collections::BTreeMap;
+use crate::pitch::Pitch;
+use crate::Note;
+
+/// What to do when a note starts before an earlier note of the same pitch has ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverlapPolicy {
+    /// Shorten the earlier note so it ends where the later one starts (legato)
+    Truncate,
+    /// Join the notes into one, from the first onset to the last end
+    Merge,
+    /// Leave both notes sounding
+    KeepBoth,
+}
+
+/// Resolve overlaps between notes given as `(position in 32nds, note)`. Only notes of the
+/// same pitch overlap; notes that start where another ends don't. Returns the notes
+/// ordered by pitch and position, and how many overlaps were resolved.
+pub fn resolve(notes: &[(u32, Note)], policy: OverlapPolicy) -> (Vec<(u32, Note)>, usize) {
+    if policy == OverlapPolicy::KeepBoth {
+        return (notes.to_vec(), 0);
+    }
+
+    let mut by_pitch: BTreeMap<Pitch, Vec<(u32, Note)>> = BTreeMap::new();
+    for (position, note) in notes {
+        by_pitch.entry(note.pitch).or_default().push((*position, note.clone()));
+    }
+
+    let mut resolved = Vec::with_capacity(notes.len());
+    let mut count = 0;
+    for mut group in by_pitch.into_values() {
+        group.sort_by_key(|(position, _)| *position);
+        let mut group = group.into_iter();
+        let Some(mut current) = group.next() else { continue };
+        for next in group {
+            let end = current.0 + current.1.duration;
+            if next.0 >= end {
+                resolved.push(std::mem::replace(&mut current, next));
+                continue;
+            }
+            count += 1;
+            // Notes starting together can't be truncated, so they're always merged
+            if policy == OverlapPolicy::Merge || next.0 == current.0 {
+                current.1.duration = end.max(next.0 + next.1.duration) - current.0;
+            } else {
+                current.1.duration = next.0 - current.0;
+                resolved.push(std::mem::replace(&mut current, next));
+            }
+        }
+        resolved.push(current);
+    }
+    (resolved, count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pitch::Tone;
+
+    fn notes(spans: &[(u32, u32)]) -> Vec<(u32, Note)> {
+        spans.iter().map(|&(position, duration)| (position, Note::new(Pitch::new(Tone::C, 4), duration))).collect()
+    }
+
+    fn spans(notes: &[(u32, Note)]) -> Vec<(u32, u32)> {
+        notes.iter().map(|(position, note)| (*position, note.duration)).collect()
+    }
+
+    #[test]
+    fn test_policies() {
+        let overlapping = notes(&[(8, 8), (0, 16), (16, 4), (24, 8)]);
+
+        let (merged, count) = resolve(&overlapping, OverlapPolicy::Merge);
+        assert_eq!((spans(&merged), count), (vec![(0, 16), (16, 4), (24, 8)], 1));
+
+        let (truncated, count) = resolve(&overlapping, OverlapPolicy::Truncate);
+        assert_eq!((spans(&truncated), count), (vec![(0, 8), (8, 8), (16, 4), (24, 8)], 1));
+
+        let (kept, count) = resolve(&overlapping, OverlapPolicy::KeepBoth);
+        assert_eq!((kept, count), (overlapping, 0));
+    }
+
+    #[test]
+    fn test_only_same_pitch_overlaps() {
+        let mut chord = notes(&[(0, 8)]);
+        chord.push((4, Note::new(Pitch::new(Tone::E, 4), 8)));
+        assert_eq!(resolve(&chord, OverlapPolicy::Merge).1, 0);
+
+        // Notes starting together are merged even when truncating
+        let (unison, count) = resolve(&notes(&[(0, 4), (0, 8)]), OverlapPolicy::Truncate);
+        assert_eq!((spans(&unison), count), (vec![(0, 8)], 1));
+    }
+}
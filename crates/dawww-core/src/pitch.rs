@@ -2,11 +2,19 @@
 
 use std::cmp::Ordering;
 use std::fmt;
+use std::str::FromStr;
+use std::path::Path;
 use serde::{Serialize, Deserialize};
+use schemars::JsonSchema;
+use anyhow::{Result, anyhow, bail};
+use crate::UnknownFields;
 
-pub static OCTAVE_MAX: u16 = 8;
+pub const OCTAVE_MAX: u16 = 8;
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, Serialize, Deserialize)]
+/// Standard concert pitch for A4, in Hz
+pub const A4_FREQUENCY: f64 = 440.0;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, Serialize, Deserialize, JsonSchema)]
 pub enum Tone {
     C,
     Cs,
@@ -57,6 +65,30 @@ impl Tone {
         }
     }
 
+    /// Tone for a note name like `C`, `F#` or `Bb`
+    pub fn from_name(name: &str) -> Option<Tone> {
+        Some(match name {
+            "C" => Tone::C,
+            "C#" | "Db" => Tone::Cs,
+            "D" => Tone::D,
+            "D#" | "Eb" => Tone::Ds,
+            "E" => Tone::E,
+            "F" => Tone::F,
+            "F#" | "Gb" => Tone::Fs,
+            "G" => Tone::G,
+            "G#" | "Ab" => Tone::Gs,
+            "A" => Tone::A,
+            "A#" | "Bb" => Tone::As,
+            "B" => Tone::B,
+            _ => return None,
+        })
+    }
+
+    /// Whether the tone is a sharp, played on a black piano key
+    pub fn is_black_key(&self) -> bool {
+        matches!(self, Tone::Cs | Tone::Ds | Tone::Fs | Tone::Gs | Tone::As)
+    }
+
     pub fn as_str(&self) -> &str {
         match self {
             Tone::C => "C",
@@ -75,92 +107,667 @@ impl Tone {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, Serialize, Deserialize)]
+/// Largest cent deviation a pitch can carry, either way
+pub const MAX_CENTS: i16 = 100;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, Serialize, Deserialize, JsonSchema)]
 pub struct Pitch {
     pub tone: Tone,
     pub octave: u16,
+    /// Deviation from the equal-tempered pitch in whole cents, for microtonal music
+    /// and detuned sounds. -100 to 100.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    #[schemars(range(min = -100, max = 100))]
+    pub cents: i16,
+}
+
+fn is_zero(cents: &i16) -> bool {
+    *cents == 0
 }
 
 impl Pitch {
+    pub const LOWEST: Pitch = Pitch { tone: Tone::C, octave: 0, cents: 0 };
+    pub const HIGHEST: Pitch = Pitch { tone: Tone::B, octave: OCTAVE_MAX, cents: 0 };
+
     pub fn new(tone: Tone, octave: u16) -> Pitch {
-        Pitch { tone, octave }
+        Pitch { tone, octave, cents: 0 }
     }
 
+    /// The same pitch raised (or lowered, for negative values) by `cents`, clamped to
+    /// [`MAX_CENTS`] either way
+    pub fn with_cents(self, cents: i16) -> Pitch {
+        Pitch { cents: cents.clamp(-MAX_CENTS, MAX_CENTS), ..self }
+    }
+
+    /// Every pitch from C0 to B8, lowest first
     pub fn all() -> Vec<Pitch> {
-        let mut pitches = vec![];
-        for octave in 0..=OCTAVE_MAX {
-            pitches.push(Pitch::new(Tone::C, octave));
-            pitches.push(Pitch::new(Tone::Cs, octave));
-            pitches.push(Pitch::new(Tone::D, octave));
-            pitches.push(Pitch::new(Tone::Ds, octave));
-            pitches.push(Pitch::new(Tone::E, octave));
-            pitches.push(Pitch::new(Tone::F, octave));
-            pitches.push(Pitch::new(Tone::Fs, octave));
-            pitches.push(Pitch::new(Tone::G, octave));
-            pitches.push(Pitch::new(Tone::Gs, octave));
-            pitches.push(Pitch::new(Tone::A, octave));
-            pitches.push(Pitch::new(Tone::As, octave));
-            pitches.push(Pitch::new(Tone::B, octave));
-        }
-        pitches
+        Pitch::range(Pitch::LOWEST, Pitch::HIGHEST).collect()
     }
 
+    /// Chromatic steps from `low` up to and including `high`, ignoring cents. Empty when
+    /// `high` is below `low`; use `.rev()` to walk downward.
+    pub fn range(low: Pitch, high: Pitch) -> impl DoubleEndedIterator<Item = Pitch> + ExactSizeIterator {
+        (low.to_midi()..=high.to_midi()).map(|note| Pitch::from_midi(note).expect("note within C0..B8"))
+    }
+
+    /// The semitone above, or `None` at B8
     pub fn next(&self) -> Option<Pitch> {
-        if self.tone == Tone::B && self.octave == OCTAVE_MAX {
-            return None;
-        }
-        if self.tone == Tone::B {
-            return Some(Pitch::new(Tone::C, self.octave + 1));
-        }
-        Some(Pitch::new(
-            Tone::from_index(self.tone.index() + 1),
-            self.octave,
-        ))
+        Pitch::from_midi(self.to_midi() + 1)
     }
 
+    /// The semitone below, or `None` at C0
     pub fn prev(&self) -> Option<Pitch> {
-        if self.tone == Tone::C && self.octave == 0 {
-            return None;
-        }
-        if self.tone == Tone::C {
-            return Some(Pitch::new(Tone::B, self.octave - 1));
-        }
-        Some(Pitch::new(
-            Tone::from_index(self.tone.index() - 1),
-            self.octave,
-        ))
+        Pitch::from_midi(self.to_midi() - 1)
+    }
+
+    pub fn is_black_key(&self) -> bool {
+        self.tone.is_black_key()
     }
 
     pub fn frequency(&self, octave: u16) -> f64 {
-        // Calculate the number of half steps from A4 (440 Hz)
-        let half_steps_from_a4 = (octave as i32 - 4) * 12 + self.tone.index() as i32 - 9;
+        Pitch { octave, ..*self }.frequency_at(A4_FREQUENCY)
+    }
+
+    /// Equal-tempered frequency with A4 tuned to `a4` Hz (e.g. 432 or 442)
+    pub fn frequency_at(&self, a4: f64) -> f64 {
+        // Calculate the number of half steps from A4, including the cent deviation
+        let half_steps_from_a4 = (self.octave as i32 - 4) * 12 + self.tone.index() as i32 - 9;
+        let half_steps = half_steps_from_a4 as f64 + self.cents as f64 / 100.0;
 
-        // Calculate the frequency using the formula: 440 * 2^(n/12)
-        440.0 * 2_f64.powf(half_steps_from_a4 as f64 / 12.0)
+        // Calculate the frequency using the formula: a4 * 2^(n/12)
+        a4 * 2_f64.powf(half_steps / 12.0)
     }
 
     pub fn as_str(&self) -> String {
         format!("{}{}", self.tone.as_str(), self.octave)
     }
+
+    /// MIDI note number, ignoring cents (C4 = 60, so C0..B8 is 12..=119)
+    pub fn to_midi(&self) -> u8 {
+        ((self.octave + 1) * 12 + self.tone.index()) as u8
+    }
+
+    /// Pitch for a MIDI note number, or `None` outside C0..B8 (12..=119)
+    pub fn from_midi(note: u8) -> Option<Pitch> {
+        let octave = (note / 12).checked_sub(1)?;
+        if octave as u16 > OCTAVE_MAX {
+            return None;
+        }
+        Some(Pitch::new(Tone::from_index((note % 12) as u16), octave as u16))
+    }
+
+    /// Shift by a number of semitones, or `None` if the result would fall outside C0..B8
+    pub fn transpose(&self, semitones: i32) -> Option<Pitch> {
+        let note = u8::try_from(self.to_midi() as i32 + semitones).ok()?;
+        Pitch::from_midi(note).map(|pitch| pitch.with_cents(self.cents))
+    }
+
+    /// Shift by a number of semitones, moving the result by whole octaves to keep it in C0..B8
+    pub fn transpose_clamped(&self, semitones: i32) -> Pitch {
+        let index = self.octave as i32 * 12 + self.tone.index() as i32 + semitones;
+        let octave = (index.div_euclid(12)).clamp(0, OCTAVE_MAX as i32);
+        Pitch::new(Tone::from_index(index.rem_euclid(12) as u16), octave as u16).with_cents(self.cents)
+    }
+
+    /// Number of semitones from `other` up to this pitch (negative if this pitch is lower),
+    /// ignoring cent deviations
+    pub fn semitones_from(&self, other: &Pitch) -> i32 {
+        self.to_midi() as i32 - other.to_midi() as i32
+    }
+
+    /// Number of semitones from this pitch up to `other` (negative if `other` is lower),
+    /// ignoring cent deviations
+    pub fn interval_to(&self, other: &Pitch) -> i32 {
+        other.semitones_from(self)
+    }
+
+    /// Distance from `other` up to this pitch in semitones, including cent deviations
+    pub fn interval_from(&self, other: &Pitch) -> f64 {
+        self.semitones_from(other) as f64 + (self.cents - other.cents) as f64 / 100.0
+    }
 }
 
+/// `C#4`, with any cent deviation after it: `A4+14c`, `E3-50c`
 impl fmt::Display for Pitch {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}{}", self.tone.as_str(), self.octave)
+        write!(f, "{}{}", self.tone.as_str(), self.octave)?;
+        if self.cents != 0 {
+            write!(f, "{:+}c", self.cents)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses what `Display` writes, plus flats: `C4`, `F#3`, `Bb2`, `A4+14c`. Octaves may be
+/// negative in the text (`C-1`) but must land in C0..B8.
+impl FromStr for Pitch {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || anyhow!("Invalid pitch '{}'", s);
+        let split = s.find(|c: char| c.is_ascii_digit() || c == '-').ok_or_else(invalid)?;
+        let (name, rest) = s.split_at(split);
+        let tone = Tone::from_name(name).ok_or_else(invalid)?;
+
+        // The octave is an optionally negative number, followed by an optional `+Nc` / `-Nc`
+        let digits_start = usize::from(rest.starts_with('-'));
+        let octave_end = rest[digits_start..]
+            .find(|c: char| !c.is_ascii_digit())
+            .map_or(rest.len(), |i| i + digits_start);
+        let octave: i32 = rest[..octave_end].parse().map_err(|_| invalid())?;
+        let cents = match &rest[octave_end..] {
+            "" => 0,
+            suffix => suffix
+                .strip_suffix('c')
+                .filter(|c| c.starts_with(['+', '-']))
+                .and_then(|c| c.parse::<i16>().ok())
+                .filter(|c| c.abs() <= MAX_CENTS)
+                .ok_or_else(invalid)?,
+        };
+        if !(0..=OCTAVE_MAX as i32).contains(&octave) {
+            bail!("Pitch '{}' is outside C0..B{}", s, OCTAVE_MAX);
+        }
+        Ok(Pitch::new(tone, octave as u16).with_cents(cents))
+    }
+}
+
+/// Lower pitches first; equal notes are ordered by their cent deviation
+impl Ord for Pitch {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.to_midi(), self.cents).cmp(&(other.to_midi(), other.cents))
     }
 }
 
 impl PartialOrd for Pitch {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        if self.octave < other.octave {
-            return Some(Ordering::Less);
-        } else if self.octave > other.octave {
-            return Some(Ordering::Greater);
-        } else if self.tone.index() < other.tone.index() {
-            return Some(Ordering::Less);
-        } else if self.tone.index() > other.tone.index() {
-            return Some(Ordering::Greater);
-        }
-        Some(Ordering::Equal)
+        Some(self.cmp(other))
+    }
+}
+
+/// A span of keys from `low` up to and including `high`. Detuned pitches belong to the key
+/// they're detuned from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PitchRange {
+    pub low: Pitch,
+    pub high: Pitch,
+}
+
+impl PitchRange {
+    /// The range between two pitches, given in either order
+    pub fn new(a: Pitch, b: Pitch) -> Self {
+        Self { low: a.min(b), high: a.max(b) }
+    }
+
+    /// Every pitch from C0 to B8
+    pub fn all() -> Self {
+        Self::new(Pitch::LOWEST, Pitch::HIGHEST)
+    }
+
+    pub fn contains(&self, pitch: &Pitch) -> bool {
+        (self.low.to_midi()..=self.high.to_midi()).contains(&pitch.to_midi())
+    }
+
+    /// The keys in both ranges, if they share any
+    pub fn intersection(&self, other: &PitchRange) -> Option<PitchRange> {
+        let low = self.low.max(other.low);
+        let high = self.high.min(other.high);
+        (low.to_midi() <= high.to_midi()).then_some(PitchRange { low, high })
+    }
+
+    /// The smallest range covering both
+    pub fn union(&self, other: &PitchRange) -> PitchRange {
+        PitchRange { low: self.low.min(other.low), high: self.high.max(other.high) }
+    }
+
+    /// Every key in the range, lowest first
+    pub fn pitches(&self) -> impl DoubleEndedIterator<Item = Pitch> + ExactSizeIterator {
+        Pitch::range(self.low, self.high)
+    }
+}
+
+/// How pitches map to frequencies: a scale of steps in cents that repeats every period,
+/// laid out across the keys by a [`KeyboardMapping`]. Follows the Scala (.scl/.kbm) model,
+/// so any Scala tuning can be loaded; the default is 12-tone equal temperament at A4 = 440 Hz.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Tuning {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub name: String,
+    /// Cents above the first degree for each further degree. The last is the period the
+    /// scale repeats at, usually 1200 (an octave). Omitted for 12-tone equal temperament.
+    #[serde(default = "twelve_tone_steps", skip_serializing_if = "is_twelve_tone")]
+    pub steps: Vec<f64>,
+    #[serde(default, skip_serializing_if = "KeyboardMapping::is_default")]
+    pub mapping: KeyboardMapping,
+    #[serde(flatten)]
+    pub extra: UnknownFields,
+}
+
+/// Which scale degree each key plays, and which key is tuned to a reference frequency
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct KeyboardMapping {
+    /// MIDI note number of the key playing the scale's first degree
+    #[serde(default = "default_middle_note", skip_serializing_if = "is_default_middle_note")]
+    pub middle_note: i32,
+    /// MIDI note number of the key tuned to `reference_frequency`
+    #[serde(default = "default_reference_note", skip_serializing_if = "is_default_reference_note")]
+    pub reference_note: i32,
+    #[serde(default = "default_reference_frequency", skip_serializing_if = "is_default_reference_frequency")]
+    pub reference_frequency: f64,
+    /// Scale degree for each key from `middle_note` up, repeating; `null` leaves a key
+    /// silent. When empty, keys play consecutive degrees.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keys: Vec<Option<u32>>,
+    /// Degree one repetition of `keys` moves up by; defaults to the number of scale degrees
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub octave_degree: Option<u32>,
+    #[serde(flatten)]
+    pub extra: UnknownFields,
+}
+
+fn default_middle_note() -> i32 {
+    60
+}
+
+fn is_default_middle_note(note: &i32) -> bool {
+    *note == default_middle_note()
+}
+
+fn default_reference_note() -> i32 {
+    69
+}
+
+fn is_default_reference_note(note: &i32) -> bool {
+    *note == default_reference_note()
+}
+
+fn default_reference_frequency() -> f64 {
+    A4_FREQUENCY
+}
+
+fn is_default_reference_frequency(frequency: &f64) -> bool {
+    *frequency == A4_FREQUENCY
+}
+
+fn twelve_tone_steps() -> Vec<f64> {
+    Tuning::equal_temperament(12).steps
+}
+
+fn is_twelve_tone(steps: &[f64]) -> bool {
+    steps == twelve_tone_steps()
+}
+
+impl Default for KeyboardMapping {
+    fn default() -> Self {
+        Self {
+            middle_note: default_middle_note(),
+            reference_note: default_reference_note(),
+            reference_frequency: default_reference_frequency(),
+            keys: Vec::new(),
+            octave_degree: None,
+            extra: UnknownFields::new(),
+        }
+    }
+}
+
+impl Default for Tuning {
+    fn default() -> Self {
+        Self::equal_temperament(12)
+    }
+}
+
+/// Non-comment lines of a Scala file, trimmed
+fn scala_lines(text: &str) -> impl Iterator<Item = &str> {
+    text.lines().map(str::trim_end).filter(|line| !line.starts_with('!'))
+}
+
+impl Tuning {
+    /// Equal divisions of the octave, e.g. 12 for standard tuning or 19 for 19-TET
+    pub fn equal_temperament(divisions: u32) -> Self {
+        let name = if divisions == 12 { String::new() } else { format!("{}-TET", divisions) };
+        Self::from_cents(name, (1..=divisions).map(|i| i as f64 * 1200.0 / divisions as f64).collect())
+    }
+
+    /// 5-limit just intonation, with C as the first degree
+    pub fn just_intonation() -> Self {
+        Self::from_ratios("Just intonation", &[
+            (16, 15), (9, 8), (6, 5), (5, 4), (4, 3), (45, 32), (3, 2), (8, 5), (5, 3), (9, 5), (15, 8), (2, 1),
+        ])
+    }
+
+    /// Pythagorean tuning built from pure fifths, with C as the first degree
+    pub fn pythagorean() -> Self {
+        Self::from_ratios("Pythagorean", &[
+            (256, 243), (9, 8), (32, 27), (81, 64), (4, 3), (729, 512), (3, 2), (128, 81), (27, 16), (16, 9), (243, 128), (2, 1),
+        ])
+    }
+
+    fn from_cents(name: String, steps: Vec<f64>) -> Self {
+        Self { name, steps, mapping: KeyboardMapping::default(), extra: UnknownFields::new() }
+    }
+
+    fn from_ratios(name: &str, ratios: &[(u32, u32)]) -> Self {
+        let steps = ratios.iter().map(|&(n, d)| ratio_to_cents(n as f64 / d as f64)).collect();
+        Self::from_cents(name.to_string(), steps)
+    }
+
+    /// Parse a Scala scale (.scl): a description line, the number of degrees, then one pitch
+    /// per line as cents (containing a `.`) or a ratio (`3/2`, `2`)
+    pub fn parse_scl(text: &str) -> Result<Self> {
+        let mut lines = scala_lines(text);
+        let name = lines.next().ok_or_else(|| anyhow!("Scala file is empty"))?.trim().to_string();
+        let count: usize = lines.next()
+            .and_then(|line| line.split_whitespace().next())
+            .and_then(|n| n.parse().ok())
+            .ok_or_else(|| anyhow!("Scala file is missing its number of notes"))?;
+
+        let steps = lines.take(count)
+            .map(|line| {
+                let value = line.split_whitespace().next().unwrap_or("");
+                parse_scala_pitch(value).ok_or_else(|| anyhow!("Invalid Scala pitch '{}'", value))
+            })
+            .collect::<Result<Vec<f64>>>()?;
+        if steps.len() != count {
+            bail!("Scala file lists {} notes but has {}", count, steps.len());
+        }
+        let tuning = Self::from_cents(name, steps);
+        tuning.validate()?;
+        Ok(tuning)
+    }
+
+    pub fn load_scl(path: &Path) -> Result<Self> {
+        Self::parse_scl(&std::fs::read_to_string(path)?)
+    }
+
+    /// The same tuning with A4 moved to `a4` Hz, keeping every interval
+    pub fn with_a4(mut self, a4: f64) -> Result<Self> {
+        if !(a4.is_finite() && a4 > 0.0) {
+            bail!("A4 frequency must be positive, got {}", a4);
+        }
+        let current = self.a4().ok_or_else(|| anyhow!("A4 isn't mapped to a scale degree"))?;
+        self.mapping.reference_frequency *= a4 / current;
+        Ok(self)
+    }
+
+    /// The frequency A4 plays at
+    pub fn a4(&self) -> Option<f64> {
+        self.frequency(&Pitch::new(Tone::A, 4))
+    }
+
+    pub fn with_mapping(mut self, mapping: KeyboardMapping) -> Self {
+        self.mapping = mapping;
+        self
+    }
+
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        match self.steps.last() {
+            None => bail!("A tuning needs at least one step"),
+            Some(&period) if !(period.is_finite() && period > 0.0) => {
+                bail!("A tuning's period must be positive, got {} cents", period)
+            }
+            _ => {}
+        }
+        if !(self.mapping.reference_frequency.is_finite() && self.mapping.reference_frequency > 0.0) {
+            bail!("Reference frequency must be positive, got {}", self.mapping.reference_frequency);
+        }
+        if self.key_cents(self.mapping.reference_note).is_none() {
+            bail!("Reference note {} isn't mapped to a scale degree", self.mapping.reference_note);
+        }
+        Ok(())
+    }
+
+    /// Cents of a scale degree above the first, counting on through further periods, or
+    /// `None` for a scale with no steps
+    fn degree_cents(&self, degree: i64) -> Option<f64> {
+        let period = *self.steps.last()?;
+        let size = self.steps.len() as i64;
+        let (periods, step) = (degree.div_euclid(size), degree.rem_euclid(size));
+        let within = if step == 0 { 0.0 } else { self.steps[step as usize - 1] };
+        Some(periods as f64 * period + within)
+    }
+
+    /// Cents of a key above the middle note, or `None` for an unmapped key
+    fn key_cents(&self, key: i32) -> Option<f64> {
+        let offset = (key - self.mapping.middle_note) as i64;
+        let keys = &self.mapping.keys;
+        if keys.is_empty() {
+            return self.degree_cents(offset);
+        }
+        let size = keys.len() as i64;
+        let octave_degree = self.mapping.octave_degree.unwrap_or(self.steps.len() as u32) as i64;
+        let degree = keys[offset.rem_euclid(size) as usize]? as i64;
+        self.degree_cents(offset.div_euclid(size) * octave_degree + degree)
+    }
+
+    /// Frequency of a pitch in Hz, including its cent deviation, or `None` if the mapping
+    /// leaves its key silent
+    pub fn frequency(&self, pitch: &Pitch) -> Option<f64> {
+        let cents = self.key_cents(pitch.to_midi() as i32)? - self.key_cents(self.mapping.reference_note)?;
+        Some(self.mapping.reference_frequency * 2_f64.powf((cents + pitch.cents as f64) / 1200.0))
+    }
+}
+
+impl KeyboardMapping {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Parse a Scala keyboard mapping (.kbm). The first and last note lines are read but
+    /// not enforced: keys outside them keep following the mapping.
+    pub fn parse_kbm(text: &str) -> Result<Self> {
+        let mut values = scala_lines(text)
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| line.split_whitespace().next().unwrap_or(""));
+        let mut next = |what: &str| values.next().ok_or_else(|| anyhow!("Keyboard mapping is missing its {}", what));
+        let int = |value: &str, what: &str| value.parse::<i32>().map_err(|_| anyhow!("Invalid {} '{}'", what, value));
+
+        let size = int(next("map size")?, "map size")?;
+        next("first note")?;
+        next("last note")?;
+        let middle_note = int(next("middle note")?, "middle note")?;
+        let reference_note = int(next("reference note")?, "reference note")?;
+        let frequency = next("reference frequency")?;
+        let reference_frequency = frequency.parse::<f64>()
+            .map_err(|_| anyhow!("Invalid reference frequency '{}'", frequency))?;
+        let octave_degree = int(next("octave degree")?, "octave degree")?;
+
+        let mut keys = Vec::new();
+        for _ in 0..size.max(0) {
+            // Scala lets trailing keys be left out; they're unmapped
+            let key = match values.next() {
+                None | Some("x") | Some("X") => None,
+                Some(degree) => Some(degree.parse::<u32>().map_err(|_| anyhow!("Invalid scale degree '{}'", degree))?),
+            };
+            keys.push(key);
+        }
+
+        Ok(Self {
+            middle_note,
+            reference_note,
+            reference_frequency,
+            keys,
+            octave_degree: (size > 0 && octave_degree > 0).then_some(octave_degree as u32),
+            extra: UnknownFields::new(),
+        })
+    }
+
+    pub fn load_kbm(path: &Path) -> Result<Self> {
+        Self::parse_kbm(&std::fs::read_to_string(path)?)
+    }
+}
+
+fn ratio_to_cents(ratio: f64) -> f64 {
+    1200.0 * ratio.log2()
+}
+
+/// A Scala pitch value: cents if it contains a `.`, otherwise a ratio or whole number
+fn parse_scala_pitch(value: &str) -> Option<f64> {
+    if value.contains('.') {
+        return value.parse().ok();
+    }
+    let (numerator, denominator) = value.split_once('/').unwrap_or((value, "1"));
+    let (numerator, denominator): (f64, f64) = (numerator.parse().ok()?, denominator.parse().ok()?);
+    (numerator > 0.0 && denominator > 0.0).then(|| ratio_to_cents(numerator / denominator))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pitch_range() {
+        let low = PitchRange::new(Pitch::new(Tone::G, 4), Pitch::new(Tone::C, 4));
+        assert_eq!(low.low, Pitch::new(Tone::C, 4));
+        assert!(low.contains(&Pitch::new(Tone::G, 4).with_cents(30)));
+        assert!(!low.contains(&Pitch::new(Tone::A, 4)));
+        assert_eq!(low.pitches().len(), 8);
+
+        let high = PitchRange::new(Pitch::new(Tone::E, 4), Pitch::new(Tone::C, 5));
+        assert_eq!(low.intersection(&high), Some(PitchRange::new(Pitch::new(Tone::E, 4), Pitch::new(Tone::G, 4))));
+        assert_eq!(low.union(&high), PitchRange::new(Pitch::new(Tone::C, 4), Pitch::new(Tone::C, 5)));
+        assert_eq!(low.intersection(&PitchRange::new(Pitch::new(Tone::C, 6), Pitch::new(Tone::C, 6))), None);
+        assert!(PitchRange::all().contains(&Pitch::HIGHEST));
+    }
+
+    #[test]
+    fn test_cents() {
+        let a4 = Pitch::new(Tone::A, 4);
+        let quarter_sharp = a4.with_cents(50);
+        assert!((quarter_sharp.frequency(4) - 440.0 * 2_f64.powf(0.5 / 12.0)).abs() < 1e-9);
+        assert_eq!(a4.with_cents(250).cents, MAX_CENTS);
+        assert!(quarter_sharp > a4 && quarter_sharp < Pitch::new(Tone::As, 4));
+        assert_eq!(quarter_sharp.transpose(3).unwrap().cents, 50);
+        assert_eq!(quarter_sharp.interval_from(&Pitch::new(Tone::G, 4).with_cents(-25)), 2.75);
+        assert_eq!(quarter_sharp.to_string(), "A4+50c");
+        assert_eq!(Pitch::new(Tone::E, 3).with_cents(-14).to_string(), "E3-14c");
+    }
+
+    #[test]
+    fn test_parse_and_display_round_trip() {
+        assert_eq!("C#4".parse::<Pitch>().unwrap(), Pitch::new(Tone::Cs, 4));
+        assert_eq!("Bb2".parse::<Pitch>().unwrap(), Pitch::new(Tone::As, 2));
+        assert_eq!("A4+14c".parse::<Pitch>().unwrap(), Pitch::new(Tone::A, 4).with_cents(14));
+        assert_eq!("E3-50c".parse::<Pitch>().unwrap(), Pitch::new(Tone::E, 3).with_cents(-50));
+        for pitch in Pitch::all() {
+            for cents in [0, 33, -100] {
+                let pitch = pitch.with_cents(cents);
+                assert_eq!(pitch.to_string().parse::<Pitch>().unwrap(), pitch);
+            }
+        }
+
+        for bad in ["", "H4", "C", "C#", "C4x", "C4+c", "C4+200c", "C4 ", "4"] {
+            assert!(bad.parse::<Pitch>().is_err(), "{bad}");
+        }
+        let err = "C-1".parse::<Pitch>().unwrap_err().to_string();
+        assert!(err.contains("outside"), "{err}");
+        assert!("C9".parse::<Pitch>().is_err());
+    }
+
+    #[test]
+    fn test_midi_note_numbers() {
+        assert_eq!(Pitch::new(Tone::C, 4).to_midi(), 60);
+        assert_eq!(Pitch::new(Tone::A, 4).to_midi(), 69);
+        assert_eq!(Pitch::from_midi(61), Some(Pitch::new(Tone::Cs, 4)));
+        assert_eq!(Pitch::from_midi(11), None);
+        assert_eq!(Pitch::from_midi(120), None);
+        for pitch in Pitch::all() {
+            assert_eq!(Pitch::from_midi(pitch.to_midi()), Some(pitch));
+        }
+
+        let c4 = Pitch::new(Tone::C, 4);
+        let g3 = Pitch::new(Tone::G, 3);
+        assert_eq!(c4.interval_to(&g3), -5);
+        assert_eq!(g3.interval_to(&c4), 5);
+        assert_eq!(c4.transpose(7), Some(Pitch::new(Tone::G, 4)));
+        assert_eq!(Pitch::new(Tone::B, 8).transpose(1), None);
+        assert_eq!(Pitch::new(Tone::C, 0).transpose(-1), None);
+    }
+
+    #[test]
+    fn test_range_and_keys() {
+        let e4 = Pitch::new(Tone::E, 4);
+        let g4 = Pitch::new(Tone::G, 4);
+        let up: Vec<Pitch> = Pitch::range(e4, g4).collect();
+        assert_eq!(up, vec![e4, Pitch::new(Tone::F, 4), Pitch::new(Tone::Fs, 4), g4]);
+        assert_eq!(Pitch::range(e4, g4).rev().nth(1), Some(Pitch::new(Tone::Fs, 4)));
+        assert_eq!(Pitch::range(g4, e4).count(), 0);
+        assert_eq!(Pitch::all().len(), 12 * 9);
+        assert_eq!(Pitch::LOWEST.prev(), None);
+        assert_eq!(Pitch::HIGHEST.next(), None);
+        assert_eq!(Pitch::new(Tone::B, 3).next(), Some(Pitch::new(Tone::C, 4)));
+
+        let black: Vec<Tone> = Pitch::range(Pitch::new(Tone::C, 4), Pitch::new(Tone::B, 4))
+            .filter(Pitch::is_black_key)
+            .map(|p| p.tone)
+            .collect();
+        assert_eq!(black, vec![Tone::Cs, Tone::Ds, Tone::Fs, Tone::Gs, Tone::As]);
+
+        let mut pitches = vec![g4.with_cents(-10), e4, g4, Pitch::new(Tone::C, 5)];
+        pitches.sort();
+        assert_eq!(pitches, vec![e4, g4.with_cents(-10), g4, Pitch::new(Tone::C, 5)]);
+    }
+
+    #[test]
+    fn test_tunings() {
+        let a4 = Pitch::new(Tone::A, 4);
+        let standard = Tuning::default();
+        for pitch in [a4, Pitch::new(Tone::C, 0), Pitch::new(Tone::Fs, 7).with_cents(20)] {
+            assert!((standard.frequency(&pitch).unwrap() - pitch.frequency(pitch.octave)).abs() < 1e-9);
+        }
+
+        // Just intonation keeps A4 at 440, so C4 is 440 / (5/3) and G4 a pure fifth above it
+        let just = Tuning::just_intonation();
+        let c4 = just.frequency(&Pitch::new(Tone::C, 4)).unwrap();
+        assert!((c4 - 264.0).abs() < 1e-9);
+        assert!((just.frequency(&Pitch::new(Tone::G, 4)).unwrap() - 396.0).abs() < 1e-9);
+        assert!((just.frequency(&Pitch::new(Tone::C, 5)).unwrap() - 528.0).abs() < 1e-9);
+
+        let pythagorean = Tuning::pythagorean();
+        let ratio = pythagorean.frequency(&Pitch::new(Tone::E, 4)).unwrap() / pythagorean.frequency(&Pitch::new(Tone::C, 4)).unwrap();
+        assert!((ratio - 81.0 / 64.0).abs() < 1e-9);
+
+        // In 19-TET each key is one 63.2 cent step
+        let tet19 = Tuning::equal_temperament(19);
+        let b4 = tet19.frequency(&Pitch::new(Tone::As, 4)).unwrap();
+        assert!((b4 - 440.0 * 2_f64.powf(1.0 / 19.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_scala_files() {
+        let scl = "! meantone.scl\n!\nQuarter-comma meantone (partial)\n 3\n!\n 193.157\n 5/4\n 2\n";
+        let tuning = Tuning::parse_scl(scl).unwrap();
+        assert_eq!(tuning.name, "Quarter-comma meantone (partial)");
+        assert_eq!(tuning.steps.len(), 3);
+        assert!((tuning.steps[1] - 386.3137).abs() < 1e-3);
+        assert_eq!(tuning.steps[2], 1200.0);
+        assert!(Tuning::parse_scl("Bad\n2\n100.0\n").is_err());
+        assert!(Tuning::parse_scl("Bad\n1\nfoo\n").is_err());
+
+        // Four keys repeat from C4 with D#4 left silent, so E4 starts the next period.
+        // D4 plays the third degree and is the reference.
+        let kbm = "! test.kbm\n4\n0\n127\n60\n62\n293.66\n3\n0\n1\n2\nx\n";
+        let mapping = KeyboardMapping::parse_kbm(kbm).unwrap();
+        assert_eq!(mapping.keys, vec![Some(0), Some(1), Some(2), None]);
+        let tuning = tuning.with_mapping(mapping);
+        assert!(tuning.validate().is_ok());
+        let cents_above_d4 = |pitch: Pitch| 1200.0 * (tuning.frequency(&pitch).unwrap() / 293.66).log2();
+        assert!(cents_above_d4(Pitch::new(Tone::D, 4)).abs() < 1e-9);
+        assert!((cents_above_d4(Pitch::new(Tone::Cs, 4)) + 193.157).abs() < 1e-3);
+        assert!((cents_above_d4(Pitch::new(Tone::E, 4)) - 813.686).abs() < 1e-3);
+        assert_eq!(tuning.frequency(&Pitch::new(Tone::Ds, 4)), None);
+    }
+
+    #[test]
+    fn test_cents_serialization_is_compatible() {
+        let plain: Pitch = serde_json::from_value(serde_json::json!({ "tone": "C", "octave": 4 })).unwrap();
+        assert_eq!(plain, Pitch::new(Tone::C, 4));
+        assert_eq!(serde_json::to_value(plain).unwrap(), serde_json::json!({ "tone": "C", "octave": 4 }));
+
+        let detuned = serde_json::to_value(plain.with_cents(-7)).unwrap();
+        assert_eq!(detuned["cents"], -7);
+        assert_eq!(serde_json::from_value::<Pitch>(detuned).unwrap(), plain.with_cents(-7));
     }
 }
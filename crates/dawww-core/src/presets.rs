@@ -0,0 +1,88 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use anyhow::{Result, Context};
+use crate::Instrument;
+
+/// File extension used for preset files
+pub const PRESET_EXTENSION: &str = ".preset.json";
+
+/// A named instrument definition (parameters and effect chain) stored outside a project.
+/// Sampler paths are stored as-is and are resolved relative to the project they're imported into.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Preset {
+    pub name: String,
+    pub instrument: Instrument,
+}
+
+impl Preset {
+    pub fn new(name: String, instrument: Instrument) -> Self {
+        Self { name, instrument }
+    }
+
+    /// Write the preset as JSON
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let content = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, content)?;
+        Ok(())
+    }
+
+    /// Read and validate a preset file
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)?;
+        let preset: Preset = serde_json::from_str(&content)
+            .with_context(|| format!("Invalid preset file '{}'", path.display()))?;
+        preset.instrument.validate()?;
+        Ok(preset)
+    }
+}
+
+/// Recursively find every `.preset.json` file under `dir`, sorted by path.
+/// Files that fail to parse are skipped.
+pub fn scan_presets(dir: &Path) -> Result<Vec<(PathBuf, Preset)>> {
+    let mut presets = Vec::new();
+    scan_dir(dir, &mut presets)?;
+    presets.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(presets)
+}
+
+fn scan_dir(dir: &Path, presets: &mut Vec<(PathBuf, Preset)>) -> Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            scan_dir(&path, presets)?;
+        } else if path.to_string_lossy().ends_with(PRESET_EXTENSION) {
+            if let Ok(preset) = Preset::load(&path) {
+                presets.push((path, preset));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instrument::SubtractiveParams;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_save_load_and_scan() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::create_dir(temp_dir.path().join("bass")).unwrap();
+
+        let lead = Preset::new("Lead".to_string(), Instrument::new_subtractive(SubtractiveParams::default()));
+        let kick = Preset::new("Kick".to_string(), Instrument::new_sampler(PathBuf::from("kick.wav")));
+        lead.save(&temp_dir.path().join("lead.preset.json")).unwrap();
+        kick.save(&temp_dir.path().join("bass/kick.preset.json")).unwrap();
+        fs::write(temp_dir.path().join("broken.preset.json"), "{").unwrap();
+        fs::write(temp_dir.path().join("notes.json"), "{}").unwrap();
+
+        assert_eq!(Preset::load(&temp_dir.path().join("lead.preset.json")).unwrap(), lead);
+        assert!(Preset::load(&temp_dir.path().join("broken.preset.json")).is_err());
+
+        let found = scan_presets(temp_dir.path()).unwrap();
+        let names: Vec<&str> = found.iter().map(|(_, p)| p.name.as_str()).collect();
+        assert_eq!(names, vec!["Kick", "Lead"]);
+    }
+}
@@ -0,0 +1,279 @@
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use anyhow::{Result, bail};
+use crate::UnknownFields;
+
+/// A referenced audio file along with the content hash recorded when it was registered
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct SampleAsset {
+    pub hash: String,
+    pub size: u64,
+    /// Sample rate read from the WAV header, if the file is a readable WAV
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sample_rate: Option<u32>,
+    #[serde(flatten)]
+    pub extra: UnknownFields,
+}
+
+/// Problems found when checking registered samples against the filesystem
+#[derive(Debug, Clone, PartialEq)]
+pub enum SampleIssue {
+    /// The file no longer exists and no file with a matching hash could be found
+    Missing { path: String },
+    /// The file no longer exists at its path, but identical content was found elsewhere
+    Moved { path: String, found_at: PathBuf },
+    /// The file exists but its content no longer matches the registered hash
+    Modified { path: String },
+}
+
+/// Tracks every audio file referenced by the project, keyed by the path stored in
+/// instrument parameters (relative to the .daw.json file)
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Default, PartialEq)]
+pub struct SampleRegistry {
+    #[serde(flatten)]
+    assets: BTreeMap<String, SampleAsset>,
+}
+
+impl SampleRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.assets.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.assets.len()
+    }
+
+    /// Get the registered asset for a sample path
+    pub fn get(&self, path: &str) -> Option<&SampleAsset> {
+        self.assets.get(path)
+    }
+
+    /// List all registered sample paths
+    pub fn paths(&self) -> Vec<&str> {
+        self.assets.keys().map(|s| s.as_str()).collect()
+    }
+
+    /// Hash the file at `path` (relative to `base_dir`) and record it in the registry
+    pub fn register(&mut self, base_dir: &Path, path: &str) -> Result<&SampleAsset> {
+        let full_path = base_dir.join(path);
+        if !full_path.is_file() {
+            bail!("Sample file '{}' not found", full_path.display());
+        }
+
+        let asset = SampleAsset {
+            hash: hash_file(&full_path)?,
+            size: std::fs::metadata(&full_path)?.len(),
+            sample_rate: wav_sample_rate(&full_path),
+            extra: UnknownFields::new(),
+        };
+        self.assets.insert(path.to_string(), asset);
+        Ok(&self.assets[path])
+    }
+
+    /// Remove a sample from the registry
+    pub fn unregister(&mut self, path: &str) -> Option<SampleAsset> {
+        self.assets.remove(path)
+    }
+
+    /// Drop every registered sample that is not in `paths`
+    pub fn retain_paths(&mut self, paths: &[String]) {
+        self.assets.retain(|path, _| paths.contains(path));
+    }
+
+    /// List samples whose recorded sample rate differs from `sample_rate` and need resampling
+    pub fn needing_resample(&self, sample_rate: u32) -> Vec<&str> {
+        self.assets.iter()
+            .filter(|(_, asset)| asset.sample_rate.is_some_and(|rate| rate != sample_rate))
+            .map(|(path, _)| path.as_str())
+            .collect()
+    }
+
+    /// Resolve a sample path to a location on disk, verifying it is registered and present
+    pub fn resolve(&self, base_dir: &Path, path: &str) -> Result<PathBuf> {
+        if !self.assets.contains_key(path) {
+            bail!("Sample '{}' is not registered", path);
+        }
+
+        let full_path = base_dir.join(path);
+        if !full_path.is_file() {
+            bail!("Sample '{}' is missing from {}", path, base_dir.display());
+        }
+        Ok(full_path)
+    }
+
+    /// Compare every registered sample against the files under `base_dir`.
+    /// Missing files are searched for by content hash so moved samples can be relinked.
+    pub fn check(&self, base_dir: &Path) -> Vec<SampleIssue> {
+        let mut issues = Vec::new();
+
+        for (path, asset) in &self.assets {
+            let full_path = base_dir.join(path);
+            if full_path.is_file() {
+                match hash_file(&full_path) {
+                    Ok(hash) if hash == asset.hash => {}
+                    _ => issues.push(SampleIssue::Modified { path: path.clone() }),
+                }
+            } else if let Some(found_at) = find_by_hash(base_dir, asset) {
+                issues.push(SampleIssue::Moved { path: path.clone(), found_at });
+            } else {
+                issues.push(SampleIssue::Missing { path: path.clone() });
+            }
+        }
+
+        issues
+    }
+}
+
+/// Compute the hex-encoded SHA-256 of a file's content
+pub fn hash_file(path: &Path) -> Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = [0u8; 8192];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Read the sample rate from a WAV file's `fmt ` chunk, or `None` if the file isn't a WAV
+pub fn wav_sample_rate(path: &Path) -> Option<u32> {
+    let data = std::fs::read(path).ok()?;
+    if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+        return None;
+    }
+
+    let mut offset = 12;
+    while offset + 8 <= data.len() {
+        let chunk_id = &data[offset..offset + 4];
+        let chunk_size = u32::from_le_bytes(data[offset + 4..offset + 8].try_into().ok()?) as usize;
+        let body = offset + 8;
+        if chunk_id == b"fmt " {
+            let rate = data.get(body + 4..body + 8)?;
+            return Some(u32::from_le_bytes(rate.try_into().ok()?));
+        }
+        // Chunks are padded to an even number of bytes
+        offset = body + chunk_size + (chunk_size & 1);
+    }
+    None
+}
+
+/// Search `dir` recursively for a file whose size and content hash match `asset`
+pub fn find_by_hash(dir: &Path, asset: &SampleAsset) -> Option<PathBuf> {
+    let entries = std::fs::read_dir(dir).ok()?;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            if let Some(found) = find_by_hash(&path, asset) {
+                return Some(found);
+            }
+        } else if entry.metadata().map(|m| m.len() == asset.size).unwrap_or(false)
+            && hash_file(&path).map(|h| h == asset.hash).unwrap_or(false)
+        {
+            return Some(path);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    /// Minimal 16-bit mono WAV with a single silent sample
+    fn wav_bytes(sample_rate: u32) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&38u32.to_le_bytes());
+        bytes.extend_from_slice(b"WAVEfmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&sample_rate.to_le_bytes());
+        bytes.extend_from_slice(&(sample_rate * 2).to_le_bytes());
+        bytes.extend_from_slice(&2u16.to_le_bytes());
+        bytes.extend_from_slice(&16u16.to_le_bytes());
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&2u32.to_le_bytes());
+        bytes.extend_from_slice(&[0, 0]);
+        bytes
+    }
+
+    #[test]
+    fn test_register_and_resolve() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::create_dir(temp_dir.path().join("audio")).unwrap();
+        fs::write(temp_dir.path().join("audio/kick.wav"), b"kick").unwrap();
+
+        let mut registry = SampleRegistry::new();
+        let asset = registry.register(temp_dir.path(), "audio/kick.wav").unwrap();
+        assert_eq!(asset.size, 4);
+        assert_eq!(asset.hash.len(), 64);
+
+        let resolved = registry.resolve(temp_dir.path(), "audio/kick.wav").unwrap();
+        assert_eq!(resolved, temp_dir.path().join("audio/kick.wav"));
+        assert!(registry.resolve(temp_dir.path(), "audio/snare.wav").is_err());
+        assert!(registry.register(temp_dir.path(), "audio/snare.wav").is_err());
+    }
+
+    #[test]
+    fn test_check_detects_missing_moved_and_modified() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("kick.wav"), b"kick").unwrap();
+        fs::write(temp_dir.path().join("snare.wav"), b"snare").unwrap();
+        fs::write(temp_dir.path().join("hat.wav"), b"hat").unwrap();
+
+        let mut registry = SampleRegistry::new();
+        registry.register(temp_dir.path(), "kick.wav").unwrap();
+        registry.register(temp_dir.path(), "snare.wav").unwrap();
+        registry.register(temp_dir.path(), "hat.wav").unwrap();
+        assert!(registry.check(temp_dir.path()).is_empty());
+
+        fs::create_dir(temp_dir.path().join("drums")).unwrap();
+        fs::rename(temp_dir.path().join("kick.wav"), temp_dir.path().join("drums/kick.wav")).unwrap();
+        fs::remove_file(temp_dir.path().join("snare.wav")).unwrap();
+        fs::write(temp_dir.path().join("hat.wav"), b"open hat").unwrap();
+
+        let issues = registry.check(temp_dir.path());
+        assert_eq!(issues.len(), 3);
+        assert!(issues.contains(&SampleIssue::Modified { path: "hat.wav".to_string() }));
+        assert!(issues.contains(&SampleIssue::Missing { path: "snare.wav".to_string() }));
+        assert!(issues.contains(&SampleIssue::Moved {
+            path: "kick.wav".to_string(),
+            found_at: temp_dir.path().join("drums/kick.wav"),
+        }));
+    }
+
+    #[test]
+    fn test_needing_resample() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("kick.wav"), wav_bytes(44100)).unwrap();
+        fs::write(temp_dir.path().join("snare.wav"), wav_bytes(48000)).unwrap();
+        fs::write(temp_dir.path().join("notes.txt"), b"not audio").unwrap();
+
+        let mut registry = SampleRegistry::new();
+        for path in ["kick.wav", "snare.wav", "notes.txt"] {
+            registry.register(temp_dir.path(), path).unwrap();
+        }
+        assert_eq!(registry.get("kick.wav").unwrap().sample_rate, Some(44100));
+        assert_eq!(registry.get("notes.txt").unwrap().sample_rate, None);
+
+        assert_eq!(registry.needing_resample(44100), vec!["snare.wav"]);
+        assert_eq!(registry.needing_resample(48000), vec!["kick.wav"]);
+        assert_eq!(registry.needing_resample(22050), vec!["kick.wav", "snare.wav"]);
+    }
+}
@@ -0,0 +1,202 @@
+use std::fmt;
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use anyhow::{Result, anyhow};
+use crate::pitch::{Pitch, Tone};
+use crate::UnknownFields;
+
+/// Scale types, each a set of semitone steps above the root
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ScaleType {
+    Major,
+    /// Natural minor
+    Minor,
+    HarmonicMinor,
+    MelodicMinor,
+    Dorian,
+    Phrygian,
+    Lydian,
+    Mixolydian,
+    Locrian,
+    MajorPentatonic,
+    MinorPentatonic,
+    Blues,
+    Chromatic,
+}
+
+impl ScaleType {
+    pub const ALL: [ScaleType; 13] = [
+        ScaleType::Major,
+        ScaleType::Minor,
+        ScaleType::HarmonicMinor,
+        ScaleType::MelodicMinor,
+        ScaleType::Dorian,
+        ScaleType::Phrygian,
+        ScaleType::Lydian,
+        ScaleType::Mixolydian,
+        ScaleType::Locrian,
+        ScaleType::MajorPentatonic,
+        ScaleType::MinorPentatonic,
+        ScaleType::Blues,
+        ScaleType::Chromatic,
+    ];
+
+    /// Semitones above the root of each scale degree, starting with 0
+    pub fn intervals(&self) -> &'static [u16] {
+        match self {
+            ScaleType::Major => &[0, 2, 4, 5, 7, 9, 11],
+            ScaleType::Minor => &[0, 2, 3, 5, 7, 8, 10],
+            ScaleType::HarmonicMinor => &[0, 2, 3, 5, 7, 8, 11],
+            ScaleType::MelodicMinor => &[0, 2, 3, 5, 7, 9, 11],
+            ScaleType::Dorian => &[0, 2, 3, 5, 7, 9, 10],
+            ScaleType::Phrygian => &[0, 1, 3, 5, 7, 8, 10],
+            ScaleType::Lydian => &[0, 2, 4, 6, 7, 9, 11],
+            ScaleType::Mixolydian => &[0, 2, 4, 5, 7, 9, 10],
+            ScaleType::Locrian => &[0, 1, 3, 5, 6, 8, 10],
+            ScaleType::MajorPentatonic => &[0, 2, 4, 7, 9],
+            ScaleType::MinorPentatonic => &[0, 3, 5, 7, 10],
+            ScaleType::Blues => &[0, 3, 5, 6, 7, 10],
+            ScaleType::Chromatic => &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+        }
+    }
+
+    /// Name as written in project files, e.g. `harmonic_minor`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ScaleType::Major => "major",
+            ScaleType::Minor => "minor",
+            ScaleType::HarmonicMinor => "harmonic_minor",
+            ScaleType::MelodicMinor => "melodic_minor",
+            ScaleType::Dorian => "dorian",
+            ScaleType::Phrygian => "phrygian",
+            ScaleType::Lydian => "lydian",
+            ScaleType::Mixolydian => "mixolydian",
+            ScaleType::Locrian => "locrian",
+            ScaleType::MajorPentatonic => "major_pentatonic",
+            ScaleType::MinorPentatonic => "minor_pentatonic",
+            ScaleType::Blues => "blues",
+            ScaleType::Chromatic => "chromatic",
+        }
+    }
+}
+
+/// A key signature: a root tone and a scale built on it
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct Key {
+    pub root: Tone,
+    pub scale: ScaleType,
+    #[serde(flatten)]
+    pub extra: UnknownFields,
+}
+
+impl Key {
+    pub fn new(root: Tone, scale: ScaleType) -> Self {
+        Self { root, scale, extra: UnknownFields::new() }
+    }
+
+    /// Parse a key like `C major`, `F# dorian` or `Bb harmonic minor`. The scale defaults
+    /// to major when left out.
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut words = text.split_whitespace();
+        let root = words.next()
+            .and_then(Tone::from_name)
+            .ok_or_else(|| anyhow!("Invalid key '{}'. Expected a root like 'C' or 'F#'", text))?;
+        let scale_name = words.collect::<Vec<_>>().join("_").to_lowercase();
+        if scale_name.is_empty() {
+            return Ok(Self::new(root, ScaleType::Major));
+        }
+        let scale = ScaleType::ALL.into_iter()
+            .find(|scale| scale.as_str() == scale_name)
+            .ok_or_else(|| anyhow!("Unknown scale '{}' in key '{}'", scale_name, text))?;
+        Ok(Self::new(root, scale))
+    }
+
+    /// The tones of the scale in ascending order from the root
+    pub fn tones(&self) -> Vec<Tone> {
+        self.scale.intervals().iter()
+            .map(|interval| Tone::from_index((self.root.index() + interval) % 12))
+            .collect()
+    }
+
+    /// Scale degree of a pitch (0 for the root), or `None` if it's out of the scale
+    pub fn degree(&self, pitch: &Pitch) -> Option<usize> {
+        let offset = (pitch.tone.index() + 12 - self.root.index()) % 12;
+        self.scale.intervals().iter().position(|&interval| interval == offset)
+    }
+
+    pub fn contains(&self, pitch: &Pitch) -> bool {
+        self.degree(pitch).is_some()
+    }
+
+    /// The nearest pitch in the scale, preferring the lower one when two are equally near
+    pub fn snap_pitch_to_scale(&self, pitch: Pitch) -> Pitch {
+        (0..12)
+            .flat_map(|distance| [pitch.transpose(-distance), pitch.transpose(distance)])
+            .flatten()
+            .find(|candidate| self.contains(candidate))
+            .unwrap_or(pitch)
+    }
+
+    /// Move a pitch by `steps` scale degrees (negative moves down), snapping it into
+    /// the scale first. Returns `None` if the result would fall outside C0..B8.
+    pub fn diatonic_transpose(&self, pitch: Pitch, steps: i32) -> Option<Pitch> {
+        let snapped = self.snap_pitch_to_scale(pitch);
+        let intervals = self.scale.intervals();
+        let count = intervals.len() as i32;
+        let degree = self.degree(&snapped)? as i32;
+
+        let root = Pitch::new(self.root, 0);
+        let octave = snapped.semitones_from(&root).div_euclid(12);
+        let target = degree + steps;
+        let semitones = (octave + target.div_euclid(count)) * 12 + intervals[target.rem_euclid(count) as usize] as i32;
+        root.transpose(semitones)
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.root.as_str(), self.scale.as_str().replace('_', " "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_key() {
+        let key = Key::parse("Bb harmonic minor").unwrap();
+        assert_eq!((key.root, key.scale), (Tone::As, ScaleType::HarmonicMinor));
+        assert_eq!(key.to_string(), "A# harmonic minor");
+        assert_eq!(Key::parse("G").unwrap().scale, ScaleType::Major);
+        assert_eq!(Key::parse("E phrygian").unwrap().tones()[1], Tone::F);
+        assert!(Key::parse("H major").is_err());
+        assert!(Key::parse("C bebop").is_err());
+    }
+
+    #[test]
+    fn test_snap_pitch_to_scale() {
+        let c_major = Key::new(Tone::C, ScaleType::Major);
+        assert_eq!(c_major.snap_pitch_to_scale(Pitch::new(Tone::E, 4)), Pitch::new(Tone::E, 4));
+        assert_eq!(c_major.snap_pitch_to_scale(Pitch::new(Tone::Cs, 4)), Pitch::new(Tone::C, 4));
+        assert_eq!(c_major.snap_pitch_to_scale(Pitch::new(Tone::Fs, 4)), Pitch::new(Tone::F, 4));
+
+        let a_pentatonic = Key::new(Tone::A, ScaleType::MinorPentatonic);
+        assert_eq!(a_pentatonic.snap_pitch_to_scale(Pitch::new(Tone::B, 3)), Pitch::new(Tone::C, 4));
+        assert!(!a_pentatonic.contains(&Pitch::new(Tone::F, 2)));
+    }
+
+    #[test]
+    fn test_diatonic_transpose() {
+        let c_major = Key::new(Tone::C, ScaleType::Major);
+        let up_third = c_major.diatonic_transpose(Pitch::new(Tone::E, 4), 2).unwrap();
+        assert_eq!(up_third, Pitch::new(Tone::G, 4));
+        assert_eq!(c_major.diatonic_transpose(Pitch::new(Tone::B, 4), 1), Some(Pitch::new(Tone::C, 5)));
+        assert_eq!(c_major.diatonic_transpose(Pitch::new(Tone::D, 4), -9), Some(Pitch::new(Tone::B, 2)));
+
+        let d_dorian = Key::new(Tone::D, ScaleType::Dorian);
+        assert_eq!(d_dorian.diatonic_transpose(Pitch::new(Tone::C, 4), 1), Some(Pitch::new(Tone::D, 4)));
+        assert_eq!(c_major.diatonic_transpose(Pitch::new(Tone::B, 8), 1), None);
+    }
+}
@@ -0,0 +1,92 @@
+use std::sync::OnceLock;
+use schemars::schema_for;
+use serde_json::Value;
+use crate::DawFile;
+
+/// Regex for "bar.32nd" time strings, used in the schema
+pub(crate) const TIME_PATTERN: &str = r"^[1-9][0-9]*\.([0-9]|[12][0-9]|3[01])$";
+
+/// One place where a document doesn't match the project schema
+#[derive(Debug, Clone, PartialEq)]
+pub struct SchemaViolation {
+    /// JSON pointer to the offending value; empty for the whole document
+    pub pointer: String,
+    pub message: String,
+}
+
+/// JSON Schema (draft 2020-12) for project files
+pub fn json_schema() -> Value {
+    serde_json::to_value(schema_for!(DawFile)).expect("schema serializes to JSON")
+}
+
+/// Check a JSON document against the project schema, returning every violation found
+pub fn validate_document(document: &str) -> Vec<SchemaViolation> {
+    static VALIDATOR: OnceLock<jsonschema::Validator> = OnceLock::new();
+
+    let instance: Value = match serde_json::from_str(document) {
+        Ok(instance) => instance,
+        Err(e) => {
+            return vec![SchemaViolation {
+                pointer: String::new(),
+                message: format!("Invalid JSON: {}", e),
+            }];
+        }
+    };
+
+    let validator = VALIDATOR.get_or_init(|| {
+        jsonschema::validator_for(&json_schema()).expect("generated schema is valid")
+    });
+    validator.iter_errors(&instance)
+        .map(|error| SchemaViolation {
+            pointer: error.instance_path().to_string(),
+            message: error.to_string(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_published_schema_is_current() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/../../daw.schema.json");
+        let generated = serde_json::to_string_pretty(&json_schema()).unwrap() + "\n";
+        if std::env::var_os("DAWWW_UPDATE_SCHEMA").is_some() {
+            std::fs::write(path, &generated).unwrap();
+        }
+        let published = std::fs::read_to_string(path).unwrap_or_default();
+        assert!(published == generated, "daw.schema.json is out of date; rerun with DAWWW_UPDATE_SCHEMA=1");
+    }
+
+    #[test]
+    fn test_reports_every_violation() {
+        let mut document = serde_json::to_value(DawFile::new("Song".to_string())).unwrap();
+        document["bpm"] = serde_json::json!("fast");
+        document["instruments"]["lead"] = serde_json::json!({ "type": "theremin", "parameters": {} });
+        document["events"] = serde_json::json!([
+            { "time": "1.40", "instrument": "lead", "notes": [] },
+            { "time": "2.0", "instrument": "lead", "notes": [{ "pitch": { "tone": "C", "octave": 4 }, "duration": 8, "velocity": 0 }] }
+        ]);
+
+        let violations = validate_document(&document.to_string());
+        let pointers: Vec<&str> = violations.iter().map(|v| v.pointer.as_str()).collect();
+        assert!(pointers.contains(&"/bpm"));
+        assert!(pointers.contains(&"/instruments/lead"));
+        assert!(pointers.contains(&"/events/0/time"));
+        assert!(pointers.contains(&"/events/1/notes/0/velocity"));
+    }
+
+    #[test]
+    fn test_valid_and_malformed_documents() {
+        let document = serde_json::to_string(&DawFile::new("Song".to_string())).unwrap();
+        assert_eq!(validate_document(&document), vec![]);
+
+        let sample_song = include_str!("../../../sample_song/song.daw.json");
+        assert_eq!(validate_document(sample_song), vec![]);
+
+        let violations = validate_document("{ \"bpm\": ");
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].pointer, "");
+    }
+}
@@ -0,0 +1,56 @@
+use crate::pitch::{Pitch, PitchRange};
+use crate::time::TimeRange;
+
+/// A rectangle of the piano roll: notes starting in a span of time, within a span of keys
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Selection {
+    pub time: TimeRange,
+    pub pitches: PitchRange,
+}
+
+impl Selection {
+    pub fn new(time: TimeRange, pitches: PitchRange) -> Self {
+        Self { time, pitches }
+    }
+
+    /// Whether a note starting at `position` (in 32nds) with `pitch` is selected
+    pub fn contains(&self, position: u32, pitch: &Pitch) -> bool {
+        self.time.contains(position) && self.pitches.contains(pitch)
+    }
+
+    /// The part of the piano roll in both selections, if they share any
+    pub fn intersection(&self, other: &Selection) -> Option<Selection> {
+        Some(Selection::new(self.time.intersection(&other.time)?, self.pitches.intersection(&other.pitches)?))
+    }
+
+    /// The smallest selection covering both
+    pub fn union(&self, other: &Selection) -> Selection {
+        Selection::new(self.time.union(&other.time), self.pitches.union(&other.pitches))
+    }
+}
+
+/// Every pitch in a span of time
+impl From<TimeRange> for Selection {
+    fn from(time: TimeRange) -> Self {
+        Selection::new(time, PitchRange::all())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pitch::Tone;
+
+    #[test]
+    fn test_selection() {
+        let c4_to_g4 = PitchRange::new(Pitch::new(Tone::C, 4), Pitch::new(Tone::G, 4));
+        let selection = Selection::new(TimeRange::bars(1, 1), c4_to_g4);
+        assert!(selection.contains(31, &Pitch::new(Tone::E, 4)));
+        assert!(!selection.contains(32, &Pitch::new(Tone::E, 4)));
+        assert!(!selection.contains(0, &Pitch::new(Tone::A, 4)));
+
+        let bar_two: Selection = TimeRange::bars(2, 2).into();
+        assert_eq!(selection.intersection(&bar_two), None);
+        assert_eq!(selection.union(&bar_two), Selection::new(TimeRange::bars(1, 2), PitchRange::all()));
+    }
+}
@@ -0,0 +1,92 @@
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use anyhow::{Result, bail};
+use crate::{time_to_32nds, Grid, UnknownFields};
+
+/// Where the user was working when the song was last saved, so an editor can reopen it
+/// in the same place. It doesn't affect how the song sounds.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Default, PartialEq)]
+pub struct Session {
+    /// Loop region as "bar.32nd" times
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(regex(pattern = crate::schema::TIME_PATTERN))]
+    pub loop_start: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(regex(pattern = crate::schema::TIME_PATTERN))]
+    pub loop_end: Option<String>,
+    /// Whether playback loops over the region
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub loop_enabled: bool,
+    /// Last playhead position as a "bar.32nd" time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(regex(pattern = crate::schema::TIME_PATTERN))]
+    pub playhead: Option<String>,
+    /// Editing grid the cursor snaps to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snap: Option<Grid>,
+    #[serde(flatten)]
+    pub extra: UnknownFields,
+}
+
+impl Session {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Loop region in 32nds, if both ends are set and valid
+    pub fn loop_region(&self) -> Option<(u32, u32)> {
+        let start = time_to_32nds(self.loop_start.as_deref()?).ok()?;
+        let end = time_to_32nds(self.loop_end.as_deref()?).ok()?;
+        Some((start, end))
+    }
+
+    /// Playhead in 32nds, if set and valid
+    pub fn playhead_position(&self) -> Option<u32> {
+        time_to_32nds(self.playhead.as_deref()?).ok()
+    }
+
+    pub(crate) fn validate(&self) -> Result<()> {
+        for time in [&self.loop_start, &self.loop_end, &self.playhead].into_iter().flatten() {
+            time_to_32nds(time)?;
+        }
+        if let Some((start, end)) = self.loop_region() {
+            if start > end {
+                bail!("Loop start {} is after loop end {}", start, end);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_round_trip() {
+        let session = Session {
+            loop_start: Some("2.0".to_string()),
+            loop_end: Some("4.16".to_string()),
+            loop_enabled: true,
+            playhead: Some("3.8".to_string()),
+            snap: Some(Grid::Sixteenth),
+            extra: UnknownFields::new(),
+        };
+        session.validate().unwrap();
+        assert_eq!(session.loop_region(), Some((32, 112)));
+        assert_eq!(session.playhead_position(), Some(72));
+
+        let json = serde_json::to_value(&session).unwrap();
+        assert_eq!(json["snap"], "sixteenth");
+        assert_eq!(serde_json::from_value::<Session>(json).unwrap(), session);
+        assert_eq!(serde_json::to_string(&Session::default()).unwrap(), "{}");
+    }
+
+    #[test]
+    fn test_invalid_session() {
+        let backwards = Session { loop_start: Some("3.0".to_string()), loop_end: Some("2.0".to_string()), ..Default::default() };
+        assert!(backwards.validate().is_err());
+        let bad_time = Session { playhead: Some("0.0".to_string()), ..Default::default() };
+        assert!(bad_time.validate().is_err());
+    }
+}
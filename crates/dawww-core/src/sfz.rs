@@ -0,0 +1,330 @@
+use anyhow::{anyhow, bail, Result};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// An instrument read from an SFZ file: regions mapping samples across keys and velocities.
+/// Only the basic opcodes are understood (key and velocity ranges, tuning, volume, loops and
+/// the amplitude envelope); the rest are ignored.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Sfz {
+    pub regions: Vec<SfzRegion>,
+}
+
+/// How a region's sample loops
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SfzLoopMode {
+    /// Play the sample once, stopping early if the note is released
+    #[default]
+    NoLoop,
+    /// Play the whole sample whatever the note's length
+    OneShot,
+    /// Loop between the loop points until the sound has faded out
+    Continuous,
+    /// Loop while the note is held, then play on past the loop end
+    Sustain,
+}
+
+/// One `<region>`, with the opcodes it inherits from its `<global>`, `<master>` and `<group>`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SfzRegion {
+    /// Path to the sample, relative to the SFZ file's directory
+    pub sample: String,
+    /// MIDI keys the region plays for (C4 = 60)
+    pub lokey: u8,
+    pub hikey: u8,
+    /// Key the sample was recorded at
+    pub pitch_keycenter: u8,
+    pub lovel: u8,
+    pub hivel: u8,
+    /// Semitones
+    pub transpose: i32,
+    /// Cents
+    pub tune: f64,
+    /// Cents the pitch changes by per key; 0 plays every key at the recorded pitch
+    pub pitch_keytrack: f64,
+    /// Decibels
+    pub volume: f64,
+    pub loop_mode: SfzLoopMode,
+    /// Loop points in sample frames, the end included. Default to the whole sample.
+    pub loop_start: Option<u64>,
+    pub loop_end: Option<u64>,
+    /// Amplitude envelope in seconds, with the sustain level from 0 to 1
+    pub ampeg_attack: f64,
+    pub ampeg_decay: f64,
+    pub ampeg_sustain: f64,
+    pub ampeg_release: f64,
+}
+
+impl Default for SfzRegion {
+    fn default() -> Self {
+        Self {
+            sample: String::new(),
+            lokey: 0,
+            hikey: 127,
+            pitch_keycenter: 60,
+            lovel: 1,
+            hivel: 127,
+            transpose: 0,
+            tune: 0.0,
+            pitch_keytrack: 100.0,
+            volume: 0.0,
+            loop_mode: SfzLoopMode::NoLoop,
+            loop_start: None,
+            loop_end: None,
+            ampeg_attack: 0.0,
+            ampeg_decay: 0.0,
+            ampeg_sustain: 1.0,
+            ampeg_release: 0.0,
+        }
+    }
+}
+
+impl SfzRegion {
+    pub fn contains(&self, key: u8, velocity: u8) -> bool {
+        (self.lokey..=self.hikey).contains(&key) && (self.lovel..=self.hivel).contains(&velocity)
+    }
+
+    /// Ratio of playback speed to the sample's original speed for `key`
+    pub fn pitch_ratio(&self, key: u8) -> f64 {
+        let cents = (key as f64 - self.pitch_keycenter as f64) * self.pitch_keytrack
+            + self.transpose as f64 * 100.0
+            + self.tune;
+        2_f64.powf(cents / 1200.0)
+    }
+
+    /// Linear gain for the region's `volume`
+    pub fn gain(&self) -> f64 {
+        10_f64.powf(self.volume / 20.0)
+    }
+
+    fn set(&mut self, opcode: &str, value: &str) -> Result<()> {
+        let number = |value: &str| value.parse::<f64>().map_err(|_| anyhow!("Invalid value '{}' for {}", value, opcode));
+        let velocity = |value: &str| {
+            value.parse::<u8>().ok().filter(|v| *v <= 127).ok_or_else(|| anyhow!("Invalid velocity '{}' for {}", value, opcode))
+        };
+        let frame = |value: &str| value.parse::<u64>().map_err(|_| anyhow!("Invalid sample frame '{}' for {}", value, opcode));
+        match opcode {
+            "sample" => self.sample = value.replace('\\', "/"),
+            "lokey" => self.lokey = parse_key(value)?,
+            "hikey" => self.hikey = parse_key(value)?,
+            "key" => {
+                let key = parse_key(value)?;
+                (self.lokey, self.hikey, self.pitch_keycenter) = (key, key, key);
+            }
+            "pitch_keycenter" => self.pitch_keycenter = parse_key(value)?,
+            "lovel" => self.lovel = velocity(value)?,
+            "hivel" => self.hivel = velocity(value)?,
+            "transpose" => self.transpose = number(value)? as i32,
+            "tune" => self.tune = number(value)?,
+            "pitch_keytrack" => self.pitch_keytrack = number(value)?,
+            "volume" => self.volume = number(value)?,
+            "loop_mode" | "loopmode" => {
+                self.loop_mode = match value {
+                    "no_loop" => SfzLoopMode::NoLoop,
+                    "one_shot" => SfzLoopMode::OneShot,
+                    "loop_continuous" => SfzLoopMode::Continuous,
+                    "loop_sustain" => SfzLoopMode::Sustain,
+                    other => bail!("Unknown loop_mode '{}'", other),
+                }
+            }
+            "loop_start" | "loopstart" => self.loop_start = Some(frame(value)?),
+            "loop_end" | "loopend" => self.loop_end = Some(frame(value)?),
+            "ampeg_attack" => self.ampeg_attack = number(value)?.max(0.0),
+            "ampeg_decay" => self.ampeg_decay = number(value)?.max(0.0),
+            "ampeg_sustain" => self.ampeg_sustain = (number(value)? / 100.0).clamp(0.0, 1.0),
+            "ampeg_release" => self.ampeg_release = number(value)?.max(0.0),
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Header levels that pass their opcodes down to the regions under them
+const LEVELS: [&str; 3] = ["global", "master", "group"];
+
+impl Sfz {
+    /// Parse SFZ text. Opcodes set in `<global>`, `<master>` and `<group>` headers apply to
+    /// the regions that follow, and `<control>`'s `default_path` is put in front of every
+    /// sample. Comments and unknown opcodes are skipped; `#define` and `#include` aren't
+    /// supported.
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut sfz = Self::default();
+        let mut default_path = String::new();
+        // Opcodes set at each level above the region
+        let mut inherited: [Vec<(String, String)>; 3] = Default::default();
+        let mut header = String::new();
+        let mut opcodes: Vec<(String, String)> = Vec::new();
+
+        let mut finish = |header: &str, opcodes: &mut Vec<(String, String)>, inherited: &mut [Vec<(String, String)>; 3]| -> Result<()> {
+            let opcodes = std::mem::take(opcodes);
+            if let Some(level) = LEVELS.iter().position(|&level| level == header) {
+                inherited[level] = opcodes;
+                // A new group starts without the opcodes of the previous one
+                for lower in &mut inherited[level + 1..] {
+                    lower.clear();
+                }
+            } else if header == "control" {
+                if let Some((_, path)) = opcodes.iter().rev().find(|(opcode, _)| opcode == "default_path") {
+                    default_path = path.replace('\\', "/");
+                }
+            } else if header == "region" {
+                let mut region = SfzRegion::default();
+                for (opcode, value) in inherited.iter().flatten().chain(&opcodes) {
+                    region.set(opcode, value)?;
+                }
+                if region.sample.is_empty() {
+                    bail!("An SFZ region has no sample");
+                }
+                region.sample = format!("{}{}", default_path, region.sample);
+                sfz.regions.push(region);
+            }
+            Ok(())
+        };
+
+        for (number, line) in strip_block_comments(text).lines().enumerate() {
+            let line = line.split("//").next().unwrap_or("");
+            for mut word in line.split_whitespace() {
+                // Headers can run straight into an opcode, as in `<region>sample=a.wav`
+                while let Some(rest) = word.strip_prefix('<') {
+                    let (name, after) = rest.split_once('>')
+                        .ok_or_else(|| anyhow!("Line {}: unclosed header '{}'", number + 1, word))?;
+                    finish(&header, &mut opcodes, &mut inherited)?;
+                    header = name.to_string();
+                    word = after;
+                }
+                if word.is_empty() {
+                    continue;
+                }
+                match word.split_once('=') {
+                    Some((opcode, value)) => opcodes.push((opcode.to_string(), value.to_string())),
+                    // Sample paths can contain spaces: anything up to the next opcode is
+                    // part of the value
+                    None => match opcodes.last_mut() {
+                        Some((_, value)) => {
+                            value.push(' ');
+                            value.push_str(word);
+                        }
+                        None => bail!("Line {}: expected an opcode, got '{}'", number + 1, word),
+                    },
+                }
+            }
+        }
+        finish(&header, &mut opcodes, &mut inherited)?;
+        Ok(sfz)
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// Every region that plays for a key and velocity; SFZ can layer several
+    pub fn regions_for(&self, key: u8, velocity: u8) -> impl Iterator<Item = &SfzRegion> {
+        self.regions.iter().filter(move |region| region.contains(key, velocity))
+    }
+
+    /// Every sample the regions use, each once
+    pub fn samples(&self) -> Vec<&str> {
+        let unique: BTreeMap<&str, ()> = self.regions.iter().map(|region| (region.sample.as_str(), ())).collect();
+        unique.into_keys().collect()
+    }
+}
+
+fn strip_block_comments(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("/*") {
+        output.push_str(&rest[..start]);
+        // Keep the line breaks so error line numbers stay right
+        let end = rest[start..].find("*/").map_or(rest.len(), |end| start + end + 2);
+        output.extend(rest[start..end].chars().filter(|&c| c == '\n'));
+        rest = &rest[end..];
+    }
+    output.push_str(rest);
+    output
+}
+
+/// A key as a MIDI number or a note name like `c4`, `f#3` or `eb2` (C4 = 60)
+fn parse_key(value: &str) -> Result<u8> {
+    let invalid = || anyhow!("Invalid key '{}'", value);
+    let key = match value.parse::<i32>() {
+        Ok(number) => number,
+        Err(_) => {
+            let lower = value.to_ascii_lowercase();
+            let mut chars = lower.chars();
+            let base = match chars.next() {
+                Some('c') => 0,
+                Some('d') => 2,
+                Some('e') => 4,
+                Some('f') => 5,
+                Some('g') => 7,
+                Some('a') => 9,
+                Some('b') => 11,
+                _ => return Err(invalid()),
+            };
+            let rest = chars.as_str();
+            let (accidental, octave) = match rest.strip_prefix('#') {
+                Some(octave) => (1, octave),
+                None => match rest.strip_prefix('b') {
+                    Some(octave) => (-1, octave),
+                    None => (0, rest),
+                },
+            };
+            let octave: i32 = octave.parse().map_err(|_| invalid())?;
+            (octave + 1) * 12 + base + accidental
+        }
+    };
+    u8::try_from(key).ok().filter(|key| *key <= 127).ok_or_else(invalid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let text = "
+            // A two-layer piano
+            <control> default_path=Samples\\Piano/
+            <global> ampeg_release=0.5 /* applies to
+            every region */
+            <group> lovel=1 hivel=63 volume=-6
+            <region> sample=C4 soft.wav lokey=c4 hikey=e4 pitch_keycenter=60
+            <region>sample=F#4 soft.wav key=f#4 tune=-10
+            <group> lovel=64
+            <region> sample=C4 loud.wav lokey=48 hikey=71 pitch_keycenter=c4
+                loop_mode=loop_sustain loop_start=100 loop_end=999 ampeg_sustain=50
+        ";
+        let sfz = Sfz::parse(text).unwrap();
+        assert_eq!(sfz.regions.len(), 3);
+
+        let soft = &sfz.regions[0];
+        assert_eq!(soft.sample, "Samples/Piano/C4 soft.wav");
+        assert_eq!((soft.lokey, soft.hikey, soft.pitch_keycenter), (60, 64, 60));
+        assert_eq!((soft.lovel, soft.hivel, soft.volume), (1, 63, -6.0));
+        assert_eq!(soft.ampeg_release, 0.5);
+
+        let sharp = &sfz.regions[1];
+        assert_eq!((sharp.lokey, sharp.hikey, sharp.pitch_keycenter, sharp.tune), (66, 66, 66, -10.0));
+
+        // The second group replaces the first, but the global opcodes still apply
+        let loud = &sfz.regions[2];
+        assert_eq!((loud.lovel, loud.hivel, loud.volume, loud.ampeg_release), (64, 127, 0.0, 0.5));
+        assert_eq!(loud.loop_mode, SfzLoopMode::Sustain);
+        assert_eq!((loud.loop_start, loud.loop_end, loud.ampeg_sustain), (Some(100), Some(999), 0.5));
+
+        assert_eq!(sfz.regions_for(62, 40).count(), 1);
+        assert_eq!(sfz.regions_for(80, 40).count(), 0);
+        assert_eq!(sfz.regions_for(62, 100).map(|r| r.sample.as_str()).collect::<Vec<_>>(), vec!["Samples/Piano/C4 loud.wav"]);
+        assert_eq!(sfz.samples().len(), 3);
+        assert!((loud.pitch_ratio(72) - 2.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Sfz::parse("<region> lokey=c4").is_err());
+        assert!(Sfz::parse("<region> sample=a.wav lokey=h4").is_err());
+        assert!(Sfz::parse("<region sample=a.wav").is_err());
+        assert_eq!(parse_key("eb2").unwrap(), 39);
+        assert!(parse_key("128").is_err());
+    }
+}
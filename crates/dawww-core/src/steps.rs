@@ -0,0 +1,149 @@
+use anyhow::{Result, anyhow, bail};
+use crate::pitch::Pitch;
+use crate::Note;
+
+/// A single step in a step-sequencer string
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Step {
+    /// Trigger the pattern's hit pitch (`x`)
+    Hit,
+    /// Trigger a specific pitch (`C4`, `F#3`)
+    Note(Pitch),
+    /// Extend the previous note by one step (`-`)
+    Hold,
+    /// Silence (`.`)
+    Rest,
+}
+
+/// A parsed step-sequencer string.
+///
+/// Two notations are accepted:
+/// * drum style, one character per step: `x..x..x.`
+/// * melody style, whitespace-separated tokens: `C4 E4 G4 -`
+///
+/// `|` can be used as a visual bar separator and is ignored.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StepPattern {
+    pub steps: Vec<Step>,
+}
+
+impl StepPattern {
+    pub fn parse(pattern: &str) -> Result<Self> {
+        // Patterns made only of step symbols are read one character per step
+        let drum_style = pattern.chars().all(|c| "xX.-|".contains(c) || c.is_whitespace());
+        let steps = if drum_style {
+            pattern.chars()
+                .filter(|c| *c != '|' && !c.is_whitespace())
+                .map(|c| parse_token(&c.to_string()))
+                .collect::<Result<Vec<_>>>()?
+        } else {
+            pattern.split_whitespace()
+                .filter(|token| *token != "|")
+                .map(parse_token)
+                .collect::<Result<Vec<_>>>()?
+        };
+
+        if steps.first() == Some(&Step::Hold) {
+            bail!("Step pattern can't start with a hold");
+        }
+        Ok(Self { steps })
+    }
+
+    /// Length of the pattern in steps
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// Expand into notes as `(offset in 32nds, note)` pairs. Each step lasts `step_length`
+    /// 32nds; `hit_pitch` is used for `x` steps.
+    pub fn expand(&self, step_length: u32, hit_pitch: Pitch) -> Vec<(u32, Note)> {
+        let mut notes: Vec<(u32, Note)> = Vec::new();
+        let mut holding = false;
+
+        for (i, step) in self.steps.iter().enumerate() {
+            let offset = i as u32 * step_length;
+            match step {
+                Step::Hit => notes.push((offset, Note::new(hit_pitch, step_length))),
+                Step::Note(pitch) => notes.push((offset, Note::new(*pitch, step_length))),
+                Step::Hold if holding => {
+                    if let Some((_, note)) = notes.last_mut() {
+                        note.duration += step_length;
+                    }
+                    continue;
+                }
+                Step::Hold | Step::Rest => {
+                    holding = false;
+                    continue;
+                }
+            }
+            holding = true;
+        }
+
+        notes
+    }
+}
+
+fn parse_token(token: &str) -> Result<Step> {
+    match token {
+        "x" | "X" => Ok(Step::Hit),
+        "-" => Ok(Step::Hold),
+        "." => Ok(Step::Rest),
+        _ => parse_pitch(token).map(Step::Note),
+    }
+}
+
+/// Parse a pitch name such as `C4`, `F#3`, or `Bb2`
+pub(crate) fn parse_pitch(token: &str) -> Result<Pitch> {
+    token.parse().map_err(|e| anyhow!("Invalid step '{}': {}", token, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pitch::Tone;
+
+    #[test]
+    fn test_drum_pattern() {
+        let pattern = StepPattern::parse("x..x|..x.").unwrap();
+        assert_eq!(pattern.len(), 8);
+
+        let kick = Pitch::new(Tone::C, 2);
+        let notes = pattern.expand(4, kick);
+        let offsets: Vec<u32> = notes.iter().map(|(offset, _)| *offset).collect();
+        assert_eq!(offsets, vec![0, 12, 24]);
+        assert!(notes.iter().all(|(_, n)| n.pitch == kick && n.duration == 4));
+    }
+
+    #[test]
+    fn test_melody_pattern_with_holds() {
+        let pattern = StepPattern::parse("C4 E4 | G4 - - . Bb3 -").unwrap();
+        let notes = pattern.expand(8, Pitch::new(Tone::C, 2));
+
+        assert_eq!(notes.len(), 4);
+        assert_eq!(notes[2].0, 16);
+        assert_eq!(notes[2].1.pitch, Pitch::new(Tone::G, 4));
+        assert_eq!(notes[2].1.duration, 24);
+        assert_eq!(notes[3].0, 48);
+        assert_eq!(notes[3].1.pitch, Pitch::new(Tone::As, 3));
+        assert_eq!(notes[3].1.duration, 16);
+    }
+
+    #[test]
+    fn test_hold_after_rest_is_silent() {
+        let notes = StepPattern::parse("x.-x").unwrap().expand(2, Pitch::new(Tone::C, 2));
+        assert_eq!(notes.len(), 2);
+        assert_eq!(notes[0].1.duration, 2);
+    }
+
+    #[test]
+    fn test_invalid_patterns() {
+        assert!(StepPattern::parse("x.y.").is_err());
+        assert!(StepPattern::parse("C4 H4").is_err());
+        assert!(StepPattern::parse("- C4").is_err());
+        assert!(StepPattern::parse("C4 E").is_err());
+    }
+}
@@ -0,0 +1,155 @@
+use anyhow::{Result, anyhow, bail};
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+
+/// Convert a "bar.32nd" time string to 32nds from the start of the song
+pub fn time_to_32nds(time: &str) -> Result<u32> {
+    let (bar, thirty_second) = time.split_once('.')
+        .ok_or_else(|| anyhow!("Invalid time format '{}'. Expected 'bar.32nd'", time))?;
+    let bar = bar.parse::<u32>()
+        .map_err(|_| anyhow!("Invalid bar number in time '{}'", time))?;
+    let thirty_second = thirty_second.parse::<u32>()
+        .map_err(|_| anyhow!("Invalid 32nd note in time '{}'", time))?;
+
+    if bar == 0 || thirty_second >= 32 {
+        bail!("Time '{}' is out of range", time);
+    }
+    Ok((bar - 1) * 32 + thirty_second)
+}
+
+/// Convert 32nds from the start of the song to a "bar.32nd" time string
+pub fn time_from_32nds(position: u32) -> String {
+    format!("{}.{}", position / 32 + 1, position % 32)
+}
+
+/// Note-value grid used for quantizing and snapping
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Grid {
+    ThirtySecond,
+    Sixteenth,
+    Eighth,
+    Quarter,
+}
+
+impl Grid {
+    /// Grid spacing in 32nds
+    pub fn step(&self) -> u32 {
+        match self {
+            Grid::ThirtySecond => 1,
+            Grid::Sixteenth => 2,
+            Grid::Eighth => 4,
+            Grid::Quarter => 8,
+        }
+    }
+
+    /// Move `position` toward the nearest grid line by `strength` (0 = unchanged, 1 = on the grid)
+    pub fn snap(&self, position: u32, strength: f64) -> u32 {
+        let step = self.step();
+        let nearest = (position + step / 2) / step * step;
+        let moved = position as f64 + (nearest as f64 - position as f64) * strength;
+        moved.round() as u32
+    }
+}
+
+/// A span of the song in 32nds, including `start` and excluding `end`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeRange {
+    pub start: u32,
+    pub end: u32,
+}
+
+impl TimeRange {
+    pub fn new(start: u32, end: u32) -> Self {
+        Self { start, end }
+    }
+
+    /// Range between two "bar.32nd" times
+    pub fn from_times(start: &str, end: &str) -> Result<Self> {
+        Ok(Self::new(time_to_32nds(start)?, time_to_32nds(end)?))
+    }
+
+    /// Whole bars `first..=last` (bars are numbered from 1)
+    pub fn bars(first: u32, last: u32) -> Self {
+        Self::new(first.saturating_sub(1) * 32, last * 32)
+    }
+
+    pub fn contains(&self, position: u32) -> bool {
+        position >= self.start && position < self.end
+    }
+
+    /// Whether a span from `start` to `end` shares any time with the range
+    pub fn overlaps(&self, start: u32, end: u32) -> bool {
+        start < self.end && end > self.start
+    }
+
+    /// Whether a "bar.32nd" time falls in the range
+    pub fn contains_time(&self, time: &str) -> bool {
+        time_to_32nds(time).map(|p| self.contains(p)).unwrap_or(false)
+    }
+
+    /// Length in 32nds
+    pub fn len(&self) -> u32 {
+        self.end.saturating_sub(self.start)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The time in both ranges, if they share any
+    pub fn intersection(&self, other: &TimeRange) -> Option<TimeRange> {
+        let range = TimeRange::new(self.start.max(other.start), self.end.min(other.end));
+        (!range.is_empty()).then_some(range)
+    }
+
+    /// The smallest range covering both
+    pub fn union(&self, other: &TimeRange) -> TimeRange {
+        TimeRange::new(self.start.min(other.start), self.end.max(other.end))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_time_conversion() {
+        assert_eq!(time_to_32nds("1.0").unwrap(), 0);
+        assert_eq!(time_to_32nds("3.5").unwrap(), 69);
+        assert_eq!(time_from_32nds(69), "3.5");
+        assert!(time_to_32nds("0.0").is_err());
+        assert!(time_to_32nds("1.32").is_err());
+    }
+
+    #[test]
+    fn test_grid_snap() {
+        assert_eq!(Grid::Eighth.snap(5, 1.0), 4);
+        assert_eq!(Grid::Eighth.snap(6, 1.0), 8);
+        assert_eq!(Grid::Quarter.snap(4, 0.5), 6);
+        assert_eq!(Grid::Quarter.snap(3, 0.0), 3);
+        assert_eq!(Grid::ThirtySecond.snap(3, 1.0), 3);
+    }
+
+    #[test]
+    fn test_time_range() {
+        let range = TimeRange::bars(2, 3);
+        assert_eq!(range, TimeRange::new(32, 96));
+        assert!(range.contains_time("2.0"));
+        assert!(range.contains_time("3.31"));
+        assert!(!range.contains_time("4.0"));
+        assert!(!range.contains_time("1.31"));
+        assert_eq!(TimeRange::from_times("1.16", "2.0").unwrap(), TimeRange::new(16, 32));
+    }
+
+    #[test]
+    fn test_range_intersection_and_union() {
+        let a = TimeRange::new(0, 32);
+        let b = TimeRange::new(16, 64);
+        assert_eq!(a.intersection(&b), Some(TimeRange::new(16, 32)));
+        assert_eq!(a.union(&b), TimeRange::new(0, 64));
+        assert_eq!(a.intersection(&TimeRange::new(32, 40)), None);
+        assert_eq!(b.len(), 48);
+        assert!(TimeRange::new(8, 8).is_empty());
+    }
+}
@@ -0,0 +1,116 @@
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use anyhow::{Result, bail};
+use crate::UnknownFields;
+
+/// How a note's velocity is bent before it reaches its targets
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum VelocityCurve {
+    #[default]
+    Linear,
+    /// Responds most to soft playing: moderate velocities already sound close to full
+    Soft,
+    /// Responds most to hard playing: only high velocities sound close to full
+    Hard,
+}
+
+/// How an instrument's notes respond to their velocity. Notes at full velocity play as the
+/// instrument is set up; softer notes are quieter and darker by the amounts given.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq, Default)]
+pub struct VelocityResponse {
+    #[serde(default)]
+    pub curve: VelocityCurve,
+    /// How much velocity sets the level, 0 to 1: at 0 every note plays at full level, at 1
+    /// the level follows the curve all the way down
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub amp: f64,
+    /// Octaves a subtractive synth's filter cutoff closes by from full velocity down to
+    /// none, -8 to 8; negative values open it instead
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub filter_octaves: f64,
+    #[serde(flatten)]
+    pub extra: UnknownFields,
+}
+
+fn is_zero(value: &f64) -> bool {
+    *value == 0.0
+}
+
+impl VelocityResponse {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn new(curve: VelocityCurve, amp: f64, filter_octaves: f64) -> Self {
+        Self { curve, amp, filter_octaves, extra: UnknownFields::new() }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if !(0.0..=1.0).contains(&self.amp) {
+            bail!("Velocity amp amount must be between 0 and 1, got {}", self.amp);
+        }
+        if !(-8.0..=8.0).contains(&self.filter_octaves) {
+            bail!("Velocity filter amount must be between -8 and 8 octaves, got {}", self.filter_octaves);
+        }
+        Ok(())
+    }
+
+    /// A velocity from 0 to 127 through the curve, from 0 to 1
+    pub fn shaped(&self, velocity: u8) -> f64 {
+        let linear = velocity.min(127) as f64 / 127.0;
+        match self.curve {
+            VelocityCurve::Linear => linear,
+            VelocityCurve::Soft => linear.sqrt(),
+            VelocityCurve::Hard => linear * linear,
+        }
+    }
+
+    /// Gain for a note played at `velocity`
+    pub fn gain(&self, velocity: u8) -> f64 {
+        1.0 - self.amp * (1.0 - self.shaped(velocity))
+    }
+
+    /// Multiplier for the filter cutoff of a note played at `velocity`
+    pub fn cutoff_ratio(&self, velocity: u8) -> f64 {
+        2_f64.powf(-self.filter_octaves * (1.0 - self.shaped(velocity)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_response() {
+        // By default velocity changes nothing
+        let flat = VelocityResponse::default();
+        assert_eq!((flat.gain(1), flat.cutoff_ratio(1)), (1.0, 1.0));
+
+        let response = VelocityResponse::new(VelocityCurve::Linear, 1.0, 2.0);
+        assert_eq!((response.gain(127), response.cutoff_ratio(127)), (1.0, 1.0));
+        assert_eq!(response.gain(0), 0.0);
+        assert_eq!(response.cutoff_ratio(0), 0.25);
+        let half = VelocityResponse::new(VelocityCurve::Linear, 0.5, 0.0);
+        assert!((half.gain(0) - 0.5).abs() < 1e-12);
+
+        // Soft curves sit above linear in the middle, hard ones below
+        let soft = VelocityResponse::new(VelocityCurve::Soft, 1.0, 0.0);
+        let hard = VelocityResponse::new(VelocityCurve::Hard, 1.0, 0.0);
+        assert!(soft.gain(64) > response.gain(64) && response.gain(64) > hard.gain(64));
+    }
+
+    #[test]
+    fn test_serialization_and_validation() {
+        assert!(VelocityResponse::default().validate().is_ok());
+        assert!(VelocityResponse::new(VelocityCurve::Hard, 1.5, 0.0).validate().is_err());
+        assert!(VelocityResponse::new(VelocityCurve::Hard, 0.5, -9.0).validate().is_err());
+
+        let response = VelocityResponse::new(VelocityCurve::Soft, 0.8, 3.0);
+        let json = serde_json::to_value(&response).unwrap();
+        assert_eq!(json, serde_json::json!({ "curve": "soft", "amp": 0.8, "filter_octaves": 3.0 }));
+        assert_eq!(serde_json::from_value::<VelocityResponse>(json).unwrap(), response);
+        let json = serde_json::to_value(VelocityResponse::default()).unwrap();
+        assert_eq!(json, serde_json::json!({ "curve": "linear" }));
+    }
+}
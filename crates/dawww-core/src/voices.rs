@@ -0,0 +1,120 @@
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use anyhow::{Result, bail};
+use crate::UnknownFields;
+
+/// Which sounding note gives up its voice when a new note needs one
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum StealPolicy {
+    /// The note that started first
+    #[default]
+    Oldest,
+    /// The note with the lowest velocity, oldest first on ties
+    Quietest,
+}
+
+/// How many notes an instrument can sound at once
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq, Default)]
+pub struct Polyphony {
+    /// Voice limit; unlimited when `None`. 1 makes a mono synth.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(range(min = 1))]
+    pub max_voices: Option<u32>,
+    #[serde(default)]
+    pub steal: StealPolicy,
+    #[serde(flatten)]
+    pub extra: UnknownFields,
+}
+
+/// A note as seen by voice allocation: when it starts and stops, and how loud it is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VoiceRequest {
+    pub start: u64,
+    pub end: u64,
+    pub velocity: u8,
+}
+
+impl Polyphony {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn mono() -> Self {
+        Self { max_voices: Some(1), ..Default::default() }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.max_voices == Some(0) {
+            bail!("max_voices must be at least 1");
+        }
+        Ok(())
+    }
+
+    /// When each note actually stops once the voice limit is applied, in the same order
+    /// and units as `notes`. A new note always gets a voice; when none is free the note
+    /// chosen by the steal policy is cut off where the new one starts. Notes starting
+    /// together are allocated in the order given. A limit of 0, which `validate` rejects,
+    /// is taken as 1.
+    pub fn allocate(&self, notes: &[VoiceRequest]) -> Vec<u64> {
+        let mut ends: Vec<u64> = notes.iter().map(|n| n.end).collect();
+        let Some(max_voices) = self.max_voices.map(|max_voices| max_voices.max(1)) else {
+            return ends;
+        };
+
+        let mut order: Vec<usize> = (0..notes.len()).collect();
+        order.sort_by_key(|&i| notes[i].start);
+
+        let mut sounding: Vec<usize> = Vec::new();
+        for i in order {
+            let start = notes[i].start;
+            sounding.retain(|&j| ends[j] > start);
+            if sounding.len() >= max_voices as usize {
+                let victim = match self.steal {
+                    StealPolicy::Oldest => 0,
+                    StealPolicy::Quietest => (0..sounding.len())
+                        .min_by_key(|&k| notes[sounding[k]].velocity)
+                        .unwrap_or(0),
+                };
+                ends[sounding.remove(victim)] = start;
+            }
+            sounding.push(i);
+        }
+        ends
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(start: u64, end: u64, velocity: u8) -> VoiceRequest {
+        VoiceRequest { start, end, velocity }
+    }
+
+    #[test]
+    fn test_unlimited_voices() {
+        let notes = [note(0, 10, 100), note(2, 8, 100)];
+        assert_eq!(Polyphony::default().allocate(&notes), vec![10, 8]);
+    }
+
+    #[test]
+    fn test_mono_cuts_previous_note() {
+        let notes = [note(4, 12, 100), note(0, 8, 100), note(8, 10, 100), note(8, 16, 90)];
+        assert_eq!(Polyphony::mono().allocate(&notes), vec![8, 4, 8, 16]);
+    }
+
+    #[test]
+    fn test_steal_quietest() {
+        let notes = [note(0, 16, 50), note(1, 16, 100), note(2, 16, 80)];
+        let oldest = Polyphony { max_voices: Some(2), ..Default::default() };
+        assert_eq!(oldest.allocate(&notes), vec![2, 16, 16]);
+
+        let quietest = Polyphony { max_voices: Some(2), steal: StealPolicy::Quietest, ..Default::default() };
+        let notes = [note(0, 16, 100), note(1, 16, 50), note(2, 16, 80)];
+        assert_eq!(quietest.allocate(&notes), vec![16, 2, 16]);
+        let none = Polyphony { max_voices: Some(0), ..Default::default() };
+        assert!(none.validate().is_err());
+        assert_eq!(none.allocate(&[note(0, 8, 100), note(4, 12, 100)]), vec![4, 12]);
+    }
+}
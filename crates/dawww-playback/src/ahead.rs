@@ -0,0 +1,374 @@
+use crate::Source;
+use anyhow::Result;
+use dawww_render::AudioEngine;
+use std::collections::VecDeque;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// How long a waiting thread sleeps before checking whether rendering has stopped
+const STOP_POLL: Duration = Duration::from_millis(20);
+
+/// A source that can say where it is and carry on from anywhere, so audio rendered ahead
+/// of playback can be rendered again after an edit. Positions are frames on the source's
+/// own timeline.
+pub trait Seek: Source {
+    /// Frame `fill` produces next
+    fn position(&self) -> u64;
+
+    /// Make `fill` carry on from `frame`
+    fn seek(&mut self, frame: u64);
+}
+
+impl Seek for AudioEngine {
+    fn position(&self) -> u64 {
+        AudioEngine::position(self) as u64
+    }
+
+    fn seek(&mut self, frame: u64) {
+        AudioEngine::seek(self, frame as usize);
+    }
+}
+
+impl<S: Seek> Seek for Arc<Mutex<S>> {
+    fn position(&self) -> u64 {
+        self.lock().unwrap_or_else(PoisonError::into_inner).position()
+    }
+
+    fn seek(&mut self, frame: u64) {
+        self.lock().unwrap_or_else(PoisonError::into_inner).seek(frame);
+    }
+}
+
+/// Interleaved stereo frames rendered ahead, starting at frame `start` of the source
+struct Block {
+    start: u64,
+    samples: Vec<f32>,
+}
+
+impl Block {
+    fn frames(&self) -> u64 {
+        self.samples.len() as u64 / 2
+    }
+}
+
+struct AheadState {
+    blocks: VecDeque<Block>,
+    /// Samples of the front block already played
+    played: usize,
+    /// Frames rendered and not yet played
+    frames: usize,
+    /// Where the source has to carry on from, once audio has been thrown away
+    seek_to: Option<u64>,
+    /// Frames of the block being rendered, if one is
+    rendering: Option<Range<u64>>,
+    finished: bool,
+}
+
+impl AheadState {
+    /// Throw away every block from `index` on, plus what's left of the front block from
+    /// `index` 0, and return the frame the source has to carry on from
+    fn truncate(&mut self, index: usize) -> u64 {
+        let from = match index {
+            0 => self.blocks[0].start + self.played as u64 / 2,
+            _ => self.blocks[index].start,
+        };
+        self.blocks.truncate(index);
+        if index == 0 {
+            self.played = 0;
+        }
+        self.frames = self.blocks.iter().map(|block| block.frames() as usize).sum::<usize>() - self.played / 2;
+        from
+    }
+}
+
+struct AheadShared {
+    state: Mutex<AheadState>,
+    /// Signalled when blocks are added or taken, or rendering has to start again
+    changed: Condvar,
+    stop: AtomicBool,
+    ahead_frames: usize,
+}
+
+impl AheadShared {
+    fn lock(&self) -> MutexGuard<'_, AheadState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn wait<'a>(&self, state: MutexGuard<'a, AheadState>) -> MutexGuard<'a, AheadState> {
+        self.changed.wait_timeout(state, STOP_POLL).unwrap_or_else(PoisonError::into_inner).0
+    }
+}
+
+/// Plays a source that's too heavy to render in real time by rendering it ahead of
+/// playback on a worker thread, keeping up to `ahead_frames` ready to play. Audio that's
+/// already been rendered is redone when an `Invalidator` says its part of the source was
+/// edited. Play it with `Playback` like any other source; when the worker falls behind,
+/// `fill` waits for it.
+pub struct RenderAhead {
+    shared: Arc<AheadShared>,
+    worker: Option<JoinHandle<()>>,
+    sample_rate: u32,
+}
+
+impl RenderAhead {
+    /// Start rendering `source` from where it is, `block_frames` at a time, until
+    /// `ahead_frames` are ready
+    pub fn start(source: impl Seek, ahead_frames: usize, block_frames: usize) -> Result<Self> {
+        let sample_rate = source.sample_rate();
+        let shared = Arc::new(AheadShared {
+            state: Mutex::new(AheadState {
+                blocks: VecDeque::new(),
+                played: 0,
+                frames: 0,
+                seek_to: None,
+                rendering: None,
+                finished: false,
+            }),
+            changed: Condvar::new(),
+            stop: AtomicBool::new(false),
+            ahead_frames: ahead_frames.max(block_frames),
+        });
+        let worker = {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name("dawww-render-ahead".to_string())
+                .spawn(move || render_ahead(source, &shared, block_frames.max(1)))?
+        };
+        Ok(Self { shared, worker: Some(worker), sample_rate })
+    }
+
+    /// A handle for throwing away audio rendered ahead from other threads, e.g. the editor's
+    pub fn invalidator(&self) -> Invalidator {
+        Invalidator { shared: self.shared.clone() }
+    }
+
+    /// Frames rendered and waiting to play
+    pub fn buffered_frames(&self) -> usize {
+        self.shared.lock().frames
+    }
+
+    /// Frame of the source `fill` plays next, if it's been rendered
+    pub fn position(&self) -> Option<u64> {
+        let state = self.shared.lock();
+        state.blocks.front().map(|block| block.start + state.played as u64 / 2)
+    }
+}
+
+impl Source for RenderAhead {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn fill(&mut self, out: &mut [f32]) {
+        let mut filled = 0;
+        let mut state = self.shared.lock();
+        while filled < out.len() && !self.shared.stop.load(Ordering::Relaxed) {
+            let Some(block) = state.blocks.front() else {
+                if state.finished {
+                    break;
+                }
+                state = self.shared.wait(state);
+                continue;
+            };
+            let played = state.played;
+            let count = (out.len() - filled).min(block.samples.len() - played);
+            out[filled..filled + count].copy_from_slice(&block.samples[played..played + count]);
+            let emptied = played + count == block.samples.len();
+            filled += count;
+            state.played += count;
+            state.frames -= count / 2;
+            if emptied {
+                state.blocks.pop_front();
+                state.played = 0;
+            }
+            self.shared.changed.notify_all();
+        }
+        out[filled..].fill(0.0);
+    }
+
+    fn is_finished(&self) -> bool {
+        let state = self.shared.lock();
+        state.finished && state.blocks.is_empty()
+    }
+}
+
+impl Drop for RenderAhead {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::Relaxed);
+        self.shared.changed.notify_all();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Tells a `RenderAhead` which parts of its source have changed
+#[derive(Clone)]
+pub struct Invalidator {
+    shared: Arc<AheadShared>,
+}
+
+impl Invalidator {
+    /// Say frames `range` of the source were edited. Audio rendered ahead from the first
+    /// block that overlaps them on is thrown away and rendered again, leaving what plays
+    /// before the edit alone. Edits to parts that have played, or haven't been rendered
+    /// yet, need nothing.
+    pub fn invalidate(&self, range: Range<u64>) {
+        let mut state = self.shared.lock();
+        let overlaps = |start: u64, frames: u64| start < range.end && range.start < start + frames;
+        if let Some(index) = state.blocks.iter().position(|block| overlaps(block.start, block.frames())) {
+            let from = state.truncate(index);
+            state.seek_to = Some(from);
+        } else if let Some(rendering) = state.rendering.clone().filter(|rendering| {
+            overlaps(rendering.start, rendering.end - rendering.start)
+        }) {
+            // The block being rendered may have read the source from before the edit
+            state.seek_to = Some(rendering.start);
+        }
+        // Edits can make a finished source longer
+        state.finished = false;
+        self.shared.changed.notify_all();
+    }
+
+    /// Throw away everything rendered ahead and carry on from frame `frame` of the source
+    pub fn seek(&self, frame: u64) {
+        let mut state = self.shared.lock();
+        if !state.blocks.is_empty() {
+            state.truncate(0);
+        }
+        state.seek_to = Some(frame);
+        state.finished = false;
+        self.shared.changed.notify_all();
+    }
+}
+
+/// Render blocks of `source` until `shared` holds as many frames as it should, starting
+/// again wherever an invalidation says to, until rendering stops
+fn render_ahead(mut source: impl Seek, shared: &AheadShared, block_frames: usize) {
+    let mut block = vec![0.0; block_frames * 2];
+    loop {
+        let mut state = shared.lock();
+        while !shared.stop.load(Ordering::Relaxed)
+            && state.seek_to.is_none()
+            && (state.finished || state.frames >= shared.ahead_frames)
+        {
+            state = shared.wait(state);
+        }
+        if shared.stop.load(Ordering::Relaxed) {
+            return;
+        }
+        let seek_to = state.seek_to.take();
+        drop(state);
+
+        if let Some(frame) = seek_to {
+            source.seek(frame);
+        }
+        if source.is_finished() {
+            shared.lock().finished = true;
+            shared.changed.notify_all();
+            continue;
+        }
+        let start = source.position();
+        shared.lock().rendering = Some(start..start + block_frames as u64);
+        source.fill(&mut block);
+
+        let mut state = shared.lock();
+        state.rendering = None;
+        // Anything rendered while an edit came in is done again
+        if state.seek_to.is_none() {
+            state.frames += block_frames;
+            state.blocks.push_back(Block { start, samples: block.clone() });
+            shared.changed.notify_all();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicU64;
+
+    /// Plays each frame's number, plus 1000 for each edit, for `frames` frames
+    struct Counter {
+        frame: u64,
+        frames: u64,
+        edits: Arc<AtomicU64>,
+    }
+
+    impl Source for Counter {
+        fn sample_rate(&self) -> u32 {
+            8000
+        }
+
+        fn fill(&mut self, out: &mut [f32]) {
+            let edits = self.edits.load(Ordering::Relaxed);
+            for pair in out.chunks_exact_mut(2) {
+                pair.fill((self.frame + edits * 1000) as f32);
+                self.frame += 1;
+            }
+        }
+
+        fn is_finished(&self) -> bool {
+            self.frame >= self.frames
+        }
+    }
+
+    impl Seek for Counter {
+        fn position(&self) -> u64 {
+            self.frame
+        }
+
+        fn seek(&mut self, frame: u64) {
+            self.frame = frame;
+        }
+    }
+
+    fn wait_for(ahead: &RenderAhead, frames: usize) {
+        while ahead.buffered_frames() < frames {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    fn play(ahead: &mut RenderAhead, frames: usize) -> Vec<f32> {
+        let mut out = vec![0.0; frames * 2];
+        ahead.fill(&mut out);
+        out.chunks_exact(2).map(|pair| pair[0]).collect()
+    }
+
+    #[test]
+    fn test_edits_are_rendered_again() {
+        let edits = Arc::new(AtomicU64::new(0));
+        let counter = Counter { frame: 0, frames: 20, edits: edits.clone() };
+        let mut ahead = RenderAhead::start(counter, 8, 2).unwrap();
+        let invalidator = ahead.invalidator();
+        assert_eq!(play(&mut ahead, 3), vec![0.0, 1.0, 2.0]);
+        wait_for(&ahead, 8);
+        assert_eq!(ahead.position(), Some(3));
+
+        // Frames rendered before the edit still play; from its first block on they're redone
+        edits.store(1, Ordering::Relaxed);
+        invalidator.invalidate(6..7);
+        assert_eq!(play(&mut ahead, 5), vec![3.0, 4.0, 5.0, 1006.0, 1007.0]);
+
+        // Edits to what's played need nothing, and seeking starts over
+        invalidator.invalidate(0..2);
+        invalidator.seek(18);
+        assert_eq!(play(&mut ahead, 4), vec![1018.0, 1019.0, 0.0, 0.0]);
+        assert!(ahead.is_finished());
+    }
+
+    #[test]
+    fn test_partly_played_blocks_are_redone_from_the_playhead() {
+        let edits = Arc::new(AtomicU64::new(0));
+        let counter = Counter { frame: 0, frames: 100, edits: edits.clone() };
+        let mut ahead = RenderAhead::start(counter, 8, 4).unwrap();
+        assert_eq!(play(&mut ahead, 1), vec![0.0]);
+        wait_for(&ahead, 7);
+        edits.store(1, Ordering::Relaxed);
+        ahead.invalidator().invalidate(0..100);
+        assert_eq!(play(&mut ahead, 2), vec![1001.0, 1002.0]);
+    }
+}
@@ -0,0 +1,433 @@
+use crate::{Backend, Source};
+use anyhow::{Context, Result, anyhow, bail};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{FromSample, Sample, SampleFormat, SizedSample};
+use dawww_core::mixdown::MixdownSettings;
+use dawww_render::stereo::Stereo;
+use dawww_render::wav::write_wav;
+use crate::ring::SampleRing;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Most audio kept waiting to be monitored, in seconds. Past it the oldest is dropped, so
+/// monitoring never falls further behind the input than this.
+const MONITOR_SECONDS: f64 = 0.05;
+
+/// Input the device callback can get ahead of the thread keeping the recording, in seconds
+const INPUT_SECONDS: f64 = 1.0;
+
+/// How often the recording is brought up to date with the input
+const DRAIN_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Frames the device callback converts at a time, so it needn't allocate
+const CALLBACK_FRAMES: usize = 256;
+
+/// Where audio is recorded from and how it's monitored
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CaptureOptions {
+    /// Audio system to record through; the preferred one when `None`
+    pub backend: Option<Backend>,
+    /// Name of the input device, as `Backend::input_devices` lists it; the default when `None`
+    pub device: Option<String>,
+    /// Gain in dB the input is monitored at; `None` leaves it unmonitored
+    pub monitor_gain_db: Option<f64>,
+}
+
+impl CaptureOptions {
+    pub fn with_backend(mut self, backend: Backend) -> Self {
+        self.backend = Some(backend);
+        self
+    }
+
+    pub fn with_device(mut self, name: &str) -> Self {
+        self.device = Some(name.to_string());
+        self
+    }
+
+    pub fn with_monitor_gain_db(mut self, gain_db: f64) -> Self {
+        self.monitor_gain_db = Some(gain_db);
+        self
+    }
+}
+
+/// Audio recorded from an input: interleaved stereo frames
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RecordedAudio {
+    pub sample_rate: u32,
+    pub samples: Vec<f32>,
+}
+
+impl RecordedAudio {
+    pub fn frames(&self) -> usize {
+        self.samples.len() / 2
+    }
+
+    pub fn seconds(&self) -> f64 {
+        self.frames() as f64 / f64::from(self.sample_rate.max(1))
+    }
+
+    /// Move the audio later by `frames` of silence, or earlier by cutting off its start
+    /// when `frames` is negative, e.g. to line it up with what the player was playing
+    pub fn shift(&mut self, frames: i64) {
+        let samples = frames.unsigned_abs() as usize * 2;
+        if frames >= 0 {
+            self.samples.splice(0..0, std::iter::repeat_n(0.0, samples));
+        } else {
+            self.samples.drain(..samples.min(self.samples.len()));
+        }
+    }
+
+    /// Write the audio to a 32-bit float WAV file at its own rate
+    pub fn write_wav(&self, path: &Path) -> Result<()> {
+        let (left, right) = self.samples.chunks_exact(2).map(|pair| (f64::from(pair[0]), f64::from(pair[1]))).unzip();
+        let settings = MixdownSettings::float(self.sample_rate);
+        write_wav(path, &Stereo { left, right }, &settings, self.sample_rate, 1.0, 0)
+    }
+}
+
+/// State shared with the device callback, which only ever touches the atomics and rings
+struct CaptureShared {
+    /// Everything recorded since recording started, if it has. Input is moved into it
+    /// from `input` off the device callback, with the lock held.
+    recording: Mutex<Option<Vec<f32>>>,
+    /// Whether the device callback should queue input to be recorded
+    is_recording: AtomicBool,
+    /// Input waiting to be added to the recording
+    input: SampleRing,
+    /// Set when the recording fell so far behind that input had to be dropped
+    input_dropped: AtomicBool,
+    /// Input waiting to be monitored
+    monitor: SampleRing,
+    monitor_capacity: usize,
+    /// Linear gain the input is monitored at, as `f64` bits; 0 when it isn't
+    monitor_gain: AtomicU64,
+    /// Set when the capture closes, to stop the thread keeping the recording
+    closed: AtomicBool,
+    error: Mutex<Option<String>>,
+}
+
+impl CaptureShared {
+    fn new(sample_rate: u32, monitor_gain_db: Option<f64>) -> Self {
+        let input_capacity = (f64::from(sample_rate) * INPUT_SECONDS) as usize * 2;
+        let shared = Self {
+            recording: Mutex::new(None),
+            is_recording: AtomicBool::new(false),
+            input: SampleRing::new(input_capacity),
+            input_dropped: AtomicBool::new(false),
+            monitor: SampleRing::new(input_capacity),
+            monitor_capacity: (f64::from(sample_rate) * MONITOR_SECONDS) as usize * 2,
+            monitor_gain: AtomicU64::new(0),
+            closed: AtomicBool::new(false),
+            error: Mutex::new(None),
+        };
+        shared.set_monitor_gain_db(monitor_gain_db);
+        shared
+    }
+
+    fn set_monitor_gain_db(&self, gain_db: Option<f64>) {
+        let gain = gain_db.map_or(0.0, |db| 10f64.powf(db / 20.0));
+        self.monitor_gain.store(gain.to_bits(), Ordering::Relaxed);
+    }
+
+    fn monitor_gain(&self) -> f64 {
+        f64::from_bits(self.monitor_gain.load(Ordering::Relaxed))
+    }
+
+    fn recording(&self) -> MutexGuard<'_, Option<Vec<f32>>> {
+        self.recording.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Take interleaved stereo frames from the input: queue them up to be recorded if
+    /// recording and to be heard if monitoring. Called from the device callback, so it
+    /// neither locks nor allocates.
+    fn take_input(&self, stereo: &[f32]) {
+        if self.is_recording.load(Ordering::Acquire) && self.input.push(stereo) < stereo.len() {
+            self.input_dropped.store(true, Ordering::Relaxed);
+        }
+        if self.monitor_gain() > 0.0 {
+            self.monitor.push(stereo);
+        }
+    }
+
+    /// Move the input waiting to be recorded into `recording`, or throw it away if there
+    /// isn't a recording
+    fn drain_input(&self, recording: &mut Option<Vec<f32>>) {
+        let mut block = [0.0; CALLBACK_FRAMES * 2];
+        loop {
+            let count = self.input.pop_into(&mut block);
+            if count == 0 {
+                break;
+            }
+            if let Some(recording) = recording.as_mut() {
+                recording.extend_from_slice(&block[..count]);
+            }
+        }
+    }
+
+    /// Keep the recording up to date with the input until the capture closes
+    fn keep_recording(&self) {
+        while !self.closed.load(Ordering::Acquire) {
+            self.drain_input(&mut self.recording());
+            thread::sleep(DRAIN_INTERVAL);
+        }
+    }
+}
+
+/// Records from an audio input device, and lets the input be heard through a [`Monitor`]
+/// played with the song. The device stops when this is dropped.
+pub struct Capture {
+    stream: cpal::Stream,
+    shared: Arc<CaptureShared>,
+    /// Moves recorded input out of the device callback's queue
+    drainer: Option<JoinHandle<()>>,
+    backend: Backend,
+    device: String,
+    sample_rate: u32,
+    channels: u16,
+}
+
+impl Capture {
+    /// Open the input `options` say at `sample_rate`, which should be the rate playback
+    /// runs at so monitored and recorded audio lines up with the song
+    pub fn start(sample_rate: u32, options: CaptureOptions) -> Result<Self> {
+        let backend = options.backend.unwrap_or_else(Backend::preferred);
+        let host = backend.host()?;
+        let device = match &options.device {
+            Some(name) => host.input_devices()
+                .with_context(|| format!("Couldn't list {} input devices", backend))?
+                .find(|device| device.name().is_ok_and(|found| found == *name))
+                .with_context(|| format!("No {} input device named '{}'", backend, name))?,
+            None => host.default_input_device().with_context(|| format!("No {} input device found", backend))?,
+        };
+        let device_name = device.name().unwrap_or_default();
+        let supported = device.default_input_config().context("Couldn't read the input device's configuration")?;
+        let records_rate = device.supported_input_configs()
+            .is_ok_and(|mut configs| configs.any(|config| (config.min_sample_rate().0..=config.max_sample_rate().0).contains(&sample_rate)));
+        if !records_rate {
+            bail!("Input device '{}' can't record at {} Hz", device_name, sample_rate);
+        }
+        let config = cpal::StreamConfig {
+            channels: supported.channels(),
+            sample_rate: cpal::SampleRate(sample_rate),
+            buffer_size: cpal::BufferSize::Default,
+        };
+
+        let shared = Arc::new(CaptureShared::new(sample_rate, options.monitor_gain_db));
+        let stream = match supported.sample_format() {
+            SampleFormat::F32 => build_input_stream::<f32>(&device, &config, &shared),
+            SampleFormat::F64 => build_input_stream::<f64>(&device, &config, &shared),
+            SampleFormat::I16 => build_input_stream::<i16>(&device, &config, &shared),
+            SampleFormat::U16 => build_input_stream::<u16>(&device, &config, &shared),
+            SampleFormat::I32 => build_input_stream::<i32>(&device, &config, &shared),
+            format => Err(anyhow!("Unsupported input sample format {}", format)),
+        }?;
+        stream.play().context("Couldn't start the input stream")?;
+        let drainer_shared = shared.clone();
+        let drainer = thread::Builder::new()
+            .name("dawww-capture".to_string())
+            .spawn(move || drainer_shared.keep_recording())
+            .context("Couldn't start the capture thread")?;
+        Ok(Self { stream, shared, drainer: Some(drainer), backend, device: device_name, sample_rate, channels: config.channels })
+    }
+
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    /// Name of the device recording
+    pub fn device(&self) -> &str {
+        &self.device
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Channels the device records; the first two are kept
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    /// Start keeping what comes in, throwing away anything recorded before
+    pub fn start_recording(&self) {
+        let mut recording = self.shared.recording();
+        self.shared.is_recording.store(false, Ordering::Release);
+        self.shared.drain_input(&mut None);
+        self.shared.input_dropped.store(false, Ordering::Relaxed);
+        *recording = Some(Vec::new());
+        self.shared.is_recording.store(true, Ordering::Release);
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.shared.is_recording.load(Ordering::Acquire)
+    }
+
+    /// Stop recording and return what was recorded
+    pub fn stop_recording(&self) -> RecordedAudio {
+        let mut recording = self.shared.recording();
+        self.shared.is_recording.store(false, Ordering::Release);
+        self.shared.drain_input(&mut recording);
+        let samples = recording.take().unwrap_or_default();
+        RecordedAudio { sample_rate: self.sample_rate, samples }
+    }
+
+    /// Monitor the input at `gain_db`, or stop monitoring it with `None`
+    pub fn set_monitor_gain_db(&self, gain_db: Option<f64>) {
+        self.shared.set_monitor_gain_db(gain_db);
+    }
+
+    /// A source playing the input as it comes in, to mix with what's playing
+    pub fn monitor(&self) -> Monitor {
+        Monitor { shared: self.shared.clone(), sample_rate: self.sample_rate }
+    }
+
+    /// The last error the device reported, if any, or a note that the recording lost
+    /// input because it fell behind
+    pub fn error(&self) -> Option<String> {
+        let error = self.shared.error.lock().unwrap_or_else(PoisonError::into_inner).clone();
+        error.or_else(|| self.shared.input_dropped.load(Ordering::Relaxed).then(|| "The recording fell behind the input and lost some of it".to_string()))
+    }
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        let _ = self.stream.pause();
+        self.shared.closed.store(true, Ordering::Release);
+        if let Some(drainer) = self.drainer.take() {
+            let _ = drainer.join();
+        }
+    }
+}
+
+/// The input of a [`Capture`] as a source, at the monitoring gain. Plays silence while
+/// the input isn't monitored or hasn't caught up.
+pub struct Monitor {
+    shared: Arc<CaptureShared>,
+    sample_rate: u32,
+}
+
+impl Source for Monitor {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn fill(&mut self, out: &mut [f32]) {
+        let gain = self.shared.monitor_gain() as f32;
+        let monitor = &self.shared.monitor;
+        // Whatever came in while the input wasn't monitored isn't heard late
+        monitor.skip_to(if gain > 0.0 { self.shared.monitor_capacity } else { 0 });
+        let count = monitor.pop_into(out);
+        for out in &mut out[..count] {
+            *out *= gain;
+        }
+        out[count..].fill(0.0);
+    }
+}
+
+/// Gather a device buffer of `channels` channels into interleaved stereo frames, handing
+/// them to `take` a few at a time without allocating: mono inputs go to both sides, and
+/// channels past the first two are left out
+fn read_frames<T: SizedSample>(input: &[T], channels: usize, mut take: impl FnMut(&[f32]))
+where
+    f32: FromSample<T>,
+{
+    let mut stereo = [0.0; CALLBACK_FRAMES * 2];
+    for frames in input.chunks(channels * CALLBACK_FRAMES) {
+        let mut count = 0;
+        for frame in frames.chunks_exact(channels) {
+            let left = f32::from_sample(frame[0]);
+            let right = frame.get(1).map_or(left, |&sample| f32::from_sample(sample));
+            stereo[count..count + 2].copy_from_slice(&[left, right]);
+            count += 2;
+        }
+        take(&stereo[..count]);
+    }
+}
+
+fn build_input_stream<T: SizedSample>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    shared: &Arc<CaptureShared>,
+) -> Result<cpal::Stream>
+where
+    f32: FromSample<T>,
+{
+    let channels = config.channels as usize;
+    let (callback_shared, error_shared) = (shared.clone(), shared.clone());
+    device.build_input_stream(
+        config,
+        move |input: &[T], _: &cpal::InputCallbackInfo| {
+            read_frames(input, channels, |stereo| callback_shared.take_input(stereo));
+        },
+        move |error| *error_shared.error.lock().unwrap_or_else(PoisonError::into_inner) = Some(error.to_string()),
+        None,
+    )
+    .context("Couldn't open the input stream")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_input_is_recorded_and_monitored() {
+        let read = |input: &[f32], channels: usize| {
+            let mut stereo = Vec::new();
+            read_frames(input, channels, |frames| stereo.extend_from_slice(frames));
+            stereo
+        };
+        assert_eq!(read(&[0.5, -0.25], 1), vec![0.5, 0.5, -0.25, -0.25]);
+        let mut stereo = Vec::new();
+        read_frames(&[i16::MIN, 0, 7], 3, |frames| stereo.extend_from_slice(frames));
+        assert_eq!(stereo, vec![-1.0, 0.0]);
+        // Buffers longer than the callback converts at once come through whole
+        let long: Vec<f32> = (0..CALLBACK_FRAMES * 3).map(|i| i as f32).collect();
+        assert_eq!(read(&long, 1).len(), CALLBACK_FRAMES * 6);
+
+        // At 200 Hz, MONITOR_SECONDS holds 10 frames
+        let shared = Arc::new(CaptureShared::new(200, Some(-6.0)));
+        let mut monitor = Monitor { shared: shared.clone(), sample_rate: 200 };
+        let input: Vec<f32> = (0..30).map(|i| i as f32 / 100.0).collect();
+        // Input only reaches the recording once it's drained off the callback's queue
+        shared.take_input(&input);
+        *shared.recording() = Some(Vec::new());
+        shared.is_recording.store(true, Ordering::Release);
+        shared.take_input(&input);
+        shared.drain_input(&mut shared.recording());
+        assert_eq!(shared.recording().as_ref().unwrap().len(), 30);
+
+        // Monitoring falls no more than MONITOR_SECONDS behind, and plays at its gain
+        let mut out = vec![1.0; 24];
+        monitor.fill(&mut out);
+        let gain = 10f32.powf(-6.0 / 20.0);
+        assert!((out[0] - 0.1 * gain).abs() < 1e-6);
+        assert_eq!(out[20..], [0.0; 4]);
+
+        shared.set_monitor_gain_db(None);
+        shared.take_input(&input);
+        monitor.fill(&mut out);
+        assert!(out.iter().all(|&sample| sample == 0.0));
+    }
+
+    #[test]
+    fn test_recorded_audio() {
+        let mut audio = RecordedAudio { sample_rate: 8000, samples: vec![0.5, -0.5, 0.25, -0.25] };
+        audio.shift(1);
+        assert_eq!(audio.samples, vec![0.0, 0.0, 0.5, -0.5, 0.25, -0.25]);
+        audio.shift(-2);
+        assert_eq!(audio.samples, vec![0.25, -0.25]);
+        assert_eq!((audio.frames(), audio.seconds()), (1, 1.0 / 8000.0));
+
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("take.wav");
+        audio.write_wav(&path).unwrap();
+        let mut reader = hound::WavReader::open(&path).unwrap();
+        assert_eq!((reader.spec().sample_rate, reader.spec().channels), (8000, 2));
+        let samples: Vec<f32> = reader.samples::<f32>().map(Result::unwrap).collect();
+        assert_eq!(samples, vec![0.25, -0.25]);
+    }
+}
@@ -0,0 +1,222 @@
+use anyhow::{Context, Result, anyhow, bail};
+use cpal::traits::{DeviceTrait, HostTrait};
+use cpal::{SupportedBufferSize, SupportedStreamConfigRange};
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+/// An audio system playback can go through
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Backend {
+    /// JACK, for low-latency setups on Linux. Needs the `jack` feature and a running server.
+    Jack,
+    /// ALSA on Linux, which also reaches PulseAudio and PipeWire through their ALSA plugins
+    Alsa,
+    /// The platform's own audio system elsewhere, e.g. CoreAudio or WASAPI
+    System,
+}
+
+impl Backend {
+    pub fn name(self) -> &'static str {
+        match self {
+            Backend::Jack => "jack",
+            Backend::Alsa => "alsa",
+            Backend::System => "system",
+        }
+    }
+
+    /// Backends this build can use here, most preferred first
+    pub fn available() -> Vec<Backend> {
+        let hosts: Vec<&str> = cpal::available_hosts().iter().map(|id| id.name()).collect();
+        let mut backends: Vec<Backend> = [Backend::Jack, Backend::Alsa]
+            .into_iter()
+            .filter(|backend| backend.host_name().is_some_and(|name| hosts.contains(&name)))
+            .collect();
+        if backends.is_empty() {
+            backends.push(Backend::System);
+        }
+        backends
+    }
+
+    /// JACK when its server is running, otherwise whatever else is available
+    pub fn preferred() -> Backend {
+        let available = Backend::available();
+        available.iter()
+            .copied()
+            .find(|backend| backend.host().is_ok_and(|host| host.default_output_device().is_some()))
+            .unwrap_or(available[0])
+    }
+
+    /// The cpal host this backend plays through
+    pub fn host(self) -> Result<cpal::Host> {
+        let Some(name) = self.host_name() else {
+            return Ok(cpal::default_host());
+        };
+        let id = cpal::available_hosts()
+            .into_iter()
+            .find(|id| id.name() == name)
+            .ok_or_else(|| anyhow!("The {} audio backend isn't available in this build", self))?;
+        cpal::host_from_id(id).with_context(|| format!("Couldn't open the {} audio backend", self))
+    }
+
+    /// The backend's output devices, its default first
+    pub fn output_devices(self) -> Result<Vec<OutputDevice>> {
+        let host = self.host()?;
+        let default = host.default_output_device().and_then(|device| device.name().ok());
+        let mut devices = Vec::new();
+        for device in host.output_devices().with_context(|| format!("Couldn't list {} output devices", self))? {
+            // Devices that are busy or vanish while being listed are left out
+            let (Ok(name), Ok(configs)) = (device.name(), device.supported_output_configs()) else {
+                continue;
+            };
+            let is_default = default.as_deref() == Some(name.as_str());
+            devices.push(OutputDevice::from_configs(name, self, is_default, configs));
+        }
+        devices.sort_by_key(|device| !device.is_default);
+        Ok(devices)
+    }
+
+    /// Names of the backend's input devices, its default first
+    pub fn input_devices(self) -> Result<Vec<String>> {
+        let host = self.host()?;
+        let default = host.default_input_device().and_then(|device| device.name().ok());
+        let mut names: Vec<String> = host.input_devices()
+            .with_context(|| format!("Couldn't list {} input devices", self))?
+            .filter_map(|device| device.name().ok())
+            .collect();
+        names.sort_by_key(|name| default.as_deref() != Some(name.as_str()));
+        Ok(names)
+    }
+
+    /// Name of the cpal host behind this backend, if it isn't just the platform default
+    fn host_name(self) -> Option<&'static str> {
+        match self {
+            Backend::Jack => Some("JACK"),
+            Backend::Alsa => Some("ALSA"),
+            Backend::System => None,
+        }
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Backend {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "jack" => Ok(Backend::Jack),
+            "alsa" | "pulse" | "pulseaudio" => Ok(Backend::Alsa),
+            "system" => Ok(Backend::System),
+            _ => bail!("Unknown audio backend '{}', expected jack, alsa or system", name),
+        }
+    }
+}
+
+/// An output device and what it can play
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputDevice {
+    pub name: String,
+    pub backend: Backend,
+    /// Whether it's the backend's default output
+    pub is_default: bool,
+    /// Most channels it plays
+    pub max_channels: u16,
+    /// Sample rates it plays, lowest first
+    pub sample_rates: Vec<RangeInclusive<u32>>,
+    /// Smallest and largest buffer it takes, in frames, if it says
+    pub buffer_frames: Option<RangeInclusive<u32>>,
+}
+
+impl OutputDevice {
+    fn from_configs(
+        name: String,
+        backend: Backend,
+        is_default: bool,
+        configs: impl IntoIterator<Item = SupportedStreamConfigRange>,
+    ) -> Self {
+        let mut device = Self { name, backend, is_default, max_channels: 0, sample_rates: Vec::new(), buffer_frames: None };
+        for config in configs {
+            device.max_channels = device.max_channels.max(config.channels());
+            let rates = config.min_sample_rate().0..=config.max_sample_rate().0;
+            if !device.sample_rates.contains(&rates) {
+                device.sample_rates.push(rates);
+            }
+            if let SupportedBufferSize::Range { min, max } = *config.buffer_size() {
+                device.buffer_frames = Some(match device.buffer_frames.take() {
+                    Some(frames) => (*frames.start()).min(min)..=(*frames.end()).max(max),
+                    None => min..=max,
+                });
+            }
+        }
+        device.sample_rates.sort_by_key(|rates| (*rates.start(), *rates.end()));
+        device
+    }
+
+    pub fn supports_sample_rate(&self, sample_rate: u32) -> bool {
+        self.sample_rates.iter().any(|rates| rates.contains(&sample_rate))
+    }
+}
+
+impl fmt::Display for OutputDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.backend, self.name)?;
+        if self.is_default {
+            write!(f, " (default)")?;
+        }
+        let rates: Vec<String> = self.sample_rates.iter()
+            .map(|rates| if rates.start() == rates.end() {
+                rates.start().to_string()
+            } else {
+                format!("{}-{}", rates.start(), rates.end())
+            })
+            .collect();
+        write!(f, ", {} channels, {} Hz", self.max_channels, rates.join(", "))?;
+        if let Some(frames) = &self.buffer_frames {
+            write!(f, ", buffers of {}-{} frames", frames.start(), frames.end())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cpal::{SampleFormat, SampleRate};
+
+    #[test]
+    fn test_backend_names() {
+        for backend in [Backend::Jack, Backend::Alsa, Backend::System] {
+            assert_eq!(backend.to_string().parse::<Backend>().unwrap(), backend);
+        }
+        assert_eq!("PulseAudio".parse::<Backend>().unwrap(), Backend::Alsa);
+        assert!("coreaudio".parse::<Backend>().is_err());
+        assert!(!Backend::available().is_empty());
+    }
+
+    #[test]
+    fn test_device_summary() {
+        let config = |channels, min, max, buffer| {
+            SupportedStreamConfigRange::new(channels, SampleRate(min), SampleRate(max), buffer, SampleFormat::F32)
+        };
+        let configs = [
+            config(2, 44100, 48000, SupportedBufferSize::Range { min: 64, max: 4096 }),
+            config(6, 96000, 96000, SupportedBufferSize::Range { min: 128, max: 8192 }),
+            config(2, 8000, 8000, SupportedBufferSize::Unknown),
+            config(6, 44100, 48000, SupportedBufferSize::Range { min: 64, max: 4096 }),
+        ];
+        let device = OutputDevice::from_configs("hw:0".to_string(), Backend::Alsa, true, configs);
+        assert_eq!(device.max_channels, 6);
+        assert_eq!(device.sample_rates, vec![8000..=8000, 44100..=48000, 96000..=96000]);
+        assert_eq!(device.buffer_frames, Some(64..=8192));
+        assert!(device.supports_sample_rate(44100) && !device.supports_sample_rate(22050));
+        assert_eq!(
+            device.to_string(),
+            "alsa: hw:0 (default), 6 channels, 8000, 44100-48000, 96000 Hz, buffers of 64-8192 frames"
+        );
+    }
+}
@@ -0,0 +1,506 @@
+//! Real-time playback through an audio output device, over JACK, ALSA or the platform's
+//! own audio system. A producer thread pulls
+//! blocks of audio from a [`Source`] into a bounded queue, and the device callback drains
+//! it. When the queue runs dry the callback plays silence and counts an underrun rather
+//! than waiting. Sources too heavy to render in real time can be rendered further ahead
+//! with [`RenderAhead`]. Input devices are recorded and monitored with [`Capture`].
+
+use anyhow::{Context, Result, anyhow, bail};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{BufferSize, FromSample, SampleFormat, SizedSample, SupportedBufferSize};
+use dawww_render::AudioEngine;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+pub mod ahead;
+pub mod capture;
+pub mod device;
+pub mod queue;
+mod ring;
+
+pub use ahead::{Invalidator, RenderAhead, Seek};
+pub use capture::{Capture, CaptureOptions, Monitor, RecordedAudio};
+pub use device::{Backend, OutputDevice};
+pub use queue::FrameQueue;
+
+/// Audio to play: interleaved stereo frames at a fixed sample rate
+pub trait Source: Send + 'static {
+    fn sample_rate(&self) -> u32;
+
+    /// Fill `out` with the next interleaved stereo frames, each sample between -1 and 1
+    fn fill(&mut self, out: &mut [f32]);
+
+    /// Whether there's nothing more to play. Playback ends once what's queued has played.
+    fn is_finished(&self) -> bool {
+        false
+    }
+}
+
+/// Plays the song, rendering it before the first block if it isn't already
+impl Source for AudioEngine {
+    fn sample_rate(&self) -> u32 {
+        AudioEngine::sample_rate(self)
+    }
+
+    fn fill(&mut self, out: &mut [f32]) {
+        self.process(out);
+    }
+
+    fn is_finished(&self) -> bool {
+        AudioEngine::is_finished(self)
+    }
+}
+
+/// A source shared with other threads, e.g. to seek or pause it while it plays
+impl<S: Source> Source for Arc<Mutex<S>> {
+    fn sample_rate(&self) -> u32 {
+        self.lock().unwrap_or_else(PoisonError::into_inner).sample_rate()
+    }
+
+    fn fill(&mut self, out: &mut [f32]) {
+        self.lock().unwrap_or_else(PoisonError::into_inner).fill(out);
+    }
+
+    fn is_finished(&self) -> bool {
+        self.lock().unwrap_or_else(PoisonError::into_inner).is_finished()
+    }
+}
+
+/// Where audio plays, how much is produced at a time and how much is queued ahead of the
+/// device
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlaybackOptions {
+    /// Audio system to play through; `None` picks `Backend::preferred`
+    pub backend: Option<Backend>,
+    /// Name of the output device, as `Backend::output_devices` lists it; `None` plays on
+    /// the backend's default output
+    pub device: Option<String>,
+    /// Frames the device asks for at a time; `None` leaves it to the device
+    pub device_buffer_frames: Option<u32>,
+    /// Frames each call to `Source::fill` produces
+    pub block_frames: usize,
+    /// Frames queued ahead of the device. More survives longer stalls in the source, at
+    /// the cost of latency.
+    pub buffer_frames: usize,
+}
+
+impl Default for PlaybackOptions {
+    /// About 46 ms blocks and 186 ms of queue at 44.1 kHz
+    fn default() -> Self {
+        Self { backend: None, device: None, device_buffer_frames: None, block_frames: 2048, buffer_frames: 8192 }
+    }
+}
+
+impl PlaybackOptions {
+    /// Small blocks and a short queue, for playback that has to follow edits closely
+    pub fn low_latency() -> Self {
+        Self { block_frames: 256, buffer_frames: 2048, ..Self::default() }
+    }
+
+    pub fn with_backend(mut self, backend: Backend) -> Self {
+        self.backend = Some(backend);
+        self
+    }
+
+    pub fn with_device(mut self, name: &str) -> Self {
+        self.device = Some(name.to_string());
+        self
+    }
+
+    pub fn with_device_buffer_frames(mut self, frames: u32) -> Self {
+        self.device_buffer_frames = Some(frames);
+        self
+    }
+
+    /// How long audio waits in the queue before the device takes it, once the queue is
+    /// full, at `sample_rate`. The device's own buffering comes on top.
+    pub fn queue_latency(&self, sample_rate: u32) -> Duration {
+        Duration::from_secs_f64(self.buffer_frames as f64 / f64::from(sample_rate))
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.block_frames == 0 {
+            bail!("Playback blocks must be at least one frame");
+        }
+        if self.buffer_frames < self.block_frames {
+            bail!("The playback buffer must hold at least one block");
+        }
+        if self.device_buffer_frames == Some(0) {
+            bail!("Device buffers must be at least one frame");
+        }
+        Ok(())
+    }
+}
+
+/// State shared by the producer thread, the device callback and the `Playback` handle
+struct Shared {
+    queue: FrameQueue,
+    stop: AtomicBool,
+    /// Set once the first block is queued; the device plays silence until then without
+    /// counting underruns
+    primed: AtomicBool,
+    /// Set once the source has nothing more to play
+    finished: AtomicBool,
+    underruns: AtomicU64,
+    /// Nanoseconds from the device asking for audio to playing it, as it last reported;
+    /// `u64::MAX` until it has
+    device_latency: AtomicU64,
+    error: Mutex<Option<String>>,
+}
+
+impl Shared {
+    fn new(options: &PlaybackOptions) -> Self {
+        Self {
+            queue: FrameQueue::new(options.buffer_frames * 2),
+            stop: AtomicBool::new(false),
+            primed: AtomicBool::new(false),
+            finished: AtomicBool::new(false),
+            underruns: AtomicU64::new(0),
+            device_latency: AtomicU64::new(u64::MAX),
+            error: Mutex::new(None),
+        }
+    }
+
+    /// Fill a device buffer of `channels` channels from the queue, playing silence for
+    /// anything it's short of. `stereo` is scratch space for the queued frames.
+    fn play<T: SizedSample + FromSample<f32>>(&self, out: &mut [T], channels: usize, stereo: &mut Vec<f32>) {
+        stereo.resize(out.len() / channels * 2, 0.0);
+        let filled = self.queue.pop_into(stereo);
+        if filled < stereo.len() {
+            stereo[filled..].fill(0.0);
+            if self.primed.load(Ordering::Relaxed) && !self.finished.load(Ordering::Relaxed) {
+                self.underruns.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        write_frames(out, channels, stereo);
+    }
+
+    fn device_latency(&self) -> Option<Duration> {
+        match self.device_latency.load(Ordering::Relaxed) {
+            u64::MAX => None,
+            nanos => Some(Duration::from_nanos(nanos)),
+        }
+    }
+
+    /// How long audio the source produces now takes to be heard: the queue ahead of it
+    /// plus the device's latency, if it's reported one
+    fn output_latency(&self, sample_rate: u32) -> Duration {
+        let queued = self.queue.len() as f64 / 2.0 / f64::from(sample_rate);
+        Duration::from_secs_f64(queued) + self.device_latency().unwrap_or_default()
+    }
+}
+
+/// Pull blocks from `source` into the queue until it finishes or playback stops
+fn produce(mut source: impl Source, shared: &Shared, block_frames: usize) {
+    let mut block = vec![0.0; block_frames * 2];
+    while !shared.stop.load(Ordering::Relaxed) {
+        if source.is_finished() {
+            shared.finished.store(true, Ordering::Relaxed);
+            break;
+        }
+        source.fill(&mut block);
+        if !shared.queue.push(&block, &shared.stop) {
+            break;
+        }
+        shared.primed.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Spread interleaved stereo frames over a device's `channels`: mono devices get the
+/// average of the pair, and channels past the first two are silent
+fn write_frames<T: SizedSample + FromSample<f32>>(out: &mut [T], channels: usize, stereo: &[f32]) {
+    let sample = |value: f32| T::from_sample(value.clamp(-1.0, 1.0));
+    for (frame, pair) in out.chunks_exact_mut(channels).zip(stereo.chunks_exact(2)) {
+        match frame {
+            [mono] => *mono = sample((pair[0] + pair[1]) / 2.0),
+            _ => {
+                for (channel, out) in frame.iter_mut().enumerate() {
+                    *out = sample(pair.get(channel).copied().unwrap_or(0.0));
+                }
+            }
+        }
+    }
+}
+
+/// A source playing on the default output device. Playback stops when this is dropped.
+pub struct Playback {
+    stream: cpal::Stream,
+    shared: Arc<Shared>,
+    producer: Option<JoinHandle<()>>,
+    backend: Backend,
+    device: String,
+    sample_rate: u32,
+    channels: u16,
+}
+
+impl Playback {
+    /// Start playing `source` on the preferred backend's default output with the default
+    /// options
+    pub fn start(source: impl Source) -> Result<Self> {
+        Self::start_with(source, PlaybackOptions::default())
+    }
+
+    /// Start playing `source` where `options` say. The device runs at the source's sample
+    /// rate, so pick a rate the device lists, with as many channels as it has by default.
+    pub fn start_with(source: impl Source, options: PlaybackOptions) -> Result<Self> {
+        options.validate()?;
+        let backend = options.backend.unwrap_or_else(Backend::preferred);
+        let host = backend.host()?;
+        let device = match &options.device {
+            Some(name) => host.output_devices()
+                .with_context(|| format!("Couldn't list {} output devices", backend))?
+                .find(|device| device.name().is_ok_and(|found| found == *name))
+                .with_context(|| format!("No {} output device named '{}'", backend, name))?,
+            None => host.default_output_device().with_context(|| format!("No {} output device found", backend))?,
+        };
+        let device_name = device.name().unwrap_or_default();
+        let supported = device.default_output_config().context("Couldn't read the output device's configuration")?;
+        let sample_rate = source.sample_rate();
+        let plays_rate = device.supported_output_configs()
+            .is_ok_and(|mut configs| configs.any(|config| (config.min_sample_rate().0..=config.max_sample_rate().0).contains(&sample_rate)));
+        if !plays_rate {
+            bail!("Output device '{}' can't play at {} Hz", device_name, sample_rate);
+        }
+        let buffer_size = match options.device_buffer_frames {
+            Some(frames) => {
+                if let SupportedBufferSize::Range { min, max } = *supported.buffer_size() {
+                    if !(min..=max).contains(&frames) {
+                        bail!("Output device '{}' takes buffers of {} to {} frames, not {}", device_name, min, max, frames);
+                    }
+                }
+                BufferSize::Fixed(frames)
+            }
+            None => BufferSize::Default,
+        };
+        let config = cpal::StreamConfig {
+            channels: supported.channels(),
+            sample_rate: cpal::SampleRate(sample_rate),
+            buffer_size,
+        };
+
+        let shared = Arc::new(Shared::new(&options));
+        let producer = {
+            let (shared, block_frames) = (shared.clone(), options.block_frames);
+            thread::Builder::new()
+                .name("dawww-playback".to_string())
+                .spawn(move || produce(source, &shared, block_frames))?
+        };
+        let stream = match supported.sample_format() {
+            SampleFormat::F32 => build_stream::<f32>(&device, &config, &shared),
+            SampleFormat::F64 => build_stream::<f64>(&device, &config, &shared),
+            SampleFormat::I16 => build_stream::<i16>(&device, &config, &shared),
+            SampleFormat::U16 => build_stream::<u16>(&device, &config, &shared),
+            SampleFormat::I32 => build_stream::<i32>(&device, &config, &shared),
+            format => Err(anyhow!("Unsupported output sample format {}", format)),
+        };
+        let mut playback = Self {
+            stream: stream?,
+            shared,
+            producer: Some(producer),
+            backend,
+            device: device_name,
+            sample_rate,
+            channels: config.channels,
+        };
+        if let Err(error) = playback.stream.play() {
+            playback.shutdown();
+            return Err(error).context("Couldn't start the output stream");
+        }
+        Ok(playback)
+    }
+
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    /// Name of the device playing
+    pub fn device(&self) -> &str {
+        &self.device
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Channels the device is playing
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    /// How long the device takes from asking for audio to playing it, once it's said
+    pub fn device_latency(&self) -> Option<Duration> {
+        self.shared.device_latency()
+    }
+
+    /// How long audio the source produces now takes to be heard, as measured: what's
+    /// queued plus the device's latency. Recorded notes line up with what the player heard
+    /// when moved back by this much.
+    pub fn output_latency(&self) -> Duration {
+        self.shared.output_latency(self.sample_rate)
+    }
+
+    /// How many times the device asked for audio the source hadn't produced yet
+    pub fn underruns(&self) -> u64 {
+        self.shared.underruns.load(Ordering::Relaxed)
+    }
+
+    /// The last error the device reported, if any
+    pub fn error(&self) -> Option<String> {
+        self.shared.error.lock().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+
+    /// Whether the source has finished and everything it produced has played
+    pub fn is_finished(&self) -> bool {
+        self.shared.finished.load(Ordering::Relaxed) && self.shared.queue.is_empty()
+    }
+
+    /// Stop the device pulling audio. The source stops once the queue is full.
+    pub fn pause(&self) -> Result<()> {
+        self.stream.pause().context("Couldn't pause the output stream")
+    }
+
+    pub fn resume(&self) -> Result<()> {
+        self.stream.play().context("Couldn't resume the output stream")
+    }
+
+    /// Stop the producer thread and wait for it
+    fn shutdown(&mut self) {
+        self.shared.stop.store(true, Ordering::Relaxed);
+        self.shared.queue.wake();
+        if let Some(producer) = self.producer.take() {
+            let _ = producer.join();
+        }
+    }
+}
+
+impl Drop for Playback {
+    fn drop(&mut self) {
+        let _ = self.stream.pause();
+        self.shutdown();
+    }
+}
+
+fn build_stream<T: SizedSample + FromSample<f32>>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    shared: &Arc<Shared>,
+) -> Result<cpal::Stream> {
+    let channels = config.channels as usize;
+    let (callback_shared, error_shared) = (shared.clone(), shared.clone());
+    let mut stereo = Vec::new();
+    device.build_output_stream(
+        config,
+        move |out: &mut [T], info: &cpal::OutputCallbackInfo| {
+            let timestamp = info.timestamp();
+            if let Some(latency) = timestamp.playback.duration_since(&timestamp.callback) {
+                let nanos = u64::try_from(latency.as_nanos()).unwrap_or(u64::MAX - 1);
+                callback_shared.device_latency.store(nanos, Ordering::Relaxed);
+            }
+            callback_shared.play(out, channels, &mut stereo);
+        },
+        move |error| *error_shared.error.lock().unwrap_or_else(PoisonError::into_inner) = Some(error.to_string()),
+        None,
+    )
+    .context("Couldn't open the output stream")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Counts up from 1, one step per frame, for `frames` frames
+    struct Ramp {
+        next: f32,
+        frames: usize,
+    }
+
+    impl Source for Ramp {
+        fn sample_rate(&self) -> u32 {
+            8000
+        }
+
+        fn fill(&mut self, out: &mut [f32]) {
+            for frame in out.chunks_exact_mut(2) {
+                frame.fill(self.next / 100.0);
+                self.next += 1.0;
+            }
+        }
+
+        fn is_finished(&self) -> bool {
+            self.next as usize > self.frames
+        }
+    }
+
+    #[test]
+    fn test_write_frames() {
+        let stereo = [0.5, -0.5, 1.0, 2.0];
+        let mut mono = [0.0_f32; 2];
+        write_frames(&mut mono, 1, &stereo);
+        assert_eq!(mono, [0.0, 1.0]);
+
+        let mut surround = [9.0_f32; 12];
+        write_frames(&mut surround, 6, &stereo);
+        assert_eq!(surround, [0.5, -0.5, 0.0, 0.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 0.0, 0.0]);
+
+        let mut ints = [0_i16; 4];
+        write_frames(&mut ints, 2, &stereo);
+        assert_eq!(ints[0], 16384);
+        assert_eq!(ints[1], -16384);
+        assert_eq!(ints[3], i16::MAX);
+    }
+
+    #[test]
+    fn test_underruns_are_counted_once_primed() {
+        let options = PlaybackOptions { block_frames: 2, buffer_frames: 4, ..PlaybackOptions::default() };
+        let shared = Shared::new(&options);
+        let mut stereo = Vec::new();
+        let mut out = [1.0_f32; 4];
+
+        // Waiting for the first block is silent but isn't an underrun
+        shared.play(&mut out, 2, &mut stereo);
+        assert_eq!(out, [0.0; 4]);
+        assert_eq!(shared.underruns.load(Ordering::Relaxed), 0);
+
+        produce(Ramp { next: 1.0, frames: 3 }, &shared, options.block_frames);
+        assert!(shared.finished.load(Ordering::Relaxed));
+        shared.play(&mut out, 2, &mut stereo);
+        assert_eq!(out, [0.01, 0.01, 0.02, 0.02]);
+
+        // A finished source running out isn't an underrun either
+        shared.play(&mut out, 2, &mut stereo);
+        assert_eq!(out, [0.03, 0.03, 0.04, 0.04]);
+        shared.play(&mut out, 2, &mut stereo);
+        assert_eq!(out, [0.0; 4]);
+        assert_eq!(shared.underruns.load(Ordering::Relaxed), 0);
+
+        // One that's still playing is
+        shared.finished.store(false, Ordering::Relaxed);
+        shared.play(&mut out, 2, &mut stereo);
+        assert_eq!(shared.underruns.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_options() {
+        assert!(PlaybackOptions::default().validate().is_ok());
+        assert!(PlaybackOptions::low_latency().validate().is_ok());
+        assert!(PlaybackOptions { block_frames: 0, buffer_frames: 8, ..PlaybackOptions::default() }.validate().is_err());
+        assert!(PlaybackOptions { block_frames: 16, buffer_frames: 8, ..PlaybackOptions::default() }.validate().is_err());
+        assert!(PlaybackOptions::default().with_device_buffer_frames(0).validate().is_err());
+        let options = PlaybackOptions::low_latency().with_backend(Backend::Jack).with_device("system").with_device_buffer_frames(128);
+        assert!(options.validate().is_ok());
+        assert_eq!(PlaybackOptions::low_latency().queue_latency(8000), Duration::from_millis(256));
+    }
+
+    #[test]
+    fn test_output_latency() {
+        let shared = Shared::new(&PlaybackOptions::default());
+        assert_eq!(shared.device_latency(), None);
+        assert_eq!(shared.output_latency(8000), Duration::ZERO);
+
+        produce(Ramp { next: 1.0, frames: 80 }, &shared, 40);
+        shared.device_latency.store(5_000_000, Ordering::Relaxed);
+        assert_eq!(shared.output_latency(8000), Duration::from_millis(15));
+    }
+}
@@ -0,0 +1,112 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+/// How long a waiting producer sleeps before checking whether playback has stopped
+const STOP_POLL: Duration = Duration::from_millis(20);
+
+/// A bounded queue of interleaved samples between the thread producing audio and the
+/// device callback playing it. The callback never waits: it takes what's there.
+pub struct FrameQueue {
+    samples: Mutex<VecDeque<f32>>,
+    space: Condvar,
+    capacity: usize,
+}
+
+impl FrameQueue {
+    /// A queue holding up to `capacity` samples
+    pub fn new(capacity: usize) -> Self {
+        Self { samples: Mutex::new(VecDeque::with_capacity(capacity)), space: Condvar::new(), capacity }
+    }
+
+    /// Append `block`, waiting while the queue is too full to take it. Gives up without
+    /// adding it, returning false, once `stop` is set.
+    pub fn push(&self, block: &[f32], stop: &AtomicBool) -> bool {
+        let mut samples = self.lock();
+        // A block bigger than the whole queue goes in once the queue is empty
+        while samples.len() + block.len() > self.capacity && !samples.is_empty() {
+            if stop.load(Ordering::Relaxed) {
+                return false;
+            }
+            samples = self.space.wait_timeout(samples, STOP_POLL).unwrap_or_else(PoisonError::into_inner).0;
+        }
+        samples.extend(block);
+        true
+    }
+
+    /// Move as many queued samples as fit into the start of `out`, returning how many
+    pub fn pop_into(&self, out: &mut [f32]) -> usize {
+        let mut samples = self.lock();
+        let count = out.len().min(samples.len());
+        for (out, sample) in out.iter_mut().zip(samples.drain(..count)) {
+            *out = sample;
+        }
+        drop(samples);
+        self.space.notify_one();
+        count
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Wake a producer waiting for space, e.g. so it notices playback stopping
+    pub fn wake(&self) {
+        self.space.notify_all();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, VecDeque<f32>> {
+        self.samples.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_push_waits_for_space() {
+        let queue = Arc::new(FrameQueue::new(4));
+        let stop = Arc::new(AtomicBool::new(false));
+        assert!(queue.push(&[1.0, 2.0, 3.0], &stop));
+
+        let producer = {
+            let (queue, stop) = (queue.clone(), stop.clone());
+            thread::spawn(move || queue.push(&[4.0, 5.0], &stop))
+        };
+        // The second block only fits once some of the first has been taken
+        let mut out = [0.0; 4];
+        assert_eq!(queue.pop_into(&mut out[..1]), 1);
+        assert!(producer.join().unwrap());
+        assert_eq!(queue.pop_into(&mut out), 4);
+        assert_eq!(out, [2.0, 3.0, 4.0, 5.0]);
+        queue.push(&[6.0], &stop);
+
+        // Short reads take what's there
+        let mut out = [9.0; 3];
+        assert_eq!(queue.pop_into(&mut out), 1);
+        assert_eq!(out, [6.0, 9.0, 9.0]);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_push_gives_up_when_stopped() {
+        let queue = FrameQueue::new(2);
+        let stop = AtomicBool::new(false);
+        assert!(queue.push(&[1.0, 2.0], &stop));
+        stop.store(true, Ordering::Relaxed);
+        assert!(!queue.push(&[3.0], &stop));
+        assert_eq!(queue.len(), 2);
+
+        // Oversized blocks still go into an empty queue
+        let queue = FrameQueue::new(2);
+        assert!(queue.push(&[1.0; 5], &stop));
+    }
+}
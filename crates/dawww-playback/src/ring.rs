@@ -0,0 +1,80 @@
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+/// A fixed-size queue of samples from one thread to another that never locks or
+/// allocates, so an audio device callback can write to it. Samples that don't fit are
+/// dropped. Only one thread may push and one pop at a time.
+pub(crate) struct SampleRing {
+    /// Samples as `f32` bits
+    samples: Box<[AtomicU32]>,
+    /// Samples pushed so far, wrapping
+    written: AtomicUsize,
+    /// Samples popped or skipped so far, wrapping
+    read: AtomicUsize,
+}
+
+impl SampleRing {
+    /// A ring holding up to `capacity` samples
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            samples: (0..capacity.max(1)).map(|_| AtomicU32::new(0)).collect(),
+            written: AtomicUsize::new(0),
+            read: AtomicUsize::new(0),
+        }
+    }
+
+    /// Add as many of `samples` as there's room for, returning how many
+    pub(crate) fn push(&self, samples: &[f32]) -> usize {
+        let written = self.written.load(Ordering::Relaxed);
+        let room = self.samples.len() - written.wrapping_sub(self.read.load(Ordering::Acquire));
+        let count = samples.len().min(room);
+        for (offset, sample) in samples[..count].iter().enumerate() {
+            self.slot(written.wrapping_add(offset)).store(sample.to_bits(), Ordering::Relaxed);
+        }
+        self.written.store(written.wrapping_add(count), Ordering::Release);
+        count
+    }
+
+    /// Move as many waiting samples as fit into the start of `out`, returning how many
+    pub(crate) fn pop_into(&self, out: &mut [f32]) -> usize {
+        let read = self.read.load(Ordering::Relaxed);
+        let count = out.len().min(self.written.load(Ordering::Acquire).wrapping_sub(read));
+        for (offset, out) in out[..count].iter_mut().enumerate() {
+            *out = f32::from_bits(self.slot(read.wrapping_add(offset)).load(Ordering::Relaxed));
+        }
+        self.read.store(read.wrapping_add(count), Ordering::Release);
+        count
+    }
+
+    /// Throw away the oldest waiting samples until no more than `keep` are left
+    pub(crate) fn skip_to(&self, keep: usize) {
+        let read = self.read.load(Ordering::Relaxed);
+        let waiting = self.written.load(Ordering::Acquire).wrapping_sub(read);
+        self.read.store(read.wrapping_add(waiting.saturating_sub(keep)), Ordering::Release);
+    }
+
+    fn slot(&self, index: usize) -> &AtomicU32 {
+        &self.samples[index % self.samples.len()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ring_wraps_and_drops_what_doesnt_fit() {
+        let ring = SampleRing::new(4);
+        assert_eq!(ring.push(&[1.0, 2.0, 3.0]), 3);
+        let mut out = [0.0; 2];
+        assert_eq!(ring.pop_into(&mut out), 2);
+        assert_eq!(out, [1.0, 2.0]);
+
+        // The write goes round the end, and whatever is past the room left is dropped
+        assert_eq!(ring.push(&[4.0, 5.0, 6.0, 7.0]), 3);
+        ring.skip_to(2);
+        let mut out = [0.0; 4];
+        assert_eq!(ring.pop_into(&mut out), 2);
+        assert_eq!(out[..2], [5.0, 6.0]);
+        assert_eq!(ring.pop_into(&mut out), 0);
+    }
+}
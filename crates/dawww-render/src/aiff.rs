@@ -0,0 +1,134 @@
+use dawww_core::{MixdownSettings, SampleFormat};
+use anyhow::{Result, bail};
+use std::path::Path;
+use crate::speakers::from_stereo;
+use crate::stereo::Stereo;
+use crate::wav::{Quantizer, check_channels, interleave, to_int};
+
+/// Write `buffer`, scaled by `gain`, to an AIFF file in the mixdown's channel layout and bit
+/// depth. Samples are clipped and dithered as `write_wav` does; AIFF only holds integers.
+pub fn write_aiff(path: &Path, buffer: &Stereo, settings: &MixdownSettings, sample_rate: u32, gain: f64, seed: u64) -> Result<()> {
+    write_channels(path, &from_stereo(buffer, settings.channels), settings, sample_rate, gain, seed)
+}
+
+/// Write one signal for each of the mixdown's channels to an AIFF file as `write_aiff` does,
+/// in the same order as WAV files
+pub fn write_channels<C: AsRef<[f64]>>(
+    path: &Path,
+    channels: &[C],
+    settings: &MixdownSettings,
+    sample_rate: u32,
+    gain: f64,
+    seed: u64,
+) -> Result<()> {
+    settings.validate()?;
+    if settings.sample_format == SampleFormat::Float {
+        bail!("AIFF files only hold integer samples");
+    }
+    let frames = check_channels(channels, settings)?;
+    let channel_count = channels.len();
+    let bytes_per_sample = settings.bit_depth as usize / 8;
+
+    // Samples are big-endian, and signed even at 8 bits
+    let mut data: Vec<u8> = Vec::with_capacity(frames * channel_count * bytes_per_sample);
+    let mut quantizers = Quantizer::for_channels(settings, seed);
+    for (i, sample) in interleave(channels, gain).enumerate() {
+        let value = match settings.bit_depth {
+            8 | 16 => quantizers[i % channel_count].quantize(sample),
+            bits => to_int(sample, bits),
+        };
+        data.extend(&value.to_be_bytes()[4 - bytes_per_sample..]);
+    }
+
+    let mut common = Vec::with_capacity(18);
+    common.extend((channel_count as i16).to_be_bytes());
+    common.extend((frames as u32).to_be_bytes());
+    common.extend((settings.bit_depth as i16).to_be_bytes());
+    common.extend(extended(sample_rate as f64));
+    // The sound data starts with an offset and block size, neither of which is used
+    let mut sound = vec![0; 8];
+    sound.extend(data);
+
+    let mut form = b"AIFF".to_vec();
+    write_chunk(&mut form, b"COMM", &common);
+    write_chunk(&mut form, b"SSND", &sound);
+    let mut file = b"FORM".to_vec();
+    file.extend((form.len() as u32).to_be_bytes());
+    file.extend(form);
+    std::fs::write(path, file)?;
+    Ok(())
+}
+
+/// Append a chunk: its ID, length and data, padded to an even length
+fn write_chunk(form: &mut Vec<u8>, id: &[u8; 4], data: &[u8]) {
+    form.extend(id);
+    form.extend((data.len() as u32).to_be_bytes());
+    form.extend(data);
+    if data.len() % 2 == 1 {
+        form.push(0);
+    }
+}
+
+/// A positive number as the 80-bit extended float AIFF stores its sample rate in: a biased
+/// exponent, then a 64-bit mantissa with an explicit leading one
+fn extended(value: f64) -> [u8; 10] {
+    let exponent = value.log2().floor() as i32;
+    let mantissa = (value / 2_f64.powi(exponent) * 2_f64.powi(63)) as u64;
+    let mut bytes = [0; 10];
+    bytes[..2].copy_from_slice(&((16383 + exponent) as u16).to_be_bytes());
+    bytes[2..].copy_from_slice(&mantissa.to_be_bytes());
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dawww_core::ChannelLayout;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_extended() {
+        assert_eq!(extended(44100.0), [0x40, 0x0E, 0xAC, 0x44, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(extended(48000.0), [0x40, 0x0E, 0xBB, 0x80, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(extended(8000.0), [0x40, 0x0B, 0xFA, 0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_write_aiff() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("out.aiff");
+        let buffer = Stereo { left: vec![0.0, 0.5, 2.0], right: vec![0.0, -0.5, -2.0] };
+
+        write_aiff(&path, &buffer, &MixdownSettings::new(8000, 24), 8000, 1.0, 0).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(&bytes[..4], b"FORM");
+        assert_eq!(u32::from_be_bytes(bytes[4..8].try_into().unwrap()) as usize, bytes.len() - 8);
+        assert_eq!(&bytes[8..16], b"AIFFCOMM");
+        assert_eq!(&bytes[16..20], [0, 0, 0, 18]);
+        // Two channels, three frames, 24 bits
+        assert_eq!(&bytes[20..28], [0, 2, 0, 0, 0, 3, 0, 24]);
+        assert_eq!(&bytes[28..38], extended(8000.0));
+        assert_eq!(&bytes[38..42], b"SSND");
+        assert_eq!(&bytes[42..46], [0, 0, 0, 8 + 18]);
+        let samples = &bytes[54..];
+        assert_eq!(&samples[6..12], [0x40, 0, 0, 0xC0, 0, 0]);
+        assert_eq!(&samples[12..], [0x7F, 0xFF, 0xFF, 0x80, 0, 0x01]);
+
+        // An odd number of 8-bit samples is padded to an even chunk
+        let mono = MixdownSettings::new(8000, 8).with_channels(ChannelLayout::Mono);
+        write_aiff(&path, &buffer, &mono, 8000, 1.0, 0).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(&bytes[42..46], [0, 0, 0, 8 + 3]);
+        assert_eq!(&bytes[54..], [0, 0, 0, 0]);
+        let loud = Stereo::from_mono(vec![0.5, -0.5, 1.0]);
+        write_aiff(&path, &loud, &mono, 8000, 1.0, 0).unwrap();
+        assert_eq!(&std::fs::read(&path).unwrap()[54..], [64, 192, 127, 0]);
+
+        // Surround files hold their channels in WAV order
+        let quad = MixdownSettings::new(8000, 8).with_channels(ChannelLayout::Quad);
+        write_channels(&path, &[[0.5], [-0.5], [0.0], [1.0]], &quad, 8000, 1.0, 0).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(&bytes[20..28], [0, 4, 0, 0, 0, 1, 0, 8]);
+        assert_eq!(&bytes[54..], [64, 192, 0, 127]);
+    }
+}
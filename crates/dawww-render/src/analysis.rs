@@ -0,0 +1,88 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use crate::compare::{rms, to_db};
+use crate::loudness;
+use crate::stereo::Stereo;
+
+/// Levels of a rendered mix, for checking it without listening. Levels of silence are
+/// negative infinity, which JSON writes as `null`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RenderAnalysis {
+    pub sample_rate: u32,
+    pub frames: usize,
+    /// Highest level between samples as well as on them, in dBTP
+    pub true_peak_db: f64,
+    /// Highest sample level in dBFS
+    pub sample_peak_db: f64,
+    /// RMS level of both channels in dBFS
+    pub rms_db: f64,
+    /// EBU R128 integrated loudness; `None` for silence
+    pub integrated_lufs: Option<f64>,
+    /// Mean of the left and right channels
+    pub dc_offset: [f64; 2],
+    /// Samples at or past full scale, which integer files clip
+    pub clipped_samples: usize,
+    /// Sample peak of each track after its insert effects, before the mixer, in dBFS
+    pub track_peaks_db: BTreeMap<String, f64>,
+}
+
+/// Analyze a mix as written to the file (before clipping) and the tracks that fed it
+pub fn analyze(mix: &Stereo, tracks: &BTreeMap<&str, Stereo>, sample_rate: u32) -> RenderAnalysis {
+    let mean = |channel: &[f64]| if channel.is_empty() { 0.0 } else { channel.iter().sum::<f64>() / channel.len() as f64 };
+    let both: Vec<f64> = mix.left.iter().chain(&mix.right).copied().collect();
+    RenderAnalysis {
+        sample_rate,
+        frames: mix.len(),
+        true_peak_db: to_db(loudness::true_peak(mix)),
+        sample_peak_db: to_db(mix.peak()),
+        rms_db: to_db(rms(&both)),
+        integrated_lufs: loudness::integrated_loudness(mix, sample_rate),
+        dc_offset: [mean(&mix.left), mean(&mix.right)],
+        clipped_samples: both.iter().filter(|sample| sample.abs() >= 1.0).count(),
+        track_peaks_db: tracks.iter().map(|(id, track)| (id.to_string(), to_db(track.peak()))).collect(),
+    }
+}
+
+impl fmt::Display for RenderAnalysis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Length:          {} frames at {} Hz", self.frames, self.sample_rate)?;
+        writeln!(f, "True peak:       {:.2} dBTP", self.true_peak_db)?;
+        writeln!(f, "Sample peak:     {:.2} dBFS", self.sample_peak_db)?;
+        writeln!(f, "RMS level:       {:.2} dBFS", self.rms_db)?;
+        match self.integrated_lufs {
+            Some(lufs) => writeln!(f, "Loudness:        {:.2} LUFS", lufs)?,
+            None => writeln!(f, "Loudness:        silent")?,
+        }
+        writeln!(f, "DC offset:       L={:+.5} R={:+.5}", self.dc_offset[0], self.dc_offset[1])?;
+        writeln!(f, "Clipped samples: {}", self.clipped_samples)?;
+        writeln!(f, "Track peaks:")?;
+        for (track, peak_db) in &self.track_peaks_db {
+            writeln!(f, "  {}: {:.2} dBFS", track, peak_db)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_analyze() {
+        let mix = Stereo { left: vec![0.5, 1.5, 0.5, -0.5], right: vec![0.0; 4] };
+        let tracks = BTreeMap::from([("lead", Stereo::from_mono(vec![0.25; 4])), ("pad", Stereo::silent(4))]);
+        let analysis = analyze(&mix, &tracks, 8000);
+        assert_eq!(analysis.frames, 4);
+        assert_eq!(analysis.clipped_samples, 1);
+        assert_eq!(analysis.dc_offset, [0.5, 0.0]);
+        assert!((analysis.sample_peak_db - to_db(1.5)).abs() < 1e-9);
+        assert!(analysis.true_peak_db >= analysis.sample_peak_db);
+        assert!((analysis.track_peaks_db["lead"] - to_db(0.25)).abs() < 1e-9);
+
+        let json = serde_json::to_value(&analysis).unwrap();
+        assert_eq!(json["clipped_samples"], 1);
+        assert_eq!(json["track_peaks_db"]["pad"], serde_json::Value::Null);
+        assert!(analysis.to_string().contains("Clipped samples: 1"));
+    }
+}
@@ -0,0 +1,248 @@
+use anyhow::{Result, bail};
+use std::fmt;
+use std::path::Path;
+
+/// Center frequencies of the octave bands used for spectral comparison
+pub const OCTAVE_BANDS: [f64; 10] = [31.5, 63.0, 125.0, 250.0, 500.0, 1000.0, 2000.0, 4000.0, 8000.0, 16000.0];
+
+/// Level difference for a single octave band
+#[derive(Debug, Clone, PartialEq)]
+pub struct BandDifference {
+    pub center_hz: f64,
+    pub level_a_db: f64,
+    pub level_b_db: f64,
+}
+
+impl BandDifference {
+    /// Level of B relative to A in dB
+    pub fn difference_db(&self) -> f64 {
+        self.level_b_db - self.level_a_db
+    }
+}
+
+/// Result of comparing two renders
+#[derive(Debug, Clone)]
+pub struct ComparisonReport {
+    pub sample_rate: u32,
+    pub length_a: usize,
+    pub length_b: usize,
+    /// RMS level of each input in dBFS
+    pub rms_a_db: f64,
+    pub rms_b_db: f64,
+    /// RMS level of A - B in dBFS (the null-test residual)
+    pub residual_rms_db: f64,
+    /// Peak absolute value of A - B in dBFS
+    pub residual_peak_db: f64,
+    pub bands: Vec<BandDifference>,
+}
+
+impl ComparisonReport {
+    /// Loudness of B relative to A in dB
+    pub fn loudness_difference_db(&self) -> f64 {
+        self.rms_b_db - self.rms_a_db
+    }
+
+    /// Whether the two renders null below the given residual level
+    pub fn nulls_below(&self, threshold_db: f64) -> bool {
+        self.length_a == self.length_b && self.residual_peak_db <= threshold_db
+    }
+}
+
+impl fmt::Display for ComparisonReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Sample rate:         {} Hz", self.sample_rate)?;
+        writeln!(f, "Length (frames):     A={} B={}", self.length_a, self.length_b)?;
+        writeln!(f, "RMS level:           A={:.2} dBFS B={:.2} dBFS", self.rms_a_db, self.rms_b_db)?;
+        writeln!(f, "Loudness difference: {:+.2} dB", self.loudness_difference_db())?;
+        writeln!(f, "Null residual:       {:.2} dBFS RMS, {:.2} dBFS peak", self.residual_rms_db, self.residual_peak_db)?;
+        writeln!(f, "Band differences (B - A):")?;
+        for band in &self.bands {
+            writeln!(f, "  {:>7.1} Hz: {:+.2} dB", band.center_hz, band.difference_db())?;
+        }
+        Ok(())
+    }
+}
+
+/// Decoded audio used for comparison
+struct Audio {
+    sample_rate: u32,
+    channels: usize,
+    samples: Vec<f64>,
+}
+
+/// Compare two WAV files
+pub fn compare_wav_files(a: &Path, b: &Path) -> Result<ComparisonReport> {
+    let audio_a = read_wav(a)?;
+    let audio_b = read_wav(b)?;
+
+    if audio_a.sample_rate != audio_b.sample_rate {
+        bail!("Sample rates differ: {} Hz vs {} Hz", audio_a.sample_rate, audio_b.sample_rate);
+    }
+    if audio_a.channels != audio_b.channels {
+        bail!("Channel counts differ: {} vs {}", audio_a.channels, audio_b.channels);
+    }
+
+    Ok(compare_buffers(&audio_a.samples, &audio_b.samples, audio_a.channels, audio_a.sample_rate))
+}
+
+/// Compare two interleaved buffers with the same channel count and sample rate.
+/// The shorter buffer is treated as if padded with silence.
+pub fn compare_buffers(a: &[f64], b: &[f64], channels: usize, sample_rate: u32) -> ComparisonReport {
+    let channels = channels.max(1);
+    let len = a.len().max(b.len());
+    let residual: Vec<f64> = (0..len)
+        .map(|i| a.get(i).copied().unwrap_or(0.0) - b.get(i).copied().unwrap_or(0.0))
+        .collect();
+
+    let mono_a = to_mono(a, channels);
+    let mono_b = to_mono(b, channels);
+    let bands = OCTAVE_BANDS.iter()
+        .filter(|&&center| center < sample_rate as f64 / 2.0)
+        .map(|&center_hz| BandDifference {
+            center_hz,
+            level_a_db: to_db(rms(&band_pass(&mono_a, center_hz, sample_rate))),
+            level_b_db: to_db(rms(&band_pass(&mono_b, center_hz, sample_rate))),
+        })
+        .collect();
+
+    ComparisonReport {
+        sample_rate,
+        length_a: a.len() / channels,
+        length_b: b.len() / channels,
+        rms_a_db: to_db(rms(a)),
+        rms_b_db: to_db(rms(b)),
+        residual_rms_db: to_db(rms(&residual)),
+        residual_peak_db: to_db(residual.iter().fold(0.0_f64, |m, s| m.max(s.abs()))),
+        bands,
+    }
+}
+
+fn read_wav(path: &Path) -> Result<Audio> {
+    let mut reader = hound::WavReader::open(path)?;
+    let spec = reader.spec();
+    let samples = match spec.sample_format {
+        hound::SampleFormat::Float => reader.samples::<f32>()
+            .map(|s| s.map(|s| s as f64))
+            .collect::<std::result::Result<Vec<_>, _>>()?,
+        hound::SampleFormat::Int => {
+            let scale = (1_i64 << (spec.bits_per_sample - 1)) as f64;
+            reader.samples::<i32>()
+                .map(|s| s.map(|s| s as f64 / scale))
+                .collect::<std::result::Result<Vec<_>, _>>()?
+        }
+    };
+
+    Ok(Audio {
+        sample_rate: spec.sample_rate,
+        channels: spec.channels as usize,
+        samples,
+    })
+}
+
+fn to_mono(samples: &[f64], channels: usize) -> Vec<f64> {
+    samples.chunks(channels)
+        .map(|frame| frame.iter().sum::<f64>() / channels as f64)
+        .collect()
+}
+
+pub(crate) fn rms(samples: &[f64]) -> f64 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    (samples.iter().map(|s| s * s).sum::<f64>() / samples.len() as f64).sqrt()
+}
+
+pub(crate) fn to_db(level: f64) -> f64 {
+    if level <= 0.0 {
+        return f64::NEG_INFINITY;
+    }
+    20.0 * level.log10()
+}
+
+/// Octave-wide band-pass biquad (RBJ cookbook, constant 0 dB peak gain)
+fn band_pass(samples: &[f64], center_hz: f64, sample_rate: u32) -> Vec<f64> {
+    let w0 = 2.0 * std::f64::consts::PI * center_hz / sample_rate as f64;
+    let q = std::f64::consts::SQRT_2;
+    let alpha = w0.sin() / (2.0 * q);
+    let a0 = 1.0 + alpha;
+    let (b0, b2) = (alpha / a0, -alpha / a0);
+    let (a1, a2) = (-2.0 * w0.cos() / a0, (1.0 - alpha) / a0);
+
+    let (mut x1, mut x2, mut y1, mut y2) = (0.0, 0.0, 0.0, 0.0);
+    samples.iter()
+        .map(|&x| {
+            let y = b0 * x + b2 * x2 - a1 * y1 - a2 * y2;
+            x2 = x1;
+            x1 = x;
+            y2 = y1;
+            y1 = y;
+            y
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(frequency: f64, amplitude: f64, sample_rate: u32, len: usize) -> Vec<f64> {
+        (0..len)
+            .map(|i| amplitude * (2.0 * std::f64::consts::PI * frequency * i as f64 / sample_rate as f64).sin())
+            .collect()
+    }
+
+    #[test]
+    fn test_identical_buffers_null() {
+        let a = sine(440.0, 0.5, 44100, 44100);
+        let report = compare_buffers(&a, &a, 1, 44100);
+        assert_eq!(report.residual_peak_db, f64::NEG_INFINITY);
+        assert_eq!(report.loudness_difference_db(), 0.0);
+        assert!(report.nulls_below(-120.0));
+    }
+
+    #[test]
+    fn test_gain_change_is_reported() {
+        let a = sine(440.0, 0.5, 44100, 44100);
+        let b: Vec<f64> = a.iter().map(|s| s * 0.5).collect();
+        let report = compare_buffers(&a, &b, 1, 44100);
+        assert!((report.loudness_difference_db() + 6.02).abs() < 0.01);
+        assert!(!report.nulls_below(-60.0));
+    }
+
+    #[test]
+    fn test_spectral_difference_lands_in_band() {
+        let a = sine(1000.0, 0.5, 44100, 44100);
+        let b: Vec<f64> = a.iter()
+            .zip(sine(8000.0, 0.5, 44100, 44100))
+            .map(|(x, y)| x + y)
+            .collect();
+        let report = compare_buffers(&a, &b, 1, 44100);
+
+        let band = |hz: f64| report.bands.iter().find(|b| b.center_hz == hz).unwrap().difference_db();
+        assert!(band(1000.0).abs() < 1.0);
+        assert!(band(8000.0) > 20.0);
+    }
+
+    #[test]
+    fn test_compare_wav_files() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 44100,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        for (name, amplitude) in [("a.wav", 0.5), ("b.wav", 0.25)] {
+            let mut writer = hound::WavWriter::create(temp_dir.path().join(name), spec).unwrap();
+            for s in sine(440.0, amplitude, 44100, 4410) {
+                writer.write_sample((s * i16::MAX as f64) as i16).unwrap();
+                writer.write_sample((s * i16::MAX as f64) as i16).unwrap();
+            }
+            writer.finalize().unwrap();
+        }
+
+        let report = compare_wav_files(&temp_dir.path().join("a.wav"), &temp_dir.path().join("b.wav")).unwrap();
+        assert_eq!(report.length_a, 4410);
+        assert!((report.loudness_difference_db() + 6.02).abs() < 0.05);
+    }
+}
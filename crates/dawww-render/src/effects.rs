@@ -0,0 +1,641 @@
+use dawww_core::effects::{
+    DelayParams, DistortionMode, DistortionParams, Effect, EffectInstance, EqParams, ReverbParams, MAX_DELAY_SECONDS,
+};
+use std::f64::consts::PI;
+use crate::db_to_gain;
+use crate::oversample;
+use crate::stereo::Stereo;
+
+/// Apply an insert chain to a track or bus, in order, skipping bypassed effects.
+/// Tempo-synced effects follow `bpm`, and distortion runs at `oversampling` times the rate.
+pub fn apply_chain(chain: &[EffectInstance], buffer: &mut Stereo, sample_rate: u32, bpm: f64, oversampling: usize) {
+    Chain::new(chain, sample_rate, bpm, oversampling).process(buffer);
+}
+
+/// An insert chain that keeps its filters, delay lines and reverb between calls, so a signal
+/// can be run through it a block at a time and come out as it would in one go. Oversampled
+/// distortion filters each block on its own, so blocks only join up seamlessly at an
+/// `oversampling` of 1. An `oversampling` of 0 is taken as 1.
+pub struct Chain {
+    effects: Vec<EffectState>,
+}
+
+impl Chain {
+    pub fn new(chain: &[EffectInstance], sample_rate: u32, bpm: f64, oversampling: usize) -> Self {
+        let effects = chain.iter()
+            .filter(|e| !e.bypass)
+            .map(|instance| EffectState::new(&instance.effect, sample_rate, bpm, oversampling))
+            .collect();
+        Self { effects }
+    }
+
+    /// Run the next block of the signal through the chain
+    pub fn process(&mut self, buffer: &mut Stereo) {
+        for effect in &mut self.effects {
+            effect.process(buffer);
+        }
+    }
+}
+
+/// Seconds a chain keeps sounding after its input falls silent, until it has died away by 60 dB
+pub fn chain_tail_seconds(chain: &[EffectInstance], bpm: f64) -> f64 {
+    chain.iter().filter(|e| !e.bypass).map(|e| tail_seconds(&e.effect, bpm)).sum()
+}
+
+/// Seconds an effect keeps sounding after its input falls silent
+pub fn tail_seconds(effect: &Effect, bpm: f64) -> f64 {
+    // Repeats of a loop fed back at `feedback` until they're 60 dB down
+    let ring = |period: f64, feedback: f64| {
+        if feedback <= 0.0 {
+            period
+        } else {
+            period * (1.0 + (0.001_f64).ln() / feedback.min(0.999).ln())
+        }
+    };
+    match effect {
+        Effect::Delay(params) => {
+            let period = params.time.seconds(bpm).min(MAX_DELAY_SECONDS);
+            // Ping-pong repeats take two trips to come back to the same channel
+            let repeats = ring(period, params.feedback);
+            if params.ping_pong { repeats * 2.0 } else { repeats }
+        }
+        Effect::Reverb(params) => {
+            let longest_comb = (COMB_TUNING[COMB_TUNING.len() - 1] + STEREO_SPREAD) as f64 / 44100.0;
+            ring(longest_comb, reverb_feedback(params))
+        }
+        Effect::Gain(_) | Effect::Eq(_) | Effect::Distortion(_) => 0.0,
+    }
+}
+
+/// Apply a single effect in place
+pub fn apply_effect(effect: &Effect, buffer: &mut Stereo, sample_rate: u32, bpm: f64, oversampling: usize) {
+    EffectState::new(effect, sample_rate, bpm, oversampling).process(buffer);
+}
+
+/// One effect of a `Chain`, with what it remembers from the blocks before
+enum EffectState {
+    Gain(f64),
+    /// Each channel's bands, lowest first
+    Eq([Vec<Biquad>; 2]),
+    Distortion {
+        params: DistortionParams,
+        oversampling: usize,
+        /// Each channel's tone control
+        tone: [OnePole; 2],
+    },
+    Delay(Delay),
+    Reverb(Box<Reverb>),
+}
+
+impl EffectState {
+    fn new(effect: &Effect, sample_rate: u32, bpm: f64, oversampling: usize) -> Self {
+        let oversampling = oversampling.max(1);
+        match effect {
+            Effect::Gain(params) => EffectState::Gain(db_to_gain(params.gain_db)),
+            Effect::Eq(params) => EffectState::Eq([eq_bands(params, sample_rate), eq_bands(params, sample_rate)]),
+            Effect::Distortion(params) => {
+                // The waveshaper's harmonics would fold back below Nyquist at the plain rate
+                let rate = sample_rate * oversampling as u32;
+                EffectState::Distortion {
+                    params: params.clone(),
+                    oversampling,
+                    tone: [distortion_tone(params, rate), distortion_tone(params, rate)],
+                }
+            }
+            Effect::Delay(params) => EffectState::Delay(Delay::new(params, sample_rate, bpm)),
+            Effect::Reverb(params) => EffectState::Reverb(Box::new(Reverb::new(params, sample_rate))),
+        }
+    }
+
+    fn process(&mut self, buffer: &mut Stereo) {
+        match self {
+            EffectState::Gain(gain) => {
+                for channel in buffer.channels_mut() {
+                    channel.iter_mut().for_each(|s| *s *= *gain);
+                }
+            }
+            EffectState::Eq(bands) => {
+                for (channel, bands) in buffer.channels_mut().into_iter().zip(bands) {
+                    for band in bands {
+                        band.process(channel);
+                    }
+                }
+            }
+            EffectState::Distortion { params, oversampling, tone } => {
+                for (channel, tone) in buffer.channels_mut().into_iter().zip(tone) {
+                    oversample::process(channel, *oversampling, |channel| apply_distortion(params, tone, channel));
+                }
+            }
+            EffectState::Delay(delay) => delay.process(buffer),
+            EffectState::Reverb(reverb) => reverb.process(buffer),
+        }
+    }
+}
+
+/// The EQ's bands for one channel. Flat bands and bands above Nyquist leave the signal
+/// untouched, so they're left out.
+fn eq_bands(params: &EqParams, sample_rate: u32) -> Vec<Biquad> {
+    let nyquist = sample_rate as f64 / 2.0;
+    let bands = [
+        (BiquadKind::LowShelf, params.low_gain_db, params.low_frequency, std::f64::consts::FRAC_1_SQRT_2),
+        (BiquadKind::Peaking, params.mid_gain_db, params.mid_frequency, params.mid_q),
+        (BiquadKind::HighShelf, params.high_gain_db, params.high_frequency, std::f64::consts::FRAC_1_SQRT_2),
+    ];
+    bands.into_iter()
+        .filter(|&(_, gain_db, frequency, _)| gain_db != 0.0 && frequency < nyquist)
+        .map(|(kind, gain_db, frequency, q)| Biquad::new(kind, gain_db, frequency, q, sample_rate))
+        .collect()
+}
+
+/// Feedback delay; the wet signal is mixed with the dry input. Each pass through the line
+/// is filtered. Ping-pong feeds both channels into the left line, which repeats into the
+/// right line and back.
+struct Delay {
+    params: DelayParams,
+    left_line: Vec<f64>,
+    right_line: Vec<f64>,
+    filters: [OnePole; 2],
+    position: usize,
+}
+
+impl Delay {
+    fn new(params: &DelayParams, sample_rate: u32, bpm: f64) -> Self {
+        let seconds = params.time.seconds(bpm).min(MAX_DELAY_SECONDS);
+        let delay_samples = ((seconds * sample_rate as f64) as usize).max(1);
+        Self {
+            params: params.clone(),
+            left_line: vec![0.0; delay_samples],
+            right_line: vec![0.0; delay_samples],
+            filters: [
+                OnePole::new(params.low_cut, params.high_cut, sample_rate),
+                OnePole::new(params.low_cut, params.high_cut, sample_rate),
+            ],
+            position: 0,
+        }
+    }
+
+    fn process(&mut self, buffer: &mut Stereo) {
+        let params = &self.params;
+        for (left, right) in buffer.left.iter_mut().zip(buffer.right.iter_mut()) {
+            let position = self.position;
+            let (delayed_left, delayed_right) = (self.left_line[position], self.right_line[position]);
+            let (into_left, into_right) = if params.ping_pong {
+                ((*left + *right) / 2.0 + delayed_right * params.feedback, delayed_left * params.feedback)
+            } else {
+                (*left + delayed_left * params.feedback, *right + delayed_right * params.feedback)
+            };
+            self.left_line[position] = self.filters[0].process(into_left);
+            self.right_line[position] = self.filters[1].process(into_right);
+            self.position = (position + 1) % self.left_line.len();
+            *left = *left * (1.0 - params.mix) + delayed_left * params.mix;
+            *right = *right * (1.0 - params.mix) + delayed_right * params.mix;
+        }
+    }
+}
+
+/// One-pole high-pass and low-pass in series, for gently thinning and darkening a signal
+struct OnePole {
+    high_pass: Option<f64>,
+    low_pass: Option<f64>,
+    high_pass_state: f64,
+    low_pass_state: f64,
+}
+
+impl OnePole {
+    fn new(low_cut: Option<f64>, high_cut: Option<f64>, sample_rate: u32) -> Self {
+        let nyquist = sample_rate as f64 / 2.0;
+        let coefficient = |frequency: f64| 1.0 - (-2.0 * PI * frequency / sample_rate as f64).exp();
+        Self {
+            high_pass: low_cut.filter(|&f| f < nyquist).map(coefficient),
+            low_pass: high_cut.filter(|&f| f < nyquist).map(coefficient),
+            high_pass_state: 0.0,
+            low_pass_state: 0.0,
+        }
+    }
+
+    fn process(&mut self, mut sample: f64) -> f64 {
+        if let Some(a) = self.high_pass {
+            self.high_pass_state += a * (sample - self.high_pass_state);
+            sample -= self.high_pass_state;
+        }
+        if let Some(a) = self.low_pass {
+            self.low_pass_state += a * (sample - self.low_pass_state);
+            sample = self.low_pass_state;
+        }
+        sample
+    }
+}
+
+/// Lowest cutoff of the distortion's tone control, at a `tone` of 0
+const DARKEST_TONE: f64 = 500.0;
+
+/// The distortion's tone control at `sample_rate`. Tone sweeps the cutoff exponentially up
+/// to 20 kHz; fully bright is unfiltered.
+fn distortion_tone(params: &DistortionParams, sample_rate: u32) -> OnePole {
+    let cutoff = (params.tone < 1.0).then(|| DARKEST_TONE * (20000.0 / DARKEST_TONE).powf(params.tone));
+    OnePole::new(None, cutoff, sample_rate)
+}
+
+/// Drive the signal into a waveshaper, darken it with the tone control and blend it with
+/// the dry signal
+fn apply_distortion(params: &DistortionParams, tone: &mut OnePole, buffer: &mut [f64]) {
+    let drive = db_to_gain(params.drive_db);
+    let levels = 2.0_f64.powi(params.bits as i32 - 1);
+
+    for sample in buffer.iter_mut() {
+        let driven = *sample * drive;
+        let shaped = match params.mode {
+            DistortionMode::SoftClip => {
+                let x = driven.clamp(-1.0, 1.0);
+                1.5 * x - 0.5 * x * x * x
+            }
+            DistortionMode::Tanh => driven.tanh(),
+            DistortionMode::Bitcrush => (driven.clamp(-1.0, 1.0) * levels).round() / levels,
+        };
+        *sample = *sample * (1.0 - params.mix) + tone.process(shaped) * params.mix;
+    }
+}
+
+/// Freeverb's comb and allpass lengths in samples at 44.1 kHz
+const COMB_TUNING: [usize; 8] = [1116, 1188, 1277, 1356, 1422, 1491, 1557, 1617];
+const ALLPASS_TUNING: [usize; 4] = [556, 441, 341, 225];
+/// Extra length of the right channel's filters, so the channels decorrelate
+const STEREO_SPREAD: usize = 23;
+
+/// Freeverb: each channel runs the summed input through eight damped feedback combs in
+/// parallel and then four allpasses in series
+struct Reverb {
+    feedback: f64,
+    damping: f64,
+    wet: f64,
+    dry: f64,
+    /// Each channel's combs and allpasses
+    channels: [(Vec<Comb>, Vec<Allpass>); 2],
+}
+
+impl Reverb {
+    fn new(params: &ReverbParams, sample_rate: u32) -> Self {
+        let scale = sample_rate as f64 / 44100.0;
+        let length = |samples: usize| ((samples as f64 * scale) as usize).max(1);
+        let filters = |spread: usize| {
+            let combs = COMB_TUNING.iter().map(|&n| Comb::new(length(n + spread))).collect();
+            let allpasses = ALLPASS_TUNING.iter().map(|&n| Allpass::new(length(n + spread))).collect();
+            (combs, allpasses)
+        };
+        Self {
+            feedback: reverb_feedback(params),
+            damping: 0.4 * params.damping,
+            wet: 3.0 * params.mix,
+            dry: 1.0 - params.mix,
+            channels: [filters(0), filters(STEREO_SPREAD)],
+        }
+    }
+
+    fn process(&mut self, buffer: &mut Stereo) {
+        let input: Vec<f64> = buffer.left.iter().zip(&buffer.right).map(|(l, r)| (l + r) * 0.015).collect();
+        for ((combs, allpasses), channel) in self.channels.iter_mut().zip(buffer.channels_mut()) {
+            for (out, &input) in channel.iter_mut().zip(&input) {
+                let mut reverb: f64 = combs.iter_mut().map(|comb| comb.process(input, self.feedback, self.damping)).sum();
+                for allpass in allpasses.iter_mut() {
+                    reverb = allpass.process(reverb);
+                }
+                *out = *out * self.dry + reverb * self.wet;
+            }
+        }
+    }
+}
+
+/// Feedback of the reverb's combs, which sets how long the room rings
+fn reverb_feedback(params: &ReverbParams) -> f64 {
+    0.7 + 0.28 * params.room_size
+}
+
+/// Feedback comb filter with a one-pole low-pass in the loop
+struct Comb {
+    line: Vec<f64>,
+    position: usize,
+    filtered: f64,
+}
+
+impl Comb {
+    fn new(length: usize) -> Self {
+        Self { line: vec![0.0; length], position: 0, filtered: 0.0 }
+    }
+
+    fn process(&mut self, input: f64, feedback: f64, damping: f64) -> f64 {
+        let output = self.line[self.position];
+        self.filtered = output * (1.0 - damping) + self.filtered * damping;
+        self.line[self.position] = input + self.filtered * feedback;
+        self.position = (self.position + 1) % self.line.len();
+        output
+    }
+}
+
+/// Schroeder allpass, which smears echoes without colouring the sound
+struct Allpass {
+    line: Vec<f64>,
+    position: usize,
+}
+
+impl Allpass {
+    fn new(length: usize) -> Self {
+        Self { line: vec![0.0; length], position: 0 }
+    }
+
+    fn process(&mut self, input: f64) -> f64 {
+        let delayed = self.line[self.position];
+        self.line[self.position] = input + delayed * 0.5;
+        self.position = (self.position + 1) % self.line.len();
+        delayed - input
+    }
+}
+
+#[derive(Clone, Copy)]
+enum BiquadKind {
+    LowShelf,
+    Peaking,
+    HighShelf,
+}
+
+/// Biquad filter with coefficients from the RBJ audio EQ cookbook. It remembers its last
+/// inputs and outputs, so a signal can be filtered a block at a time.
+pub(crate) struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+    /// The last two inputs and outputs
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    fn new(kind: BiquadKind, gain_db: f64, frequency: f64, q: f64, sample_rate: u32) -> Self {
+        let a = 10.0_f64.powf(gain_db / 40.0);
+        let w0 = 2.0 * PI * frequency / sample_rate as f64;
+        let (sin_w0, cos_w0) = w0.sin_cos();
+        let alpha = sin_w0 / (2.0 * q);
+        let shelf = 2.0 * a.sqrt() * alpha;
+
+        let (b0, b1, b2, a0, a1, a2) = match kind {
+            BiquadKind::Peaking => (
+                1.0 + alpha * a,
+                -2.0 * cos_w0,
+                1.0 - alpha * a,
+                1.0 + alpha / a,
+                -2.0 * cos_w0,
+                1.0 - alpha / a,
+            ),
+            BiquadKind::LowShelf => (
+                a * ((a + 1.0) - (a - 1.0) * cos_w0 + shelf),
+                2.0 * a * ((a - 1.0) - (a + 1.0) * cos_w0),
+                a * ((a + 1.0) - (a - 1.0) * cos_w0 - shelf),
+                (a + 1.0) + (a - 1.0) * cos_w0 + shelf,
+                -2.0 * ((a - 1.0) + (a + 1.0) * cos_w0),
+                (a + 1.0) + (a - 1.0) * cos_w0 - shelf,
+            ),
+            BiquadKind::HighShelf => (
+                a * ((a + 1.0) + (a - 1.0) * cos_w0 + shelf),
+                -2.0 * a * ((a - 1.0) + (a + 1.0) * cos_w0),
+                a * ((a + 1.0) + (a - 1.0) * cos_w0 - shelf),
+                (a + 1.0) - (a - 1.0) * cos_w0 + shelf,
+                2.0 * ((a - 1.0) - (a + 1.0) * cos_w0),
+                (a + 1.0) - (a - 1.0) * cos_w0 - shelf,
+            ),
+        };
+
+        Self::from_coefficients([b0 / a0, b1 / a0, b2 / a0], [a1 / a0, a2 / a0])
+    }
+
+    /// A filter from coefficients already divided by `a0`
+    pub(crate) fn from_coefficients(b: [f64; 3], a: [f64; 2]) -> Self {
+        Self { b0: b[0], b1: b[1], b2: b[2], a1: a[0], a2: a[1], x: [0.0; 2], y: [0.0; 2] }
+    }
+
+    pub(crate) fn process(&mut self, buffer: &mut [f64]) {
+        let ([mut x1, mut x2], [mut y1, mut y2]) = (self.x, self.y);
+        for sample in buffer.iter_mut() {
+            let x = *sample;
+            let y = self.b0 * x + self.b1 * x1 + self.b2 * x2 - self.a1 * y1 - self.a2 * y2;
+            x2 = x1;
+            x1 = x;
+            y2 = y1;
+            y1 = y;
+            *sample = y;
+        }
+        (self.x, self.y) = ([x1, x2], [y1, y2]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dawww_core::effects::{GainParams, NoteValue};
+
+    fn sine(frequency: f64, sample_rate: u32, len: usize) -> Stereo {
+        Stereo::from_mono((0..len)
+            .map(|i| (2.0 * PI * frequency * i as f64 / sample_rate as f64).sin())
+            .collect())
+    }
+
+    fn peak(buffer: &[f64]) -> f64 {
+        buffer.iter().fold(0.0_f64, |m, s| m.max(s.abs()))
+    }
+
+    fn impulse(len: usize) -> Stereo {
+        let mut buffer = Stereo::silent(len);
+        buffer.left[0] = 1.0;
+        buffer.right[0] = 1.0;
+        buffer
+    }
+
+    #[test]
+    fn test_gain_and_bypass() {
+        let mut buffer = Stereo::from_mono(vec![0.5; 4]);
+        let chain = vec![
+            EffectInstance::new(Effect::Gain(GainParams::new(-6.0206))),
+            EffectInstance {
+                effect: Effect::Gain(GainParams::new(20.0)),
+                bypass: true,
+                extra: Default::default(),
+            },
+        ];
+        apply_chain(&chain, &mut buffer, 44100, 120.0, 1);
+        assert!((buffer.left[0] - 0.25).abs() < 1e-4);
+        assert_eq!(buffer.left, buffer.right);
+    }
+
+    #[test]
+    fn test_eq_boosts_only_its_band() {
+        let eq = Effect::Eq(EqParams { high_gain_db: 12.0, ..EqParams::default() });
+
+        let mut low = sine(100.0, 44100, 44100);
+        apply_effect(&eq, &mut low, 44100, 120.0, 1);
+        assert!((peak(&low.left[22050..]) - 1.0).abs() < 0.05);
+
+        let mut high = sine(12000.0, 44100, 44100);
+        apply_effect(&eq, &mut high, 44100, 120.0, 1);
+        assert!((peak(&high.left[22050..]) - db_to_gain(12.0)).abs() < 0.2);
+    }
+
+    #[test]
+    fn test_delay_repeats_with_feedback() {
+        let mut buffer = impulse(10);
+        let delay = Effect::Delay(DelayParams::new(3.0, 0.5, 0.5));
+        apply_effect(&delay, &mut buffer, 1, 120.0, 1);
+        let buffer = buffer.left;
+
+        assert_eq!(buffer[0], 0.5);
+        assert_eq!(buffer[3], 0.5);
+        assert_eq!(buffer[6], 0.25);
+        assert_eq!(buffer[9], 0.125);
+        assert_eq!(buffer[1], 0.0);
+    }
+
+    #[test]
+    fn test_reverb_tail() {
+        let energy = |s: &[f64]| s.iter().map(|x| x * x).sum::<f64>();
+        let mut small = impulse(44100);
+        apply_effect(&Effect::Reverb(ReverbParams { room_size: 0.2, mix: 1.0, ..ReverbParams::default() }), &mut small, 44100, 120.0, 1);
+        let mut large = impulse(44100);
+        apply_effect(&Effect::Reverb(ReverbParams { room_size: 0.9, mix: 1.0, ..ReverbParams::default() }), &mut large, 44100, 120.0, 1);
+
+        // Fully wet, the impulse itself is gone and the first echo arrives with the shortest comb
+        assert_eq!(large.left[0], 0.0);
+        assert!(large.left[..1116].iter().all(|&s| s == 0.0));
+        assert!(energy(&large.left[1116..2000]) > 0.0);
+        // Bigger rooms ring for longer, and the two channels differ
+        assert!(energy(&large.left[22050..]) > energy(&small.left[22050..]) * 10.0);
+        assert_ne!(large.left, large.right);
+        assert!(peak(&large.left) < 1.0);
+
+        let mut dry = impulse(100);
+        apply_effect(&Effect::Reverb(ReverbParams { mix: 0.0, ..ReverbParams::default() }), &mut dry, 44100, 120.0, 1);
+        assert_eq!(dry, impulse(100));
+    }
+
+    #[test]
+    fn test_synced_ping_pong_delay() {
+        // A dotted eighth at 120 BPM is 0.375 s, or 3 samples at 8 Hz
+        let mut buffer = impulse(12);
+        let delay = DelayParams::new(NoteValue::new(1, 8).dotted(), 0.5, 1.0).with_ping_pong();
+        apply_effect(&Effect::Delay(delay), &mut buffer, 8, 120.0, 1);
+
+        assert_eq!(buffer.left, vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.25, 0.0, 0.0]);
+        assert_eq!(buffer.right, vec![0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.5, 0.0, 0.0, 0.0, 0.0, 0.0]);
+
+        // Twice the tempo halves the time
+        let mut faster = impulse(12);
+        apply_effect(&Effect::Delay(DelayParams::new(NoteValue::new(1, 4), 0.0, 1.0)), &mut faster, 8, 240.0, 1);
+        assert_eq!(faster.left[2], 1.0);
+    }
+
+    #[test]
+    fn test_delay_filter_darkens_repeats() {
+        let bright = |buffer: &[f64]| buffer.windows(2).map(|w| (w[1] - w[0]).abs()).sum::<f64>();
+        let repeats = |delay: DelayParams| {
+            let mut buffer = sine(8000.0, 44100, 44100);
+            buffer.left[4410..].iter_mut().for_each(|s| *s = 0.0);
+            apply_effect(&Effect::Delay(delay), &mut buffer, 44100, 120.0, 1);
+            buffer.left[22050..].to_vec()
+        };
+        let plain = repeats(DelayParams::new(0.1, 0.7, 1.0));
+        let filtered = repeats(DelayParams::new(0.1, 0.7, 1.0).with_filter(Some(100.0), Some(1000.0)));
+        assert!(bright(&filtered) < bright(&plain) * 0.01);
+    }
+
+    #[test]
+    fn test_distortion_modes() {
+        let shape = |params: DistortionParams, input: f64| {
+            let mut buffer = Stereo::from_mono(vec![input]);
+            apply_effect(&Effect::Distortion(params), &mut buffer, 44100, 120.0, 1);
+            buffer.left[0]
+        };
+
+        assert_eq!(shape(DistortionParams::new(DistortionMode::SoftClip, 12.0), 0.9), 1.0);
+        assert_eq!(shape(DistortionParams::new(DistortionMode::SoftClip, 0.0), 0.5), 0.6875);
+        assert!((shape(DistortionParams::new(DistortionMode::Tanh, 0.0), 0.5) - 0.5_f64.tanh()).abs() < 1e-12);
+        let saturated = shape(DistortionParams::new(DistortionMode::Tanh, 12.0), -0.5);
+        assert!(saturated > -1.0 && saturated < -0.95);
+        let crush = DistortionParams { bits: 2, ..DistortionParams::new(DistortionMode::Bitcrush, 0.0) };
+        assert_eq!(shape(crush.clone(), 0.3), 0.5);
+        assert_eq!(shape(crush, -0.2), 0.0);
+        let blend = DistortionParams { mix: 0.5, ..DistortionParams::new(DistortionMode::SoftClip, 0.0) };
+        assert_eq!(shape(blend, 0.5), 0.59375);
+    }
+
+    #[test]
+    fn test_distortion_tone_darkens() {
+        let drive = |tone: f64| {
+            let mut buffer = sine(5000.0, 44100, 44100);
+            apply_effect(&Effect::Distortion(DistortionParams { tone, ..DistortionParams::default() }), &mut buffer, 44100, 120.0, 1);
+            peak(&buffer.left[22050..])
+        };
+        assert!(drive(0.0) < drive(1.0) * 0.2);
+    }
+
+    #[test]
+    fn test_oversampled_distortion_aliases_less() {
+        // Hard-driven 5 kHz makes a 35 kHz harmonic, which folds down to 9.1 kHz at 44.1 kHz
+        let alias = |oversampling: usize| {
+            let mut buffer = sine(5000.0, 44100, 44100);
+            let params = DistortionParams::new(DistortionMode::Tanh, 24.0);
+            apply_effect(&Effect::Distortion(params), &mut buffer, 44100, 120.0, oversampling);
+            let (mut re, mut im) = (0.0, 0.0);
+            for (i, s) in buffer.left[4410..39690].iter().enumerate() {
+                let angle = 2.0 * PI * 9100.0 * i as f64 / 44100.0;
+                re += s * angle.cos();
+                im += s * angle.sin();
+            }
+            (re * re + im * im).sqrt() / 35280.0
+        };
+        assert!(alias(1) > 0.01, "{}", alias(1));
+        assert!(alias(4) < alias(1) * 0.05, "{} {}", alias(4), alias(1));
+        // No oversampling at all runs at the plain rate
+        assert_eq!(alias(0), alias(1));
+    }
+
+    #[test]
+    fn test_chain_runs_in_blocks() {
+        let chain = vec![
+            EffectInstance::new(Effect::Eq(EqParams { low_gain_db: 6.0, high_gain_db: -6.0, ..EqParams::default() })),
+            EffectInstance::new(Effect::Distortion(DistortionParams { tone: 0.5, ..DistortionParams::default() })),
+            EffectInstance::new(Effect::Delay(DelayParams::new(0.01, 0.5, 0.5).with_filter(Some(100.0), Some(4000.0)))),
+            EffectInstance::new(Effect::Reverb(ReverbParams::default())),
+        ];
+        let mut whole = sine(440.0, 8000, 4000);
+        whole.left[2000..].iter_mut().for_each(|s| *s = 0.0);
+        let mut blocks = whole.clone();
+        apply_chain(&chain, &mut whole, 8000, 120.0, 1);
+
+        let mut streamed = Chain::new(&chain, 8000, 120.0, 1);
+        for start in (0..4000).step_by(300) {
+            let end = (start + 300).min(4000);
+            let mut block = Stereo { left: blocks.left[start..end].to_vec(), right: blocks.right[start..end].to_vec() };
+            streamed.process(&mut block);
+            blocks.left[start..end].copy_from_slice(&block.left);
+            blocks.right[start..end].copy_from_slice(&block.right);
+        }
+        assert_eq!(blocks, whole);
+    }
+
+    #[test]
+    fn test_tail_seconds() {
+        let echo = DelayParams::new(0.5, 0.5, 0.5);
+        // Half the level each repeat: ten repeats to fall 60 dB, after the first
+        assert!((tail_seconds(&Effect::Delay(echo.clone()), 120.0) - 0.5 * (1.0 + 9.9658)).abs() < 1e-3);
+        assert_eq!(tail_seconds(&Effect::Delay(DelayParams::new(NoteValue::new(1, 4), 0.0, 0.5)), 120.0), 0.5);
+
+        let small = tail_seconds(&Effect::Reverb(ReverbParams { room_size: 0.2, ..ReverbParams::default() }), 120.0);
+        let large = tail_seconds(&Effect::Reverb(ReverbParams { room_size: 0.9, ..ReverbParams::default() }), 120.0);
+        assert!(small > 0.3 && large > small * 3.0);
+
+        let chain = vec![
+            EffectInstance::new(Effect::Gain(GainParams::new(-3.0))),
+            EffectInstance::new(Effect::Delay(DelayParams::new(0.25, 0.0, 0.5))),
+            EffectInstance { bypass: true, ..EffectInstance::new(Effect::Delay(echo)) },
+        ];
+        assert_eq!(chain_tail_seconds(&chain, 120.0), 0.25);
+    }
+}
@@ -0,0 +1,241 @@
+use dawww_core::{ExportFormat, MixdownSettings};
+use anyhow::Result;
+use std::path::Path;
+use crate::aiff;
+use crate::speakers::to_stereo;
+use crate::stereo::Stereo;
+use crate::wav;
+
+/// Write `buffer`, scaled by `gain`, in the mixdown's file format, with dither drawn from `seed`. WAV and AIFF are always
+/// available; MP3, Ogg Vorbis and Opus need the `mp3`, `vorbis` and `opus` features.
+pub fn write_audio(path: &Path, buffer: &Stereo, settings: &MixdownSettings, sample_rate: u32, gain: f64, seed: u64) -> Result<()> {
+    settings.validate()?;
+    match settings.format {
+        ExportFormat::Wav => wav::write_wav(path, buffer, settings, sample_rate, gain, seed),
+        ExportFormat::Aiff => aiff::write_aiff(path, buffer, settings, sample_rate, gain, seed),
+        ExportFormat::Mp3 => write_mp3(path, buffer, settings, sample_rate, gain),
+        ExportFormat::Ogg => write_vorbis(path, buffer, settings, sample_rate, gain),
+        ExportFormat::Opus => write_opus(path, buffer, settings, sample_rate, gain),
+    }
+}
+
+/// Write one signal for each of the mixdown's channels, in file order, in its file format.
+/// Lossy formats hold mono and stereo only.
+pub fn write_channels(path: &Path, channels: &[Vec<f64>], settings: &MixdownSettings, sample_rate: u32, gain: f64, seed: u64) -> Result<()> {
+    settings.validate()?;
+    match settings.format {
+        ExportFormat::Wav => wav::write_channels(path, channels, settings, sample_rate, gain, seed),
+        ExportFormat::Aiff => aiff::write_channels(path, channels, settings, sample_rate, gain, seed),
+        _ => write_audio(path, &to_stereo(channels), settings, sample_rate, gain, seed),
+    }
+}
+
+/// One clipped `f32` plane per output channel; mono takes the average of the two channels
+#[cfg(any(feature = "mp3", feature = "vorbis", feature = "opus"))]
+fn planes(buffer: &Stereo, settings: &MixdownSettings, gain: f64) -> Vec<Vec<f32>> {
+    let clip = |sample: f64| (sample * gain).clamp(-1.0, 1.0) as f32;
+    match settings.channels {
+        dawww_core::ChannelLayout::Mono => {
+            vec![buffer.left.iter().zip(&buffer.right).map(|(l, r)| clip((l + r) / 2.0)).collect()]
+        }
+        // Lossy mixdowns are mono or stereo
+        _ => vec![
+            buffer.left.iter().map(|&s| clip(s)).collect(),
+            buffer.right.iter().map(|&s| clip(s)).collect(),
+        ],
+    }
+}
+
+#[cfg(feature = "mp3")]
+fn write_mp3(path: &Path, buffer: &Stereo, settings: &MixdownSettings, sample_rate: u32, gain: f64) -> Result<()> {
+    use anyhow::anyhow;
+    use mp3lame_encoder::{Bitrate, Builder, DualPcm, FlushNoGap, MonoPcm, Quality};
+
+    let planes = planes(buffer, settings, gain);
+    let bitrate = match settings.bitrate.unwrap_or(192) {
+        0..=15 => Bitrate::Kbps8,
+        16..=23 => Bitrate::Kbps16,
+        24..=31 => Bitrate::Kbps24,
+        32..=39 => Bitrate::Kbps32,
+        40..=47 => Bitrate::Kbps40,
+        48..=63 => Bitrate::Kbps48,
+        64..=79 => Bitrate::Kbps64,
+        80..=95 => Bitrate::Kbps80,
+        96..=111 => Bitrate::Kbps96,
+        112..=127 => Bitrate::Kbps112,
+        128..=159 => Bitrate::Kbps128,
+        160..=191 => Bitrate::Kbps160,
+        192..=223 => Bitrate::Kbps192,
+        224..=255 => Bitrate::Kbps224,
+        256..=319 => Bitrate::Kbps256,
+        _ => Bitrate::Kbps320,
+    };
+    let mut builder = Builder::new().ok_or_else(|| anyhow!("Couldn't start the MP3 encoder"))?;
+    builder.set_num_channels(planes.len() as u8)?;
+    builder.set_sample_rate(sample_rate)?;
+    builder.set_brate(bitrate)?;
+    builder.set_quality(Quality::NearBest)?;
+    let mut encoder = builder.build()?;
+
+    let mut bytes = Vec::with_capacity(mp3lame_encoder::max_required_buffer_size(planes[0].len()));
+    match planes.as_slice() {
+        [mono] => encoder.encode_to_vec(MonoPcm(mono.as_slice()), &mut bytes)?,
+        [left, right] => encoder.encode_to_vec(DualPcm { left, right }, &mut bytes)?,
+        _ => unreachable!("mixdowns are mono or stereo"),
+    };
+    bytes.reserve(7200);
+    encoder.flush_to_vec::<FlushNoGap>(&mut bytes)?;
+    std::fs::write(path, bytes)?;
+    Ok(())
+}
+
+#[cfg(not(feature = "mp3"))]
+fn write_mp3(_: &Path, _: &Stereo, _: &MixdownSettings, _: u32, _: f64) -> Result<()> {
+    anyhow::bail!("MP3 export needs dawww-render built with the `mp3` feature")
+}
+
+#[cfg(feature = "vorbis")]
+fn write_vorbis(path: &Path, buffer: &Stereo, settings: &MixdownSettings, sample_rate: u32, gain: f64) -> Result<()> {
+    use anyhow::Context;
+    use std::io::Write;
+    use std::num::{NonZeroU32, NonZeroU8};
+    use vorbis_rs::{VorbisBitrateManagementStrategy, VorbisEncoderBuilder};
+
+    let planes = planes(buffer, settings, gain);
+    let strategy = match (settings.quality, settings.bitrate.and_then(|kbps| NonZeroU32::new(kbps * 1000))) {
+        (None, Some(average_bitrate)) => VorbisBitrateManagementStrategy::Abr { average_bitrate },
+        (quality, _) => VorbisBitrateManagementStrategy::QualityVbr { target_quality: quality.unwrap_or(0.5) as f32 },
+    };
+    let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    let mut encoder = VorbisEncoderBuilder::new(
+        NonZeroU32::new(sample_rate).context("Sample rate must be greater than zero")?,
+        NonZeroU8::new(planes.len() as u8).context("A mixdown needs at least one channel")?,
+        file,
+    )?
+    .bitrate_management_strategy(strategy)
+    .build()?;
+
+    const BLOCK: usize = 4096;
+    for start in (0..planes[0].len()).step_by(BLOCK) {
+        let end = (start + BLOCK).min(planes[0].len());
+        let block: Vec<&[f32]> = planes.iter().map(|plane| &plane[start..end]).collect();
+        encoder.encode_audio_block(&block)?;
+    }
+    encoder.finish()?.flush()?;
+    Ok(())
+}
+
+#[cfg(not(feature = "vorbis"))]
+fn write_vorbis(_: &Path, _: &Stereo, _: &MixdownSettings, _: u32, _: f64) -> Result<()> {
+    anyhow::bail!("Ogg Vorbis export needs dawww-render built with the `vorbis` feature")
+}
+
+/// Opus packets in an Ogg stream, with the identification and comment headers from RFC 7845
+#[cfg(feature = "opus")]
+fn write_opus(path: &Path, buffer: &Stereo, settings: &MixdownSettings, sample_rate: u32, gain: f64) -> Result<()> {
+    use audiopus::{coder::Encoder, Application, Bitrate, Channels, SampleRate};
+    use ogg::{PacketWriteEndInfo, PacketWriter};
+    use std::io::Write;
+
+    const SERIAL: u32 = 0x6461_7777;
+    let planes = planes(buffer, settings, gain);
+    let channels = planes.len();
+    let mut encoder = Encoder::new(
+        SampleRate::try_from(sample_rate as i32)?,
+        if channels == 1 { Channels::Mono } else { Channels::Stereo },
+        Application::Audio,
+    )?;
+    encoder.set_bitrate(Bitrate::BitsPerSecond(settings.bitrate.unwrap_or(128) as i32 * 1000))?;
+
+    // Granule positions always count 48 kHz samples, whatever rate was encoded
+    let to_granule = |samples: usize| (samples as u64 * 48000) / sample_rate as u64;
+    let pre_skip = to_granule(encoder.lookahead()? as usize);
+
+    let mut head = b"OpusHead".to_vec();
+    head.push(1);
+    head.push(channels as u8);
+    head.extend_from_slice(&(pre_skip as u16).to_le_bytes());
+    head.extend_from_slice(&sample_rate.to_le_bytes());
+    head.extend_from_slice(&0_i16.to_le_bytes());
+    head.push(0);
+    let vendor = b"dawww";
+    let mut tags = b"OpusTags".to_vec();
+    tags.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+    tags.extend_from_slice(vendor);
+    tags.extend_from_slice(&0_u32.to_le_bytes());
+
+    let mut writer = PacketWriter::new(std::io::BufWriter::new(std::fs::File::create(path)?));
+    writer.write_packet(head, SERIAL, PacketWriteEndInfo::EndPage, 0)?;
+    writer.write_packet(tags, SERIAL, PacketWriteEndInfo::EndPage, 0)?;
+
+    // 20 ms frames, the last one padded with silence
+    let length = planes[0].len();
+    let frame = sample_rate as usize / 50;
+    let frame_count = length.div_ceil(frame).max(1);
+    let mut packet = vec![0_u8; 4000];
+    for index in 0..frame_count {
+        let mut interleaved = vec![0.0_f32; frame * channels];
+        for (offset, sample) in (index * frame..length.min((index + 1) * frame)).enumerate() {
+            for (channel, plane) in planes.iter().enumerate() {
+                interleaved[offset * channels + channel] = plane[sample];
+            }
+        }
+        let size = encoder.encode_float(&interleaved, &mut packet)?;
+        let last = index + 1 == frame_count;
+        let granule = pre_skip + to_granule(length.min((index + 1) * frame));
+        let end = if last { PacketWriteEndInfo::EndStream } else { PacketWriteEndInfo::NormalPacket };
+        writer.write_packet(packet[..size].to_vec(), SERIAL, end, granule)?;
+    }
+    writer.into_inner().flush()?;
+    Ok(())
+}
+
+#[cfg(not(feature = "opus"))]
+fn write_opus(_: &Path, _: &Stereo, _: &MixdownSettings, _: u32, _: f64) -> Result<()> {
+    anyhow::bail!("Opus export needs dawww-render built with the `opus` feature")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn tone(sample_rate: u32) -> Stereo {
+        let samples: Vec<f64> = (0..sample_rate / 2)
+            .map(|i| (i as f64 * 440.0 * std::f64::consts::TAU / sample_rate as f64).sin() * 0.5)
+            .collect();
+        Stereo { left: samples.clone(), right: samples }
+    }
+
+    fn encode(format: ExportFormat, sample_rate: u32) -> Result<Vec<u8>> {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join(format!("out.{}", format.extension()));
+        let settings = MixdownSettings::new(sample_rate, 16).with_format(format, Some(96));
+        write_audio(&path, &tone(sample_rate), &settings, sample_rate, 1.0, 0)?;
+        Ok(std::fs::read(path).unwrap())
+    }
+
+    #[test]
+    fn test_wav_is_default() {
+        let bytes = encode(ExportFormat::Wav, 8000).unwrap();
+        assert_eq!(&bytes[..4], b"RIFF");
+        let bytes = encode(ExportFormat::Aiff, 8000).unwrap();
+        assert_eq!(&bytes[8..12], b"AIFF");
+    }
+
+    #[test]
+    fn test_lossy_formats() {
+        match encode(ExportFormat::Mp3, 44100) {
+            Ok(bytes) => assert!(bytes.len() > 1000 && (bytes[0] == 0xFF || &bytes[..3] == b"ID3")),
+            Err(error) => assert!(!cfg!(feature = "mp3") && error.to_string().contains("`mp3` feature")),
+        }
+        match encode(ExportFormat::Ogg, 44100) {
+            Ok(bytes) => assert!(&bytes[..4] == b"OggS" && bytes.windows(6).any(|w| w == b"vorbis")),
+            Err(error) => assert!(!cfg!(feature = "vorbis") && error.to_string().contains("`vorbis` feature")),
+        }
+        match encode(ExportFormat::Opus, 48000) {
+            Ok(bytes) => assert!(&bytes[..4] == b"OggS" && bytes.windows(8).any(|w| w == b"OpusHead")),
+            Err(error) => assert!(!cfg!(feature = "opus") && error.to_string().contains("`opus` feature")),
+        }
+    }
+}
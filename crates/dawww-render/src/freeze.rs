@@ -0,0 +1,73 @@
+use anyhow::Result;
+use dawww_core::DawFile;
+use std::path::{Path, PathBuf};
+use crate::AudioEngine;
+
+/// Directory, relative to the project, that frozen tracks are written to
+pub const FROZEN_DIR: &str = "frozen";
+
+/// Bounce an instrument to `frozen/<id>.wav` in the project directory and switch it to
+/// play that file. Its events are kept; use [`unfreeze_instrument`] to go back to them.
+/// Returns the path of the written file.
+pub fn freeze_instrument(daw_file: &mut DawFile, project_dir: &Path, instrument_id: &str) -> Result<PathBuf> {
+    let relative_path = Path::new(FROZEN_DIR).join(format!("{}.wav", instrument_id));
+    let output_path = project_dir.join(&relative_path);
+    std::fs::create_dir_all(output_path.parent().unwrap())?;
+
+    AudioEngine::new(daw_file.clone())
+        .with_project_dir(project_dir.to_path_buf())
+        .render_instrument(instrument_id, &output_path)?;
+    daw_file.freeze_instrument(instrument_id, relative_path)?;
+    Ok(output_path)
+}
+
+/// Switch a frozen instrument back to its events and delete the frozen audio
+pub fn unfreeze_instrument(daw_file: &mut DawFile, project_dir: &Path, instrument_id: &str) -> Result<()> {
+    let freeze = daw_file.unfreeze_instrument(instrument_id)?;
+    let audio_path = project_dir.join(&freeze.audio_file);
+    if audio_path.exists() {
+        std::fs::remove_file(audio_path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dawww_core::effects::{Effect, EffectInstance, GainParams};
+    use dawww_core::instrument::{Instrument, SubtractiveParams};
+    use dawww_core::{pitch::{Pitch, Tone}, Note};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_freeze_and_unfreeze() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut daw_file = DawFile::new("Test".to_string());
+        daw_file.add_instrument(
+            "lead".to_string(),
+            Instrument::new_subtractive(SubtractiveParams::default())
+                .with_effect(EffectInstance::new(Effect::Gain(GainParams::new(-6.0)))),
+        ).unwrap();
+        daw_file.add_note("1.0", "lead", Note::new(Pitch::new(Tone::A, 4), 8)).unwrap();
+
+        let spb = 60.0 / (120.0 * 8.0);
+        let live = AudioEngine::new(daw_file.clone()).processed_tracks(22050, spb, |_| true, true).unwrap()["lead"].left.clone();
+
+        let path = freeze_instrument(&mut daw_file, temp_dir.path(), "lead").unwrap();
+        assert_eq!(path, temp_dir.path().join("frozen/lead.wav"));
+        assert_eq!(daw_file.instruments["lead"].frozen.as_ref().unwrap().audio_file, "frozen/lead.wav");
+        assert_eq!(daw_file.events.len(), 1);
+
+        // The frozen audio plays back the effected track, and stays in place even if the
+        // events change until the track is unfrozen
+        daw_file.add_note("1.0", "lead", Note::new(Pitch::new(Tone::C, 5), 8)).unwrap();
+        let engine = AudioEngine::new(daw_file.clone()).with_project_dir(temp_dir.path().to_path_buf());
+        let frozen = &engine.processed_tracks(22050, spb, |_| true, true).unwrap()["lead"].left;
+        assert!(live.iter().zip(frozen).all(|(a, b)| (a - b).abs() < 1e-6));
+
+        unfreeze_instrument(&mut daw_file, temp_dir.path(), "lead").unwrap();
+        assert!(daw_file.instruments["lead"].frozen.is_none());
+        assert!(!path.exists());
+        assert!(unfreeze_instrument(&mut daw_file, temp_dir.path(), "lead").is_err());
+    }
+}
@@ -0,0 +1,336 @@
+use anyhow::{Result, bail};
+use std::f64::consts::PI;
+use std::path::Path;
+use crate::stereo::Stereo;
+
+/// What a picture of a render shows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageKind {
+    /// Each channel's peaks and RMS over time, left above right
+    Waveform,
+    /// How loud each frequency is over time, from 20 Hz at the bottom to Nyquist at the top
+    /// on a log scale
+    Spectrogram,
+}
+
+/// An 8-bit RGB picture
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    /// Rows from the top, each from left to right
+    pub pixels: Vec<[u8; 3]>,
+}
+
+const BACKGROUND: [u8; 3] = [20, 20, 28];
+const AXIS: [u8; 3] = [60, 60, 76];
+const PEAK: [u8; 3] = [56, 150, 220];
+const RMS: [u8; 3] = [150, 210, 250];
+
+/// Frames analysed for each column of a spectrogram
+const FFT_SIZE: usize = 2048;
+/// Level drawn as black in a spectrogram, in dBFS
+const FLOOR_DB: f64 = -100.0;
+const LOWEST_FREQUENCY: f64 = 20.0;
+
+impl Image {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self { width, height, pixels: vec![BACKGROUND; width as usize * height as usize] }
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 3] {
+        self.pixels[(y * self.width + x) as usize]
+    }
+
+    fn set(&mut self, x: u32, y: u32, color: [u8; 3]) {
+        self.pixels[(y * self.width + x) as usize] = color;
+    }
+
+    pub fn write_png(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, self.to_png())?;
+        Ok(())
+    }
+
+    /// The picture as a PNG file. The image data is stored rather than compressed, which
+    /// every PNG reader accepts.
+    pub fn to_png(&self) -> Vec<u8> {
+        // Each row starts with its filter type, 0 for none
+        let mut raw = Vec::with_capacity(self.height as usize * (1 + 3 * self.width as usize));
+        for row in self.pixels.chunks(self.width.max(1) as usize) {
+            raw.push(0);
+            raw.extend(row.iter().flatten());
+        }
+
+        let mut header = Vec::with_capacity(13);
+        header.extend(self.width.to_be_bytes());
+        header.extend(self.height.to_be_bytes());
+        // 8 bits per channel, RGB, then the standard compression, filtering and no interlacing
+        header.extend([8, 2, 0, 0, 0]);
+
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        write_chunk(&mut png, b"IHDR", &header);
+        write_chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+        write_chunk(&mut png, b"IEND", &[]);
+        png
+    }
+}
+
+/// Draw `buffer`, recorded at `sample_rate`, as a `width` by `height` picture
+pub fn render(kind: ImageKind, buffer: &Stereo, sample_rate: u32, width: u32, height: u32) -> Result<Image> {
+    if width == 0 || height < 2 {
+        bail!("Images must be at least 1x2 pixels, got {}x{}", width, height);
+    }
+    Ok(match kind {
+        ImageKind::Waveform => waveform(buffer, width, height),
+        ImageKind::Spectrogram => spectrogram(buffer, sample_rate, width, height),
+    })
+}
+
+/// Each channel in its own lane, left above right, with every column showing the span of
+/// the samples it covers and their RMS level inside it. Full scale reaches the lane's edges.
+pub fn waveform(buffer: &Stereo, width: u32, height: u32) -> Image {
+    let mut image = Image::new(width, height);
+    let lanes = [(&buffer.left, 0, height / 2), (&buffer.right, height / 2, height - height / 2)];
+    for (channel, top, lane_height) in lanes {
+        if lane_height == 0 {
+            continue;
+        }
+        let bottom = top + lane_height - 1;
+        let half = lane_height as f64 / 2.0;
+        let row = |level: f64| (top as f64 + half - level.clamp(-1.0, 1.0) * half).clamp(top as f64, bottom as f64) as u32;
+        for x in 0..width {
+            image.set(x, row(0.0), AXIS);
+            let start = channel.len() * x as usize / width as usize;
+            let end = (channel.len() * (x as usize + 1) / width as usize).max(start + 1).min(channel.len());
+            if start >= end {
+                continue;
+            }
+            let column = &channel[start..end];
+            let (low, high) = column.iter().fold((f64::MAX, f64::MIN), |(low, high), &s| (low.min(s), high.max(s)));
+            let rms = crate::compare::rms(column);
+            for y in row(high)..=row(low) {
+                image.set(x, y, PEAK);
+            }
+            if rms > 0.0 {
+                for y in row(rms.min(high))..=row((-rms).max(low)) {
+                    image.set(x, y, RMS);
+                }
+            }
+        }
+    }
+    image
+}
+
+/// Short-time spectra of the channels' average, one Hann-windowed FFT centred on each
+/// column. Rows split the frequencies from 20 Hz to Nyquist into equal steps of pitch and
+/// show the loudest bin they hold, from black at -100 dBFS up through red and yellow to
+/// white at full scale.
+pub fn spectrogram(buffer: &Stereo, sample_rate: u32, width: u32, height: u32) -> Image {
+    let mut image = Image::new(width, height);
+    let mono = buffer.to_mono();
+    let window: Vec<f64> = (0..FFT_SIZE).map(|i| 0.5 - 0.5 * (2.0 * PI * i as f64 / FFT_SIZE as f64).cos()).collect();
+    // A full-scale sine reads 0 dB
+    let window_gain = window.iter().sum::<f64>() / 2.0;
+    let nyquist = sample_rate as f64 / 2.0;
+    let lowest = LOWEST_FREQUENCY.min(nyquist / 2.0);
+    let bin = |frequency: f64| (frequency / sample_rate as f64 * FFT_SIZE as f64) as usize;
+    // The bins each row covers, bottom row first
+    let rows: Vec<(usize, usize)> = (0..height)
+        .map(|row| {
+            let frequency = |row: u32| lowest * (nyquist / lowest).powf(row as f64 / height as f64);
+            let first = bin(frequency(row)).min(FFT_SIZE / 2);
+            (first, bin(frequency(row + 1)).clamp(first + 1, FFT_SIZE / 2 + 1))
+        })
+        .collect();
+
+    for x in 0..width {
+        let centre = (mono.len() * (2 * x as usize + 1) / (2 * width as usize)) as isize;
+        let mut real: Vec<f64> = window.iter().enumerate()
+            .map(|(i, w)| {
+                let index = centre + i as isize - FFT_SIZE as isize / 2;
+                usize::try_from(index).ok().and_then(|index| mono.get(index)).map_or(0.0, |s| s * w)
+            })
+            .collect();
+        let mut imaginary = vec![0.0; FFT_SIZE];
+        fft(&mut real, &mut imaginary);
+
+        for (row, &(first, last)) in rows.iter().enumerate() {
+            let magnitude = (first..last).map(|k| real[k].hypot(imaginary[k])).fold(0.0, f64::max) / window_gain;
+            let db = (20.0 * magnitude.log10()).max(FLOOR_DB);
+            image.set(x, height - 1 - row as u32, heat(1.0 - db / FLOOR_DB));
+        }
+    }
+    image
+}
+
+/// Colour for a level from 0 (black) to 1 (white), through purple, red and yellow
+fn heat(level: f64) -> [u8; 3] {
+    const STOPS: [[f64; 3]; 5] = [[0.0, 0.0, 0.0], [90.0, 20.0, 130.0], [220.0, 40.0, 50.0], [250.0, 200.0, 40.0], [255.0, 255.0, 255.0]];
+    let position = level.clamp(0.0, 1.0) * (STOPS.len() - 1) as f64;
+    let index = (position as usize).min(STOPS.len() - 2);
+    let fraction = position - index as f64;
+    let [from, to] = [STOPS[index], STOPS[index + 1]];
+    [0, 1, 2].map(|c| (from[c] + (to[c] - from[c]) * fraction).round() as u8)
+}
+
+/// In-place radix-2 FFT of a signal whose length is a power of two
+fn fft(real: &mut [f64], imaginary: &mut [f64]) {
+    let n = real.len();
+    // Reorder into bit-reversed index order
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            real.swap(i, j);
+            imaginary.swap(i, j);
+        }
+    }
+
+    let twiddles: Vec<(f64, f64)> = (0..n / 2).map(|k| (-2.0 * PI * k as f64 / n as f64).sin_cos()).collect();
+    let mut len = 2;
+    while len <= n {
+        let stride = n / len;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (sin, cos) = twiddles[k * stride];
+                let (a, b) = (start + k, start + k + len / 2);
+                let re = real[b] * cos - imaginary[b] * sin;
+                let im = real[b] * sin + imaginary[b] * cos;
+                real[b] = real[a] - re;
+                imaginary[b] = imaginary[a] - im;
+                real[a] += re;
+                imaginary[a] += im;
+            }
+        }
+        len *= 2;
+    }
+}
+
+/// Append a PNG chunk: its length, type, data and a CRC of the type and data
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend((data.len() as u32).to_be_bytes());
+    png.extend(kind);
+    png.extend(data);
+    png.extend(crc32(kind.iter().chain(data)).to_be_bytes());
+}
+
+/// A zlib stream holding `data` in uncompressed deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    const MAX_BLOCK: usize = 65535;
+    let mut stream = vec![0x78, 0x01];
+    let block_count = data.len().div_ceil(MAX_BLOCK).max(1);
+    for (i, block) in data.chunks(MAX_BLOCK).chain(data.is_empty().then_some(&[][..])).enumerate() {
+        // Stored blocks start with whether they're the last, then their length and its complement
+        stream.push(u8::from(i + 1 == block_count));
+        let len = block.len() as u16;
+        stream.extend(len.to_le_bytes());
+        stream.extend((!len).to_le_bytes());
+        stream.extend(block);
+    }
+    stream.extend(adler32(data).to_be_bytes());
+    stream
+}
+
+fn crc32<'a>(bytes: impl Iterator<Item = &'a u8>) -> u32 {
+    let mut crc = u32::MAX;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1_u32, 0_u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(frequency: f64, sample_rate: u32, len: usize) -> Vec<f64> {
+        (0..len).map(|i| (2.0 * PI * frequency * i as f64 / sample_rate as f64).sin()).collect()
+    }
+
+    #[test]
+    fn test_png_layout() {
+        let mut image = Image::new(3, 2);
+        image.set(2, 1, [1, 2, 3]);
+        let png = image.to_png();
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(&png[16..29], [0, 0, 0, 3, 0, 0, 0, 2, 8, 2, 0, 0, 0]);
+        // IEND's CRC is the same in every PNG
+        assert_eq!(&png[png.len() - 12..], [0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]);
+
+        // The stored image data is each row behind a filter byte
+        let idat = &png[33 + 8..png.len() - 12 - 4];
+        assert_eq!(&idat[..3], [0x78, 0x01, 1]);
+        let raw = &idat[7..idat.len() - 4];
+        assert_eq!(raw.len(), 2 * (1 + 9));
+        assert_eq!(&raw[17..20], [1, 2, 3]);
+        assert_eq!(&idat[idat.len() - 4..], adler32(raw).to_be_bytes());
+    }
+
+    #[test]
+    fn test_zlib_stored_splits_long_data() {
+        let data = vec![7; 70000];
+        let stream = zlib_stored(&data);
+        assert_eq!(stream.len(), 2 + 5 + 65535 + 5 + 4465 + 4);
+        assert_eq!(stream[2], 0);
+        assert_eq!(stream[2 + 5 + 65535], 1);
+        assert_eq!(zlib_stored(&[]), [0x78, 0x01, 1, 0, 0, 0xFF, 0xFF, 0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn test_waveform() {
+        let buffer = Stereo { left: sine(100.0, 8000, 8000), right: vec![0.0; 8000] };
+        let image = waveform(&buffer, 40, 20);
+        // The left lane spans its full height; the silent right one is a flat line
+        assert_eq!(image.pixel(10, 0), PEAK);
+        assert_eq!(image.pixel(10, 9), PEAK);
+        assert_eq!(image.pixel(10, 5), RMS);
+        assert_eq!(image.pixel(10, 15), PEAK);
+        assert_eq!(image.pixel(10, 12), BACKGROUND);
+
+        // An empty render only shows the centre lines
+        let image = waveform(&Stereo::default(), 40, 20);
+        assert_eq!(image.pixel(30, 5), AXIS);
+        assert_eq!(image.pixel(30, 15), AXIS);
+        assert_eq!(image.pixel(30, 3), BACKGROUND);
+    }
+
+    #[test]
+    fn test_spectrogram_shows_a_sine_at_its_pitch() {
+        let buffer = Stereo::from_mono(sine(1000.0, 8000, 8000));
+        let image = spectrogram(&buffer, 8000, 10, 100);
+        // 1 kHz is at log(1000 / 20) / log(4000 / 20) of the way up
+        let expected = 99 - (100.0 * (50.0_f64).ln() / (200.0_f64).ln()) as u32;
+        for x in 0..10 {
+            let brightest = (0..100).max_by_key(|&y| image.pixel(x, y).iter().map(|&c| c as u32).sum::<u32>()).unwrap();
+            assert!(brightest.abs_diff(expected) <= 1, "{} {}", brightest, expected);
+        }
+        assert_eq!(image.pixel(5, 0), [0, 0, 0]);
+    }
+
+    #[test]
+    fn test_fft() {
+        let mut real = sine(4.0, 64, 64);
+        let mut imaginary = vec![0.0; 64];
+        fft(&mut real, &mut imaginary);
+        assert!((imaginary[4] + 32.0).abs() < 1e-9);
+        assert!((0..64).filter(|&k| k != 4 && k != 60).all(|k| real[k].hypot(imaginary[k]) < 1e-9));
+    }
+}
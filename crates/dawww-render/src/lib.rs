@@ -1,105 +1,1034 @@
-use dawww_core::DawFile;
-use anyhow::Result;
-use std::path::Path;
+use dawww_core::{Click, ClickMode, DawFile, Events, ExportFormat, InstrumentKind, MixdownSettings, Normalize, Note, Speakers, VoiceRequest};
+use dawww_core::controllers::{bend_ratio, ControlCurve, Controller};
+use dawww_core::pitch::Pitch;
+use dawww_core::effects::EffectInstance;
+use anyhow::{Context, Result, anyhow};
+use rayon::prelude::*;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+pub mod aiff;
+pub mod analysis;
+pub mod compare;
+pub mod effects;
+pub mod encode;
+pub mod freeze;
+pub mod image;
+pub mod loudness;
+pub mod note;
+pub mod oversample;
+pub mod progress;
+pub mod sampler;
+pub mod sfz;
+pub mod speakers;
+pub mod stereo;
+mod stream;
+pub mod synth;
+pub mod wav;
+
+pub use analysis::RenderAnalysis;
+pub use compare::{compare_buffers, compare_wav_files, ComparisonReport};
+pub use freeze::{freeze_instrument, unfreeze_instrument};
+pub use image::ImageKind;
+pub use note::{NoteRenderer, PlayedNote};
+pub use progress::{CancelToken, ProgressCallback, RenderProgress};
+use sampler::{wav_seconds, Sample};
+use sfz::SfzPlayer;
+use effects::Chain;
+use stereo::{balance_gains, Stereo};
+use stream::Stream;
+use synth::{note_seed, Envelope};
+
+/// A note's index in the song's notes, in time order
+type NoteKey = usize;
+
+/// One signal for each of a mixdown's channels, in file order
+type Channels = Vec<Vec<f64>>;
+
+/// Share of a render's progress spent rendering notes, then running insert effects;
+/// mixing takes the rest
+const NOTES_PERCENT: f64 = 85.0;
+const EFFECTS_PERCENT: f64 = 10.0;
+
+/// Length of one metronome click, and how quickly it decays
+const CLICK_SECONDS: f64 = 0.05;
+const CLICK_DECAY_SECONDS: f64 = 0.008;
+
+/// The signals summed on the master bus, keyed by track id or bus name
+#[derive(Default)]
+struct MasterInputs<'a> {
+    /// Tracks routed straight to master
+    tracks: BTreeMap<&'a str, Stereo>,
+    /// Buses that output to master
+    buses: BTreeMap<&'a str, Stereo>,
+}
+
+/// Effect chains of the mixer's buses, and of the master bus for each group of speakers,
+/// made as the mix first needs them. A stream keeps them from one block to the next.
+struct BusChains {
+    sample_rate: u32,
+    bpm: f64,
+    oversampling: usize,
+    buses: BTreeMap<String, Chain>,
+    master: BTreeMap<Speakers, Chain>,
+}
+
+impl BusChains {
+    fn new(sample_rate: u32, bpm: f64, oversampling: usize) -> Self {
+        Self { sample_rate, bpm, oversampling, buses: BTreeMap::new(), master: BTreeMap::new() }
+    }
+
+    fn bus(&mut self, name: &str, effects: &[EffectInstance]) -> &mut Chain {
+        let (sample_rate, bpm, oversampling) = (self.sample_rate, self.bpm, self.oversampling);
+        self.buses.entry(name.to_string()).or_insert_with(|| Chain::new(effects, sample_rate, bpm, oversampling))
+    }
+
+    fn master(&mut self, speakers: Speakers, effects: &[EffectInstance]) -> &mut Chain {
+        let (sample_rate, bpm, oversampling) = (self.sample_rate, self.bpm, self.oversampling);
+        self.master.entry(speakers).or_insert_with(|| Chain::new(effects, sample_rate, bpm, oversampling))
+    }
+}
+
+/// One of the song's notes, as `Events::notes` lists it
+struct SongNote<'a> {
+    position: u32,
+    instrument_id: &'a str,
+    note: &'a Note,
+    /// How many notes of the same pitch start with it on its track before it
+    doubling: usize,
+}
+
+/// Each of `notes`, in `Events::notes` order, with how many notes of the same pitch come
+/// before it in its chord, which tells doubled notes apart
+fn with_doublings<'a>(
+    notes: impl Iterator<Item = (u32, &'a str, &'a Note)>,
+) -> impl Iterator<Item = ((u32, &'a str, &'a Note), usize)> {
+    let mut chord: (u32, &str, Vec<Pitch>) = (0, "", Vec::new());
+    notes.map(move |(position, instrument_id, note)| {
+        if (chord.0, chord.1) != (position, instrument_id) {
+            chord = (position, instrument_id, Vec::new());
+        }
+        let doubling = chord.2.iter().filter(|pitch| **pitch == note.pitch).count();
+        chord.2.push(note.pitch);
+        ((position, instrument_id, note), doubling)
+    })
+}
 
 /// The main audio rendering engine that converts a DawFile into audio output
 pub struct AudioEngine {
     daw_file: DawFile,
+    /// The song's events as played, with arpeggios expanded
+    events: Events,
+    project_dir: PathBuf,
+    sample_rate: Option<u32>,
+    profile: Option<String>,
+    /// Pool for rendering tracks, built once by `with_threads`; `None` uses rayon's
+    /// global pool
+    pool: Option<rayon::ThreadPool>,
+    progress: Option<ProgressCallback>,
+    /// The furthest the render under way has been reported to get, so reports made on
+    /// worker threads never go backwards
+    reported: Mutex<Option<RenderProgress>>,
+    cancel: CancelToken,
+    /// Seed for the render's randomness in place of the project's
+    seed: Option<u64>,
+    /// The song as `process` renders it, made by `prepare`
+    stream: Option<Stream>,
+    /// Next frame `process` plays
+    stream_position: usize,
 }
 
 impl AudioEngine {
     /// Create a new AudioEngine instance from a DawFile
     pub fn new(daw_file: DawFile) -> Self {
-        Self { daw_file }
+        Self {
+            events: daw_file.played_events().into_owned(),
+            daw_file,
+            project_dir: PathBuf::new(),
+            sample_rate: None,
+            profile: None,
+            pool: None,
+            progress: None,
+            reported: Mutex::new(None),
+            cancel: CancelToken::new(),
+            seed: None,
+            stream: None,
+            stream_position: 0,
+        }
+    }
+
+    /// Set the directory that sample paths are relative to (usually the .daw.json's directory)
+    pub fn with_project_dir(mut self, project_dir: PathBuf) -> Self {
+        self.project_dir = project_dir;
+        self
+    }
+
+    /// Render at `sample_rate` instead of the project's mixdown rate (e.g. for quick drafts)
+    pub fn with_sample_rate(mut self, sample_rate: u32) -> Self {
+        self.sample_rate = Some(sample_rate);
+        self.stream = None;
+        self
+    }
+
+    /// Render tracks on `threads` worker threads instead of one per core. The output is
+    /// the same whatever the count.
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        // Fall back on the global pool if the threads can't be started
+        self.pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().ok();
+        self
+    }
+
+    /// Call `progress` as renders make progress
+    pub fn with_progress(mut self, progress: impl Fn(RenderProgress) + Send + Sync + 'static) -> Self {
+        self.progress = Some(Arc::new(progress));
+        self
+    }
+
+    /// Stop renders when `cancel` is cancelled; they return an error instead of finishing
+    pub fn with_cancel_token(mut self, cancel: CancelToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Draw noise and dither from `seed` instead of the project's seed. Renders with the same
+    /// seed are identical.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self.stream = None;
+        self
+    }
+
+    /// Render with one of the project's named mixdown profiles instead of its default settings
+    pub fn with_profile(mut self, name: &str) -> Result<Self> {
+        self.daw_file.mixdown_profile(Some(name))?;
+        self.profile = Some(name.to_string());
+        self.stream = None;
+        Ok(self)
+    }
+
+    /// The mixdown settings used for rendering
+    pub fn mixdown(&self) -> &MixdownSettings {
+        self.daw_file.mixdown_profile(self.profile.as_deref()).unwrap_or(&self.daw_file.mixdown)
+    }
+
+    /// The sample rate used for rendering
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate.unwrap_or(self.mixdown().sample_rate)
+    }
+
+    /// The mixdown settings for writing to `path`, in the format its extension names if any
+    fn mixdown_for(&self, path: &Path) -> Cow<'_, MixdownSettings> {
+        let mixdown = self.mixdown();
+        match path.extension().and_then(|e| e.to_str()).and_then(ExportFormat::from_extension) {
+            Some(format) if format != mixdown.format => Cow::Owned(MixdownSettings { format, ..mixdown.clone() }),
+            _ => Cow::Borrowed(mixdown),
+        }
+    }
+
+    /// How many times the sample rate synth filters and distortion run at, at least once
+    fn oversampling(&self) -> usize {
+        self.mixdown().oversampling.max(1) as usize
+    }
+
+    /// The seed all of a render's randomness is drawn from
+    pub fn seed(&self) -> u64 {
+        self.seed.unwrap_or(self.daw_file.seed)
     }
 
-    /// Render the song to a WAV file at the specified path
+    /// Resolve the sample file for a sampler instrument through the project's sample registry
+    pub fn resolve_sample(&self, instrument_id: &str) -> Result<PathBuf> {
+        let instrument = self.daw_file.get_instrument(instrument_id)
+            .ok_or_else(|| anyhow!("Instrument '{}' not found", instrument_id))?;
+        let sample_path = instrument.sample_file()
+            .ok_or_else(|| anyhow!("Instrument '{}' is not a sampler", instrument_id))?;
+        self.daw_file.samples.resolve(&self.project_dir, sample_path)
+    }
+
+    /// Render the song to the specified path, in the file format its extension names (e.g.
+    /// `.wav`, `.aif` or `.mp3`) or the mixdown's format if it doesn't name one
     pub fn render(&self, output_path: &Path) -> Result<()> {
-        // Calculate total duration in seconds
+        let (feeds, _) = self.render_mix()?;
+        encode::write_channels(output_path, &feeds, &self.mixdown_for(output_path), self.sample_rate(), 1.0, self.seed())
+    }
+
+    /// Render the song like `render`, then analyze what was written. Surround mixes are
+    /// analyzed folded down to stereo.
+    pub fn render_analyzed(&self, output_path: &Path) -> Result<RenderAnalysis> {
+        let (feeds, tracks) = self.render_mix()?;
+        encode::write_channels(output_path, &feeds, &self.mixdown_for(output_path), self.sample_rate(), 1.0, self.seed())?;
+        Ok(analysis::analyze(&speakers::to_stereo(&feeds), &tracks, self.sample_rate()))
+    }
+
+    /// Render the whole song in memory at the mixdown's level: normalized if the mixdown
+    /// asks for it, not yet clipped, with equal channels for mono mixdowns and surround
+    /// mixdowns folded down to stereo
+    pub fn render_buffer(&self) -> Result<Stereo> {
+        self.render_mix().map(|(feeds, _)| speakers::to_stereo(&feeds))
+    }
+
+    /// The mix in the mixdown's channels, in file order, along with the processed tracks
+    /// that fed it
+    fn render_mix(&self) -> Result<(Channels, BTreeMap<&str, Stereo>)> {
         let seconds_per_32nd_note = 60.0 / (self.daw_file.bpm as f64 * 8.0);
         let total_duration = self.calculate_total_duration(seconds_per_32nd_note);
 
-        // Create WAV writer
-        let spec = hound::WavSpec {
-            channels: 2,
-            sample_rate: self.daw_file.mixdown.sample_rate,
-            bits_per_sample: self.daw_file.mixdown.bit_depth,
-            sample_format: hound::SampleFormat::Int,
-        };
+        let mixdown = self.mixdown();
+        mixdown.validate()?;
+        let buffer_len = (total_duration * self.sample_rate() as f64) as usize;
+        let mixer = &self.daw_file.mixer;
+        let tracks = self.processed_tracks(buffer_len, seconds_per_32nd_note, |id| mixer.is_audible(id), true)?;
+        let mut feeds = self.mix_tracks(&tracks, buffer_len, seconds_per_32nd_note)?;
+
+        let scale = self.output_gain(&feeds);
+        for channel in &mut feeds {
+            channel.iter_mut().for_each(|s| *s *= scale);
+        }
+        let kept = self.kept_range(&feeds, 1.0);
+        if let Some(click) = mixdown.click.as_ref().filter(|click| click.mode == ClickMode::Mix) {
+            // The click plays from the front pair
+            let click = self.click_track(click, 0..buffer_len, seconds_per_32nd_note);
+            for channel in feeds.iter_mut().take(2) {
+                mix_into(channel, &click, 1.0);
+            }
+        }
+        self.finish(&mut feeds, kept);
+        self.report(100.0, self.end_bar(buffer_len, seconds_per_32nd_note));
+        Ok((feeds, tracks))
+    }
 
-        let mut writer = hound::WavWriter::create(output_path, spec)?;
-        let mut buffer = vec![0.0; (total_duration * self.daw_file.mixdown.sample_rate as f64) as usize];
-
-        // Process each event
-        for event in &self.daw_file.events {
-            let time_in_seconds = self.parse_time(&event.time, seconds_per_32nd_note);
-            let sample_index = (time_in_seconds * self.daw_file.mixdown.sample_rate as f64) as usize;
-
-            // For now, just generate a simple sine wave for each note
-            for note in &event.notes {
-                let frequency = note.pitch.frequency(note.pitch.octave);
-                let duration_samples = (note.duration as f64 * seconds_per_32nd_note * self.daw_file.mixdown.sample_rate as f64) as usize;
-
-                for i in 0..duration_samples {
-                    let t = i as f64 / self.daw_file.mixdown.sample_rate as f64;
-                    let sample = (2.0 * std::f64::consts::PI * frequency * t).sin();
-                    
-                    if sample_index + i < buffer.len() {
-                        buffer[sample_index + i] += sample;
-                    }
+    /// Get ready to stream the song with `process`: check the mix and that every sample it
+    /// plays can be found, and load frozen tracks. Nothing is rendered yet. Does nothing if
+    /// it's already prepared.
+    pub fn prepare(&mut self) -> Result<()> {
+        if self.stream.is_none() {
+            self.stream = Some(Stream::new(self)?);
+        }
+        Ok(())
+    }
+
+    /// Play `daw_file` in place of the song from now on, e.g. after an edit, keeping the
+    /// stream's place. Returns the first frame that may sound different, or `None` if none
+    /// does: audio streamed from there on is out of date and should be streamed again. The
+    /// new song is prepared straight away, and if it can't be played the old one is kept.
+    pub fn update(&mut self, daw_file: DawFile) -> Result<Option<usize>> {
+        let changed_from = self.changed_from(&daw_file);
+        let old_daw_file = std::mem::replace(&mut self.daw_file, daw_file);
+        let old_events = std::mem::replace(&mut self.events, self.daw_file.played_events().into_owned());
+        if changed_from.is_some() || self.stream.is_none() {
+            match Stream::new(self) {
+                Ok(stream) => self.stream = Some(stream),
+                Err(e) => {
+                    self.daw_file = old_daw_file;
+                    self.events = old_events;
+                    return Err(e);
+                }
+            }
+        }
+        Ok(changed_from)
+    }
+
+    /// The first frame where `daw_file` may sound different from the song. Changes to
+    /// anything but the notes may change any of it.
+    fn changed_from(&self, daw_file: &DawFile) -> Option<usize> {
+        let (old, new) = (&self.daw_file, daw_file);
+        let sounds_the_same = old.bpm == new.bpm
+            && old.groove == new.groove
+            && old.tuning == new.tuning
+            && old.seed == new.seed
+            && old.mixdown == new.mixdown
+            && old.mixdown_profiles == new.mixdown_profiles
+            && old.instruments == new.instruments
+            && old.samples == new.samples
+            && old.mixer == new.mixer
+            && old.controls == new.controls;
+        if !sounds_the_same {
+            return Some(0);
+        }
+        let events = new.played_events();
+        let (old_notes, new_notes): (Vec<_>, Vec<_>) = (self.events.notes().collect(), events.notes().collect());
+        let first = old_notes.iter().zip(&new_notes).position(|(old, new)| old != new)
+            .unwrap_or(old_notes.len().min(new_notes.len()));
+        // The edit is heard from the earlier of the notes that differ
+        let seconds_per_32nd_note = 60.0 / (new.bpm as f64 * 8.0);
+        [old_notes.get(first), new_notes.get(first)].into_iter().flatten()
+            .map(|&(position, instrument_id, _)| {
+                (self.seconds_at(instrument_id, position, seconds_per_32nd_note) * self.sample_rate() as f64) as usize
+            })
+            .min()
+    }
+
+    /// Fill `out` with the next interleaved stereo frames of the song at `sample_rate()`,
+    /// clipped to full scale, and silence once the song has ended. Only the frames asked for
+    /// are rendered. They're at the mixer's level, as the song sounds before normalization
+    /// and silence trimming, which need the whole song. The stream is prepared on the first
+    /// call unless `prepare` already did; if that fails `out` is left silent.
+    pub fn process(&mut self, out: &mut [f32]) {
+        out.fill(0.0);
+        if self.prepare().is_err() {
+            return;
+        }
+        let mut stream = self.stream.take().unwrap();
+        let frames = (out.len() / 2).min(stream.len().saturating_sub(self.stream_position));
+        if let Ok(block) = stream.render(self, self.stream_position, frames) {
+            for (frame, (left, right)) in out.chunks_exact_mut(2).zip(block.left.iter().zip(&block.right)) {
+                frame[0] = left.clamp(-1.0, 1.0) as f32;
+                frame[1] = right.clamp(-1.0, 1.0) as f32;
+            }
+        }
+        self.stream_position += frames;
+        self.stream = Some(stream);
+    }
+
+    /// Move `process` to a frame of the song
+    pub fn seek(&mut self, frame: usize) {
+        self.stream_position = frame;
+    }
+
+    /// Frame `process` plays next
+    pub fn position(&self) -> usize {
+        self.stream_position
+    }
+
+    /// Whether `process` has played the whole song
+    pub fn is_finished(&self) -> bool {
+        self.stream.as_ref().is_some_and(|stream| self.stream_position >= stream.len())
+    }
+
+    /// Render one instrument from its events, with its insert effects, to a stereo 32-bit
+    /// float WAV file. The level isn't normalized and the track's mixer pan isn't applied,
+    /// so the file can stand in for the track.
+    pub fn render_instrument(&self, instrument_id: &str, output_path: &Path) -> Result<()> {
+        let track = self.instrument_buffer(instrument_id)?;
+        wav::write_wav(output_path, &track, &MixdownSettings::float(self.sample_rate()), self.sample_rate(), 1.0, self.seed())
+    }
+
+    /// One instrument rendered from its events with its insert effects, as `render_instrument`
+    /// writes it
+    fn instrument_buffer(&self, instrument_id: &str) -> Result<Stereo> {
+        if self.daw_file.get_instrument(instrument_id).is_none() {
+            return Err(anyhow!("Instrument '{}' not found", instrument_id));
+        }
+        let seconds_per_32nd_note = 60.0 / (self.daw_file.bpm as f64 * 8.0);
+        let buffer_len = (self.calculate_total_duration(seconds_per_32nd_note) * self.sample_rate() as f64) as usize;
+        let mut tracks = self.processed_tracks(buffer_len, seconds_per_32nd_note, |id| id == instrument_id, false)?;
+        self.report(100.0, self.end_bar(buffer_len, seconds_per_32nd_note));
+        Ok(tracks.remove(instrument_id).unwrap_or_default())
+    }
+
+    /// Draw the mix, as `render_buffer` returns it, to a `width` by `height` PNG image
+    pub fn render_image(&self, output_path: &Path, kind: ImageKind, width: u32, height: u32) -> Result<()> {
+        let buffer = self.render_buffer()?;
+        image::render(kind, &buffer, self.sample_rate(), width, height)?.write_png(output_path)
+    }
+
+    /// Draw one instrument, as `render_instrument` renders it, to a `width` by `height` PNG
+    /// image. Its level isn't normalized, so quiet tracks look quiet.
+    pub fn render_instrument_image(
+        &self,
+        instrument_id: &str,
+        output_path: &Path,
+        kind: ImageKind,
+        width: u32,
+        height: u32,
+    ) -> Result<()> {
+        let track = self.instrument_buffer(instrument_id)?;
+        image::render(kind, &track, self.sample_rate(), width, height)?.write_png(output_path)
+    }
+
+    /// Render a stem for every signal feeding the master bus into `output_dir`: one per
+    /// instrument routed straight to master (`track-<id>`) and one per bus feeding master
+    /// (`bus-<name>`), each after its inserts, pan and fader but before the master bus. Stems
+    /// cover the whole song, use the mixdown's format, and share the full mix's normalization,
+    /// so summing them through the master bus rebuilds the mix. They're trimmed and faded
+    /// like the mix so they stay lined up with it. Tracks the mixer mutes, or leaves out
+    /// of a solo, get silent stems. A metronome click, if the mixdown has one, gets a
+    /// `click` stem at its own level. Returns the written paths.
+    pub fn render_stems(&self, output_dir: &Path) -> Result<Vec<PathBuf>> {
+        let seconds_per_32nd_note = 60.0 / (self.daw_file.bpm as f64 * 8.0);
+        let total_duration = self.calculate_total_duration(seconds_per_32nd_note);
+
+        let mixdown = self.mixdown();
+        mixdown.validate()?;
+        let sample_rate = self.sample_rate();
+        let buffer_len = (total_duration * sample_rate as f64) as usize;
+        let mixer = &self.daw_file.mixer;
+        let mut tracks = self.processed_tracks(buffer_len, seconds_per_32nd_note, |id| mixer.is_audible(id), true)?;
+        // Instruments without notes still get a (silent) stem, so the set is complete
+        for instrument_id in self.daw_file.instruments.keys() {
+            tracks.entry(instrument_id).or_insert_with(|| Stereo::silent(buffer_len));
+        }
+        let mut chains = self.bus_chains(self.oversampling());
+        let inputs = self.master_inputs(&tracks, buffer_len, seconds_per_32nd_note, 0, &mut chains)?;
+        let mix = self.master_mix(&inputs, buffer_len, &mut chains);
+        let scale = self.output_gain(&mix);
+        let kept = self.kept_range(&mix, scale);
+
+        std::fs::create_dir_all(output_dir)?;
+        let mut paths = Vec::new();
+        for (kind, name, speakers, stem) in self.routed_inputs(&inputs) {
+            self.check_cancelled()?;
+            let path = output_dir.join(format!("{}-{}.{}", kind, file_safe(name), mixdown.format.extension()));
+            // Each stem plays from its own speakers, as it does in the mix
+            let mut stem = speakers::speaker_feeds(&BTreeMap::from([(speakers, stem.clone())]), mixdown.channels, buffer_len);
+            self.finish(&mut stem, kept.clone());
+            encode::write_channels(&path, &stem, mixdown, sample_rate, scale, self.seed())?;
+            paths.push(path);
+        }
+        if let Some(click) = &mixdown.click {
+            let path = output_dir.join(format!("click.{}", mixdown.format.extension()));
+            let click = Stereo::from_mono(self.click_track(click, 0..buffer_len, seconds_per_32nd_note));
+            let mut stem = speakers::from_stereo(&click, mixdown.channels);
+            self.finish(&mut stem, kept);
+            encode::write_channels(&path, &stem, mixdown, sample_rate, 1.0, self.seed())?;
+            paths.push(path);
+        }
+        self.report(100.0, self.end_bar(buffer_len, seconds_per_32nd_note));
+        Ok(paths)
+    }
+
+    /// Render the instruments accepted by `include` with their insert effects applied.
+    /// With `use_frozen`, frozen instruments play their frozen audio instead; if it can't
+    /// be read they're rendered from their events.
+    fn processed_tracks(
+        &self,
+        buffer_len: usize,
+        seconds_per_32nd_note: f64,
+        include: impl Fn(&str) -> bool,
+        use_frozen: bool,
+    ) -> Result<BTreeMap<&str, Stereo>> {
+        let frozen = self.frozen_tracks(|id| use_frozen && include(id), buffer_len);
+        self.check_samples(|id| include(id) && !frozen.contains_key(id))?;
+        let mut tracks = self.render_tracks_where(buffer_len, seconds_per_32nd_note, |id| {
+            include(id) && !frozen.contains_key(id)
+        });
+        self.check_cancelled()?;
+
+        let end_bar = self.end_bar(buffer_len, seconds_per_32nd_note);
+        let track_count = tracks.len();
+        let processed = AtomicUsize::new(0);
+        self.in_pool(|| {
+            tracks.par_iter_mut().for_each(|(instrument_id, track)| {
+                if self.cancel.is_cancelled() {
+                    return;
+                }
+                if let Some(instrument) = self.daw_file.get_instrument(instrument_id) {
+                    effects::apply_chain(&instrument.effects, track, self.sample_rate(), self.daw_file.bpm as f64, self.oversampling());
                 }
+                let processed = processed.fetch_add(1, Ordering::Relaxed) + 1;
+                self.report(NOTES_PERCENT + EFFECTS_PERCENT * processed as f64 / track_count as f64, end_bar);
+            })
+        });
+        self.check_cancelled()?;
+        tracks.extend(frozen);
+        Ok(tracks)
+    }
+
+    /// The frozen audio of the frozen instruments accepted by `include`, `buffer_len` frames
+    /// long. Instruments whose audio can't be read are left out, to be rendered from their
+    /// events.
+    fn frozen_tracks(&self, include: impl Fn(&str) -> bool, buffer_len: usize) -> BTreeMap<&str, Stereo> {
+        self.daw_file.instruments.iter()
+            .filter(|(id, _)| include(id))
+            .filter_map(|(id, instrument)| {
+                let freeze = instrument.frozen.as_ref()?;
+                let (left, right) = Sample::load_stereo(&self.project_dir.join(&freeze.audio_file)).ok()?;
+                let mut track = Stereo::silent(buffer_len);
+                let start = ((freeze.start * self.sample_rate() as f64).round() as usize).min(buffer_len);
+                left.resampled(self.sample_rate()).play(1.0, self.sample_rate(), &mut track.left[start..], None);
+                right.resampled(self.sample_rate()).play(1.0, self.sample_rate(), &mut track.right[start..], None);
+                Some((id.as_str(), track))
+            })
+            .collect()
+    }
+
+    /// Render the dry signal of every instrument, keyed by instrument id
+    #[cfg(test)]
+    fn render_tracks(&self, buffer_len: usize, seconds_per_32nd_note: f64) -> BTreeMap<&str, Stereo> {
+        self.render_tracks_where(buffer_len, seconds_per_32nd_note, |_| true)
+    }
+
+    /// Render the dry signal of the instruments accepted by `include`
+    fn render_tracks_where(
+        &self,
+        buffer_len: usize,
+        seconds_per_32nd_note: f64,
+        include: impl Fn(&str) -> bool,
+    ) -> BTreeMap<&str, Stereo> {
+        let voice_limits = self.voice_limited_ends(seconds_per_32nd_note);
+        let mut instrument_ids: Vec<&str> = self.events.notes().map(|(_, id, _)| id).filter(|id| include(id)).collect();
+        instrument_ids.sort_unstable();
+        instrument_ids.dedup();
+
+        let note_count = self.events.notes().filter(|(_, id, _)| include(id)).count();
+        let started = AtomicUsize::new(0);
+        let furthest_bar = AtomicU32::new(1);
+        let on_note = |position: u32| {
+            let started = started.fetch_add(1, Ordering::Relaxed) + 1;
+            let bar = position / 32 + 1;
+            let bar = furthest_bar.fetch_max(bar, Ordering::Relaxed).max(bar);
+            self.report(NOTES_PERCENT * started as f64 / note_count as f64, bar);
+        };
+
+        // Tracks only read the song, so they render in parallel. Each track still mixes its
+        // own notes in order, so the output is the same on any number of threads.
+        self.in_pool(|| {
+            instrument_ids.par_iter()
+                .map(|&id| (id, self.render_track(id, buffer_len, seconds_per_32nd_note, &voice_limits, &on_note)))
+                .collect()
+        })
+    }
+
+    /// Render the dry signal of one instrument's notes, calling `on_note` with the position of
+    /// each note as it starts. Stops early if the render is cancelled.
+    fn render_track(
+        &self,
+        instrument_id: &str,
+        buffer_len: usize,
+        seconds_per_32nd_note: f64,
+        voice_limits: &HashMap<NoteKey, usize>,
+        on_note: &(dyn Fn(u32) + Sync),
+    ) -> Stereo {
+        let mut buffer = Stereo::silent(buffer_len);
+        let mut renderer = self.note_renderer();
+        let bend = self.daw_file.control_curve(instrument_id, Controller::PitchBend);
+
+        for (key, ((position, _, note), doubling)) in with_doublings(self.events.notes()).enumerate()
+            .filter(|(_, ((_, id, _), _))| *id == instrument_id)
+        {
+            if self.cancel.is_cancelled() {
+                break;
+            }
+            on_note(position);
+            let end = voice_limits.get(&key).map_or(buffer.len(), |&limit| limit.min(buffer.len()));
+            let song_note = SongNote { position, instrument_id, note, doubling };
+            let (start, output) = self.render_note(&mut renderer, song_note, end, &bend, seconds_per_32nd_note);
+            buffer.mix_panned(start, &output, note.pan.unwrap_or(0.0));
+        }
+
+        buffer
+    }
+
+    fn note_renderer(&self) -> NoteRenderer {
+        NoteRenderer::new(self.sample_rate(), self.project_dir.clone()).with_oversampling(self.oversampling())
+    }
+
+    /// Render one note on its own, before it's placed at its pan, cut off by frame `end`.
+    /// Returns the frame it starts at along with its sound.
+    fn render_note(
+        &self,
+        renderer: &mut NoteRenderer,
+        song_note: SongNote,
+        end: usize,
+        bend: &ControlCurve,
+        seconds_per_32nd_note: f64,
+    ) -> (usize, Vec<f64>) {
+        let SongNote { position, instrument_id, note, doubling } = song_note;
+        let sample_rate = self.sample_rate() as f64;
+        let time_in_seconds = self.seconds_at(instrument_id, position, seconds_per_32nd_note);
+        let sample_index = (time_in_seconds * sample_rate) as usize;
+        let end_in_seconds = self.seconds_at(instrument_id, position + note.duration, seconds_per_32nd_note);
+        let note_off = (end_in_seconds * sample_rate) as usize;
+
+        let played = PlayedNote {
+            pitch: note.pitch,
+            velocity: note.velocity,
+            gate: note_off.saturating_sub(sample_index),
+            seed: note_seed(self.seed(), instrument_id, position as u64, note.pitch, doubling),
+        };
+        let output = renderer.render(&self.daw_file, instrument_id, &played, end.saturating_sub(sample_index), |i| {
+            if bend.is_empty() {
+                1.0
+            } else {
+                bend_ratio(bend.value_at((sample_index + i) as f64 / sample_rate / seconds_per_32nd_note))
+            }
+        });
+        (sample_index, output)
+    }
+
+    /// Report that the render has got to `percent` and `bar`, unless it's already been
+    /// reported further. Reaching 100% ends the render, so the next starts again from 0.
+    fn report(&self, percent: f64, bar: u32) {
+        let Some(progress) = &self.progress else {
+            return;
+        };
+        // Held while the callback runs, so reports from different threads arrive in order
+        let mut reported = self.reported.lock().unwrap();
+        if reported.is_some_and(|last| percent < last.percent) {
+            return;
+        }
+        let report = RenderProgress { percent, bar: reported.map_or(bar, |last| bar.max(last.bar)) };
+        *reported = (percent < 100.0).then_some(report);
+        progress(report);
+    }
+
+    fn check_cancelled(&self) -> Result<()> {
+        if self.cancel.is_cancelled() {
+            return Err(anyhow!("Render cancelled"));
+        }
+        Ok(())
+    }
+
+    /// Bar a buffer of `buffer_len` samples ends in
+    fn end_bar(&self, buffer_len: usize, seconds_per_32nd_note: f64) -> u32 {
+        let thirty_seconds = buffer_len as f64 / self.sample_rate() as f64 / seconds_per_32nd_note;
+        (thirty_seconds / 32.0).ceil().max(1.0) as u32
+    }
+
+    /// Run `work` on the engine's thread pool: `with_threads` if set, otherwise rayon's
+    /// global pool
+    fn in_pool<T: Send>(&self, work: impl FnOnce() -> T + Send) -> T {
+        match &self.pool {
+            Some(pool) => pool.install(work),
+            None => work(),
+        }
+    }
+
+    /// For instruments with a voice limit, the sample index where each note is cut off
+    /// by voice stealing
+    fn voice_limited_ends(&self, seconds_per_32nd_note: f64) -> HashMap<NoteKey, usize> {
+        let sample_rate = self.sample_rate() as f64;
+        let mut requests: BTreeMap<&str, Vec<(NoteKey, VoiceRequest)>> = BTreeMap::new();
+        for (note_index, (position, instrument_id, note)) in self.events.notes().enumerate() {
+            let limited = self.daw_file.get_instrument(instrument_id)
+                .is_some_and(|i| i.polyphony.max_voices.is_some());
+            if !limited {
+                continue;
             }
+            let start = (self.seconds_at(instrument_id, position, seconds_per_32nd_note) * sample_rate) as u64;
+            let end = (self.seconds_at(instrument_id, position + note.duration, seconds_per_32nd_note) * sample_rate) as u64;
+            requests.entry(instrument_id).or_default().push((
+                note_index,
+                VoiceRequest { start, end, velocity: note.velocity },
+            ));
         }
 
-        // Normalize and write to WAV file
-        let max_sample = buffer.iter().fold(0.0_f64, |a, &b| a.max(b.abs()));
-        for sample in buffer {
-            let normalized = (sample / max_sample * i16::MAX as f64) as i16;
-            writer.write_sample(normalized)?;
-            writer.write_sample(normalized)?; // Stereo
+        let mut limits = HashMap::new();
+        for (instrument_id, notes) in requests {
+            let polyphony = &self.daw_file.instruments[instrument_id].polyphony;
+            let voices: Vec<VoiceRequest> = notes.iter().map(|(_, request)| *request).collect();
+            for ((key, _), end) in notes.iter().zip(polyphony.allocate(&voices)) {
+                limits.insert(*key, end as usize);
+            }
         }
+        limits
+    }
 
-        writer.finalize()?;
+    /// Check that every sample the instruments accepted by `include` play is registered and
+    /// on disk, so a missing one fails the render rather than playing silence
+    fn check_samples(&self, include: impl Fn(&str) -> bool) -> Result<()> {
+        for (id, instrument) in &self.daw_file.instruments {
+            if !include(id) {
+                continue;
+            }
+            for sample_file in instrument.sample_files() {
+                self.daw_file.samples.resolve(&self.project_dir, sample_file)
+                    .with_context(|| format!("Instrument '{}' can't be played", id))?;
+            }
+        }
         Ok(())
     }
 
-    /// Calculate the total duration of the song in seconds
+    /// Route processed tracks through the mixer graph and return the master bus output in
+    /// the mixdown's channels
+    fn mix_tracks(&self, tracks: &BTreeMap<&str, Stereo>, buffer_len: usize, seconds_per_32nd_note: f64) -> Result<Channels> {
+        let mut chains = self.bus_chains(self.oversampling());
+        let inputs = self.master_inputs(tracks, buffer_len, seconds_per_32nd_note, 0, &mut chains)?;
+        Ok(self.master_mix(&inputs, buffer_len, &mut chains))
+    }
+
+    fn bus_chains(&self, oversampling: usize) -> BusChains {
+        BusChains::new(self.sample_rate(), self.daw_file.bpm as f64, oversampling)
+    }
+
+    /// Route processed tracks through the buses, returning every signal that reaches the
+    /// master bus after its inserts, pan and fader. The tracks start `offset` frames into
+    /// the song.
+    fn master_inputs<'a>(
+        &'a self,
+        tracks: &BTreeMap<&'a str, Stereo>,
+        buffer_len: usize,
+        seconds_per_32nd_note: f64,
+        offset: usize,
+        chains: &mut BusChains,
+    ) -> Result<MasterInputs<'a>> {
+        let mixer = &self.daw_file.mixer;
+        mixer.validate()?;
+
+        let mut inputs = MasterInputs::default();
+        let mut buses: BTreeMap<&str, Stereo> = mixer.buses.keys()
+            .map(|name| (name.as_str(), Stereo::silent(buffer_len)))
+            .collect();
+
+        for (&track_id, track) in tracks {
+            let routing = mixer.track(track_id);
+            let track = self.pan_track(track, track_id, routing.pan, offset, seconds_per_32nd_note);
+            let output = match routing.output.as_deref() {
+                Some(bus) => buses.get_mut(bus).unwrap(),
+                None => inputs.tracks.entry(track_id).or_insert_with(|| Stereo::silent(buffer_len)),
+            };
+            output.mix_in(&track, db_to_gain(routing.gain_db));
+
+            // Sends tap the track after its inserts, pan and fader
+            for (bus, level_db) in &routing.sends {
+                buses.get_mut(bus.as_str()).unwrap().mix_in(&track, db_to_gain(routing.gain_db + level_db));
+            }
+        }
+
+        for name in mixer.bus_order()? {
+            let bus = &mixer.buses[name];
+            let mut signal = buses.insert(name, Stereo::default()).unwrap();
+            chains.bus(name, &bus.effects).process(&mut signal);
+            let output = match bus.output.as_deref() {
+                Some(target) => buses.get_mut(target).unwrap(),
+                None => inputs.buses.entry(name).or_insert_with(|| Stereo::silent(buffer_len)),
+            };
+            output.mix_in(&signal, db_to_gain(bus.gain_db));
+        }
+        Ok(inputs)
+    }
+
+    /// Every master bus input with its kind (`track` or `bus`), name and the speakers it
+    /// plays from
+    fn routed_inputs<'b>(&self, inputs: &'b MasterInputs) -> Vec<(&'static str, &'b str, Speakers, &'b Stereo)> {
+        let mixer = &self.daw_file.mixer;
+        let tracks = inputs.tracks.iter().map(|(&id, stem)| ("track", id, mixer.track(id).speakers, stem));
+        let buses = inputs.buses.iter().map(|(&name, stem)| ("bus", name, mixer.buses[name].speakers, stem));
+        tracks.chain(buses).collect()
+    }
+
+    /// Sum the master bus inputs by the speakers they play from, lowered by the mixer's
+    /// headroom, run each group through the master bus, and spread the groups over the
+    /// mixdown's channels
+    fn master_mix(&self, inputs: &MasterInputs, buffer_len: usize, chains: &mut BusChains) -> Channels {
+        let master_bus = &self.daw_file.mixer.master;
+        let headroom = db_to_gain(-self.daw_file.mixer.headroom_db);
+        let mut groups = BTreeMap::from([(Speakers::Front, Stereo::silent(buffer_len))]);
+        for (_, _, speakers, input) in self.routed_inputs(inputs) {
+            groups.entry(speakers).or_insert_with(|| Stereo::silent(buffer_len)).mix_in(input, headroom);
+        }
+        let master_gain = db_to_gain(master_bus.gain_db);
+        for (&speakers, group) in groups.iter_mut() {
+            chains.master(speakers, &master_bus.effects).process(group);
+            for channel in group.channels_mut() {
+                channel.iter_mut().for_each(|s| *s *= master_gain);
+            }
+        }
+        speakers::speaker_feeds(&groups, self.mixdown().channels, buffer_len)
+    }
+
+    /// Gain applied to the mix when writing it, following the mixdown's normalization as
+    /// measured over its channels. Silent mixes are left alone.
+    fn output_gain(&self, feeds: &[Vec<f64>]) -> f64 {
+        let mixdown = self.mixdown();
+        match mixdown.normalize {
+            Normalize::Off => 1.0,
+            Normalize::Peak => {
+                let peak = speakers::peak(feeds);
+                if peak > 0.0 { db_to_gain(-self.daw_file.mixer.headroom_db) / peak } else { 1.0 }
+            }
+            Normalize::Loudness { target_lufs, true_peak_db } => {
+                let weights = speakers::loudness_weights(mixdown.channels);
+                let Some(lufs) = loudness::integrated_loudness_weighted(feeds, weights, self.sample_rate()) else {
+                    return 1.0;
+                };
+                let true_peak = loudness::true_peak_channels(feeds);
+                db_to_gain(target_lufs - lufs).min(db_to_gain(true_peak_db) / true_peak)
+            }
+        }
+    }
+
+    /// Place a track starting `offset` frames into the song at its mixer pan plus any pan
+    /// automation
+    fn pan_track<'a>(&self, track: &'a Stereo, track_id: &str, pan: f64, offset: usize, seconds_per_32nd_note: f64) -> Cow<'a, Stereo> {
+        let automation = self.daw_file.control_curve(track_id, Controller::Pan);
+        if pan == 0.0 && automation.is_empty() {
+            return Cow::Borrowed(track);
+        }
+        let sample_rate = self.sample_rate() as f64;
+        let mut panned = track.clone();
+        for (i, (left, right)) in panned.left.iter_mut().zip(panned.right.iter_mut()).enumerate() {
+            let position = (offset + i) as f64 / sample_rate / seconds_per_32nd_note;
+            let (left_gain, right_gain) = balance_gains((pan + automation.value_at(position)).clamp(-1.0, 1.0));
+            *left *= left_gain;
+            *right *= right_gain;
+        }
+        Cow::Owned(panned)
+    }
+
+    /// Frames of a mix the mixdown's silence trimming keeps once the mix is scaled by `gain`.
+    /// A mix that never reaches the threshold is kept whole.
+    fn kept_range(&self, mix: &[Vec<f64>], gain: f64) -> Range<usize> {
+        self.mixdown().trim_silence_db
+            .and_then(|threshold_db| speakers::audible_range(mix, db_to_gain(threshold_db) / gain))
+            .unwrap_or(0..mix.first().map_or(0, Vec::len))
+    }
+
+    /// Cut a rendered signal down to the `kept` frames and apply the mixdown's fades
+    fn finish(&self, feeds: &mut [Vec<f64>], kept: Range<usize>) {
+        let mixdown = self.mixdown();
+        let frames = |seconds: Option<f64>| (seconds.unwrap_or(0.0) * self.sample_rate() as f64) as usize;
+        speakers::trim(feeds, kept);
+        speakers::fade(feeds, frames(mixdown.fade_in), frames(mixdown.fade_out));
+    }
+
+    /// A metronome click on every quarter note of the song's `frames`, in straight time:
+    /// short decaying sine blips, higher and louder on the first beat of each bar. Bars are
+    /// always 32 32nds, so the click counts in 4/4.
+    fn click_track(&self, click: &Click, frames: Range<usize>, seconds_per_32nd_note: f64) -> Vec<f64> {
+        let sample_rate = self.sample_rate() as f64;
+        let mut track = vec![0.0; frames.len()];
+        let length = (CLICK_SECONDS * sample_rate) as usize;
+        for beat in 0_u32.. {
+            let position = beat * 8;
+            let start = (position as f64 * seconds_per_32nd_note * sample_rate) as usize;
+            if start >= frames.end {
+                break;
+            }
+            let (frequency, level_db) = match position % 32 {
+                0 => (1760.0, click.level_db),
+                _ => (1320.0, click.level_db - 6.0),
+            };
+            let gain = db_to_gain(level_db);
+            for frame in start.max(frames.start)..(start + length).min(frames.end) {
+                let time = (frame - start) as f64 / sample_rate;
+                track[frame - frames.start] += gain * (std::f64::consts::TAU * frequency * time).sin() * (-time / CLICK_DECAY_SECONDS).exp();
+            }
+        }
+        track
+    }
+
+    /// Calculate the total duration of the song in seconds, long enough for every note and
+    /// all of any frozen audio, which recorded tracks have instead of notes
     fn calculate_total_duration(&self, seconds_per_32nd_note: f64) -> f64 {
-        let mut max_time = 0.0_f64;
-        for event in &self.daw_file.events {
-            let time = self.parse_time(&event.time, seconds_per_32nd_note);
-            for note in &event.notes {
-                let duration = note.duration as f64 * seconds_per_32nd_note;
-                max_time = max_time.max(time + duration);
+        let fixed_tail = self.mixdown().tail;
+        let mut effect_tails = HashMap::new();
+        let frozen_ends = self.daw_file.instruments.values()
+            .filter_map(|instrument| {
+                let freeze = instrument.frozen.as_ref()?;
+                Some(freeze.start + wav_seconds(&self.project_dir.join(&freeze.audio_file)).ok()?)
+            });
+        self.events.notes()
+            .map(|(position, instrument_id, note)| {
+                let start = self.seconds_at(instrument_id, position, seconds_per_32nd_note);
+                let end = self.seconds_at(instrument_id, position + note.duration, seconds_per_32nd_note);
+                if let Some(tail) = fixed_tail {
+                    return end + tail;
+                }
+                // SFZ releases are read from the file, so once per instrument alongside its effects
+                let effect_tail = *effect_tails.entry(instrument_id)
+                    .or_insert_with(|| self.effect_tail_seconds(instrument_id) + self.sfz_release_seconds(instrument_id));
+                self.sounding_until(instrument_id, start, end) + effect_tail
+            })
+            .chain(frozen_ends)
+            .fold(0.0, f64::max)
+    }
+
+    /// When a note from `start` to `end` seconds falls silent, including its release
+    fn sounding_until(&self, instrument_id: &str, start: f64, end: f64) -> f64 {
+        match self.daw_file.get_instrument(instrument_id).map(|i| &i.kind) {
+            Some(InstrumentKind::Synth(params)) => end + Envelope::from_params(params).release,
+            Some(InstrumentKind::Sampler(params)) => end + Envelope::for_sampler(params).release,
+            Some(InstrumentKind::Percussion(params)) => {
+                let envelope = Envelope::for_percussion(params);
+                start + envelope.attack + envelope.decay
             }
+            Some(InstrumentKind::Sfz(_)) | None => end,
+        }
+    }
+
+    /// Longest release in an SFZ instrument's file; 0 for other instruments
+    fn sfz_release_seconds(&self, instrument_id: &str) -> f64 {
+        match self.daw_file.get_instrument(instrument_id).map(|i| &i.kind) {
+            Some(InstrumentKind::Sfz(params)) => self.daw_file.samples.resolve(&self.project_dir, &params.sfz_file)
+                .and_then(|path| dawww_core::Sfz::load(&path))
+                .map_or(0.0, |sfz| SfzPlayer::release_seconds(&sfz)),
+            _ => 0.0,
+        }
+    }
+
+    /// Seconds a track's effects ring on after it falls silent: its insert chain, then the
+    /// longest path through the buses it feeds to the master
+    fn effect_tail_seconds(&self, instrument_id: &str) -> f64 {
+        let bpm = self.daw_file.bpm as f64;
+        let mixer = &self.daw_file.mixer;
+        let master_tail = effects::chain_tail_seconds(&mixer.master.effects, bpm);
+        // Outputs come after their inputs in processing order, so walk it backwards
+        let mut bus_tails: HashMap<&str, f64> = HashMap::new();
+        for name in mixer.bus_order().unwrap_or_default().into_iter().rev() {
+            let bus = &mixer.buses[name];
+            let downstream = bus.output.as_deref().and_then(|output| bus_tails.get(output)).copied().unwrap_or(master_tail);
+            bus_tails.insert(name, effects::chain_tail_seconds(&bus.effects, bpm) + downstream);
         }
-        max_time
+
+        let routing = mixer.track(instrument_id);
+        let routed_tail = routing.output.iter()
+            .chain(routing.sends.keys())
+            .map(|bus| bus_tails.get(bus.as_str()).copied().unwrap_or(master_tail))
+            .fold(master_tail, f64::max);
+        let inserts = self.daw_file.get_instrument(instrument_id)
+            .map_or(0.0, |instrument| effects::chain_tail_seconds(&instrument.effects, bpm));
+        inserts + routed_tail
+    }
+
+    /// A position in 32nds as seconds, played with the instrument's groove
+    fn seconds_at(&self, instrument_id: &str, position: u32, seconds_per_32nd_note: f64) -> f64 {
+        self.daw_file.groove_for(instrument_id).apply(position) * seconds_per_32nd_note
     }
+}
+
+/// A name with anything that isn't safe in a file name replaced by `_`
+fn file_safe(name: &str) -> String {
+    name.chars().map(|c| if c.is_alphanumeric() || matches!(c, '-' | '_' | '.' | ' ') { c } else { '_' }).collect()
+}
+
+pub(crate) fn db_to_gain(db: f64) -> f64 {
+    10.0_f64.powf(db / 20.0)
+}
 
-    /// Parse a time string in the format "bar.32nd" into seconds
-    fn parse_time(&self, time: &str, seconds_per_32nd_note: f64) -> f64 {
-        let parts: Vec<&str> = time.split('.').collect();
-        let bar = parts[0].parse::<f64>().unwrap();
-        let thirty_second = parts[1].parse::<f64>().unwrap();
-        ((bar - 1.0) * 32.0 + thirty_second) * seconds_per_32nd_note
+fn mix_into(output: &mut [f64], input: &[f64], gain: f64) {
+    for (out, sample) in output.iter_mut().zip(input) {
+        *out += sample * gain;
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use dawww_core::{Note, pitch::{Pitch, Tone}, Event};
-    use std::path::PathBuf;
+    use dawww_core::{ChannelLayout, Note, pitch::{Pitch, Tone}, Event, Groove};
+    use tempfile::TempDir;
+    use dawww_core::instrument::SubtractiveParams;
+
+    /// Level of a centred note in each channel: 3 dB down under the constant-power pan law
+    const CENTRE: f64 = std::f64::consts::FRAC_1_SQRT_2;
+
+    /// Synth settings that play an unfiltered sine at full level for exactly the note's length
+    fn plain_sine() -> SubtractiveParams {
+        SubtractiveParams {
+            envelope_attack: 0.0,
+            envelope_decay: 0.0,
+            envelope_sustain: 1.0,
+            envelope_release: 0.0,
+            filter_cutoff: 100_000.0,
+            ..SubtractiveParams::default()
+        }
+    }
 
     #[test]
-    fn test_parse_time() {
-        let daw_file = DawFile::new("Test".to_string());
-        let engine = AudioEngine::new(daw_file);
+    fn test_seconds_at() {
+        let mut daw_file = DawFile::new("Test".to_string());
         let seconds_per_32nd = 60.0 / (120.0 * 8.0); // At 120 BPM
+        let engine = AudioEngine::new(daw_file.clone());
+        assert_eq!(engine.seconds_at("any", 0, seconds_per_32nd), 0.0);
+        assert_eq!(engine.seconds_at("any", 16, seconds_per_32nd), 16.0 * seconds_per_32nd);
+        assert_eq!(engine.seconds_at("any", 32, seconds_per_32nd), 32.0 * seconds_per_32nd);
 
-        assert_eq!(engine.parse_time("1.0", seconds_per_32nd), 0.0);
-        assert_eq!(engine.parse_time("1.16", seconds_per_32nd), 16.0 * seconds_per_32nd);
-        assert_eq!(engine.parse_time("2.0", seconds_per_32nd), 32.0 * seconds_per_32nd);
+        daw_file.set_groove(Groove::new(dawww_core::Grid::Eighth).with_swing(75.0)).unwrap();
+        let engine = AudioEngine::new(daw_file);
+        assert_eq!(engine.seconds_at("any", 4, seconds_per_32nd), 6.0 * seconds_per_32nd);
     }
 
     #[test]
@@ -112,12 +1041,1114 @@ mod tests {
             time: "1.0".to_string(),
             instrument: "test".to_string(),
             notes: vec![note],
+            extra: Default::default(),
         };
-        daw_file.events.push(event);
+        daw_file.events.insert(event).unwrap();
 
         let engine = AudioEngine::new(daw_file);
         let seconds_per_32nd = 60.0 / (120.0 * 8.0);
         
         assert_eq!(engine.calculate_total_duration(seconds_per_32nd), 8.0 * seconds_per_32nd);
     }
+
+    #[test]
+    fn test_duration_includes_releases_and_effect_tails() {
+        use dawww_core::effects::{DelayParams, Effect, EffectInstance};
+        use dawww_core::instrument::Instrument;
+        use dawww_core::mixer::Bus;
+
+        let seconds_per_32nd = 60.0 / (120.0 * 8.0);
+        let mut daw_file = DawFile::new("Test".to_string());
+        let pad = SubtractiveParams { envelope_release: 1.5, ..SubtractiveParams::default() };
+        daw_file.add_instrument("pad".to_string(), Instrument::new_subtractive(pad)).unwrap();
+        daw_file.add_note("1.0", "pad", Note::new(Pitch::new(Tone::C, 4), 32)).unwrap();
+        let duration = |daw_file: &DawFile| AudioEngine::new(daw_file.clone()).calculate_total_duration(seconds_per_32nd);
+        assert!((duration(&daw_file) - 3.5).abs() < 1e-9);
+
+        // A delay on a bus the pad sends to rings on after the release
+        daw_file.mixer.add_bus("echo", Bus {
+            effects: vec![EffectInstance::new(Effect::Delay(DelayParams::new(0.25, 0.0, 1.0)))],
+            ..Bus::default()
+        }).unwrap();
+        daw_file.mixer.set_send("pad", "echo", -6.0).unwrap();
+        assert!((duration(&daw_file) - 3.75).abs() < 1e-9);
+
+        // A fixed tail replaces the calculated one
+        daw_file.mixdown = daw_file.mixdown.clone().with_tail(0.5);
+        assert!((duration(&daw_file) - 2.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_duration_covers_frozen_audio() {
+        let temp_dir = TempDir::new().unwrap();
+        let take = Stereo { left: vec![0.5; 3000], right: vec![0.5; 3000] };
+        wav::write_wav(&temp_dir.path().join("take.wav"), &take, &MixdownSettings::float(1000), 1000, 1.0, 0).unwrap();
+        let mut daw_file = DawFile::new("Test".to_string());
+        daw_file.add_audio_clip("take", PathBuf::from("take.wav"), 1.5).unwrap();
+
+        // A recorded track has no notes, so its audio alone sets how long the song is
+        let engine = AudioEngine::new(daw_file).with_project_dir(temp_dir.path().to_path_buf());
+        assert!((engine.calculate_total_duration(60.0 / (120.0 * 8.0)) - 4.5).abs() < 1e-9);
+
+        // and it plays from where it was placed
+        let rate = engine.sample_rate() as usize;
+        let tracks = engine.frozen_tracks(|_| true, rate * 5);
+        let start = rate * 3 / 2;
+        assert!(tracks["take"].left[..start].iter().all(|sample| *sample == 0.0));
+        assert!((tracks["take"].left[start + rate] - 0.5).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_resolve_sample_uses_registry() {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::write(temp_dir.path().join("kick.wav"), b"kick").unwrap();
+
+        let mut daw_file = DawFile::new("Test".to_string());
+        daw_file.create_sampler_instrument("kick".to_string(), PathBuf::from("kick.wav")).unwrap();
+        daw_file.create_sampler_instrument("snare".to_string(), PathBuf::from("snare.wav")).unwrap();
+        daw_file.samples.register(temp_dir.path(), "kick.wav").unwrap();
+
+        let engine = AudioEngine::new(daw_file).with_project_dir(temp_dir.path().to_path_buf());
+        assert_eq!(engine.resolve_sample("kick").unwrap(), temp_dir.path().join("kick.wav"));
+        assert!(engine.resolve_sample("snare").is_err());
+        assert!(engine.resolve_sample("missing").is_err());
+
+        // Rendering a sample that isn't registered fails instead of playing silence
+        let error = engine.render(&temp_dir.path().join("out.wav")).unwrap_err();
+        assert!(format!("{:#}", error).contains("'snare.wav' is not registered"), "{:#}", error);
+    }
+
+    #[test]
+    fn test_render_applies_instrument_effects() {
+        use dawww_core::effects::{Effect, EffectInstance, GainParams};
+        use dawww_core::instrument::Instrument;
+
+        let mut daw_file = DawFile::new("Test".to_string());
+        daw_file.add_instrument("lead".to_string(), Instrument::new_subtractive(SubtractiveParams::default())).unwrap();
+        daw_file.add_instrument(
+            "pad".to_string(),
+            Instrument::new_subtractive(SubtractiveParams::default())
+                .with_effect(EffectInstance::new(Effect::Gain(GainParams::new(-120.0)))),
+        ).unwrap();
+        for instrument in ["lead", "pad"] {
+            daw_file.events.insert(Event {
+                time: "1.0".to_string(),
+                instrument: instrument.to_string(),
+                notes: vec![Note::new(Pitch::new(Tone::A, 4), 8)],
+                extra: Default::default(),
+            }).unwrap();
+        }
+
+        let engine = AudioEngine::new(daw_file);
+        let seconds_per_32nd = 60.0 / (120.0 * 8.0);
+        let peak = |track: &Stereo| track.left.iter().chain(&track.right).fold(0.0_f64, |peak, s| peak.max(s.abs()));
+        let dry = engine.render_tracks(4410, seconds_per_32nd);
+        assert_eq!(dry.keys().copied().collect::<Vec<_>>(), vec!["lead", "pad"]);
+        assert!((peak(&dry["lead"]) - peak(&dry["pad"])).abs() < 1e-9);
+
+        // The pad's -120 dB insert silences it, and leaves the lead alone
+        let processed = engine.processed_tracks(4410, seconds_per_32nd, |_| true, false).unwrap();
+        assert!((peak(&processed["lead"]) - peak(&dry["lead"])).abs() < 1e-9);
+        assert!(peak(&processed["pad"]) < peak(&dry["pad"]) * 1e-5);
+
+        let temp_dir = TempDir::new().unwrap();
+        let output = temp_dir.path().join("out.wav");
+        engine.render(&output).unwrap();
+        assert!(output.exists());
+
+        // The extension picks the format
+        let output = temp_dir.path().join("out.aif");
+        engine.render(&output).unwrap();
+        assert_eq!(&std::fs::read(&output).unwrap()[8..12], b"AIFF");
+        assert_eq!(&std::fs::read(temp_dir.path().join("out.wav")).unwrap()[..4], b"RIFF");
+    }
+
+    #[test]
+    fn test_render_is_the_same_on_any_number_of_threads() {
+        use dawww_core::effects::{DelayParams, Effect, EffectInstance};
+        use dawww_core::instrument::{Instrument, PercussionParams};
+
+        let mut daw_file = DawFile::new("Test".to_string());
+        daw_file.add_instrument("hat".to_string(), Instrument::new_percussion(PercussionParams::default())).unwrap();
+        daw_file.add_instrument(
+            "lead".to_string(),
+            Instrument::new_subtractive(SubtractiveParams::default())
+                .with_effect(EffectInstance::new(Effect::Delay(DelayParams::new(0.1, 0.4, 0.5)))),
+        ).unwrap();
+        daw_file.add_instrument("bass".to_string(), Instrument::new_subtractive(plain_sine())).unwrap();
+        for (time, tone) in [("1.0", Tone::C), ("1.8", Tone::E), ("1.16", Tone::G)] {
+            for instrument in ["hat", "lead", "bass"] {
+                daw_file.add_note(time, instrument, Note::new(Pitch::new(tone, 3), 8)).unwrap();
+            }
+        }
+
+        let single = AudioEngine::new(daw_file.clone()).with_sample_rate(8000).with_threads(1).render_buffer().unwrap();
+        let engine = AudioEngine::new(daw_file).with_sample_rate(8000).with_threads(4);
+        let parallel = engine.render_buffer().unwrap();
+        assert!(single.peak() > 0.0);
+        assert_eq!(single, parallel);
+        // The pool is kept for later renders
+        assert_eq!(engine.render_buffer().unwrap(), parallel);
+    }
+
+    #[test]
+    fn test_render_analyzed() {
+        use dawww_core::instrument::Instrument;
+
+        let mut daw_file = DawFile::new("Test".to_string());
+        daw_file.add_instrument("lead".to_string(), Instrument::new_subtractive(plain_sine())).unwrap();
+        daw_file.add_note("1.0", "lead", Note::new(Pitch::new(Tone::A, 4), 32)).unwrap();
+        daw_file.mixdown = daw_file.mixdown.clone().with_normalize(Normalize::Peak);
+        daw_file.mixer.set_headroom(3.0).unwrap();
+
+        let temp_dir = TempDir::new().unwrap();
+        let output = temp_dir.path().join("out.wav");
+        let analysis = AudioEngine::new(daw_file).with_sample_rate(8000).render_analyzed(&output).unwrap();
+        assert!(output.exists());
+        assert!((analysis.sample_peak_db + 3.0).abs() < 1e-9);
+        assert_eq!(analysis.clipped_samples, 0);
+        assert!(analysis.integrated_lufs.is_some());
+        assert_eq!(analysis.track_peaks_db.keys().collect::<Vec<_>>(), vec!["lead"]);
+    }
+
+    #[test]
+    fn test_loudness_normalization() {
+        use dawww_core::instrument::Instrument;
+
+        let mut daw_file = DawFile::new("Test".to_string());
+        daw_file.add_instrument("lead".to_string(), Instrument::new_subtractive(plain_sine())).unwrap();
+        daw_file.add_note("1.0", "lead", Note::new(Pitch::new(Tone::A, 4), 64)).unwrap();
+
+        daw_file.mixdown = MixdownSettings::float(44100).with_normalize(Normalize::loudness(-20.0));
+        let mix = AudioEngine::new(daw_file.clone()).render_buffer().unwrap();
+        let lufs = loudness::integrated_loudness(&mix, 44100).unwrap();
+        assert!((lufs + 20.0).abs() < 0.05, "{}", lufs);
+
+        // A stereo sine measures about as loud as its peak, so the ceiling stops it short of -5 LUFS
+        daw_file.mixdown.normalize = Normalize::Loudness { target_lufs: -5.0, true_peak_db: -6.0 };
+        let mix = AudioEngine::new(daw_file.clone()).render_buffer().unwrap();
+        assert!((loudness::true_peak(&mix) - db_to_gain(-6.0)).abs() < 1e-9);
+        assert!(loudness::integrated_loudness(&mix, 44100).unwrap() < -5.5);
+
+        daw_file.mixdown.normalize = Normalize::Peak;
+        daw_file.mixer.set_headroom(6.0).unwrap();
+        let mix = AudioEngine::new(daw_file).render_buffer().unwrap();
+        assert!((mix.peak() - db_to_gain(-6.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_render_reports_progress_and_can_be_cancelled() {
+        use dawww_core::instrument::Instrument;
+        use std::sync::Mutex;
+
+        let mut daw_file = DawFile::new("Test".to_string());
+        for id in ["lead", "pad", "bass", "keys"] {
+            daw_file.add_instrument(id.to_string(), Instrument::new_subtractive(plain_sine())).unwrap();
+        }
+        for bar in 1..=3 {
+            daw_file.add_note(&format!("{}.0", bar), "lead", Note::new(Pitch::new(Tone::A, 4), 8)).unwrap();
+        }
+        for id in ["pad", "bass", "keys"] {
+            daw_file.add_note("1.0", id, Note::new(Pitch::new(Tone::A, 3), 8)).unwrap();
+        }
+
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let sink = reports.clone();
+        let engine = AudioEngine::new(daw_file.clone())
+            .with_sample_rate(8000)
+            .with_threads(4)
+            .with_progress(move |progress| sink.lock().unwrap().push(progress));
+        // Tracks render at once on several threads, but each render's reports only go forward
+        for _ in 0..2 {
+            engine.render_buffer().unwrap();
+            let reports = std::mem::take(&mut *reports.lock().unwrap());
+            assert!(reports.windows(2).all(|pair| pair[0].percent <= pair[1].percent && pair[0].bar <= pair[1].bar));
+            assert_eq!(reports.iter().filter(|p| p.percent <= NOTES_PERCENT).map(|p| p.bar).max(), Some(3));
+            assert!(reports[0].percent < NOTES_PERCENT);
+            assert_eq!(reports.last().unwrap().percent, 100.0);
+        }
+
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        let temp_dir = TempDir::new().unwrap();
+        let output = temp_dir.path().join("out.wav");
+        let engine = AudioEngine::new(daw_file).with_cancel_token(cancel);
+        assert!(engine.render(&output).is_err());
+        assert!(!output.exists());
+    }
+
+    #[test]
+    fn test_process_streams_the_mix_in_blocks() {
+        use dawww_core::effects::{DelayParams, Effect, EffectInstance, ReverbParams};
+        use dawww_core::instrument::Instrument;
+        use dawww_core::mixer::Bus;
+
+        let mut daw_file = DawFile::new("Test".to_string());
+        let echo = EffectInstance::new(Effect::Delay(DelayParams::new(0.1, 0.5, 0.5)));
+        daw_file.add_instrument("lead".to_string(), Instrument::new_subtractive(plain_sine()).with_effect(echo)).unwrap();
+        daw_file.add_instrument("pad".to_string(), Instrument::new_subtractive(SubtractiveParams::default())).unwrap();
+        for (time, tone) in [("1.0", Tone::A), ("1.12", Tone::C), ("2.4", Tone::E)] {
+            daw_file.add_note(time, "lead", Note::new(Pitch::new(tone, 4), 8).with_pan(-0.5)).unwrap();
+        }
+        daw_file.add_note("1.8", "pad", Note::new(Pitch::new(Tone::G, 3), 24)).unwrap();
+        daw_file.mixer.add_bus("room", Bus {
+            effects: vec![EffectInstance::new(Effect::Reverb(ReverbParams::default()))],
+            ..Bus::default()
+        }).unwrap();
+        daw_file.mixer.set_send("pad", "room", -6.0).unwrap();
+        daw_file.mixdown = daw_file.mixdown.clone()
+            .with_normalize(false)
+            .with_click(Click::new(ClickMode::Mix, -12.0))
+            .with_fades(0.05, 0.05);
+
+        // The stream plays what a render makes at the mixer's level
+        let mut engine = AudioEngine::new(daw_file).with_sample_rate(8000);
+        let mix = engine.render_buffer().unwrap();
+        assert!(!engine.is_finished());
+        engine.prepare().unwrap();
+
+        let mut streamed = Vec::new();
+        let mut block = [1.0_f32; 256];
+        while !engine.is_finished() {
+            engine.process(&mut block);
+            streamed.extend_from_slice(&block);
+        }
+        assert_eq!(engine.position(), mix.len());
+        assert_eq!(streamed.len(), mix.len().div_ceil(128) * 256);
+        for (i, frame) in streamed.chunks(2).enumerate() {
+            let expected = [mix.left.get(i), mix.right.get(i)].map(|s| s.map_or(0.0, |&s| s.clamp(-1.0, 1.0) as f32));
+            assert_eq!(frame, expected, "frame {}", i);
+        }
+
+        // Seeking starts the notes still sounding there, without the effects' tails
+        engine.seek(2100);
+        engine.process(&mut block);
+        assert!(block.iter().any(|s| s.abs() > 0.1));
+        engine.seek(0);
+        let mut replayed = Vec::new();
+        while !engine.is_finished() {
+            engine.process(&mut block);
+            replayed.extend_from_slice(&block);
+        }
+        assert_eq!(replayed, streamed);
+    }
+
+    #[test]
+    fn test_update_streams_the_edited_song() {
+        use dawww_core::instrument::Instrument;
+
+        let mut daw_file = DawFile::new("Test".to_string());
+        daw_file.add_instrument("lead".to_string(), Instrument::new_subtractive(plain_sine())).unwrap();
+        daw_file.add_note("1.0", "lead", Note::new(Pitch::new(Tone::A, 4), 8)).unwrap();
+        daw_file.add_note("2.0", "lead", Note::new(Pitch::new(Tone::C, 4), 8)).unwrap();
+        daw_file.mixdown = daw_file.mixdown.clone().with_normalize(false);
+        let mut engine = AudioEngine::new(daw_file.clone()).with_sample_rate(8000);
+        let mut block = [0.0_f32; 2000];
+        engine.process(&mut block);
+
+        // A bar is 16000 frames at 120 BPM; moving the second note changes the song from
+        // where it used to start
+        let mut edited = daw_file.clone();
+        edited.remove_note("2.0", "lead", &Note::new(Pitch::new(Tone::C, 4), 8)).unwrap();
+        edited.add_note("2.16", "lead", Note::new(Pitch::new(Tone::E, 4), 8)).unwrap();
+        assert_eq!(engine.update(edited.clone()).unwrap(), Some(16000));
+        assert_eq!(engine.position(), 1000);
+        assert_eq!(engine.update(edited.clone()).unwrap(), None);
+        edited.set_bpm(60);
+        assert_eq!(engine.update(edited.clone()).unwrap(), Some(0));
+
+        // What's streamed from then on is the edited song
+        let expected = AudioEngine::new(edited.clone()).with_sample_rate(8000).render_buffer().unwrap();
+        engine.seek(48000);
+        engine.process(&mut block);
+        assert_eq!(block[200], expected.left[48100].clamp(-1.0, 1.0) as f32);
+        assert!(block.iter().any(|s| s.abs() > 0.1));
+
+        // Songs that can't be played are refused, and the old one carries on
+        edited.add_instrument("drums".to_string(), Instrument::new_sampler(PathBuf::from("kick.wav"))).unwrap();
+        edited.add_note("1.0", "drums", Note::new(Pitch::new(Tone::C, 4), 8)).unwrap();
+        assert!(engine.update(edited).is_err());
+        assert!(engine.daw_file.get_instrument("drums").is_none());
+    }
+
+    #[test]
+    fn test_prepare_reports_what_cant_be_played() {
+        use dawww_core::instrument::Instrument;
+
+        let mut daw_file = DawFile::new("Test".to_string());
+        daw_file.add_instrument("drums".to_string(), Instrument::new_sampler(PathBuf::from("kick.wav"))).unwrap();
+        daw_file.add_note("1.0", "drums", Note::new(Pitch::new(Tone::C, 4), 8)).unwrap();
+        let mut engine = AudioEngine::new(daw_file);
+        assert!(engine.prepare().is_err());
+        let mut block = [1.0_f32; 8];
+        engine.process(&mut block);
+        assert_eq!(block, [0.0; 8]);
+        assert!(!engine.is_finished());
+    }
+
+    #[test]
+    fn test_render_at_draft_sample_rate() {
+        let mut daw_file = DawFile::new("Test".to_string());
+        daw_file.events.insert(Event {
+            time: "1.0".to_string(),
+            instrument: "test".to_string(),
+            notes: vec![Note::new(Pitch::new(Tone::A, 4), 32)],
+            extra: Default::default(),
+        }).unwrap();
+
+        let temp_dir = TempDir::new().unwrap();
+        let output = temp_dir.path().join("draft.wav");
+        let engine = AudioEngine::new(daw_file).with_sample_rate(22050);
+        assert_eq!(engine.sample_rate(), 22050);
+        engine.render(&output).unwrap();
+
+        let reader = hound::WavReader::open(&output).unwrap();
+        assert_eq!(reader.spec().sample_rate, 22050);
+        // One bar at 120 BPM is two seconds
+        assert_eq!(reader.duration(), 44100);
+    }
+
+    #[test]
+    fn test_render_with_mixdown_profile() {
+        use dawww_core::effects::{Effect, EffectInstance, GainParams};
+        use dawww_core::instrument::Instrument;
+
+        let mut daw_file = DawFile::new("Test".to_string());
+        daw_file.add_instrument(
+            "test".to_string(),
+            Instrument::new_subtractive(plain_sine())
+                .with_effect(EffectInstance::new(Effect::Gain(GainParams::new(-6.0206)))),
+        ).unwrap();
+        daw_file.events.insert(Event {
+            time: "1.0".to_string(),
+            instrument: "test".to_string(),
+            notes: vec![Note::new(Pitch::new(Tone::A, 4), 32)],
+            extra: Default::default(),
+        }).unwrap();
+        let profile = MixdownSettings::new(48000, 16).with_channels(ChannelLayout::Mono).with_normalize(false);
+        daw_file.set_mixdown_profile("preview", profile).unwrap();
+
+        assert!(AudioEngine::new(daw_file.clone()).with_profile("missing").is_err());
+        let engine = AudioEngine::new(daw_file).with_profile("preview").unwrap();
+        assert_eq!(engine.sample_rate(), 48000);
+
+        let temp_dir = TempDir::new().unwrap();
+        let output = temp_dir.path().join("preview.wav");
+        engine.render(&output).unwrap();
+
+        let mut reader = hound::WavReader::open(&output).unwrap();
+        assert_eq!(reader.spec().channels, 1);
+        assert_eq!(reader.spec().sample_rate, 48000);
+        assert_eq!(reader.duration(), 96000);
+        // Without normalization the sine keeps its own level
+        let peak = reader.samples::<i16>().map(|s| s.unwrap().unsigned_abs()).max().unwrap();
+        assert!((peak as f64 - i16::MAX as f64 / 2.0 * CENTRE).abs() < 100.0);
+    }
+
+    #[test]
+    fn test_surround_render_places_tracks_on_speakers() {
+        use dawww_core::effects::{Effect, EffectInstance, GainParams};
+        use dawww_core::instrument::Instrument;
+
+        let mut daw_file = DawFile::new("Test".to_string());
+        daw_file.mixdown = MixdownSettings::new(8000, 16)
+            .with_channels(ChannelLayout::Surround51)
+            .with_normalize(false);
+        for id in ["lead", "pad"] {
+            daw_file.add_instrument(
+                id.to_string(),
+                Instrument::new_subtractive(plain_sine())
+                    .with_effect(EffectInstance::new(Effect::Gain(GainParams::new(-6.0206)))),
+            ).unwrap();
+            daw_file.events.insert(Event {
+                time: "1.0".to_string(),
+                instrument: id.to_string(),
+                notes: vec![Note::new(Pitch::new(Tone::A, 4), 32)],
+                extra: Default::default(),
+            }).unwrap();
+        }
+        daw_file.mixer.set_track_speakers("pad", Speakers::Rear);
+
+        let temp_dir = TempDir::new().unwrap();
+        let output = temp_dir.path().join("surround.wav");
+        let engine = AudioEngine::new(daw_file);
+        engine.render(&output).unwrap();
+        let mut reader = hound::WavReader::open(&output).unwrap();
+        assert_eq!(reader.spec().channels, 6);
+        let samples: Vec<i16> = reader.samples::<i16>().map(|s| s.unwrap()).collect();
+        let channel_peak = |channel: usize| samples.iter().skip(channel).step_by(6).map(|s| s.unsigned_abs()).max().unwrap();
+        // Front and rear pairs play one track each; the center and LFE are silent
+        for channel in [0, 1, 4, 5] {
+            assert!((channel_peak(channel) as f64 - i16::MAX as f64 / 2.0 * CENTRE).abs() < 100.0);
+        }
+        assert_eq!((channel_peak(2), channel_peak(3)), (0, 0));
+
+        // The in-memory mix folds the rear pair into the front
+        let folded = engine.render_buffer().unwrap();
+        assert!((folded.peak() - 0.5 * (1.0 + std::f64::consts::FRAC_1_SQRT_2) * CENTRE).abs() < 0.01);
+
+        // Stems keep their speakers
+        let paths = engine.render_stems(&temp_dir.path().join("stems")).unwrap();
+        let pad = paths.iter().find(|path| path.ends_with("track-pad.wav")).unwrap();
+        let samples: Vec<i16> = hound::WavReader::open(pad).unwrap().samples::<i16>().map(|s| s.unwrap()).collect();
+        assert!(samples.chunks(6).all(|frame| frame[..4].iter().all(|&s| s == 0)));
+        assert!(samples.chunks(6).any(|frame| frame[4] != 0));
+    }
+
+    #[test]
+    fn test_mix_routes_tracks_through_buses() {
+        use dawww_core::effects::{Effect, EffectInstance, GainParams};
+        use dawww_core::mixer::Bus;
+
+        let mut daw_file = DawFile::new("Test".to_string());
+        daw_file.mixer.add_bus("drums", Bus {
+            effects: vec![EffectInstance::new(Effect::Gain(GainParams::new(-6.0206)))],
+            ..Bus::default()
+        }).unwrap();
+        daw_file.mixer.add_bus("fx", Bus { output: Some("drums".to_string()), ..Bus::default() }).unwrap();
+        daw_file.mixer.set_track_output("kick", Some("drums")).unwrap();
+        daw_file.mixer.set_send("lead", "fx", -6.0206).unwrap();
+
+        let engine = AudioEngine::new(daw_file);
+        let tracks = BTreeMap::from([("kick", Stereo::from_mono(vec![1.0; 4])), ("lead", Stereo::from_mono(vec![1.0; 4]))]);
+        let master = speakers::to_stereo(&engine.mix_tracks(&tracks, 4, 1.0).unwrap());
+
+        // lead direct (1.0) + lead -> fx -> drums (0.5 * 0.5) + kick -> drums (0.5)
+        assert!((master.left[0] - 1.75).abs() < 1e-4);
+        assert_eq!(master.left, master.right);
+    }
+
+    #[test]
+    fn test_track_gain_and_headroom() {
+        let mut daw_file = DawFile::new("Test".to_string());
+        daw_file.mixer.set_track_gain("kick", -6.0206).unwrap();
+        daw_file.mixer.set_headroom(6.0206).unwrap();
+        let engine = AudioEngine::new(daw_file);
+        let tracks = BTreeMap::from([("kick", Stereo::from_mono(vec![1.0; 4])), ("lead", Stereo::from_mono(vec![1.0; 4]))]);
+        let master = speakers::to_stereo(&engine.mix_tracks(&tracks, 4, 1.0).unwrap());
+        // (lead + kick at half level) lowered by the headroom
+        assert!((master.left[0] - 0.75).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_muted_tracks_are_left_out_of_the_mix() {
+        use dawww_core::instrument::Instrument;
+
+        let mut daw_file = DawFile::new("Test".to_string());
+        daw_file.mixdown.normalize = Normalize::Off;
+        for (instrument, tone) in [("lead", Tone::A), ("bass", Tone::C)] {
+            daw_file.add_instrument(instrument.to_string(), Instrument::new_subtractive(plain_sine())).unwrap();
+            daw_file.events.insert(Event {
+                time: "1.0".to_string(),
+                instrument: instrument.to_string(),
+                notes: vec![Note::new(Pitch::new(tone, 3), 8)],
+                extra: Default::default(),
+            }).unwrap();
+        }
+        let render = |daw_file: &DawFile| AudioEngine::new(daw_file.clone()).render_buffer().unwrap();
+        let full = render(&daw_file);
+
+        daw_file.mixer.set_mute("lead", true);
+        let muted = render(&daw_file);
+        assert_ne!(muted.left, full.left);
+        daw_file.mixer.set_mute("lead", false);
+        daw_file.mixer.set_solo("bass", true);
+        assert_eq!(render(&daw_file).left, muted.left);
+
+        daw_file.mixer.set_mute("bass", true);
+        assert!(render(&daw_file).left.iter().all(|&s| s == 0.0));
+    }
+
+    #[test]
+    fn test_stems_sum_to_the_mix() {
+        use dawww_core::instrument::Instrument;
+        use dawww_core::mixer::Bus;
+
+        let mut daw_file = DawFile::new("Test".to_string());
+        daw_file.mixdown = MixdownSettings::float(44100);
+        for instrument in ["lead", "bass", "pad"] {
+            daw_file.add_instrument(instrument.to_string(), Instrument::new_subtractive(plain_sine())).unwrap();
+        }
+        for (instrument, tone) in [("lead", Tone::A), ("bass", Tone::C)] {
+            daw_file.events.insert(Event {
+                time: "1.0".to_string(),
+                instrument: instrument.to_string(),
+                notes: vec![Note::new(Pitch::new(tone, 3), 8)],
+                extra: Default::default(),
+            }).unwrap();
+        }
+        daw_file.mixer.add_bus("low end", Bus { gain_db: -6.0, ..Bus::default() }).unwrap();
+        daw_file.mixer.set_track_output("bass", Some("low end")).unwrap();
+
+        let temp_dir = TempDir::new().unwrap();
+        let engine = AudioEngine::new(daw_file);
+        let stems = engine.render_stems(&temp_dir.path().join("stems")).unwrap();
+        let names: Vec<_> = stems.iter().map(|p| p.file_name().unwrap().to_str().unwrap()).collect();
+        assert_eq!(names, vec!["track-lead.wav", "track-pad.wav", "bus-low end.wav"]);
+
+        let mix_path = temp_dir.path().join("mix.wav");
+        engine.render(&mix_path).unwrap();
+        let read = |path: &Path| -> Vec<f32> {
+            hound::WavReader::open(path).unwrap().samples::<f32>().map(|s| s.unwrap()).collect()
+        };
+        let mix = read(&mix_path);
+        let mut sum = vec![0.0_f32; mix.len()];
+        for stem in &stems {
+            let samples = read(stem);
+            assert_eq!(samples.len(), mix.len());
+            sum.iter_mut().zip(samples).for_each(|(total, s)| *total += s);
+        }
+        assert!(sum.iter().zip(&mix).all(|(a, b)| (a - b).abs() < 1e-5));
+        assert!(read(&stems[1]).iter().all(|&s| s == 0.0));
+    }
+
+    #[test]
+    fn test_oversampled_voices_match_plain_ones() {
+        use dawww_core::instrument::Instrument;
+
+        let mut daw_file = DawFile::new("Test".to_string());
+        let params = SubtractiveParams { filter_cutoff: 2000.0, ..plain_sine() };
+        daw_file.add_instrument("lead".to_string(), Instrument::new_subtractive(params)).unwrap();
+        daw_file.add_note("1.0", "lead", Note::new(Pitch::new(Tone::A, 3), 16)).unwrap();
+        daw_file.mixdown = MixdownSettings::float(8000).with_normalize(false).with_tail(0.0);
+        let plain = AudioEngine::new(daw_file.clone()).render_buffer().unwrap();
+        daw_file.mixdown = daw_file.mixdown.clone().with_oversampling(4);
+        let oversampled = AudioEngine::new(daw_file).render_buffer().unwrap();
+
+        // A low sine is well below both rates' Nyquist, so only the filter's shape moves
+        assert_eq!(oversampled.len(), plain.len());
+        assert!((oversampled.peak() - plain.peak()).abs() < 0.02);
+        assert!(oversampled.left[200..7800].iter().zip(&plain.left[200..]).all(|(a, b)| (a - b).abs() < 0.05));
+    }
+
+    #[test]
+    fn test_render_images() {
+        use dawww_core::instrument::Instrument;
+
+        let mut daw_file = DawFile::new("Test".to_string());
+        daw_file.add_instrument("lead".to_string(), Instrument::new_subtractive(plain_sine())).unwrap();
+        daw_file.add_note("1.0", "lead", Note::new(Pitch::new(Tone::A, 4), 32)).unwrap();
+        daw_file.mixdown = MixdownSettings::float(8000);
+        let engine = AudioEngine::new(daw_file);
+        let temp_dir = TempDir::new().unwrap();
+        for (kind, name) in [(ImageKind::Waveform, "waveform.png"), (ImageKind::Spectrogram, "spectrogram.png")] {
+            let path = temp_dir.path().join(name);
+            engine.render_image(&path, kind, 64, 32).unwrap();
+            let png = std::fs::read(&path).unwrap();
+            assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+            assert_eq!(&png[16..24], [0, 0, 0, 64, 0, 0, 0, 32]);
+        }
+        let path = temp_dir.path().join("lead.png");
+        engine.render_instrument_image("lead", &path, ImageKind::Waveform, 64, 32).unwrap();
+        assert!(path.exists());
+        assert!(engine.render_instrument_image("bass", &path, ImageKind::Waveform, 64, 32).is_err());
+        assert!(engine.render_image(&path, ImageKind::Waveform, 0, 32).is_err());
+    }
+
+    #[test]
+    fn test_trim_silence_and_fades() {
+        use dawww_core::instrument::Instrument;
+
+        // A bar of silence, then a bar-long note
+        let mut daw_file = DawFile::new("Test".to_string());
+        daw_file.add_instrument("lead".to_string(), Instrument::new_subtractive(plain_sine())).unwrap();
+        daw_file.add_note("2.0", "lead", Note::new(Pitch::new(Tone::A, 4), 32)).unwrap();
+        daw_file.mixdown = MixdownSettings::float(8000).with_normalize(true).with_tail(0.0);
+        let whole = AudioEngine::new(daw_file.clone()).render_buffer().unwrap();
+        assert_eq!(whole.len(), 32000);
+
+        daw_file.mixdown = daw_file.mixdown.clone().with_trim_silence(-60.0);
+        let trimmed = AudioEngine::new(daw_file.clone()).render_buffer().unwrap();
+        assert!((15990..=16000).contains(&trimmed.len()), "{}", trimmed.len());
+        assert_eq!(trimmed.left, whole.left[32000 - trimmed.len()..]);
+
+        // Stems are cut the same way, so they still line up with the mix
+        let temp_dir = TempDir::new().unwrap();
+        let stems = AudioEngine::new(daw_file.clone()).render_stems(temp_dir.path()).unwrap();
+        assert_eq!(hound::WavReader::open(&stems[0]).unwrap().duration() as usize, trimmed.len());
+
+        daw_file.mixdown = daw_file.mixdown.clone().with_fades(0.5, 0.5);
+        let faded = AudioEngine::new(daw_file).render_buffer().unwrap();
+        assert_eq!(faded.len(), trimmed.len());
+        assert_eq!(faded.left[0], 0.0);
+        assert!(Stereo::from_mono(faded.left[..400].to_vec()).peak() <= 0.1);
+        assert!(Stereo::from_mono(faded.left[6000..10000].to_vec()).peak() > 0.99);
+        assert_eq!(*faded.left.last().unwrap(), 0.0);
+    }
+
+    #[test]
+    fn test_click_track() {
+        use dawww_core::instrument::Instrument;
+
+        let mut daw_file = DawFile::new("Test".to_string());
+        daw_file.add_instrument("lead".to_string(), Instrument::new_subtractive(plain_sine())).unwrap();
+        daw_file.add_note("1.0", "lead", Note::new(Pitch::new(Tone::A, 4), 64)).unwrap();
+        daw_file.mixdown = MixdownSettings::float(8000).with_normalize(false).with_tail(0.0);
+        let plain = AudioEngine::new(daw_file.clone()).render_buffer().unwrap();
+
+        // At 120 BPM beats are 4000 frames apart, with every fourth accented
+        let click = Click::new(ClickMode::Mix, -6.0);
+        daw_file.mixdown = daw_file.mixdown.clone().with_click(click.clone());
+        let clicked = AudioEngine::new(daw_file.clone()).render_buffer().unwrap();
+        let difference: Vec<f64> = clicked.left.iter().zip(&plain.left).map(|(a, b)| a - b).collect();
+        let peak = |start: usize| difference[start..start + 400].iter().fold(0.0_f64, |peak, s| peak.max(s.abs()));
+        assert_eq!(difference.len(), 32000);
+        assert!((peak(0) - 0.5).abs() < 0.05, "{}", peak(0));
+        assert!((peak(16000) - 0.5).abs() < 0.05);
+        for beat in [1, 2, 3, 5, 6, 7] {
+            assert!((peak(beat * 4000) - 0.25).abs() < 0.05);
+        }
+        assert!(difference[2000..3900].iter().all(|&s| s == 0.0));
+
+        // As a stem, the click stays out of the mix
+        daw_file.mixdown.click = Some(Click { mode: ClickMode::Stem, ..click });
+        let engine = AudioEngine::new(daw_file);
+        assert_eq!(engine.render_buffer().unwrap(), plain);
+        let temp_dir = TempDir::new().unwrap();
+        let stems = engine.render_stems(temp_dir.path()).unwrap();
+        assert_eq!(stems.last().unwrap().file_name().unwrap(), "click.wav");
+    }
+
+    #[test]
+    fn test_render_pans_notes() {
+        use dawww_core::instrument::Instrument;
+
+        let mut daw_file = DawFile::new("Test".to_string());
+        daw_file.add_instrument("lead".to_string(), Instrument::new_subtractive(plain_sine())).unwrap();
+        daw_file.add_note("1.0", "lead", Note::new(Pitch::new(Tone::A, 4), 16).with_pan(-1.0)).unwrap();
+        daw_file.add_note("1.16", "lead", Note::new(Pitch::new(Tone::A, 4), 16)).unwrap();
+        daw_file.mixdown = daw_file.mixdown.clone().with_normalize(false);
+
+        let temp_dir = TempDir::new().unwrap();
+        let output = temp_dir.path().join("panned.wav");
+        AudioEngine::new(daw_file).with_sample_rate(8000).render(&output).unwrap();
+
+        let mut reader = hound::WavReader::open(&output).unwrap();
+        assert_eq!(reader.spec().channels, 2);
+        let samples: Vec<i16> = reader.samples::<i16>().map(|s| s.unwrap()).collect();
+        let peak = |frames: std::ops::Range<usize>, channel: usize| {
+            frames.map(|i| samples[i * 2 + channel].unsigned_abs()).max().unwrap()
+        };
+        // The first note is hard left and the second is in the middle of both channels
+        assert_eq!(peak(0..8000, 1), 0);
+        assert!(peak(0..8000, 0) > i16::MAX as u16 - 100);
+        assert_eq!(peak(8000..16000, 0), peak(8000..16000, 1));
+    }
+
+    #[test]
+    fn test_mix_pans_tracks_with_automation() {
+        use dawww_core::ControlEvent;
+        use dawww_core::controllers::Curve;
+
+        let mut daw_file = DawFile::new("Test".to_string());
+        daw_file.mixer.set_pan("right", 1.0).unwrap();
+        daw_file.mixer.set_pan("swept", -1.0).unwrap();
+        daw_file.add_instrument("swept".to_string(), dawww_core::Instrument::new_subtractive(plain_sine())).unwrap();
+        daw_file.add_control(ControlEvent {
+            time: "1.2".to_string(),
+            instrument: "swept".to_string(),
+            controller: Controller::Pan,
+            value: 1.0,
+            curve: Curve::Step,
+            extra: Default::default(),
+        }).unwrap();
+
+        // One sample per 32nd
+        let engine = AudioEngine::new(daw_file).with_sample_rate(1);
+        let tracks = BTreeMap::from([("right", Stereo::from_mono(vec![0.5; 4]))]);
+        let master = speakers::to_stereo(&engine.mix_tracks(&tracks, 4, 1.0).unwrap());
+        assert!(master.left.iter().all(|s| s.abs() < 1e-12));
+        assert!((master.right[0] - 0.5).abs() < 1e-12);
+
+        // The automation is added to the track's pan: hard left, then centred from the third 32nd
+        let tracks = BTreeMap::from([("swept", Stereo::from_mono(vec![1.0; 4]))]);
+        let master = speakers::to_stereo(&engine.mix_tracks(&tracks, 4, 1.0).unwrap());
+        assert!(master.right[1].abs() < 1e-12);
+        assert_eq!((master.left[2], master.right[2]), (1.0, 1.0));
+    }
+
+    #[test]
+    fn test_pitch_bend_shifts_frequency() {
+        use dawww_core::instrument::Instrument;
+        use dawww_core::ControlEvent;
+        use dawww_core::controllers::Curve;
+
+        let mut daw_file = DawFile::new("Test".to_string());
+        daw_file.add_instrument("lead".to_string(), Instrument::new_subtractive(plain_sine())).unwrap();
+        daw_file.add_note("1.0", "lead", Note::new(Pitch::new(Tone::A, 4), 32)).unwrap();
+        daw_file.add_control(ControlEvent {
+            time: "1.0".to_string(),
+            instrument: "lead".to_string(),
+            controller: Controller::PitchBend,
+            value: 12.0,
+            curve: Curve::Step,
+            extra: Default::default(),
+        }).unwrap();
+
+        let engine = AudioEngine::new(daw_file);
+        let tracks = engine.render_tracks(44100, 60.0 / (120.0 * 8.0));
+        let track = &tracks["lead"].left;
+
+        // An octave up from A4 is 880 Hz: count rising zero crossings over one second
+        let crossings = track.windows(2).filter(|w| w[0] < 0.0 && w[1] >= 0.0).count();
+        assert!((crossings as i32 - 880).abs() <= 1);
+    }
+
+    #[test]
+    fn test_velocity_sets_level_and_cutoff() {
+        use dawww_core::instrument::{FilterType, Instrument, Waveform};
+        use dawww_core::{VelocityCurve, VelocityResponse};
+
+        let saw = SubtractiveParams { oscillator_wave: Waveform::Sawtooth, filter_type: FilterType::Lowpass, filter_cutoff: 4000.0, ..plain_sine() };
+        let response = VelocityResponse::new(VelocityCurve::Linear, 0.8, 2.0);
+        let mut daw_file = DawFile::new("Test".to_string());
+        daw_file.add_instrument("played".to_string(), Instrument::new_subtractive(saw.clone()).with_velocity(response.clone())).unwrap();
+        daw_file.add_note("1.0", "played", Note::new(Pitch::new(Tone::A, 3), 8).with_velocity(127)).unwrap();
+        daw_file.add_note("1.8", "played", Note::new(Pitch::new(Tone::A, 3), 8).with_velocity(40)).unwrap();
+        // The same notes without a velocity response, the soft one set up by hand
+        let dark = SubtractiveParams { filter_cutoff: 4000.0 * response.cutoff_ratio(40), ..saw.clone() };
+        daw_file.add_instrument("loud".to_string(), Instrument::new_subtractive(saw)).unwrap();
+        daw_file.add_instrument("soft".to_string(), Instrument::new_subtractive(dark)).unwrap();
+        daw_file.add_note("1.0", "loud", Note::new(Pitch::new(Tone::A, 3), 8)).unwrap();
+        daw_file.add_note("1.8", "soft", Note::new(Pitch::new(Tone::A, 3), 8)).unwrap();
+
+        let engine = AudioEngine::new(daw_file);
+        let tracks = engine.render_tracks(44100, 60.0 / (120.0 * 8.0));
+        let played = &tracks["played"].left;
+        let (loud, soft) = (&tracks["loud"].left, &tracks["soft"].left);
+        // Full velocity plays as the instrument is set up; softer notes are quieter and darker
+        let gain = response.gain(40);
+        assert!(gain < 0.6);
+        for i in 0..played.len() {
+            assert!((played[i] - loud[i] - soft[i] * gain).abs() < 1e-9, "{}", i);
+        }
+    }
+
+    #[test]
+    fn test_mono_instrument_cuts_overlapping_notes() {
+        use dawww_core::instrument::Instrument;
+        use dawww_core::Polyphony;
+
+        let mut daw_file = DawFile::new("Test".to_string());
+        daw_file.add_instrument(
+            "bass".to_string(),
+            Instrument::new_subtractive(plain_sine()).with_polyphony(Polyphony::mono()),
+        ).unwrap();
+        daw_file.add_note("1.0", "bass", Note::new(Pitch::new(Tone::A, 4), 32)).unwrap();
+        daw_file.add_note("1.16", "bass", Note::new(Pitch::new(Tone::A, 5), 16)).unwrap();
+
+        let engine = AudioEngine::new(daw_file);
+        let seconds_per_32nd = 60.0 / (120.0 * 8.0);
+        let track = &engine.render_tracks(88200, seconds_per_32nd)["bass"].left;
+
+        // The first note stops when the second starts, so only A5 sounds for the second
+        // second and the two sine waves never add up
+        let second_half = &track[44100..];
+        assert!(track.iter().all(|s| s.abs() <= 1.0 + 1e-9));
+        let crossings = second_half.windows(2).filter(|w| w[0] < 0.0 && w[1] >= 0.0).count();
+        assert!((crossings as i32 - 880).abs() <= 1);
+    }
+
+    #[test]
+    fn test_instrument_tuning_shifts_frequency() {
+        use dawww_core::instrument::Instrument;
+        use dawww_core::Tuning;
+
+        let mut daw_file = DawFile::new("Test".to_string());
+        daw_file.add_instrument("lead".to_string(), Instrument::new_subtractive(plain_sine())).unwrap();
+        daw_file.add_note("1.0", "lead", Note::new(Pitch::new(Tone::A, 4), 32)).unwrap();
+        daw_file.set_instrument_tuning("lead", Tuning { octave: -1, transpose: 7, ..Default::default() }).unwrap();
+
+        let engine = AudioEngine::new(daw_file);
+        let track = &engine.render_tracks(44100, 60.0 / (120.0 * 8.0))["lead"].left;
+
+        // Down an octave and up a fifth from A4 is E4, about 329.6 Hz
+        let crossings = track.windows(2).filter(|w| w[0] < 0.0 && w[1] >= 0.0).count();
+        assert!((crossings as i32 - 330).abs() <= 1);
+    }
+
+    #[test]
+    fn test_groove_delays_off_beats() {
+        use dawww_core::instrument::Instrument;
+        use dawww_core::Grid;
+
+        let mut daw_file = DawFile::new("Test".to_string());
+        for id in ["swung", "straight"] {
+            daw_file.add_instrument(id.to_string(), Instrument::new_subtractive(plain_sine())).unwrap();
+            daw_file.add_note("1.4", id, Note::new(Pitch::new(Tone::A, 4), 4)).unwrap();
+        }
+        daw_file.set_groove(Groove::new(Grid::Eighth).with_swing(75.0)).unwrap();
+        daw_file.set_instrument_groove("straight", Some(Groove::default())).unwrap();
+
+        // A 32nd is 1/16 s at 120 BPM: 2756.25 samples at 44.1 kHz
+        let engine = AudioEngine::new(daw_file);
+        let tracks = engine.render_tracks(44100, 60.0 / (120.0 * 8.0));
+        let onset = |id: &str| tracks[id].left.iter().position(|&s| s != 0.0).unwrap();
+        let span = |id: &str| tracks[id].left.iter().rposition(|&s| s != 0.0).unwrap() - onset(id);
+        assert!(onset("straight").abs_diff(11025) <= 1);
+        assert!(onset("swung").abs_diff(16537) <= 1);
+        // The swung note ends on the following (straight) beat, so it's shorter
+        assert!(span("swung").abs_diff(5512) <= 2);
+    }
+
+    #[test]
+    fn test_arpeggio_plays_notes_in_turn() {
+        use dawww_core::instrument::Instrument;
+        use dawww_core::{ArpDirection, Arpeggio, Grid};
+
+        let mut daw_file = DawFile::new("Test".to_string());
+        daw_file.add_instrument("arp".to_string(), Instrument::new_subtractive(plain_sine())).unwrap();
+        for tone in [Tone::A, Tone::C] {
+            daw_file.add_note("1.0", "arp", Note::new(Pitch::new(tone, 4), 12)).unwrap();
+        }
+        let arpeggio = Arpeggio::new(ArpDirection::Down, Grid::Quarter).with_gate(1.0);
+        daw_file.set_instrument_arpeggio("arp", Some(arpeggio)).unwrap();
+
+        // Each quarter note (half a second) plays one note alone: A4, then C4 for the
+        // remaining 32nds of the chord
+        let engine = AudioEngine::new(daw_file);
+        let track = &engine.render_tracks(44100, 60.0 / (120.0 * 8.0))["arp"].left;
+        let crossings = |range: std::ops::Range<usize>| {
+            track[range].windows(2).filter(|w| w[0] < 0.0 && w[1] >= 0.0).count() as i32
+        };
+        assert!((crossings(0..22050) - 220).abs() <= 1);
+        assert!((crossings(22050..33075) - 65).abs() <= 1);
+        assert!(track[33075..].iter().all(|&s| s == 0.0));
+    }
+
+    #[test]
+    fn test_project_tuning_sets_frequency() {
+        use dawww_core::instrument::Instrument;
+        use dawww_core::pitch::Tuning;
+
+        let mut daw_file = DawFile::new("Test".to_string());
+        daw_file.add_instrument("lead".to_string(), Instrument::new_subtractive(plain_sine())).unwrap();
+        daw_file.add_note("1.0", "lead", Note::new(Pitch::new(Tone::C, 4), 32)).unwrap();
+        daw_file.set_tuning(Tuning::just_intonation()).unwrap();
+
+        // Just C4 is 264 Hz against A4 = 440, rather than 261.6
+        let engine = AudioEngine::new(daw_file);
+        let track = &engine.render_tracks(44100, 60.0 / (120.0 * 8.0))["lead"].left;
+        let crossings = track.windows(2).filter(|w| w[0] < 0.0 && w[1] >= 0.0).count();
+        assert!((crossings as i32 - 264).abs() <= 1);
+    }
+
+    #[test]
+    fn test_sampler_plays_zone_sample() {
+        use dawww_core::instrument::{Instrument, SampleZone};
+
+        let temp_dir = TempDir::new().unwrap();
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 44100,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(temp_dir.path().join("ramp.wav"), spec).unwrap();
+        for i in 0..100 {
+            writer.write_sample((i * 100) as i16).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let mut params = dawww_core::instrument::SamplerParams::new(PathBuf::from("missing.wav"));
+        params.zones.push(SampleZone::new(PathBuf::from("ramp.wav"), Pitch::new(Tone::C, 4), Pitch::new(Tone::C, 4), Pitch::new(Tone::C, 5)));
+        let mut daw_file = DawFile::new("Test".to_string());
+        daw_file.add_instrument("keys".to_string(), Instrument::new(InstrumentKind::Sampler(params))).unwrap();
+        daw_file.add_note("1.0", "keys", Note::new(Pitch::new(Tone::C, 5), 8)).unwrap();
+        daw_file.samples.register(temp_dir.path(), "ramp.wav").unwrap();
+
+        let engine = AudioEngine::new(daw_file).with_project_dir(temp_dir.path().to_path_buf());
+        let track = &engine.render_tracks(1000, 60.0 / (120.0 * 8.0))["keys"].left;
+
+        // An octave above the root plays every other frame and stops at the end of the sample,
+        // fading in so it doesn't click
+        let fade_in = |i: usize| i as f64 / (synth::DECLICK_SECONDS * 44100.0);
+        assert!((track[1] - fade_in(1) * 200.0 / 32768.0 * CENTRE).abs() < 1e-9);
+        assert!((track[49] - fade_in(49) * 9800.0 / 32768.0 * CENTRE).abs() < 1e-9);
+        assert_eq!(track[50], 0.0);
+    }
+
+    #[test]
+    fn test_sfz_instrument_plays_its_regions() {
+        use dawww_core::instrument::Instrument;
+
+        let temp_dir = TempDir::new().unwrap();
+        let spec = hound::WavSpec { channels: 1, sample_rate: 1000, bits_per_sample: 16, sample_format: hound::SampleFormat::Int };
+        let mut writer = hound::WavWriter::create(temp_dir.path().join("tone.wav"), spec).unwrap();
+        for i in 0..1000 {
+            writer.write_sample(if i % 4 < 2 { 16384_i16 } else { -16384 }).unwrap();
+        }
+        writer.finalize().unwrap();
+        std::fs::write(temp_dir.path().join("keys.sfz"), "<region> sample=tone.wav lokey=c4 hikey=c5 pitch_keycenter=c4 volume=-6.0206").unwrap();
+
+        let mut daw_file = DawFile::new("Test".to_string());
+        daw_file.add_instrument("keys".to_string(), Instrument::new_sfz(PathBuf::from("keys.sfz"))).unwrap();
+        daw_file.add_note("1.0", "keys", Note::new(Pitch::new(Tone::C, 5), 8)).unwrap();
+        daw_file.add_note("2.0", "keys", Note::new(Pitch::new(Tone::C, 6), 8)).unwrap();
+        daw_file.sync_samples(temp_dir.path()).unwrap();
+
+        let engine = AudioEngine::new(daw_file).with_project_dir(temp_dir.path().to_path_buf()).with_sample_rate(1000);
+        let track = &engine.render_tracks(3000, 60.0 / (120.0 * 8.0))["keys"].left;
+        // An octave up, the four-frame cycle takes two frames
+        assert!((track[100] - 0.25 * CENTRE).abs() < 1e-6 && (track[102] - 0.25 * CENTRE).abs() < 1e-6);
+        assert!((track[101] + 0.25 * CENTRE).abs() < 1e-6);
+        // No region covers C6
+        assert!(track[2000..].iter().all(|&s| s == 0.0));
+    }
+
+    #[test]
+    fn test_sampler_repitches_from_root_with_envelope() {
+        use dawww_core::instrument::Instrument;
+
+        let temp_dir = TempDir::new().unwrap();
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 1000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(temp_dir.path().join("ramp.wav"), spec).unwrap();
+        for i in 0..2000 {
+            writer.write_sample((i * 10) as i16).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let mut params = dawww_core::instrument::SamplerParams::new(PathBuf::from("ramp.wav"));
+        params.root = Some(Pitch::new(Tone::C, 4));
+        params.envelope_attack = 0.1;
+        params.envelope_release = 0.1;
+        let mut daw_file = DawFile::new("Test".to_string());
+        daw_file.add_instrument("keys".to_string(), Instrument::new(InstrumentKind::Sampler(params))).unwrap();
+        // Half a second at 120 BPM
+        daw_file.add_note("1.0", "keys", Note::new(Pitch::new(Tone::C, 5), 8)).unwrap();
+        daw_file.samples.register(temp_dir.path(), "ramp.wav").unwrap();
+
+        let engine = AudioEngine::new(daw_file)
+            .with_project_dir(temp_dir.path().to_path_buf())
+            .with_sample_rate(1000);
+        let track = &engine.render_tracks(1000, 60.0 / (120.0 * 8.0))["keys"].left;
+
+        // An octave above the root plays at double speed, fading in and out
+        assert!((track[50] - 0.5 * 1000.0 / 32768.0 * CENTRE).abs() < 1e-9);
+        assert!((track[200] - 4000.0 / 32768.0 * CENTRE).abs() < 1e-9);
+        assert!((track[550] - 0.5 * 11000.0 / 32768.0 * CENTRE).abs() < 1e-9);
+        assert!(track[600..].iter().all(|&s| s == 0.0));
+    }
+
+    #[test]
+    fn test_percussion_hits_ring_for_their_envelope() {
+        use dawww_core::instrument::{Instrument, PercussionParams};
+
+        let hat = PercussionParams {
+            tone_level: 0.0,
+            noise_level: 1.0,
+            envelope_attack: 0.0,
+            envelope_decay: 0.25,
+            ..PercussionParams::default()
+        };
+        let mut daw_file = DawFile::new("Test".to_string());
+        daw_file.add_instrument("hat".to_string(), Instrument::new_percussion(hat)).unwrap();
+        daw_file.add_note("1.0", "hat", Note::new(Pitch::new(Tone::C, 4), 1)).unwrap();
+        daw_file.add_note("1.16", "hat", Note::new(Pitch::new(Tone::C, 4), 16)).unwrap();
+
+        // A 32nd lasts 1/16th of a second at 120 BPM; each hit lasts a quarter of a second
+        let engine = AudioEngine::new(daw_file).with_sample_rate(1000);
+        let track = &engine.render_tracks(2000, 60.0 / (120.0 * 8.0))["hat"].left;
+        assert!(track[100..240].iter().any(|s| s.abs() > 0.1));
+        assert!(track[250..1000].iter().all(|&s| s == 0.0));
+        assert!(track[1250..].iter().all(|&s| s == 0.0));
+        // Every hit gets its own noise
+        assert_ne!(track[..250], track[1000..1250]);
+        assert_eq!(track, &engine.render_tracks(2000, 60.0 / (120.0 * 8.0))["hat"].left);
+    }
+
+    #[test]
+    fn test_hits_keep_their_noise_when_notes_are_added() {
+        use dawww_core::instrument::{Instrument, PercussionParams};
+
+        let hat = PercussionParams { tone_level: 0.0, noise_level: 1.0, ..PercussionParams::default() };
+        let mut daw_file = DawFile::new("Test".to_string());
+        daw_file.add_instrument("hat".to_string(), Instrument::new_percussion(hat)).unwrap();
+        daw_file.add_note("1.16", "hat", Note::new(Pitch::new(Tone::C, 4), 1)).unwrap();
+        let render = |daw_file: &DawFile| {
+            let engine = AudioEngine::new(daw_file.clone()).with_sample_rate(1000);
+            engine.render_tracks(2000, 60.0 / (120.0 * 8.0))["hat"].left[1000..].to_vec()
+        };
+        let hit = render(&daw_file);
+        assert!(hit.iter().any(|s| s.abs() > 0.1));
+
+        // An earlier note doesn't change which noise the later hit gets
+        daw_file.add_note("1.0", "hat", Note::new(Pitch::new(Tone::C, 4), 1)).unwrap();
+        assert_eq!(render(&daw_file), hit);
+
+        // but a doubled note gets noise of its own
+        daw_file.add_note("1.16", "hat", Note::new(Pitch::new(Tone::C, 4), 1)).unwrap();
+        let doubled = render(&daw_file);
+        assert_ne!(doubled, hit);
+        assert!(doubled.iter().zip(&hit).any(|(doubled, hit)| (doubled - 2.0 * hit).abs() > 1e-6));
+    }
+
+    #[test]
+    fn test_seed_makes_renders_repeat() {
+        use dawww_core::instrument::{Instrument, PercussionParams};
+
+        let mut daw_file = DawFile::new("Test".to_string());
+        let snare = PercussionParams { noise_level: 0.5, ..PercussionParams::default() };
+        daw_file.add_instrument("snare".to_string(), Instrument::new_percussion(snare)).unwrap();
+        daw_file.add_note("1.0", "snare", Note::new(Pitch::new(Tone::D, 4), 8)).unwrap();
+        daw_file.mixdown = daw_file.mixdown.clone().with_dither(dawww_core::Dither::Tpdf);
+        daw_file.set_seed(5);
+
+        let temp_dir = TempDir::new().unwrap();
+        let render = |engine: AudioEngine, name: &str| {
+            let path = temp_dir.path().join(name);
+            engine.with_sample_rate(8000).render(&path).unwrap();
+            std::fs::read(path).unwrap()
+        };
+        let first = render(AudioEngine::new(daw_file.clone()), "a.wav");
+        assert_eq!(render(AudioEngine::new(daw_file.clone()), "b.wav"), first);
+        // A seed given to the engine wins over the project's
+        assert_ne!(render(AudioEngine::new(daw_file.clone()).with_seed(6), "c.wav"), first);
+        daw_file.set_seed(0);
+        assert_eq!(render(AudioEngine::new(daw_file).with_seed(5), "d.wav"), first);
+    }
+
+    #[test]
+    fn test_subtractive_synth_shapes_notes() {
+        use dawww_core::instrument::{FilterType, Instrument, Waveform};
+
+        let saw = SubtractiveParams { oscillator_wave: Waveform::Sawtooth, ..plain_sine() };
+        let mut daw_file = DawFile::new("Test".to_string());
+        daw_file.add_instrument("bright".to_string(), Instrument::new_subtractive(saw.clone())).unwrap();
+        daw_file.add_instrument("dark".to_string(), Instrument::new_subtractive(SubtractiveParams {
+            filter_type: FilterType::Lowpass,
+            filter_cutoff: 200.0,
+            filter_resonance: 0.0,
+            ..saw.clone()
+        })).unwrap();
+        daw_file.add_instrument("pad".to_string(), Instrument::new_subtractive(SubtractiveParams {
+            envelope_attack: 0.5,
+            envelope_release: 0.25,
+            ..saw
+        })).unwrap();
+        for id in ["bright", "dark", "pad"] {
+            daw_file.add_note("1.0", id, Note::new(Pitch::new(Tone::A, 4), 16)).unwrap();
+        }
+
+        // The note lasts one second at 120 BPM
+        let engine = AudioEngine::new(daw_file);
+        let tracks = engine.render_tracks(88200, 60.0 / (120.0 * 8.0));
+        let peak = |id: &str, range: std::ops::Range<usize>| {
+            tracks[id].left[range].iter().fold(0.0_f64, |peak, s| peak.max(s.abs())) / CENTRE
+        };
+
+        // A 440 Hz sawtooth through a 200 Hz low-pass loses most of its level
+        assert!(peak("bright", 0..44100) > 0.95);
+        assert!(peak("dark", 22050..44100) < 0.5);
+
+        // The attack ramps up over half a second and the release rings past the note's end
+        assert!(peak("pad", 0..2205) < 0.1);
+        assert!(peak("pad", 44100..49000) > 0.5);
+        assert_eq!(peak("pad", 55200..88200), 0.0);
+    }
 }
@@ -0,0 +1,166 @@
+use std::f64::consts::PI;
+use crate::effects::Biquad;
+use crate::stereo::Stereo;
+
+/// Loudness of a gating block with the mean square `power`, in LUFS
+fn block_loudness(power: f64) -> f64 {
+    -0.691 + 10.0 * power.log10()
+}
+
+/// The BS.1770 K-weighting filter: a high shelf for the head's acoustic effect, then a
+/// high pass. Coefficients are worked out for any sample rate, following libebur128.
+fn k_weight(channel: &[f64], sample_rate: u32) -> Vec<f64> {
+    let sample_rate = sample_rate as f64;
+    let mut output = channel.to_vec();
+
+    let k = (PI * 1681.974450955533 / sample_rate).tan();
+    let q = 0.7071752369554196;
+    let vh = 10_f64.powf(3.999843853973347 / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    Biquad::from_coefficients(
+        [(vh + vb * k / q + k * k) / a0, 2.0 * (k * k - vh) / a0, (vh - vb * k / q + k * k) / a0],
+        [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    )
+    .process(&mut output);
+
+    let k = (PI * 38.13547087602444 / sample_rate).tan();
+    let q = 0.5003270373238773;
+    let a0 = 1.0 + k / q + k * k;
+    Biquad::from_coefficients([1.0, -2.0, 1.0], [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0])
+        .process(&mut output);
+    output
+}
+
+/// EBU R128 integrated loudness in LUFS: the K-weighted power of 400 ms blocks overlapping
+/// by 75%, leaving out blocks below -70 LUFS and then those 10 LU below the rest. `None` for
+/// silence.
+pub fn integrated_loudness(buffer: &Stereo, sample_rate: u32) -> Option<f64> {
+    integrated_loudness_weighted(&[&buffer.left, &buffer.right], &[1.0, 1.0], sample_rate)
+}
+
+/// Integrated loudness, as `integrated_loudness` measures it, of any number of channels of
+/// the same length, each counting toward the total by its weight
+pub fn integrated_loudness_weighted<C: AsRef<[f64]>>(channels: &[C], weights: &[f64], sample_rate: u32) -> Option<f64> {
+    let len = channels.first().map_or(0, |channel| channel.as_ref().len());
+    if len == 0 {
+        return None;
+    }
+    // Running sum of the squared, K-weighted samples of every channel
+    let mut energy = vec![0.0; len + 1];
+    for (channel, &weight) in channels.iter().zip(weights) {
+        if weight == 0.0 {
+            continue;
+        }
+        let mut total = 0.0;
+        for (i, sample) in k_weight(channel.as_ref(), sample_rate).iter().enumerate() {
+            total += weight * sample * sample;
+            energy[i + 1] += total;
+        }
+    }
+
+    // Songs shorter than a block are measured as one block
+    let block = ((0.4 * sample_rate as f64) as usize).clamp(1, len);
+    let step = (block / 4).max(1);
+    let powers: Vec<f64> = (0..=len - block).step_by(step)
+        .map(|start| (energy[start + block] - energy[start]) / block as f64)
+        .filter(|&power| power > 0.0 && block_loudness(power) > -70.0)
+        .collect();
+    if powers.is_empty() {
+        return None;
+    }
+    let mean = |powers: &[f64]| powers.iter().sum::<f64>() / powers.len() as f64;
+    let relative_gate = block_loudness(mean(&powers)) - 10.0;
+    let gated: Vec<f64> = powers.into_iter().filter(|&power| block_loudness(power) > relative_gate).collect();
+    Some(block_loudness(mean(&gated)))
+}
+
+/// Highest level of either channel between samples as well as on them, found by
+/// oversampling four times with a windowed-sinc interpolator
+pub fn true_peak(buffer: &Stereo) -> f64 {
+    true_peak_channels(&[&buffer.left, &buffer.right])
+}
+
+/// Highest true peak of any of a signal's channels
+pub fn true_peak_channels<C: AsRef<[f64]>>(channels: &[C]) -> f64 {
+    const OVERSAMPLING: usize = 4;
+    const HALF_TAPS: usize = 8;
+    // Taps for the points a quarter, half and three quarters of the way to the next sample
+    let phases: Vec<Vec<f64>> = (1..OVERSAMPLING)
+        .map(|phase| {
+            let fraction = phase as f64 / OVERSAMPLING as f64;
+            (0..2 * HALF_TAPS)
+                .map(|tap| {
+                    let distance = fraction + HALF_TAPS as f64 - 1.0 - tap as f64;
+                    let sinc = (PI * distance).sin() / (PI * distance);
+                    let window = 0.5 * (1.0 + (PI * distance / HALF_TAPS as f64).cos());
+                    sinc * window
+                })
+                .collect()
+        })
+        .collect();
+
+    let mut peak: f64 = 0.0;
+    for channel in channels {
+        let channel = channel.as_ref();
+        for (i, sample) in channel.iter().enumerate() {
+            peak = peak.max(sample.abs());
+            for taps in &phases {
+                let mut value = 0.0;
+                for (tap, weight) in taps.iter().enumerate() {
+                    let index = (i + tap + 1).checked_sub(HALF_TAPS);
+                    if let Some(input) = index.and_then(|index| channel.get(index)) {
+                        value += input * weight;
+                    }
+                }
+                peak = peak.max(value.abs());
+            }
+        }
+    }
+    peak
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(frequency: f64, amplitude: f64, phase: f64, sample_rate: u32, seconds: f64) -> Stereo {
+        Stereo::from_mono((0..(seconds * sample_rate as f64) as usize)
+            .map(|i| amplitude * (2.0 * PI * frequency * i as f64 / sample_rate as f64 + phase).sin())
+            .collect())
+    }
+
+    #[test]
+    fn test_integrated_loudness() {
+        // A full-scale 997 Hz sine in both channels measures 0 LUFS
+        let loudness = integrated_loudness(&sine(997.0, 1.0, 0.0, 48000, 3.0), 48000).unwrap();
+        assert!(loudness.abs() < 0.1, "{}", loudness);
+        let loudness = integrated_loudness(&sine(997.0, 0.1, 0.0, 44100, 3.0), 44100).unwrap();
+        assert!((loudness + 20.0).abs() < 0.1, "{}", loudness);
+
+        // Silence is gated out, so a long gap barely moves the measurement
+        let mut gapped = sine(997.0, 0.1, 0.0, 44100, 3.0);
+        for channel in gapped.channels_mut() {
+            channel.extend(vec![0.0; 44100 * 3]);
+        }
+        let loudness = integrated_loudness(&gapped, 44100).unwrap();
+        assert!((loudness + 20.0).abs() < 0.3, "{}", loudness);
+        assert_eq!(integrated_loudness(&Stereo::silent(44100), 44100), None);
+
+        // Weighted channels count by their weight; one channel alone is 3 dB down on two
+        let tone = sine(997.0, 0.1, 0.0, 44100, 3.0).left;
+        let single = integrated_loudness_weighted(&[&tone], &[1.0], 44100).unwrap();
+        assert!((single + 23.0).abs() < 0.1, "{}", single);
+        let rear = integrated_loudness_weighted(&[&tone], &[1.41], 44100).unwrap();
+        assert!((rear - single - 1.5).abs() < 0.01, "{}", rear - single);
+        assert_eq!(integrated_loudness_weighted(&[&tone], &[0.0], 44100), None);
+    }
+
+    #[test]
+    fn test_true_peak_finds_peaks_between_samples() {
+        // A quarter of the sample rate, with every sample landing halfway up the wave
+        let buffer = sine(11025.0, 1.0, PI / 4.0, 44100, 0.1);
+        assert!((buffer.peak() - std::f64::consts::FRAC_1_SQRT_2).abs() < 1e-3);
+        assert!((true_peak(&buffer) - 1.0).abs() < 0.02, "{}", true_peak(&buffer));
+    }
+}
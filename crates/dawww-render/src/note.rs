@@ -0,0 +1,210 @@
+use dawww_core::pitch::Pitch;
+use dawww_core::{DawFile, InstrumentKind};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use crate::oversample;
+use crate::sampler::{LoopRegion, Sample};
+use crate::sfz::SfzPlayer;
+use crate::synth::{self, Drum, Envelope, Voice};
+
+/// A note for a `NoteRenderer` to play
+#[derive(Debug, Clone, Copy)]
+pub struct PlayedNote {
+    pub pitch: Pitch,
+    pub velocity: u8,
+    /// Samples the note is held for before it's released
+    pub gate: usize,
+    /// Seed for the note's noise, if its instrument makes any
+    pub seed: u64,
+}
+
+/// Renders single notes the way an instrument plays them, dry and in mono. Offline renders
+/// and live playback share it, so both sound the same. Samples and SFZ instruments are
+/// loaded the first time a note needs them and kept for later notes.
+pub struct NoteRenderer {
+    sample_rate: u32,
+    oversampling: usize,
+    /// Directory that sample paths are relative to
+    project_dir: PathBuf,
+    /// Samples converted to the output rate, by file. `None` for files that can't be read or
+    /// aren't in the project's sample registry.
+    samples: HashMap<String, Option<Sample>>,
+    /// SFZ instruments by file. `None` for files that can't be read.
+    sfz: HashMap<String, Option<SfzPlayer>>,
+}
+
+impl NoteRenderer {
+    pub fn new(sample_rate: u32, project_dir: PathBuf) -> Self {
+        Self { sample_rate, oversampling: 1, project_dir, samples: HashMap::new(), sfz: HashMap::new() }
+    }
+
+    /// Run synth voices at `factor` times the sample rate, so their filters don't alias
+    pub fn with_oversampling(mut self, factor: usize) -> Self {
+        self.oversampling = factor.max(1);
+        self
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Render `note` on `instrument_id` from its start through the end of its release, at
+    /// most `max_len` samples of it. `bend` gives the pitch bend ratio any number of samples
+    /// in. Notes the tuning leaves unmapped render nothing.
+    pub fn render(
+        &mut self,
+        daw_file: &DawFile,
+        instrument_id: &str,
+        note: &PlayedNote,
+        max_len: usize,
+        bend: impl Fn(usize) -> f64,
+    ) -> Vec<f64> {
+        let sample_rate = self.sample_rate as f64;
+        let oversampling = self.oversampling;
+        let instrument = daw_file.get_instrument(instrument_id);
+        let (sampler, synth, percussion) = match instrument.map(|i| &i.kind) {
+            Some(InstrumentKind::Sampler(params)) => (Some(params), None, None),
+            Some(InstrumentKind::Synth(params)) => (None, Some(params), None),
+            Some(InstrumentKind::Percussion(params)) => (None, None, Some(params)),
+            Some(InstrumentKind::Sfz(_)) | None => (None, None, None),
+        };
+        let mut sfz = match instrument.map(|i| &i.kind) {
+            Some(InstrumentKind::Sfz(params)) => {
+                let rate = self.sample_rate;
+                self.sfz.entry(params.sfz_file.clone())
+                    .or_insert_with(|| {
+                        let path = daw_file.samples.resolve(&self.project_dir, &params.sfz_file).ok()?;
+                        SfzPlayer::load(&path, rate).ok()
+                    })
+                    .as_mut()
+            }
+            _ => None,
+        };
+        let tuning_ratio = instrument.map_or(1.0, |i| i.tuning.ratio());
+        let (velocity_gain, cutoff_ratio) = instrument.map_or((1.0, 1.0), |i| {
+            (i.velocity.gain(note.velocity), i.velocity.cutoff_ratio(note.velocity))
+        });
+        // Notes the tuning's keyboard mapping leaves unmapped are silent
+        let Some(frequency) = daw_file.tuning.frequency(&note.pitch) else {
+            return Vec::new();
+        };
+        let frequency = frequency * tuning_ratio;
+        // SFZ regions are repitched by key in equal temperament, so bend them to the
+        // project tuning, cents and the instrument's tuning
+        let key = note.pitch.to_midi();
+        let sfz_pitch_scale = frequency / (440.0 * 2_f64.powf((key as f64 - 69.0) / 12.0));
+
+        // Synth voices run at the oversampled rate, so their filters don't alias
+        let mut voice = synth.map(|params| {
+            let mut voice = Voice::new(params, self.sample_rate * oversampling as u32);
+            voice.scale_cutoff(cutoff_ratio);
+            voice
+        });
+        let sampler_envelope = sampler.map(Envelope::for_sampler);
+        // Synth and sampler notes ring on through their release
+        let release = match (&voice, &sampler_envelope) {
+            (Some(voice), _) => voice.release_samples().div_ceil(oversampling),
+            (None, Some(envelope)) => (envelope.release * sample_rate).ceil() as usize,
+            (None, None) => 0,
+        };
+        let mut drum = percussion.map(|params| Drum::new(params, self.sample_rate, note.seed));
+        // Drum hits last as long as their envelope, whatever the note's length
+        let length = match (&drum, &mut sfz) {
+            (Some(drum), _) => drum.length_samples(),
+            (None, Some(sfz)) => sfz.note_length(key, note.velocity, sfz_pitch_scale, note.gate),
+            (None, None) => note.gate + release,
+        };
+        let gate = note.gate;
+        let mut output = vec![0.0; length.min(max_len)];
+        if output.is_empty() {
+            return output;
+        }
+
+        'synthesis: {
+            if let (Some(params), Some(envelope)) = (sampler, sampler_envelope) {
+                let zone = params.zone_for(&note.pitch, note.velocity);
+                // Samples are repitched from their root in equal temperament, so bend them to
+                // the project tuning. Samples without a root play as recorded.
+                let temperament = if zone.repitched {
+                    frequency / (note.pitch.frequency(note.pitch.octave) * tuning_ratio)
+                } else {
+                    1.0
+                };
+                let rate = self.sample_rate;
+                let sample = self.samples.entry(zone.sample_file.to_string()).or_insert_with(|| {
+                    let path = daw_file.samples.resolve(&self.project_dir, zone.sample_file).ok()?;
+                    Sample::load(&path).ok().map(|sample| sample.resampled(rate))
+                });
+                if let Some(sample) = sample {
+                    let loop_region = LoopRegion::for_sampler(params, sample);
+                    sample.play(zone.pitch_ratio * tuning_ratio * temperament, rate, &mut output, loop_region);
+                    let gate = gate as f64 / sample_rate;
+                    for (i, out) in output.iter_mut().enumerate() {
+                        *out *= envelope.level(i as f64 / sample_rate, gate);
+                    }
+                    break 'synthesis;
+                }
+            }
+
+            let bent_frequency = |i: usize| frequency * bend(i);
+            if let Some(voice) = &mut voice {
+                if oversampling == 1 {
+                    voice.render(&mut output, gate, bent_frequency);
+                } else {
+                    let mut oversampled = vec![0.0; output.len() * oversampling];
+                    voice.render(&mut oversampled, gate * oversampling, |i| bent_frequency(i / oversampling));
+                    crate::mix_into(&mut output, &oversample::downsample(&oversampled, oversampling), 1.0);
+                }
+                break 'synthesis;
+            }
+            if let Some(drum) = &mut drum {
+                drum.render(&mut output, bent_frequency);
+                break 'synthesis;
+            }
+            if let Some(sfz) = &mut sfz {
+                sfz.render(key, note.velocity, sfz_pitch_scale, &mut output, gate);
+                break 'synthesis;
+            }
+
+            // Everything else (samplers and SFZ instruments whose file can't be read, and
+            // events for unknown instruments) is a plain sine wave for the length of the note. Accumulate phase
+            // so the frequency can follow pitch bend smoothly.
+            let mut phase = 0.0_f64;
+            let held = gate.min(output.len());
+            for (i, out) in output[..held].iter_mut().enumerate() {
+                *out += phase.sin();
+                phase += 2.0 * std::f64::consts::PI * bent_frequency(i) / sample_rate;
+            }
+            synth::declick(&mut output[..held], sample_rate);
+        }
+        if velocity_gain != 1.0 {
+            output.iter_mut().for_each(|s| *s *= velocity_gain);
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dawww_core::instrument::{Instrument, SubtractiveParams};
+    use dawww_core::pitch::Tone;
+
+    #[test]
+    fn test_notes_ring_through_their_release() {
+        let release = SubtractiveParams { envelope_release: 0.01, ..SubtractiveParams::default() };
+        let mut daw_file = DawFile::new("Test".to_string());
+        daw_file.add_instrument("synth".to_string(), Instrument::new_subtractive(release)).unwrap();
+        let mut renderer = NoteRenderer::new(8000, PathBuf::new());
+        let note = PlayedNote { pitch: Pitch::new(Tone::A, 4), velocity: 100, gate: 400, seed: 0 };
+
+        let output = renderer.render(&daw_file, "synth", &note, usize::MAX, |_| 1.0);
+        assert_eq!(output.len(), 480);
+        assert!(output[420..460].iter().any(|s| s.abs() > 0.0));
+        assert_eq!(renderer.render(&daw_file, "synth", &note, 100, |_| 1.0), output[..100]);
+
+        // Instruments without a sound of their own play a sine for as long as they're held
+        let output = renderer.render(&daw_file, "unknown", &note, usize::MAX, |_| 1.0);
+        assert_eq!(output.len(), 400);
+    }
+}
@@ -0,0 +1,103 @@
+use std::f64::consts::PI;
+use crate::sampler::bessel_i0;
+
+/// Half the anti-aliasing filter's length, in samples at the original rate
+const HALF_TAPS: usize = 16;
+const BETA: f64 = 8.0;
+
+/// Taps of a Kaiser-windowed sinc low-pass at `factor` times the rate, passing up to 90% of
+/// the original rate's Nyquist frequency. Centred, so it doesn't delay the signal.
+fn low_pass(factor: usize) -> Vec<f64> {
+    let half = (HALF_TAPS * factor) as f64;
+    let cutoff = 0.9 / factor as f64;
+    let window_scale = bessel_i0(BETA).recip();
+    (0..=2 * HALF_TAPS * factor)
+        .map(|tap| {
+            let distance = tap as f64 - half;
+            let x = distance / half;
+            let sinc = match distance * cutoff {
+                0.0 => 1.0,
+                t => (PI * t).sin() / (PI * t),
+            };
+            cutoff * sinc * bessel_i0(BETA * (1.0 - x * x).sqrt()) * window_scale
+        })
+        .collect()
+}
+
+/// Raise a signal to `factor` times its rate
+pub fn upsample(input: &[f64], factor: usize) -> Vec<f64> {
+    let taps = low_pass(factor);
+    let half = HALF_TAPS * factor;
+    let mut output = vec![0.0; input.len() * factor];
+    // Each input sample is spread over the samples around its place, scaled back up for
+    // the zeros it's padded with
+    for (i, &sample) in input.iter().enumerate() {
+        if sample == 0.0 {
+            continue;
+        }
+        let centre = i * factor;
+        let first = half.saturating_sub(centre);
+        let last = (output.len() + half - centre).min(taps.len());
+        for (tap, &weight) in taps.iter().enumerate().take(last).skip(first) {
+            output[centre + tap - half] += sample * weight * factor as f64;
+        }
+    }
+    output
+}
+
+/// Lower a signal to `1 / factor` of its rate, filtering out what the lower rate can't hold
+pub fn downsample(input: &[f64], factor: usize) -> Vec<f64> {
+    let taps = low_pass(factor);
+    let half = HALF_TAPS * factor;
+    (0..input.len() / factor)
+        .map(|i| {
+            let centre = i * factor;
+            let first = half.saturating_sub(centre);
+            let last = (input.len() + half - centre).min(taps.len());
+            taps[first..last].iter().enumerate()
+                .map(|(tap, weight)| input[centre + first + tap - half] * weight)
+                .sum()
+        })
+        .collect()
+}
+
+/// Run a nonlinear `stage` over `buffer` at `factor` times its rate
+pub fn process(buffer: &mut [f64], factor: usize, stage: impl FnOnce(&mut [f64])) {
+    if factor <= 1 {
+        return stage(buffer);
+    }
+    let mut oversampled = upsample(buffer, factor);
+    stage(&mut oversampled);
+    buffer.copy_from_slice(&downsample(&oversampled, factor));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(frequency: f64, sample_rate: f64, len: usize) -> Vec<f64> {
+        (0..len).map(|i| (2.0 * PI * frequency * i as f64 / sample_rate).sin()).collect()
+    }
+
+    #[test]
+    fn test_round_trip_keeps_the_signal() {
+        let input = sine(1000.0, 44100.0, 2000);
+        for factor in [2, 4] {
+            let up = upsample(&input, factor);
+            assert_eq!(up.len(), 2000 * factor);
+            // The upsampled signal is the same sine at the higher rate
+            let expected = sine(1000.0, 44100.0 * factor as f64, 2000 * factor);
+            assert!(up[200..up.len() - 200].iter().zip(&expected[200..]).all(|(a, b)| (a - b).abs() < 1e-3));
+            let down = downsample(&up, factor);
+            assert_eq!(down.len(), 2000);
+            assert!(down[100..1900].iter().zip(&input[100..]).all(|(a, b)| (a - b).abs() < 1e-3));
+        }
+    }
+
+    #[test]
+    fn test_downsample_removes_what_would_alias() {
+        // 30 kHz at 88.2 kHz would fold down to 14.1 kHz at 44.1 kHz
+        let down = downsample(&sine(30000.0, 88200.0, 4000), 2);
+        assert!(down[100..1900].iter().all(|s| s.abs() < 1e-3));
+    }
+}
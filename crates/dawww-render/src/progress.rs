@@ -0,0 +1,47 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// How far a render has got
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RenderProgress {
+    /// From 0 to 100
+    pub percent: f64,
+    /// Furthest bar rendered so far, numbered from 1
+    pub bar: u32,
+}
+
+/// Called as a render makes progress, possibly from worker threads
+pub type ProgressCallback = Arc<dyn Fn(RenderProgress) + Send + Sync>;
+
+/// Stops a render from another thread. Clones share the same flag.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ask every render holding this token to stop
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clones_share_cancellation() {
+        let token = CancelToken::new();
+        let clone = token.clone();
+        assert!(!clone.is_cancelled());
+        token.cancel();
+        assert!(clone.is_cancelled());
+    }
+}
@@ -0,0 +1,290 @@
+use anyhow::Result;
+use dawww_core::instrument::SamplerParams;
+use std::path::Path;
+
+/// A sustain loop in sample frames
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LoopRegion {
+    pub start: f64,
+    pub end: f64,
+    /// Frames before `end` over which the loop end fades into the audio before `start`
+    pub crossfade: f64,
+}
+
+impl LoopRegion {
+    /// The loop region for a sample played by a sampler, or `None` if it doesn't loop
+    pub fn for_sampler(params: &SamplerParams, sample: &Sample) -> Option<Self> {
+        if !params.looping {
+            return None;
+        }
+        let rate = sample.sample_rate as f64;
+        let len = sample.data.len() as f64;
+        let start = (params.loop_start.unwrap_or(0.0) * rate).min(len);
+        let end = params.loop_end.map(|end| (end * rate).min(len)).unwrap_or(len);
+        if end <= start {
+            return None;
+        }
+
+        // The crossfade reads audio from before the loop start, so it can't be longer than that
+        let crossfade = (params.loop_crossfade * rate).min(start).min(end - start);
+        Some(Self { start, end, crossfade })
+    }
+}
+
+/// A decoded audio sample, mixed down to mono
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    pub sample_rate: u32,
+    pub data: Vec<f64>,
+}
+
+/// Sample rate, channel count and interleaved samples between -1 and 1
+fn read_wav(path: &Path) -> Result<(u32, usize, Vec<f64>)> {
+    let mut reader = hound::WavReader::open(path)?;
+    let spec = reader.spec();
+    let samples: Vec<f64> = match spec.sample_format {
+        hound::SampleFormat::Float => reader.samples::<f32>()
+            .map(|s| s.map(|s| s as f64))
+            .collect::<std::result::Result<_, _>>()?,
+        hound::SampleFormat::Int => {
+            let scale = (1_i64 << (spec.bits_per_sample - 1)) as f64;
+            reader.samples::<i32>()
+                .map(|s| s.map(|s| s as f64 / scale))
+                .collect::<std::result::Result<_, _>>()?
+        }
+    };
+    Ok((spec.sample_rate, spec.channels.max(1) as usize, samples))
+}
+
+/// How long a WAV file plays for, read from its header without decoding it
+pub fn wav_seconds(path: &Path) -> Result<f64> {
+    let reader = hound::WavReader::open(path)?;
+    Ok(reader.duration() as f64 / reader.spec().sample_rate.max(1) as f64)
+}
+
+impl Sample {
+    /// Load a WAV file, averaging all channels into one
+    pub fn load(path: &Path) -> Result<Self> {
+        let (sample_rate, channels, samples) = read_wav(path)?;
+        let data = samples.chunks(channels)
+            .map(|frame| frame.iter().sum::<f64>() / channels as f64)
+            .collect();
+        Ok(Self { sample_rate, data })
+    }
+
+    /// Load a WAV file's first two channels as left and right. A mono file plays in both.
+    pub fn load_stereo(path: &Path) -> Result<(Self, Self)> {
+        let (sample_rate, channels, samples) = read_wav(path)?;
+        let channel = |index: usize| Self {
+            sample_rate,
+            data: samples.chunks(channels).map(|frame| frame[index.min(channels - 1)]).collect(),
+        };
+        Ok((channel(0), channel(1)))
+    }
+
+    /// Convert the sample to `sample_rate` with a Kaiser-windowed sinc filter, so samples
+    /// recorded at another rate play without aliasing or the dulling of linear interpolation.
+    /// The filter passes up to 90% of the lower rate's Nyquist frequency.
+    pub fn resampled(&self, sample_rate: u32) -> Self {
+        if sample_rate == self.sample_rate || self.data.is_empty() {
+            return Self { sample_rate, ..self.clone() };
+        }
+        const HALF_TAPS: f64 = 32.0;
+        const BETA: f64 = 8.0;
+        let step = self.sample_rate as f64 / sample_rate as f64;
+        // When lowering the rate, the cutoff drops with it and the filter widens to match
+        let cutoff = 0.9 * step.recip().min(1.0);
+        let radius = HALF_TAPS / cutoff;
+        let window_scale = bessel_i0(BETA).recip();
+        let kernel = |distance: f64| {
+            let x = distance / radius;
+            if x.abs() >= 1.0 {
+                return 0.0;
+            }
+            let sinc = match distance * cutoff {
+                0.0 => 1.0,
+                t => (std::f64::consts::PI * t).sin() / (std::f64::consts::PI * t),
+            };
+            cutoff * sinc * bessel_i0(BETA * (1.0 - x * x).sqrt()) * window_scale
+        };
+
+        let length = (self.data.len() as f64 / step).round() as usize;
+        let data = (0..length)
+            .map(|frame| {
+                let position = frame as f64 * step;
+                let first = (position - radius).ceil().max(0.0) as usize;
+                let last = ((position + radius).floor() as usize).min(self.data.len() - 1);
+                (first..=last).map(|index| self.data[index] * kernel(position - index as f64)).sum()
+            })
+            .collect();
+        Self { sample_rate, data }
+    }
+
+    /// Value at a fractional position, linearly interpolated. Silent past the end.
+    pub fn value_at(&self, position: f64) -> f64 {
+        let index = position as usize;
+        let Some(&current) = self.data.get(index) else {
+            return 0.0;
+        };
+        let next = self.data.get(index + 1).copied().unwrap_or(0.0);
+        current + (next - current) * position.fract()
+    }
+
+    /// Mix the sample into `output`, sped up by `pitch_ratio`. One-shot playback stops at
+    /// the end of the sample; looped playback repeats the loop region until the output is full.
+    pub fn play(&self, pitch_ratio: f64, output_rate: u32, output: &mut [f64], loop_region: Option<LoopRegion>) {
+        self.play_held(pitch_ratio, output_rate, output, loop_region, output.len());
+    }
+
+    /// Like `play`, but the loop only repeats for the first `held` frames of output. After
+    /// that the sample plays on through the loop end to its own end, as a sustain loop does.
+    pub fn play_held(&self, pitch_ratio: f64, output_rate: u32, output: &mut [f64], loop_region: Option<LoopRegion>, held: usize) {
+        let step = pitch_ratio * self.sample_rate as f64 / output_rate as f64;
+        let mut position = 0.0;
+        for (i, out) in output.iter_mut().enumerate() {
+            match loop_region {
+                Some(region) if i < held => {
+                    while position >= region.end {
+                        position -= region.end - region.start;
+                    }
+                    *out += self.looped_value_at(position, &region);
+                }
+                _ if position >= self.data.len() as f64 => break,
+                _ => *out += self.value_at(position),
+            }
+            position += step;
+        }
+    }
+
+    /// Value inside a loop, blending the loop end with the audio before the loop start
+    fn looped_value_at(&self, position: f64, region: &LoopRegion) -> f64 {
+        let fade_start = region.end - region.crossfade;
+        if region.crossfade <= 0.0 || position < fade_start {
+            return self.value_at(position);
+        }
+        let amount = (position - fade_start) / region.crossfade;
+        let wrapped = position - (region.end - region.start);
+        self.value_at(position) * (1.0 - amount) + self.value_at(wrapped) * amount
+    }
+}
+
+/// Modified Bessel function of the first kind, order zero, for the Kaiser window
+pub(crate) fn bessel_i0(x: f64) -> f64 {
+    let mut term = 1.0;
+    let mut sum = 1.0;
+    for k in 1..50 {
+        term *= (x / (2.0 * k as f64)).powi(2);
+        sum += term;
+        if term < sum * 1e-12 {
+            break;
+        }
+    }
+    sum
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_play_repitches_and_resamples() {
+        let sample = Sample { sample_rate: 2, data: vec![0.0, 1.0, 2.0, 3.0] };
+
+        // Same rate and pitch copies the data, then stops
+        let mut output = vec![0.0; 6];
+        sample.play(1.0, 2, &mut output, None);
+        assert_eq!(output, vec![0.0, 1.0, 2.0, 3.0, 0.0, 0.0]);
+
+        // An octave up skips every other frame
+        let mut output = vec![0.0; 3];
+        sample.play(2.0, 2, &mut output, None);
+        assert_eq!(output, vec![0.0, 2.0, 0.0]);
+
+        // Playing at twice the output rate interpolates between frames
+        let mut output = vec![0.0; 4];
+        sample.play(1.0, 4, &mut output, None);
+        assert_eq!(output, vec![0.0, 0.5, 1.0, 1.5]);
+    }
+
+    #[test]
+    fn test_resampled() {
+        let sine = |frequency: f64, rate: u32, seconds: f64| Sample {
+            sample_rate: rate,
+            data: (0..(seconds * rate as f64) as usize)
+                .map(|i| (std::f64::consts::TAU * frequency * i as f64 / rate as f64).sin())
+                .collect(),
+        };
+        let tone = sine(1000.0, 48000, 0.5);
+        assert_eq!(tone.resampled(48000), tone);
+
+        // Away from the edges, a 48 kHz tone converts to the same tone at 44.1 kHz
+        let converted = tone.resampled(44100);
+        let expected = sine(1000.0, 44100, 0.5);
+        assert_eq!(converted.sample_rate, 44100);
+        assert_eq!(converted.data.len(), expected.data.len());
+        let error = converted.data[100..22000].iter().zip(&expected.data[100..22000])
+            .map(|(a, b)| (a - b).abs())
+            .fold(0.0, f64::max);
+        assert!(error < 1e-3, "{}", error);
+
+        // Tones above the new Nyquist frequency are filtered out rather than folding back
+        let aliased = sine(15000.0, 48000, 0.5).resampled(22050);
+        assert!(aliased.data[200..10800].iter().all(|s| s.abs() < 1e-3));
+    }
+
+    #[test]
+    fn test_loop_and_crossfade() {
+        let sample = Sample { sample_rate: 1, data: vec![0.0, 1.0, 2.0, 3.0, 4.0, 5.0] };
+
+        let hard = LoopRegion { start: 2.0, end: 5.0, crossfade: 0.0 };
+        let mut output = vec![0.0; 10];
+        sample.play(1.0, 1, &mut output, Some(hard));
+        assert_eq!(output, vec![0.0, 1.0, 2.0, 3.0, 4.0, 2.0, 3.0, 4.0, 2.0, 3.0]);
+
+        // Over the last two frames the loop end fades toward the frames before the loop start
+        let faded = LoopRegion { start: 2.0, end: 5.0, crossfade: 2.0 };
+        let mut output = vec![0.0; 6];
+        sample.play(1.0, 1, &mut output, Some(faded));
+        assert_eq!(output, vec![0.0, 1.0, 2.0, 3.0, 2.5, 2.0]);
+
+        // A sustain loop lets go when the note is released and plays out the rest
+        let mut output = vec![0.0; 10];
+        sample.play_held(1.0, 1, &mut output, Some(hard), 6);
+        assert_eq!(output, vec![0.0, 1.0, 2.0, 3.0, 4.0, 2.0, 3.0, 4.0, 5.0, 0.0]);
+    }
+
+    #[test]
+    fn test_loop_region_for_sampler() {
+        let sample = Sample { sample_rate: 10, data: vec![0.0; 20] };
+        let mut params = SamplerParams::new("pad.wav".into());
+        assert_eq!(LoopRegion::for_sampler(&params, &sample), None);
+
+        params.looping = true;
+        assert_eq!(LoopRegion::for_sampler(&params, &sample), Some(LoopRegion { start: 0.0, end: 20.0, crossfade: 0.0 }));
+
+        params.loop_start = Some(0.5);
+        params.loop_end = Some(1.5);
+        params.loop_crossfade = 1.0;
+        assert_eq!(LoopRegion::for_sampler(&params, &sample), Some(LoopRegion { start: 5.0, end: 15.0, crossfade: 5.0 }));
+    }
+
+    #[test]
+    fn test_load_mixes_to_mono() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("stereo.wav");
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 22050,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        writer.write_sample(16384_i16).unwrap();
+        writer.write_sample(0_i16).unwrap();
+        writer.finalize().unwrap();
+
+        let sample = Sample::load(&path).unwrap();
+        assert_eq!(sample.sample_rate, 22050);
+        assert_eq!(sample.data, vec![0.25]);
+    }
+}
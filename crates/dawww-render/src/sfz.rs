@@ -0,0 +1,168 @@
+use anyhow::Result;
+use dawww_core::sfz::{Sfz, SfzLoopMode, SfzRegion};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use crate::sampler::{LoopRegion, Sample};
+use crate::synth::{Envelope, DECLICK_SECONDS};
+
+/// Plays an SFZ instrument, loading each sample the first time a note needs it
+pub struct SfzPlayer {
+    sfz: Sfz,
+    /// Directory the SFZ file's sample paths are relative to
+    dir: PathBuf,
+    sample_rate: u32,
+    /// Samples converted to the output rate, with the rate they were recorded at. `None`
+    /// for samples that can't be read; their regions are silent.
+    samples: HashMap<String, Option<(Sample, u32)>>,
+}
+
+impl SfzPlayer {
+    pub fn load(path: &Path, sample_rate: u32) -> Result<Self> {
+        Ok(Self {
+            sfz: Sfz::load(path)?,
+            dir: path.parent().map(Path::to_path_buf).unwrap_or_default(),
+            sample_rate,
+            samples: HashMap::new(),
+        })
+    }
+
+    /// Longest release of any region, in seconds
+    pub fn release_seconds(sfz: &Sfz) -> f64 {
+        sfz.regions.iter().map(release).fold(0.0, f64::max)
+    }
+
+    /// How many output frames a note held for `gate` frames sounds for. Notes ring on
+    /// through their release, and one-shot regions play to the end of their sample.
+    /// `pitch_scale` multiplies the regions' own repitching, e.g. for the instrument's tuning.
+    pub fn note_length(&mut self, key: u8, velocity: u8, pitch_scale: f64, gate: usize) -> usize {
+        let rate = self.sample_rate as f64;
+        self.layers(key, velocity)
+            .map(|(region, sample, _)| match region.loop_mode {
+                SfzLoopMode::OneShot => {
+                    (sample.data.len() as f64 / (region.pitch_ratio(key) * pitch_scale)).ceil() as usize
+                }
+                _ => gate + (release(region) * rate).ceil() as usize,
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Mix a note held for `gate` frames into `output`: every region matching its key and
+    /// velocity, each under its own envelope and volume
+    pub fn render(&mut self, key: u8, velocity: u8, pitch_scale: f64, output: &mut [f64], gate: usize) {
+        let sample_rate = self.sample_rate;
+        let rate = sample_rate as f64;
+        for (region, sample, recorded_rate) in self.layers(key, velocity) {
+            // One-shots ignore the note's end
+            let gate = if region.loop_mode == SfzLoopMode::OneShot { output.len() } else { gate };
+            // Loop points count frames at the recorded rate
+            let frames = rate / recorded_rate as f64;
+            let loop_region = matches!(region.loop_mode, SfzLoopMode::Continuous | SfzLoopMode::Sustain)
+                .then(|| LoopRegion {
+                    start: region.loop_start.map_or(0.0, |start| start as f64 * frames),
+                    end: region.loop_end.map_or(sample.data.len() as f64, |end| (end + 1) as f64 * frames)
+                        .min(sample.data.len() as f64),
+                    crossfade: 0.0,
+                })
+                .filter(|loop_region| loop_region.end > loop_region.start);
+
+            let mut layer = vec![0.0; output.len()];
+            let pitch_ratio = region.pitch_ratio(key) * pitch_scale;
+            match region.loop_mode {
+                SfzLoopMode::Sustain => sample.play_held(pitch_ratio, sample_rate, &mut layer, loop_region, gate),
+                _ => sample.play(pitch_ratio, sample_rate, &mut layer, loop_region),
+            }
+
+            let envelope = Envelope {
+                attack: region.ampeg_attack,
+                decay: region.ampeg_decay,
+                sustain: region.ampeg_sustain,
+                release: release(region),
+            };
+            let gain = region.gain();
+            let gate = gate as f64 / rate;
+            for (i, (out, value)) in output.iter_mut().zip(&layer).enumerate() {
+                *out += value * gain * envelope.level(i as f64 / rate, gate);
+            }
+        }
+    }
+
+    /// The regions playing for a key and velocity whose samples could be read, loading any
+    /// that haven't been yet
+    fn layers(&mut self, key: u8, velocity: u8) -> impl Iterator<Item = (&SfzRegion, &Sample, u32)> {
+        for region in self.sfz.regions_for(key, velocity) {
+            if !self.samples.contains_key(&region.sample) {
+                let sample = Sample::load(&self.dir.join(&region.sample)).ok()
+                    .map(|sample| (sample.resampled(self.sample_rate), sample.sample_rate));
+                self.samples.insert(region.sample.clone(), sample);
+            }
+        }
+        let samples = &self.samples;
+        self.sfz.regions_for(key, velocity).filter_map(move |region| {
+            let (sample, recorded_rate) = samples.get(&region.sample)?.as_ref()?;
+            Some((region, sample, *recorded_rate))
+        })
+    }
+}
+
+/// A region's release, long enough not to click when the note is let go
+fn release(region: &SfzRegion) -> f64 {
+    region.ampeg_release.max(DECLICK_SECONDS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn write_sample(path: &Path, sample_rate: u32, frames: usize) {
+        let spec = hound::WavSpec { channels: 1, sample_rate, bits_per_sample: 16, sample_format: hound::SampleFormat::Int };
+        let mut writer = hound::WavWriter::create(path, spec).unwrap();
+        for _ in 0..frames {
+            writer.write_sample(16384_i16).unwrap();
+        }
+        writer.finalize().unwrap();
+    }
+
+    #[test]
+    fn test_render_layers_and_loops() {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::create_dir(temp_dir.path().join("samples")).unwrap();
+        write_sample(&temp_dir.path().join("samples/soft.wav"), 1000, 100);
+        write_sample(&temp_dir.path().join("samples/hit.wav"), 2000, 400);
+        let path = temp_dir.path().join("kit.sfz");
+        std::fs::write(&path, "
+            <control> default_path=samples/
+            <group> lokey=48 hikey=72
+            <region> sample=soft.wav hivel=63 loop_mode=loop_continuous loop_start=10 loop_end=49
+            <region> sample=soft.wav lovel=64 volume=-6.0206
+            <group>
+            <region> sample=hit.wav key=36 loop_mode=one_shot
+            <region> sample=missing.wav key=37
+        ").unwrap();
+        let mut player = SfzPlayer::load(&path, 1000).unwrap();
+
+        // The looped layer keeps playing its 100 frames past the end of the sample
+        let mut soft = vec![0.0; 300];
+        assert_eq!(player.note_length(60, 40, 1.0, 290), 293);
+        player.render(60, 40, 1.0, &mut soft, 290);
+        assert!(soft[150..280].iter().all(|s| (s - 0.5).abs() < 0.01));
+        assert_eq!(*soft.last().unwrap(), 0.0);
+
+        // The louder layer plays once, 6 dB down
+        let mut loud = vec![0.0; 300];
+        player.render(60, 100, 1.0, &mut loud, 290);
+        assert!((loud[50] - 0.25).abs() < 1e-3);
+        assert!(loud[150..].iter().all(|&s| s == 0.0));
+
+        // A one-shot plays to its end however short the note, at the output rate
+        assert_eq!(player.note_length(36, 100, 1.0, 10), 200);
+        let mut hit = vec![0.0; 200];
+        player.render(36, 100, 1.0, &mut hit, 10);
+        assert!((hit[150] - 0.5).abs() < 0.01);
+
+        // Regions whose sample is missing, and keys no region covers, are silent
+        assert_eq!(player.note_length(37, 100, 1.0, 10), 0);
+        assert_eq!(player.note_length(90, 100, 1.0, 10), 0);
+    }
+}
@@ -0,0 +1,162 @@
+use dawww_core::{ChannelLayout, Speakers};
+use std::collections::BTreeMap;
+use std::f64::consts::FRAC_1_SQRT_2;
+use std::ops::Range;
+use crate::mix_into;
+use crate::stereo::Stereo;
+
+/// Level of speakers a layout lacks, or a surround file's extra channels, when folded into
+/// the front pair: -3 dB
+const FOLD_GAIN: f64 = FRAC_1_SQRT_2;
+
+/// One signal per channel of `layout`, in file order, from the signals assigned to each
+/// group of speakers. Groups the layout has no speakers for are folded into the front pair,
+/// and mono takes the average of the pair. The center and LFE play in mono.
+pub fn speaker_feeds(groups: &BTreeMap<Speakers, Stereo>, layout: ChannelLayout, len: usize) -> Vec<Vec<f64>> {
+    let mut front = Stereo::silent(len);
+    let mut rear = Stereo::silent(len);
+    let mut center = vec![0.0; len];
+    let mut lfe = vec![0.0; len];
+    for (speakers, signal) in groups {
+        match (speakers, layout) {
+            (Speakers::Front, _) => front.mix_in(signal, 1.0),
+            (Speakers::Rear, ChannelLayout::Quad | ChannelLayout::Surround51) => rear.mix_in(signal, 1.0),
+            (Speakers::Center, ChannelLayout::Surround51) => mix_into(&mut center, &signal.to_mono(), 1.0),
+            (Speakers::Lfe, ChannelLayout::Surround51) => mix_into(&mut lfe, &signal.to_mono(), 1.0),
+            _ => front.mix_in(signal, FOLD_GAIN),
+        }
+    }
+    match layout {
+        ChannelLayout::Mono => vec![front.to_mono()],
+        ChannelLayout::Stereo => vec![front.left, front.right],
+        ChannelLayout::Quad => vec![front.left, front.right, rear.left, rear.right],
+        ChannelLayout::Surround51 => vec![front.left, front.right, center, lfe, rear.left, rear.right],
+    }
+}
+
+/// A stereo signal in `layout`'s channels, on the front pair
+pub fn from_stereo(buffer: &Stereo, layout: ChannelLayout) -> Vec<Vec<f64>> {
+    speaker_feeds(&BTreeMap::from([(Speakers::Front, buffer.clone())]), layout, buffer.len())
+}
+
+/// Fold a signal's channels, in file order, down to stereo: mono plays in both channels,
+/// and the center, LFE and rear pair join the front pair at -3 dB
+pub fn to_stereo(feeds: &[Vec<f64>]) -> Stereo {
+    match feeds {
+        [mono] => Stereo::from_mono(mono.clone()),
+        [left, right, rest @ ..] => {
+            let mut stereo = Stereo { left: left.clone(), right: right.clone() };
+            if let [middle @ .., rear_left, rear_right] = rest {
+                for channel in middle {
+                    stereo.mix_in(&Stereo { left: channel.clone(), right: channel.clone() }, FOLD_GAIN);
+                }
+                mix_into(&mut stereo.left, rear_left, FOLD_GAIN);
+                mix_into(&mut stereo.right, rear_right, FOLD_GAIN);
+            }
+            stereo
+        }
+        [] => Stereo::default(),
+    }
+}
+
+/// How much each of `layout`'s channels counts toward its loudness, following BS.1770: the
+/// rear pair counts 1.5 dB louder and the LFE not at all. Mono counts twice, as if it
+/// played from both speakers of a pair.
+pub fn loudness_weights(layout: ChannelLayout) -> &'static [f64] {
+    match layout {
+        ChannelLayout::Mono => &[2.0],
+        ChannelLayout::Stereo => &[1.0, 1.0],
+        ChannelLayout::Quad => &[1.0, 1.0, 1.41, 1.41],
+        ChannelLayout::Surround51 => &[1.0, 1.0, 1.0, 0.0, 1.41, 1.41],
+    }
+}
+
+/// Largest absolute sample in any channel
+pub fn peak(feeds: &[Vec<f64>]) -> f64 {
+    feeds.iter().flatten().fold(0.0_f64, |peak, s| peak.max(s.abs()))
+}
+
+/// Frames from the first to the last where any channel reaches `threshold`, or `None` if
+/// none do
+pub fn audible_range(feeds: &[Vec<f64>], threshold: f64) -> Option<Range<usize>> {
+    let len = feeds.first().map_or(0, Vec::len);
+    let audible = |&i: &usize| feeds.iter().any(|channel| channel[i].abs() >= threshold);
+    let start = (0..len).find(audible)?;
+    let end = (0..len).rfind(audible)? + 1;
+    Some(start..end)
+}
+
+/// Keep only the frames in `range`
+pub fn trim(feeds: &mut [Vec<f64>], range: Range<usize>) {
+    for channel in feeds {
+        channel.truncate(range.end);
+        channel.drain(..range.start.min(channel.len()));
+    }
+}
+
+/// Ramp the first `fade_in` frames up from silence and the last `fade_out` frames down to
+/// it, in straight lines
+pub fn fade(feeds: &mut [Vec<f64>], fade_in: usize, fade_out: usize) {
+    let len = feeds.first().map_or(0, Vec::len);
+    fade_part(feeds, 0, len, fade_in, fade_out);
+}
+
+/// Apply `fade` to the part of a signal `len` frames long that starts at frame `offset`
+pub fn fade_part(feeds: &mut [Vec<f64>], offset: usize, len: usize, fade_in: usize, fade_out: usize) {
+    for channel in feeds {
+        for (frame, s) in (offset..len).zip(channel.iter_mut()) {
+            if frame < fade_in {
+                *s *= frame as f64 / fade_in as f64;
+            }
+            let from_end = len - 1 - frame;
+            if from_end < fade_out {
+                *s *= from_end as f64 / fade_out as f64;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_speaker_feeds_fold_missing_speakers() {
+        let groups = BTreeMap::from([
+            (Speakers::Front, Stereo { left: vec![1.0], right: vec![0.5] }),
+            (Speakers::Rear, Stereo { left: vec![0.0], right: vec![1.0] }),
+            (Speakers::Lfe, Stereo { left: vec![0.5], right: vec![0.5] }),
+        ]);
+        let surround = speaker_feeds(&groups, ChannelLayout::Surround51, 1);
+        assert_eq!(surround, [[1.0], [0.5], [0.0], [0.5], [0.0], [1.0]]);
+
+        let quad = speaker_feeds(&groups, ChannelLayout::Quad, 1);
+        assert!((quad[0][0] - (1.0 + 0.5 * FOLD_GAIN)).abs() < 1e-12);
+        assert_eq!(&quad[2..], [[0.0], [1.0]]);
+
+        let stereo = speaker_feeds(&groups, ChannelLayout::Stereo, 1);
+        assert!((stereo[1][0] - (0.5 + 1.5 * FOLD_GAIN)).abs() < 1e-12);
+        assert_eq!(speaker_feeds(&groups, ChannelLayout::Mono, 1)[0][0], (stereo[0][0] + stereo[1][0]) / 2.0);
+
+        // Folding the surround channels to stereo lands where a stereo render would
+        let folded = to_stereo(&surround);
+        assert!((folded.left[0] - stereo[0][0]).abs() < 1e-12);
+        assert!((folded.right[0] - stereo[1][0]).abs() < 1e-12);
+        assert_eq!(from_stereo(&folded, ChannelLayout::Quad)[2..], [[0.0], [0.0]]);
+    }
+
+    #[test]
+    fn test_trim_and_fade() {
+        let mut feeds = vec![vec![0.0, 0.001, 0.5, 1.0, 0.5, 0.0], vec![0.0, 0.0, 0.0, 0.0, 0.0, -0.2]];
+        assert_eq!(audible_range(&feeds, 0.1), Some(2..6));
+        assert_eq!(audible_range(&feeds, 2.0), None);
+        trim(&mut feeds, 2..6);
+        assert_eq!(feeds, [vec![0.5, 1.0, 0.5, 0.0], vec![0.0, 0.0, 0.0, -0.2]]);
+
+        let mut feeds = vec![vec![1.0; 8]; 3];
+        fade(&mut feeds, 4, 2);
+        assert_eq!(feeds[0], [0.0, 0.25, 0.5, 0.75, 1.0, 1.0, 0.5, 0.0]);
+        assert!(feeds.iter().all(|channel| *channel == feeds[0]));
+        assert_eq!(peak(&feeds), 1.0);
+    }
+}
@@ -0,0 +1,105 @@
+use std::f64::consts::{PI, SQRT_2};
+
+/// Left and right channels of the same length
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Stereo {
+    pub left: Vec<f64>,
+    pub right: Vec<f64>,
+}
+
+impl Stereo {
+    pub fn silent(len: usize) -> Self {
+        Self { left: vec![0.0; len], right: vec![0.0; len] }
+    }
+
+    /// The same signal in both channels
+    pub fn from_mono(mono: Vec<f64>) -> Self {
+        Self { left: mono.clone(), right: mono }
+    }
+
+    pub fn len(&self) -> usize {
+        self.left.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.left.is_empty()
+    }
+
+    /// Both channels, for processing each the same way
+    pub fn channels_mut(&mut self) -> [&mut Vec<f64>; 2] {
+        [&mut self.left, &mut self.right]
+    }
+
+    /// Mix in another stereo signal, scaled by `gain`
+    pub fn mix_in(&mut self, input: &Stereo, gain: f64) {
+        crate::mix_into(&mut self.left, &input.left, gain);
+        crate::mix_into(&mut self.right, &input.right, gain);
+    }
+
+    /// Mix a mono signal in starting at `offset`, placed at `pan`
+    pub fn mix_panned(&mut self, offset: usize, input: &[f64], pan: f64) {
+        let (left, right) = pan_gains(pan);
+        crate::mix_into(&mut self.left[offset..], input, left);
+        crate::mix_into(&mut self.right[offset..], input, right);
+    }
+
+    /// Both channels averaged, so centred audio keeps its level
+    pub fn to_mono(&self) -> Vec<f64> {
+        self.left.iter().zip(&self.right).map(|(l, r)| (l + r) / 2.0).collect()
+    }
+
+    /// Largest absolute sample in either channel
+    pub fn peak(&self) -> f64 {
+        self.left.iter().chain(&self.right).fold(0.0_f64, |peak, s| peak.max(s.abs()))
+    }
+}
+
+/// Left and right gains placing a mono signal at `pan`, from -1 (left) to 1 (right). The
+/// law is constant power: the centre is 3 dB down in each channel and a hard pan is at unity
+/// in one.
+pub fn pan_gains(pan: f64) -> (f64, f64) {
+    let angle = (pan.clamp(-1.0, 1.0) + 1.0) * PI / 4.0;
+    (angle.cos(), angle.sin())
+}
+
+/// Left and right gains moving a stereo signal towards `pan`: the far channel fades out along
+/// the constant-power curve and the near one stays at unity, so the centre leaves it as it is
+pub fn balance_gains(pan: f64) -> (f64, f64) {
+    let (left, right) = pan_gains(pan);
+    ((SQRT_2 * left).min(1.0), (SQRT_2 * right).min(1.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::FRAC_1_SQRT_2;
+
+    #[test]
+    fn test_pan_gains_keep_constant_power() {
+        let (left, right) = pan_gains(0.0);
+        assert!((left - FRAC_1_SQRT_2).abs() < 1e-12 && (right - FRAC_1_SQRT_2).abs() < 1e-12);
+        let (left, right) = pan_gains(-1.0);
+        assert!((left - 1.0).abs() < 1e-12 && right.abs() < 1e-12);
+        for pan in [-0.7, -0.2, 0.4, 1.0] {
+            let (left, right) = pan_gains(pan);
+            assert!((left * left + right * right - 1.0).abs() < 1e-12);
+        }
+        assert!(pan_gains(0.5).1 > pan_gains(0.5).0);
+
+        // Balancing a stereo signal leaves the centre alone and never boosts
+        assert_eq!(balance_gains(0.0), (1.0, 1.0));
+        assert_eq!(balance_gains(0.5).1, 1.0);
+        assert!(balance_gains(0.5).0 < 1.0 && balance_gains(1.0).0.abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_mix_panned() {
+        let mut stereo = Stereo::silent(4);
+        stereo.mix_panned(1, &[1.0, 1.0], 1.0);
+        stereo.mix_panned(2, &[0.5, 0.5], 0.0);
+        assert!(stereo.left.iter().all(|s| s.abs() < 0.5 + 1e-12));
+        assert!((stereo.right[1] - 1.0).abs() < 1e-12);
+        assert!((stereo.right[3] - 0.5 * FRAC_1_SQRT_2).abs() < 1e-12);
+        assert!((stereo.left[3] - stereo.right[3]).abs() < 1e-12);
+    }
+}
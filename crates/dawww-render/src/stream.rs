@@ -0,0 +1,218 @@
+use anyhow::Result;
+use dawww_core::controllers::{ControlCurve, Controller};
+use dawww_core::{ClickMode, Note};
+use rayon::prelude::*;
+use std::collections::{BTreeMap, HashMap};
+use crate::effects::Chain;
+use crate::note::NoteRenderer;
+use crate::stereo::Stereo;
+use crate::{speakers, with_doublings, AudioEngine, BusChains, NoteKey, SongNote};
+
+/// The song rendered a block at a time for `AudioEngine::process`. Each block renders the
+/// notes that start in it and runs the tracks' inserts and the mixer over just that block;
+/// the rest of each note, and every effect's state, carries over to the blocks after.
+/// Distortion isn't oversampled, since its filters would need audio from the next block.
+pub(crate) struct Stream {
+    /// Frames in the song
+    len: usize,
+    /// Frame the next block starts at
+    position: usize,
+    seconds_per_32nd_note: f64,
+    /// Where voice stealing cuts notes off
+    voice_limits: HashMap<NoteKey, usize>,
+    tracks: Vec<TrackStream>,
+    chains: BusChains,
+}
+
+/// One audible track of a stream
+struct TrackStream {
+    id: String,
+    /// The track's notes in the order they start
+    notes: Vec<StreamNote>,
+    /// The first note not started yet
+    next: usize,
+    renderer: NoteRenderer,
+    bend: ControlCurve,
+    /// The sound of the notes already started, from the stream's position on
+    pending: Stereo,
+    inserts: Chain,
+    /// Audio played in place of the notes, for frozen tracks
+    frozen: Option<Stereo>,
+}
+
+struct StreamNote {
+    key: NoteKey,
+    position: u32,
+    note: Note,
+    doubling: usize,
+    /// Frame the note starts at
+    start: usize,
+    /// Frame its release has died away by
+    silent_at: usize,
+}
+
+impl Stream {
+    /// Check that `engine`'s song can be played and set up its tracks, ready to play from
+    /// the start
+    pub(crate) fn new(engine: &AudioEngine) -> Result<Self> {
+        let seconds_per_32nd_note = 60.0 / (engine.daw_file.bpm as f64 * 8.0);
+        let sample_rate = engine.sample_rate() as f64;
+        let len = (engine.calculate_total_duration(seconds_per_32nd_note) * sample_rate) as usize;
+        engine.mixdown().validate()?;
+        let mixer = &engine.daw_file.mixer;
+        mixer.validate()?;
+
+        let frozen = engine.frozen_tracks(|id| mixer.is_audible(id), len);
+        engine.check_samples(|id| mixer.is_audible(id) && !frozen.contains_key(id))?;
+        let mut notes: BTreeMap<&str, Vec<StreamNote>> = BTreeMap::new();
+        let mut releases = HashMap::new();
+        for (key, ((position, instrument_id, note), doubling)) in with_doublings(engine.events.notes()).enumerate() {
+            if !mixer.is_audible(instrument_id) || frozen.contains_key(instrument_id) {
+                continue;
+            }
+            let start = engine.seconds_at(instrument_id, position, seconds_per_32nd_note);
+            let end = engine.seconds_at(instrument_id, position + note.duration, seconds_per_32nd_note);
+            let sfz_release = *releases.entry(instrument_id).or_insert_with(|| engine.sfz_release_seconds(instrument_id));
+            let silent_at = engine.sounding_until(instrument_id, start, end) + sfz_release;
+            notes.entry(instrument_id).or_default().push(StreamNote {
+                key,
+                position,
+                note: note.clone(),
+                doubling,
+                start: (start * sample_rate) as usize,
+                silent_at: (silent_at * sample_rate).ceil() as usize,
+            });
+        }
+
+        let track = |id: &str, notes: Vec<StreamNote>, frozen: Option<Stereo>| TrackStream {
+            id: id.to_string(),
+            notes,
+            next: 0,
+            renderer: engine.note_renderer(),
+            bend: engine.daw_file.control_curve(id, Controller::PitchBend),
+            pending: Stereo::default(),
+            inserts: Chain::new(&[], engine.sample_rate(), engine.daw_file.bpm as f64, 1),
+            frozen,
+        };
+        let mut tracks: Vec<TrackStream> = notes.into_iter()
+            .map(|(id, mut notes)| {
+                notes.sort_by_key(|note| note.start);
+                track(id, notes, None)
+            })
+            .collect();
+        tracks.extend(frozen.into_iter().map(|(id, audio)| track(id, Vec::new(), Some(audio))));
+
+        let mut stream = Self {
+            len,
+            position: 0,
+            seconds_per_32nd_note,
+            voice_limits: engine.voice_limited_ends(seconds_per_32nd_note),
+            tracks,
+            chains: engine.bus_chains(1),
+        };
+        stream.seek(engine, 0);
+        Ok(stream)
+    }
+
+    /// Frames in the song
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Render `frames` frames of the song from `position`, folded down to stereo. Playing on
+    /// from the last block carries every note and effect over; anywhere else starts afresh
+    /// from `position`, with the notes still sounding there but without effect tails.
+    pub(crate) fn render(&mut self, engine: &AudioEngine, position: usize, frames: usize) -> Result<Stereo> {
+        if position != self.position {
+            self.seek(engine, position);
+        }
+        let end = position + frames;
+        let seconds_per_32nd_note = self.seconds_per_32nd_note;
+        let voice_limits = &self.voice_limits;
+        let len = self.len;
+        let blocks: Vec<Stereo> = engine.in_pool(|| {
+            self.tracks.par_iter_mut()
+                .map(|track| {
+                    while let Some(key) = track.notes.get(track.next).filter(|note| note.start < end).map(|note| note.key) {
+                        let limit = voice_limits.get(&key).map_or(len, |&limit| limit.min(len));
+                        track.start_note(engine, track.next, position, limit, seconds_per_32nd_note);
+                        track.next += 1;
+                    }
+                    track.block(frames, position)
+                })
+                .collect()
+        });
+        self.position = end;
+
+        let tracks: BTreeMap<&str, Stereo> = self.tracks.iter().map(|track| track.id.as_str()).zip(blocks).collect();
+        let inputs = engine.master_inputs(&tracks, frames, seconds_per_32nd_note, position, &mut self.chains)?;
+        let mut feeds = engine.master_mix(&inputs, frames, &mut self.chains);
+        let mixdown = engine.mixdown();
+        if let Some(click) = mixdown.click.as_ref().filter(|click| click.mode == ClickMode::Mix) {
+            let click = engine.click_track(click, position..end, seconds_per_32nd_note);
+            for channel in feeds.iter_mut().take(2) {
+                crate::mix_into(channel, &click, 1.0);
+            }
+        }
+        let fade = |seconds: Option<f64>| (seconds.unwrap_or(0.0) * engine.sample_rate() as f64) as usize;
+        speakers::fade_part(&mut feeds, position, self.len, fade(mixdown.fade_in), fade(mixdown.fade_out));
+        Ok(speakers::to_stereo(&feeds))
+    }
+
+    /// Get ready to play from `position`: start the notes still sounding there, and clear
+    /// every effect
+    fn seek(&mut self, engine: &AudioEngine, position: usize) {
+        self.position = position;
+        self.chains = engine.bus_chains(1);
+        let len = self.len;
+        for track in &mut self.tracks {
+            track.pending = Stereo::default();
+            // Frozen audio already has the track's inserts on it
+            let instrument = engine.daw_file.get_instrument(&track.id).filter(|_| track.frozen.is_none());
+            let effects = instrument.map_or(&[][..], |instrument| &instrument.effects);
+            track.inserts = Chain::new(effects, engine.sample_rate(), engine.daw_file.bpm as f64, 1);
+            track.next = track.notes.partition_point(|note| note.start < position);
+            for index in 0..track.next {
+                if track.notes[index].silent_at > position {
+                    let limit = self.voice_limits.get(&track.notes[index].key).map_or(len, |&limit| limit.min(len));
+                    track.start_note(engine, index, position, limit, self.seconds_per_32nd_note);
+                }
+            }
+        }
+    }
+}
+
+impl TrackStream {
+    /// Render note `index` and add whatever of it comes after `position` to the pending
+    /// sound
+    fn start_note(&mut self, engine: &AudioEngine, index: usize, position: usize, limit: usize, seconds_per_32nd_note: f64) {
+        let StreamNote { position: note_position, ref note, doubling, .. } = self.notes[index];
+        let song_note = SongNote { position: note_position, instrument_id: &self.id, note, doubling };
+        let (start, output) = engine.render_note(&mut self.renderer, song_note, limit, &self.bend, seconds_per_32nd_note);
+        let output = &output[position.saturating_sub(start).min(output.len())..];
+        let offset = start.saturating_sub(position);
+        if self.pending.len() < offset + output.len() {
+            self.pending.left.resize(offset + output.len(), 0.0);
+            self.pending.right.resize(offset + output.len(), 0.0);
+        }
+        self.pending.mix_panned(offset, output, note.pan.unwrap_or(0.0));
+    }
+
+    /// The track's next `frames` frames from `position`, through its inserts
+    fn block(&mut self, frames: usize, position: usize) -> Stereo {
+        let mut block = match &self.frozen {
+            Some(frozen) => Stereo {
+                left: frozen.left[position..position + frames].to_vec(),
+                right: frozen.right[position..position + frames].to_vec(),
+            },
+            None => Stereo::silent(frames),
+        };
+        let pending = frames.min(self.pending.len());
+        for (channel, pending_channel) in block.channels_mut().into_iter().zip(self.pending.channels_mut()) {
+            crate::mix_into(&mut channel[..pending], &pending_channel[..pending], 1.0);
+            pending_channel.drain(..pending);
+        }
+        self.inserts.process(&mut block);
+        block
+    }
+}
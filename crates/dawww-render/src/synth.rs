@@ -0,0 +1,566 @@
+use dawww_core::instrument::{FilterType, Lfo, LfoTarget, NoiseColor, PercussionParams, SamplerParams, SubtractiveParams, Waveform};
+use dawww_core::pitch::Pitch;
+use std::f64::consts::PI;
+
+/// One cycle of `wave` at `phase` (0 to 1), between -1 and 1
+pub fn oscillator(wave: Waveform, phase: f64) -> f64 {
+    let phase = phase.rem_euclid(1.0);
+    match wave {
+        Waveform::Sine => (2.0 * PI * phase).sin(),
+        Waveform::Square => if phase < 0.5 { 1.0 } else { -1.0 },
+        Waveform::Sawtooth => 2.0 * phase - 1.0,
+        Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+    }
+}
+
+/// [`oscillator`] with its discontinuities smoothed by PolyBLEP, so it doesn't alias at
+/// high pitches. `increment` is the phase advanced per sample (frequency / sample rate).
+pub fn band_limited(wave: Waveform, phase: f64, increment: f64) -> f64 {
+    let phase = phase.rem_euclid(1.0);
+    let dt = increment.abs().min(0.5);
+    if dt == 0.0 {
+        return oscillator(wave, phase);
+    }
+    let half = (phase + 0.5).fract();
+    match wave {
+        Waveform::Sine => oscillator(wave, phase),
+        Waveform::Square => oscillator(wave, phase) + poly_blep(phase, dt) - poly_blep(half, dt),
+        Waveform::Sawtooth => oscillator(wave, phase) - poly_blep(phase, dt),
+        Waveform::Triangle => oscillator(wave, phase) + 4.0 * dt * (poly_blamp(phase, dt) - poly_blamp(half, dt)),
+    }
+}
+
+/// Correction for a step of -2 at phase 0
+fn poly_blep(t: f64, dt: f64) -> f64 {
+    if t < dt {
+        let t = t / dt;
+        2.0 * t - t * t - 1.0
+    } else if t > 1.0 - dt {
+        let t = (t - 1.0) / dt;
+        t * t + 2.0 * t + 1.0
+    } else {
+        0.0
+    }
+}
+
+/// Correction for a corner (a step in slope) at phase 0
+fn poly_blamp(t: f64, dt: f64) -> f64 {
+    if t < dt {
+        let t = t / dt - 1.0;
+        -t * t * t / 3.0
+    } else if t > 1.0 - dt {
+        let t = (t - 1.0) / dt + 1.0;
+        t * t * t / 3.0
+    } else {
+        0.0
+    }
+}
+
+/// Shortest fade in and out a note gets, so it doesn't click when it starts or stops
+/// away from a zero crossing
+pub const DECLICK_SECONDS: f64 = 0.003;
+
+/// Fade the first and last few milliseconds of a note rendered without an envelope
+pub fn declick(output: &mut [f64], sample_rate: f64) {
+    let fade = DECLICK_SECONDS * sample_rate;
+    let len = output.len();
+    for (i, out) in output.iter_mut().enumerate() {
+        let edge = i.min(len - 1 - i) as f64;
+        if edge >= fade {
+            continue;
+        }
+        *out *= edge / fade;
+    }
+}
+
+/// Attack, decay, sustain and release, with times in seconds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Envelope {
+    pub attack: f64,
+    pub decay: f64,
+    pub sustain: f64,
+    pub release: f64,
+}
+
+impl Envelope {
+    pub fn from_params(params: &SubtractiveParams) -> Self {
+        Self {
+            attack: params.envelope_attack.max(0.0),
+            decay: params.envelope_decay.max(0.0),
+            sustain: params.envelope_sustain.clamp(0.0, 1.0),
+            release: params.envelope_release.max(0.0),
+        }
+    }
+
+    /// A sampler's fade in and out, at least long enough not to click; the sample plays
+    /// at full level in between
+    pub fn for_sampler(params: &SamplerParams) -> Self {
+        Self {
+            attack: params.envelope_attack.max(DECLICK_SECONDS),
+            decay: 0.0,
+            sustain: 1.0,
+            release: params.envelope_release.max(DECLICK_SECONDS),
+        }
+    }
+
+    /// A percussion hit's attack and decay to silence
+    pub fn for_percussion(params: &PercussionParams) -> Self {
+        Self {
+            attack: params.envelope_attack.max(0.0),
+            decay: params.envelope_decay.max(0.0),
+            sustain: 0.0,
+            release: 0.0,
+        }
+    }
+
+    /// Level `time` seconds into a note that's released after `gate` seconds
+    pub fn level(&self, time: f64, gate: f64) -> f64 {
+        if time < gate {
+            return self.held_level(time);
+        }
+        if self.release == 0.0 {
+            return 0.0;
+        }
+        self.held_level(gate) * (1.0 - (time - gate) / self.release).max(0.0)
+    }
+
+    /// Level while the note is held
+    fn held_level(&self, time: f64) -> f64 {
+        if time < self.attack {
+            time / self.attack
+        } else if time < self.attack + self.decay {
+            1.0 - (1.0 - self.sustain) * (time - self.attack) / self.decay
+        } else {
+            self.sustain
+        }
+    }
+}
+
+/// Resonant state-variable filter (the trapezoidal form, which stays stable as the cutoff
+/// approaches Nyquist)
+#[derive(Debug, Clone)]
+pub struct Filter {
+    kind: FilterType,
+    sample_rate: f64,
+    g: f64,
+    k: f64,
+    ic1: f64,
+    ic2: f64,
+    bypass: bool,
+}
+
+impl Filter {
+    /// `resonance` runs from 0 (no peak) to 1 (close to self-oscillation). A low-pass at or
+    /// above Nyquist passes the signal untouched.
+    pub fn new(kind: FilterType, cutoff: f64, resonance: f64, sample_rate: u32) -> Self {
+        let mut filter = Self {
+            kind,
+            sample_rate: sample_rate as f64,
+            g: 0.0,
+            k: 2.0 - 1.98 * resonance.clamp(0.0, 1.0),
+            ic1: 0.0,
+            ic2: 0.0,
+            bypass: false,
+        };
+        filter.set_cutoff(cutoff);
+        filter
+    }
+
+    /// Move the cutoff without resetting the filter's state
+    pub fn set_cutoff(&mut self, cutoff: f64) {
+        let nyquist = self.sample_rate / 2.0;
+        self.bypass = self.kind == FilterType::Lowpass && cutoff >= nyquist;
+        self.g = (PI * cutoff.clamp(1.0, nyquist * 0.99) / self.sample_rate).tan();
+    }
+
+    pub fn process(&mut self, input: f64) -> f64 {
+        if self.bypass {
+            return input;
+        }
+        let a1 = 1.0 / (1.0 + self.g * (self.g + self.k));
+        let a2 = self.g * a1;
+        let a3 = self.g * a2;
+        let v3 = input - self.ic2;
+        let band = a1 * self.ic1 + a2 * v3;
+        let low = self.ic2 + a2 * self.ic1 + a3 * v3;
+        self.ic1 = 2.0 * band - self.ic1;
+        self.ic2 = 2.0 * low - self.ic2;
+        match self.kind {
+            FilterType::Lowpass => low,
+            FilterType::Highpass => input - self.k * band - low,
+            FilterType::Bandpass => band,
+        }
+    }
+}
+
+/// One note of the subtractive synth: an oscillator through the filter, shaped by the envelope
+pub struct Voice {
+    wave: Waveform,
+    filter: Filter,
+    cutoff: f64,
+    envelope: Envelope,
+    lfos: Vec<Lfo>,
+    sample_rate: f64,
+    phase: f64,
+}
+
+impl Voice {
+    pub fn new(params: &SubtractiveParams, sample_rate: u32) -> Self {
+        Self {
+            wave: params.oscillator_wave,
+            filter: Filter::new(params.filter_type, params.filter_cutoff, params.filter_resonance, sample_rate),
+            cutoff: params.filter_cutoff,
+            envelope: Envelope::from_params(params),
+            lfos: params.lfos.clone(),
+            sample_rate: sample_rate as f64,
+            phase: 0.0,
+        }
+    }
+
+    /// Move the filter cutoff by `ratio`, e.g. for the note's velocity
+    pub fn scale_cutoff(&mut self, ratio: f64) {
+        self.cutoff *= ratio;
+        self.filter.set_cutoff(self.cutoff);
+    }
+
+    /// Samples the note keeps sounding for after it's released
+    pub fn release_samples(&self) -> usize {
+        (self.envelope.release * self.sample_rate).ceil() as usize
+    }
+
+    /// Mix the note into `output`, releasing it after `gate` samples. `frequency` gives the
+    /// oscillator frequency at each sample, so it can follow pitch bend.
+    pub fn render(&mut self, output: &mut [f64], gate: usize, frequency: impl Fn(usize) -> f64) {
+        let gate = gate as f64 / self.sample_rate;
+        for (i, out) in output.iter_mut().enumerate() {
+            let time = i as f64 / self.sample_rate;
+            let mut level = self.envelope.level(time, gate);
+            let mut semitones = 0.0;
+            let mut octaves = 0.0;
+            for lfo in &self.lfos {
+                let value = oscillator(lfo.shape, lfo.rate * time);
+                match lfo.target {
+                    LfoTarget::Pitch => semitones += value * lfo.depth,
+                    LfoTarget::Cutoff => octaves += value * lfo.depth,
+                    // Dips from full level down by the depth
+                    LfoTarget::Amp => level *= 1.0 - lfo.depth * (1.0 - value) / 2.0,
+                }
+            }
+            if octaves != 0.0 {
+                self.filter.set_cutoff(self.cutoff * 2_f64.powf(octaves));
+            }
+
+            let increment = frequency(i) * 2_f64.powf(semitones / 12.0) / self.sample_rate;
+            *out += self.filter.process(band_limited(self.wave, self.phase, increment)) * level;
+            self.phase = (self.phase + increment).fract();
+        }
+    }
+}
+
+/// Noise between -1 and 1. The same seed always gives the same noise, so renders repeat.
+#[derive(Debug, Clone)]
+pub struct Noise {
+    color: NoiseColor,
+    state: u64,
+    /// Pink noise filter poles
+    pink: [f64; 7],
+}
+
+impl Noise {
+    pub fn new(color: NoiseColor, seed: u64) -> Self {
+        // Xorshift gets stuck on zero, and similar seeds start out similar, so scramble it
+        let state = seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1;
+        Self { color, state, pink: [0.0; 7] }
+    }
+
+    pub fn next_sample(&mut self) -> f64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        let white = (self.state >> 11) as f64 / (1_u64 << 52) as f64 - 1.0;
+        match self.color {
+            NoiseColor::White => white,
+            // Paul Kellet's filter: close to -3 dB per octave across the audible range
+            NoiseColor::Pink => {
+                let b = &mut self.pink;
+                b[0] = 0.99886 * b[0] + white * 0.0555179;
+                b[1] = 0.99332 * b[1] + white * 0.0750759;
+                b[2] = 0.96900 * b[2] + white * 0.1538520;
+                b[3] = 0.86650 * b[3] + white * 0.3104856;
+                b[4] = 0.55000 * b[4] + white * 0.5329522;
+                b[5] = -0.7616 * b[5] - white * 0.0168980;
+                let pink = b[0] + b[1] + b[2] + b[3] + b[4] + b[5] + b[6] + white * 0.5362;
+                b[6] = white * 0.115926;
+                (pink * 0.11).clamp(-1.0, 1.0)
+            }
+        }
+    }
+}
+
+/// A seed for one source of randomness in a render, such as one drum hit or one channel's
+/// dither, drawn from the render's `seed`. Sources get unrelated noise, and the same seed
+/// always gives the same result.
+pub fn derive_seed(seed: u64, source: &str, index: u64) -> u64 {
+    // FNV-1a, which unlike std's hashers is fixed across Rust releases
+    seed.to_le_bytes().iter().chain(source.as_bytes()).chain(&index.to_le_bytes())
+        .fold(0xCBF2_9CE4_8422_2325, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x0100_0000_01B3))
+}
+
+/// The seed for the noise of a note on `instrument` starting at `position_b32`, which
+/// stays the same whatever other notes are added or removed. `doubling` tells notes of the
+/// same pitch starting together on the track apart: it's how many come before this one.
+pub fn note_seed(seed: u64, instrument: &str, position_b32: u64, pitch: Pitch, doubling: usize) -> u64 {
+    derive_seed(seed, instrument, position_b32 << 16 | u64::from(pitch.to_midi()) << 8 | doubling.min(255) as u64)
+}
+
+/// One hit of the percussion synth: a sine sweeping down to the note's pitch plus noise,
+/// under an attack and decay
+pub struct Drum {
+    tone_level: f64,
+    pitch_sweep: f64,
+    sweep_time: f64,
+    noise_level: f64,
+    noise: Noise,
+    envelope: Envelope,
+    sample_rate: f64,
+    phase: f64,
+}
+
+impl Drum {
+    /// `seed` picks the noise, so each hit can sound slightly different
+    pub fn new(params: &PercussionParams, sample_rate: u32, seed: u64) -> Self {
+        Self {
+            tone_level: params.tone_level,
+            pitch_sweep: params.pitch_sweep,
+            sweep_time: params.sweep_time.max(0.0),
+            noise_level: params.noise_level,
+            noise: Noise::new(params.noise_color, seed),
+            envelope: Envelope::for_percussion(params),
+            sample_rate: sample_rate as f64,
+            phase: 0.0,
+        }
+    }
+
+    /// Samples the hit lasts, however long its note is
+    pub fn length_samples(&self) -> usize {
+        ((self.envelope.attack + self.envelope.decay) * self.sample_rate).ceil() as usize
+    }
+
+    /// Mix the hit into `output`. `frequency` gives the note's frequency at each sample,
+    /// which the tone sweeps down to.
+    pub fn render(&mut self, output: &mut [f64], frequency: impl Fn(usize) -> f64) {
+        for (i, out) in output.iter_mut().take(self.length_samples()).enumerate() {
+            let time = i as f64 / self.sample_rate;
+            let level = self.envelope.level(time, f64::INFINITY);
+            let tone = (2.0 * PI * self.phase).sin() * self.tone_level;
+            *out += (tone + self.noise.next_sample() * self.noise_level) * level;
+
+            // Three time constants in, the sweep is 95% of the way down
+            let sweep = if self.sweep_time > 0.0 {
+                self.pitch_sweep * (-3.0 * time / self.sweep_time).exp()
+            } else {
+                0.0
+            };
+            let increment = frequency(i) * 2_f64.powf(sweep / 12.0) / self.sample_rate;
+            self.phase = (self.phase + increment).fract();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine_level(kind: FilterType, frequency: f64) -> f64 {
+        let mut filter = Filter::new(kind, 1000.0, 0.0, 44100);
+        (0..44100)
+            .map(|i| filter.process((2.0 * PI * frequency * i as f64 / 44100.0).sin()))
+            .skip(22050)
+            .fold(0.0, |peak: f64, s| peak.max(s.abs()))
+    }
+
+    #[test]
+    fn test_oscillator_shapes() {
+        assert_eq!(oscillator(Waveform::Square, 0.25), 1.0);
+        assert_eq!(oscillator(Waveform::Square, 0.75), -1.0);
+        assert_eq!(oscillator(Waveform::Sawtooth, 0.0), -1.0);
+        assert_eq!(oscillator(Waveform::Triangle, 0.5), 1.0);
+        assert_eq!(oscillator(Waveform::Triangle, 1.0), -1.0);
+        assert!((oscillator(Waveform::Sine, 0.25) - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_declick_fades_both_ends() {
+        // Three milliseconds is three samples at 1 kHz
+        let mut note = vec![1.0; 10];
+        declick(&mut note, 1000.0);
+        assert_eq!(note[0], 0.0);
+        assert!((note[1] - 1.0 / 3.0).abs() < 1e-12);
+        assert_eq!(note[3..7], [1.0; 4]);
+        assert!((note[8] - 1.0 / 3.0).abs() < 1e-12);
+        assert_eq!(note[9], 0.0);
+    }
+
+    /// Share of a 1 second oscillator's power that isn't at its harmonics, i.e. aliasing
+    fn aliased_power(wave: impl Fn(f64) -> f64, frequency: f64, sample_rate: f64) -> f64 {
+        let signal: Vec<f64> = (0..sample_rate as usize)
+            .map(|i| wave((frequency * i as f64 / sample_rate).fract()))
+            .collect();
+        let total: f64 = signal.iter().map(|s| s * s).sum();
+        let harmonics: f64 = (1..)
+            .map(|k| k as f64 * frequency)
+            .take_while(|&f| f < sample_rate / 2.0)
+            .map(|f| {
+                let (re, im) = signal.iter().enumerate().fold((0.0, 0.0), |(re, im), (i, s)| {
+                    let angle = 2.0 * PI * f * i as f64 / sample_rate;
+                    (re + s * angle.cos(), im - s * angle.sin())
+                });
+                2.0 * (re * re + im * im) / signal.len() as f64
+            })
+            .sum();
+        1.0 - harmonics / total
+    }
+
+    #[test]
+    fn test_band_limited_oscillators_alias_less() {
+        let (frequency, sample_rate) = (3110.0, 44100.0);
+        for wave in [Waveform::Sawtooth, Waveform::Square, Waveform::Triangle] {
+            let naive = aliased_power(|phase| oscillator(wave, phase), frequency, sample_rate);
+            let smooth = aliased_power(|phase| band_limited(wave, phase, frequency / sample_rate), frequency, sample_rate);
+            assert!(smooth < naive / 4.0, "{wave:?}: {smooth} vs {naive}");
+        }
+        // Away from the discontinuities the waves are unchanged
+        assert_eq!(band_limited(Waveform::Sawtooth, 0.5, 0.01), oscillator(Waveform::Sawtooth, 0.5));
+        assert_eq!(band_limited(Waveform::Square, 0.25, 0.01), 1.0);
+    }
+
+    #[test]
+    fn test_envelope_stages() {
+        let envelope = Envelope { attack: 0.1, decay: 0.2, sustain: 0.5, release: 0.4 };
+        assert_eq!(envelope.level(0.05, 1.0), 0.5);
+        assert_eq!(envelope.level(0.1, 1.0), 1.0);
+        assert!((envelope.level(0.2, 1.0) - 0.75).abs() < 1e-12);
+        assert_eq!(envelope.level(0.9, 1.0), 0.5);
+        assert!((envelope.level(1.2, 1.0) - 0.25).abs() < 1e-12);
+        assert_eq!(envelope.level(1.5, 1.0), 0.0);
+        // Released during the attack: fades from where it got to
+        assert!((envelope.level(0.25, 0.05) - 0.25).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_filter_types() {
+        assert!(sine_level(FilterType::Lowpass, 100.0) > 0.95);
+        assert!(sine_level(FilterType::Lowpass, 10000.0) < 0.02);
+        assert!(sine_level(FilterType::Highpass, 100.0) < 0.02);
+        assert!(sine_level(FilterType::Highpass, 10000.0) > 0.95);
+        assert!(sine_level(FilterType::Bandpass, 1000.0) > sine_level(FilterType::Bandpass, 100.0) * 5.0);
+
+        // Resonance boosts the cutoff frequency
+        let mut resonant = Filter::new(FilterType::Lowpass, 1000.0, 0.9, 44100);
+        let peak = (0..44100)
+            .map(|i| resonant.process((2.0 * PI * 1000.0 * i as f64 / 44100.0).sin()))
+            .skip(22050)
+            .fold(0.0, |peak: f64, s| peak.max(s.abs()));
+        assert!(peak > 2.0);
+    }
+
+    #[test]
+    fn test_noise_is_repeatable() {
+        let mut noise = Noise::new(NoiseColor::White, 7);
+        let white: Vec<f64> = (0..44100).map(|_| noise.next_sample()).collect();
+        let mut again = Noise::new(NoiseColor::White, 7);
+        assert!(white.iter().all(|&s| s == again.next_sample()));
+        assert!(white.iter().all(|s| (-1.0..=1.0).contains(s)));
+        let mean = white.iter().sum::<f64>() / white.len() as f64;
+        assert!(mean.abs() < 0.02);
+        assert_ne!(Noise::new(NoiseColor::White, 8).next_sample(), white[0]);
+
+        // Pink noise changes more slowly than white
+        let mut pink = Noise::new(NoiseColor::Pink, 7);
+        let pink: Vec<f64> = (0..44100).map(|_| pink.next_sample()).collect();
+        let roughness = |s: &[f64]| {
+            let steps: f64 = s.windows(2).map(|w| (w[1] - w[0]).powi(2)).sum();
+            steps / s.iter().map(|x| x * x).sum::<f64>()
+        };
+        assert!(roughness(&pink) < roughness(&white) / 4.0);
+    }
+
+    #[test]
+    fn test_drum_sweeps_down_and_decays() {
+        let params = PercussionParams {
+            pitch_sweep: 24.0,
+            sweep_time: 0.2,
+            envelope_attack: 0.0,
+            envelope_decay: 0.5,
+            ..PercussionParams::default()
+        };
+        let hit = |params: &PercussionParams| {
+            let mut output = vec![0.0; 600];
+            Drum::new(params, 1000, 0).render(&mut output, |_| 20.0);
+            output
+        };
+        let crossings = |s: &[f64]| s.windows(2).filter(|w| w[0] < 0.0 && w[1] >= 0.0).count();
+        let swept = hit(&params);
+        let flat = hit(&PercussionParams { pitch_sweep: 0.0, ..params.clone() });
+        assert_eq!(crossings(&flat[0..200]), 3);
+        assert!(crossings(&swept[0..200]) >= 5);
+
+        assert_eq!(Drum::new(&params, 1000, 0).length_samples(), 500);
+        assert!(swept[250..350].iter().all(|s| s.abs() <= 0.5));
+        assert!(swept[500..].iter().all(|&s| s == 0.0));
+    }
+
+    #[test]
+    fn test_voice_lfos() {
+        let plain = SubtractiveParams {
+            envelope_attack: 0.0,
+            envelope_decay: 0.0,
+            envelope_sustain: 1.0,
+            envelope_release: 0.0,
+            filter_cutoff: 100_000.0,
+            ..SubtractiveParams::default()
+        };
+        let render = |lfo: Lfo, filter_cutoff: f64| {
+            let params = SubtractiveParams { lfos: vec![lfo], filter_cutoff, ..plain.clone() };
+            let mut output = vec![0.0; 8000];
+            Voice::new(&params, 8000).render(&mut output, 8000, |_| 400.0);
+            output
+        };
+        let peak = |s: &[f64]| s.iter().fold(0.0_f64, |peak, s| peak.max(s.abs()));
+        let crossings = |s: &[f64]| s.windows(2).filter(|w| w[0] < 0.0 && w[1] >= 0.0).count();
+
+        // Tremolo at 1 Hz: a triangle starts its cycle at the bottom, so silent, and peaks half way
+        let tremolo = render(Lfo::new(LfoTarget::Amp, Waveform::Triangle, 1.0, 1.0), 100_000.0);
+        assert!(peak(&tremolo[..100]) < 0.1);
+        assert!(peak(&tremolo[3900..4100]) > 0.95);
+
+        // A square LFO on pitch jumps an octave up for the first half of each cycle, then down
+        let vibrato = render(Lfo::new(LfoTarget::Pitch, Waveform::Square, 1.0, 12.0), 100_000.0);
+        assert!((crossings(&vibrato[..4000]) as i32 - 400).abs() <= 1);
+        assert!((crossings(&vibrato[4000..]) as i32 - 100).abs() <= 1);
+
+        // Sweeping a 400 Hz low-pass four octaves up and down opens and closes it
+        let wobble = render(Lfo::new(LfoTarget::Cutoff, Waveform::Square, 1.0, -4.0), 400.0);
+        assert!(peak(&wobble[2000..4000]) < 0.1);
+        assert!(peak(&wobble[6000..]) > 0.6);
+    }
+
+    #[test]
+    fn test_voice_release() {
+        let params = SubtractiveParams {
+            envelope_attack: 0.0,
+            envelope_decay: 0.0,
+            envelope_sustain: 1.0,
+            envelope_release: 0.01,
+            filter_cutoff: 100_000.0,
+            ..SubtractiveParams::default()
+        };
+        let mut voice = Voice::new(&params, 1000);
+        assert_eq!(voice.release_samples(), 10);
+        let mut output = vec![0.0; 120];
+        voice.render(&mut output, 100, |_| 250.0);
+        assert!((output[1] - 1.0).abs() < 1e-9);
+        assert!(output[105].abs() < 1.0);
+        assert!(output[110..].iter().all(|&s| s.abs() < 1e-9));
+    }
+}
@@ -0,0 +1,237 @@
+use dawww_core::{ChannelLayout, Dither, MixdownSettings, SampleFormat};
+use dawww_core::instrument::NoiseColor;
+use anyhow::{Result, bail};
+use std::fs::OpenOptions;
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+use crate::speakers::from_stereo;
+use crate::stereo::Stereo;
+use crate::synth::{Noise, derive_seed};
+
+/// Where the speaker mask of hound's WAVE_FORMAT_EXTENSIBLE header sits, and the mask for
+/// front and rear pairs
+const CHANNEL_MASK_OFFSET: u64 = 40;
+const QUAD_CHANNEL_MASK: u32 = 0x33;
+
+/// Write `buffer`, scaled by `gain`, to a WAV file in the mixdown's channel layout, bit depth
+/// and sample format. Integer samples are clipped at full scale; float samples are not.
+/// Integer samples of 16 bits or fewer get the mixdown's dither, drawn from `seed`. Mono files take the
+/// average of the two channels, and surround files play the buffer from the front pair.
+pub fn write_wav(path: &Path, buffer: &Stereo, settings: &MixdownSettings, sample_rate: u32, gain: f64, seed: u64) -> Result<()> {
+    write_channels(path, &from_stereo(buffer, settings.channels), settings, sample_rate, gain, seed)
+}
+
+/// Write one signal for each of the mixdown's channels, in file order, to a WAV file as
+/// `write_wav` does
+pub fn write_channels<C: AsRef<[f64]>>(
+    path: &Path,
+    channels: &[C],
+    settings: &MixdownSettings,
+    sample_rate: u32,
+    gain: f64,
+    seed: u64,
+) -> Result<()> {
+    settings.validate()?;
+    check_channels(channels, settings)?;
+    let spec = hound::WavSpec {
+        channels: settings.channels.channels(),
+        sample_rate,
+        bits_per_sample: settings.bit_depth,
+        sample_format: match settings.sample_format {
+            SampleFormat::Int => hound::SampleFormat::Int,
+            SampleFormat::Float => hound::SampleFormat::Float,
+        },
+    };
+    let mut writer = hound::WavWriter::create(path, spec)?;
+
+    let mut quantizers = Quantizer::for_channels(settings, seed);
+    for (i, sample) in interleave(channels, gain).enumerate() {
+        let quantizer = &mut quantizers[i % spec.channels as usize];
+        match (settings.sample_format, settings.bit_depth) {
+            (SampleFormat::Float, _) => writer.write_sample(sample as f32)?,
+            (SampleFormat::Int, 8) => writer.write_sample(quantizer.quantize(sample) as i8)?,
+            (SampleFormat::Int, 16) => writer.write_sample(quantizer.quantize(sample) as i16)?,
+            (SampleFormat::Int, bits @ (24 | 32)) => writer.write_sample(to_int(sample, bits))?,
+            (SampleFormat::Int, bits) => bail!("Unsupported bit depth {}", bits),
+        }
+    }
+    writer.finalize()?;
+    if settings.channels == ChannelLayout::Quad {
+        // hound assigns the first channels in the speaker mask, which for four channels
+        // would be front left, right, center and LFE rather than the rear pair
+        let mut file = OpenOptions::new().write(true).open(path)?;
+        file.seek(SeekFrom::Start(CHANNEL_MASK_OFFSET))?;
+        file.write_all(&QUAD_CHANNEL_MASK.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+/// Fail unless there's one signal for each of the mixdown's channels, all the same length.
+/// Returns the length.
+pub(crate) fn check_channels<C: AsRef<[f64]>>(channels: &[C], settings: &MixdownSettings) -> Result<usize> {
+    if channels.len() != settings.channels.channels() as usize {
+        bail!("Expected {} channels but got {}", settings.channels.channels(), channels.len());
+    }
+    let len = channels[0].as_ref().len();
+    if channels.iter().any(|channel| channel.as_ref().len() != len) {
+        bail!("Channels must all be the same length");
+    }
+    Ok(len)
+}
+
+/// The samples of `channels` scaled by `gain`, frame by frame
+pub(crate) fn interleave<C: AsRef<[f64]>>(channels: &[C], gain: f64) -> impl Iterator<Item = f64> + '_ {
+    let len = channels.first().map_or(0, |channel| channel.as_ref().len());
+    (0..len).flat_map(move |i| channels.iter().map(move |channel| channel.as_ref()[i] * gain))
+}
+
+/// Clip a sample to full scale and round it to a `bits`-bit integer
+pub(crate) fn to_int(sample: f64, bits: u16) -> i32 {
+    (sample.clamp(-1.0, 1.0) * full_scale(bits)).round() as i32
+}
+
+fn full_scale(bits: u16) -> f64 {
+    ((1_i64 << (bits - 1)) - 1) as f64
+}
+
+/// Rounds one channel's samples to integers with dither
+pub(crate) struct Quantizer {
+    full_scale: f64,
+    dither: Dither,
+    noise: Noise,
+    /// The last two rounding errors, newest first, for noise shaping
+    errors: [f64; 2],
+}
+
+impl Quantizer {
+    /// The same `seed` always gives the same dither, so renders repeat
+    fn new(bits: u16, dither: Dither, seed: u64) -> Self {
+        Self { full_scale: full_scale(bits), dither, noise: Noise::new(NoiseColor::White, seed), errors: [0.0; 2] }
+    }
+
+    /// One quantizer for each of a mixdown's channels, each with its own dither
+    pub(crate) fn for_channels(settings: &MixdownSettings, seed: u64) -> Vec<Self> {
+        (0..settings.channels.channels())
+            .map(|channel| Self::new(settings.bit_depth, settings.dither, derive_seed(seed, "dither", channel as u64)))
+            .collect()
+    }
+
+    /// Clip a sample to full scale and round it to an integer
+    pub(crate) fn quantize(&mut self, sample: f64) -> i32 {
+        let mut value = sample.clamp(-1.0, 1.0) * self.full_scale;
+        if self.dither == Dither::Shaped {
+            // Second-order error feedback: the noise rises 12 dB per octave toward Nyquist
+            value -= 2.0 * self.errors[0] - self.errors[1];
+        }
+        let noise = match self.dither {
+            Dither::None => 0.0,
+            // The sum of two uniform values is triangular, spanning one step either way
+            Dither::Tpdf | Dither::Shaped => (self.noise.next_sample() + self.noise.next_sample()) / 2.0,
+        };
+        let quantized = (value + noise).round().clamp(-self.full_scale, self.full_scale);
+        self.errors = [quantized - value, self.errors[0]];
+        quantized as i32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn ramp() -> Stereo {
+        Stereo { left: vec![0.0, 0.5, 1.0, 2.0], right: vec![0.0, -0.5, -1.0, -2.0] }
+    }
+
+    #[test]
+    fn test_int_depths() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("out.wav");
+
+        write_wav(&path, &ramp(), &MixdownSettings::new(8000, 24), 8000, 1.0, 0).unwrap();
+        let mut reader = hound::WavReader::open(&path).unwrap();
+        assert_eq!(reader.spec().bits_per_sample, 24);
+        let samples: Vec<i32> = reader.samples::<i32>().map(|s| s.unwrap()).collect();
+        assert_eq!(samples, vec![0, 0, 4194304, -4194304, 8388607, -8388607, 8388607, -8388607]);
+
+        write_wav(&path, &ramp(), &MixdownSettings::new(8000, 32), 8000, 0.5, 0).unwrap();
+        let samples: Vec<i32> = hound::WavReader::open(&path).unwrap().samples::<i32>().map(|s| s.unwrap()).collect();
+        assert_eq!(samples[4], 1073741824);
+        assert_eq!(samples[6], i32::MAX);
+
+        write_wav(&path, &ramp(), &MixdownSettings::new(8000, 8), 8000, 1.0, 0).unwrap();
+        let samples: Vec<i8> = hound::WavReader::open(&path).unwrap().samples::<i8>().map(|s| s.unwrap()).collect();
+        assert_eq!(samples[2..4], [64, -64]);
+    }
+
+    #[test]
+    fn test_dither() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("out.wav");
+        // A constant level between two steps
+        let quiet = Stereo::from_mono(vec![0.3 / 32767.0; 20000]);
+        let read = |settings: &MixdownSettings| -> Vec<i16> {
+            write_wav(&path, &quiet, settings, 8000, 1.0, 0).unwrap();
+            hound::WavReader::open(&path).unwrap().samples::<i16>().map(|s| s.unwrap()).collect()
+        };
+        let mean = |samples: &[i16]| samples.iter().map(|&s| s as f64).sum::<f64>() / samples.len() as f64;
+
+        // Rounding alone loses the level; dither keeps it on average, and repeats exactly
+        assert!(read(&MixdownSettings::new(8000, 16)).iter().all(|&s| s == 0));
+        let tpdf = read(&MixdownSettings::new(8000, 16).with_dither(Dither::Tpdf));
+        assert!((mean(&tpdf) - 0.3).abs() < 0.03);
+        assert!(tpdf.iter().all(|s| (-1..=2).contains(s)));
+        assert_eq!(read(&MixdownSettings::new(8000, 16).with_dither(Dither::Tpdf)), tpdf);
+
+        let shaped = read(&MixdownSettings::new(8000, 16).with_dither(Dither::Shaped));
+        assert!((mean(&shaped) - 0.3).abs() < 0.03);
+        // Shaped noise changes more from sample to sample
+        let roughness = |samples: &[i16]| samples.windows(2).map(|w| (w[1] - w[0]).abs() as f64).sum::<f64>();
+        assert!(roughness(&shaped) > roughness(&tpdf));
+
+        // 24-bit files aren't dithered
+        write_wav(&path, &quiet, &MixdownSettings::new(8000, 24), 8000, 1.0, 0).unwrap();
+        assert!(hound::WavReader::open(&path).unwrap().samples::<i32>().all(|s| s.unwrap() == 77));
+    }
+
+    #[test]
+    fn test_float_keeps_overs() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("out.wav");
+        write_wav(&path, &ramp(), &MixdownSettings::float(8000).with_channels(ChannelLayout::Mono), 8000, 1.0, 0).unwrap();
+
+        let mut reader = hound::WavReader::open(&path).unwrap();
+        assert_eq!(reader.spec().sample_format, hound::SampleFormat::Float);
+        assert_eq!(reader.spec().channels, 1);
+        let samples: Vec<f32> = reader.samples::<f32>().map(|s| s.unwrap()).collect();
+        assert_eq!(samples, vec![0.0, 0.0, 0.0, 0.0]);
+
+        let loud = Stereo::from_mono(vec![1.5, -0.25]);
+        write_wav(&path, &loud, &MixdownSettings::float(8000), 8000, 1.0, 0).unwrap();
+        let samples: Vec<f32> = hound::WavReader::open(&path).unwrap().samples::<f32>().map(|s| s.unwrap()).collect();
+        assert_eq!(samples, vec![1.5, 1.5, -0.25, -0.25]);
+    }
+
+    #[test]
+    fn test_surround_channels() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("out.wav");
+        let surround = MixdownSettings::new(8000, 16).with_channels(ChannelLayout::Surround51);
+        let feeds = vec![vec![0.5], vec![0.0], vec![0.25], vec![0.0], vec![-0.5], vec![1.0]];
+        write_channels(&path, &feeds, &surround, 8000, 1.0, 0).unwrap();
+        let mut reader = hound::WavReader::open(&path).unwrap();
+        assert_eq!(reader.spec().channels, 6);
+        let samples: Vec<i16> = reader.samples::<i16>().map(|s| s.unwrap()).collect();
+        assert_eq!(samples, vec![16384, 0, 8192, 0, -16384, 32767]);
+        let mask = |path: &Path| u32::from_le_bytes(std::fs::read(path).unwrap()[40..44].try_into().unwrap());
+        assert_eq!(mask(&path), 0x3F);
+        assert!(write_channels(&path, &feeds[..4], &surround, 8000, 1.0, 0).is_err());
+
+        // Stereo buffers play from the front pair; quad files mark the rear pair as such
+        let quad = MixdownSettings::new(8000, 16).with_channels(ChannelLayout::Quad);
+        write_wav(&path, &Stereo { left: vec![0.5], right: vec![-0.5] }, &quad, 8000, 1.0, 0).unwrap();
+        let samples: Vec<i16> = hound::WavReader::open(&path).unwrap().samples::<i16>().map(|s| s.unwrap()).collect();
+        assert_eq!(samples, vec![16384, -16384, 0, 0]);
+        assert_eq!(mask(&path), 0x33);
+    }
+}
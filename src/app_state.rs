@@ -1,16 +1,19 @@
 // app_state.rs
 use crate::cursor::Cursor;
-use crate::draw_components::ViewportDrawResult;
+use crate::draw_components::{LaneDrawResult, ViewportDrawResult};
+use crate::live_notes::LiveNotes;
 use crate::loop_state::LoopState;
+use crate::palette::PaletteCommand;
 use dawww_core::pitch::{Pitch, Tone};
-use crate::player::Player;
+use crate::player::{PlayState, Player};
+use crate::playhead::Playhead;
 use crate::resolution::Resolution;
 use crate::score::Score;
 use crate::score_viewport::ScoreViewport;
 use crate::{
     cursor::CursorMode,
     draw_components::{
-        self, score_draw_component::ScoreDrawComponent, status_bar_component::StatusBarComponent,
+        self, score_draw_component::{ScoreDrawComponent, NOTE_CHARS}, status_bar_component::{PromptComponent, RecordStatus, StatusBarComponent},
         BoxDrawComponent, DrawComponent, DrawResult, NullComponent, Position, VSplitDrawComponent,
         Window,
     },
@@ -29,9 +32,23 @@ use std::io::{self, Write};
 use std::sync::mpsc;
 use std::sync::{Arc, Mutex};
 use std::thread::{self, JoinHandle};
+use std::time::Duration;
 use crate::song_file::SongFile;
-use log::error;
-use crate::audio::audio_player;
+use log::{error, info};
+use crate::audio::{audio_player, AudioSettings};
+use crate::midi::{midi_clock_output, midi_input, SyncScheduler};
+use midir::MidiInputConnection;
+use dawww_playback::{Capture, CaptureOptions, Monitor, PlaybackOptions};
+use dawww_core::{time_from_32nds, Session};
+
+/// How often the player reports the playhead while it moves, per second
+const PLAYHEAD_RATE_HZ: f64 = 30.0;
+
+/// How far each press moves the monitoring gain, in dB
+const MONITOR_GAIN_STEP_DB: f64 = 3.0;
+
+/// Monitoring gain, in dB, below which the input isn't monitored at all
+const MONITOR_GAIN_FLOOR_DB: f64 = -48.0;
 
 pub struct AppState {
     score: Arc<Mutex<Score>>,
@@ -41,38 +58,108 @@ pub struct AppState {
     input_rx: mpsc::Receiver<InputEvent>,
     input_thread: Option<JoinHandle<()>>,
     audio_thread: Option<JoinHandle<()>>,
+    audio_options: PlaybackOptions,
+    latency_compensation: Option<Duration>,
     buffer: Option<Vec<Vec<char>>>,
     cursor: Cursor,
     selection_buffer: SelectionBuffer,
     viewport_draw_result: Option<ViewportDrawResult>,
+    /// The track header and color last drawn
+    lane_draw_result: Option<LaneDrawResult>,
     loop_state: LoopState,
     song_file: SongFile,
+    /// Brings in notes from a MIDI keyboard while it's kept open
+    midi_input: Option<MidiInputConnection<()>>,
+    /// Whether notes played on the MIDI keyboard are entered into the score
+    step_entry: bool,
+    /// Sounds the keys played on the MIDI keyboard
+    live_notes: LiveNotes,
+    /// Keys held down on the MIDI keyboard
+    held_keys: Vec<Pitch>,
+    /// Whether recorded notes are snapped to the current resolution
+    quantize: bool,
+    /// Whether step entry put in notes for the keys held, so the cursor moves on once
+    /// they're all let go
+    step_entered: bool,
+    /// Where the player last reported the playhead
+    playhead: Playhead,
+    capture_options: CaptureOptions,
+    /// The audio input while audio is being recorded, with the heard position recording
+    /// started at, in 32nds
+    capture: Option<(Capture, f64)>,
+    /// The audio input, played along with the player while it's monitored
+    monitor: Arc<Mutex<Option<Monitor>>>,
+    /// What's been typed into the command palette while it's open
+    palette: Option<String>,
 }
 
 impl AppState {
     pub fn new(score: Arc<Mutex<Score>>) -> AppState {
         let (tx, rx) = mpsc::channel();
 
-        let player = Player::create(Arc::clone(&score), 44100);
+        // Pick up where the song was left: loop, playhead and snap resolution
+        let session = score.lock().unwrap().session().clone();
+        let loop_state = LoopState::from_session(&session);
+        let playhead = session.playhead_position().map_or(0, u64::from);
+        let resolution = session.snap.map_or(Resolution::Time1_16, Resolution::from_grid);
+
+        let audio = AudioSettings::from_env().unwrap_or_else(|e| {
+            error!("Ignoring audio settings: {}", e);
+            AudioSettings::default()
+        });
+        let mut player = Player::create(Arc::clone(&score), audio.sample_rate);
+        player.set_loop_state(loop_state);
+        player.set_max_voices(audio.max_voices);
+        player.set_time_b32(playhead);
+        let playhead_tx = tx.clone();
+        player.on_playhead(PLAYHEAD_RATE_HZ, move |playhead| {
+            // The UI may already be shutting down
+            let _ = playhead_tx.send(InputEvent::PlayerPosition(playhead));
+        });
+        let reported_playhead = player.playhead();
         let shared_player = Arc::new(Mutex::new(player));
+        let live_notes = LiveNotes::start(Arc::clone(&shared_player), Arc::clone(&score));
 
         AppState {
             score,
-            score_viewport: ScoreViewport::new(Pitch::new(Tone::C, 4), Resolution::Time1_16, 0, 0),
+            score_viewport: ScoreViewport::new(Pitch::new(Tone::C, 4), resolution, playhead - playhead % 32, playhead),
             player: shared_player,
             input_tx: tx,
             input_rx: rx,
             input_thread: None,
             audio_thread: None,
+            audio_options: audio.options,
+            latency_compensation: audio.latency_compensation,
             buffer: None,
-            cursor: Cursor::new(Pitch::new(Tone::C, 4), 0),
+            cursor: Cursor::new(Pitch::new(Tone::C, 4), playhead - playhead % resolution.duration_b32()),
             selection_buffer: SelectionBuffer::None,
             viewport_draw_result: None,
-            loop_state: LoopState::new(),
+            lane_draw_result: None,
+            loop_state,
             song_file: SongFile::new(),
+            midi_input: None,
+            step_entry: false,
+            live_notes,
+            held_keys: Vec::new(),
+            step_entered: false,
+            quantize: true,
+            playhead: reported_playhead,
+            capture_options: audio.capture,
+            capture: None,
+            palette: None,
+            monitor: Arc::new(Mutex::new(None)),
         }
     }
 
+    /// Editor state to save with the song
+    fn session(&self) -> Session {
+        let mut session = self.score.lock().unwrap().session().clone();
+        self.loop_state.write_to(&mut session);
+        session.playhead = Some(time_from_32nds(self.score_viewport.playback_time_point as u32));
+        session.snap = Some(self.score_viewport.resolution.grid());
+        session
+    }
+
     pub fn run(&mut self) -> io::Result<()> {
         // Setup terminal
         let mut stdout = io::stdout();
@@ -85,12 +172,41 @@ impl AppState {
         }));
 
         // Start audio thread
-        let player_tx = self.input_tx.clone();
         let player = Arc::clone(&self.player);
+        let audio_options = self.audio_options.clone();
+        let latency_compensation = self.latency_compensation;
+        let monitor = Arc::clone(&self.monitor);
         self.audio_thread = Some(thread::spawn(move || {
-            let _ = audio_player(&player, player_tx.clone());
+            if let Err(e) = audio_player(&player, audio_options, latency_compensation, monitor) {
+                error!("Audio playback stopped: {}", e);
+            }
         }));
 
+        // Play and enter notes from a MIDI keyboard, if one's connected
+        match midi_input(self.input_tx.clone()) {
+            Ok(connection) => self.midi_input = connection,
+            Err(e) => error!("MIDI input unavailable: {}", e),
+        }
+
+        // Take commands from OSC controllers, if asked to
+        #[cfg(feature = "osc")]
+        if let Err(e) = crate::osc::osc_server(self.input_tx.clone()) {
+            error!("OSC control unavailable: {}", e);
+        }
+
+        // Keep external gear in time, if a clock output is chosen
+        match midi_clock_output() {
+            Ok(Some(mut connection)) => {
+                let scheduler = SyncScheduler::start(move |message| {
+                    // Gear that's been unplugged just stops following
+                    let _ = connection.send(&message.bytes());
+                });
+                self.player.lock().unwrap().on_sync(move |at, message| scheduler.schedule(at, message));
+            }
+            Ok(None) => {}
+            Err(e) => error!("MIDI clock output unavailable: {}", e),
+        }
+
         // Main loop
         self.draw()?;
         self.event_loop()?;
@@ -104,19 +220,18 @@ impl AppState {
             match self.input_rx.recv() {
                 Ok(msg) => {
                     match msg {
-                        InputEvent::Quit => break,
+                        InputEvent::Quit => {
+                            let session = self.session();
+                            self.score.lock().unwrap().save_session(session);
+                            break;
+                        }
                         
                         // Viewer navigation
-                        InputEvent::ViewerOctaveIncrease => {
-                            self.score_viewport = self.score_viewport.next_octave();
-                        }
-                        InputEvent::ViewerOctaveDecrease => {
-                            self.score_viewport = self.score_viewport.prev_octave();
-                        }
                         InputEvent::ViewerBarNext => {
                             let current_time = self.player.lock().unwrap().current_time_b32();
                             let next_time = current_time + 32 - current_time % 32;
-                            self.player.lock().unwrap().set_time_b32(next_time);
+                            // Play a moment from where the playhead lands, to find a spot by ear
+                            self.player.lock().unwrap().scrub(next_time);
                             self.score_viewport = self.score_viewport.set_playback_time(next_time);
                             self.score_viewport = self.score_viewport.next_bar(&self.viewport_draw_result.unwrap());
                         }
@@ -124,12 +239,12 @@ impl AppState {
                             let current_time = self.player.lock().unwrap().current_time_b32();
                             let prev_time = if current_time < 32 {
                                 0
-                            } else if current_time % 32 == 0 {
+                            } else if current_time.is_multiple_of(32) {
                                 current_time - 32
                             } else {
                                 current_time - (current_time % 32)
                             };
-                            self.player.lock().unwrap().set_time_b32(prev_time);
+                            self.player.lock().unwrap().scrub(prev_time);
                             self.score_viewport = self.score_viewport.set_playback_time(prev_time);
                             self.score_viewport = self.score_viewport.prev_bar(&self.viewport_draw_result.unwrap());
                         }
@@ -149,24 +264,25 @@ impl AppState {
                             let mut player_guard = self.player.lock().unwrap();
                             player_guard.toggle_playback();
                         }
-                        InputEvent::PlayerBeatChange(playback_time_point_b32) => {
-                            self.score_viewport = self.score_viewport.set_playback_time(playback_time_point_b32);
+                        InputEvent::PlayerPosition(playhead) => {
+                            self.playhead = playhead;
+                            if playhead.time_b32 != self.score_viewport.playback_time_point {
+                                self.score_viewport = self.score_viewport.set_playback_time(playhead.time_b32);
+                            }
                         }
                         
                         // Cursor movement
                         InputEvent::CursorUp => {
                             self.cursor = self.cursor.up();
-                            match self.score_viewport.middle_pitch.next() {
-                                Some(next_pitch) => self.score_viewport.middle_pitch = next_pitch,
-                                None => (),
+                            if let Some(next_pitch) = self.score_viewport.middle_pitch.next() {
+                                self.score_viewport.middle_pitch = next_pitch;
                             }
                             self.player.lock().unwrap().preview_note(self.cursor.pitch());
                         }
                         InputEvent::CursorDown => {
                             self.cursor = self.cursor.down();
-                            match self.score_viewport.middle_pitch.prev() {
-                                Some(prev_pitch) => self.score_viewport.middle_pitch = prev_pitch,
-                                None => (),
+                            if let Some(prev_pitch) = self.score_viewport.middle_pitch.prev() {
+                                self.score_viewport.middle_pitch = prev_pitch;
                             }
                             self.player.lock().unwrap().preview_note(self.cursor.pitch());
                         }
@@ -184,13 +300,13 @@ impl AppState {
                             match self.cursor.mode() {
                                 CursorMode::Select(_, _) => {
                                     // Insert notes for the entire selection
-                                    let selection_range = self.cursor.selection_range().unwrap();
+                                    let selection = self.cursor.selection().unwrap();
                                     let pitch = self.cursor.pitch();
                                     let mut score_guard = self.score.lock().unwrap();
                                     
-                                    // Calculate duration based on selection time points
-                                    let duration = selection_range.time_point_end_b32 - selection_range.time_point_start_b32;
-                                    score_guard.insert_or_remove(pitch, selection_range.time_point_start_b32, duration);
+                                    // The note runs from the start of the selection up to the cursor
+                                    let duration = selection.time.len() as u64 - 1;
+                                    score_guard.insert_or_remove(pitch, selection.time.start as u64, duration);
                                     
                                     // Move cursor to end of selection and clear selection mode
                                     self.cursor = self.cursor.end_select();
@@ -213,23 +329,23 @@ impl AppState {
                         }
                         InputEvent::Yank => {
                             if let CursorMode::Select(_, _) = self.cursor.mode() {
-                                let selection_range = self.cursor.selection_range().unwrap();
-                                let selection_score = self.score.lock().unwrap().clone_at_selection(selection_range);
+                                let selection = self.cursor.selection().unwrap();
+                                let selection_score = self.score.lock().unwrap().clone_at_selection(selection);
                                 self.cursor = self.cursor.yank().right(self.score_viewport.resolution.duration_b32());
-                                self.selection_buffer = SelectionBuffer::Score(
+                                self.selection_buffer = SelectionBuffer::Score(Box::new(
                                     selection_score.translate(Some(self.cursor.time_point())),
-                                );
+                                ));
                             }
                         }
                         InputEvent::Cut => {
                             if let CursorMode::Select(_, _) = self.cursor.mode() {
-                                let selection_range = self.cursor.selection_range().unwrap();
-                                let selection_score = self.score.lock().unwrap().clone_at_selection(selection_range);
-                                self.score.lock().unwrap().delete_in_selection(selection_range);
+                                let selection = self.cursor.selection().unwrap();
+                                let selection_score = self.score.lock().unwrap().clone_at_selection(selection);
+                                self.score.lock().unwrap().delete_in_selection(selection);
                                 self.cursor = self.cursor.end_select();
-                                self.selection_buffer = SelectionBuffer::Score(
+                                self.selection_buffer = SelectionBuffer::Score(Box::new(
                                     selection_score.translate(Some(self.cursor.time_point())),
-                                );
+                                ));
                             }
                         }
                         InputEvent::Paste => {
@@ -238,14 +354,14 @@ impl AppState {
                                 *score_guard = score_guard.merge_down(selection_buffer_score);
                                 let duration = selection_buffer_score.duration();
                                 self.cursor = self.cursor.right(duration);
-                                self.selection_buffer = SelectionBuffer::Score(
+                                self.selection_buffer = SelectionBuffer::Score(Box::new(
                                     selection_buffer_score.translate(Some(self.cursor.time_point())),
-                                );
+                                ));
                             }
                         }
                         InputEvent::Delete => {
-                            if let Some(selection_range) = self.cursor.selection_range() {
-                                self.score.lock().unwrap().delete_in_selection(selection_range);
+                            if let Some(selection) = self.cursor.selection() {
+                                self.score.lock().unwrap().delete_in_selection(selection);
                                 self.cursor = self.cursor.end_select();
                             }
                         }
@@ -255,6 +371,9 @@ impl AppState {
                             self.loop_state = self.loop_state.toggle_mode();
                             self.player.lock().unwrap().set_loop_state(self.loop_state);
                         }
+                        InputEvent::TogglePunch => {
+                            self.loop_state = self.loop_state.toggle_punch();
+                        }
                         InputEvent::SetLoopTimes => {
                             self.loop_state = self.loop_state.mark(self.score_viewport.playback_time_point);
                             self.player.lock().unwrap().set_loop_state(self.loop_state);
@@ -271,7 +390,100 @@ impl AppState {
                         InputEvent::SelectIn => {
                             self.cursor = self.cursor.start_select();
                         }
+
+                        // MIDI keyboard
+                        InputEvent::ToggleStepEntry => {
+                            self.step_entry = !self.step_entry;
+                        }
+                        InputEvent::ToggleRecording => {
+                            let mut player = self.player.lock().unwrap();
+                            if player.is_recording() {
+                                let take = player.stop_recording();
+                                drop(player);
+                                let grid = if self.quantize { self.score_viewport.resolution.duration_b32() } else { 1 };
+                                let notes: Vec<_> = take.notes.iter()
+                                    .map(|note| {
+                                        let (onset, duration) = note.placed(grid);
+                                        (note.pitch, onset, duration, note.velocity)
+                                    })
+                                    .collect();
+                                let mut score = self.score.lock().unwrap();
+                                match take.replaces {
+                                    Some(punch) => score.replace_with_take(punch.start_b32, punch.end_b32, &notes),
+                                    None => score.insert_take(&notes),
+                                }
+                            } else {
+                                player.start_recording(self.loop_state.punch_range());
+                            }
+                        }
+                        InputEvent::ToggleAudioRecording => self.toggle_audio_recording(),
+                        InputEvent::MonitorGainUp => self.nudge_monitor_gain(MONITOR_GAIN_STEP_DB),
+                        InputEvent::MonitorGainDown => self.nudge_monitor_gain(-MONITOR_GAIN_STEP_DB),
+                        InputEvent::ToggleQuantize => {
+                            self.quantize = !self.quantize;
+                        }
+                        InputEvent::AuditionChord => {
+                            self.player.lock().unwrap().audition_chord(self.cursor.time_point());
+                        }
+                        InputEvent::TransportRewind => self.transport(|player| player.nudge(-1)),
+                        InputEvent::TransportFastForward => self.transport(|player| player.nudge(1)),
+                        InputEvent::TransportReturnToStart => self.transport(Player::return_to_start),
+                        InputEvent::TransportNextMarker => self.transport(|player| {
+                            player.go_to_next_marker();
+                        }),
+                        InputEvent::ToggleMute => {
+                            self.score.lock().unwrap().toggle_mute();
+                            self.player.lock().unwrap().refresh_mix();
+                        }
+                        InputEvent::CycleInstrument => {
+                            info!("Editing {}", self.score.lock().unwrap().cycle_instrument());
+                            self.player.lock().unwrap().refresh_mix();
+                        }
+                        InputEvent::Palette(text) => self.palette = text,
+                        InputEvent::RunPalette(text) => {
+                            self.palette = None;
+                            if let Err(e) = self.run_palette(&text) {
+                                error!("{}", e);
+                            }
+                        }
+                        InputEvent::MidiNoteOn { pitch, velocity } => {
+                            self.live_notes.press(pitch, velocity);
+                            let mut player = self.player.lock().unwrap();
+                            player.record_note_on(pitch, velocity);
+                            let recording = player.is_recording();
+                            // Played along with the song, notes go where it was heard
+                            let playhead = (player.state() == PlayState::Playing).then(|| player.heard_position_b32() as u64);
+                            drop(player);
+                            self.held_keys.push(pitch);
+                            // Recording enters notes with their timing when it stops
+                            if self.step_entry && !recording {
+                                // Notes go in at the cursor, or at the playhead snapped to the
+                                // grid while the song plays
+                                let duration = self.score_viewport.resolution.duration_b32();
+                                let onset = match playhead {
+                                    Some(time) => time - time % duration,
+                                    None => {
+                                        self.step_entered = true;
+                                        self.cursor.time_point()
+                                    }
+                                };
+                                self.score.lock().unwrap().insert_with_velocity(pitch, onset, duration, velocity);
+                            }
+                        }
+                        #[cfg(feature = "osc")]
+                        InputEvent::Osc(command) => self.handle_osc(command),
+                        InputEvent::MidiNoteOff(pitch) => {
+                            self.live_notes.release(pitch);
+                            self.player.lock().unwrap().record_note_off(pitch);
+                            self.held_keys.retain(|held| *held != pitch);
+                            // A chord goes in at one step
+                            if self.held_keys.is_empty() && std::mem::take(&mut self.step_entered) {
+                                self.cursor = self.cursor.right(self.score_viewport.resolution.duration_b32());
+                            }
+                        }
                     }
+                    let session = self.session();
+                    self.score.lock().unwrap().set_session(session);
                     self.draw()?;
                 }
                 Err(e) => {
@@ -283,6 +495,128 @@ impl AppState {
         Ok(())
     }
 
+    /// Start recording the audio input from the playhead, playing the song along with it,
+    /// or stop and place what was recorded on the timeline where it started
+    fn toggle_audio_recording(&mut self) {
+        let Some((capture, started_b32)) = self.capture.take() else {
+            let mut player = self.player.lock().unwrap();
+            let capture = match Capture::start(player.sample_rate() as u32, self.capture_options.clone()) {
+                Ok(capture) => capture,
+                Err(e) => {
+                    error!("Can't record audio: {}", e);
+                    return;
+                }
+            };
+            info!("Recording audio from '{}'", capture.device());
+            *self.monitor.lock().unwrap() = Some(capture.monitor());
+            capture.start_recording();
+            // What comes in now is played against what's being heard, not what the player
+            // is producing
+            self.capture = Some((capture, player.heard_position_b32()));
+            player.play();
+            return;
+        };
+
+        *self.monitor.lock().unwrap() = None;
+        let audio = capture.stop_recording();
+        drop(capture);
+        if audio.frames() == 0 {
+            return;
+        }
+        let placed = self.score.lock().unwrap().add_audio_clip(audio, started_b32);
+        match placed {
+            Ok(_) => self.refresh_frozen(),
+            Err(e) => error!("Couldn't place the recorded audio: {}", e),
+        }
+    }
+
+    /// Have the player pick up tracks being frozen or unfrozen. Their audio is read with
+    /// the player unlocked, so playback carries on meanwhile.
+    fn refresh_frozen(&self) {
+        let update = self.player.lock().unwrap().frozen_update();
+        let update = update.load();
+        self.player.lock().unwrap().apply_frozen(update);
+    }
+
+    /// Raise or lower how loud the audio input is monitored, turning monitoring off below
+    /// MONITOR_GAIN_FLOOR_DB and back on from there
+    fn nudge_monitor_gain(&mut self, step_db: f64) {
+        let gain_db = self.capture_options.monitor_gain_db.unwrap_or(MONITOR_GAIN_FLOOR_DB - step_db.abs()) + step_db;
+        self.capture_options.monitor_gain_db = (gain_db >= MONITOR_GAIN_FLOOR_DB).then_some(gain_db.min(12.0));
+        if let Some((capture, _)) = &self.capture {
+            capture.set_monitor_gain_db(self.capture_options.monitor_gain_db);
+        }
+    }
+
+    /// Move the playhead with one of the player's transport controls and scroll the
+    /// viewport to the bar it lands in
+    fn transport(&mut self, control: impl FnOnce(&mut Player)) {
+        let mut player = self.player.lock().unwrap();
+        control(&mut player);
+        let time_b32 = player.current_time_b32();
+        drop(player);
+        self.score_viewport = self.score_viewport
+            .set_playback_time(time_b32)
+            .set_time_point(time_b32 - time_b32 % 32);
+    }
+
+    /// Carry out a command typed into the command palette
+    fn run_palette(&mut self, text: &str) -> anyhow::Result<()> {
+        match PaletteCommand::parse(text)? {
+            PaletteCommand::Steps(pattern) => {
+                let added = self.score.lock().unwrap().insert_steps(
+                    &pattern,
+                    self.cursor.pitch(),
+                    self.cursor.time_point(),
+                    self.score_viewport.resolution.duration_b32(),
+                )?;
+                info!("Entered {} steps", added);
+            }
+            PaletteCommand::Track(id) => {
+                self.score.lock().unwrap().select_instrument(&id)?;
+                self.player.lock().unwrap().refresh_mix();
+                info!("Editing {}", id);
+            }
+            PaletteCommand::Bar(bar) => self.transport(|player| player.seek_to_bar(bar)),
+            PaletteCommand::Marker(name) => {
+                let mut result = Ok(0);
+                self.transport(|player| result = player.go_to_marker(&name));
+                result?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Carry out a command from an OSC controller
+    #[cfg(feature = "osc")]
+    fn handle_osc(&mut self, command: crate::osc::OscCommand) {
+        use crate::osc::OscCommand;
+        match command {
+            OscCommand::Play => self.player.lock().unwrap().play(),
+            OscCommand::Pause => self.player.lock().unwrap().pause(),
+            OscCommand::Stop => self.player.lock().unwrap().stop(),
+            OscCommand::TogglePlayback => self.player.lock().unwrap().toggle_playback(),
+            OscCommand::Locate(time_b32) => self.transport(|player| player.seek(time_b32)),
+            OscCommand::InsertNote { pitch, onset_b32, duration_b32, velocity } => {
+                self.score.lock().unwrap().insert_with_velocity(pitch, onset_b32, duration_b32, velocity);
+            }
+            OscCommand::SetGain(gain_db) => {
+                if let Err(e) = self.score.lock().unwrap().set_gain(gain_db) {
+                    error!("Ignoring OSC gain: {}", e);
+                }
+                self.player.lock().unwrap().refresh_mix();
+            }
+            OscCommand::SetMute(mute) => {
+                self.score.lock().unwrap().set_mute(mute);
+                self.player.lock().unwrap().refresh_mix();
+            }
+            OscCommand::SetSolo(solo) => {
+                self.score.lock().unwrap().set_solo(solo);
+                self.player.lock().unwrap().refresh_mix();
+            }
+        }
+    }
+
     fn draw(&mut self) -> io::Result<()> {
         let (width, height) = terminal::size()?;
         
@@ -314,9 +648,7 @@ impl AppState {
                 draw_components::VSplitStyle::HalfWithDivider,
                 Box::new(ScoreDrawComponent::new(
                     Arc::clone(&self.score),
-                    self.player.lock().unwrap().state(),
                     self.score_viewport,
-                    self.input_tx.clone(),
                     self.cursor,
                     self.selection_buffer.clone(),
                     self.loop_state,
@@ -324,11 +656,22 @@ impl AppState {
                 Box::new(VSplitDrawComponent::new(
                     draw_components::VSplitStyle::StatusBarNoDivider,
                     Box::new(NullComponent {}),
-                    Box::new(StatusBarComponent::new(
-                        self.cursor,
-                        self.score_viewport,
-                        self.loop_state,
-                    )),
+                    match &self.palette {
+                        Some(text) => Box::new(PromptComponent::new(format!(":{}", text))),
+                        None => Box::new(StatusBarComponent::new(
+                            self.cursor,
+                            self.score_viewport,
+                            self.loop_state,
+                            self.step_entry,
+                            RecordStatus {
+                                notes: self.playhead.recording,
+                                audio: self.capture.is_some(),
+                                monitor_gain_db: self.capture_options.monitor_gain_db,
+                            },
+                            self.quantize,
+                            self.score.lock().unwrap().is_muted(),
+                        )),
+                    },
                 )),
             ),
         )))]);
@@ -340,16 +683,18 @@ impl AppState {
             h: height as usize,
         };
         let draw_results = base_component.draw(&mut buffer, &position);
+        let mut lane_draw_result = None;
         for draw_result in draw_results {
             match draw_result {
+                DrawResult::LaneDrawResult(result) => lane_draw_result = Some(result),
                 DrawResult::ViewportDrawResult(viewport_draw_result) => {
                     self.viewport_draw_result = Some(viewport_draw_result);
-                    let player = self.player.lock().unwrap();
-                    if player.is_playing()
-                        && (player.current_time_b32() < viewport_draw_result.time_point_start
-                            || player.current_time_b32() >= viewport_draw_result.time_point_end)
+                    let time_b32 = self.playhead.time_b32;
+                    if self.playhead.is_playing()
+                        && (time_b32 < viewport_draw_result.time_point_start
+                            || time_b32 >= viewport_draw_result.time_point_end)
                     {
-                        let new_time = player.current_time_b32() - player.current_time_b32() % 32;
+                        let new_time = time_b32 - time_b32 % 32;
                         self.score_viewport = self.score_viewport.set_time_point(new_time);
                     }
                     
@@ -363,18 +708,28 @@ impl AppState {
             }
         }
 
+        // A new track color repaints everything, since the characters may not have changed
+        let recolored = lane_draw_result != self.lane_draw_result;
+        self.lane_draw_result = lane_draw_result;
         for y in 0..height {
             for x in 0..width {
                 let char = buffer[y as usize][x as usize];
                 if self.buffer.is_none()
+                    || recolored
                     || char != self.buffer.as_ref().unwrap()[y as usize][x as usize]
                 {
+                    let color = match lane_draw_result {
+                        Some(lane) if lane.header_row == y as usize || NOTE_CHARS.contains(&char) => lane.color,
+                        _ => style::Color::Reset,
+                    };
                     stdout
                         .queue(cursor::MoveTo(x, y))?
+                        .queue(style::SetForegroundColor(color))?
                         .queue(style::Print(char))?;
                 }
             }
         }
+        stdout.queue(style::ResetColor)?;
         stdout.flush()?;
 
         self.buffer = Some(buffer);
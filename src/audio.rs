@@ -1,60 +1,145 @@
-use crate::events::InputEvent;
-use crate::player::Player;
-use std::sync::mpsc;
+use crate::player::{Player, DEFAULT_MAX_VOICES};
+use anyhow::Context;
+use dawww_playback::{Backend, CaptureOptions, Monitor, Playback, PlaybackOptions, Source};
+use std::env;
 use std::{
     sync::{Arc, Mutex},
     thread,
 };
 use std::time::Duration;
-use cpal::traits::{HostTrait, DeviceTrait, StreamTrait};
+
+/// Where and how live playback plays
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioSettings {
+    pub sample_rate: u64,
+    pub options: PlaybackOptions,
+    /// How far recorded notes are moved back to line up with what was heard; `None`
+    /// uses the latency playback measures
+    pub latency_compensation: Option<Duration>,
+    /// Most notes live playback sounds at once
+    pub max_voices: usize,
+    /// Where audio is recorded from and how loud it's monitored
+    pub capture: CaptureOptions,
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        // Short blocks keep the cursor and note previews close to what's heard
+        Self {
+            sample_rate: 44100,
+            options: PlaybackOptions::low_latency(),
+            latency_compensation: None,
+            max_voices: DEFAULT_MAX_VOICES,
+            capture: CaptureOptions::default(),
+        }
+    }
+}
+
+impl AudioSettings {
+    /// The defaults, overridden by DAWWW_AUDIO_BACKEND (jack, alsa or system),
+    /// DAWWW_AUDIO_DEVICE (a device name `dawww devices` lists), DAWWW_AUDIO_RATE (Hz)
+    /// DAWWW_AUDIO_BUFFER (frames the device asks for at a time), DAWWW_AUDIO_QUEUE
+    /// (frames queued ahead of the device), DAWWW_AUDIO_LATENCY (milliseconds recorded
+    /// notes are moved back, instead of the measured latency), DAWWW_AUDIO_VOICES
+    /// (notes sounding at once), DAWWW_AUDIO_INPUT (the input device audio is recorded from)
+    /// and DAWWW_MONITOR_GAIN (dB the input is heard at while recording)
+    pub fn from_env() -> anyhow::Result<Self> {
+        let mut settings = Self::default();
+        if let Ok(backend) = env::var("DAWWW_AUDIO_BACKEND") {
+            settings.options = settings.options.with_backend(backend.parse::<Backend>()?);
+        }
+        if let Ok(device) = env::var("DAWWW_AUDIO_DEVICE") {
+            settings.options = settings.options.with_device(&device);
+        }
+        if let Ok(rate) = env::var("DAWWW_AUDIO_RATE") {
+            settings.sample_rate = rate.parse().with_context(|| format!("Invalid DAWWW_AUDIO_RATE '{}'", rate))?;
+        }
+        if let Ok(frames) = env::var("DAWWW_AUDIO_BUFFER") {
+            let frames = frames.parse().with_context(|| format!("Invalid DAWWW_AUDIO_BUFFER '{}'", frames))?;
+            settings.options = settings.options.with_device_buffer_frames(frames);
+        }
+        if let Ok(frames) = env::var("DAWWW_AUDIO_QUEUE") {
+            let frames = frames.parse().with_context(|| format!("Invalid DAWWW_AUDIO_QUEUE '{}'", frames))?;
+            settings.options.buffer_frames = frames;
+            settings.options.block_frames = settings.options.block_frames.min(frames);
+        }
+        if let Ok(ms) = env::var("DAWWW_AUDIO_LATENCY") {
+            let ms: f64 = ms.parse().with_context(|| format!("Invalid DAWWW_AUDIO_LATENCY '{}'", ms))?;
+            if !ms.is_finite() || ms < 0.0 {
+                anyhow::bail!("DAWWW_AUDIO_LATENCY can't be negative");
+            }
+            settings.latency_compensation = Some(Duration::from_secs_f64(ms / 1000.0));
+        }
+        if let Ok(voices) = env::var("DAWWW_AUDIO_VOICES") {
+            settings.max_voices = voices.parse().with_context(|| format!("Invalid DAWWW_AUDIO_VOICES '{}'", voices))?;
+            if settings.max_voices == 0 {
+                anyhow::bail!("DAWWW_AUDIO_VOICES has to be at least 1");
+            }
+        }
+        if let Ok(device) = env::var("DAWWW_AUDIO_INPUT") {
+            settings.capture = settings.capture.with_device(&device);
+        }
+        if let Ok(gain_db) = env::var("DAWWW_MONITOR_GAIN") {
+            let gain_db = gain_db.parse().with_context(|| format!("Invalid DAWWW_MONITOR_GAIN '{}'", gain_db))?;
+            settings.capture = settings.capture.with_monitor_gain_db(gain_db);
+        }
+        // Recording goes through the same audio system as playback
+        settings.capture.backend = settings.options.backend;
+        settings.options.validate()?;
+        Ok(settings)
+    }
+}
 
 pub fn audio_player(
     player: &Arc<Mutex<Player>>,
-    tx: mpsc::Sender<InputEvent>,
+    options: PlaybackOptions,
+    latency_compensation: Option<Duration>,
+    monitor: Arc<Mutex<Option<Monitor>>>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let host = cpal::default_host();
-    let device = host
-        .default_output_device()
-        .expect("Did not find default output device");
-    let config = device.default_output_config().unwrap();
-
-    let err_fn = |err| eprintln!("an error occurred on stream: {err}");
-    let stream_config: cpal::StreamConfig = config.into();
-    let channels = stream_config.channels as usize;
-
-    let player_clone = player.clone();
-    let stream = device.build_output_stream(
-        &stream_config,
-        move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
-            write_data(data, channels, &player_clone.clone(), &tx.clone());
-        },
-        err_fn,
-        None,
-    )?;
-    stream.play()?;
+    let source = PlayerSource { player: player.clone(), monitor, monitored: Vec::new() };
+    let playback = Playback::start_with(source, options)?;
+    log::info!("Playing on {} device '{}' at {} Hz", playback.backend(), playback.device(), playback.sample_rate());
 
+    let mut reported = false;
     loop {
         thread::sleep(Duration::from_millis(1000));
+        // Keep recordings lined up with the latency as measured, once the device says
+        let latency = latency_compensation.unwrap_or_else(|| playback.output_latency());
+        if !reported && playback.device_latency().is_some() {
+            reported = true;
+            log::info!("Output latency is about {:.1} ms", playback.output_latency().as_secs_f64() * 1000.0);
+        }
+        player.lock().unwrap().set_output_latency(latency);
     }
 }
 
-fn write_data(
-    output: &mut [f32],
-    channels: usize,
-    player: &Arc<Mutex<Player>>,
-    tx: &mpsc::Sender<InputEvent>,
-) {
-    let mut time_b32 = player.lock().unwrap().current_time_b32();
-    for frame in output.chunks_mut(channels) {
-        #[allow(clippy::cast_possible_truncation)]
-        let sample = player.lock().unwrap().next().unwrap() as f32;
-        let next_time_b32 = player.lock().unwrap().current_time_b32();
-        if next_time_b32 != time_b32 {
-            time_b32 = next_time_b32;
-            tx.send(InputEvent::PlayerBeatChange(time_b32)).unwrap();
-        }
-        for s in frame.iter_mut() {
-            *s = sample;
+/// Plays the player, with the audio input mixed in while it's monitored. The player
+/// reports the playhead itself, through `Player::on_playhead`.
+struct PlayerSource {
+    player: Arc<Mutex<Player>>,
+    monitor: Arc<Mutex<Option<Monitor>>>,
+    /// Scratch space for the monitored input
+    monitored: Vec<f32>,
+}
+
+impl Source for PlayerSource {
+    fn sample_rate(&self) -> u32 {
+        self.player.lock().unwrap().sample_rate() as u32
+    }
+
+    fn fill(&mut self, out: &mut [f32]) {
+        let mut player = self.player.lock().unwrap();
+        player.begin_block();
+        for frame in out.chunks_exact_mut(2) {
+            #[allow(clippy::cast_possible_truncation)]
+            let sample = player.next().unwrap_or(0.0) as f32;
+            frame.fill(sample);
+        }
+        drop(player);
+        if let Some(monitor) = self.monitor.lock().unwrap().as_mut() {
+            self.monitored.resize(out.len(), 0.0);
+            monitor.fill(&mut self.monitored);
+            out.iter_mut().zip(&self.monitored).for_each(|(out, input)| *out += input);
         }
     }
 }
@@ -2,27 +2,19 @@ use std::{
     fmt,
 };
 
-use dawww_core::pitch::Pitch;
-use crate::selection_range::SelectionRange;
+use dawww_core::pitch::{Pitch, PitchRange};
+use dawww_core::{Selection, TimeRange};
 
 #[derive(Clone, Copy)]
 pub struct Cursor {
     pitch: Pitch,
     time_point: u64,
-    visibility: Visibility,
     mode: CursorMode,
 }
 
-#[derive(Clone, Copy, PartialEq, Eq)]
-enum Visibility {
-    Hidden,
-    Visible,
-}
-
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum CursorMode {
     Move,
-    Insert(u64),        // Start insert onset
     Select(Pitch, u64), // Start select onset and pitch
     Yank,
     // SELECT
@@ -35,7 +27,6 @@ impl Cursor {
         Cursor {
             pitch,
             time_point,
-            visibility: Visibility::Hidden,
             mode: CursorMode::Move,
         }
     }
@@ -49,13 +40,6 @@ impl Cursor {
     pub fn left(self, duration: u64) -> Cursor {
         let mut next_cursor = self;
 
-        // Don't allow moving cursor before onset on insert.
-        if let CursorMode::Insert(onset_b32) = self.mode {
-            if self.time_point == onset_b32 {
-                return next_cursor;
-            }
-        }
-
         if self.time_point >= duration {
             next_cursor.time_point -= duration;
         } else {
@@ -74,47 +58,25 @@ impl Cursor {
 
     pub fn up(self) -> Cursor {
         let mut next_cursor = self;
-        let next_pitch = self.pitch.next();
-        if next_pitch.is_some() {
-            next_cursor.pitch = next_pitch.unwrap();
+        if let Some(next_pitch) = self.pitch.next() {
+            next_cursor.pitch = next_pitch;
         }
         next_cursor
     }
 
     pub fn down(self) -> Cursor {
         let mut next_cursor = self;
-        let prev_pitch = self.pitch.prev();
-        if prev_pitch.is_some() {
-            next_cursor.pitch = prev_pitch.unwrap();
+        if let Some(prev_pitch) = self.pitch.prev() {
+            next_cursor.pitch = prev_pitch;
         }
         next_cursor
     }
 
-    pub fn show(self) -> Cursor {
-        let mut next_cursor = self;
-        next_cursor.visibility = Visibility::Visible;
-        next_cursor
-    }
-
-    pub fn hide(self) -> Cursor {
-        let mut next_cursor = self;
-        next_cursor.visibility = Visibility::Hidden;
-        next_cursor
-    }
-
-    pub fn visible(self) -> bool {
-        // self.visibility == Visibility::Visible
-        true
-    }
-
     pub fn visible_at(self, pitch: Pitch, time_point: u64) -> bool {
         match self.mode {
             CursorMode::Move | CursorMode::Yank => {
                 time_point == self.time_point && self.pitch == pitch
             }
-            CursorMode::Insert(onset_b32) => {
-                time_point >= onset_b32 && time_point <= self.time_point && self.pitch == pitch
-            }
             CursorMode::Select(start_pitch, onset_b32) => {
                 let (low_pitch, high_pitch) = if self.pitch > start_pitch {
                     (start_pitch, self.pitch)
@@ -141,18 +103,6 @@ impl Cursor {
         self.mode
     }
 
-    pub fn start_insert(self) -> Cursor {
-        let mut cursor = self;
-        cursor.mode = CursorMode::Insert(self.time_point);
-        cursor
-    }
-
-    pub fn end_insert(self) -> Cursor {
-        let mut cursor = self;
-        cursor.mode = CursorMode::Move;
-        cursor
-    }
-
     pub fn start_select(self) -> Cursor {
         let mut cursor = self;
         match cursor.mode {
@@ -186,24 +136,15 @@ impl Cursor {
         cursor
     }
 
-    pub fn selection_range(self) -> Option<SelectionRange> {
+    /// The area between where selecting started and the cursor, including the cursor's own position
+    pub fn selection(self) -> Option<Selection> {
         if let CursorMode::Select(pitch, time_point_b32) = self.mode {
-            let (time_point_start_b32, time_point_end_b32) = if time_point_b32 < self.time_point {
-                (time_point_b32, self.time_point)
-            } else {
-                (self.time_point, time_point_b32)
-            };
-            let (pitch_low, pitch_high) = if pitch < self.pitch {
-                (pitch, self.pitch)
-            } else {
-                (self.pitch, pitch)
-            };
-            return Some(SelectionRange {
-                time_point_start_b32,
-                time_point_end_b32,
-                pitch_low,
-                pitch_high,
-            });
+            let start = time_point_b32.min(self.time_point);
+            let end = time_point_b32.max(self.time_point) + 1;
+            return Some(Selection::new(
+                TimeRange::new(start as u32, end as u32),
+                PitchRange::new(pitch, self.pitch),
+            ));
         }
         None
     }
@@ -0,0 +1,340 @@
+use crate::audio::AudioSettings;
+use anyhow::{bail, Context, Result};
+use dawww_core::{time_from_32nds, time_to_32nds, DawFile};
+use dawww_playback::{Invalidator, Playback, RenderAhead, Source};
+use dawww_render::AudioEngine;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+/// Where `dawww serve` listens when no address is given
+pub const DEFAULT_DAEMON_ADDR: &str = "127.0.0.1:7771";
+
+/// Seconds of the song rendered ahead of the playhead
+const AHEAD_SECONDS: u32 = 1;
+
+/// Frames the song is rendered ahead in at a time
+const BLOCK_FRAMES: usize = 1024;
+
+/// Something a client asks the playback daemon to do
+#[derive(Debug, Clone, PartialEq)]
+pub enum DaemonCommand {
+    Play,
+    Pause,
+    /// Pause and go back to the start
+    Stop,
+    /// Move the playhead to a 32nd
+    Seek(u64),
+    /// Read the song again, from another file if one is given, carrying on from where it was
+    Reload(Option<PathBuf>),
+    /// Report whether it's playing and where
+    Status,
+    /// Stop playing and exit
+    Quit,
+}
+
+impl DaemonCommand {
+    /// The command on a line of the protocol:
+    ///
+    /// * `play`, `pause`, `stop`, `status`, `quit`
+    /// * `seek <bar.32nd>` or `seek <32nd>`
+    /// * `reload [path]`
+    pub fn parse(line: &str) -> Result<DaemonCommand> {
+        let line = line.trim();
+        let (name, arg) = match line.split_once(char::is_whitespace) {
+            Some((name, arg)) => (name, Some(arg.trim())),
+            None => (line, None),
+        };
+        Ok(match (name, arg) {
+            ("play", None) => DaemonCommand::Play,
+            ("pause", None) => DaemonCommand::Pause,
+            ("stop", None) => DaemonCommand::Stop,
+            ("status", None) => DaemonCommand::Status,
+            ("quit", None) => DaemonCommand::Quit,
+            ("seek", Some(time)) if time.contains('.') => DaemonCommand::Seek(u64::from(time_to_32nds(time)?)),
+            ("seek", Some(b32)) => DaemonCommand::Seek(b32.parse().with_context(|| format!("Can't seek to '{}'", b32))?),
+            ("seek", None) => bail!("seek needs a time, as bar.32nd or 32nds"),
+            ("reload", path) => DaemonCommand::Reload(path.map(PathBuf::from)),
+            ("play" | "pause" | "stop" | "status" | "quit", Some(_)) => bail!("{} takes no arguments", name),
+            _ => bail!("Unknown command '{}'", line),
+        })
+    }
+}
+
+/// The song as the daemon plays it: silence while paused, and the song from the playhead
+/// while playing, rendered ahead of it so edits can be heard without stopping
+pub struct Transport {
+    engine: Arc<Mutex<AudioEngine>>,
+    ahead: RenderAhead,
+    invalidator: Invalidator,
+    project_dir: PathBuf,
+    playing: bool,
+    /// Frame of the song the playhead is on
+    position: u64,
+    samples_per_b32: f64,
+}
+
+impl Transport {
+    /// Get `daw_file` ready to play at `sample_rate`, with sample paths relative to
+    /// `project_dir`
+    pub fn render(daw_file: DawFile, project_dir: &Path, sample_rate: u32) -> Result<Self> {
+        let samples_per_b32 = samples_per_b32(&daw_file, sample_rate);
+        let mut engine = AudioEngine::new(daw_file)
+            .with_project_dir(project_dir.to_path_buf())
+            .with_sample_rate(sample_rate);
+        engine.prepare()?;
+        let engine = Arc::new(Mutex::new(engine));
+        let ahead = RenderAhead::start(engine.clone(), (sample_rate * AHEAD_SECONDS) as usize, BLOCK_FRAMES)?;
+        let invalidator = ahead.invalidator();
+        Ok(Self {
+            engine,
+            ahead,
+            invalidator,
+            project_dir: project_dir.to_path_buf(),
+            playing: false,
+            position: 0,
+            samples_per_b32,
+        })
+    }
+
+    /// The 32nd the playhead is in
+    fn position_b32(&self) -> u64 {
+        (self.position as f64 / self.samples_per_b32) as u64
+    }
+
+    fn seek_b32(&mut self, b32: u64) {
+        self.position = (b32 as f64 * self.samples_per_b32).round() as u64;
+        self.invalidator.seek(self.position);
+    }
+}
+
+fn samples_per_b32(daw_file: &DawFile, sample_rate: u32) -> f64 {
+    f64::from(sample_rate) * 60.0 / (f64::from(daw_file.bpm) * 8.0)
+}
+
+impl Source for Transport {
+    fn sample_rate(&self) -> u32 {
+        self.ahead.sample_rate()
+    }
+
+    fn fill(&mut self, out: &mut [f32]) {
+        if self.playing && self.ahead.is_finished() {
+            self.playing = false;
+        }
+        if !self.playing {
+            out.fill(0.0);
+            return;
+        }
+        self.ahead.fill(out);
+        // Past the end there's nothing rendered to say where the playhead is
+        self.position = self.ahead.position().unwrap_or(self.position + out.len() as u64 / 2);
+    }
+}
+
+/// Runs commands against the transport playing the song from `path`
+pub struct Daemon {
+    transport: Arc<Mutex<Transport>>,
+    path: PathBuf,
+}
+
+impl Daemon {
+    /// Render the song in `path` at `sample_rate`, paused at the start
+    pub fn load(path: PathBuf, sample_rate: u32) -> Result<Self> {
+        let transport = Self::render(&path, sample_rate)?;
+        Ok(Self { transport: Arc::new(Mutex::new(transport)), path })
+    }
+
+    fn render(path: &Path, sample_rate: u32) -> Result<Transport> {
+        Transport::render(Self::read(path)?, project_dir(path), sample_rate)
+    }
+
+    fn read(path: &Path) -> Result<DawFile> {
+        let daw_file = dawww_core::read_daw_file(&path.to_path_buf())?;
+        for issue in daw_file.sample_issues() {
+            eprintln!("Sample problem in {}: {:?}", path.display(), issue);
+        }
+        Ok(daw_file)
+    }
+
+    /// Run `command`, returning what to tell the client
+    pub fn handle(&mut self, command: DaemonCommand) -> Result<String> {
+        let mut transport = self.transport.lock().unwrap();
+        match command {
+            DaemonCommand::Play => {
+                if transport.ahead.is_finished() {
+                    transport.seek_b32(0);
+                }
+                transport.playing = true;
+            }
+            DaemonCommand::Pause => transport.playing = false,
+            DaemonCommand::Stop => {
+                transport.playing = false;
+                transport.seek_b32(0);
+            }
+            DaemonCommand::Seek(b32) => transport.seek_b32(b32),
+            DaemonCommand::Reload(path) => {
+                let path = path.unwrap_or_else(|| self.path.clone());
+                // Render while the old song carries on playing
+                let sample_rate = transport.sample_rate();
+                let engine = transport.engine.clone();
+                let same_project = transport.project_dir == project_dir(&path);
+                drop(transport);
+                if same_project {
+                    // The edited song takes over in place, and only what it changed is
+                    // rendered again
+                    let daw_file = Self::read(&path)?;
+                    let samples_per_b32 = samples_per_b32(&daw_file, sample_rate);
+                    let changed_from = engine.lock().unwrap().update(daw_file)?;
+                    transport = self.transport.lock().unwrap();
+                    if samples_per_b32 != transport.samples_per_b32 {
+                        let b32 = transport.position_b32();
+                        transport.samples_per_b32 = samples_per_b32;
+                        transport.seek_b32(b32);
+                    } else if let Some(frame) = changed_from {
+                        transport.invalidator.invalidate(frame as u64..u64::MAX);
+                    }
+                } else {
+                    let mut reloaded = Self::render(&path, sample_rate)?;
+                    transport = self.transport.lock().unwrap();
+                    reloaded.playing = transport.playing;
+                    reloaded.seek_b32(transport.position_b32());
+                    *transport = reloaded;
+                }
+                self.path = path;
+            }
+            DaemonCommand::Status | DaemonCommand::Quit => {}
+        }
+        let state = if transport.playing { "playing" } else { "paused" };
+        Ok(format!("{} {}", state, time_from_32nds(transport.position_b32() as u32)))
+    }
+}
+
+/// The directory sample paths in the song at `path` are relative to
+fn project_dir(path: &Path) -> &Path {
+    path.parent().unwrap_or(Path::new(""))
+}
+
+/// Play the song in `path` and take commands from clients connecting to `address`, one per
+/// line, until one sends `quit`. Each command is answered with `ok <playing|paused>
+/// <bar.32nd>` or `error <reason>`.
+pub fn serve(path: PathBuf, address: &str, settings: AudioSettings) -> Result<()> {
+    let daemon = Daemon::load(path, settings.sample_rate as u32)?;
+    let playback = Playback::start_with(daemon.transport.clone(), settings.options)?;
+    let listener = TcpListener::bind(address).with_context(|| format!("Couldn't listen on {}", address))?;
+    eprintln!("Playing on '{}', listening on {}", playback.device(), address);
+
+    let daemon = Arc::new(Mutex::new(daemon));
+    let (quit_tx, quit_rx) = mpsc::channel();
+    thread::Builder::new().name("dawww-daemon".to_string()).spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else {
+                continue;
+            };
+            let (daemon, quit_tx) = (daemon.clone(), quit_tx.clone());
+            thread::spawn(move || {
+                if let Err(e) = serve_client(stream, &daemon, &quit_tx) {
+                    eprintln!("Client disconnected: {}", e);
+                }
+            });
+        }
+    })?;
+    let _ = quit_rx.recv();
+    Ok(())
+}
+
+/// Answer a client's commands until it disconnects or sends `quit`
+fn serve_client(stream: TcpStream, daemon: &Mutex<Daemon>, quit_tx: &mpsc::Sender<()>) -> Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let command = DaemonCommand::parse(&line);
+        let quit = matches!(command, Ok(DaemonCommand::Quit));
+        match command.and_then(|command| daemon.lock().unwrap().handle(command)) {
+            Ok(reply) => writeln!(writer, "ok {}", reply)?,
+            Err(e) => writeln!(writer, "error {}", e)?,
+        }
+        if quit {
+            let _ = quit_tx.send(());
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dawww_core::instrument::SubtractiveParams;
+    use dawww_core::pitch::{Pitch, Tone};
+    use dawww_core::{Instrument, Note};
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(DaemonCommand::parse("play").unwrap(), DaemonCommand::Play);
+        assert_eq!(DaemonCommand::parse(" stop \r").unwrap(), DaemonCommand::Stop);
+        assert_eq!(DaemonCommand::parse("seek 3.8").unwrap(), DaemonCommand::Seek(72));
+        assert_eq!(DaemonCommand::parse("seek 40").unwrap(), DaemonCommand::Seek(40));
+        assert_eq!(DaemonCommand::parse("reload").unwrap(), DaemonCommand::Reload(None));
+        assert_eq!(DaemonCommand::parse("reload other.daw.json").unwrap(), DaemonCommand::Reload(Some(PathBuf::from("other.daw.json"))));
+        assert!(DaemonCommand::parse("seek").is_err());
+        assert!(DaemonCommand::parse("seek 0.0").is_err());
+        assert!(DaemonCommand::parse("play now").is_err());
+        assert!(DaemonCommand::parse("rewind").is_err());
+    }
+
+    #[test]
+    fn test_transport() {
+        let mut daw_file = DawFile::new("Test Song".to_string());
+        daw_file.add_instrument("synth1".to_string(), Instrument::new_subtractive(SubtractiveParams::default())).unwrap();
+        daw_file.add_note("2.0", "synth1", Note::new(Pitch::new(Tone::A, 4), 32)).unwrap();
+        let transport = Transport::render(daw_file, Path::new(""), 8000).unwrap();
+        let mut daemon = Daemon { transport: Arc::new(Mutex::new(transport)), path: PathBuf::from("song.daw.json") };
+
+        assert_eq!(daemon.handle(DaemonCommand::Status).unwrap(), "paused 1.0");
+        assert_eq!(daemon.handle(DaemonCommand::Seek(40)).unwrap(), "paused 2.8");
+        let mut out = vec![1.0; 64];
+        daemon.transport.lock().unwrap().fill(&mut out);
+        assert!(out.iter().all(|&sample| sample == 0.0));
+        assert_eq!(daemon.handle(DaemonCommand::Play).unwrap(), "playing 2.8");
+        assert_eq!(daemon.handle(DaemonCommand::Stop).unwrap(), "paused 1.0");
+        assert!(daemon.handle(DaemonCommand::Reload(Some(PathBuf::from("missing.daw.json")))).is_err());
+        assert_eq!(daemon.path, PathBuf::from("song.daw.json"));
+    }
+
+    #[test]
+    fn test_edits_while_playing_are_heard() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("song.daw.json");
+        let mut daw_file = DawFile::new("Test Song".to_string());
+        daw_file.add_instrument("synth1".to_string(), Instrument::new_subtractive(SubtractiveParams::default())).unwrap();
+        daw_file.add_note("1.0", "synth1", Note::new(Pitch::new(Tone::A, 4), 8)).unwrap();
+        daw_file.add_note("4.0", "synth1", Note::new(Pitch::new(Tone::A, 4), 8)).unwrap();
+        daw_file.save(&path).unwrap();
+        let mut daemon = Daemon::load(path.clone(), 8000).unwrap();
+        daemon.handle(DaemonCommand::Play).unwrap();
+
+        // Play up to just before bar 2, at 16000 frames, with the second past it rendered
+        let play = |daemon: &Daemon, frames: usize| {
+            let mut out = vec![0.0; frames * 2];
+            daemon.transport.lock().unwrap().fill(&mut out);
+            out
+        };
+        play(&daemon, 12000);
+        while daemon.transport.lock().unwrap().ahead.buffered_frames() < 8000 {
+            thread::sleep(std::time::Duration::from_millis(1));
+        }
+
+        // A note added to bar 2 is heard, not the silence rendered before it was
+        daw_file.add_note("2.0", "synth1", Note::new(Pitch::new(Tone::E, 5), 8)).unwrap();
+        daw_file.save(&path).unwrap();
+        assert_eq!(daemon.handle(DaemonCommand::Reload(None)).unwrap(), "playing 1.24");
+        let out = play(&daemon, 6000);
+        assert!(out[..8000].iter().all(|&sample| sample == 0.0));
+        assert!(out[8000..].iter().any(|&sample| sample.abs() > 0.01));
+    }
+}
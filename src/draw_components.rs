@@ -1,19 +1,25 @@
-use dawww_core::pitch::Pitch;
+use crossterm::style::Color;
 
 pub mod score_draw_component;
 pub mod status_bar_component;
 
 #[derive(Clone, Copy)]
 pub struct ViewportDrawResult {
-    pub pitch_low: Pitch,
-    pub pitch_high: Pitch,
     pub time_point_start: u64, // Inclusive
     pub time_point_end: u64,   // Exclusive
 }
 
+/// The score's track header, drawn in the track's color along with its notes
+#[derive(Clone, Copy, PartialEq)]
+pub struct LaneDrawResult {
+    pub header_row: usize,
+    pub color: Color,
+}
+
 #[derive(Clone, Copy)]
 pub enum DrawResult {
     ViewportDrawResult(ViewportDrawResult),
+    LaneDrawResult(LaneDrawResult),
 }
 
 pub trait DrawComponent {
@@ -80,9 +86,9 @@ impl DrawComponent for Window {
     fn draw(&self, buffer: &mut Vec<Vec<char>>, pos: &Position) -> Vec<DrawResult> {
         let mut results = vec![];
         for component in &self.components {
-            results.append(component.draw(buffer, &pos).as_mut());
+            results.append(component.draw(buffer, pos).as_mut());
         }
-        return results;
+        results
     }
 }
 
@@ -98,20 +104,24 @@ impl BoxDrawComponent {
 
 impl DrawComponent for BoxDrawComponent {
     fn draw(&self, buffer: &mut Vec<Vec<char>>, pos: &Position) -> Vec<DrawResult> {
+        // A terminal too small to have room for the box draws nothing
+        if pos.h == 0 || pos.w == 0 {
+            return vec![];
+        }
         for x in pos.x + 1..pos.right() {
             self.wb(buffer, pos, x, 0, BOX_HORIZONTAL);
-            self.wb(buffer, pos, x, pos.h - 1, BOX_HORIZONTAL);
+            self.wb(buffer, pos, x, pos.h.saturating_sub(1), BOX_HORIZONTAL);
         }
-        for y in pos.y + 1..pos.bottom() {
-            buffer[y][0] = BOX_VERTICAL;
-            buffer[y][pos.x + pos.w - 1] = BOX_VERTICAL;
+        for row in buffer.iter_mut().take(pos.bottom()).skip(pos.y + 1) {
+            row[0] = BOX_VERTICAL;
+            row[pos.x + pos.w - 1] = BOX_VERTICAL;
         }
         self.wb(buffer, pos, pos.x, pos.y, BOX_TOP_LEFT);
         self.wb(buffer, pos, pos.right(), pos.y, BOX_TOP_RIGHT);
         self.wb(buffer, pos, pos.x, pos.bottom(), BOX_BOTTOM_LEFT);
         self.wb(buffer, pos, pos.right(), pos.bottom(), BOX_BOTTOM_RIGHT);
 
-        return self.component.draw(buffer, pos);
+        self.component.draw(buffer, pos)
     }
 }
 
@@ -143,6 +153,9 @@ impl VSplitDrawComponent {
 
 impl DrawComponent for VSplitDrawComponent {
     fn draw(&self, buffer: &mut Vec<Vec<char>>, pos: &Position) -> Vec<DrawResult> {
+        if pos.h == 0 {
+            return vec![];
+        }
         let pos_top = match self.style {
             VSplitStyle::HalfWithDivider => Position {
                 x: pos.x + 1,
@@ -154,7 +167,7 @@ impl DrawComponent for VSplitDrawComponent {
                 x: pos.x,
                 y: pos.y,
                 w: pos.w,
-                h: pos.h - 1,
+                h: pos.h.saturating_sub(1),
             },
         };
 
@@ -167,7 +180,7 @@ impl DrawComponent for VSplitDrawComponent {
             },
             VSplitStyle::StatusBarNoDivider => Position {
                 x: pos.x,
-                y: pos.y + pos.h - 1,
+                y: pos.y + pos.h.saturating_sub(1),
                 w: pos.w,
                 h: 1,
             },
@@ -197,17 +210,21 @@ impl DrawComponent for NullComponent {
     }
 }
 
-pub struct FillComponent {
-    pub value: char,
-}
 
-impl DrawComponent for FillComponent {
-    fn draw(&self, buffer: &mut Vec<Vec<char>>, pos: &Position) -> Vec<DrawResult> {
-        for x in 0..pos.w {
-            for y in 0..pos.h {
-                self.wb(buffer, pos, x, y, self.value);
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_terminal_with_no_rows_draws_nothing() {
+        let component = VSplitDrawComponent::new(
+            VSplitStyle::StatusBarNoDivider,
+            Box::new(BoxDrawComponent::new(Box::new(NullComponent {}))),
+            Box::new(NullComponent {}),
+        );
+        let mut buffer = vec![vec![' '; 10]; 1];
+        for h in [0, 1] {
+            assert!(component.draw(&mut buffer, &Position { x: 0, y: 0, w: 10, h }).is_empty());
         }
-        vec![]
     }
 }
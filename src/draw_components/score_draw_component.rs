@@ -1,23 +1,26 @@
 use std::collections::HashMap;
-use std::sync::{mpsc, Arc, Mutex};
+use std::sync::{Arc, Mutex};
 
-use super::{DrawComponent, DrawResult, ViewportDrawResult};
+use super::{DrawComponent, DrawResult, LaneDrawResult, ViewportDrawResult};
+use crossterm::style::Color;
 use crate::cursor::Cursor;
 use crate::draw_components::Position;
-use crate::events::InputEvent;
 use dawww_core::pitch::Pitch;
-use crate::player::PlayState;
 use crate::score::{ActiveNote, NoteState, Score};
 use crate::score_viewport::ScoreViewport;
 use crate::selection_buffer::SelectionBuffer;
 use log::debug;
 use crate::loop_state::{LoopState, LoopMode};
 
+/// Characters notes are drawn with, shown in the track's color
+pub const NOTE_CHARS: [char; 3] = ['█', '░', '▒'];
+
+/// Track colors, given out in name order
+const LANE_COLORS: [Color; 6] = [Color::Cyan, Color::Magenta, Color::Yellow, Color::Green, Color::Blue, Color::Red];
+
 pub struct ScoreDrawComponent {
     score: Arc<Mutex<Score>>,
-    play_state: PlayState,
     score_viewport: ScoreViewport,
-    event_tx: mpsc::Sender<InputEvent>,
     cursor: Cursor,
     selection_buffer: SelectionBuffer,
     loop_state: LoopState,
@@ -30,6 +33,13 @@ impl DrawComponent for ScoreDrawComponent {
             pos.x, pos.y, pos.w, pos.h
         );
 
+        let lane_draw_result = self.draw_lane_header(buffer, pos);
+        let pos = &Position {
+            x: pos.x,
+            y: pos.y + 1,
+            w: pos.w,
+            h: pos.h - 1,
+        };
         self.draw_pitches(buffer, pos);
         let viewport_draw_result = self.draw_score(
             buffer,
@@ -40,49 +50,38 @@ impl DrawComponent for ScoreDrawComponent {
                 h: pos.h,
             },
         );
-        vec![DrawResult::ViewportDrawResult(viewport_draw_result)]
+        vec![
+            DrawResult::LaneDrawResult(lane_draw_result),
+            DrawResult::ViewportDrawResult(viewport_draw_result),
+        ]
     }
 }
 
 impl ScoreDrawComponent {
     pub fn new(
         score: Arc<Mutex<Score>>,
-        play_state: PlayState,
         score_viewport: ScoreViewport,
-        tx: mpsc::Sender<InputEvent>,
         cursor: Cursor,
         selection_buffer: SelectionBuffer,
         loop_state: LoopState,
     ) -> ScoreDrawComponent {
         ScoreDrawComponent {
             score,
-            play_state,
             score_viewport,
-            event_tx: tx,
             cursor,
             selection_buffer,
             loop_state,
         }
     }
 
+    /// Pitch rows from top to bottom, with the middle pitch just below the center
     fn visible_pitches(&self, pos: &Position) -> Vec<Pitch> {
-        let num_pitches_to_display = pos.h - 1;
+        let half = ((pos.h - 1) / 2) as i32;
 
         let middle_pitch = self.score_viewport.middle_pitch;
-        let mut pitches: Vec<Pitch> = vec![middle_pitch];
-        for _ in 0..(num_pitches_to_display / 2) {
-            if let Some(prev_pitch) = pitches.last().unwrap().prev() {
-                pitches.push(prev_pitch);
-            }
-        }
-        pitches.reverse();
-        for _ in 0..(num_pitches_to_display / 2) - 1 {
-            if let Some(next_pitch) = pitches.last().unwrap().next() {
-                pitches.push(next_pitch);
-            }
-        }
-        pitches.reverse();
-        pitches
+        let low = middle_pitch.transpose(-half).unwrap_or(Pitch::LOWEST);
+        let high = middle_pitch.transpose((half - 1).max(0)).unwrap_or(Pitch::HIGHEST);
+        Pitch::range(low, high).rev().collect()
     }
 
     fn draw_score(&self, buffer: &mut Vec<Vec<char>>, pos: &super::Position) -> ViewportDrawResult {
@@ -188,10 +187,11 @@ impl ScoreDrawComponent {
             }
 
             for ((row, _), state) in col_states {
+                let [onset, sustain, release] = NOTE_CHARS;
                 let note_char = match state {
-                    NoteState::Onset => '█',
-                    NoteState::Sustain => '░',
-                    NoteState::Release => '▒',
+                    NoteState::Onset => onset,
+                    NoteState::Sustain => sustain,
+                    NoteState::Release => release,
                 };
                 self.wb(buffer, pos, col, row, note_char);
             }
@@ -209,13 +209,27 @@ impl ScoreDrawComponent {
         }
 
         ViewportDrawResult {
-            pitch_low: *pitches.last().unwrap(),
-            pitch_high: *pitches.first().unwrap(),
             time_point_start: self.score_viewport.time_point,
             time_point_end: time_point,
         }
     }
 
+    /// Name the tracks along the top row, with the one being edited in brackets
+    fn draw_lane_header(&self, buffer: &mut Vec<Vec<char>>, pos: &Position) -> LaneDrawResult {
+        let score = self.score.lock().unwrap();
+        let instruments = score.instruments();
+        let index = instruments.iter().position(|id| id == score.instrument()).unwrap_or(0);
+        let header: Vec<String> = instruments
+            .iter()
+            .map(|id| if id == score.instrument() { format!("[{}]", id) } else { format!(" {} ", id) })
+            .collect();
+        self.wb_string(buffer, pos, 0, 0, format!("TRACK {}", header.join(" ")));
+        LaneDrawResult {
+            header_row: pos.y,
+            color: LANE_COLORS[index % LANE_COLORS.len()],
+        }
+    }
+
     fn draw_pitches(&self, buffer: &mut Vec<Vec<char>>, pos: &super::Position) {
         for (i, pitch) in self.visible_pitches(pos).iter().enumerate() {
             self.wb_string(buffer, pos, 0, i, pitch.as_str());
@@ -4,10 +4,46 @@ use crate::draw_components::Position;
 use crate::score_viewport::ScoreViewport;
 use crate::loop_state::{LoopState, LoopMode};
 
+/// What's being recorded, for the status bar
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RecordStatus {
+    /// Notes from the MIDI keyboard
+    pub notes: bool,
+    /// The audio input
+    pub audio: bool,
+    /// Gain the audio input is monitored at while it's recorded, if it is
+    pub monitor_gain_db: Option<f64>,
+}
+
+/// A line of typed text shown in place of the status bar, such as the command palette
+pub struct PromptComponent {
+    text: String,
+}
+
+impl PromptComponent {
+    pub fn new(text: String) -> PromptComponent {
+        PromptComponent { text }
+    }
+}
+
+impl DrawComponent for PromptComponent {
+    fn draw(&self, buffer: &mut Vec<Vec<char>>, pos: &Position) -> Vec<DrawResult> {
+        self.wb_string(buffer, pos, 0, 0, " ".repeat(pos.w));
+        self.wb_string(buffer, pos, 0, 0, format!("{}_", self.text));
+        vec![]
+    }
+}
+
 pub struct StatusBarComponent {
     cursor: Cursor,
     score_viewport: ScoreViewport,
     loop_state: LoopState,
+    /// Whether notes played on a MIDI keyboard are entered into the score
+    step_entry: bool,
+    recording: RecordStatus,
+    /// Whether recorded notes are snapped to the resolution
+    quantize: bool,
+    muted: bool,
 }
 
 impl DrawComponent for StatusBarComponent {
@@ -25,9 +61,25 @@ impl DrawComponent for StatusBarComponent {
             }
         };
 
+        let record_str = match (self.recording.notes, self.recording.audio) {
+            (false, false) => String::new(),
+            (true, false) => "[REC] ".to_string(),
+            (notes, true) => {
+                let monitor = self.recording.monitor_gain_db.map_or("OFF".to_string(), |db| format!("{:+.0} dB", db));
+                format!("[REC {}AUDIO, MONITOR {}] ", if notes { "NOTES+" } else { "" }, monitor)
+            }
+        };
+        let punch_str = match self.loop_state.punch_range() {
+            Some(punch) => format!(" [PUNCH {}-{}]", punch.start_b32, punch.end_b32),
+            None if self.loop_state.punch => " [PUNCH]".to_string(),
+            None => String::new(),
+        };
+        let mute_str = if self.muted { "[MUTE] " } else { "" };
+        let quantize_str = if self.quantize { "" } else { "[Q:OFF] " };
+        let step_str = if self.step_entry { "[STEP] " } else { "" };
         let status_str = format!(
-            "{} [Cursor: {}] [Score Viewport: {}]",
-            loop_str, self.cursor, self.score_viewport
+            "{}{}{}{}{}{} [Cursor: {}] [Score Viewport: {}]",
+            record_str, mute_str, quantize_str, step_str, loop_str, punch_str, self.cursor, self.score_viewport
         );
         self.wb_string(buffer, pos, 0, 0, status_str);
         vec![]
@@ -39,11 +91,19 @@ impl StatusBarComponent {
         cursor: Cursor,
         score_viewport: ScoreViewport,
         loop_state: LoopState,
+        step_entry: bool,
+        recording: RecordStatus,
+        quantize: bool,
+        muted: bool,
     ) -> StatusBarComponent {
         StatusBarComponent {
             cursor,
             score_viewport,
             loop_state,
+            step_entry,
+            recording,
+            quantize,
+            muted,
         }
     }
 }
@@ -1,4 +1,6 @@
 use crossterm::event::{poll, read, Event, KeyCode};
+use crate::playhead::Playhead;
+use dawww_core::pitch::Pitch;
 use std::io;
 use std::sync::mpsc;
 use std::time::Duration;
@@ -8,11 +10,9 @@ pub enum InputEvent {
     ViewerBarPrevious,
     ViewerResolutionIncrease,
     ViewerResolutionDecrease,
-    ViewerOctaveIncrease,
-    ViewerOctaveDecrease,
     PlayerTogglePlayback,
     Quit,
-    PlayerBeatChange(u64),
+    PlayerPosition(Playhead),
     CursorUp,
     CursorDown,
     CursorLeft,
@@ -25,23 +25,64 @@ pub enum InputEvent {
     Delete,
     ToggleLoopMode,
     SetLoopTimes,
+    TogglePunch,
     SaveSong,
     SelectIn,
+    ToggleStepEntry,
+    ToggleRecording,
+    ToggleAudioRecording,
+    MonitorGainUp,
+    MonitorGainDown,
+    ToggleQuantize,
+    ToggleMute,
+    CycleInstrument,
+    AuditionChord,
+    TransportRewind,
+    TransportFastForward,
+    TransportReturnToStart,
+    TransportNextMarker,
+    /// What's been typed into the command palette so far, or `None` once it's closed
+    Palette(Option<String>),
+    /// Run a command typed into the palette
+    RunPalette(String),
+    MidiNoteOn { pitch: Pitch, velocity: u8 },
+    MidiNoteOff(Pitch),
+    #[cfg(feature = "osc")]
+    Osc(crate::osc::OscCommand),
 }
 
 pub fn capture_input(tx: &mpsc::Sender<InputEvent>) -> io::Result<()> {
     crossterm::terminal::enable_raw_mode()?;
     let mut alt_pressed = false;
+    // Text typed into the command palette while it's open
+    let mut palette: Option<String> = None;
 
     loop {
         if poll(Duration::from_millis(500))? {
             if let Event::Key(event) = read()? {
-                // Unmapped:
-                // 3, 4, q, w, x
+                if let Some(text) = palette.as_mut() {
+                    match event.code {
+                        KeyCode::Char(c) => text.push(c),
+                        KeyCode::Backspace => {
+                            text.pop();
+                        }
+                        KeyCode::Enter => {
+                            tx.send(InputEvent::RunPalette(text.clone())).unwrap();
+                            palette = None;
+                        }
+                        KeyCode::Esc => palette = None,
+                        _ => (),
+                    }
+                    tx.send(InputEvent::Palette(palette.clone())).unwrap();
+                    continue;
+                }
                 match event.code {
                     // Core navigation and alt key
                     KeyCode::Char('1') => tx.send(InputEvent::Cancel).unwrap(),
                     KeyCode::Char('2') => alt_pressed = !alt_pressed,
+                    KeyCode::Char('3') => tx.send(InputEvent::ToggleMute).unwrap(),
+                    KeyCode::Char('4') => tx.send(InputEvent::AuditionChord).unwrap(),
+                    KeyCode::Char('t') => tx.send(InputEvent::CycleInstrument).unwrap(),
 
                     // Arrow keys - Cursor movement or Viewport navigation
                     KeyCode::Left => {
@@ -79,6 +120,12 @@ pub fn capture_input(tx: &mpsc::Sender<InputEvent>) -> io::Result<()> {
 
                     // Most common operations - top row right side
                     KeyCode::Char('r') => tx.send(InputEvent::InsertNote).unwrap(),
+                    KeyCode::Char('w') => tx.send(InputEvent::ToggleStepEntry).unwrap(),
+                    KeyCode::Char('x') => tx.send(InputEvent::ToggleRecording).unwrap(),
+                    KeyCode::Char('y') => tx.send(InputEvent::ToggleAudioRecording).unwrap(),
+                    KeyCode::Char('=') => tx.send(InputEvent::MonitorGainUp).unwrap(),
+                    KeyCode::Char('-') => tx.send(InputEvent::MonitorGainDown).unwrap(),
+                    KeyCode::Char('q') => tx.send(InputEvent::ToggleQuantize).unwrap(),
                     // TODO: Delete is not working for a single note.
                     KeyCode::Char('f') => tx.send(InputEvent::Delete).unwrap(),
 
@@ -93,6 +140,12 @@ pub fn capture_input(tx: &mpsc::Sender<InputEvent>) -> io::Result<()> {
                     // Loop controls - grouped together
                     KeyCode::Char('c') => tx.send(InputEvent::ToggleLoopMode).unwrap(),
                     KeyCode::Char('v') => tx.send(InputEvent::SetLoopTimes).unwrap(),
+                    KeyCode::Char('b') => tx.send(InputEvent::TogglePunch).unwrap(),
+
+                    KeyCode::Char(':') => {
+                        palette = Some(String::new());
+                        tx.send(InputEvent::Palette(palette.clone())).unwrap();
+                    }
 
                     // Save and quit - bottom row
                     KeyCode::Char('z') => tx.send(InputEvent::SaveSong).unwrap(),
@@ -104,6 +157,10 @@ pub fn capture_input(tx: &mpsc::Sender<InputEvent>) -> io::Result<()> {
 
                     // Playback control
                     KeyCode::Char('\\') => tx.send(InputEvent::PlayerTogglePlayback).unwrap(),
+                    KeyCode::Char('[') => tx.send(InputEvent::TransportRewind).unwrap(),
+                    KeyCode::Char(']') => tx.send(InputEvent::TransportFastForward).unwrap(),
+                    KeyCode::Char('0') => tx.send(InputEvent::TransportReturnToStart).unwrap(),
+                    KeyCode::Char('m') => tx.send(InputEvent::TransportNextMarker).unwrap(),
 
                     _ => (),
                 }
@@ -0,0 +1,181 @@
+// live_notes.rs
+// Keys held down on a MIDI keyboard are rendered here, away from the player's lock, a
+// bar ahead and then twice as far each time they get near the end, and handed to the
+// player to sound.
+
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use dawww_core::pitch::Pitch;
+use dawww_render::NoteRenderer;
+
+use crate::player::{render_dry, Player};
+use crate::score::{Note, Score};
+
+/// How long a key is first rendered as held for, in 32nd notes
+const SEGMENT_B32: u64 = 32;
+
+/// How often held keys are checked for whether they need rendering further
+const CHECK_INTERVAL: Duration = Duration::from_millis(20);
+
+enum KeyEvent {
+    Press { pitch: Pitch, velocity: u8 },
+    Release(Pitch),
+}
+
+/// Plays the keys of a MIDI keyboard through the player
+pub struct LiveNotes {
+    tx: mpsc::Sender<KeyEvent>,
+}
+
+impl LiveNotes {
+    /// Start rendering keys for `player` on their own thread, which ends with this
+    pub fn start(player: Arc<Mutex<Player>>, score: Arc<Mutex<Score>>) -> LiveNotes {
+        let (tx, rx) = mpsc::channel();
+        let mut keys = LiveKeys::new(player, score);
+        thread::spawn(move || loop {
+            match rx.recv_timeout(CHECK_INTERVAL) {
+                Ok(KeyEvent::Press { pitch, velocity }) => keys.press(pitch, velocity),
+                Ok(KeyEvent::Release(pitch)) => keys.release(pitch),
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            }
+            keys.extend_held();
+        });
+        LiveNotes { tx }
+    }
+
+    /// Sound `pitch` on the track being edited until it's released
+    pub fn press(&self, pitch: Pitch, velocity: u8) {
+        let _ = self.tx.send(KeyEvent::Press { pitch, velocity });
+    }
+
+    /// Let go of `pitch`, which plays on into its release
+    pub fn release(&self, pitch: Pitch) {
+        let _ = self.tx.send(KeyEvent::Release(pitch));
+    }
+}
+
+struct HeldKey {
+    key: u64,
+    instrument: String,
+    note: Note,
+}
+
+/// The keys held down, each with the note its voice was last rendered as
+struct LiveKeys {
+    player: Arc<Mutex<Player>>,
+    score: Arc<Mutex<Score>>,
+    renderer: NoteRenderer,
+    held: Vec<HeldKey>,
+    next_key: u64,
+}
+
+impl LiveKeys {
+    fn new(player: Arc<Mutex<Player>>, score: Arc<Mutex<Score>>) -> LiveKeys {
+        let sample_rate = player.lock().unwrap().sample_rate() as u32;
+        let renderer = NoteRenderer::new(sample_rate, score.lock().unwrap().project_dir());
+        LiveKeys { player, score, renderer, held: Vec::new(), next_key: 0 }
+    }
+
+    fn press(&mut self, pitch: Pitch, velocity: u8) {
+        let (instrument, onset_b32) = {
+            let player = self.player.lock().unwrap();
+            (player.current_instrument(), player.current_time_b32())
+        };
+        let note = Note { pitch, onset_b32, duration_b32: SEGMENT_B32, velocity };
+        let key = self.next_key;
+        self.next_key += 1;
+        let samples = self.render(&instrument, &note);
+        self.player.lock().unwrap().start_live_voice(key, instrument.clone(), note, samples);
+        self.held.push(HeldKey { key, instrument, note });
+    }
+
+    fn release(&mut self, pitch: Pitch) {
+        let Some(index) = self.held.iter().position(|held| held.note.pitch == pitch) else {
+            return;
+        };
+        let held = self.held.remove(index);
+        let (position, ticks_per_b32) = {
+            let player = self.player.lock().unwrap();
+            (player.live_voice_position(held.key), player.ticks_per_b32())
+        };
+        // A stolen voice has nothing left to release
+        let Some(position) = position else {
+            return;
+        };
+        let note = Note { duration_b32: (position as u64).div_ceil(ticks_per_b32).max(1), ..held.note };
+        let samples = self.render(&held.instrument, &note);
+        self.player.lock().unwrap().replace_live_voice(held.key, note, samples, false);
+    }
+
+    /// Render further any held key that's getting near the end of what it was rendered
+    /// for, so it doesn't go into its release while still held down
+    fn extend_held(&mut self) {
+        for index in (0..self.held.len()).rev() {
+            let (position, ticks_per_b32) = {
+                let player = self.player.lock().unwrap();
+                (player.live_voice_position(self.held[index].key), player.ticks_per_b32())
+            };
+            let Some(position) = position else {
+                self.held.remove(index);
+                continue;
+            };
+            let gate = self.held[index].note.duration_b32 * ticks_per_b32;
+            if gate.saturating_sub(position as u64) >= SEGMENT_B32 * ticks_per_b32 / 2 {
+                continue;
+            }
+            let held = &self.held[index];
+            let note = Note { duration_b32: held.note.duration_b32 * 2, ..held.note };
+            let (key, instrument) = (held.key, held.instrument.clone());
+            let samples = self.render(&instrument, &note);
+            self.player.lock().unwrap().replace_live_voice(key, note, samples, true);
+            self.held[index].note = note;
+        }
+    }
+
+    /// Render a key's note, with the noise a note entered where it was played would have
+    fn render(&mut self, instrument: &str, note: &Note) -> Vec<f64> {
+        let daw_file = self.score.lock().unwrap().daw_file().clone();
+        let ticks_per_b32 = self.player.lock().unwrap().ticks_per_b32();
+        render_dry(&mut self.renderer, &daw_file, instrument, note, false, 0, ticks_per_b32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dawww_core::pitch::Tone;
+
+    #[test]
+    fn test_held_keys_are_rendered_further_until_let_go() {
+        let score = Arc::new(Mutex::new(Score::new()));
+        let player = Arc::new(Mutex::new(Player::create(Arc::clone(&score), 8000)));
+        let mut keys = LiveKeys::new(Arc::clone(&player), score);
+        let (c, e) = (Pitch::new(Tone::C, 4), Pitch::new(Tone::E, 4));
+        keys.press(c, 100);
+        keys.press(e, 100);
+        assert_eq!(keys.held.iter().map(|held| held.note.duration_b32).collect::<Vec<_>>(), [SEGMENT_B32; 2]);
+
+        // Getting near the end of the first segment, both are rendered for longer
+        let (_, first) = player.lock().unwrap().voice_rendering(0);
+        for _ in 0..2500 {
+            player.lock().unwrap().next();
+        }
+        keys.extend_held();
+        assert_eq!(keys.held.iter().map(|held| held.note.duration_b32).collect::<Vec<_>>(), [SEGMENT_B32 * 2; 2]);
+        let (_, longer) = player.lock().unwrap().voice_rendering(0);
+        assert_eq!(first[..2500], longer[..2500]);
+
+        // Letting go of one ends its note where it had got to
+        keys.release(c);
+        assert_eq!(keys.held.len(), 1);
+        let player = player.lock().unwrap();
+        assert_eq!(player.live_voice_position(0), None);
+        assert_eq!(player.live_voice_position(1), Some(2500));
+        let (gate, samples) = player.voice_rendering(0);
+        assert_eq!(gate, 2500_usize.div_ceil(125) * 125);
+        assert!(gate < samples.len());
+    }
+}
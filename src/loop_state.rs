@@ -1,3 +1,6 @@
+use crate::recording::PunchRange;
+use dawww_core::{time_from_32nds, time_to_32nds, Session};
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum LoopMode {
     Disabled,
@@ -9,6 +12,9 @@ pub struct LoopState {
     pub start_time_b32: Option<u64>,
     pub end_time_b32: Option<u64>,
     pub mode: LoopMode,
+    /// Whether recording punches in and out at the loop's marks, replacing only what's
+    /// between them
+    pub punch: bool,
 }
 
 impl LoopState {
@@ -17,6 +23,7 @@ impl LoopState {
             start_time_b32: None,
             end_time_b32: None,
             mode: LoopMode::Disabled,
+            punch: false,
         }
     }
 
@@ -45,12 +52,6 @@ impl LoopState {
         new_state
     }
 
-    pub fn set_mode(&self, mode: LoopMode) -> Self {
-        let mut new_state = *self;
-        new_state.mode = mode;
-        new_state
-    }
-
     pub fn toggle_mode(&self) -> Self {
         let mut new_state = *self;
         new_state.mode = match new_state.mode {
@@ -60,8 +61,18 @@ impl LoopState {
         new_state
     }
 
-    pub fn clear(&self) -> Self {
-        Self::new()
+    pub fn toggle_punch(&self) -> Self {
+        let mut new_state = *self;
+        new_state.punch = !new_state.punch;
+        new_state
+    }
+
+    /// Range recording punches in and out of, once punching is on and both marks are set
+    pub fn punch_range(&self) -> Option<PunchRange> {
+        match (self.punch, self.start_time_b32, self.end_time_b32) {
+            (true, Some(start), Some(end)) => Some(PunchRange::new(start, end)),
+            _ => None,
+        }
     }
 
     pub fn is_looping(&self) -> bool {
@@ -69,6 +80,26 @@ impl LoopState {
             && self.start_time_b32.is_some()
             && self.end_time_b32.is_some()
     }
+
+    /// Loop state saved in a project's session
+    pub fn from_session(session: &Session) -> Self {
+        let position = |time: &Option<String>| {
+            time.as_deref().and_then(|t| time_to_32nds(t).ok()).map(u64::from)
+        };
+        Self {
+            start_time_b32: position(&session.loop_start),
+            end_time_b32: position(&session.loop_end),
+            mode: if session.loop_enabled { LoopMode::Looping } else { LoopMode::Disabled },
+            punch: false,
+        }
+    }
+
+    /// Store the loop state in a project's session
+    pub fn write_to(&self, session: &mut Session) {
+        session.loop_start = self.start_time_b32.map(|t| time_from_32nds(t as u32));
+        session.loop_end = self.end_time_b32.map(|t| time_from_32nds(t as u32));
+        session.loop_enabled = self.mode == LoopMode::Looping;
+    }
 }
 
 impl Default for LoopState {
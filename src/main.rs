@@ -1,4 +1,7 @@
 // main.rs
+// Several editor types (cursor modes, fill component, loop helpers) are
+// scaffolding for features that are not wired up yet.
+
 use log::*;
 use simplelog::*;
 use std::fs::File;
@@ -11,16 +14,22 @@ use std::path::PathBuf;
 mod app_state;
 mod audio;
 mod cursor;
+mod daemon;
 mod draw_components;
 mod events;
+mod live_notes;
 mod loop_state;
+mod midi;
+#[cfg(feature = "osc")]
+mod osc;
+mod palette;
 mod player;
+mod playhead;
+mod recording;
 mod resolution;
 mod score;
 mod score_viewport;
 mod selection_buffer;
-mod selection_range;
-mod song;
 mod song_file;
 
 use app_state::AppState;
@@ -28,6 +37,67 @@ use crate::score::Score;
 use crate::song_file::SongFile;
 
 fn main() -> io::Result<()> {
+    // `dawww compare a.wav b.wav` reports how two renders differ and exits
+    let args: Vec<String> = env::args().collect();
+    if args.get(1).map(String::as_str) == Some("compare") {
+        if args.len() != 4 {
+            eprintln!("Usage: dawww compare <a.wav> <b.wav>");
+            std::process::exit(2);
+        }
+        match dawww_render::compare_wav_files(&PathBuf::from(&args[2]), &PathBuf::from(&args[3])) {
+            Ok(report) => {
+                print!("{}", report);
+                return Ok(());
+            }
+            Err(e) => {
+                eprintln!("Error comparing files: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // `dawww devices` lists the audio backends, the output devices playback can use, the
+    // inputs audio can be recorded from, and the MIDI inputs notes can be played from and
+    // outputs clock can be sent to
+    if args.get(1).map(String::as_str) == Some("devices") {
+        for backend in dawww_playback::Backend::available() {
+            match backend.output_devices() {
+                Ok(devices) => devices.iter().for_each(|device| println!("{}", device)),
+                Err(e) => eprintln!("{}: {}", backend, e),
+            }
+            match backend.input_devices() {
+                Ok(inputs) => inputs.iter().for_each(|input| println!("{} in: {}", backend, input)),
+                Err(e) => eprintln!("{} in: {}", backend, e),
+            }
+        }
+        match midi::midi_inputs() {
+            Ok(inputs) => inputs.iter().for_each(|input| println!("midi: {}", input)),
+            Err(e) => eprintln!("midi: {}", e),
+        }
+        match midi::midi_outputs() {
+            Ok(outputs) => outputs.iter().for_each(|output| println!("midi out: {}", output)),
+            Err(e) => eprintln!("midi out: {}", e),
+        }
+        return Ok(());
+    }
+
+    // `dawww serve song.daw.json [address]` plays the song without the editor, taking
+    // commands like `play` and `seek 5.0` over TCP
+    if args.get(1).map(String::as_str) == Some("serve") {
+        if !(3..=4).contains(&args.len()) {
+            eprintln!("Usage: dawww serve <song.daw.json> [address]");
+            std::process::exit(2);
+        }
+        let address = args.get(3).map_or(daemon::DEFAULT_DAEMON_ADDR, String::as_str);
+        let served = audio::AudioSettings::from_env()
+            .and_then(|settings| daemon::serve(PathBuf::from(&args[2]), address, settings));
+        if let Err(e) = served {
+            eprintln!("Error serving {}: {}", args[2], e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     // Initialize logging
     CombinedLogger::init(vec![WriteLogger::new(
         LevelFilter::Debug,
@@ -39,7 +109,11 @@ fn main() -> io::Result<()> {
     info!("Application starting...");
 
     let mut song_file = SongFile::new();
-    if let Some(path) = env::args().nth(1) {
+    // With no song named, open the project in the current directory if there is one
+    let path = env::args().nth(1).or_else(|| {
+        dawww_core::find_daw_file(&PathBuf::from(".")).ok().map(|path| path.display().to_string())
+    });
+    if let Some(path) = path {
         info!("Loading song from {}", path);
         match song_file.load(PathBuf::from(&path)) {
             Ok(score) => {
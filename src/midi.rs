@@ -0,0 +1,205 @@
+use crate::events::InputEvent;
+use anyhow::{anyhow, Result};
+use dawww_core::pitch::Pitch;
+use midir::{MidiInput, MidiInputConnection, MidiOutput, MidiOutputConnection};
+use std::env;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Instant;
+
+/// A note message from a MIDI keyboard
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MidiMessage {
+    NoteOn { key: u8, velocity: u8 },
+    NoteOff { key: u8 },
+}
+
+impl MidiMessage {
+    /// Read a note on or off on any channel. Note-ons at velocity 0 are note-offs, and
+    /// anything else is ignored.
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        let [status, key, velocity, ..] = *bytes else {
+            return None;
+        };
+        let (key, velocity) = (key & 0x7f, velocity & 0x7f);
+        match status & 0xf0 {
+            0x90 if velocity > 0 => Some(MidiMessage::NoteOn { key, velocity }),
+            0x80 | 0x90 => Some(MidiMessage::NoteOff { key }),
+            _ => None,
+        }
+    }
+
+    fn event(self) -> Option<InputEvent> {
+        match self {
+            MidiMessage::NoteOn { key, velocity } => {
+                Pitch::from_midi(key).map(|pitch| InputEvent::MidiNoteOn { pitch, velocity })
+            }
+            MidiMessage::NoteOff { key } => Pitch::from_midi(key).map(InputEvent::MidiNoteOff),
+        }
+    }
+}
+
+/// A message keeping external gear in time with the player
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncMessage {
+    /// One of 24 pulses a quarter note
+    Clock,
+    /// Play from the top of the song
+    Start,
+    /// Play from the song position last sent
+    Continue,
+    Stop,
+    /// Where playback goes on from, in 16th notes
+    SongPosition(u16),
+}
+
+impl SyncMessage {
+    pub fn bytes(self) -> Vec<u8> {
+        match self {
+            SyncMessage::Clock => vec![0xf8],
+            SyncMessage::Start => vec![0xfa],
+            SyncMessage::Continue => vec![0xfb],
+            SyncMessage::Stop => vec![0xfc],
+            SyncMessage::SongPosition(sixteenths) => {
+                let sixteenths = sixteenths.min(0x3fff);
+                vec![0xf2, (sixteenths & 0x7f) as u8, (sixteenths >> 7) as u8]
+            }
+        }
+    }
+}
+
+/// Sends sync messages to external gear at the times they're stamped with, from a thread
+/// of its own, so clocks produced a block of audio at a time go out evenly spaced as that
+/// audio is heard. The thread ends when this is dropped.
+pub struct SyncScheduler {
+    tx: mpsc::Sender<(Instant, SyncMessage)>,
+}
+
+impl SyncScheduler {
+    /// Start sending to `send`. Messages go out in the order they're scheduled.
+    pub fn start(mut send: impl FnMut(SyncMessage) + Send + 'static) -> Self {
+        let (tx, rx) = mpsc::channel::<(Instant, SyncMessage)>();
+        thread::spawn(move || {
+            for (at, message) in rx {
+                if let Some(wait) = at.checked_duration_since(Instant::now()) {
+                    thread::sleep(wait);
+                }
+                send(message);
+            }
+        });
+        Self { tx }
+    }
+
+    /// Send `message` at `at`, or straight away if that's passed
+    pub fn schedule(&self, at: Instant, message: SyncMessage) {
+        // The thread only stops once this is dropped
+        let _ = self.tx.send((at, message));
+    }
+}
+
+/// Names of the MIDI inputs that can be played from
+pub fn midi_inputs() -> Result<Vec<String>> {
+    let input = MidiInput::new("dawww").map_err(|e| anyhow!("Couldn't open MIDI input: {}", e))?;
+    Ok(input.ports().iter().filter_map(|port| input.port_name(port).ok()).collect())
+}
+
+/// Names of the MIDI outputs clock can be sent to
+pub fn midi_outputs() -> Result<Vec<String>> {
+    let output = MidiOutput::new("dawww").map_err(|e| anyhow!("Couldn't open MIDI output: {}", e))?;
+    Ok(output.ports().iter().filter_map(|port| output.port_name(port).ok()).collect())
+}
+
+/// Open the first MIDI output whose name contains DAWWW_MIDI_CLOCK_PORT, to send clock
+/// and transport to. Returns `None` when it isn't set, so nothing is sent unasked.
+pub fn midi_clock_output() -> Result<Option<MidiOutputConnection>> {
+    let Ok(wanted) = env::var("DAWWW_MIDI_CLOCK_PORT") else {
+        return Ok(None);
+    };
+    let output = MidiOutput::new("dawww").map_err(|e| anyhow!("Couldn't open MIDI output: {}", e))?;
+    let port = output.ports().into_iter()
+        .find(|port| output.port_name(port).is_ok_and(|name| name.contains(wanted.as_str())))
+        .ok_or_else(|| anyhow!("No MIDI output named '{}'", wanted))?;
+    let name = output.port_name(&port).unwrap_or_default();
+    let connection = output
+        .connect(&port, "dawww-clock")
+        .map_err(|e| anyhow!("Couldn't connect to MIDI output '{}': {}", name, e))?;
+    log::info!("Sending MIDI clock to '{}'", name);
+    Ok(Some(connection))
+}
+
+/// Send notes from a MIDI keyboard to `tx` for as long as the returned connection is kept.
+/// Plays from the first input whose name contains DAWWW_MIDI_PORT, or the first input
+/// there is; returns `None` when there's no MIDI input.
+pub fn midi_input(tx: mpsc::Sender<InputEvent>) -> Result<Option<MidiInputConnection<()>>> {
+    let input = MidiInput::new("dawww").map_err(|e| anyhow!("Couldn't open MIDI input: {}", e))?;
+    let wanted = env::var("DAWWW_MIDI_PORT").ok();
+    let port = input.ports().into_iter().find(|port| match &wanted {
+        Some(wanted) => input.port_name(port).is_ok_and(|name| name.contains(wanted.as_str())),
+        None => true,
+    });
+    let Some(port) = port else {
+        return match wanted {
+            Some(wanted) => Err(anyhow!("No MIDI input named '{}'", wanted)),
+            None => Ok(None),
+        };
+    };
+    let name = input.port_name(&port).unwrap_or_default();
+    let connection = input
+        .connect(
+            &port,
+            "dawww-in",
+            move |_, bytes, _| {
+                if let Some(event) = MidiMessage::parse(bytes).and_then(MidiMessage::event) {
+                    // The editor may already be shutting down
+                    let _ = tx.send(event);
+                }
+            },
+            (),
+        )
+        .map_err(|e| anyhow!("Couldn't connect to MIDI input '{}': {}", name, e))?;
+    log::info!("Playing from MIDI input '{}'", name);
+    Ok(Some(connection))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(MidiMessage::parse(&[0x90, 60, 100]), Some(MidiMessage::NoteOn { key: 60, velocity: 100 }));
+        assert_eq!(MidiMessage::parse(&[0x9f, 61, 1]), Some(MidiMessage::NoteOn { key: 61, velocity: 1 }));
+        assert_eq!(MidiMessage::parse(&[0x90, 60, 0]), Some(MidiMessage::NoteOff { key: 60 }));
+        assert_eq!(MidiMessage::parse(&[0x83, 60, 64]), Some(MidiMessage::NoteOff { key: 60 }));
+        // Controllers, clock and short messages aren't notes
+        assert_eq!(MidiMessage::parse(&[0xb0, 1, 64]), None);
+        assert_eq!(MidiMessage::parse(&[0xf8]), None);
+        assert_eq!(MidiMessage::parse(&[0x90, 60]), None);
+    }
+
+    #[test]
+    fn test_sync_bytes() {
+        assert_eq!(SyncMessage::Clock.bytes(), vec![0xf8]);
+        assert_eq!(SyncMessage::Continue.bytes(), vec![0xfb]);
+        assert_eq!(SyncMessage::SongPosition(300).bytes(), vec![0xf2, 44, 2]);
+        // Song positions only have 14 bits
+        assert_eq!(SyncMessage::SongPosition(u16::MAX).bytes(), vec![0xf2, 0x7f, 0x7f]);
+    }
+
+    #[test]
+    fn test_sync_scheduler() {
+        let (tx, rx) = mpsc::channel();
+        let scheduler = SyncScheduler::start(move |message| tx.send((Instant::now(), message)).unwrap());
+        let start = Instant::now();
+        scheduler.schedule(start, SyncMessage::Start);
+        scheduler.schedule(start + std::time::Duration::from_millis(20), SyncMessage::Clock);
+
+        // Nothing goes out before its time
+        let (sent_at, message) = rx.recv().unwrap();
+        assert_eq!(message, SyncMessage::Start);
+        assert!(sent_at.duration_since(start).as_millis() < 20);
+        let (sent_at, message) = rx.recv().unwrap();
+        assert_eq!(message, SyncMessage::Clock);
+        assert!(sent_at.duration_since(start).as_millis() >= 20);
+    }
+}
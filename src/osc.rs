@@ -0,0 +1,275 @@
+use crate::events::InputEvent;
+use anyhow::{anyhow, bail, Context, Result};
+use dawww_core::pitch::Pitch;
+use dawww_core::DEFAULT_VELOCITY;
+use std::env;
+use std::net::UdpSocket;
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+
+/// An argument of an OSC message
+#[derive(Debug, Clone, PartialEq)]
+pub enum OscArg {
+    Int(i32),
+    Float(f32),
+    Str(String),
+    Bool(bool),
+}
+
+impl OscArg {
+    fn as_f64(&self) -> Option<f64> {
+        match self {
+            OscArg::Int(value) => Some(f64::from(*value)),
+            OscArg::Float(value) => Some(f64::from(*value)),
+            _ => None,
+        }
+    }
+
+    fn as_bool(&self) -> Option<bool> {
+        match self {
+            OscArg::Bool(value) => Some(*value),
+            _ => self.as_f64().map(|value| value != 0.0),
+        }
+    }
+}
+
+/// An OSC message: an address like `/dawww/play` and its arguments
+#[derive(Debug, Clone, PartialEq)]
+pub struct OscMessage {
+    pub address: String,
+    pub args: Vec<OscArg>,
+}
+
+impl OscMessage {
+    /// Read the messages in an OSC packet, which is a message or a bundle of them. Bundles
+    /// are played as they arrive, whatever their time tag says.
+    pub fn parse_packet(packet: &[u8]) -> Result<Vec<OscMessage>> {
+        let Some(mut elements) = packet.strip_prefix(b"#bundle\0") else {
+            return Ok(vec![Self::parse(packet)?]);
+        };
+        // Skip the time tag
+        elements = elements.get(8..).context("OSC bundle is cut short")?;
+        let mut messages = Vec::new();
+        while !elements.is_empty() {
+            let size = elements.get(..4).context("OSC bundle is cut short")?;
+            let size = u32::from_be_bytes(size.try_into()?) as usize;
+            let element = elements.get(4..4 + size).context("OSC bundle is cut short")?;
+            messages.extend(Self::parse_packet(element)?);
+            elements = &elements[4 + size..];
+        }
+        Ok(messages)
+    }
+
+    fn parse(packet: &[u8]) -> Result<OscMessage> {
+        let mut reader = Reader { bytes: packet };
+        let address = reader.string()?;
+        if !address.starts_with('/') {
+            bail!("'{}' isn't an OSC address", address);
+        }
+        // Old senders may leave out the type tags, and so the arguments
+        let tags = if reader.bytes.is_empty() { ",".to_string() } else { reader.string()? };
+        let tags = tags.strip_prefix(',').with_context(|| format!("Bad OSC type tags '{}'", tags))?;
+        let mut args = Vec::new();
+        for tag in tags.chars() {
+            args.push(match tag {
+                'i' => OscArg::Int(i32::from_be_bytes(reader.word()?)),
+                'f' => OscArg::Float(f32::from_be_bytes(reader.word()?)),
+                's' => OscArg::Str(reader.string()?),
+                'T' => OscArg::Bool(true),
+                'F' => OscArg::Bool(false),
+                _ => bail!("Unsupported OSC argument type '{}'", tag),
+            });
+        }
+        Ok(OscMessage { address, args })
+    }
+
+    fn arg(&self, index: usize) -> Result<&OscArg> {
+        self.args.get(index).ok_or_else(|| anyhow!("{} needs {} arguments", self.address, index + 1))
+    }
+
+    fn number(&self, index: usize) -> Result<f64> {
+        self.arg(index)?.as_f64().ok_or_else(|| anyhow!("Argument {} of {} should be a number", index + 1, self.address))
+    }
+
+    fn count(&self, index: usize) -> Result<u64> {
+        let value = self.number(index)?;
+        if value < 0.0 {
+            bail!("Argument {} of {} can't be negative", index + 1, self.address);
+        }
+        Ok(value as u64)
+    }
+
+    fn flag(&self, index: usize) -> Result<bool> {
+        self.arg(index)?.as_bool().ok_or_else(|| anyhow!("Argument {} of {} should be on or off", index + 1, self.address))
+    }
+}
+
+/// Reads the parts of an OSC message, each padded to four bytes
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn word(&mut self) -> Result<[u8; 4]> {
+        let word = self.bytes.get(..4).context("OSC message is cut short")?;
+        self.bytes = &self.bytes[4..];
+        Ok(word.try_into()?)
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let end = self.bytes.iter().position(|&b| b == 0).context("OSC string isn't terminated")?;
+        let string = std::str::from_utf8(&self.bytes[..end])?.to_string();
+        self.bytes = self.bytes.get((end + 4) & !3..).unwrap_or_default();
+        Ok(string)
+    }
+}
+
+/// Something an OSC controller asks dawww to do
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OscCommand {
+    Play,
+    Pause,
+    Stop,
+    TogglePlayback,
+    /// Move the playhead to a 32nd
+    Locate(u64),
+    InsertNote { pitch: Pitch, onset_b32: u64, duration_b32: u64, velocity: u8 },
+    /// Set the track's fader, in dB
+    SetGain(f64),
+    SetMute(bool),
+    SetSolo(bool),
+}
+
+impl OscCommand {
+    /// The command a message asks for:
+    ///
+    /// * `/dawww/play`, `/dawww/pause`, `/dawww/stop`, `/dawww/toggle`
+    /// * `/dawww/locate <32nd>`
+    /// * `/dawww/note <MIDI key> <onset 32nd> <length in 32nds> [velocity]`
+    /// * `/dawww/mixer/gain <dB>`, `/dawww/mixer/mute <on>`, `/dawww/mixer/solo <on>`
+    pub fn from_message(message: &OscMessage) -> Result<OscCommand> {
+        Ok(match message.address.as_str() {
+            "/dawww/play" => OscCommand::Play,
+            "/dawww/pause" => OscCommand::Pause,
+            "/dawww/stop" => OscCommand::Stop,
+            "/dawww/toggle" => OscCommand::TogglePlayback,
+            "/dawww/locate" => OscCommand::Locate(message.count(0)?),
+            "/dawww/note" => {
+                let key = u8::try_from(message.count(0)?).ok();
+                let pitch = key.and_then(Pitch::from_midi).context("/dawww/note needs a MIDI key from 0 to 127")?;
+                let velocity = match message.args.len() {
+                    ..=3 => DEFAULT_VELOCITY,
+                    _ => message.count(3)?.clamp(1, 127) as u8,
+                };
+                OscCommand::InsertNote {
+                    pitch,
+                    onset_b32: message.count(1)?,
+                    duration_b32: message.count(2)?.max(1),
+                    velocity,
+                }
+            }
+            "/dawww/mixer/gain" => OscCommand::SetGain(message.number(0)?),
+            "/dawww/mixer/mute" => OscCommand::SetMute(message.flag(0)?),
+            "/dawww/mixer/solo" => OscCommand::SetSolo(message.flag(0)?),
+            address => bail!("Unknown OSC address '{}'", address),
+        })
+    }
+}
+
+/// Listen for OSC commands on the UDP address in DAWWW_OSC_ADDR (e.g. `127.0.0.1:9000`)
+/// and send them to `tx`. Returns `None` when it isn't set, so no port is opened unasked.
+pub fn osc_server(tx: mpsc::Sender<InputEvent>) -> Result<Option<JoinHandle<()>>> {
+    let Ok(address) = env::var("DAWWW_OSC_ADDR") else {
+        return Ok(None);
+    };
+    let socket = UdpSocket::bind(&address).with_context(|| format!("Couldn't listen for OSC on {}", address))?;
+    log::info!("Listening for OSC on {}", address);
+    let server = thread::Builder::new().name("dawww-osc".to_string()).spawn(move || {
+        let mut packet = [0; 65536];
+        loop {
+            let size = match socket.recv(&mut packet) {
+                Ok(size) => size,
+                Err(e) => {
+                    log::error!("OSC server stopped: {}", e);
+                    return;
+                }
+            };
+            let messages = match OscMessage::parse_packet(&packet[..size]) {
+                Ok(messages) => messages,
+                Err(e) => {
+                    log::warn!("Ignoring OSC packet: {}", e);
+                    continue;
+                }
+            };
+            for message in messages {
+                match OscCommand::from_message(&message) {
+                    // The editor may already be shutting down
+                    Ok(command) => {
+                        if tx.send(InputEvent::Osc(command)).is_err() {
+                            return;
+                        }
+                    }
+                    Err(e) => log::warn!("Ignoring OSC message: {}", e),
+                }
+            }
+        }
+    })?;
+    Ok(Some(server))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dawww_core::pitch::Tone;
+
+    /// Encode a message as OSC, with int and float arguments only
+    fn encode(address: &str, args: &[OscArg]) -> Vec<u8> {
+        let pad = |bytes: &mut Vec<u8>, string: &str| {
+            bytes.extend(string.as_bytes());
+            bytes.push(0);
+            bytes.resize(bytes.len().div_ceil(4) * 4, 0);
+        };
+        let mut bytes = Vec::new();
+        pad(&mut bytes, address);
+        let tags: String = args.iter().map(|arg| if matches!(arg, OscArg::Int(_)) { 'i' } else { 'f' }).collect();
+        pad(&mut bytes, &format!(",{}", tags));
+        for arg in args {
+            match arg {
+                OscArg::Int(value) => bytes.extend(value.to_be_bytes()),
+                OscArg::Float(value) => bytes.extend(value.to_be_bytes()),
+                _ => unreachable!(),
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_parse() {
+        let packet = encode("/dawww/note", &[OscArg::Int(60), OscArg::Int(8), OscArg::Float(4.0)]);
+        let messages = OscMessage::parse_packet(&packet).unwrap();
+        assert_eq!(messages[0].address, "/dawww/note");
+        assert_eq!(
+            OscCommand::from_message(&messages[0]).unwrap(),
+            OscCommand::InsertNote { pitch: Pitch::new(Tone::C, 4), onset_b32: 8, duration_b32: 4, velocity: DEFAULT_VELOCITY }
+        );
+
+        // Bundles hold several messages
+        let mut bundle = b"#bundle\0".to_vec();
+        bundle.extend([0, 0, 0, 0, 0, 0, 0, 1]);
+        for message in [encode("/dawww/play", &[]), encode("/dawww/mixer/gain", &[OscArg::Float(-6.0)])] {
+            bundle.extend((message.len() as u32).to_be_bytes());
+            bundle.extend(message);
+        }
+        let commands: Vec<OscCommand> = OscMessage::parse_packet(&bundle).unwrap()
+            .iter()
+            .map(|message| OscCommand::from_message(message).unwrap())
+            .collect();
+        assert_eq!(commands, vec![OscCommand::Play, OscCommand::SetGain(-6.0)]);
+
+        let solo = OscMessage { address: "/dawww/mixer/solo".to_string(), args: vec![OscArg::Bool(true)] };
+        assert_eq!(OscCommand::from_message(&solo).unwrap(), OscCommand::SetSolo(true));
+        assert!(OscCommand::from_message(&OscMessage::parse_packet(&encode("/dawww/locate", &[])).unwrap()[0]).is_err());
+        assert!(OscCommand::from_message(&OscMessage::parse_packet(&encode("/other", &[])).unwrap()[0]).is_err());
+        assert!(OscMessage::parse_packet(&packet[..10]).is_err());
+    }
+}
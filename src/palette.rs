@@ -0,0 +1,51 @@
+use anyhow::{bail, Context, Result};
+
+/// A command typed into the editor's command palette, opened with `:`
+#[derive(Debug, Clone, PartialEq)]
+pub enum PaletteCommand {
+    /// Enter a step-sequencer string at the cursor on the current track, one step per
+    /// grid cell, with `x` steps playing the cursor's pitch: `steps x..x..x.` or
+    /// `steps C4 E4 G4 -`
+    Steps(String),
+    /// Enter and show notes on another track: `track bass`
+    Track(String),
+    /// Move the playhead to the start of a bar, counting from 1: `bar 9`
+    Bar(u64),
+    /// Move the playhead to the next marker with a name: `marker chorus`
+    Marker(String),
+}
+
+impl PaletteCommand {
+    pub fn parse(text: &str) -> Result<PaletteCommand> {
+        let text = text.trim();
+        let (name, arg) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+        let arg = arg.trim();
+        if arg.is_empty() {
+            bail!("'{}' needs an argument", name);
+        }
+        Ok(match name {
+            "steps" => PaletteCommand::Steps(arg.to_string()),
+            "track" => PaletteCommand::Track(arg.to_string()),
+            "bar" => PaletteCommand::Bar(arg.parse().with_context(|| format!("'{}' isn't a bar number", arg))?),
+            "marker" => PaletteCommand::Marker(arg.to_string()),
+            _ => bail!("Unknown command '{}'", name),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(PaletteCommand::parse("steps x..x..x.").unwrap(), PaletteCommand::Steps("x..x..x.".to_string()));
+        assert_eq!(PaletteCommand::parse(" steps C4 E4 G4 - ").unwrap(), PaletteCommand::Steps("C4 E4 G4 -".to_string()));
+        assert_eq!(PaletteCommand::parse("track bass").unwrap(), PaletteCommand::Track("bass".to_string()));
+        assert_eq!(PaletteCommand::parse("bar 9").unwrap(), PaletteCommand::Bar(9));
+        assert_eq!(PaletteCommand::parse("marker Big Chorus").unwrap(), PaletteCommand::Marker("Big Chorus".to_string()));
+        assert!(PaletteCommand::parse("bar nine").is_err());
+        assert!(PaletteCommand::parse("steps").is_err());
+        assert!(PaletteCommand::parse("quit now").is_err());
+    }
+}
@@ -1,11 +1,29 @@
-use crate::score::{Note, Score};
-use std::f64::consts::PI;
+use crate::score::{Note, NoteState, Score};
+use anyhow::{bail, Result};
+use std::collections::{BTreeMap, VecDeque};
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 use crate::loop_state::LoopState;
-use std::time::Instant;
+use crate::midi::SyncMessage;
+use crate::playhead::{Playhead, PlayheadListener};
+use crate::recording::{PunchRange, Recorded, Take};
+use std::time::{Duration, Instant};
+use dawww_core::controllers::{bend_ratio, Controller};
 use dawww_core::pitch::Pitch;
+use dawww_core::{DawFile, VoiceRequest, DEFAULT_VELOCITY};
+use dawww_render::effects::{apply_chain, chain_tail_seconds};
+use dawww_render::sampler::Sample;
+use dawww_render::stereo::Stereo;
+use dawww_render::synth::{note_seed, DECLICK_SECONDS};
+use dawww_render::{NoteRenderer, PlayedNote};
 
-#[derive(PartialEq, Clone, Copy)]
+/// How long previews, auditions and scrubs sound for
+const PREVIEW_DURATION_MS: u64 = 250;
+
+/// Voices the player sounds at once unless told otherwise
+pub const DEFAULT_MAX_VOICES: usize = 32;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum PlayState {
     Stopped,
     Playing,
@@ -13,16 +31,209 @@ pub enum PlayState {
     Preview,
 }
 
+/// A track's gain from the mixer, ramping to changes so they don't click
+struct Level {
+    current: f64,
+    target: f64,
+}
+
+impl Level {
+    fn new(level: f64) -> Self {
+        Self { current: level, target: level }
+    }
+
+    /// Move toward the target by up to `step` and return the gain for the next sample
+    fn next(&mut self, step: f64) -> f64 {
+        self.current += (self.target - self.current).clamp(-step, step);
+        self.current
+    }
+}
+
+/// A note being played, rendered by its instrument as it starts. Notes cut off before
+/// they've finished fade out quickly instead, and notes joined partway through fade in.
+struct Voice {
+    /// Track the note is on
+    instrument: String,
+    note: Note,
+    /// The note from its start to the end of its release
+    samples: Vec<f64>,
+    /// Next sample to play
+    position: usize,
+    /// Sample the note was cut off on
+    cut: Option<u64>,
+    /// Position the note was joined at, if it didn't play from its start
+    joined: Option<usize>,
+    /// Samples the note is held for before its release
+    gate: usize,
+    /// Player sample the voice started on
+    started: u64,
+    level: Level,
+    /// For a key held down on a MIDI keyboard, the key `LiveNotes` knows it by, so its
+    /// rendering can be swapped for a longer one or one ending where the key is let go
+    live: Option<u64>,
+}
+
+impl Voice {
+    fn cut(&mut self, sample: u64) {
+        self.cut.get_or_insert(sample);
+    }
+
+    /// Whether the song still holds the note down at `time_b32`. Keys held on a MIDI
+    /// keyboard are left sounding until they're let go.
+    fn is_held(&self, time_b32: u64) -> bool {
+        self.live.is_none() && self.cut.is_none() && time_b32 < self.note.onset_b32 + self.note.duration_b32
+    }
+
+    /// Play the next sample, fading in over `fade` samples if the note was joined partway
+    /// through and fading out over as many once it's cut off
+    fn next_sample(&mut self, sample: u64, fade: f64) -> f64 {
+        let gain = self.cut.map_or(1.0, |cut| (1.0 - sample.saturating_sub(cut) as f64 / fade).max(0.0))
+            * self.joined.map_or(1.0, |joined| ((self.position - joined) as f64 / fade).min(1.0));
+        let value = self.samples.get(self.position).copied().unwrap_or(0.0);
+        self.position += 1;
+        value * gain
+    }
+
+    /// Whether the note has been let go and only its release, or fade, is left
+    fn is_released(&self) -> bool {
+        self.cut.is_some() || self.position >= self.gate
+    }
+
+    fn is_finished(&self, sample: u64, fade: f64) -> bool {
+        self.position >= self.samples.len() || self.cut.is_some_and(|cut| sample.saturating_sub(cut) as f64 >= fade)
+    }
+}
+
+/// Where the transport was for the last output latency's worth of samples, so where the
+/// audio being heard was played from can be told across seeks, loops and pauses
+#[derive(Default)]
+struct PositionHistory {
+    /// Each stretch of samples the transport ran or stood still through: the sample it
+    /// started on, its position in 32nds then, and whether it moved on from there
+    stretches: VecDeque<(u64, f64, bool)>,
+}
+
+impl PositionHistory {
+    /// Note where the transport is on `sample`, starting a new stretch if it isn't where
+    /// the last one would have it. Stretches from before `keep_from` are let go.
+    fn note(&mut self, sample: u64, position_b32: f64, moving: bool, ticks_per_b32: u64, keep_from: u64) {
+        let expected = self.position_at(sample, ticks_per_b32);
+        let last_moving = self.stretches.back().is_some_and(|&(_, _, moving)| moving);
+        if expected.is_none_or(|expected| (expected - position_b32).abs() > 1e-9) || last_moving != moving {
+            self.stretches.push_back((sample, position_b32, moving));
+        }
+        while self.stretches.get(1).is_some_and(|&(start, _, _)| start <= keep_from) {
+            self.stretches.pop_front();
+        }
+    }
+
+    /// Where the transport was on `sample`, if anything's been noted
+    fn position_at(&self, sample: u64, ticks_per_b32: u64) -> Option<f64> {
+        let index = self.stretches.partition_point(|&(start, _, _)| start <= sample).saturating_sub(1);
+        let &(start, position_b32, moving) = self.stretches.get(index)?;
+        let moved = if moving { sample.saturating_sub(start) as f64 / ticks_per_b32 as f64 } else { 0.0 };
+        Some(position_b32 + moved)
+    }
+}
+
+/// A frozen track's audio, played in place of its notes
+struct FrozenAudio {
+    /// The file it was read from
+    path: PathBuf,
+    /// The sample of the song the audio starts at
+    start: u64,
+    /// The audio mixed to mono at the player's rate, or `None` if it couldn't be read, in
+    /// which case the notes play instead as they do in renders
+    samples: Option<Vec<f64>>,
+    level: Level,
+}
+
+impl FrozenAudio {
+    fn load((path, start): (PathBuf, u64), sample_rate: u32, level: f64) -> Self {
+        let samples = Sample::load_stereo(&path)
+            .map(|(left, right)| {
+                let (left, right) = (left.resampled(sample_rate), right.resampled(sample_rate));
+                left.data.iter().zip(&right.data).map(|(left, right)| (left + right) / 2.0).collect()
+            })
+            .map_err(|e| log::error!("Couldn't read the frozen track {}: {}", path.display(), e))
+            .ok();
+        Self { path, start, samples, level: Level::new(level) }
+    }
+
+    /// The audio at `song_sample`, if the song is playing
+    fn next_sample(&mut self, song_sample: Option<u64>, step: f64) -> f64 {
+        let gain = self.level.next(step);
+        let samples = self.samples.as_deref().unwrap_or_default();
+        song_sample.and_then(|sample| sample.checked_sub(self.start))
+            .and_then(|sample| samples.get(sample as usize))
+            .map_or(0.0, |sample| sample * gain)
+    }
+
+    /// The file it was read from and the sample of the song it starts at
+    fn source(&self) -> (PathBuf, u64) {
+        (self.path.clone(), self.start)
+    }
+}
+
+/// A change to which tracks are frozen, from `Player::frozen_update`. Reading the audio
+/// with `load` takes a while, so it's done without the player locked, and the result is
+/// handed back with `Player::apply_frozen`.
+pub struct FrozenUpdate {
+    sample_rate: u32,
+    /// Every frozen track's audio file, with the sample of the song it starts at
+    sources: BTreeMap<String, (PathBuf, u64)>,
+    /// Tracks the player doesn't have the audio of yet, with their levels
+    missing: Vec<(String, f64)>,
+    loaded: Vec<(String, FrozenAudio)>,
+}
+
+impl FrozenUpdate {
+    /// Read the audio of the tracks the player is missing
+    pub fn load(mut self) -> Self {
+        for (id, level) in std::mem::take(&mut self.missing) {
+            let frozen = FrozenAudio::load(self.sources[&id].clone(), self.sample_rate, level);
+            self.loaded.push((id, frozen));
+        }
+        self
+    }
+}
+
 pub struct Player {
     score: Arc<Mutex<Score>>,
     sample_rate: u64,
     state: PlayState,
     tick: u64,
     time_b32: u64,
-    active_notes: Vec<Note>,
+    active_notes: Vec<Voice>,
     ticks_per_b32: u64,
+    /// Samples produced so far; unlike `tick` it never resets, so fades can span a seek or loop
+    sample_clock: u64,
     loop_state: LoopState,
     preview_start: Option<Instant>,
+    /// Plays notes as the offline render does
+    renderer: NoteRenderer,
+    /// Notes pushed back by the groove, with the tick they start on and their track
+    pending_notes: Vec<(u64, String, Note)>,
+    /// Notes played on the MIDI keyboard while recording
+    take: Option<Take>,
+    /// Told where the playhead is as it moves
+    playhead_listener: Option<PlayheadListener>,
+    /// Sent MIDI clock and transport as the song plays, with when each should go out
+    sync: Option<Box<dyn FnMut(Instant, SyncMessage) + Send>>,
+    /// Whether `sync` was last told the song is playing
+    sync_running: bool,
+    /// Samples between the player producing audio and it being heard
+    output_latency: u64,
+    /// When the block of audio being produced started, and the sample it started on
+    block_start: Option<(Instant, u64)>,
+    /// Where the transport was lately, to tell where what's being heard was played from
+    history: PositionHistory,
+    /// Most voices sounding at once before the oldest are stolen for new notes
+    max_voices: usize,
+    /// Audio of the frozen tracks, by track
+    frozen: BTreeMap<String, FrozenAudio>,
+    /// Whether a scrub is playing the song, rather than a preview playing notes
+    scrubbing: bool,
 }
 
 impl Player {
@@ -31,8 +242,9 @@ impl Player {
         // For 120 BPM: 44100 samples/sec * 60 sec/min / 120 beats/min / 32 subdivisions = 689.0625 samples/b32
         // Rounding to 689 samples per b32 unit
         let ticks_per_b32 = (sample_rate * 60 / score.lock().unwrap().get_bpm() as u64) / 32;
+        let renderer = NoteRenderer::new(sample_rate as u32, score.lock().unwrap().project_dir());
 
-        Player {
+        let mut player = Player {
             score,
             sample_rate,
             state: PlayState::Stopped,
@@ -40,9 +252,26 @@ impl Player {
             time_b32: 0,
             active_notes: Vec::new(),
             ticks_per_b32,
+            sample_clock: 0,
             loop_state: LoopState::new(),
             preview_start: None,
-        }
+            renderer,
+            pending_notes: Vec::new(),
+            take: None,
+            playhead_listener: None,
+            sync: None,
+            sync_running: false,
+            output_latency: 0,
+            block_start: None,
+            history: PositionHistory::default(),
+            max_voices: DEFAULT_MAX_VOICES,
+            frozen: BTreeMap::new(),
+            scrubbing: false,
+        };
+        // Read any frozen audio now rather than on the audio thread
+        let frozen = player.frozen_update().load();
+        player.apply_frozen(frozen);
+        player
     }
 
     pub fn play(&mut self) {
@@ -57,7 +286,8 @@ impl Player {
         self.state = PlayState::Stopped;
         self.time_b32 = 0;
         self.tick = 0;
-        self.active_notes.clear();
+        self.cut_all();
+        self.pending_notes.clear();
     }
 
     pub fn toggle_playback(&mut self) {
@@ -72,40 +302,412 @@ impl Player {
         self.state == PlayState::Playing || self.state == PlayState::Preview
     }
 
+    pub fn sample_rate(&self) -> u64 {
+        self.sample_rate
+    }
+
     pub fn current_time_b32(&self) -> u64 {
         self.time_b32
     }
 
+    /// Where the transport is, in 32nds including how far it is through the current one
+    pub fn position_b32(&self) -> f64 {
+        self.time_b32 as f64 + (self.tick % self.ticks_per_b32) as f64 / self.ticks_per_b32 as f64
+    }
+
+    /// Where the transport is as it's heard, which lags the player by the output latency
+    pub fn playhead(&self) -> Playhead {
+        let position_b32 = self.heard_position_b32();
+        Playhead {
+            state: self.state,
+            recording: self.is_recording(),
+            time_b32: position_b32 as u64,
+            position_b32,
+        }
+    }
+
+    /// Report the playhead to `callback` about `rate_hz` times a second of audio whenever
+    /// it's changed, so the UI can follow playback without locking the player to ask. It's
+    /// called from the audio thread, so should hand the playhead on rather than draw.
+    pub fn on_playhead(&mut self, rate_hz: f64, callback: impl FnMut(Playhead) + Send + 'static) {
+        self.playhead_listener = Some(PlayheadListener::new(self.sample_rate, rate_hz, callback));
+    }
+
+    /// Send MIDI clock, start, stop and song position to `callback` as the song plays, so
+    /// external synths and drum machines follow it. It's called from the audio thread as
+    /// each block is produced, with when the sample each message goes with will be heard;
+    /// the messages should be held back until then, as a `SyncScheduler` does.
+    pub fn on_sync(&mut self, callback: impl FnMut(Instant, SyncMessage) + Send + 'static) {
+        self.sync = Some(Box::new(callback));
+    }
+
+    /// Say that the samples about to be produced are a block being produced now, which
+    /// sync messages are timed from
+    pub fn begin_block(&mut self) {
+        self.block_start = Some((Instant::now(), self.sample_clock));
+    }
+
+    /// When the sample being produced will be heard: as far into its block as it is after
+    /// the block started, plus the output latency
+    fn heard_at(&self) -> Instant {
+        let (start, start_sample) = self.block_start.unwrap_or_else(|| (Instant::now(), self.sample_clock));
+        let samples = self.sample_clock.saturating_sub(start_sample) + self.output_latency;
+        start + Duration::from_secs_f64(samples as f64 / self.sample_rate as f64)
+    }
+
+    fn send_sync(&mut self, message: SyncMessage) {
+        let at = self.heard_at();
+        if let Some(sync) = &mut self.sync {
+            sync(at, message);
+        }
+    }
+
+    /// The playhead as a MIDI song position, which counts 16ths
+    fn song_position(&self) -> SyncMessage {
+        SyncMessage::SongPosition(u16::try_from(self.time_b32 / 2).unwrap_or(u16::MAX))
+    }
+
+    /// Keep MIDI sync going while playing: start it from the playhead, then send three
+    /// clocks a 32nd, which is 24 a quarter note
+    fn clock(&mut self) {
+        if self.sync.is_none() {
+            return;
+        }
+        if !self.sync_running {
+            self.sync_running = true;
+            if self.time_b32 == 0 && self.tick == 0 {
+                self.send_sync(SyncMessage::Start);
+            } else {
+                self.send_sync(self.song_position());
+                self.send_sync(SyncMessage::Continue);
+            }
+        }
+        let phase = self.tick % self.ticks_per_b32;
+        if phase == 0 || phase == self.ticks_per_b32 / 3 || phase == self.ticks_per_b32 * 2 / 3 {
+            self.send_sync(SyncMessage::Clock);
+        }
+    }
+
+    /// Cap how many voices sound at once, so dense passages don't use more time than a
+    /// block of audio allows. Past it, new notes take over the voices of old ones.
+    pub fn set_max_voices(&mut self, max_voices: usize) {
+        self.max_voices = max_voices.max(1);
+    }
+
+    /// Say how long audio takes from the player to the speakers, so notes recorded along
+    /// with what's heard land where they were played
+    pub fn set_output_latency(&mut self, latency: Duration) {
+        self.output_latency = (latency.as_secs_f64() * self.sample_rate as f64).round() as u64;
+    }
+
+    /// Samples in each 32nd
+    pub fn ticks_per_b32(&self) -> u64 {
+        self.ticks_per_b32
+    }
+
+    /// Where the song being heard is, in 32nds: where the transport was the output latency
+    /// ago, counting any seek, loop or pause in between
+    pub fn heard_position_b32(&self) -> f64 {
+        if self.output_latency == 0 {
+            return self.position_b32();
+        }
+        let sample = self.sample_clock.saturating_sub(self.output_latency);
+        self.history.position_at(sample, self.ticks_per_b32).unwrap_or_else(|| self.position_b32())
+    }
+
+    /// Note where the transport is on the sample being produced
+    fn note_position(&mut self) {
+        let moving = matches!(self.state, PlayState::Playing | PlayState::Preview);
+        let keep_from = self.sample_clock.saturating_sub(self.output_latency);
+        self.history.note(self.sample_clock, self.position_b32(), moving, self.ticks_per_b32, keep_from);
+    }
+
+    /// How long a preview sounds for from when it's asked for, as it's heard
+    fn preview_duration(&self) -> Duration {
+        Duration::from_millis(PREVIEW_DURATION_MS) + Duration::from_secs_f64(self.output_latency as f64 / self.sample_rate as f64)
+    }
+
+    /// Start recording notes played on the MIDI keyboard, playing the song from where it is.
+    /// The transport keeps running past the end of the song while recording. With a
+    /// `punch` range, only what's played inside it is kept.
+    pub fn start_recording(&mut self, punch: Option<PunchRange>) {
+        self.take = Some(punch.map_or_else(Take::default, Take::punched));
+        self.play();
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.take.is_some()
+    }
+
+    pub fn record_note_on(&mut self, pitch: Pitch, velocity: u8) {
+        let at = self.heard_position_b32();
+        if let Some(take) = &mut self.take {
+            take.note_on(pitch, velocity, at);
+        }
+    }
+
+    pub fn record_note_off(&mut self, pitch: Pitch) {
+        let at = self.heard_position_b32();
+        if let Some(take) = &mut self.take {
+            take.note_off(pitch, at);
+        }
+    }
+
+    /// Stop recording and return the notes played, ending any still held now
+    pub fn stop_recording(&mut self) -> Recorded {
+        let at = self.heard_position_b32();
+        self.take.take().map_or_else(Recorded::default, |take| take.finish_punched(at))
+    }
+
     pub fn set_time_b32(&mut self, time_b32: u64) {
         self.pause();
         self.time_b32 = time_b32;
         self.tick = 0;
-        self.active_notes.clear();
-        self.update_active_notes();
+        self.cut_all();
+        self.pending_notes.clear();
+    }
+
+    /// Pick up a change to the tracks' mute, solo or gain. Sounding notes follow it
+    /// straight away rather than at the next note.
+    pub fn refresh_mix(&mut self) {
+        let score = self.score.lock().unwrap();
+        for voice in &mut self.active_notes {
+            voice.level.target = score.track_level(&voice.instrument);
+        }
+        for (instrument, frozen) in &mut self.frozen {
+            frozen.level.target = score.track_level(instrument);
+        }
     }
 
     pub fn set_loop_state(&mut self, loop_state: LoopState) {
         self.loop_state = loop_state;
     }
 
+    /// What's changed in which tracks are frozen since the audio was last swapped in. A
+    /// newly frozen track plays its notes until `apply_frozen` is given its audio.
+    pub fn frozen_update(&self) -> FrozenUpdate {
+        let score = self.score.lock().unwrap();
+        let project_dir = score.project_dir();
+        let sources: BTreeMap<String, (PathBuf, u64)> = score.daw_file().instruments.iter()
+            .filter_map(|(id, instrument)| {
+                let freeze = instrument.frozen.as_ref()?;
+                let start = (freeze.start * self.sample_rate as f64).round() as u64;
+                Some((id.clone(), (project_dir.join(&freeze.audio_file), start)))
+            })
+            .collect();
+        let missing = sources.iter()
+            .filter(|(id, source)| self.frozen.get(*id).is_none_or(|frozen| frozen.source() != **source))
+            .map(|(id, _)| (id.clone(), score.track_level(id)))
+            .collect();
+        FrozenUpdate { sample_rate: self.sample_rate as u32, sources, missing, loaded: Vec::new() }
+    }
+
+    /// Swap in the frozen tracks' audio read by `FrozenUpdate::load`, and let go of the
+    /// audio of tracks no longer frozen
+    pub fn apply_frozen(&mut self, update: FrozenUpdate) {
+        self.frozen.retain(|id, frozen| update.sources.get(id) == Some(&frozen.source()));
+        self.frozen.extend(update.loaded);
+    }
+
+    /// Whether `instrument` plays frozen audio instead of its notes
+    fn is_frozen(&self, instrument: &str) -> bool {
+        self.frozen.get(instrument).is_some_and(|frozen| frozen.samples.is_some())
+    }
+
+    /// Whether any frozen audio goes on past the current 32nd note
+    fn frozen_audio_continues(&self) -> bool {
+        let sample = self.time_b32 * self.ticks_per_b32;
+        self.frozen.values().any(|frozen| frozen.samples.as_ref().is_some_and(|samples| sample < frozen.start + samples.len() as u64))
+    }
+
+    /// Sample of the song being played, while it's playing or being scrubbed
+    fn song_sample(&self) -> Option<u64> {
+        match self.state {
+            PlayState::Playing => Some(self.time_b32 * self.ticks_per_b32 + self.tick % self.ticks_per_b32),
+            PlayState::Preview if self.scrubbing => Some(self.time_b32 * self.ticks_per_b32 + self.tick),
+            _ => None,
+        }
+    }
+
+    /// Notes on the tracks that play their notes, with their tracks, that start in `onsets`
+    fn notes_starting(&self, onsets: std::ops::Range<u64>) -> Vec<(String, Note)> {
+        let score = self.score.lock().unwrap();
+        let mut notes = Vec::new();
+        for instrument in score.played_tracks().into_iter().filter(|id| !self.is_frozen(id)) {
+            let starting = onsets.clone().flat_map(|onset| score.notes_starting_on(&instrument, onset));
+            notes.extend(starting.map(|note| (instrument.clone(), note)));
+        }
+        notes
+    }
+
+    /// Notes on the tracks that play their notes, with their tracks, held over `time_b32`
+    /// from before it
+    fn notes_held_over(&self, time_b32: u64) -> Vec<(String, Note)> {
+        let score = self.score.lock().unwrap();
+        let mut notes = Vec::new();
+        for instrument in score.played_tracks().into_iter().filter(|id| !self.is_frozen(id)) {
+            let held = score.notes_active_on(&instrument, time_b32).into_iter()
+                .filter(|active| active.state == NoteState::Sustain)
+                .map(|active| (instrument.clone(), active.note));
+            notes.extend(held);
+        }
+        notes
+    }
+
     fn update_active_notes(&mut self) {
-        // Get notes starting at current time
-        let new_notes = self
-            .score
-            .lock()
-            .unwrap()
-            .notes_starting_at_time(self.time_b32);
+        self.refresh_mix();
+        // Get the notes each track's groove places in the current 32nd. Grooves move notes
+        // by less than a grid step, so only onsets that close can land here.
+        let score = self.score.lock().unwrap();
+        let mut new_notes = Vec::new();
+        let mut polyphonies = Vec::new();
+        for instrument in score.played_tracks().into_iter().filter(|id| !self.is_frozen(id)) {
+            let groove = score.groove(&instrument);
+            let reach = if groove.is_straight() { 0 } else { groove.grid.step() as u64 };
+            for onset in self.time_b32.saturating_sub(reach)..=self.time_b32 + reach {
+                let grooved = groove.apply(onset as u32);
+                if grooved.floor() as u64 == self.time_b32 {
+                    let delay = ((grooved - self.time_b32 as f64) * self.ticks_per_b32 as f64) as u64;
+                    let notes = score.notes_starting_on(&instrument, onset);
+                    new_notes.extend(notes.into_iter().map(|n| (delay, instrument.clone(), n)));
+                }
+            }
+            polyphonies.push((score.polyphony(&instrument), instrument));
+        }
+        drop(score);
 
-        // Remove finished notes and add new ones
-        self.active_notes
-            .retain(|note| note.onset_b32 + note.duration_b32 > self.time_b32);
-        self.active_notes.extend(new_notes);
+        // Start new notes, holding back those the groove delays
+        let time_b32 = self.time_b32;
+        for (delay, instrument, note) in new_notes {
+            if delay == 0 {
+                self.start_voice(instrument, note, true);
+            } else {
+                self.pending_notes.push((self.tick + delay, instrument, note));
+            }
+        }
+
+        // Cut off notes that lose their voice to the new ones on their track
+        let now = self.sample_clock;
+        for (polyphony, instrument) in polyphonies {
+            let mut held: Vec<&mut Voice> = self.active_notes.iter_mut()
+                .filter(|v| v.instrument == instrument && v.is_held(time_b32))
+                .collect();
+            let requests: Vec<VoiceRequest> = held.iter()
+                .map(|voice| VoiceRequest {
+                    start: voice.note.onset_b32,
+                    end: voice.note.onset_b32 + voice.note.duration_b32,
+                    velocity: voice.note.velocity,
+                })
+                .collect();
+            for (voice, end) in held.iter_mut().zip(polyphony.allocate(&requests)) {
+                if end <= time_b32 {
+                    voice.cut(now);
+                }
+            }
+        }
+    }
+
+    /// Render `note` on `instrument` and start playing it. Previews ignore the song's pitch
+    /// bend, since they don't play at a point in the song.
+    fn start_voice(&mut self, instrument: String, note: Note, follow_bend: bool) {
+        self.join_voice(instrument, note, follow_bend, 0);
+    }
+
+    /// Start playing `note` from `offset` samples in, as if it had been playing all along
+    fn join_voice(&mut self, instrument: String, note: Note, follow_bend: bool, offset: usize) {
+        let samples = self.render_note(&instrument, &note, follow_bend);
+        self.push_voice(instrument, note, samples, offset);
+    }
+
+    /// The track notes are entered on, which previews and the MIDI keyboard play
+    pub fn current_instrument(&self) -> String {
+        self.score.lock().unwrap().instrument().to_string()
+    }
+
+    /// Render `note` dry on the track `instrument`, from its start to the end of its release.
+    /// Notes of the same pitch starting together on a track are told apart by the order
+    /// they're started in, as the renderer tells them apart by their order in the chord.
+    fn render_note(&mut self, instrument: &str, note: &Note, follow_bend: bool) -> Vec<f64> {
+        let doubling = self.active_notes.iter()
+            .filter(|voice| voice.started == self.sample_clock && voice.instrument == instrument)
+            .filter(|voice| voice.note.onset_b32 == note.onset_b32 && voice.note.pitch == note.pitch)
+            .count();
+        let score = self.score.lock().unwrap();
+        render_dry(&mut self.renderer, score.daw_file(), instrument, note, follow_bend, doubling, self.ticks_per_b32)
+    }
+
+    fn push_voice(&mut self, instrument: String, note: Note, samples: Vec<f64>, offset: usize) {
+        self.steal_voices();
+        let joined = (offset > 0).then_some(offset);
+        let gate = (note.duration_b32 * self.ticks_per_b32) as usize;
+        let started = self.sample_clock;
+        let level = Level::new(self.score.lock().unwrap().track_level(&instrument));
+        self.active_notes.push(Voice { instrument, note, samples, position: offset, cut: None, joined, gate, started, level, live: None });
+    }
+
+    /// Sound `notes` together from their starts through the instrument's insert effects, as
+    /// its track sounds in the mix, so previews carry its EQ, distortion and echoes. Only as
+    /// much as a preview plays is rendered.
+    fn start_preview_voice(&mut self, notes: Vec<Note>) {
+        let Some(first) = notes.first().copied() else {
+            return;
+        };
+        let fade = (DECLICK_SECONDS * self.sample_rate as f64).ceil() as usize;
+        let len = (self.sample_rate * PREVIEW_DURATION_MS / 1000) as usize + fade;
+        let instrument = self.current_instrument();
+        let mut mix = Vec::new();
+        for note in &notes {
+            let samples = self.render_note(&instrument, note, false);
+            if mix.len() < samples.len() {
+                mix.resize(samples.len(), 0.0);
+            }
+            mix.iter_mut().zip(samples).for_each(|(sum, sample)| *sum += sample);
+        }
+
+        let score = self.score.lock().unwrap();
+        let daw_file = score.daw_file();
+        if let Some(instrument) = daw_file.get_instrument(&instrument).filter(|i| !i.effects.is_empty()) {
+            let bpm = daw_file.bpm as f64;
+            let tail = (chain_tail_seconds(&instrument.effects, bpm) * self.sample_rate as f64).ceil() as usize;
+            mix.resize((mix.len() + tail).min(len), 0.0);
+            let mut track = Stereo::from_mono(mix);
+            apply_chain(&instrument.effects, &mut track, self.sample_rate as u32, bpm, 1);
+            // Live playback is mono
+            mix = track.left.iter().zip(&track.right).map(|(left, right)| (left + right) / 2.0).collect();
+        }
+        drop(score);
+        mix.truncate(len);
+        let duration_b32 = notes.iter().map(|note| note.duration_b32).max().unwrap_or(first.duration_b32);
+        self.push_voice(instrument, Note { duration_b32, ..first }, mix, 0);
+    }
+
+    /// Make room for one more voice under `max_voices` by fading out the ones it's stolen
+    /// from: notes ringing on after they've been let go first, then the oldest held notes.
+    /// Voices already fading out finish within the declick time, so aren't counted.
+    fn steal_voices(&mut self) {
+        let now = self.sample_clock;
+        let sounding = self.active_notes.iter().filter(|voice| voice.cut.is_none()).count();
+        for _ in self.max_voices - 1..sounding {
+            let victim = self.active_notes.iter_mut()
+                .filter(|voice| voice.cut.is_none())
+                .min_by_key(|voice| (!voice.is_released(), voice.started));
+            if let Some(victim) = victim {
+                victim.cut(now);
+            }
+        }
+    }
+
+    /// Let every sounding note fade out
+    fn cut_all(&mut self) {
+        let now = self.sample_clock;
+        self.active_notes.iter_mut().for_each(|voice| voice.cut(now));
     }
 
     pub fn state(&self) -> PlayState {
-        return self.state;
+        self.state
     }
 
+    /// Move on to the next 32nd, or back to the loop's start once the loop's end is reached
     fn handle_time_update(&mut self) {
         if self.tick != 0 {
             self.time_b32 += 1;
@@ -114,77 +716,697 @@ impl Player {
         if self.loop_state.is_looping() {
             if let (Some(start), Some(end)) = (self.loop_state.start_time_b32, self.loop_state.end_time_b32) {
                 if self.time_b32 >= end || self.time_b32 < start {
-                    self.time_b32 = start;
-                    self.tick = 0;
-                    self.active_notes.clear();
+                    self.wrap_to(start);
                 }
             }
         }
     }
 
-    pub fn preview_note(&mut self, pitch: Pitch) {
+    /// Jump to `start` without a gap, as the loop does when it wraps. Notes still held
+    /// cross-fade into the notes held over `start`, which join partway through; notes
+    /// already let go ring on through their release.
+    fn wrap_to(&mut self, start: u64) {
+        let now = self.sample_clock;
+        let time_b32 = self.time_b32;
+        for voice in &mut self.active_notes {
+            if voice.is_held(time_b32) {
+                voice.cut(now);
+            }
+        }
+        self.pending_notes.clear();
+        self.time_b32 = start;
+        self.tick = 0;
+        // Gear following along jumps back with the song
+        if self.sync_running {
+            self.send_sync(SyncMessage::Stop);
+            self.send_sync(self.song_position());
+            self.send_sync(SyncMessage::Continue);
+        }
+
+        for (instrument, note) in self.notes_held_over(start) {
+            let offset = ((start - note.onset_b32) * self.ticks_per_b32) as usize;
+            self.join_voice(instrument, note, true, offset);
+        }
+    }
+
+    /// Move the playhead to `time_b32`. Playback carries on from there without a gap;
+    /// otherwise the song is left paused or stopped where it lands.
+    pub fn seek(&mut self, time_b32: u64) {
+        match self.state {
+            PlayState::Playing => self.wrap_to(time_b32),
+            state => {
+                self.set_time_b32(time_b32);
+                self.state = if state == PlayState::Preview { PlayState::Stopped } else { state };
+                self.preview_start = None;
+            }
+        }
+    }
+
+    /// Move the playhead to the start of `bar`, counting from 1 as the status bar does
+    pub fn seek_to_bar(&mut self, bar: u64) {
+        self.seek(bar.saturating_sub(1) * 32);
+    }
+
+    /// Fast-forward or rewind `bars` bar lines from the playhead. Rewinding from partway
+    /// through a bar counts its start as the first, so one bar back goes to where the
+    /// current bar began.
+    pub fn nudge(&mut self, bars: i64) {
+        let bar = (self.time_b32 / 32) as i64;
+        let target = if bars < 0 && !self.time_b32.is_multiple_of(32) { bar + bars + 1 } else { bar + bars };
+        self.seek(target.max(0) as u64 * 32);
+    }
+
+    /// Move the playhead back to the top of the song
+    pub fn return_to_start(&mut self) {
+        self.seek(0);
+    }
+
+    /// Move the playhead to the marker called `name` (ignoring case) and return where it
+    /// is. Markers sharing a name are visited in turn: the first one after the playhead,
+    /// or the first in the song once past the last.
+    pub fn go_to_marker(&mut self, name: &str) -> Result<u64> {
+        let times: Vec<u64> = self.score.lock().unwrap()
+            .markers()
+            .into_iter()
+            .filter(|(_, marker)| marker.eq_ignore_ascii_case(name.trim()))
+            .map(|(time_b32, _)| time_b32)
+            .collect();
+        match self.seek_next_of(times) {
+            Some(time_b32) => Ok(time_b32),
+            None => bail!("There's no marker called '{}'", name),
+        }
+    }
+
+    /// Move the playhead to the first marker after it, or the first in the song once past
+    /// the last, and return where it is
+    pub fn go_to_next_marker(&mut self) -> Option<u64> {
+        let times = self.score.lock().unwrap().markers().into_iter().map(|(time_b32, _)| time_b32).collect();
+        self.seek_next_of(times)
+    }
+
+    /// Seek to the first of `times` after the playhead, going round to the first of them
+    fn seek_next_of(&mut self, times: Vec<u64>) -> Option<u64> {
+        let first = *times.first()?;
+        let time_b32 = times.into_iter().find(|&time_b32| time_b32 > self.time_b32).unwrap_or(first);
+        self.seek(time_b32);
+        Some(time_b32)
+    }
+
+    /// Move grooved notes whose start tick has come into the active notes
+    fn start_pending_notes(&mut self) {
+        let tick = self.tick;
+        let (due, waiting): (Vec<_>, Vec<_>) = std::mem::take(&mut self.pending_notes)
+            .into_iter()
+            .partition(|(start, _, _)| *start <= tick);
+        self.pending_notes = waiting;
+        for (_, instrument, note) in due {
+            self.start_voice(instrument, note, true);
+        }
+    }
+
+    /// Silence everything and start a preview, which ends on its own
+    fn start_preview(&mut self) {
         self.state = PlayState::Preview;
-        self.active_notes.clear();
-        self.active_notes.push(Note {
+        self.scrubbing = false;
+        self.cut_all();
+        self.pending_notes.clear();
+        self.preview_start = Some(Instant::now());
+    }
+
+    pub fn preview_note(&mut self, pitch: Pitch) {
+        self.start_preview();
+        let note = Note {
             pitch,
             onset_b32: 0,
             duration_b32: 16,
-        });
-        self.preview_start = Some(Instant::now());
+            velocity: DEFAULT_VELOCITY,
+        };
+        self.start_preview_voice(vec![note]);
+    }
+
+    /// Play every note on the current track sounding at `time_b32` together from its start,
+    /// as `preview_note` plays one, so the chord under the cursor can be heard
+    pub fn audition_chord(&mut self, time_b32: u64) {
+        self.start_preview();
+        let notes = self.score.lock().unwrap().notes_active_at_time(time_b32)
+            .into_iter()
+            .filter(|active| active.state != NoteState::Release)
+            .map(|active| Note { onset_b32: time_b32, duration_b32: 16, ..active.note })
+            .collect();
+        self.start_preview_voice(notes);
+    }
+
+    /// Move the playhead to `time_b32` and play a moment of the song from there, so a spot
+    /// can be found by ear while dragging the playhead. Notes held over it join partway
+    /// through and notes starting in the moment come in on time.
+    pub fn scrub(&mut self, time_b32: u64) {
+        self.start_preview();
+        self.scrubbing = true;
+        self.time_b32 = time_b32;
+        self.tick = 0;
+        let window_b32 = (self.sample_rate * PREVIEW_DURATION_MS / 1000).div_ceil(self.ticks_per_b32);
+        for (instrument, note) in self.notes_held_over(time_b32) {
+            let offset = ((time_b32 - note.onset_b32) * self.ticks_per_b32) as usize;
+            self.join_voice(instrument, note, true, offset);
+        }
+        for (instrument, note) in self.notes_starting(time_b32..time_b32 + window_b32) {
+            match (note.onset_b32 - time_b32) * self.ticks_per_b32 {
+                0 => self.start_voice(instrument, note, true),
+                start => self.pending_notes.push((start, instrument, note)),
+            }
+        }
+    }
+
+    /// Sound a key held down on a MIDI keyboard, rendered by `LiveNotes`, over the song if
+    /// it's playing. Unlike `preview_note` it leaves other notes sounding, so chords can
+    /// be heard, and it's held until the key's let go.
+    pub fn start_live_voice(&mut self, key: u64, instrument: String, note: Note, samples: Vec<f64>) {
+        if self.state != PlayState::Playing {
+            self.state = PlayState::Preview;
+            self.preview_start = Some(Instant::now());
+        }
+        self.push_voice(instrument, note, samples, 0);
+        if let Some(voice) = self.active_notes.last_mut() {
+            voice.live = Some(key);
+        }
+    }
+
+    /// How far the held key `key` has played, or `None` once it's been let go or its
+    /// voice was stolen
+    pub fn live_voice_position(&self, key: u64) -> Option<usize> {
+        self.active_notes.iter()
+            .find(|voice| voice.live == Some(key) && voice.cut.is_none())
+            .map(|voice| voice.position)
+    }
+
+    /// Swap the rendering of the held key `key` for one of `note`, which starts the same
+    /// but is held for longer, or ends where the key was let go unless it's `held`. The
+    /// voice plays on from where it's got to.
+    pub fn replace_live_voice(&mut self, key: u64, note: Note, samples: Vec<f64>, held: bool) {
+        let ticks_per_b32 = self.ticks_per_b32;
+        let Some(voice) = self.active_notes.iter_mut().find(|voice| voice.live == Some(key)) else {
+            return;
+        };
+        voice.gate = (note.duration_b32 * ticks_per_b32) as usize;
+        voice.note = note;
+        voice.samples = samples;
+        if !held {
+            voice.live = None;
+            // A preview lasts long enough from here for the release to be heard
+            if self.preview_start.is_some() {
+                self.preview_start = Some(Instant::now());
+            }
+        }
+    }
+
+    /// The gate and samples of the `index`th voice sounding
+    #[cfg(test)]
+    pub fn voice_rendering(&self, index: usize) -> (usize, Vec<f64>) {
+        let voice = &self.active_notes[index];
+        (voice.gate, voice.samples.clone())
+    }
+
+    /// Whether a key played on the MIDI keyboard is still held down
+    fn live_key_held(&self) -> bool {
+        self.active_notes.iter().any(|voice| voice.live.is_some() && voice.cut.is_none())
     }
 
     pub fn clear_preview(&mut self) {
         if self.state == PlayState::Preview {
             self.state = PlayState::Stopped;
-            self.active_notes.clear();
+            self.scrubbing = false;
+            self.cut_all();
+            self.pending_notes.clear();
             self.preview_start = None;
+            // Playing on from here starts the playhead's 32nd from its first sample
+            self.tick = 0;
         }
     }
 }
 
+/// Render `note` dry on the track `instrument`, from its start to the end of its release,
+/// with the noise the renderer gives it. `doubling` is how many notes of the same pitch
+/// start with it before it. Unless it's to `follow_bend`, it ignores the song's pitch bend.
+pub fn render_dry(
+    renderer: &mut NoteRenderer,
+    daw_file: &DawFile,
+    instrument: &str,
+    note: &Note,
+    follow_bend: bool,
+    doubling: usize,
+    ticks_per_b32: u64,
+) -> Vec<f64> {
+    let bend = follow_bend.then(|| daw_file.control_curve(instrument, Controller::PitchBend))
+        .filter(|bend| !bend.is_empty());
+    let played = PlayedNote {
+        pitch: note.pitch,
+        velocity: note.velocity,
+        gate: (note.duration_b32 * ticks_per_b32) as usize,
+        seed: note_seed(daw_file.seed, instrument, note.onset_b32, note.pitch, doubling),
+    };
+    renderer.render(daw_file, instrument, &played, usize::MAX, |i| {
+        bend.as_ref().map_or(1.0, |bend| bend_ratio(bend.value_at(note.onset_b32 as f64 + i as f64 / ticks_per_b32 as f64)))
+    })
+}
+
 impl Iterator for Player {
     type Item = f64;
 
     fn next(&mut self) -> Option<Self::Item> {
-        // Check if preview should end
+        // Check if preview should end; keys held on a MIDI keyboard keep it going
         if let Some(start_time) = self.preview_start {
-            const PREVIEW_DURATION_MS: u128 = 250;
-            if start_time.elapsed().as_millis() > PREVIEW_DURATION_MS {
+            if start_time.elapsed() > self.preview_duration() && !self.live_key_held() {
                 self.clear_preview();
             }
         }
 
+        let mut song_sample = None;
         match self.state {
             PlayState::Playing => {
-                if self.tick % self.ticks_per_b32 == 0 {
-                    if self.score.lock().unwrap().time_within_song(self.time_b32) {
+                // Each 32nd's notes start on its first sample
+                if self.tick.is_multiple_of(self.ticks_per_b32) {
+                    self.handle_time_update();
+                    let heard = self.heard_position_b32();
+                    if let Some(take) = &mut self.take {
+                        take.reach(heard);
+                    }
+                    if self.take.is_some() || self.frozen_audio_continues() || self.score.lock().unwrap().time_within_song(self.time_b32) {
                         self.update_active_notes();
-                        self.handle_time_update();
                     } else {
-                        self.active_notes.clear();
                         self.stop();
                     }
                 }
+                if self.state == PlayState::Playing {
+                    self.clock();
+                }
+                self.note_position();
+                song_sample = self.song_sample();
                 self.tick += 1;
+                self.start_pending_notes();
             }
             PlayState::Preview => {
-                // Just continue playing the preview note
+                // Just continue playing the preview, bringing in notes a scrub reaches
+                self.note_position();
+                song_sample = self.song_sample();
                 self.tick += 1;
+                self.start_pending_notes();
             }
-            _ => return Some(0.0),
+            _ => self.note_position(),
+        }
+        if self.sync_running && self.state != PlayState::Playing {
+            self.sync_running = false;
+            self.send_sync(SyncMessage::Stop);
+        }
+
+        // While paused, held notes wait where they are but cut off ones finish fading out
+        let now = self.sample_clock;
+        self.sample_clock += 1;
+        let fade = DECLICK_SECONDS * self.sample_rate as f64;
+        self.active_notes.retain(|voice| !voice.is_finished(now, fade));
+        let sounding = self.is_playing();
+        let step = 1.0 / fade;
+        let frozen: f64 = self.frozen.values_mut().map(|frozen| frozen.next_sample(song_sample, step)).sum();
+        let sum: f64 = self.active_notes.iter_mut()
+            .filter(|voice| sounding || voice.cut.is_some())
+            .map(|voice| voice.next_sample(now, fade) * voice.level.next(step))
+            .sum::<f64>()
+            + frozen;
+        if let Some(mut listener) = self.playhead_listener.take() {
+            listener.update(|| self.playhead());
+            self.playhead_listener = Some(listener);
+        }
+        Some(sum)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loop_state::LoopMode;
+    use crate::score::INSTRUMENT;
+    use dawww_core::effects::{Effect, EffectInstance, GainParams};
+    use dawww_core::pitch::Tone;
+    use dawww_core::{DawFile, Instrument, LyricEvent};
+
+    #[test]
+    fn test_loop_wraps_seamlessly() {
+        let (pad, stab) = (Pitch::new(Tone::C, 3), Pitch::new(Tone::E, 4));
+        let mut score = Score::new();
+        score.insert(pad, 0, 32);
+        score.insert(stab, 4, 2);
+        // 125 samples a 32nd
+        let mut player = Player::create(Arc::new(Mutex::new(score)), 8000);
+        let loop_state = LoopState { start_time_b32: Some(4), end_time_b32: Some(8), mode: LoopMode::Looping, punch: false };
+        player.set_loop_state(loop_state);
+        player.set_time_b32(4);
+        player.play();
+
+        player.next();
+        assert_eq!(player.active_notes.len(), 1);
+        assert_eq!(player.active_notes[0].note.pitch, stab);
+        for _ in 1..500 {
+            player.next();
         }
+        assert_eq!(player.playhead().time_b32, 7);
+
+        // The loop's start plays on the sample after its end, and the pad held over it
+        // joins partway through
+        player.next();
+        assert_eq!(player.playhead().time_b32, 4);
+        // (the stab from the first time round may still be ringing out)
+        let stabs = player.active_notes.iter().filter(|voice| voice.note.pitch == stab && voice.position == 1).count();
+        assert_eq!(stabs, 1);
+        let pad_voice = player.active_notes.iter().find(|voice| voice.note.pitch == pad).unwrap();
+        assert_eq!((pad_voice.joined, pad_voice.position), (Some(500), 501));
+    }
+
+    #[test]
+    fn test_transport() {
+        let mut daw_file = DawFile::new("Markers".to_string());
+        daw_file.add_lyric(LyricEvent::annotation("3.0", "Chorus")).unwrap();
+        daw_file.add_lyric(LyricEvent::annotation("7.0", "chorus")).unwrap();
+        // As a hand-edited file might have it, out of time order
+        daw_file.lyrics.insert(0, LyricEvent::annotation("5.0", "Verse"));
+        let mut score = Score::from_daw_file(daw_file);
+        score.insert(Pitch::new(Tone::C, 4), 60, 8);
+        let mut player = Player::create(Arc::new(Mutex::new(score)), 8000);
 
-        if self.active_notes.is_empty() {
-            return Some(0.0);
+        player.seek_to_bar(2);
+        assert_eq!((player.current_time_b32(), player.state()), (32, PlayState::Stopped));
+        player.nudge(2);
+        assert_eq!(player.current_time_b32(), 96);
+        player.seek(100);
+        player.nudge(-1);
+        assert_eq!(player.current_time_b32(), 96);
+        player.nudge(-5);
+        assert_eq!(player.current_time_b32(), 0);
+
+        // Markers of the same name are visited in turn
+        assert_eq!(player.go_to_marker("CHORUS").unwrap(), 64);
+        assert_eq!(player.go_to_marker("chorus").unwrap(), 192);
+        assert_eq!(player.go_to_marker("chorus").unwrap(), 64);
+        assert!(player.go_to_marker("bridge").is_err());
+
+        // Any marker, in time order
+        player.return_to_start();
+        assert_eq!(player.go_to_next_marker(), Some(64));
+        assert_eq!(player.go_to_next_marker(), Some(128));
+        assert_eq!(player.go_to_next_marker(), Some(192));
+        assert_eq!(player.go_to_next_marker(), Some(64));
+
+        // Seeking while playing carries on from the new spot, joining notes held over it
+        player.return_to_start();
+        player.play();
+        player.next();
+        player.seek(62);
+        assert_eq!(player.state(), PlayState::Playing);
+        let joined: Vec<Option<usize>> = player.active_notes.iter().map(|voice| voice.joined).collect();
+        assert_eq!(joined, vec![Some(250)]);
+        player.next();
+        assert_eq!(player.current_time_b32(), 62);
+    }
+
+    #[test]
+    fn test_every_audible_track_plays() {
+        let mut daw_file = DawFile::new("Tracks".to_string());
+        for (instrument, tone) in [("bass", Tone::C), ("drums", Tone::D), ("lead", Tone::E)] {
+            daw_file.add_instrument(instrument.to_string(), Instrument::new_subtractive(Default::default())).unwrap();
+            daw_file.add_note("1.0", instrument, dawww_core::Note::new(Pitch::new(tone, 3), 8)).unwrap();
+        }
+        daw_file.mixer.set_mute("drums", true);
+        let mut score = Score::from_daw_file(daw_file);
+        score.select_instrument("lead").unwrap();
+        let mut player = Player::create(Arc::new(Mutex::new(score)), 8000);
+
+        // The current track is only where notes are entered; muted tracks aren't rendered
+        player.play();
+        player.next();
+        let playing: Vec<&str> = player.active_notes.iter().map(|voice| voice.instrument.as_str()).collect();
+        assert_eq!(playing, vec!["bass", "lead"]);
+
+        // Muting a sounding track fades it out
+        player.score.lock().unwrap().set_mute(true);
+        player.refresh_mix();
+        let levels: Vec<f64> = player.active_notes.iter().map(|voice| voice.level.target).collect();
+        assert_eq!(levels, vec![1.0, 0.0]);
+    }
+
+    #[test]
+    fn test_voices_get_the_noise_of_the_bounce() {
+        use dawww_core::instrument::PercussionParams;
+
+        let hat = PercussionParams { tone_level: 0.0, noise_level: 1.0, ..PercussionParams::default() };
+        let mut daw_file = DawFile::new("Hats".to_string());
+        daw_file.set_seed(3);
+        daw_file.add_instrument(INSTRUMENT.to_string(), Instrument::new_percussion(hat)).unwrap();
+        let c = Pitch::new(Tone::C, 4);
+        for time in ["1.0", "1.16", "1.16"] {
+            daw_file.add_note(time, INSTRUMENT, dawww_core::Note::new(c, 1)).unwrap();
         }
+        let mut renderer = NoteRenderer::new(8000, PathBuf::new());
+        let mut bounced = |doubling| {
+            let played = PlayedNote { pitch: c, velocity: DEFAULT_VELOCITY, gate: 125, seed: note_seed(3, INSTRUMENT, 16, c, doubling) };
+            renderer.render(&daw_file, INSTRUMENT, &played, usize::MAX, |_| 1.0)
+        };
+        let expected = [bounced(0), bounced(1)];
+        assert_ne!(expected[0], expected[1]);
 
-        let mut total_amplitudes: f64 = 0.0;
-        for note in &self.active_notes {
-            let frequency = note.pitch.frequency(note.pitch.octave);
-            total_amplitudes +=
-                (2.0 * PI * frequency * (self.tick as f64) / self.sample_rate as f64).sin();
+        // However many notes were started before them, the doubled hits sound as they're
+        // bounced
+        let mut player = Player::create(Arc::new(Mutex::new(Score::from_daw_file(daw_file))), 8000);
+        for start in [0, 16] {
+            player.set_time_b32(start);
+            player.play();
+            for _ in 0..=(16 - start) * 125 {
+                player.next();
+            }
+            let voices: Vec<&Vec<f64>> = player.active_notes.iter()
+                .filter(|voice| voice.note.onset_b32 == 16 && voice.cut.is_none())
+                .map(|voice| &voice.samples)
+                .collect();
+            assert_eq!(voices, [&expected[0], &expected[1]]);
         }
+    }
+
+    fn live_note(tone: Tone) -> Note {
+        Note { pitch: Pitch::new(tone, 4), onset_b32: 0, duration_b32: 32, velocity: 100 }
+    }
+
+    #[test]
+    fn test_voice_stealing() {
+        let mut player = Player::create(Arc::new(Mutex::new(Score::new())), 8000);
+        player.set_max_voices(2);
+        for (key, tone) in [Tone::C, Tone::E, Tone::G].into_iter().enumerate() {
+            player.start_live_voice(key as u64, INSTRUMENT.to_string(), live_note(tone), vec![0.5; 8000]);
+        }
+        // The oldest note gives up its voice and fades out
+        let held: Vec<Tone> = player.active_notes.iter().filter(|voice| voice.cut.is_none()).map(|voice| voice.note.pitch.tone).collect();
+        assert_eq!(held, vec![Tone::E, Tone::G]);
+        for _ in 0..100 {
+            player.next();
+        }
+        assert_eq!(player.active_notes.len(), 2);
+    }
+
+    #[test]
+    fn test_held_keys_keep_a_preview_going() {
+        let mut player = Player::create(Arc::new(Mutex::new(Score::new())), 8000);
+        player.start_live_voice(0, INSTRUMENT.to_string(), live_note(Tone::C), vec![0.5; 8000]);
+        player.start_live_voice(1, INSTRUMENT.to_string(), live_note(Tone::E), vec![0.5; 8000]);
+        // Held past the preview's length, both keys keep sounding
+        let long_ago = Instant::now().checked_sub(Duration::from_millis(PREVIEW_DURATION_MS * 2)).unwrap();
+        player.preview_start = Some(long_ago);
+        player.next();
+        assert_eq!(player.state(), PlayState::Preview);
+        assert_eq!((player.live_voice_position(0), player.live_voice_position(1)), (Some(1), Some(1)));
+
+        // Letting go of one swaps in its ending and gives the preview time to play it
+        player.replace_live_voice(0, Note { duration_b32: 1, ..live_note(Tone::C) }, vec![0.25; 400], false);
+        player.next();
+        assert_eq!(player.state(), PlayState::Preview);
+        assert_eq!((player.live_voice_position(0), player.live_voice_position(1)), (None, Some(2)));
+        assert_eq!(player.active_notes[0].gate, 125);
+
+        // Once no key's held, the preview ends as usual
+        player.replace_live_voice(1, Note { duration_b32: 1, ..live_note(Tone::E) }, vec![0.25; 400], false);
+        player.preview_start = Some(long_ago);
+        player.next();
+        assert_eq!(player.state(), PlayState::Stopped);
+    }
+
+    #[test]
+    fn test_recording_allows_for_latency() {
+        let mut player = Player::create(Arc::new(Mutex::new(Score::new())), 8000);
+        // 80 samples, or 0.64 of a 32nd
+        player.set_output_latency(Duration::from_millis(10));
+        player.start_recording(None);
+        for _ in 0..200 {
+            player.next();
+        }
+        player.record_note_on(Pitch::new(Tone::C, 4), 100);
+        let take = player.stop_recording();
+        assert!((take.notes[0].start - 0.96).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_playhead_is_where_its_heard() {
+        let mut score = Score::new();
+        score.insert(Pitch::new(Tone::C, 3), 0, 64);
+        let mut player = Player::create(Arc::new(Mutex::new(score)), 8000);
+        // 80 samples, or 0.64 of a 32nd
+        player.set_output_latency(Duration::from_millis(10));
+        let run = |player: &mut Player, samples: usize| (0..samples).for_each(|_| { player.next(); });
+        player.play();
+        run(&mut player, 40);
+        assert_eq!(player.playhead().position_b32, 0.0);
+        run(&mut player, 160);
+        assert!((player.playhead().position_b32 - 0.96).abs() < 1e-9);
+
+        // Paused, what's heard catches up with the transport and stops there
+        player.pause();
+        run(&mut player, 40);
+        assert!((player.playhead().position_b32 - 1.28).abs() < 1e-9);
+        run(&mut player, 100);
+        assert!((player.playhead().position_b32 - 1.6).abs() < 1e-9);
+
+        // A seek while playing is heard once the audio from before it has played
+        player.play();
+        player.seek(16);
+        run(&mut player, 40);
+        assert!((player.playhead().position_b32 - 1.6).abs() < 1e-9);
+        run(&mut player, 60);
+        assert!((player.playhead().position_b32 - 16.16).abs() < 1e-9);
+        assert_eq!((player.playhead().time_b32, player.current_time_b32()), (16, 16));
+    }
+
+    #[test]
+    fn test_midi_sync() {
+        let mut score = Score::new();
+        score.insert(Pitch::new(Tone::C, 3), 0, 64);
+        let mut player = Player::create(Arc::new(Mutex::new(score)), 8000);
+        let (tx, rx) = std::sync::mpsc::channel();
+        player.on_sync(move |at, message| tx.send((at, message)).unwrap());
+        // 80 samples
+        player.set_output_latency(Duration::from_millis(10));
+
+        // Two 32nds from the top: six clocks, produced in one block but timed to go out
+        // as each of their samples is heard
+        player.play();
+        player.begin_block();
+        let (block_start, _) = player.block_start.unwrap();
+        for _ in 0..250 {
+            player.next();
+        }
+        player.pause();
+        player.next();
+        let (times, sent): (Vec<Instant>, Vec<SyncMessage>) = rx.try_iter().unzip();
+        assert_eq!(sent[0], SyncMessage::Start);
+        assert_eq!(sent[1..7], [SyncMessage::Clock; 6]);
+        assert_eq!(sent[7..], [SyncMessage::Stop]);
+        let offsets: Vec<u128> = times.iter().map(|at| at.duration_since(block_start).as_micros()).collect();
+        // Clocks on samples 0, 41, 83 and 125, 166, 208, and the stop on 250
+        assert_eq!(offsets, vec![10000, 10000, 15125, 20375, 25625, 30750, 36000, 41250]);
+
+        // Playing on from elsewhere says where first
+        player.set_time_b32(8);
+        player.play();
+        player.next();
+        let sent: Vec<SyncMessage> = rx.try_iter().map(|(_, message)| message).collect();
+        assert_eq!(sent, vec![SyncMessage::SongPosition(4), SyncMessage::Continue, SyncMessage::Clock]);
+    }
+
+    #[test]
+    fn test_scrub_and_audition() {
+        let (pad, stab) = (Pitch::new(Tone::C, 3), Pitch::new(Tone::E, 4));
+        let mut score = Score::new();
+        score.insert(pad, 0, 32);
+        score.insert(stab, 5, 2);
+        let mut player = Player::create(Arc::new(Mutex::new(score)), 8000);
+
+        // The pad joins where the playhead lands and the stab comes in a 32nd later
+        player.scrub(4);
+        assert_eq!(player.state(), PlayState::Preview);
+        assert_eq!(player.playhead().time_b32, 4);
+        assert_eq!(player.active_notes.len(), 1);
+        assert_eq!(player.active_notes[0].joined, Some(500));
+        for _ in 0..125 {
+            player.next();
+        }
+        assert_eq!(player.active_notes.len(), 2);
+
+        // Auditioning cuts the scrub off and plays the chord under the cursor afresh, as
+        // one voice through the track's effects
+        player.audition_chord(5);
+        let fresh: Vec<&Voice> = player.active_notes.iter().filter(|voice| voice.cut.is_none()).collect();
+        assert_eq!(fresh.len(), 1);
+        assert_eq!((fresh[0].note.pitch, fresh[0].position), (pad, 0));
+    }
+
+    #[test]
+    fn test_frozen_tracks_play_their_audio() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let mut daw_file = DawFile::new("Frozen".to_string());
+        daw_file.mixdown = dawww_core::MixdownSettings::float(8000);
+        daw_file.add_instrument(INSTRUMENT.to_string(), Instrument::new_subtractive(Default::default())).unwrap();
+        daw_file.add_note("1.0", INSTRUMENT, dawww_core::Note::new(Pitch::new(Tone::A, 4), 32)).unwrap();
+        dawww_render::freeze_instrument(&mut daw_file, temp_dir.path(), INSTRUMENT).unwrap();
+        let mut score = Score::from_daw_file(daw_file);
+        score.set_save_path(temp_dir.path().join("song.daw.json"));
+        let mut player = Player::create(Arc::new(Mutex::new(score)), 8000);
+        let audio = player.frozen[INSTRUMENT].samples.clone().unwrap();
+
+        // The notes aren't rendered again: the frozen audio plays from the playhead
+        player.set_time_b32(2);
+        player.play();
+        let played: Vec<f64> = player.by_ref().take(400).collect();
+        assert!(player.active_notes.is_empty());
+        assert!(played.iter().any(|sample| sample.abs() > 0.01));
+        assert_eq!(played, audio[250..650]);
+
+        // Moved later in the song, it's read again and plays from where it starts
+        let score = player.score.clone();
+        let mut daw_file = score.lock().unwrap().daw_file().clone();
+        let freeze = daw_file.instruments.get_mut(INSTRUMENT).unwrap().frozen.take().unwrap();
+        daw_file.instruments.get_mut(INSTRUMENT).unwrap().frozen = Some(freeze.starting_at(0.125));
+        let mut moved = Score::from_daw_file(daw_file);
+        moved.set_save_path(temp_dir.path().join("song.daw.json"));
+        *score.lock().unwrap() = moved;
+        let update = player.frozen_update().load();
+        player.apply_frozen(update);
+        player.set_time_b32(8);
+        player.play();
+        let played: Vec<f64> = player.by_ref().take(400).collect();
+        assert_eq!(played, audio[..400]);
+
+        // Unfreezing is picked up once the change is applied, not on the audio thread
+        let mut daw_file = score.lock().unwrap().daw_file().clone();
+        dawww_render::unfreeze_instrument(&mut daw_file, temp_dir.path(), INSTRUMENT).unwrap();
+        *score.lock().unwrap() = Score::from_daw_file(daw_file);
+        assert!(player.frozen.contains_key(INSTRUMENT));
+        let update = player.frozen_update().load();
+        player.apply_frozen(update);
+        assert!(player.frozen.is_empty());
+    }
 
-        Some(total_amplitudes / self.active_notes.len() as f64)
+    #[test]
+    fn test_previews_play_through_the_instrument_effects() {
+        let preview = |daw_file: DawFile| {
+            let mut player = Player::create(Arc::new(Mutex::new(Score::from_daw_file(daw_file))), 8000);
+            player.preview_note(Pitch::new(Tone::A, 4));
+            player.active_notes[0].samples.clone()
+        };
+        let with_inserts = |effects: Vec<EffectInstance>| {
+            let mut daw_file = DawFile::new("Preview".to_string());
+            let instrument = effects.into_iter().fold(Instrument::new_sampler(INSTRUMENT.into()), Instrument::with_effect);
+            daw_file.add_instrument(INSTRUMENT.to_string(), instrument).unwrap();
+            daw_file
+        };
+        let dry = preview(with_inserts(vec![]));
+        let quieter = preview(with_inserts(vec![EffectInstance::new(Effect::Gain(GainParams::new(-6.0)))]));
+        assert!(dry.iter().any(|sample| sample.abs() > 0.01));
+        // Previews stop after PREVIEW_DURATION_MS, so no more than that is rendered
+        assert!(quieter.len() <= 2000 + 24);
+        let gain = 10f64.powf(-6.0 / 20.0);
+        assert!(dry.iter().zip(&quieter).all(|(dry, wet)| (dry * gain - wet).abs() < 1e-9));
     }
 }
@@ -0,0 +1,94 @@
+use crate::player::PlayState;
+
+/// Where the transport is, as the player reports it to the UI
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Playhead {
+    pub state: PlayState,
+    pub recording: bool,
+    /// 32nd the transport is in
+    pub time_b32: u64,
+    /// Position in 32nds, including how far through the current one it is
+    pub position_b32: f64,
+}
+
+impl Playhead {
+    pub fn is_playing(&self) -> bool {
+        self.state == PlayState::Playing || self.state == PlayState::Preview
+    }
+
+    /// Bar the playhead is in, counting from 1
+    #[cfg(test)]
+    pub fn bar(&self) -> u64 {
+        self.time_b32 / 32 + 1
+    }
+
+    /// Beat of the bar the playhead is in, counting from 1
+    #[cfg(test)]
+    pub fn beat(&self) -> u64 {
+        self.time_b32 % 32 / 8 + 1
+    }
+}
+
+/// Hands the playhead to a callback every so many samples, if it's changed since last time
+pub struct PlayheadListener {
+    interval: u64,
+    countdown: u64,
+    last: Option<Playhead>,
+    callback: Box<dyn FnMut(Playhead) + Send>,
+}
+
+impl PlayheadListener {
+    /// Report about `rate_hz` times a second of audio at `sample_rate`
+    pub fn new(sample_rate: u64, rate_hz: f64, callback: impl FnMut(Playhead) + Send + 'static) -> Self {
+        let interval = (sample_rate as f64 / rate_hz).round().max(1.0) as u64;
+        Self { interval, countdown: 0, last: None, callback: Box::new(callback) }
+    }
+
+    /// Called once a sample. `playhead` is only asked for when a report is due.
+    pub fn update(&mut self, playhead: impl FnOnce() -> Playhead) {
+        if self.countdown > 0 {
+            self.countdown -= 1;
+            return;
+        }
+        self.countdown = self.interval - 1;
+        let playhead = playhead();
+        if self.last != Some(playhead) {
+            self.last = Some(playhead);
+            (self.callback)(playhead);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_listener() {
+        let (tx, rx) = mpsc::channel();
+        let mut listener = PlayheadListener::new(1000, 250.0, move |playhead| tx.send(playhead).unwrap());
+        let at = |sample: u64, state| Playhead {
+            state,
+            recording: false,
+            time_b32: sample / 10,
+            position_b32: sample as f64 / 10.0,
+        };
+        for sample in 0..10 {
+            listener.update(|| at(sample, PlayState::Playing));
+        }
+        // Stopped, the playhead stays put and isn't reported again
+        for _ in 0..10 {
+            listener.update(|| at(10, PlayState::Stopped));
+        }
+        let reported: Vec<Playhead> = rx.try_iter().collect();
+        let positions: Vec<f64> = reported.iter().map(|playhead| playhead.position_b32).collect();
+        assert_eq!(positions, vec![0.0, 0.4, 0.8, 1.0]);
+        assert_eq!(reported[3].state, PlayState::Stopped);
+
+        let playhead = at(1000, PlayState::Playing);
+        assert_eq!((playhead.bar(), playhead.beat()), (4, 1));
+        let playhead = at(1200, PlayState::Playing);
+        assert_eq!((playhead.bar(), playhead.beat()), (4, 4));
+    }
+}
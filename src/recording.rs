@@ -0,0 +1,176 @@
+use dawww_core::pitch::Pitch;
+
+/// A note played while recording, timed in 32nd notes from the start of the song
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TakeNote {
+    pub pitch: Pitch,
+    pub velocity: u8,
+    pub start: f64,
+    pub end: f64,
+}
+
+impl TakeNote {
+    /// Onset and duration in 32nds, on a grid of `grid` 32nds. The score can't hold
+    /// anything finer than a 32nd, so unquantized notes still land on one. Notes last at
+    /// least one step, including ones let go after the loop jumped back.
+    pub fn placed(&self, grid: u64) -> (u64, u64) {
+        let grid = grid.max(1) as f64;
+        let onset = (self.start.max(0.0) / grid).round() * grid;
+        let steps = ((self.end - self.start) / grid).round().max(1.0);
+        (onset as u64, (steps * grid) as u64)
+    }
+}
+
+/// The part of the song a punch-in recording replaces, in 32nds from `start_b32` up to
+/// `end_b32`. The song plays on either side of it, but only notes started inside it are
+/// kept, and those held past its end are cut short there.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PunchRange {
+    pub start_b32: u64,
+    pub end_b32: u64,
+}
+
+impl PunchRange {
+    /// The range between two times, given in either order
+    pub fn new(a: u64, b: u64) -> Self {
+        Self { start_b32: a.min(b), end_b32: a.max(b) }
+    }
+
+    pub fn contains(&self, at: f64) -> bool {
+        at >= self.start_b32 as f64 && at < self.end_b32 as f64
+    }
+}
+
+/// What a recording leaves to go into the score
+#[derive(Debug, Default)]
+pub struct Recorded {
+    pub notes: Vec<TakeNote>,
+    /// Part of the score the notes replace, if the recording punched in: the punch range,
+    /// or as much of it as the transport got through if recording stopped partway
+    pub replaces: Option<PunchRange>,
+}
+
+/// The notes played during one recording
+#[derive(Debug, Default)]
+pub struct Take {
+    /// Notes still held down, with `end` not yet known
+    held: Vec<TakeNote>,
+    notes: Vec<TakeNote>,
+    punch: Option<PunchRange>,
+    /// Whether the transport has been inside the punch range
+    punched_in: bool,
+    /// Furthest the transport has got, in 32nds
+    reached: f64,
+}
+
+impl Take {
+    /// A take that only records inside `punch`
+    pub fn punched(punch: PunchRange) -> Self {
+        Self { punch: Some(punch), ..Self::default() }
+    }
+
+    /// Note that the transport reached `at`, so a punch range it's inside gets replaced
+    /// even if nothing is played there
+    pub fn reach(&mut self, at: f64) {
+        self.punched_in |= self.punch.is_some_and(|punch| punch.contains(at));
+        self.reached = self.reached.max(at);
+    }
+
+    pub fn note_on(&mut self, pitch: Pitch, velocity: u8, at: f64) {
+        // Striking a key again before the keyboard let it go ends the first note
+        self.note_off(pitch, at);
+        self.held.push(TakeNote { pitch, velocity, start: at, end: at });
+    }
+
+    pub fn note_off(&mut self, pitch: Pitch, at: f64) {
+        if let Some(index) = self.held.iter().position(|note| note.pitch == pitch) {
+            let note = self.held.remove(index);
+            self.notes.push(TakeNote { end: at, ..note });
+        }
+    }
+
+    /// The notes played, in the order they started. Notes still held end `at`.
+    pub fn finish(mut self, at: f64) -> Vec<TakeNote> {
+        for note in std::mem::take(&mut self.held) {
+            self.notes.push(TakeNote { end: at, ..note });
+        }
+        if let Some(punch) = self.punch {
+            self.notes.retain(|note| punch.contains(note.start));
+            for note in &mut self.notes {
+                note.end = note.end.min(punch.end_b32 as f64);
+            }
+        }
+        self.notes.sort_by(|a, b| a.start.total_cmp(&b.start));
+        self.notes
+    }
+
+    /// Finish the take, along with the punch range it replaces once the transport's
+    /// been inside it. Stopping partway through leaves the notes the transport hadn't
+    /// reached yet in place.
+    pub fn finish_punched(self, at: f64) -> Recorded {
+        let reached = self.reached.max(at).ceil() as u64;
+        let replaces = self.punch
+            .filter(|_| self.punched_in)
+            .map(|punch| PunchRange { end_b32: punch.end_b32.min(reached), ..punch });
+        Recorded { notes: self.finish(at), replaces }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dawww_core::pitch::Tone;
+
+    #[test]
+    fn test_take() {
+        let (c, e) = (Pitch::new(Tone::C, 4), Pitch::new(Tone::E, 4));
+        let mut take = Take::default();
+        take.note_on(c, 90, 3.8);
+        take.note_on(e, 60, 4.1);
+        take.note_off(c, 11.9);
+        // Releasing a key that was never recorded does nothing
+        take.note_off(Pitch::new(Tone::G, 4), 12.0);
+        take.note_on(c, 100, 12.2);
+        let notes = take.finish(13.0);
+
+        assert_eq!(notes.len(), 3);
+        assert_eq!((notes[0].pitch, notes[0].velocity), (c, 90));
+        assert_eq!(notes[0].placed(1), (4, 8));
+        assert_eq!(notes[0].placed(8), (0, 8));
+        assert_eq!(notes[1].placed(4), (4, 8));
+        // Short notes last a step
+        assert_eq!(notes[2].placed(1), (12, 1));
+        assert_eq!(notes[2].placed(8), (16, 8));
+    }
+
+    #[test]
+    fn test_punch_in() {
+        let (c, e, g) = (Pitch::new(Tone::C, 4), Pitch::new(Tone::E, 4), Pitch::new(Tone::G, 4));
+        let mut take = Take::punched(PunchRange::new(40, 32));
+        take.reach(31.0);
+        take.note_on(c, 90, 30.0);
+        take.note_on(e, 90, 33.0);
+        take.reach(33.0);
+        take.note_on(g, 90, 39.0);
+        take.note_off(c, 35.0);
+        let recorded = take.finish_punched(44.0);
+
+        // Notes started before punching in are left out and ones held past punching out end there
+        let kept: Vec<(Pitch, f64, f64)> = recorded.notes.iter().map(|note| (note.pitch, note.start, note.end)).collect();
+        assert_eq!(kept, vec![(e, 33.0, 40.0), (g, 39.0, 40.0)]);
+        assert_eq!(recorded.replaces, Some(PunchRange { start_b32: 32, end_b32: 40 }));
+
+        // Stopping partway through only replaces as far as the transport got
+        let mut take = Take::punched(PunchRange::new(32, 40));
+        take.reach(33.0);
+        take.note_on(e, 90, 34.0);
+        let recorded = take.finish_punched(35.5);
+        assert_eq!(recorded.replaces, Some(PunchRange { start_b32: 32, end_b32: 36 }));
+        assert_eq!((recorded.notes[0].start, recorded.notes[0].end), (34.0, 35.5));
+
+        // Stopping before the punch range leaves the score alone
+        let mut take = Take::punched(PunchRange::new(32, 40));
+        take.reach(20.0);
+        assert_eq!(take.finish_punched(21.0).replaces, None);
+    }
+}
@@ -1,3 +1,5 @@
+use dawww_core::Grid;
+
 #[derive(Clone, Copy)]
 pub enum Resolution {
     Time1_4,
@@ -7,15 +9,6 @@ pub enum Resolution {
 }
 
 impl Resolution {
-    pub fn as_str(&self) -> &str {
-        match self {
-            Resolution::Time1_4 => "1/4",
-            Resolution::Time1_8 => "1/8",
-            Resolution::Time1_16 => "1/16",
-            Resolution::Time1_32 => "1/32",
-        }
-    }
-
     pub fn bar_length_in_beats(&self) -> usize {
         match self {
             Resolution::Time1_4 => 4,
@@ -51,4 +44,23 @@ impl Resolution {
             Resolution::Time1_32 => Resolution::Time1_32,
         }
     }
+
+    /// The core editing grid with the same step
+    pub fn grid(&self) -> Grid {
+        match self {
+            Resolution::Time1_4 => Grid::Quarter,
+            Resolution::Time1_8 => Grid::Eighth,
+            Resolution::Time1_16 => Grid::Sixteenth,
+            Resolution::Time1_32 => Grid::ThirtySecond,
+        }
+    }
+
+    pub fn from_grid(grid: Grid) -> Resolution {
+        match grid {
+            Grid::Quarter => Resolution::Time1_4,
+            Grid::Eighth => Resolution::Time1_8,
+            Grid::Sixteenth => Resolution::Time1_16,
+            Grid::ThirtySecond => Resolution::Time1_32,
+        }
+    }
 }
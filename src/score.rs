@@ -3,17 +3,23 @@
 use std::collections::HashMap;
 use std::path::PathBuf;
 use dawww_core::{
-    pitch::{Pitch, Tone},
-    DawFile, Note as DawNote, Instrument,
+    instrument::SubtractiveParams,
+    pitch::Pitch,
+    time_to_32nds, DawFile, Groove, Note as DawNote, DEFAULT_VELOCITY, Instrument, OverlapPolicy, Polyphony, Selection,
+    Session, TextKind, TimeRange,
 };
+use dawww_playback::RecordedAudio;
 use dawww_render::AudioEngine;
-use crate::selection_range::SelectionRange;
+
+/// Track added to songs that don't have any, so notes have somewhere to go
+pub const INSTRUMENT: &str = "synth1";
 
 #[derive(Debug, Clone, Copy)]
 pub struct Note {
     pub pitch: Pitch,
     pub onset_b32: u64,
     pub duration_b32: u64,
+    pub velocity: u8,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -33,30 +39,67 @@ pub struct ActiveNote {
 pub struct Score {
     daw_file: DawFile,
     save_path: Option<PathBuf>,
+    /// Track notes are entered on, shown and played live from
+    instrument: String,
 }
 
 impl Score {
     pub fn new() -> Self {
-        let mut daw_file = DawFile::new("Untitled".to_string());
-        daw_file.add_instrument("synth1".to_string(), Instrument::new_sampler("synth1".into())).unwrap();
-        
-        Self {
-            daw_file,
-            save_path: None,
-        }
+        Self::from_daw_file(DawFile::new("Untitled".to_string()))
     }
 
+    /// Edit `daw_file`, starting on its first track
     pub fn from_daw_file(mut daw_file: DawFile) -> Self {
-        // Ensure the default instrument exists
-        if daw_file.get_instrument("synth1").is_none() {
-            daw_file.add_instrument("synth1".to_string(), Instrument::new_sampler("synth1".into()))
+        if daw_file.instruments.is_empty() {
+            daw_file.add_instrument(INSTRUMENT.to_string(), Instrument::new_subtractive(SubtractiveParams::default()))
                 .expect("Failed to add default instrument");
         }
+        let instrument = daw_file.instruments.keys().min().cloned().unwrap_or_default();
 
         Self {
             daw_file,
             save_path: None,
+            instrument,
+        }
+    }
+
+    /// Track notes are entered on and shown
+    pub fn instrument(&self) -> &str {
+        &self.instrument
+    }
+
+    /// The song's tracks, in name order
+    pub fn instruments(&self) -> Vec<String> {
+        let mut ids: Vec<String> = self.daw_file.instruments.keys().cloned().collect();
+        ids.sort();
+        ids
+    }
+
+    /// Tracks playback sounds: every one the mixer lets be heard, in name order
+    pub fn played_tracks(&self) -> Vec<String> {
+        let mut ids = self.instruments();
+        ids.retain(|id| self.daw_file.mixer.is_audible(id));
+        ids
+    }
+
+    /// Move to the next track, going back to the first after the last, and return its name.
+    /// A song without tracks stays where it is.
+    pub fn cycle_instrument(&mut self) -> &str {
+        let ids = self.instruments();
+        let next = ids.iter().position(|id| *id == self.instrument).map_or(0, |index| index + 1);
+        if let Some(id) = ids.get(next).or(ids.first()) {
+            self.instrument = id.clone();
+        }
+        &self.instrument
+    }
+
+    /// Enter and show notes on the track `id`
+    pub fn select_instrument(&mut self, id: &str) -> Result<(), anyhow::Error> {
+        if self.daw_file.get_instrument(id).is_none() {
+            anyhow::bail!("There's no track named '{}'", id);
         }
+        self.instrument = id.to_string();
+        Ok(())
     }
 
     fn b32_to_time_str(&self, b32: u64) -> String {
@@ -66,22 +109,41 @@ impl Score {
         format!("{}.{}", bar, thirty_second)
     }
 
-    fn time_str_to_b32(&self, time: &str) -> u64 {
-        // Convert bar.32nd format from DawFile to b32
-        let parts: Vec<&str> = time.split('.').collect();
-        let bar = parts[0].parse::<u64>().unwrap();
-        let thirty_second = parts[1].parse::<u64>().unwrap();
-        ((bar - 1) * 32) + thirty_second
+    /// Notes on the current track as (onset in b32, note), in time order
+    fn daw_notes(&self) -> impl DoubleEndedIterator<Item = (u64, &DawNote)> {
+        self.daw_notes_on(&self.instrument)
     }
 
+    /// Notes on the track `instrument` as (onset in b32, note), in time order
+    fn daw_notes_on(&self, instrument: &str) -> impl DoubleEndedIterator<Item = (u64, &DawNote)> {
+        self.daw_file.iter_notes_for(instrument).map(|(onset, _, note)| (onset as u64, note))
+    }
+
+
     pub fn get_bpm(&self) -> u16 {
         self.daw_file.bpm as u16
     }
 
-    pub fn set_bpm(&mut self, bpm: u16) {
-        log::info!("Setting BPM to {}", bpm);
-        self.daw_file.set_bpm(bpm as u32);
-        self.try_save();
+    /// Editor state saved with the song
+    pub fn session(&self) -> &Session {
+        &self.daw_file.session
+    }
+
+    /// Update the editor state; it's written with the next save
+    pub fn set_session(&mut self, session: Session) {
+        if let Err(e) = self.daw_file.set_session(session) {
+            log::error!("Invalid session state: {}", e);
+        }
+    }
+
+    /// Update the editor state and write the project file, without rendering a mixdown
+    pub fn save_session(&mut self, session: Session) {
+        self.set_session(session);
+        if let Some(path) = &self.save_path {
+            if let Err(e) = self.daw_file.save(path) {
+                log::error!("Failed to save session: {}", e);
+            }
+        }
     }
 
     pub fn set_save_path(&mut self, path: PathBuf) {
@@ -89,6 +151,11 @@ impl Score {
         self.save_path = Some(path);
     }
 
+    /// The project file edits are saved to, if there is one
+    pub fn save_path(&self) -> Option<&PathBuf> {
+        self.save_path.as_ref()
+    }
+
     fn try_save(&mut self) {
         log::info!("Attempting to save DawFile...");
         if let Some(path) = &self.save_path {
@@ -115,7 +182,8 @@ impl Score {
 
                 // Render the WAV file
                 log::info!("Creating AudioEngine with current DAW file");
-                let audio_engine = AudioEngine::new(self.daw_file.clone());
+                let audio_engine = AudioEngine::new(self.daw_file.clone())
+                    .with_project_dir(path.parent().unwrap().to_path_buf());
                 log::info!("Starting WAV file rendering...");
                 match audio_engine.render(&wav_path) {
                     Ok(_) => {
@@ -158,34 +226,92 @@ impl Score {
         }
     }
 
+    /// The project as played back
+    pub fn daw_file(&self) -> &DawFile {
+        &self.daw_file
+    }
+
+    /// Directory the project's sample paths are relative to
+    pub fn project_dir(&self) -> PathBuf {
+        self.save_path.as_ref().and_then(|path| path.parent()).map(PathBuf::from).unwrap_or_default()
+    }
+
+    /// Gain the mixer plays `instrument` at, 0 while it's muted or another track is soloed
+    pub fn track_level(&self, instrument: &str) -> f64 {
+        self.daw_file.mixer.track_level(instrument)
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.daw_file.mixer.track(&self.instrument).mute
+    }
+
+    pub fn toggle_mute(&mut self) {
+        self.set_mute(!self.is_muted());
+    }
+
+    pub fn set_mute(&mut self, mute: bool) {
+        self.daw_file.mixer.set_mute(&self.instrument, mute);
+        self.try_save();
+    }
+
+    #[cfg(feature = "osc")]
+    pub fn set_solo(&mut self, solo: bool) {
+        self.daw_file.mixer.set_solo(&self.instrument, solo);
+        self.try_save();
+    }
+
+    /// Set the instrument's fader, in dB
+    #[cfg(feature = "osc")]
+    pub fn set_gain(&mut self, gain_db: f64) -> Result<(), anyhow::Error> {
+        self.daw_file.mixer.set_track_gain(&self.instrument, gain_db)?;
+        self.try_save();
+        Ok(())
+    }
+
+    /// Voice limit of `instrument`
+    pub fn polyphony(&self, instrument: &str) -> Polyphony {
+        self.daw_file.get_instrument(instrument).map(|i| i.polyphony.clone()).unwrap_or_default()
+    }
+
+    /// Markers the transport can jump to, as (time in b32, name) in time order. They're
+    /// the song's annotations that aren't attached to an instrument, e.g. "chorus".
+    pub fn markers(&self) -> Vec<(u64, String)> {
+        let mut markers = self.daw_file.lyrics.iter()
+            .filter(|lyric| lyric.kind == TextKind::Annotation && lyric.instrument.is_none())
+            .filter_map(|lyric| Some((time_to_32nds(&lyric.time).ok()? as u64, lyric.text.clone())))
+            .collect::<Vec<_>>();
+        // A loaded file's annotations may be in any order
+        markers.sort_by_key(|(time_b32, _)| *time_b32);
+        markers
+    }
+
+    /// Timing feel `instrument` plays with
+    pub fn groove(&self, instrument: &str) -> Groove {
+        self.daw_file.groove_for(instrument).clone()
+    }
+
+    #[cfg(test)]
     pub fn notes_starting_at_time(&self, onset_b32: u64) -> Vec<Note> {
-        let time_str = self.b32_to_time_str(onset_b32);
-        let events = self.daw_file.get_events_by_instrument("synth1");
-        
-        events.iter()
-            .filter(|e| e.time == time_str)
-            .flat_map(|e| e.notes.iter().map(|n| Note {
+        self.notes_starting_on(&self.instrument, onset_b32)
+    }
+
+    /// Notes on the track `instrument` starting at `onset_b32`
+    pub fn notes_starting_on(&self, instrument: &str, onset_b32: u64) -> Vec<Note> {
+        self.daw_notes_on(instrument)
+            .filter(|(onset, _)| *onset == onset_b32)
+            .map(|(_, n)| Note {
                 pitch: n.pitch,
                 onset_b32,
                 duration_b32: n.duration as u64,
-            }))
+                velocity: n.velocity,
+            })
             .collect()
     }
 
+    /// Whether `time_point_b32` comes before the last note on any track has ended
     pub fn time_within_song(&self, time_point_b32: u64) -> bool {
-        let events = self.daw_file.get_events_by_instrument("synth1");
-        if events.is_empty() {
-            return false;
-        }
-
-        let last_event = events.last().unwrap();
-        let last_time = self.time_str_to_b32(&last_event.time);
-        let last_duration = events.iter()
-            .flat_map(|e| e.notes.iter().map(|n| n.duration as u64))
-            .max()
-            .unwrap_or(0);
-
-        time_point_b32 < last_time + last_duration
+        self.daw_file.iter_notes()
+            .any(|(onset, _, note)| time_point_b32 < onset as u64 + note.duration as u64)
     }
 
     pub fn insert_or_remove(&mut self, pitch: Pitch, onset_b32: u64, duration_b32: u64) {
@@ -194,42 +320,27 @@ impl Score {
         let daw_note = DawNote::new(pitch, duration_b32 as u32);
 
         // Check if note exists
-        let events = self.daw_file.get_events_by_instrument("synth1");
-        let note_exists = events.iter()
-            .filter(|e| e.time == time_str)
-            .flat_map(|e| &e.notes)
-            .any(|n| n.pitch == pitch && n.duration == duration_b32 as u32);
+        let note_exists = self.daw_notes()
+            .any(|(onset, n)| onset == onset_b32 && n.pitch == pitch && n.duration == duration_b32 as u32);
 
         if note_exists {
             // Remove the note
             log::info!("Removing existing note");
-            self.daw_file.remove_note(&time_str, "synth1", &daw_note).unwrap();
+            self.daw_file.remove_note(&time_str, &self.instrument, &daw_note).unwrap();
         } else {
             // Add the note
             log::info!("Adding new note");
-            self.daw_file.add_note(&time_str, "synth1", daw_note).unwrap();
+            self.daw_file.add_note(&time_str, &self.instrument, daw_note).unwrap();
         }
 
         self.try_save();
     }
 
-    pub fn clone_at_selection(&self, selection_range: SelectionRange) -> Score {
+    pub fn clone_at_selection(&self, selection: Selection) -> Score {
         let mut new_score = Score::new();
 
-        let start_time = self.b32_to_time_str(selection_range.time_point_start_b32);
-        let end_time = self.b32_to_time_str(selection_range.time_point_end_b32);
-
-        if let Ok(events) = self.daw_file.get_events_in_range(&start_time, &end_time) {
-            for event in events {
-                if event.instrument == "synth1" {
-                    for note in &event.notes {
-                        if note.pitch >= selection_range.pitch_low && note.pitch <= selection_range.pitch_high {
-                            let onset_b32 = self.time_str_to_b32(&event.time);
-                            new_score.insert_or_remove(note.pitch, onset_b32, note.duration as u64);
-                        }
-                    }
-                }
-            }
+        for (onset_b32, _, note) in self.daw_file.iter_notes_in_range(selection).filter(|(_, i, _)| *i == self.instrument) {
+            new_score.insert_or_remove(note.pitch, onset_b32 as u64, note.duration as u64);
         }
 
         new_score
@@ -239,34 +350,19 @@ impl Score {
         match time_point_start_b32 {
             Some(new_start_time) => {
                 let mut new_score = Score::new();
-                let events = self.daw_file.get_events_by_instrument("synth1");
-
-                if events.is_empty() {
+                let Some((min_onset, _)) = self.daw_notes().next() else {
                     return self.clone();
-                }
-
-                let min_onset = events.iter()
-                    .map(|e| self.time_str_to_b32(&e.time))
-                    .min()
-                    .unwrap();
-
-                let time_offset = if min_onset > new_start_time {
-                    min_onset - new_start_time
-                } else {
-                    new_start_time - min_onset
                 };
 
-                for event in events {
-                    let old_onset = self.time_str_to_b32(&event.time);
+                let time_offset = min_onset.abs_diff(new_start_time);
+
+                for (old_onset, note) in self.daw_notes() {
                     let new_onset = if min_onset > new_start_time {
                         old_onset - time_offset
                     } else {
                         old_onset + time_offset
                     };
-
-                    for note in &event.notes {
-                        new_score.insert_or_remove(note.pitch, new_onset, note.duration as u64);
-                    }
+                    new_score.insert_or_remove(note.pitch, new_onset, note.duration as u64);
                 }
 
                 new_score
@@ -276,99 +372,89 @@ impl Score {
     }
 
     pub fn insert(&mut self, pitch: Pitch, onset_b32: u64, duration_b32: u64) {
-        log::info!("Inserting note: pitch={}, onset={}, duration={}", pitch, onset_b32, duration_b32);
-        let time_str = self.b32_to_time_str(onset_b32);
-        let end_b32 = onset_b32 + duration_b32;
-
-        // Find all overlapping notes with the same pitch
-        let events = self.daw_file.get_events_by_instrument("synth1");
-        let mut overlapping_notes = Vec::new();
-
-        for event in events {
-            let event_onset = self.time_str_to_b32(&event.time);
-            for note in &event.notes {
-                if note.pitch == pitch {
-                    let event_end = event_onset + note.duration as u64;
-                    if !(event_end <= onset_b32 || event_onset >= end_b32) {
-                        overlapping_notes.push((event.time.clone(), note.clone()));
-                    }
-                }
-            }
-        }
+        self.insert_with_velocity(pitch, onset_b32, duration_b32, DEFAULT_VELOCITY);
+    }
 
-        if !overlapping_notes.is_empty() {
-            log::info!("Found {} overlapping notes to merge", overlapping_notes.len());
-        }
+    /// Insert a note played at `velocity`, merging it with any it overlaps
+    pub fn insert_with_velocity(&mut self, pitch: Pitch, onset_b32: u64, duration_b32: u64, velocity: u8) {
+        self.add_merged(pitch, onset_b32, duration_b32, velocity);
+        self.try_save();
+    }
 
-        // Remove all overlapping notes
-        for (time, note) in &overlapping_notes {
-            self.daw_file.remove_note(time, "synth1", note).unwrap();
+    /// Insert recorded notes as (pitch, onset, duration, velocity), saving once for all of them
+    pub fn insert_take(&mut self, notes: &[(Pitch, u64, u64, u8)]) {
+        log::info!("Inserting {} recorded notes", notes.len());
+        for &(pitch, onset_b32, duration_b32, velocity) in notes {
+            self.add_merged(pitch, onset_b32, duration_b32, velocity);
         }
+        if !notes.is_empty() {
+            self.try_save();
+        }
+    }
 
-        // Calculate merged note boundaries
-        let merged_onset = if overlapping_notes.is_empty() {
-            onset_b32
-        } else {
-            overlapping_notes.iter()
-                .map(|(time, _)| self.time_str_to_b32(time))
-                .min()
-                .unwrap()
-                .min(onset_b32)
-        };
-
-        let merged_end = if overlapping_notes.is_empty() {
-            end_b32
-        } else {
-            overlapping_notes.iter()
-                .map(|(time, note)| self.time_str_to_b32(time) + note.duration as u64)
-                .max()
-                .unwrap()
-                .max(end_b32)
-        };
+    /// Save recorded audio under the project's `recordings` directory and place it on the
+    /// timeline from `start_b32`, as a new frozen track named `audio1`, `audio2` and so on.
+    /// Returns the track's name.
+    pub fn add_audio_clip(&mut self, audio: RecordedAudio, start_b32: f64) -> Result<String, anyhow::Error> {
+        let id = (1..)
+            .map(|n| format!("audio{}", n))
+            .find(|id| self.daw_file.get_instrument(id).is_none())
+            .unwrap();
+        let relative_path = PathBuf::from("recordings").join(format!("{}.wav", id));
+        let path = self.project_dir().join(&relative_path);
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        log::info!("Placing {:.1} seconds of recorded audio at {:.2} as {}", audio.seconds(), start_b32, id);
+        audio.write_wav(&path)?;
+        let start = start_b32 * 60.0 / (self.daw_file.bpm as f64 * 8.0);
+        self.daw_file.add_audio_clip(&id, relative_path, start)?;
+        self.try_save();
+        Ok(id)
+    }
 
-        // Add the merged note
-        let merged_time = self.b32_to_time_str(merged_onset);
-        let merged_duration = merged_end - merged_onset;
-        let daw_note = DawNote::new(pitch, merged_duration as u32);
-        log::info!("Adding merged note: time={}, duration={}", merged_time, merged_duration);
-        self.daw_file.add_note(&merged_time, "synth1", daw_note).unwrap();
+    /// Replace the notes starting in `start_b32..end_b32` with recorded ones, as punching
+    /// in over part of the song does
+    pub fn replace_with_take(&mut self, start_b32: u64, end_b32: u64, notes: &[(Pitch, u64, u64, u8)]) {
+        let range = TimeRange::new(start_b32 as u32, end_b32 as u32);
+        match self.daw_file.delete_notes(range.into(), Some(&self.instrument)) {
+            Ok(count) => log::info!("Punched out {} notes from {} to {}", count, start_b32, end_b32),
+            Err(e) => log::error!("Failed to delete notes: {}", e),
+        }
+        for &(pitch, onset_b32, duration_b32, velocity) in notes {
+            self.add_merged(pitch, onset_b32, duration_b32, velocity);
+        }
         self.try_save();
     }
 
+    fn add_merged(&mut self, pitch: Pitch, onset_b32: u64, duration_b32: u64, velocity: u8) {
+        log::info!("Inserting note: pitch={}, onset={}, duration={}, velocity={}", pitch, onset_b32, duration_b32, velocity);
+        let time_str = self.b32_to_time_str(onset_b32);
+        let note = DawNote::new(pitch, duration_b32 as u32).with_velocity(velocity);
+        self.daw_file.add_note(&time_str, &self.instrument, note).unwrap();
+
+        // Merge with any notes of the same pitch the new note overlaps
+        let range = TimeRange::new(onset_b32 as u32, (onset_b32 + duration_b32) as u32);
+        let merged = self.daw_file.resolve_overlaps(&self.instrument, Some(range), OverlapPolicy::Merge).unwrap();
+        if merged > 0 {
+            log::info!("Merged {} overlapping notes", merged);
+        }
+    }
+
     pub fn merge_down(&self, other: &Score) -> Score {
         let mut merged_score = self.clone();
-        let other_events = other.daw_file.get_events_by_instrument("synth1");
-
-        for event in other_events {
-            for note in &event.notes {
-                let onset_b32 = self.time_str_to_b32(&event.time);
-                merged_score.insert(note.pitch, onset_b32, note.duration as u64);
-            }
+        for (onset_b32, note) in other.daw_notes() {
+            merged_score.insert(note.pitch, onset_b32, note.duration as u64);
         }
 
         merged_score
     }
 
     pub fn duration(&self) -> u64 {
-        let events = self.daw_file.get_events_by_instrument("synth1");
-        if events.is_empty() {
+        let Some((first_onset, _)) = self.daw_notes().next() else {
             return 0;
-        }
-
-        let first_onset = events.iter()
-            .map(|e| self.time_str_to_b32(&e.time))
-            .min()
-            .unwrap();
+        };
 
-        let last_final_time = events.iter()
-            .map(|e| {
-                let onset = self.time_str_to_b32(&e.time);
-                let max_duration = e.notes.iter()
-                    .map(|n| n.duration as u64)
-                    .max()
-                    .unwrap_or(0);
-                onset + max_duration
-            })
+        let last_final_time = self.daw_notes()
+            .map(|(onset, n)| onset + n.duration as u64)
             .max()
             .unwrap();
 
@@ -376,67 +462,59 @@ impl Score {
     }
 
     pub fn notes_active_at_time(&self, time_point_b32: u64) -> Vec<ActiveNote> {
-        let events = self.daw_file.get_events_by_instrument("synth1");
-        
+        self.notes_active_on(&self.instrument, time_point_b32)
+    }
+
+    /// Notes on the track `instrument` sounding at `time_point_b32`
+    pub fn notes_active_on(&self, instrument: &str, time_point_b32: u64) -> Vec<ActiveNote> {
         let mut active_notes = Vec::new();
-        
-        for event in events {
-            let event_time = self.time_str_to_b32(&event.time);
-            for note in &event.notes {
-                let note_end = event_time + note.duration as u64;
-                
-                if time_point_b32 >= event_time && time_point_b32 <= note_end {
-                    let state = if time_point_b32 == event_time {
-                        NoteState::Onset
-                    } else if time_point_b32 == note_end {
-                        NoteState::Release
-                    } else {
-                        NoteState::Sustain
-                    };
 
-                    active_notes.push(ActiveNote {
-                        note: Note {
-                            pitch: note.pitch,
-                            onset_b32: event_time,
-                            duration_b32: note.duration as u64,
-                        },
-                        state,
-                    });
-                }
-            }
-        }
+        for (event_time, note) in self.daw_notes_on(instrument) {
+            let note_end = event_time + note.duration as u64;
 
-        active_notes
-    }
+            if time_point_b32 >= event_time && time_point_b32 <= note_end {
+                let state = if time_point_b32 == event_time {
+                    NoteState::Onset
+                } else if time_point_b32 == note_end {
+                    NoteState::Release
+                } else {
+                    NoteState::Sustain
+                };
 
-    pub fn delete_in_selection(&mut self, selection_range: SelectionRange) {
-        log::info!("Deleting notes in selection range: time={}-{}, pitch={}-{}", 
-            selection_range.time_point_start_b32,
-            selection_range.time_point_end_b32,
-            selection_range.pitch_low,
-            selection_range.pitch_high);
-        let start_time = self.b32_to_time_str(selection_range.time_point_start_b32);
-        let end_time = self.b32_to_time_str(selection_range.time_point_end_b32);
-
-        // First collect all notes to remove
-        let mut notes_to_remove = Vec::new();
-        if let Ok(events) = self.daw_file.get_events_in_range(&start_time, &end_time) {
-            for event in events {
-                if event.instrument == "synth1" {
-                    for note in &event.notes {
-                        if note.pitch >= selection_range.pitch_low && note.pitch <= selection_range.pitch_high {
-                            notes_to_remove.push((event.time.clone(), note.clone()));
-                        }
-                    }
-                }
+                active_notes.push(ActiveNote {
+                    note: Note {
+                        pitch: note.pitch,
+                        onset_b32: event_time,
+                        duration_b32: note.duration as u64,
+                        velocity: note.velocity,
+                    },
+                    state,
+                });
             }
         }
 
-        log::info!("Found {} notes to remove", notes_to_remove.len());
+        active_notes
+    }
 
-        // Then remove them
-        for (time, note) in notes_to_remove {
-            self.daw_file.remove_note(&time, "synth1", &note).unwrap();
+    /// Enter a step-sequencer string at `onset_b32`, one step per `step_b32`.
+    /// `x` steps play `pitch`.
+    pub fn insert_steps(&mut self, pattern: &str, pitch: Pitch, onset_b32: u64, step_b32: u64) -> Result<usize, anyhow::Error> {
+        log::info!("Inserting step pattern '{}' at onset={}, step={}", pattern, onset_b32, step_b32);
+        let time_str = self.b32_to_time_str(onset_b32);
+        let count = self.daw_file.add_steps(&time_str, &self.instrument, pattern, step_b32 as u32, pitch)?;
+        self.try_save();
+        Ok(count)
+    }
+
+    pub fn delete_in_selection(&mut self, selection: Selection) {
+        log::info!("Deleting notes in selection: time={}-{}, pitch={}-{}",
+            selection.time.start,
+            selection.time.end,
+            selection.pitches.low,
+            selection.pitches.high);
+        match self.daw_file.delete_notes(selection, Some(&self.instrument)) {
+            Ok(count) => log::info!("Removed {} notes", count),
+            Err(e) => log::error!("Failed to delete notes: {}", e),
         }
 
         self.try_save();
@@ -463,13 +541,14 @@ impl Score {
 
             // Render the WAV file
             log::info!("Creating AudioEngine with current DAW file");
-            let audio_engine = AudioEngine::new(self.daw_file.clone());
+            let audio_engine = AudioEngine::new(self.daw_file.clone())
+                .with_project_dir(path.parent().unwrap().to_path_buf());
             log::info!("Starting WAV file rendering...");
             match audio_engine.render(&wav_path) {
                 Ok(_) => log::info!("Successfully rendered mixdown to {}", wav_path.display()),
                 Err(e) => {
                     log::error!("Failed to render mixdown: {}", e);
-                    return Err(e.into());
+                    return Err(e);
                 }
             }
         } else {
@@ -479,19 +558,15 @@ impl Score {
     }
 
     pub fn get_notes(&self) -> HashMap<u64, Vec<Note>> {
-        let mut notes = HashMap::new();
-        let events = self.daw_file.get_events_by_instrument("synth1");
-
-        for event in events {
-            let onset_b32 = self.time_str_to_b32(&event.time);
-            let notes_at_time = event.notes.iter().map(|n| Note {
+        let mut notes: HashMap<u64, Vec<Note>> = HashMap::new();
+        for (onset_b32, n) in self.daw_notes() {
+            notes.entry(onset_b32).or_default().push(Note {
                 pitch: n.pitch,
                 onset_b32,
                 duration_b32: n.duration as u64,
-            }).collect();
-            notes.insert(onset_b32, notes_at_time);
+                velocity: n.velocity,
+            });
         }
-        
         notes
     }
 }
@@ -499,6 +574,7 @@ impl Score {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use dawww_core::pitch::{PitchRange, Tone};
 
     fn create_test_score() -> Score {
         let mut daw_file = DawFile::new("Test Song".to_string());
@@ -507,6 +583,7 @@ mod tests {
         let mut score = Score {
             daw_file,
             save_path: None,
+            instrument: INSTRUMENT.to_string(),
         };
         
         // Add some test notes
@@ -516,6 +593,21 @@ mod tests {
         score
     }
 
+    #[test]
+    fn test_insert_steps() {
+        let mut score = create_test_score();
+        let count = score.insert_steps("x . x . C5 - . .", Pitch::new(Tone::A, 3), 96, 4).unwrap();
+        assert_eq!(count, 3);
+
+        let notes = score.notes_starting_at_time(104);
+        assert_eq!(notes[0].pitch, Pitch::new(Tone::A, 3));
+        let notes = score.notes_starting_at_time(112);
+        assert_eq!(notes[0].pitch, Pitch::new(Tone::C, 5));
+        assert_eq!(notes[0].duration_b32, 8);
+
+        assert!(score.insert_steps("x?", Pitch::new(Tone::A, 3), 0, 4).is_err());
+    }
+
     #[test]
     fn test_notes_starting_at_time() {
         let score = create_test_score();
@@ -552,18 +644,42 @@ mod tests {
         assert_eq!(score.notes_starting_at_time(0).len(), 0);
     }
 
+    #[test]
+    fn test_cycle_instrument() {
+        let mut score = create_test_score();
+        score.daw_file.add_instrument("bass".to_string(), Instrument::new_sampler("bass.wav".into())).unwrap();
+        assert_eq!(score.instruments(), vec!["bass", "synth1"]);
+        assert_eq!(score.instrument(), INSTRUMENT);
+
+        // Notes go on, and come from, the current track
+        assert_eq!(score.cycle_instrument(), "bass");
+        assert!(score.notes_starting_at_time(0).is_empty());
+        score.insert_or_remove(Pitch::new(Tone::C, 2), 0, 16);
+        assert_eq!(score.daw_file.iter_notes_for("bass").count(), 1);
+        assert_eq!(score.cycle_instrument(), "synth1");
+        assert_eq!(score.notes_starting_at_time(0)[0].pitch, Pitch::new(Tone::C, 4));
+
+        assert!(score.select_instrument("drums").is_err());
+        score.select_instrument("bass").unwrap();
+        assert_eq!(score.instrument(), "bass");
+
+        // Muted tracks aren't played, and a song with no tracks left has none to cycle to
+        score.daw_file.mixer.set_mute("synth1", true);
+        assert_eq!(score.played_tracks(), vec!["bass"]);
+        score.daw_file.instruments.clear();
+        assert_eq!(score.cycle_instrument(), "bass");
+    }
+
     #[test]
     fn test_clone_at_selection() {
         let score = create_test_score();
 
-        let selection_range = SelectionRange {
-            time_point_start_b32: 0,
-            time_point_end_b32: 64,
-            pitch_low: Pitch::new(Tone::C, 4),
-            pitch_high: Pitch::new(Tone::E, 4),
-        };
+        let selection = Selection::new(
+            TimeRange::new(0, 65),
+            PitchRange::new(Pitch::new(Tone::C, 4), Pitch::new(Tone::E, 4)),
+        );
 
-        let selected = score.clone_at_selection(selection_range);
+        let selected = score.clone_at_selection(selection);
 
         assert_eq!(selected.notes_starting_at_time(0).len(), 1);
         assert_eq!(selected.notes_starting_at_time(32).len(), 1);
@@ -26,22 +26,6 @@ impl ScoreViewport {
         }
     }
 
-    pub fn next_octave(&self) -> ScoreViewport {
-        let mut new_viewport = *self;
-        if let Some(next_pitch) = self.middle_pitch.next() {
-            new_viewport.middle_pitch = next_pitch;
-        }
-        new_viewport
-    }
-
-    pub fn prev_octave(&self) -> ScoreViewport {
-        let mut new_viewport = *self;
-        if let Some(prev_pitch) = self.middle_pitch.prev() {
-            new_viewport.middle_pitch = prev_pitch;
-        }
-        new_viewport
-    }
-
     pub fn next_bar(&self, viewport_draw_result: &ViewportDrawResult) -> ScoreViewport {
         let mut new_viewport = *self;
 
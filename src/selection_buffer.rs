@@ -3,7 +3,7 @@ use crate::score::Score;
 #[derive(Debug, Clone)]
 pub enum SelectionBuffer {
     None,
-    Score(Score),
+    Score(Box<Score>),
 }
 
 impl SelectionBuffer {
@@ -12,7 +12,7 @@ impl SelectionBuffer {
             SelectionBuffer::None => self.clone(),
             SelectionBuffer::Score(score) => {
                 let translated_score = score.translate(Some(time_point_start_b32));
-                SelectionBuffer::Score(translated_score)
+                SelectionBuffer::Score(Box::new(translated_score))
             }
         }
     }
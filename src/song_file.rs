@@ -1,12 +1,8 @@
-use std::fs::File;
-use std::io::{self, Write};
+use std::io;
 use std::path::PathBuf;
 use chrono::Local;
-use anyhow::{Result, anyhow};
 
 use crate::score::Score;
-use dawww_core::pitch::Tone;
-use dawww_core::{read_daw_file, find_daw_file};
 
 pub struct SongFile {
     current_path: Option<PathBuf>,
@@ -23,63 +19,31 @@ impl SongFile {
 
     fn generate_default_filename(&self) -> PathBuf {
         let date = Local::now().format("%Y%m%d");
-        PathBuf::from(format!("song_{}.txt", date))
+        PathBuf::from(format!("song_{}{}", date, dawww_core::format::JSON_EXTENSION))
     }
 
-    pub fn save(&mut self, score: &mut Score) -> io::Result<()> {
+    /// Save the song as a project file: the one it was loaded from or last saved to, or a
+    /// new dated one in the current directory
+    pub fn save(&mut self, score: &mut Score) -> anyhow::Result<()> {
         let path = self.current_path.clone()
+            .or_else(|| score.save_path().cloned())
             .unwrap_or_else(|| self.generate_default_filename());
-        
-        let mut file = File::create(&path)?;
-        
-        // Write BPM
-        writeln!(file, "BPM: {}", score.get_bpm())?;
-        
-        // Write notes
-        let notes = score.get_notes();
-        let mut sorted_times: Vec<_> = notes.keys().collect();
-        sorted_times.sort();
-        
-        for &time in sorted_times {
-            if let Some(notes) = notes.get(&time) {
-                let mut note_strs = Vec::new();
-                
-                for note in notes {
-                    let tone_str = match note.pitch.tone {
-                        Tone::C => "C",
-                        Tone::Cs => "Cs",
-                        Tone::D => "D",
-                        Tone::Ds => "Ds",
-                        Tone::E => "E",
-                        Tone::F => "F",
-                        Tone::Fs => "Fs",
-                        Tone::G => "G",
-                        Tone::Gs => "Gs",
-                        Tone::A => "A",
-                        Tone::As => "As",
-                        Tone::B => "B",
-                    };
-                    
-                    note_strs.push(format!("{}{}-{}", 
-                        tone_str,
-                        note.pitch.octave,
-                        note.duration_b32
-                    ));
-                }
-                
-                writeln!(file, "{}: {}", time, note_strs.join(" "))?;
-            }
-        }
-        
-        self.current_path = Some(path.clone());
-        score.set_save_path(path);
+        score.save_to_file(&path)?;
+        self.current_path = Some(path);
         Ok(())
     }
 
+    /// Load a project file, or the one in a project directory
     pub fn load(&mut self, path: PathBuf) -> io::Result<Score> {
-        let content = std::fs::read_to_string(&path)?;
+        let path = match path.is_dir() {
+            true => dawww_core::find_daw_file(&path).map_err(|e| io::Error::new(io::ErrorKind::NotFound, e))?,
+            false => path,
+        };
         let daw_file = dawww_core::read_daw_file(&path)
             .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        for issue in daw_file.sample_issues() {
+            log::warn!("Sample problem in {}: {:?}", path.display(), issue);
+        }
         let mut score = Score::from_daw_file(daw_file);
         score.set_save_path(path.clone());
         self.current_path = Some(path);
@@ -88,4 +52,53 @@ impl SongFile {
         log::info!("Loaded notes: {:#?}", score.get_notes());
         Ok(score)
     }
-} 
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::score::INSTRUMENT;
+    use dawww_core::instrument::SubtractiveParams;
+    use dawww_core::pitch::{Pitch, Tone};
+    use dawww_core::{DawFile, Instrument, Note};
+
+    #[test]
+    fn test_round_trip() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("song.daw.json");
+        let mut score = Score::new();
+        score.insert_or_remove(Pitch::new(Tone::E, 4), 40, 8);
+        score.set_save_path(path.clone());
+        SongFile::new().save(&mut score).unwrap();
+
+        // A project directory opens the song in it, with its notes in the grid
+        let mut song_file = SongFile::new();
+        let mut loaded = song_file.load(dir.path().to_path_buf()).unwrap();
+        let notes = loaded.get_notes();
+        assert_eq!(notes[&40].len(), 1);
+        assert_eq!((notes[&40][0].pitch, notes[&40][0].duration_b32), (Pitch::new(Tone::E, 4), 8));
+
+        loaded.insert_or_remove(Pitch::new(Tone::G, 4), 48, 4);
+        song_file.save(&mut loaded).unwrap();
+        assert_eq!(SongFile::new().load(path).unwrap().get_notes().len(), 2);
+        assert!(SongFile::new().load(dir.path().join("mixdown")).is_err());
+
+        // A song without the editor's default track opens on its own first track, and is
+        // saved without gaining one
+        let path = dir.path().join("band.daw.json");
+        let mut daw_file = DawFile::new("Band".to_string());
+        for id in ["lead", "bass"] {
+            daw_file.add_instrument(id.to_string(), Instrument::new_subtractive(SubtractiveParams::default())).unwrap();
+        }
+        daw_file.add_note("1.0", "bass", Note::new(Pitch::new(Tone::E, 2), 8)).unwrap();
+        daw_file.save(&path).unwrap();
+        let mut song_file = SongFile::new();
+        let mut loaded = song_file.load(path.clone()).unwrap();
+        assert_eq!(loaded.instruments(), vec!["bass", "lead"]);
+        assert_eq!(loaded.instrument(), "bass");
+        assert_eq!(loaded.get_notes()[&0][0].pitch, Pitch::new(Tone::E, 2));
+        song_file.save(&mut loaded).unwrap();
+        let saved = dawww_core::read_daw_file(&path).unwrap();
+        assert!(saved.get_instrument(INSTRUMENT).is_none());
+    }
+}